@@ -0,0 +1,49 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json_rules_engine::{and, int_greater_than, or, string_equals, Condition};
+use serde_json::json;
+
+const RULES: usize = 100;
+
+/// A rule of a few leaves, not met by the facts
+fn condition(i: usize) -> Condition {
+    and(vec![
+        string_equals("country", &format!("country-{}", i)),
+        or(vec![
+            int_greater_than("amount", 1_000),
+            string_equals("tier", "gold"),
+        ]),
+    ])
+}
+
+fn bench_check_status(c: &mut Criterion) {
+    let conditions: Vec<_> = (0..RULES).map(condition).collect();
+    let facts = json!({ "country": "FR", "amount": 5_000, "tier": "gold" });
+    #[cfg(feature = "eval")]
+    let rhai_engine = rhai::Engine::new();
+
+    c.bench_function("check_value 100 not met", |b| {
+        b.iter(|| {
+            for condition in &conditions {
+                black_box(condition.check_value(
+                    black_box(&facts),
+                    #[cfg(feature = "eval")]
+                    &rhai_engine,
+                ));
+            }
+        })
+    });
+    c.bench_function("check_status 100 not met", |b| {
+        b.iter(|| {
+            for condition in &conditions {
+                black_box(condition.check_status(
+                    black_box(&facts),
+                    #[cfg(feature = "eval")]
+                    &rhai_engine,
+                ));
+            }
+        })
+    });
+}
+
+criterion_group!(benches, bench_check_status);
+criterion_main!(benches);
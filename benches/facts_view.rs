@@ -0,0 +1,49 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json_rules_engine::{string_equals, Engine, Rule};
+use serde_json::{json, Value};
+
+/// Facts of about 50KB, the rule reading a single one of them
+fn facts() -> String {
+    let items = (0..500)
+        .map(|i| {
+            json!({
+                "sku": format!("sku-{}", i),
+                "quantity": i,
+                "price": i as f64 * 1.5,
+            })
+        })
+        .collect::<Vec<_>>();
+    json!({ "country": "fr", "items": items }).to_string()
+}
+
+fn bench_facts_view(c: &mut Criterion) {
+    let mut engine = Engine::new();
+    engine.add_rule(Rule {
+        id: None,
+        conditions: string_equals("country", "fr"),
+        events: Vec::new(),
+        facts_to_add: Default::default(),
+        enabled: true,
+        tags: Vec::new(),
+        sample: None,
+    });
+    let facts = facts();
+
+    c.bench_function("serde_json parse and evaluate", |b| {
+        b.iter(|| {
+            let facts: Value = serde_json::from_str(black_box(&facts)).unwrap();
+            engine.evaluate(&facts).unwrap()
+        })
+    });
+
+    c.bench_function("simd-json parse and evaluate_view", |b| {
+        b.iter(|| {
+            let mut bytes = black_box(&facts).as_bytes().to_vec();
+            let facts = simd_json::to_owned_value(&mut bytes).unwrap();
+            engine.evaluate_view(&facts).unwrap()
+        })
+    });
+}
+
+criterion_group!(benches, bench_facts_view);
+criterion_main!(benches);
@@ -0,0 +1,98 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json_rules_engine::{
+    and, int_contains, int_contains_all, int_in, int_is_subset,
+    string_contains_any, Condition, Engine, EngineOptions, Rule,
+};
+use serde_json::{json, Value};
+use std::{
+    alloc::{GlobalAlloc, Layout, System},
+    sync::atomic::{AtomicUsize, Ordering},
+};
+
+const ARRAY_LEN: i64 = 100_000;
+
+/// Counts the allocations, to report how many a run makes
+struct CountingAllocator;
+
+static ALLOCATIONS: AtomicUsize = AtomicUsize::new(0);
+
+unsafe impl GlobalAlloc for CountingAllocator {
+    unsafe fn alloc(&self, layout: Layout) -> *mut u8 {
+        ALLOCATIONS.fetch_add(1, Ordering::Relaxed);
+        System.alloc(layout)
+    }
+
+    unsafe fn dealloc(&self, ptr: *mut u8, layout: Layout) {
+        System.dealloc(ptr, layout)
+    }
+}
+
+#[global_allocator]
+static GLOBAL: CountingAllocator = CountingAllocator;
+
+fn rule(conditions: Condition) -> Rule {
+    Rule {
+        id: None,
+        conditions,
+        events: Vec::new(),
+        facts_to_add: Default::default(),
+        enabled: true,
+        tags: Vec::new(),
+        sample: None,
+    }
+}
+
+/// Runs the engine once, reporting the allocations it made, then benches it
+fn bench_engine(c: &mut Criterion, name: &str, engine: &Engine, facts: &Value) {
+    let before = ALLOCATIONS.load(Ordering::Relaxed);
+    engine.evaluate(facts).unwrap();
+    println!(
+        "{}: {} allocations per run",
+        name,
+        ALLOCATIONS.load(Ordering::Relaxed) - before
+    );
+
+    c.bench_function(name, |b| {
+        b.iter(|| engine.evaluate(black_box(facts)).unwrap())
+    });
+}
+
+fn bench_large_arrays(c: &mut Criterion) {
+    let facts = json!({
+        "ids": (0..ARRAY_LEN).collect::<Vec<_>>(),
+        "tags": (0..ARRAY_LEN)
+            .map(|i| format!("tag-{}", i))
+            .collect::<Vec<_>>(),
+    });
+
+    // worst cases: the last elements, or none at all
+    let conditions = and(vec![
+        int_contains("ids", ARRAY_LEN - 1),
+        int_contains_all("ids", vec![ARRAY_LEN - 2, ARRAY_LEN - 1]),
+        int_is_subset("ids", (0..ARRAY_LEN).collect()),
+        string_contains_any("tags", vec!["none", "tag-99999"]),
+    ]);
+    let mut engine = Engine::new();
+    engine.add_rule(rule(conditions.clone()));
+    bench_engine(c, "array constraints 100k", &engine, &facts);
+
+    let engine =
+        Engine::build(vec![rule(conditions)], EngineOptions::default())
+            .unwrap();
+    bench_engine(c, "array constraints 100k built", &engine, &facts);
+
+    let facts = json!({ "id": ARRAY_LEN - 1 });
+    let mut engine = Engine::new();
+    engine.add_rule(rule(int_in("id", (0..ARRAY_LEN).collect())));
+    bench_engine(c, "int_in 100k", &engine, &facts);
+
+    let engine = Engine::build(
+        vec![rule(int_in("id", (0..ARRAY_LEN).collect()))],
+        EngineOptions::default(),
+    )
+    .unwrap();
+    bench_engine(c, "int_in 100k built", &engine, &facts);
+}
+
+criterion_group!(benches, bench_large_arrays);
+criterion_main!(benches);
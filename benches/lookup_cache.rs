@@ -0,0 +1,61 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json_rules_engine::{Engine, EngineOptions, Rule};
+use serde_json::{json, Map, Value};
+
+const RULES: usize = 1_000;
+const FIELDS: usize = 20;
+
+/// Rules each testing a few of the same nested facts
+fn rules() -> Vec<Rule> {
+    (0..RULES)
+        .map(|i| {
+            serde_json::from_value(json!({
+                "id": format!("rule-{}", i),
+                "conditions": {
+                    "and": [
+                        {
+                            "field": format!("user/f{}", i % FIELDS),
+                            "operator": "int_greater_than",
+                            "value": i % 10
+                        },
+                        {
+                            "field": format!("user/f{}", (i + 7) % FIELDS),
+                            "operator": "int_less_than",
+                            "value": 100
+                        },
+                        {
+                            "field": format!("user/f{}", (i + 13) % FIELDS),
+                            "operator": "int_in_range",
+                            "value": [0, 50]
+                        }
+                    ]
+                },
+                "events": []
+            }))
+            .unwrap()
+        })
+        .collect()
+}
+
+fn bench_lookup_cache(c: &mut Criterion) {
+    let user: Map<String, Value> =
+        (0..FIELDS).map(|i| (format!("f{}", i), json!(i))).collect();
+    let facts = json!({ "user": user });
+
+    let mut engine = Engine::new();
+    for rule in rules() {
+        engine.add_rule(rule);
+    }
+    c.bench_function("evaluate 1k rules on 20 fields", |b| {
+        b.iter(|| engine.evaluate(black_box(&facts)).unwrap())
+    });
+
+    let engine = Engine::build(rules(), EngineOptions::default())
+        .unwrap_or_else(|_| panic!("rules failed to build"));
+    c.bench_function("evaluate 1k compiled rules on 20 fields", |b| {
+        b.iter(|| engine.evaluate(black_box(&facts)).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_lookup_cache);
+criterion_main!(benches);
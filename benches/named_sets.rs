@@ -0,0 +1,45 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json_rules_engine::{string_in, string_in_named_set, Engine, Rule};
+use serde_json::json;
+
+const SET_SIZE: usize = 400_000;
+
+fn engine_with(conditions: json_rules_engine::Condition) -> Engine {
+    let mut engine = Engine::new();
+    engine.add_rule(Rule {
+        id: None,
+        conditions,
+        events: Vec::new(),
+        facts_to_add: Default::default(),
+        enabled: true,
+        tags: Vec::new(),
+        sample: None,
+    });
+    engine
+}
+
+fn bench_named_sets(c: &mut Criterion) {
+    let ids = (0..SET_SIZE)
+        .map(|i| format!("device-{}", i))
+        .collect::<Vec<_>>();
+    // worst case for the vector: the last entry
+    let facts = json!({ "device_id": format!("device-{}", SET_SIZE - 1) });
+
+    let engine = engine_with(string_in(
+        "device_id",
+        ids.iter().map(String::as_str).collect(),
+    ));
+    c.bench_function("string_in 400k", |b| {
+        b.iter(|| engine.evaluate(black_box(&facts)).unwrap())
+    });
+
+    let mut engine =
+        engine_with(string_in_named_set("device_id", "denied_devices"));
+    engine.register_set("denied_devices", ids.into_iter().collect());
+    c.bench_function("string_in_named_set 400k", |b| {
+        b.iter(|| engine.evaluate(black_box(&facts)).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_named_sets);
+criterion_main!(benches);
@@ -0,0 +1,64 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json_rules_engine::{Engine, EngineOptions, Rule};
+use serde_json::{json, Value};
+
+const PATTERNS: usize = 200;
+
+/// Bot user agent patterns, none of which a browser matches
+fn patterns() -> Vec<String> {
+    (0..PATTERNS)
+        .map(|i| format!("(?i)crawler-{}[/ ]\\d+", i))
+        .collect()
+}
+
+/// One `string_matches_any` leaf over all the patterns
+fn regex_set_rule() -> Rule {
+    serde_json::from_value(json!({
+        "conditions": {
+            "field": "user_agent",
+            "operator": "string_matches_any",
+            "value": patterns()
+        },
+        "events": []
+    }))
+    .unwrap()
+}
+
+/// An `or` of a `string_matches` leaf per pattern
+fn or_rule() -> Rule {
+    let leaves: Vec<Value> = patterns()
+        .into_iter()
+        .map(|pattern| {
+            json!({
+                "field": "user_agent",
+                "operator": "string_matches",
+                "value": pattern
+            })
+        })
+        .collect();
+    serde_json::from_value(json!({
+        "conditions": { "or": leaves },
+        "events": []
+    }))
+    .unwrap()
+}
+
+fn bench_regex_set(c: &mut Criterion) {
+    let facts = json!({
+        "user_agent": "Mozilla/5.0 (X11; Linux x86_64; rv:109.0) Gecko/20100101 Firefox/119.0"
+    });
+
+    for (name, rule) in [
+        ("string_matches_any of 200 patterns", regex_set_rule()),
+        ("or of 200 string_matches", or_rule()),
+    ] {
+        let engine = Engine::build(vec![rule], EngineOptions::default())
+            .unwrap_or_else(|_| panic!("rules failed to build"));
+        c.bench_function(name, |b| {
+            b.iter(|| engine.evaluate(black_box(&facts)).unwrap())
+        });
+    }
+}
+
+criterion_group!(benches, bench_regex_set);
+criterion_main!(benches);
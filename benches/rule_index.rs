@@ -0,0 +1,61 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use json_rules_engine::{Engine, EngineOptions, Rule};
+use serde_json::{json, Map, Value};
+
+const RULES: usize = 2_000;
+const FIELDS: usize = 500;
+
+/// Rules each reading one of many top level facts
+fn rules() -> Vec<Rule> {
+    (0..RULES)
+        .map(|i| {
+            serde_json::from_value(json!({
+                "id": format!("rule-{}", i),
+                "conditions": {
+                    "and": [
+                        {
+                            "field": format!("field_{}", i % FIELDS),
+                            "operator": "int_greater_than",
+                            "value": i % 100
+                        },
+                        {
+                            "field": format!("field_{}/nested", i % FIELDS),
+                            "operator": "string_equals",
+                            "value": "yes"
+                        }
+                    ]
+                },
+                "events": []
+            }))
+            .unwrap()
+        })
+        .collect()
+}
+
+fn bench_rule_index(c: &mut Criterion) {
+    let rules = rules();
+    // a handful of the facts the rules read
+    let facts: Map<String, Value> = (0..10)
+        .map(|i| (format!("field_{}", i * 7), json!(42)))
+        .collect();
+    let facts = Value::Object(facts);
+
+    let engine = Engine::build(rules.clone(), EngineOptions::default())
+        .unwrap_or_else(|_| panic!("rules failed to build"));
+    c.bench_function("evaluate 2k rules, unindexed", |b| {
+        b.iter(|| engine.evaluate(black_box(&facts)).unwrap())
+    });
+
+    let options = EngineOptions {
+        enable_rule_index: true,
+        ..Default::default()
+    };
+    let engine = Engine::build(rules, options)
+        .unwrap_or_else(|_| panic!("rules failed to build"));
+    c.bench_function("evaluate 2k rules, indexed", |b| {
+        b.iter(|| engine.evaluate(black_box(&facts)).unwrap())
+    });
+}
+
+criterion_group!(benches, bench_rule_index);
+criterion_main!(benches);
@@ -0,0 +1,83 @@
+use criterion::{
+    black_box, criterion_group, criterion_main, BatchSize, Criterion,
+};
+use json_rules_engine::{Engine, Rule};
+use serde_json::json;
+
+const RULES: usize = 2_000;
+
+fn rules() -> Vec<Rule> {
+    (0..RULES)
+        .map(|i| {
+            #[allow(unused_mut)]
+            let mut conditions = vec![
+                json!({
+                    "field": "user.address.country",
+                    "operator": "string_equals",
+                    "value": "{{ countries.billing }}",
+                    "templated_value": true,
+                    "path_syntax": "dotted"
+                }),
+                json!({
+                    "field": "user/age",
+                    "operator": "int_greater_than",
+                    "value": i % 100
+                }),
+            ];
+            #[cfg(feature = "eval")]
+            conditions.push(json!({
+                "expr": format!("facts.user.age + {} > 50", i % 100)
+            }));
+
+            serde_json::from_value(json!({
+                "id": format!("rule-{}", i),
+                "conditions": { "and": conditions },
+                "events": [
+                    {
+                        "type": "counting_event",
+                        "coalescence": 60,
+                        "coalescence_group": "{{ user.name }}",
+                        "params": {}
+                    }
+                ]
+            }))
+            .unwrap()
+        })
+        .collect()
+}
+
+/// The first evaluation of engines fresh out of `add_rules` and `build`
+fn bench_warm_start(c: &mut Criterion) {
+    let rules = rules();
+    let facts = json!({
+        "user": {
+            "name": "Cheng JIANG",
+            "age": 42,
+            "address": { "country": "FR" }
+        },
+        "countries": { "billing": "FR" }
+    });
+
+    c.bench_function("first evaluate, add_rules 2k", |b| {
+        b.iter_batched(
+            || {
+                let mut engine = Engine::new();
+                engine.add_rules(rules.clone());
+                engine
+            },
+            |engine| engine.evaluate(black_box(&facts)).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+
+    c.bench_function("first evaluate, build 2k", |b| {
+        b.iter_batched(
+            || Engine::build(rules.clone(), Default::default()).unwrap(),
+            |engine| engine.evaluate(black_box(&facts)).unwrap(),
+            BatchSize::LargeInput,
+        )
+    });
+}
+
+criterion_group!(benches, bench_warm_start);
+criterion_main!(benches);
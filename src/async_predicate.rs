@@ -0,0 +1,105 @@
+//! Async predicates as conditions, see `Condition::AsyncPredicate`.
+//!
+//! Rhai and the evaluation of conditions being sync, the engine awaits the
+//! predicate calls of the rules up front, concurrently, and the conditions
+//! then read their outcome.
+
+use crate::{
+    condition::{predicate_key, Condition, PredicateResults},
+    rule::Rule,
+    Engine,
+};
+use futures_util::future::{join_all, BoxFuture};
+use serde_json::{json, Value};
+use std::{collections::HashMap, sync::Arc, time::Duration};
+
+/// A predicate `async_predicate` conditions may call, given
+/// `{"args": args, "facts": facts}`, see `Engine::add_async_predicate`
+pub type AsyncPredicateFn = Arc<
+    dyn Fn(Value) -> BoxFuture<'static, Result<bool, String>> + Send + Sync,
+>;
+
+/// How long a predicate call may take unless set otherwise, see
+/// `Engine::set_async_predicate_timeout`
+pub const DEFAULT_ASYNC_PREDICATE_TIMEOUT: Duration = Duration::from_secs(5);
+
+impl Engine {
+    /// Registers a predicate for the `async_predicate` conditions naming it.
+    /// A predicate failing, or not answering within the timeout, makes its
+    /// conditions unknown
+    pub fn add_async_predicate(
+        &mut self,
+        name: impl Into<String>,
+        predicate: AsyncPredicateFn,
+    ) {
+        self.async_predicates.insert(name.into(), predicate);
+    }
+
+    /// Bounds how long each async predicate call may take, five seconds by
+    /// default
+    pub fn set_async_predicate_timeout(&mut self, timeout: Duration) {
+        self.async_predicate_timeout = timeout;
+    }
+
+    /// Awaits every distinct predicate call of the rules, concurrently
+    pub(crate) async fn await_predicates(
+        &self,
+        rules: &[&Rule],
+        facts: &Value,
+    ) -> PredicateResults {
+        let mut calls = HashMap::new();
+        for rule in rules {
+            for (_, node) in rule.conditions.nodes() {
+                if let Condition::AsyncPredicate { name, args, .. } = node {
+                    calls
+                        .entry(predicate_key(name, args))
+                        .or_insert_with(|| (name, args));
+                }
+            }
+        }
+
+        let outcomes =
+            join_all(calls.into_iter().map(|(key, (name, args))| async move {
+                (key, self.call_predicate(name, args, facts).await)
+            }))
+            .await;
+
+        outcomes.into_iter().collect()
+    }
+
+    async fn call_predicate(
+        &self,
+        name: &str,
+        args: &Option<Value>,
+        facts: &Value,
+    ) -> Result<bool, String> {
+        let predicate = self.async_predicates.get(name).ok_or_else(|| {
+            format!("Async predicate `{}` isn't registered", name)
+        })?;
+
+        let input = json!({ "args": args, "facts": facts });
+        tokio::time::timeout(self.async_predicate_timeout, predicate(input))
+            .await
+            .unwrap_or_else(|_| {
+                Err(format!(
+                    "Async predicate `{}` timed out after {:?}",
+                    name, self.async_predicate_timeout
+                ))
+            })
+    }
+
+    /// The first async predicate the rule calls that isn't registered
+    pub(crate) fn missing_predicate<'r>(
+        &self,
+        rule: &'r Rule,
+    ) -> Option<&'r str> {
+        rule.conditions.nodes().find_map(|(_, node)| match node {
+            Condition::AsyncPredicate { name, .. }
+                if !self.async_predicates.contains_key(name) =>
+            {
+                Some(name.as_str())
+            }
+            _ => None,
+        })
+    }
+}
@@ -0,0 +1,66 @@
+//! Evaluation of a single rule against past facts, see `Engine::backtest`.
+
+use crate::{rule::Rule, status::Status, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// How a rule fared against a series of facts
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct BacktestReport {
+    pub met: usize,
+    pub not_met: usize,
+    pub unknown: usize,
+    /// The first facts the rule was met against, as many as the sample size
+    pub samples: Vec<Value>,
+    /// How many times each leaf, by name, failed the rule when it wasn't
+    /// met. Leaves sharing a name share a count
+    pub failed_leaves: HashMap<String, usize>,
+}
+
+impl BacktestReport {
+    /// The number of facts the rule was evaluated against
+    pub fn total(&self) -> usize {
+        self.met + self.not_met + self.unknown
+    }
+}
+
+impl Engine {
+    /// Evaluates the rule, which doesn't need to be added to the engine,
+    /// against each of the facts in turn, e.g. last week's, to tell how
+    /// often it would have been met. Nothing is dispatched, nor is the
+    /// engine's state touched, and the facts are only held one at a time
+    pub fn backtest(
+        &self,
+        rule: &Rule,
+        facts: impl IntoIterator<Item = Value>,
+        sample_size: usize,
+    ) -> BacktestReport {
+        let mut report = BacktestReport::default();
+        for facts in facts {
+            let rule_result = self.evaluate_rule(rule, None, &facts);
+            let condition_result = &rule_result.condition_result;
+            match condition_result.status {
+                Status::Met => {
+                    report.met += 1;
+                    if report.samples.len() < sample_size {
+                        report.samples.push(facts);
+                    }
+                }
+                Status::NotMet => {
+                    report.not_met += 1;
+                    for leaf in condition_result.failed_leaves() {
+                        if leaf.evaluated {
+                            *report
+                                .failed_leaves
+                                .entry(leaf.name.clone())
+                                .or_default() += 1;
+                        }
+                    }
+                }
+                Status::Unknown => report.unknown += 1,
+            }
+        }
+        report
+    }
+}
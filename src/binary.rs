@@ -0,0 +1,56 @@
+//! Compact binary encoding of rules and rule results.
+//!
+//! `Condition` is `#[serde(untagged)]` and leaf conditions `#[serde(flatten)]`
+//! their constraint, both of which need a self-describing format to
+//! deserialize. Non self-describing formats such as bincode can encode these
+//! types but never decode them again, so MessagePack (with field names) is
+//! used instead.
+//!
+//! Every blob starts with a single format version byte, so blobs written by
+//! older versions of this crate can still be told apart and read after the
+//! format evolves.
+
+use crate::{
+    error::{Error, Result},
+    rule::{Rule, RuleResult},
+};
+use serde::{de::DeserializeOwned, Serialize};
+
+/// Version byte written in front of every blob
+pub const BINARY_FORMAT_VERSION: u8 = 1;
+
+fn to_bytes<T: Serialize>(value: &T) -> Result<Vec<u8>> {
+    let mut bytes = vec![BINARY_FORMAT_VERSION];
+    rmp_serde::encode::write_named(&mut bytes, value)?;
+    Ok(bytes)
+}
+
+fn from_bytes<T: DeserializeOwned>(bytes: &[u8]) -> Result<T> {
+    match bytes.split_first() {
+        Some((&BINARY_FORMAT_VERSION, payload)) => {
+            Ok(rmp_serde::from_slice(payload)?)
+        }
+        Some((&version, _)) => Err(Error::BinaryVersionError(version)),
+        None => Err(Error::BinaryVersionError(0)),
+    }
+}
+
+impl Rule {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        to_bytes(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        from_bytes(bytes)
+    }
+}
+
+impl RuleResult {
+    pub fn to_bytes(&self) -> Result<Vec<u8>> {
+        to_bytes(self)
+    }
+
+    pub fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        from_bytes(bytes)
+    }
+}
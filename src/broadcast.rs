@@ -0,0 +1,70 @@
+//! The events dispatched by the engine, pushed to subscribers, see
+//! `Engine::subscribe`.
+//!
+//! The channel is bounded: a subscriber falling more than
+//! `BROADCAST_CAPACITY` envelopes behind misses the oldest ones. The
+//! envelopes subscribers missed are totalled on the engine, see
+//! `Engine::lagged_envelopes`.
+
+use crate::{event::EventEnvelope, Engine};
+
+use tokio::sync::broadcast::{
+    error::{RecvError, TryRecvError},
+    Receiver,
+};
+
+use std::sync::{
+    atomic::{AtomicU64, Ordering},
+    Arc,
+};
+
+/// The receiving end of `Engine::subscribe`
+#[derive(Debug)]
+pub struct Subscription {
+    receiver: Receiver<EventEnvelope>,
+    /// The envelopes missed by all the subscribers of the engine
+    lagged: Arc<AtomicU64>,
+}
+
+impl Subscription {
+    /// The next envelope, or `RecvError::Lagged` with the number of
+    /// envelopes missed if the subscriber fell behind, the next call
+    /// receiving the oldest one still held. `RecvError::Closed` once the
+    /// engine is dropped and every envelope received
+    pub async fn recv(&mut self) -> Result<EventEnvelope, RecvError> {
+        let received = self.receiver.recv().await;
+        if let Err(RecvError::Lagged(missed)) = received {
+            self.lagged.fetch_add(missed, Ordering::Relaxed);
+        }
+        received
+    }
+
+    /// As `recv`, but `TryRecvError::Empty` rather than waiting when no
+    /// envelope is there yet
+    pub fn try_recv(&mut self) -> Result<EventEnvelope, TryRecvError> {
+        let received = self.receiver.try_recv();
+        if let Err(TryRecvError::Lagged(missed)) = received {
+            self.lagged.fetch_add(missed, Ordering::Relaxed);
+        }
+        received
+    }
+}
+
+impl Engine {
+    /// Subscribes to every event dispatched from now on, suppressed ones
+    /// excepted, with its params rendered against the facts. A subscriber
+    /// falling more than `BROADCAST_CAPACITY` envelopes behind gets
+    /// `RecvError::Lagged` with the number of envelopes it missed
+    pub fn subscribe(&self) -> Subscription {
+        Subscription {
+            receiver: self.broadcast.subscribe(),
+            lagged: self.lagged_envelopes.clone(),
+        }
+    }
+
+    /// How many envelopes the subscribers missed for falling behind, in
+    /// total, as counted when they received `RecvError::Lagged`
+    pub fn lagged_envelopes(&self) -> u64 {
+        self.lagged_envelopes.load(Ordering::Relaxed)
+    }
+}
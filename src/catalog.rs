@@ -0,0 +1,282 @@
+//! What rules can be made of, for editors to offer: the operators of the
+//! leaves, the combinators joining them, and the event types the engine
+//! handles out of the box, as compiled.
+//!
+//! The operators are listed by a macro matching every `Constraint` variant,
+//! so one missing from here doesn't compile.
+
+use crate::{migrations::snake_case, Constraint};
+use serde::Serialize;
+
+/// An operator leaves may use, see `operators`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct OperatorInfo {
+    /// The `operator` of the leaf, e.g. `string_equals`
+    pub operator: String,
+    /// The JSON shape of the leaf's `value`, e.g. `string`, `[integer]` or
+    /// `[number, number]` for a range
+    pub value: &'static str,
+    /// The JSON types of the facts it can be met by
+    pub fact_types: Vec<&'static str>,
+    /// Whether it applies to array facts
+    pub supports_arrays: bool,
+    pub description: &'static str,
+}
+
+/// A node joining other conditions, see `combinators`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct CombinatorInfo {
+    /// The key holding the conditions it joins, e.g. `and`
+    pub key: &'static str,
+    /// `condition` for a single condition, `[condition]` for a list
+    pub children: &'static str,
+    /// Its other keys, required or not
+    pub options: Vec<&'static str>,
+    pub description: &'static str,
+}
+
+/// An event type the engine handles without registering it, see
+/// `event_types`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EventTypeInfo {
+    /// The `type` of the event
+    pub ty: &'static str,
+    /// The params it's refused without
+    pub required_params: Vec<&'static str>,
+    /// The cargo feature it comes with, empty for the ones always built in
+    pub feature: &'static str,
+    pub description: &'static str,
+}
+
+macro_rules! operators {
+    ($($(#[$meta:meta])* $variant:ident: $value:literal, [$($fact:literal),*], $description:literal;)*) => {
+        /// Never called, it only fails to compile when a `Constraint`
+        /// variant isn't catalogued
+        #[allow(dead_code)]
+        fn catalogued(constraint: &Constraint) {
+            match constraint {
+                $($(#[$meta])* Constraint::$variant { .. } => {})*
+            }
+        }
+
+        /// Every operator, in the order `Constraint` declares them
+        pub fn operators() -> Vec<OperatorInfo> {
+            vec![$($(#[$meta])* OperatorInfo {
+                operator: snake_case(stringify!($variant)),
+                value: $value,
+                fact_types: vec![$($fact),*],
+                supports_arrays: [$($fact),*].contains(&"array"),
+                description: $description,
+            }),*]
+        }
+    };
+}
+
+operators! {
+    StringEquals: "string", ["string"], "The string equals the value";
+    StringNotEquals: "string", ["string"], "The string differs from the value";
+    StringContains: "string", ["array"], "The array holds the string";
+    StringContainsAll: "[string]", ["array"], "The array holds every string";
+    StringContainsAny: "[string]", ["array"], "The array holds one of the strings";
+    StringDoesNotContain: "string", ["array"], "The array doesn't hold the string";
+    StringDoesNotContainAny: "[string]", ["array"], "The array holds none of the strings";
+    StringIn: "[string]", ["string"], "The string is one of the values";
+    StringNotIn: "[string]", ["string"], "The string is none of the values";
+    StringFuzzyMatches: "{value: string, max_distance: integer, case_sensitive?: boolean}", ["string"], "The string is at most this many edits away from the value";
+    StringSimilarityAtLeast: "{value: string, ratio: number, case_sensitive?: boolean}", ["string"], "The string is at least this similar to the value, from 0 to 1";
+    StringInNamedSet: "string", ["string"], "The string is in the named set registered on the engine";
+    StringNotInNamedSet: "string", ["string"], "The string isn't in the named set registered on the engine";
+    IntEquals: "integer", ["integer"], "The integer equals the value";
+    IntNotEquals: "integer", ["integer"], "The integer differs from the value";
+    IntContains: "integer", ["array"], "The array holds the integer";
+    IntContainsAll: "[integer]", ["array"], "The array holds every integer";
+    IntContainsAny: "[integer]", ["array"], "The array holds one of the integers";
+    IntDoesNotContain: "integer", ["array"], "The array doesn't hold the integer";
+    IntDoesNotContainAny: "[integer]", ["array"], "The array holds none of the integers";
+    IntIsSubset: "[integer]", ["array"], "Every integer of the array is one of the values";
+    IntIsSuperset: "[integer]", ["array"], "The array holds every integer of the value";
+    IntIn: "[integer]", ["integer"], "The integer is one of the values";
+    IntNotIn: "[integer]", ["integer"], "The integer is none of the values";
+    IntInNamedSet: "string", ["integer"], "The integer is in the named set registered on the engine";
+    IntNotInNamedSet: "string", ["integer"], "The integer isn't in the named set registered on the engine";
+    IntInRange: "[integer | null, integer | null]", ["integer"], "The integer is within the bounds, both included, a null one leaving that side open";
+    IntNotInRange: "[integer, integer]", ["integer"], "The integer is outside the bounds";
+    IntLessThan: "integer", ["integer"], "The integer is less than the value";
+    IntLessThanInclusive: "integer", ["integer"], "The integer is at most the value";
+    IntGreaterThan: "integer", ["integer"], "The integer is greater than the value";
+    IntGreaterThanInclusive: "integer", ["integer"], "The integer is at least the value";
+    UintEquals: "integer", ["integer"], "The unsigned integer equals the value";
+    UintNotEquals: "integer", ["integer"], "The unsigned integer differs from the value";
+    UintIn: "[integer]", ["integer"], "The unsigned integer is one of the values";
+    UintNotIn: "[integer]", ["integer"], "The unsigned integer is none of the values";
+    UintInRange: "[integer, integer]", ["integer"], "The unsigned integer is within the bounds, both included";
+    UintNotInRange: "[integer, integer]", ["integer"], "The unsigned integer is outside the bounds";
+    UintLessThan: "integer", ["integer"], "The unsigned integer is less than the value";
+    UintLessThanInclusive: "integer", ["integer"], "The unsigned integer is at most the value";
+    UintGreaterThan: "integer", ["integer"], "The unsigned integer is greater than the value";
+    UintGreaterThanInclusive: "integer", ["integer"], "The unsigned integer is at least the value";
+    FloatEquals: "number", ["number"], "The number equals the value";
+    FloatNotEquals: "number", ["number"], "The number differs from the value";
+    FloatEqualsRounded: "{value: number, decimals: integer}", ["number"], "The number equals the value once both are rounded to the decimals";
+    FloatContains: "number", ["array"], "The array holds the number";
+    FloatDoesNotContain: "number", ["array"], "The array doesn't hold the number";
+    FloatIsSubset: "[number]", ["array"], "Every number of the array is one of the values";
+    FloatIsSuperset: "[number]", ["array"], "The array holds every number of the value";
+    FloatIn: "[number]", ["number"], "The number is one of the values";
+    FloatNotIn: "[number]", ["number"], "The number is none of the values";
+    FloatInRange: "[number | null, number | null]", ["number"], "The number is within the bounds, both included, a null one leaving that side open";
+    FloatNotInRange: "[number, number]", ["number"], "The number is outside the bounds";
+    FloatLessThan: "number", ["number"], "The number is less than the value";
+    FloatLessThanInclusive: "number", ["number"], "The number is at most the value";
+    FloatGreaterThan: "number", ["number"], "The number is greater than the value";
+    FloatGreaterThanInclusive: "number", ["number"], "The number is at least the value";
+    FloatPercentileGreaterThan: "{percentile: number, value: number}", ["array"], "The percentile of the numbers of the array is greater than the value";
+    FloatPercentileLessThan: "{percentile: number, value: number}", ["array"], "The percentile of the numbers of the array is less than the value";
+    NumberEquals: "number", ["number"], "The number equals the value, integers compared exactly";
+    NumberNotEquals: "number", ["number"], "The number differs from the value";
+    NumberIn: "[number]", ["number"], "The number is one of the values";
+    NumberNotIn: "[number]", ["number"], "The number is none of the values";
+    NumberInRange: "[number, number]", ["number"], "The number is within the bounds, both included";
+    NumberNotInRange: "[number, number]", ["number"], "The number is outside the bounds";
+    NumberLessThan: "number", ["number"], "The number is less than the value";
+    NumberLessThanInclusive: "number", ["number"], "The number is at most the value";
+    NumberGreaterThan: "number", ["number"], "The number is greater than the value";
+    NumberGreaterThanInclusive: "number", ["number"], "The number is at least the value";
+    BoolEquals: "boolean", ["boolean"], "The boolean equals the value";
+    ValueEquals: "any", ["string", "number", "boolean", "array", "object", "null"], "The fact deeply equals the value, numbers compared by value";
+    ValueNotEquals: "any", ["string", "number", "boolean", "array", "object", "null"], "The fact doesn't deeply equal the value";
+    ValueIn: "[any]", ["string", "number", "boolean", "array", "object", "null"], "The fact deeply equals one of the values";
+    DatetimeWithinLast: "integer", ["string"], "The RFC 3339 datetime is at most this many seconds old";
+    DatetimeOlderThan: "integer", ["string"], "The RFC 3339 datetime is more than this many seconds old";
+    DurationGreaterThan: "string", ["string", "integer"], "The duration, ISO 8601, humanized or in seconds, is longer than the value";
+    DurationLessThan: "string", ["string", "integer"], "The duration, ISO 8601, humanized or in seconds, is shorter than the value";
+    DurationInRange: "[string, string]", ["string", "integer"], "The duration, ISO 8601, humanized or in seconds, is within the bounds";
+    ArrayAllUnique: "boolean", ["array"], "Whether the elements of the array all differ";
+    ArrayDistinctCountGreaterThanInclusive: "integer", ["array"], "The array holds at least this many distinct elements";
+    AnyMatch: "condition", ["array"], "An element of the array meets the condition";
+    NoneMatch: "condition", ["array"], "No element of the array meets the condition";
+    IsUuid: "boolean", ["string"], "Whether the string is a UUID";
+    IsUlid: "boolean", ["string"], "Whether the string is a ULID";
+    IsEmail: "boolean", ["string"], "Whether the string looks like an email address";
+    IsUrl: "boolean", ["string"], "Whether the string is an absolute URL";
+    DomainEquals: "string", ["string"], "The hostname is the domain, in any case, a trailing dot aside";
+    DomainIn: "[string]", ["string"], "The hostname is one of the domains";
+    DomainMatchesWildcard: "string", ["string"], "The hostname matches the domain, whose leading `*.` stands for one label";
+    EmailDomainIn: "[string]", ["string"], "The domain of the email address is one of the domains";
+    #[cfg(feature = "regex")]
+    StringMatches: "string", ["string"], "The regex matches the string, its named groups exposed to the event templates";
+    #[cfg(feature = "regex")]
+    StringMatchesAny: "[string]", ["string"], "One of the regexes matches the string";
+    #[cfg(feature = "regex")]
+    StringMatchesNone: "[string]", ["string"], "None of the regexes matches the string";
+}
+
+/// The nodes joining conditions
+pub fn combinators() -> Vec<CombinatorInfo> {
+    vec![
+        CombinatorInfo {
+            key: "and",
+            children: "[condition]",
+            options: vec!["label", "failure_message"],
+            description: "Every condition is met",
+        },
+        CombinatorInfo {
+            key: "or",
+            children: "[condition]",
+            options: vec!["label", "failure_message"],
+            description: "One of the conditions is met",
+        },
+        CombinatorInfo {
+            key: "not",
+            children: "condition",
+            options: vec!["label", "failure_message"],
+            description: "The condition isn't met",
+        },
+        CombinatorInfo {
+            key: "conditions",
+            children: "[condition]",
+            options: vec![
+                "should_minimum_meet",
+                "unknown_policy",
+                "label",
+                "failure_message",
+            ],
+            description: "At least `should_minimum_meet` conditions are met",
+        },
+        CombinatorInfo {
+            key: "of",
+            children: "condition",
+            options: vec![
+                "at_least",
+                "window_secs",
+                "label",
+                "failure_message",
+            ],
+            description: "The condition was met `at_least` times within the \
+                          last `window_secs`, across runs",
+        },
+    ]
+}
+
+/// The event types built in, and those of the features the crate was
+/// compiled with
+pub fn event_types() -> Vec<EventTypeInfo> {
+    vec![
+        EventTypeInfo {
+            ty: crate::event::apply_json_patch::EVENT_TYPE,
+            required_params: vec!["patch"],
+            feature: "",
+            description: "Patches the facts the next rules are evaluated \
+                          against with a JSON Patch",
+        },
+        #[cfg(feature = "callback")]
+        EventTypeInfo {
+            ty: crate::event::post_callback::EVENT_TYPE,
+            required_params: vec!["callback_url"],
+            feature: "callback",
+            description: "Posts the event and the facts to a URL",
+        },
+        #[cfg(feature = "email")]
+        EventTypeInfo {
+            ty: "email_notification",
+            required_params: vec!["to", "from", "title", "message"],
+            feature: "email",
+            description: "Sends an email through SendGrid",
+        },
+        #[cfg(feature = "discord")]
+        EventTypeInfo {
+            ty: crate::event::discord_notification::EVENT_TYPE,
+            required_params: vec!["webhook_url", "title", "message"],
+            feature: "discord",
+            description: "Posts an embed to a Discord webhook",
+        },
+        #[cfg(feature = "teams")]
+        EventTypeInfo {
+            ty: crate::event::teams_notification::EVENT_TYPE,
+            required_params: vec!["webhook_url", "title", "message"],
+            feature: "teams",
+            description: "Posts a message card to a Microsoft Teams webhook",
+        },
+        #[cfg(feature = "aws")]
+        EventTypeInfo {
+            ty: crate::event::sns_publish::EVENT_TYPE,
+            required_params: vec!["topic_arn"],
+            feature: "aws",
+            description: "Publishes the event and the facts to an SNS topic",
+        },
+        #[cfg(feature = "aws")]
+        EventTypeInfo {
+            ty: crate::event::sqs_send::EVENT_TYPE,
+            required_params: vec!["queue_url"],
+            feature: "aws",
+            description: "Sends the event and the facts to an SQS queue",
+        },
+        #[cfg(feature = "nats")]
+        EventTypeInfo {
+            ty: crate::event::nats_publish::EVENT_TYPE,
+            required_params: vec!["subject"],
+            feature: "nats",
+            description: "Publishes the event and the facts to a NATS subject",
+        },
+    ]
+}
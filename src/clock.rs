@@ -0,0 +1,7 @@
+//! The clocks the engine reads. Those of `std::time` panic on
+//! wasm32-unknown-unknown, where web-time reads the browser's instead.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub(crate) use std::time::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(target_arch = "wasm32")]
+pub(crate) use web_time::{Instant, SystemTime, UNIX_EPOCH};
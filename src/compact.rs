@@ -0,0 +1,91 @@
+//! Compact JSON representation of condition results, for storage.
+//!
+//! A result is `[version, node]`, every node being `[name, status]` or,
+//! when it has children, `[name, status, [node, ...]]`, with the status
+//! coded as `0` (met), `1` (not met) or `2` (unknown).
+
+use crate::{
+    condition::ConditionResult,
+    error::{Error, Result},
+    status::Status,
+};
+use serde_json::{json, Value};
+
+/// Version marker leading every compact result
+pub const COMPACT_FORMAT_VERSION: u64 = 1;
+
+fn status_code(status: Status) -> u64 {
+    match status {
+        Status::Met => 0,
+        Status::NotMet => 1,
+        Status::Unknown => 2,
+    }
+}
+
+fn invalid(v: &Value) -> Error {
+    Error::CompactFormatError(format!("Invalid node `{}`", v))
+}
+
+fn to_node(result: &ConditionResult) -> Value {
+    let mut node = vec![
+        Value::from(result.name.as_str()),
+        Value::from(status_code(result.status)),
+    ];
+    if !result.children.is_empty() {
+        node.push(result.children.iter().map(to_node).collect());
+    }
+
+    Value::Array(node)
+}
+
+fn from_node(v: &Value) -> Result<ConditionResult> {
+    let node = v.as_array().ok_or_else(|| invalid(v))?;
+
+    let (name, status, children) = match node.as_slice() {
+        [name, status] => (name, status, &[][..]),
+        [name, status, Value::Array(children)] => {
+            (name, status, children.as_slice())
+        }
+        _ => return Err(invalid(v)),
+    };
+
+    Ok(ConditionResult {
+        name: name.as_str().ok_or_else(|| invalid(v))?.to_owned(),
+        status: match status.as_u64() {
+            Some(0) => Status::Met,
+            Some(1) => Status::NotMet,
+            Some(2) => Status::Unknown,
+            _ => return Err(invalid(v)),
+        },
+        children: children.iter().map(from_node).collect::<Result<_>>()?,
+        error: None,
+        evaluated: true,
+        order: None,
+        used_default: false,
+        failure_message: None,
+        matched_values: Vec::new(),
+    })
+}
+
+impl ConditionResult {
+    /// Same result in the compact representation, see `from_compact_value`
+    pub fn to_compact_value(&self) -> Value {
+        json!([COMPACT_FORMAT_VERSION, to_node(self)])
+    }
+
+    /// Reads a result written by `to_compact_value`
+    pub fn from_compact_value(v: &Value) -> Result<Self> {
+        match v.as_array().map(Vec::as_slice) {
+            Some([version, node])
+                if version.as_u64() == Some(COMPACT_FORMAT_VERSION) =>
+            {
+                from_node(node)
+            }
+            Some([version, _]) => Err(Error::CompactFormatError(format!(
+                "Unsupported version `{}`",
+                version
+            ))),
+            _ => Err(invalid(v)),
+        }
+    }
+}
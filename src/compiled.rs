@@ -0,0 +1,338 @@
+//! Rules compiled ahead of their evaluation, see `Engine::build`. The rules
+//! added one by one only have their long lists hashed and their patterns
+//! compiled, see `RulePlan::lists_and_patterns`.
+//!
+//! A `RulePlan` holds what evaluating its rule would otherwise redo every
+//! time: the JSON pointers its fields address, its rhai expressions parsed
+//! into ASTs, the mustache templates of its templated values and
+//! coalescence groups, the long lists of its `*In`/`*NotIn` leaves hashed
+//! and the patterns of its `string_matches_any`/`string_matches_none` leaves
+//! compiled into regex sets. Paths, expressions and templates are looked up
+//! by their text, lists by their address, so whatever a plan misses, e.g. a
+//! variable resolved, is evaluated the naive way.
+
+#[cfg(feature = "async_predicate")]
+use crate::async_predicate::AsyncPredicateFn;
+#[cfg(feature = "regex")]
+use crate::constraint::check_patterns;
+use crate::{
+    condition::{nested_path, top_level_path, Condition, PathSyntax},
+    constraint::{Constraint, ValueOrVar},
+    error::{Error, Result},
+    event::{EscapeMode, NumberFormat},
+    limits::Limits,
+    rule::Rule,
+};
+use mustache::Template;
+#[cfg(feature = "regex")]
+use regex::RegexSet;
+#[cfg(feature = "eval")]
+use rhai::{Engine as RhaiEngine, AST};
+use serde_json::Value;
+use std::{
+    cell::RefCell,
+    collections::{HashMap, HashSet},
+};
+
+/// How `Engine::build` sets up the engine it builds. Rules referring to
+/// named sets or async predicates missing from here are refused
+#[derive(Clone, Default)]
+pub struct EngineOptions {
+    pub limits: Limits,
+    /// Sets of strings, as registered by `Engine::register_set`
+    pub sets: HashMap<String, HashSet<String>>,
+    /// Sets of integers, as registered by `Engine::register_int_set`
+    pub int_sets: HashMap<String, HashSet<i64>>,
+    /// See `Engine::set_eval_flatten_scope`
+    #[cfg(feature = "eval")]
+    pub eval_flatten_scope: bool,
+    /// See `Engine::set_rule_index`
+    pub enable_rule_index: bool,
+    /// The values the `severity` param of the events may take, any when
+    /// `None`. Templated severities are checked once rendered
+    pub allowed_severities: Option<HashSet<String>>,
+    /// See `Engine::set_root_wrap_key`
+    pub root_wrap_key: Option<String>,
+    /// See `Engine::set_strict_facts_root`
+    pub strict_facts_root: bool,
+    #[cfg(feature = "async_predicate")]
+    pub async_predicates: HashMap<String, AsyncPredicateFn>,
+    /// The server `nats_publish` events connect to on their first publish,
+    /// e.g. `nats://localhost:4222`
+    #[cfg(feature = "nats")]
+    pub nats_url: Option<String>,
+}
+
+/// The JSON pointers a field which isn't a verbatim pointer addresses
+struct FieldPaths {
+    /// When the facts have a top level key named after the field
+    top_level: String,
+    nested: String,
+}
+
+impl FieldPaths {
+    fn new(field: &str, path_syntax: PathSyntax) -> Self {
+        Self {
+            top_level: top_level_path(field),
+            nested: nested_path(field, path_syntax),
+        }
+    }
+}
+
+/// Lists of `*In`/`*NotIn` and `IntIsSubset` leaves at least this long are
+/// hashed, shorter ones being as quick to scan
+const HASHED_LIST_MIN_LEN: usize = 16;
+
+/// The key of a hashed list: its address rather than its content, which
+/// would take as long to hash as the list to scan. The rule owns its lists
+/// as long as its plan lives alongside it, and a list made while evaluating,
+/// e.g. a variable resolved, misses
+fn list_key<T>(list: &[T]) -> usize {
+    list.as_ptr() as usize
+}
+
+/// A rule compiled by `Engine::build`, or only its long lists when added
+/// otherwise, stored alongside it on the engine
+#[derive(Default)]
+pub(crate) struct RulePlan {
+    pointer_paths: HashMap<String, FieldPaths>,
+    dotted_paths: HashMap<String, FieldPaths>,
+    templates: HashMap<String, Template>,
+    #[cfg(feature = "eval")]
+    asts: HashMap<String, AST>,
+    string_sets: HashMap<usize, HashSet<String>>,
+    int_sets: HashMap<usize, HashSet<i64>>,
+    uint_sets: HashMap<usize, HashSet<u64>>,
+    #[cfg(feature = "regex")]
+    regex_sets: HashMap<usize, RegexSet>,
+}
+
+fn invalid_template(template: &str, e: mustache::Error) -> Error {
+    Error::ValidationError(format!("Invalid template `{}`: {}", template, e))
+}
+
+/// Checks every string in the params, however deeply nested, is a valid
+/// template
+fn check_params(v: &Value) -> Result<()> {
+    match v {
+        Value::String(s) => mustache::compile_str(s)
+            .map(drop)
+            .map_err(|e| invalid_template(s, e)),
+        Value::Array(xs) => xs.iter().try_for_each(check_params),
+        Value::Object(m) => m.values().try_for_each(check_params),
+        _ => Ok(()),
+    }
+}
+
+impl RulePlan {
+    /// Compiles the rule, failing on the first expression or template that
+    /// doesn't compile. The params of the events, rendered by the events
+    /// themselves, are only checked
+    pub(crate) fn compile(
+        rule: &Rule,
+        #[cfg(feature = "eval")] rhai_engine: &RhaiEngine,
+    ) -> Result<Self> {
+        let mut plan = Self::default();
+        let templates = RefCell::new(HashMap::new());
+        let compile = |s: &str| {
+            let template =
+                mustache::compile_str(s).map_err(|e| invalid_template(s, e))?;
+            templates.borrow_mut().insert(s.to_owned(), template);
+            Ok::<_, Error>(String::new())
+        };
+
+        for (_, node) in rule.conditions.nodes() {
+            if let Some(message) = node.failure_message() {
+                compile(message)?;
+            }
+
+            match node {
+                Condition::Condition {
+                    field,
+                    constraint,
+                    pointer,
+                    path_syntax,
+                    templated_value,
+                    ..
+                } => {
+                    if !pointer {
+                        let paths = match path_syntax {
+                            PathSyntax::Pointer => &mut plan.pointer_paths,
+                            PathSyntax::Dotted => &mut plan.dotted_paths,
+                        };
+                        paths.entry(field.clone()).or_insert_with(|| {
+                            FieldPaths::new(field, *path_syntax)
+                        });
+                    }
+
+                    // a variable's strings are only known once resolved
+                    match (templated_value, constraint) {
+                        (true, ValueOrVar::Value(constraint)) => {
+                            constraint.try_map_strings(compile)?;
+                        }
+                        (false, ValueOrVar::Value(constraint)) => {
+                            plan.hash_list(constraint);
+                            #[cfg(feature = "regex")]
+                            plan.compile_patterns(constraint)?;
+                        }
+                        _ => {}
+                    }
+                }
+                #[cfg(feature = "eval")]
+                Condition::Eval { expr, .. } => {
+                    let ast = rhai_engine.compile(expr).map_err(|e| {
+                        Error::ValidationError(format!(
+                            "Invalid expression `{}`: {}",
+                            expr, e
+                        ))
+                    })?;
+                    plan.asts.insert(expr.clone(), ast);
+                }
+                _ => {}
+            }
+        }
+
+        for event in &rule.events {
+            if let Some(coalescence_group) = &event.coalescence_group {
+                compile(coalescence_group)?;
+            }
+
+            EscapeMode::from_params(&event.event.params)
+                .map_err(Error::ValidationError)?;
+            NumberFormat::from_params(&event.event.params)
+                .map_err(Error::ValidationError)?;
+            event.event.params.values().try_for_each(check_params)?;
+        }
+
+        rule.facts_to_add.values().try_for_each(check_params)?;
+
+        plan.templates = templates.into_inner();
+        Ok(plan)
+    }
+
+    /// Hashes the long lists of the rule and compiles its patterns, and
+    /// nothing else, for the rules added without `Engine::build`. Unlike
+    /// compiling the rule it can't fail, patterns that don't compile being
+    /// left to the naive evaluation, which finds them `Unknown`
+    pub(crate) fn lists_and_patterns(rule: &Rule) -> Self {
+        let mut plan = Self::default();
+        for leaf in rule.conditions.leaves() {
+            if let Condition::Condition {
+                constraint: ValueOrVar::Value(constraint),
+                templated_value: false,
+                ..
+            } = leaf
+            {
+                plan.hash_list(constraint);
+                #[cfg(feature = "regex")]
+                let _ = plan.compile_patterns(constraint);
+            }
+        }
+        plan
+    }
+
+    /// The JSON pointer a field which isn't a verbatim pointer addresses in
+    /// the facts, if compiled
+    pub(crate) fn node_path(
+        &self,
+        field: &str,
+        path_syntax: PathSyntax,
+        info: &Value,
+    ) -> Option<&str> {
+        let paths = match path_syntax {
+            PathSyntax::Pointer => &self.pointer_paths,
+            PathSyntax::Dotted => &self.dotted_paths,
+        }
+        .get(field)?;
+
+        Some(if info.get(field).is_some() {
+            &paths.top_level
+        } else {
+            &paths.nested
+        })
+    }
+
+    #[cfg(feature = "eval")]
+    pub(crate) fn ast(&self, expr: &str) -> Option<&AST> {
+        self.asts.get(expr)
+    }
+
+    /// Hashes the list of an `*In`/`*NotIn` or `IntIsSubset` constraint, if
+    /// long enough
+    fn hash_list(&mut self, constraint: &Constraint) {
+        match constraint {
+            Constraint::StringIn(ss) | Constraint::StringNotIn(ss)
+                if ss.len() >= HASHED_LIST_MIN_LEN =>
+            {
+                self.string_sets
+                    .insert(list_key(ss), ss.iter().cloned().collect());
+            }
+            Constraint::IntIn(nums)
+            | Constraint::IntNotIn(nums)
+            | Constraint::IntIsSubset(nums)
+                if nums.len() >= HASHED_LIST_MIN_LEN =>
+            {
+                self.int_sets
+                    .insert(list_key(nums), nums.iter().copied().collect());
+            }
+            Constraint::UintIn(nums) | Constraint::UintNotIn(nums)
+                if nums.len() >= HASHED_LIST_MIN_LEN =>
+            {
+                self.uint_sets
+                    .insert(list_key(nums), nums.iter().copied().collect());
+            }
+            _ => {}
+        }
+    }
+
+    /// Compiles the patterns of a `StringMatchesAny`/`StringMatchesNone`
+    /// constraint into a single regex set
+    #[cfg(feature = "regex")]
+    fn compile_patterns(&mut self, constraint: &Constraint) -> Result<()> {
+        if let Constraint::StringMatchesAny(patterns)
+        | Constraint::StringMatchesNone(patterns) = constraint
+        {
+            check_patterns(patterns).map_err(Error::ValidationError)?;
+            let set = RegexSet::new(patterns)
+                .map_err(|e| Error::ValidationError(e.to_string()))?;
+            self.regex_sets.insert(list_key(patterns), set);
+        }
+        Ok(())
+    }
+
+    /// The list of a `StringIn`/`StringNotIn` leaf of the rule, hashed
+    pub(crate) fn string_set(&self, ss: &[String]) -> Option<&HashSet<String>> {
+        self.string_sets.get(&list_key(ss))
+    }
+
+    /// The list of an `IntIn`/`IntNotIn`/`IntIsSubset` leaf of the rule,
+    /// hashed
+    pub(crate) fn int_set(&self, nums: &[i64]) -> Option<&HashSet<i64>> {
+        self.int_sets.get(&list_key(nums))
+    }
+
+    /// The list of a `UintIn`/`UintNotIn` leaf of the rule, hashed
+    pub(crate) fn uint_set(&self, nums: &[u64]) -> Option<&HashSet<u64>> {
+        self.uint_sets.get(&list_key(nums))
+    }
+
+    /// The patterns of a `StringMatchesAny`/`StringMatchesNone` leaf of the
+    /// rule, compiled
+    #[cfg(feature = "regex")]
+    pub(crate) fn regex_set(&self, patterns: &[String]) -> Option<&RegexSet> {
+        self.regex_sets.get(&list_key(patterns))
+    }
+}
+
+/// Renders a template against the facts, compiling it unless the plan has it
+pub(crate) fn render(
+    plan: Option<&RulePlan>,
+    template: &str,
+    facts: &Value,
+) -> std::result::Result<String, mustache::Error> {
+    match plan.and_then(|plan| plan.templates.get(template)) {
+        Some(compiled) => compiled.render_to_string(facts),
+        None => mustache::compile_str(template)
+            .and_then(|template| template.render_to_string(facts)),
+    }
+}
@@ -1,35 +1,401 @@
-use crate::{status::Status, Constraint};
+#[cfg(feature = "unicode")]
+use crate::normalization::Normalization;
+use crate::{
+    compiled::{render, RulePlan},
+    constraint::{NamedSets, ValueOrVar},
+    frequency::{FrequencyRun, FrequencyTracker},
+    lookup::LookupCache,
+    status::Status,
+    Constraint,
+};
+use chrono::{DateTime, Utc};
 #[cfg(feature = "eval")]
-use rhai::{serde::to_dynamic, Engine, Scope};
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
-
-#[derive(Clone, Debug, Serialize, Deserialize)]
+use rhai::{serde::to_dynamic, Dynamic, Engine, EvalAltResult, Map, Scope};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::{Number, Value};
+#[cfg(feature = "regex")]
+use std::cell::RefCell;
+use std::{borrow::Cow, collections::HashMap};
+
+/// A node of a rules tree.
+///
+/// The children of `and`, `or` and `at_least` nodes are evaluated in
+/// declaration order. The engine stops at the first one deciding the status of
+/// their parent, unless tracing, see `Engine::set_trace`. Any node may have a
+/// `label`, under which its status is exposed to the `expr` conditions
+/// evaluated after it, see `Condition::Eval`. Leaves and combinators may
+/// also have a `failure_message`, rendered against the facts into their
+/// result when they aren't met, e.g. `"Orders under €50 don't ship for
+/// free"`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 #[serde(untagged)]
 pub enum Condition {
     And {
         and: Vec<Condition>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        failure_message: Option<String>,
     },
     Or {
         or: Vec<Condition>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        failure_message: Option<String>,
     },
     Not {
         not: Box<Condition>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        failure_message: Option<String>,
     },
     AtLeast {
         should_minimum_meet: usize,
         conditions: Vec<Condition>,
+        #[serde(default, skip_serializing_if = "UnknownPolicy::is_strict")]
+        unknown_policy: UnknownPolicy,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        failure_message: Option<String>,
+    },
+    /// Met on the runs its condition is met by, once it was met at least
+    /// `at_least` times within the last `window_secs` by the runs of the
+    /// same entity, this one included, see `Engine::run_keyed`. The history
+    /// is kept by the engine, evaluations outside of a run only read it
+    Frequency {
+        of: Box<Condition>,
+        at_least: u32,
+        window_secs: u64,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        failure_message: Option<String>,
     },
     Condition {
         field: String,
         #[serde(flatten)]
-        constraint: Constraint,
+        constraint: ValueOrVar,
         path: Option<String>,
+        /// Use `field` verbatim as a RFC 6901 JSON pointer
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        pointer: bool,
+        #[serde(default, skip_serializing_if = "PathSyntax::is_pointer")]
+        path_syntax: PathSyntax,
+        /// Render the string values of the constraint against the facts
+        /// before comparing, e.g. `"{{ billing_country }}"`. A value that
+        /// fails to render makes the condition `Unknown`
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        templated_value: bool,
+        /// Compared against instead of the fact when it's missing or null,
+        /// e.g. `false` for an `opt_out` flag few facts have
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        default: Option<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        failure_message: Option<String>,
+        #[cfg(feature = "unicode")]
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        normalize: Option<Normalization>,
     },
+    /// A rhai expression, seeing the facts as `facts`, the time as `now_ts`
+    /// and `now_iso`, and the status of the labeled conditions evaluated
+    /// before it, in the same node or an enclosing one, as `results`:
+    /// `true` when met, `false` when not met and `()` when unknown. A
+    /// labeled condition a short circuiting combinator skips is never in
+    /// `results`, but neither is the expression after it evaluated, so
+    /// `Engine::run` and `Condition::check_value` give the same status.
+    ///
+    /// With `Engine::set_eval_flatten_scope`, it also sees every top level
+    /// fact whose key is a valid rhai identifier as a variable, e.g. `age`
+    /// for `facts.age`. Facts named `facts`, `now_ts`, `now_iso` or
+    /// `results` are left out, and variables the expression defines shadow
+    /// the facts.
+    ///
+    /// Anything but a boolean it evaluates to, and any error it raises,
+    /// makes it unknown, with the error in the result
     #[cfg(feature = "eval")]
     Eval {
         expr: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
     },
+    /// A Lua script, seeing the facts as `facts`, met when it returns `true`
+    /// and not met when it returns `false`. Anything else it returns, and
+    /// any error it raises, makes it unknown with the error in the result
+    #[cfg(feature = "lua")]
+    LuaEval {
+        script: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+    },
+    /// A predicate registered with `Engine::add_async_predicate`, called
+    /// with `{"args": args, "facts": facts}`. Only `Engine::run` and
+    /// `Engine::dispatch_delayed` await predicates, elsewhere it's unknown,
+    /// as it is when the predicate fails or times out
+    #[cfg(feature = "async_predicate")]
+    AsyncPredicate {
+        #[serde(rename = "async_predicate")]
+        name: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        args: Option<Value>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        label: Option<String>,
+    },
+}
+
+/// Outcome of the async predicate calls of a run, by predicate name and
+/// serialized args
+#[cfg(feature = "async_predicate")]
+pub(crate) type PredicateResults =
+    HashMap<(String, String), std::result::Result<bool, String>>;
+
+/// Key of an async predicate call in `PredicateResults`
+#[cfg(feature = "async_predicate")]
+pub(crate) fn predicate_key(
+    name: &str,
+    args: &Option<Value>,
+) -> (String, String) {
+    (
+        name.to_owned(),
+        args.as_ref().map(Value::to_string).unwrap_or_default(),
+    )
+}
+
+/// Engine state a condition may need while being evaluated
+pub(crate) struct EvalContext<'a> {
+    #[cfg(feature = "eval")]
+    pub(crate) rhai_engine: &'a Engine,
+    /// Expose the top level facts to expressions as variables, see
+    /// `Engine::set_eval_flatten_scope`
+    #[cfg(feature = "eval")]
+    pub(crate) flatten_scope: bool,
+    pub(crate) sets: &'a NamedSets,
+    /// The named groups of the regexes matched so far, by the label or the
+    /// field of their leaf
+    #[cfg(feature = "regex")]
+    pub(crate) captures: &'a RefCell<serde_json::Map<String, Value>>,
+    pub(crate) variables: &'a HashMap<String, Value>,
+    pub(crate) now: DateTime<Utc>,
+    /// Status of the labeled conditions evaluated so far
+    pub(crate) results: &'a HashMap<String, Status>,
+    /// Stop evaluating a combinator's children once its status is decided
+    pub(crate) short_circuit: bool,
+    /// Evaluate every node and annotate the results, see `Engine::set_trace`
+    pub(crate) trace: bool,
+    #[cfg(feature = "async_predicate")]
+    pub(crate) predicates: &'a PredicateResults,
+    /// The rule compiled ahead, see `Engine::build`
+    pub(crate) plan: Option<&'a RulePlan>,
+    /// The history of the frequency nodes, none outside of an engine
+    pub(crate) frequencies: Option<&'a FrequencyTracker>,
+    /// Set when the frequency nodes met are to be recorded
+    pub(crate) frequency_run: Option<&'a FrequencyRun>,
+    /// Collect at most this many elements of the array facts each leaf
+    /// matched, see `Engine::set_collect_matches`
+    pub(crate) max_matched_values: Option<usize>,
+    /// The key the elements `any_match` and `none_match` evaluate see the
+    /// whole facts under, and the facts, see `Engine::set_element_alias`
+    pub(crate) element_root: Option<(&'a str, &'a Value)>,
+    /// The nodes of the facts the leaves address, resolved once per run
+    pub(crate) lookups: Option<&'a LookupCache<'a>>,
+}
+
+/// How a condition's `field` addresses the facts, unless it's flagged as a
+/// verbatim `pointer`
+#[derive(
+    Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum PathSyntax {
+    /// `a/b/c`, with an optional leading slash. A field naming an existing
+    /// top level key, slashes included, addresses that key
+    #[default]
+    Pointer,
+    /// `a.b.c`
+    Dotted,
+}
+
+impl PathSyntax {
+    fn is_pointer(&self) -> bool {
+        *self == PathSyntax::Pointer
+    }
+}
+
+/// How an `at_least` node counts its `Unknown` children
+#[derive(
+    Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum UnknownPolicy {
+    /// As not met, the node being either met or not met
+    #[default]
+    Strict,
+    /// As possibly met, the node being `Unknown` when not enough children
+    /// are met but enough could be with the unknown ones
+    Optimistic,
+}
+
+impl UnknownPolicy {
+    fn is_strict(&self) -> bool {
+        *self == UnknownPolicy::Strict
+    }
+}
+
+/// Escapes `~` and `/` in a single JSON pointer reference token
+fn escape_token(token: &str) -> String {
+    token.replace('~', "~0").replace('/', "~1")
+}
+
+/// The JSON pointer to the top level key named after the field
+pub(crate) fn top_level_path(field: &str) -> String {
+    format!("/{}", escape_token(field))
+}
+
+pub(crate) fn node_path(
+    field: &str,
+    pointer: bool,
+    path_syntax: PathSyntax,
+    info: &Value,
+) -> String {
+    if pointer {
+        return field.to_owned();
+    }
+
+    if info.get(field).is_some() {
+        return top_level_path(field);
+    }
+
+    nested_path(field, path_syntax)
+}
+
+/// The JSON pointer a field addresses when the facts have no top level key
+/// named after it
+pub(crate) fn nested_path(field: &str, path_syntax: PathSyntax) -> String {
+    match path_syntax {
+        PathSyntax::Pointer if field.starts_with('/') => field.to_owned(),
+        PathSyntax::Pointer => format!("/{}", field),
+        PathSyntax::Dotted => field
+            .split('.')
+            .map(|token| format!("/{}", escape_token(token)))
+            .collect(),
+    }
+}
+
+/// Names `expr` conditions see whatever the facts
+#[cfg(feature = "eval")]
+const RESERVED_SCOPE_NAMES: &[&str] =
+    &["facts", "now_ts", "now_iso", "results"];
+
+/// Whether rhai accepts the name as a variable: ASCII letters, digits and
+/// underscores, starting with a letter once leading underscores are
+/// skipped
+#[cfg(feature = "eval")]
+fn is_identifier(name: &str) -> bool {
+    let mut alphabetic = false;
+    for c in name.chars() {
+        match c {
+            '_' => {}
+            _ if c.is_ascii_alphabetic() => alphabetic = true,
+            _ if alphabetic && c.is_ascii_digit() => {}
+            _ => return false,
+        }
+    }
+    alphabetic
+}
+
+/// Pushes every top level fact named as a rhai variable, the reserved names
+/// aside, as a constant of its own
+#[cfg(feature = "eval")]
+fn push_top_level_facts(scope: &mut Scope, info: &Value) {
+    let facts = match info.as_object() {
+        Some(facts) => facts,
+        None => return,
+    };
+
+    for (name, value) in facts {
+        if !is_identifier(name) || RESERVED_SCOPE_NAMES.contains(&name.as_str())
+        {
+            continue;
+        }
+        if let Ok(value) = to_dynamic(value) {
+            scope.push_constant_dynamic(name.as_str(), value);
+        }
+    }
+}
+
+/// How many of a combinator's children evaluated so far have each status
+#[derive(Debug, Default, Clone, Copy)]
+struct Tally {
+    met: usize,
+    not_met: usize,
+    unknown: usize,
+}
+
+impl Tally {
+    fn of(results: &[ConditionResult]) -> Self {
+        let mut tally = Tally::default();
+        for r in results {
+            tally.push(r.status);
+        }
+        tally
+    }
+
+    fn push(&mut self, status: Status) {
+        match status {
+            Status::Met => self.met += 1,
+            Status::NotMet => self.not_met += 1,
+            Status::Unknown => self.unknown += 1,
+        }
+    }
+
+    fn len(self) -> usize {
+        self.met + self.not_met + self.unknown
+    }
+
+    /// The children's statuses folded with `&`
+    fn and(self) -> Status {
+        if self.not_met > 0 {
+            Status::NotMet
+        } else if self.unknown > 0 {
+            Status::Unknown
+        } else {
+            Status::Met
+        }
+    }
+
+    /// The children's statuses folded with `|`
+    fn or(self) -> Status {
+        if self.met > 0 {
+            Status::Met
+        } else if self.unknown > 0 {
+            Status::Unknown
+        } else {
+            Status::NotMet
+        }
+    }
+}
+
+/// What evaluating a leaf gives, its name aside
+struct Leaf {
+    status: Status,
+    error: Option<String>,
+    used_default: bool,
+    matched_values: Vec<Value>,
+}
+
+impl Leaf {
+    fn status(status: Status, error: Option<String>) -> Self {
+        Leaf {
+            status,
+            error,
+            used_default: false,
+            matched_values: Vec::new(),
+        }
+    }
 }
 
 impl Condition {
@@ -40,113 +406,551 @@ impl Condition {
         info: &Value,
         #[cfg(feature = "eval")] rhai_engine: &Engine,
     ) -> ConditionResult {
+        self.check_value_with(
+            info,
+            &EvalContext {
+                #[cfg(feature = "eval")]
+                rhai_engine,
+                #[cfg(feature = "eval")]
+                flatten_scope: false,
+                sets: &NamedSets::default(),
+                #[cfg(feature = "regex")]
+                captures: &RefCell::default(),
+                variables: &HashMap::new(),
+                now: Utc::now(),
+                results: &HashMap::new(),
+                short_circuit: false,
+                trace: false,
+                #[cfg(feature = "async_predicate")]
+                predicates: &PredicateResults::new(),
+                plan: None,
+                frequencies: None,
+                frequency_run: None,
+                max_matched_values: None,
+                element_root: None,
+                lookups: None,
+            },
+        )
+    }
+
+    /// Same status as `check_value`, without building the result tree: no
+    /// node names nor results are allocated, and combinators stop at the
+    /// first child deciding their status
+    pub fn check_status(
+        &self,
+        info: &Value,
+        #[cfg(feature = "eval")] rhai_engine: &Engine,
+    ) -> Status {
+        self.check_status_with(
+            info,
+            &EvalContext {
+                #[cfg(feature = "eval")]
+                rhai_engine,
+                #[cfg(feature = "eval")]
+                flatten_scope: false,
+                sets: &NamedSets::default(),
+                #[cfg(feature = "regex")]
+                captures: &RefCell::default(),
+                variables: &HashMap::new(),
+                now: Utc::now(),
+                results: &HashMap::new(),
+                short_circuit: true,
+                trace: false,
+                #[cfg(feature = "async_predicate")]
+                predicates: &PredicateResults::new(),
+                plan: None,
+                frequencies: None,
+                frequency_run: None,
+                max_matched_values: None,
+                element_root: None,
+                lookups: None,
+            },
+        )
+    }
+
+    /// The status `check_value_with` gives the tree, always short circuiting
+    /// and never tracing. The matched values aren't collected, and the regex
+    /// captures are recorded as the full evaluation records them
+    pub(crate) fn check_status_with(
+        &self,
+        info: &Value,
+        ctx: &EvalContext,
+    ) -> Status {
+        struct Frame<'c> {
+            node: &'c Condition,
+            children: &'c [Condition],
+            tally: Tally,
+            labels: HashMap<String, Status>,
+        }
+
+        let ctx = &EvalContext {
+            max_matched_values: None,
+            ..*ctx
+        };
+        let mut stack: Vec<Frame> = Vec::new();
+        let mut next = self;
+        loop {
+            let labels = stack.last().map_or(ctx.results, |f| &f.labels);
+            let mut status = match next.children() {
+                Some(children) => {
+                    let labels = labels.clone();
+                    stack.push(Frame {
+                        node: next,
+                        children,
+                        tally: Tally::default(),
+                        labels,
+                    });
+                    None
+                }
+                None => Some(
+                    next.evaluate_leaf(
+                        info,
+                        &EvalContext {
+                            results: labels,
+                            ..*ctx
+                        },
+                    )
+                    .status,
+                ),
+            };
+
+            // hand the status to the parents, for as long as it completes
+            // them
+            loop {
+                let frame = match stack.last_mut() {
+                    Some(frame) => frame,
+                    None => return status.unwrap(),
+                };
+
+                if let Some(status) = status.take() {
+                    if let Some(label) =
+                        frame.children[frame.tally.len()].label()
+                    {
+                        frame.labels.insert(label.to_owned(), status);
+                    }
+                    frame.tally.push(status);
+                }
+
+                if frame.tally.len() < frame.children.len()
+                    && !frame.node.decided(frame.tally)
+                {
+                    next = &frame.children[frame.tally.len()];
+                    break;
+                }
+
+                let frame = stack.pop().unwrap();
+                status = Some(frame.node.combined_status(frame.tally, ctx));
+            }
+        }
+    }
+
+    /// Evaluates the tree with an explicit stack rather than recursion, so
+    /// however deep it is it can't overflow the call stack.
+    ///
+    /// When short circuiting, combinators stop evaluating their children once
+    /// their status is decided, unless tracing, where every node is evaluated
+    /// and the ones that would have been skipped are flagged as not
+    /// `evaluated`
+    pub(crate) fn check_value_with(
+        &self,
+        info: &Value,
+        ctx: &EvalContext,
+    ) -> ConditionResult {
+        struct Frame<'c> {
+            node: &'c Condition,
+            children: &'c [Condition],
+            results: Vec<ConditionResult>,
+            tally: Tally,
+            labels: HashMap<String, Status>,
+            order: u32,
+            skipped: bool,
+        }
+
+        let mut stack: Vec<Frame> = Vec::new();
+        let mut next = self;
+        let mut skipped = false;
+        let mut visited = 0;
+        loop {
+            let order = visited;
+            visited += 1;
+
+            let labels = stack.last().map_or(ctx.results, |f| &f.labels);
+            let mut res = match next.children() {
+                Some(children) => {
+                    let labels = labels.clone();
+                    stack.push(Frame {
+                        node: next,
+                        children,
+                        results: Vec::with_capacity(children.len()),
+                        tally: Tally::default(),
+                        labels,
+                        order,
+                        skipped,
+                    });
+                    None
+                }
+                None => {
+                    let mut res = next.check_leaf(
+                        info,
+                        &EvalContext {
+                            results: labels,
+                            ..*ctx
+                        },
+                    );
+                    next.fail_with_message(&mut res, info, ctx);
+                    if ctx.trace {
+                        res.evaluated = !skipped;
+                        res.order = Some(order);
+                    }
+                    Some(res)
+                }
+            };
+
+            // hand the result to the parents, for as long as it completes them
+            loop {
+                let frame = match stack.last_mut() {
+                    Some(frame) => frame,
+                    None => return res.unwrap(),
+                };
+
+                if let Some(res) = res.take() {
+                    if let Some(label) =
+                        frame.children[frame.results.len()].label()
+                    {
+                        frame.labels.insert(label.to_owned(), res.status);
+                    }
+                    frame.tally.push(res.status);
+                    frame.results.push(res);
+                }
+
+                let decided = frame.node.decided(frame.tally);
+                if frame.results.len() < frame.children.len()
+                    && (ctx.trace || !ctx.short_circuit || !decided)
+                {
+                    next = &frame.children[frame.results.len()];
+                    skipped = frame.skipped || decided;
+                    break;
+                }
+
+                let frame = stack.pop().unwrap();
+                let mut combined = frame.node.combine(frame.results, ctx);
+                frame.node.fail_with_message(&mut combined, info, ctx);
+                if ctx.trace {
+                    combined.evaluated = !frame.skipped;
+                    combined.order = Some(frame.order);
+                }
+                res = Some(combined);
+            }
+        }
+    }
+
+    /// Whether the children evaluated so far decide the combinator's status,
+    /// whatever the status of the others
+    fn decided(&self, tally: Tally) -> bool {
         match *self {
-            Condition::And { ref and } => {
-                let mut status = Status::Met;
-                let children = and
-                    .iter()
-                    .map(|c| {
-                        c.check_value(
-                            info,
-                            #[cfg(feature = "eval")]
-                            rhai_engine,
-                        )
-                    })
-                    .inspect(|r| status = status & r.status)
-                    .collect::<Vec<_>>();
+            Condition::And { .. } => tally.not_met > 0,
+            Condition::Or { .. } => tally.met > 0,
+            Condition::AtLeast {
+                should_minimum_meet,
+                ref conditions,
+                unknown_policy,
+                ..
+            } => {
+                let Tally { met, unknown, .. } = tally;
+                let left = conditions.len() - tally.len();
+
+                // met, or out of reach of the children left, and when
+                // optimistic either unknown already or out of reach of
+                // being so
+                met >= should_minimum_meet
+                    || met + left < should_minimum_meet
+                        && (unknown_policy.is_strict()
+                            || met + unknown >= should_minimum_meet
+                            || met + unknown + left < should_minimum_meet)
+            }
+            _ => false,
+        }
+    }
+
+    /// The children of a combinator, `None` for leaves
+    pub(crate) fn children(&self) -> Option<&[Condition]> {
+        match self {
+            Condition::And { and: cs, .. }
+            | Condition::Or { or: cs, .. }
+            | Condition::AtLeast { conditions: cs, .. } => Some(cs),
+            Condition::Not { not, .. }
+            | Condition::Frequency { of: not, .. } => {
+                Some(std::slice::from_ref(not))
+            }
+            _ => None,
+        }
+    }
+
+    /// The status of a combinator, from the statuses of its children
+    /// evaluated so far
+    fn combined_status(&self, tally: Tally, ctx: &EvalContext) -> Status {
+        match *self {
+            Condition::And { .. } => tally.and(),
+            // its only child
+            Condition::Not { .. } => !tally.and(),
+            Condition::Or { .. } => tally.or(),
+            Condition::Frequency { .. } => {
+                self.frequency_status(tally.and(), ctx).0
+            }
+            Condition::AtLeast {
+                should_minimum_meet,
+                unknown_policy,
+                ..
+            } => {
+                if tally.met >= should_minimum_meet {
+                    Status::Met
+                } else if unknown_policy == UnknownPolicy::Optimistic
+                    && tally.met + tally.unknown >= should_minimum_meet
+                {
+                    Status::Unknown
+                } else {
+                    Status::NotMet
+                }
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    /// The status of a frequency node whose condition has this status, and
+    /// how many times it was met within the window, recording this time
+    /// when evaluating a run
+    fn frequency_status(
+        &self,
+        status: Status,
+        ctx: &EvalContext,
+    ) -> (Status, usize) {
+        let (at_least, window_secs) = match *self {
+            Condition::Frequency {
+                at_least,
+                window_secs,
+                ..
+            } => (at_least, window_secs),
+            _ => unreachable!(),
+        };
+        let count = match ctx.frequencies {
+            Some(frequencies) if status != Status::Unknown => frequencies
+                .count(
+                    &serde_json::to_string(self).unwrap_or_default(),
+                    ctx.frequency_run,
+                    status == Status::Met,
+                    ctx.now.timestamp_millis(),
+                    window_secs.saturating_mul(1000) as i64,
+                    at_least as usize,
+                ),
+            _ => usize::from(status == Status::Met),
+        };
+        let status = match status {
+            Status::Met if count >= at_least as usize => Status::Met,
+            Status::Unknown => Status::Unknown,
+            _ => Status::NotMet,
+        };
+        (status, count)
+    }
+
+    /// Aggregates the results of a combinator's children, in declaration
+    /// order, which may stop short of its last child once it was decided
+    fn combine(
+        &self,
+        mut children: Vec<ConditionResult>,
+        ctx: &EvalContext,
+    ) -> ConditionResult {
+        let tally = Tally::of(&children);
+        match *self {
+            Condition::And { .. } => {
+                let status = self.combined_status(tally, ctx);
 
                 ConditionResult {
                     name: "And".into(),
                     status,
                     children,
+                    error: None,
+                    evaluated: true,
+                    order: None,
+                    used_default: false,
+                    failure_message: None,
+                    matched_values: Vec::new(),
                 }
             }
-            Condition::Not { not: ref c } => {
-                let res = c.check_value(
-                    info,
-                    #[cfg(feature = "eval")]
-                    rhai_engine,
-                );
+            Condition::Not { .. } => {
+                let res = children.pop().unwrap();
 
+                // its child's result is merged into its own, error included
                 ConditionResult {
                     name: "Not".into(),
                     status: !res.status,
                     children: res.children,
+                    error: res.error,
+                    evaluated: true,
+                    order: None,
+                    used_default: false,
+                    failure_message: None,
+                    matched_values: Vec::new(),
                 }
             }
-            Condition::Or { ref or } => {
-                let mut status = Status::NotMet;
-                let children = or
-                    .iter()
-                    .map(|c| {
-                        c.check_value(
-                            info,
-                            #[cfg(feature = "eval")]
-                            rhai_engine,
-                        )
-                    })
-                    .inspect(|r| status = status | r.status)
-                    .collect::<Vec<_>>();
+            Condition::Or { .. } => {
+                let status = self.combined_status(tally, ctx);
 
                 ConditionResult {
                     name: "Or".into(),
                     status,
                     children,
+                    error: None,
+                    evaluated: true,
+                    order: None,
+                    used_default: false,
+                    failure_message: None,
+                    matched_values: Vec::new(),
+                }
+            }
+            Condition::Frequency {
+                at_least,
+                window_secs,
+                ..
+            } => {
+                let res = children.pop().unwrap();
+                let (status, count) = self.frequency_status(res.status, ctx);
+
+                ConditionResult {
+                    name: format!(
+                        "Met {} of {} times within {}s",
+                        count, at_least, window_secs
+                    ),
+                    status,
+                    children: vec![res],
+                    error: None,
+                    evaluated: true,
+                    order: None,
+                    used_default: false,
+                    failure_message: None,
+                    matched_values: Vec::new(),
                 }
             }
             Condition::AtLeast {
                 should_minimum_meet,
                 ref conditions,
+                ..
             } => {
-                let mut met_count = 0;
-                let children = conditions
-                    .iter()
-                    .map(|c| {
-                        c.check_value(
-                            info,
-                            #[cfg(feature = "eval")]
-                            rhai_engine,
-                        )
-                    })
-                    .inspect(|r| {
-                        if r.status == Status::Met {
-                            met_count += 1;
-                        }
-                    })
-                    .collect::<Vec<_>>();
-
-                let status = if met_count >= should_minimum_meet {
-                    Status::Met
-                } else {
-                    Status::NotMet
-                };
+                let status = self.combined_status(tally, ctx);
 
                 ConditionResult {
                     name: format!(
-                        "At least meet {} of {}",
+                        "At least meet {} of {} ({} met, {} unknown)",
                         should_minimum_meet,
-                        conditions.len()
+                        conditions.len(),
+                        tally.met,
+                        tally.unknown
                     ),
                     status,
                     children,
+                    error: None,
+                    evaluated: true,
+                    order: None,
+                    used_default: false,
+                    failure_message: None,
+                    matched_values: Vec::new(),
                 }
             }
+            _ => unreachable!(),
+        }
+    }
+
+    fn check_leaf(&self, info: &Value, ctx: &EvalContext) -> ConditionResult {
+        let Leaf {
+            status,
+            error,
+            used_default,
+            matched_values,
+        } = self.evaluate_leaf(info, ctx);
+        ConditionResult {
+            name: self.leaf_name(),
+            status,
+            children: Vec::new(),
+            error,
+            evaluated: true,
+            order: None,
+            used_default,
+            failure_message: None,
+            matched_values,
+        }
+    }
+
+    /// The name of a leaf's result
+    fn leaf_name(&self) -> String {
+        match self {
+            Condition::Condition { field, .. } => field.to_owned(),
+            #[cfg(feature = "eval")]
+            Condition::Eval { .. } => "Eval".to_owned(),
+            #[cfg(feature = "lua")]
+            Condition::LuaEval { .. } => "LuaEval".to_owned(),
+            #[cfg(feature = "async_predicate")]
+            Condition::AsyncPredicate { name, .. } => name.clone(),
+            _ => unreachable!(),
+        }
+    }
+
+    fn evaluate_leaf(&self, info: &Value, ctx: &EvalContext) -> Leaf {
+        match *self {
             #[allow(unused_variables)]
             Condition::Condition {
                 ref field,
                 ref constraint,
                 ref path,
+                pointer,
+                path_syntax,
+                templated_value,
+                ref default,
+                ref label,
+                #[cfg(feature = "unicode")]
+                ref normalize,
+                ..
             } => {
-                let node_path = if field.starts_with('/') {
-                    field.to_owned()
-                } else {
-                    format!("/{}", field)
+                let constraint = match constraint.resolve(ctx.variables) {
+                    Ok(constraint) => constraint,
+                    Err(e) => return Leaf::status(Status::Unknown, Some(e)),
+                };
+                let constraint = &*constraint;
+
+                let compiled_path = match (pointer, ctx.plan) {
+                    (false, Some(plan)) => {
+                        plan.node_path(field, path_syntax, info)
+                    }
+                    _ => None,
+                };
+                let owned_path;
+                let node_path = match compiled_path {
+                    Some(path) => path,
+                    None => {
+                        owned_path =
+                            node_path(field, pointer, path_syntax, info);
+                        &owned_path
+                    }
                 };
 
                 let mut status = Status::Unknown;
+                let mut matched_values = Vec::new();
+
+                let mut node = match ctx
+                    .lookups
+                    .and_then(|lookups| lookups.get(info, node_path))
+                {
+                    Some(node) => node,
+                    None => info.pointer(node_path),
+                };
+                let used_default =
+                    default.is_some() && node.is_none_or(Value::is_null);
+                if used_default {
+                    node = default.as_ref();
+                }
 
+                // borrowed unless a path or a normalization rewrites it, as
+                // facts may be large arrays
                 #[allow(unused_mut)]
-                if let Some(mut node) = info.pointer(&node_path).cloned() {
+                if let Some(mut node) = node.map(Cow::Borrowed) {
                     #[cfg(feature = "path")]
                     {
                         if let Some(p) = path {
@@ -155,46 +959,405 @@ impl Condition {
                                 .into_iter()
                                 .cloned()
                                 .collect();
-                            node = Value::Array(x);
+                            node = Cow::Owned(Value::Array(x));
                         }
                     }
 
-                    status = constraint.check_value(&node);
+                    let templated;
+                    let constraint = if templated_value {
+                        match constraint
+                            .try_map_strings(|s| render(ctx.plan, s, info))
+                        {
+                            Ok(constraint) => {
+                                templated = constraint;
+                                &templated
+                            }
+                            Err(_) => {
+                                return Leaf::status(Status::Unknown, None)
+                            }
+                        }
+                    } else {
+                        constraint
+                    };
+
+                    #[cfg(feature = "unicode")]
+                    let normalized;
+                    #[cfg(feature = "unicode")]
+                    let constraint = match normalize {
+                        Some(form) => {
+                            node = Cow::Owned(form.normalize_value(&node));
+                            normalized = constraint
+                                .map_strings(|s| form.normalize_str(s));
+                            &normalized
+                        }
+                        None => constraint,
+                    };
+
+                    status = constraint.check_value_with(&node, ctx);
+                    if let Some(max) = ctx.max_matched_values {
+                        matched_values =
+                            constraint.matched_values(&node, ctx, max);
+                    }
+
+                    #[cfg(feature = "regex")]
+                    if let Some(captures) = constraint.captures(&node) {
+                        let key = label.as_ref().unwrap_or(field);
+                        ctx.captures
+                            .borrow_mut()
+                            .insert(key.clone(), Value::Object(captures));
+                    }
                 }
 
-                ConditionResult {
-                    name: field.to_owned(),
+                Leaf {
                     status,
-                    children: Vec::new(),
+                    error: None,
+                    used_default,
+                    matched_values,
                 }
             }
             #[cfg(feature = "eval")]
-            Condition::Eval { ref expr } => {
+            Condition::Eval { ref expr, .. } => {
                 let mut scope = Scope::new();
+                if ctx.flatten_scope {
+                    push_top_level_facts(&mut scope, info);
+                }
                 if let Ok(val) = to_dynamic(info) {
                     scope.push_dynamic("facts", val);
                 }
-                let status = if rhai_engine
-                    .eval_with_scope::<bool>(&mut scope, expr)
-                    .unwrap_or(false)
-                {
-                    Status::Met
-                } else {
-                    Status::NotMet
+                scope.push_constant("now_ts", ctx.now.timestamp());
+                scope.push_constant("now_iso", ctx.now.to_rfc3339());
+                scope.push_constant(
+                    "results",
+                    ctx.results
+                        .iter()
+                        .map(|(label, status)| {
+                            let status = match status {
+                                Status::Met => Dynamic::from(true),
+                                Status::NotMet => Dynamic::from(false),
+                                Status::Unknown => Dynamic::UNIT,
+                            };
+                            (label.as_str().into(), status)
+                        })
+                        .collect::<Map>(),
+                );
+                let outcome = match ctx.plan.and_then(|plan| plan.ast(expr)) {
+                    Some(ast) => ctx
+                        .rhai_engine
+                        .eval_ast_with_scope::<bool>(&mut scope, ast),
+                    None => ctx
+                        .rhai_engine
+                        .eval_with_scope::<bool>(&mut scope, expr),
+                };
+                let (status, error) = match outcome {
+                    Ok(true) => (Status::Met, None),
+                    Ok(false) => (Status::NotMet, None),
+                    // the `Err` of a fallible function, as it returned it
+                    Err(e) => match *e {
+                        EvalAltResult::ErrorRuntime(ref message, _) => {
+                            (Status::Unknown, Some(message.to_string()))
+                        }
+                        ref e => (Status::Unknown, Some(e.to_string())),
+                    },
                 };
 
-                ConditionResult {
-                    name: "Eval".to_owned(),
-                    status,
-                    children: Vec::new(),
+                Leaf::status(status, error)
+            }
+            #[cfg(feature = "lua")]
+            Condition::LuaEval { ref script, .. } => {
+                let (status, error) = match crate::lua::eval(script, info) {
+                    Ok(true) => (Status::Met, None),
+                    Ok(false) => (Status::NotMet, None),
+                    Err(e) => (Status::Unknown, Some(e)),
+                };
+
+                Leaf::status(status, error)
+            }
+            #[cfg(feature = "async_predicate")]
+            Condition::AsyncPredicate {
+                ref name, ref args, ..
+            } => {
+                let (status, error) =
+                    match ctx.predicates.get(&predicate_key(name, args)) {
+                        Some(Ok(true)) => (Status::Met, None),
+                        Some(Ok(false)) => (Status::NotMet, None),
+                        Some(Err(e)) => (Status::Unknown, Some(e.clone())),
+                        None => (
+                            Status::Unknown,
+                            Some(
+                                "Async predicates are only awaited by the \
+                                 engine's async methods"
+                                    .to_owned(),
+                            ),
+                        ),
+                    };
+
+                Leaf::status(status, error)
+            }
+            _ => unreachable!(),
+        }
+    }
+}
+
+/// Deserializes the conditions of a rule. `Condition` being untagged, serde
+/// only tells they match none of its variants, so the error of the leaf at
+/// fault is reported instead when there's one, e.g. a duration that
+/// doesn't parse
+pub(crate) fn deserialize_conditions<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Condition, D::Error> {
+    let v = Value::deserialize(deserializer)?;
+    Condition::deserialize(&v)
+        .map_err(|e| de::Error::custom(leaf_error(&v).unwrap_or(e)))
+}
+
+/// The error of the first leaf under the node failing to deserialize,
+/// depth-first
+fn leaf_error(v: &Value) -> Option<serde_json::Error> {
+    let obj = v.as_object()?;
+    if obj.contains_key("field") {
+        // the nested conditions of `any_match`/`none_match` first
+        let nested = match obj.get("operator").and_then(Value::as_str) {
+            Some("any_match" | "none_match") => obj.get("value"),
+            _ => None,
+        };
+        return nested
+            .and_then(leaf_error)
+            .or_else(|| ValueOrVar::deserialize(v).err());
+    }
+
+    ["and", "or", "conditions", "not", "of"]
+        .iter()
+        .filter_map(|key| obj.get(*key))
+        .flat_map(|children| match children {
+            Value::Array(children) => children.iter().collect(),
+            child => vec![child],
+        })
+        .find_map(leaf_error)
+}
+
+/// A fact a rule refers to, see `Condition::referenced_fields`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FieldRef {
+    /// JSON pointer to the fact. A field which isn't a verbatim pointer is
+    /// given the pointer it addresses when the facts have no top level key
+    /// named after it
+    pub pointer: String,
+    /// The operator of the leaf, `None` for a field read by an expression
+    pub operator: Option<String>,
+    /// Found in an expression or a script, which may read facts in ways
+    /// that can't be told without running it, or seem to read some it
+    /// doesn't, e.g. in a string literal
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub approximate: bool,
+}
+
+/// The `facts.a.b` chains in an expression or a script, each once, in
+/// order of appearance
+#[cfg(any(feature = "eval", feature = "lua"))]
+fn script_fields(script: &str) -> Vec<FieldRef> {
+    let is_ident = |c: char| c.is_ascii_alphanumeric() || c == '_';
+    let mut pointers = Vec::new();
+    for (start, _) in script.match_indices("facts.") {
+        // `myfacts.a`, `x.facts.a`
+        if script[..start]
+            .chars()
+            .next_back()
+            .is_some_and(|c| is_ident(c) || c == '.')
+        {
+            continue;
+        }
+
+        let mut pointer = String::new();
+        let mut rest = &script[start + "facts".len()..];
+        while let Some(chain) = rest.strip_prefix('.') {
+            let end = chain.find(|c| !is_ident(c)).unwrap_or(chain.len());
+            if end == 0 {
+                break;
+            }
+            pointer.push('/');
+            pointer.push_str(&escape_token(&chain[..end]));
+            rest = &chain[end..];
+        }
+
+        if !pointer.is_empty() && !pointers.contains(&pointer) {
+            pointers.push(pointer);
+        }
+    }
+
+    pointers
+        .into_iter()
+        .map(|pointer| FieldRef {
+            pointer,
+            operator: None,
+            approximate: true,
+        })
+        .collect()
+}
+
+impl Condition {
+    /// Every fact the tree refers to, leaf by leaf, depth-first, along with
+    /// the facts its expressions and scripts read as `facts.a.b`. The
+    /// conditions nested in `any_match` and `none_match` constraints see the
+    /// elements of an array rather than the facts, so only the array counts
+    pub fn referenced_fields(&self) -> Vec<FieldRef> {
+        let mut fields = Vec::new();
+        for (_, node) in self.nodes() {
+            match node {
+                Condition::Condition {
+                    field,
+                    constraint,
+                    pointer,
+                    path_syntax,
+                    ..
+                } => fields.push(FieldRef {
+                    pointer: if *pointer {
+                        field.clone()
+                    } else {
+                        nested_path(field, *path_syntax)
+                    },
+                    operator: Some(constraint.operator().to_owned()),
+                    approximate: false,
+                }),
+                #[cfg(feature = "eval")]
+                Condition::Eval { expr, .. } => {
+                    fields.extend(script_fields(expr))
                 }
+                #[cfg(feature = "lua")]
+                Condition::LuaEval { script, .. } => {
+                    fields.extend(script_fields(script))
+                }
+                _ => {}
+            }
+        }
+        fields
+    }
+
+    /// Adds the JSON pointers to the missing facts which could decide the
+    /// node, given its traced result, to `missing`: those of the leaves
+    /// under its `Unknown` nodes, and the ones its expressions and scripts
+    /// seem to read
+    pub(crate) fn missing_fields(
+        &self,
+        result: &ConditionResult,
+        info: &Value,
+        missing: &mut Vec<String>,
+    ) {
+        if result.status != Status::Unknown {
+            return;
+        }
+
+        if let Some(children) = self.children() {
+            for (child, result) in children.iter().zip(&result.children) {
+                child.missing_fields(result, info, missing);
+            }
+            return;
+        }
+
+        let pointers = match self {
+            Condition::Condition {
+                field,
+                pointer,
+                path_syntax,
+                ..
+            } => vec![node_path(field, *pointer, *path_syntax, info)],
+            _ => self
+                .referenced_fields()
+                .into_iter()
+                .map(|field| field.pointer)
+                .collect(),
+        };
+        for pointer in pointers {
+            if info.pointer(&pointer).is_none() && !missing.contains(&pointer) {
+                missing.push(pointer);
+            }
+        }
+    }
+
+    /// Every node of the tree along with its depth, this one being 1 deep,
+    /// depth-first
+    pub(crate) fn nodes(&self) -> impl Iterator<Item = (usize, &Condition)> {
+        let mut stack = vec![(1, self)];
+        std::iter::from_fn(move || {
+            let (depth, node) = stack.pop()?;
+            if let Some(children) = node.children() {
+                stack.extend(children.iter().rev().map(|c| (depth + 1, c)));
+            }
+            Some((depth, node))
+        })
+    }
+
+    /// Every `Condition::Condition` under this node, depth-first
+    pub(crate) fn leaves(&self) -> Vec<&Condition> {
+        self.nodes()
+            .map(|(_, c)| c)
+            .filter(|c| matches!(c, Condition::Condition { .. }))
+            .collect()
+    }
+
+    pub(crate) fn label(&self) -> Option<&str> {
+        match self {
+            Condition::And { label, .. }
+            | Condition::Or { label, .. }
+            | Condition::Not { label, .. }
+            | Condition::AtLeast { label, .. }
+            | Condition::Frequency { label, .. }
+            | Condition::Condition { label, .. } => label.as_deref(),
+            #[cfg(feature = "eval")]
+            Condition::Eval { label, .. } => label.as_deref(),
+            #[cfg(feature = "lua")]
+            Condition::LuaEval { label, .. } => label.as_deref(),
+            #[cfg(feature = "async_predicate")]
+            Condition::AsyncPredicate { label, .. } => label.as_deref(),
+        }
+    }
+
+    /// The message of the node when it isn't met, unless it's an
+    /// expression, a script or a predicate
+    pub(crate) fn failure_message(&self) -> Option<&str> {
+        match self {
+            Condition::And {
+                failure_message, ..
+            }
+            | Condition::Or {
+                failure_message, ..
+            }
+            | Condition::Not {
+                failure_message, ..
             }
+            | Condition::AtLeast {
+                failure_message, ..
+            }
+            | Condition::Frequency {
+                failure_message, ..
+            }
+            | Condition::Condition {
+                failure_message, ..
+            } => failure_message.as_deref(),
+            #[allow(unreachable_patterns)]
+            _ => None,
+        }
+    }
+
+    /// Sets the result's failure message when it isn't met
+    fn fail_with_message(
+        &self,
+        res: &mut ConditionResult,
+        info: &Value,
+        ctx: &EvalContext,
+    ) {
+        if let (Status::NotMet, Some(message)) =
+            (res.status, self.failure_message())
+        {
+            res.failure_message = Some(
+                render(ctx.plan, message, info)
+                    .unwrap_or_else(|_| message.to_owned()),
+            );
         }
     }
 }
 
 /// Result of checking a rules tree.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct ConditionResult {
     /// Human-friendly description of the rule
     pub name: String,
@@ -202,6 +1365,111 @@ pub struct ConditionResult {
     pub status: Status,
     /// Results of any sub-rules
     pub children: Vec<ConditionResult>,
+    /// Why the condition couldn't tell whether it was met, e.g. a failing
+    /// script or an undefined variable
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    /// When tracing, whether the node would have been evaluated without
+    /// tracing, rather than skipped once its parent's status was decided
+    #[serde(
+        default = "evaluated_default",
+        skip_serializing_if = "is_evaluated"
+    )]
+    pub evaluated: bool,
+    /// When tracing, the position of the node in the order the engine
+    /// visited the tree, parents before their children
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub order: Option<u32>,
+    /// Whether the fact was missing or null, and the condition's `default`
+    /// was compared against instead
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub used_default: bool,
+    /// The condition's `failure_message` rendered against the facts, when
+    /// it isn't met
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub failure_message: Option<String>,
+    /// The elements of the array fact that met the leaf's constraint, or
+    /// broke it for the negative ones, e.g. those `string_does_not_contain`
+    /// found, when collecting them, see `Engine::set_collect_matches`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub matched_values: Vec<Value>,
+}
+
+fn evaluated_default() -> bool {
+    true
+}
+
+fn is_evaluated(evaluated: &bool) -> bool {
+    *evaluated
+}
+
+impl ConditionResult {
+    /// Depth-first traversal of this result and all of its children
+    pub fn iter(&self) -> ConditionResultIter<'_> {
+        ConditionResultIter { stack: vec![self] }
+    }
+
+    /// Every result without children, depth-first
+    pub fn leaves(&self) -> Vec<&ConditionResult> {
+        self.iter().filter(|c| c.children.is_empty()).collect()
+    }
+
+    /// Every `NotMet` result without children, depth-first
+    pub fn failed_leaves(&self) -> Vec<&ConditionResult> {
+        self.iter()
+            .filter(|c| c.children.is_empty() && c.status == Status::NotMet)
+            .collect()
+    }
+
+    fn contains(&self, status: Status) -> bool {
+        self.status == status
+            || self.children.iter().any(|c| c.contains(status))
+    }
+
+    /// Copy of this result keeping only the children whose subtree contains
+    /// at least one result with the given status
+    pub fn prune(&self, status: Status) -> ConditionResult {
+        ConditionResult {
+            name: self.name.clone(),
+            status: self.status,
+            children: self
+                .children
+                .iter()
+                .filter(|c| c.contains(status))
+                .map(|c| c.prune(status))
+                .collect(),
+            error: self.error.clone(),
+            evaluated: self.evaluated,
+            order: self.order,
+            used_default: self.used_default,
+            failure_message: self.failure_message.clone(),
+            matched_values: self.matched_values.clone(),
+        }
+    }
+}
+
+/// Depth-first iterator over a `ConditionResult`, see `ConditionResult::iter`
+pub struct ConditionResultIter<'a> {
+    stack: Vec<&'a ConditionResult>,
+}
+
+impl<'a> Iterator for ConditionResultIter<'a> {
+    type Item = &'a ConditionResult;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let next = self.stack.pop()?;
+        self.stack.extend(next.children.iter().rev());
+        Some(next)
+    }
+}
+
+impl<'a> IntoIterator for &'a ConditionResult {
+    type IntoIter = ConditionResultIter<'a>;
+    type Item = &'a ConditionResult;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
 }
 
 /// Creates a `Rule` where all child `Rule`s must be `Met`
@@ -210,7 +1478,11 @@ pub struct ConditionResult {
 /// * If the results contain only `Met` and `Unknown`, the result will be `Unknown`
 /// * Only results in `Met` if all children are `Met`
 pub fn and(and: Vec<Condition>) -> Condition {
-    Condition::And { and }
+    Condition::And {
+        and,
+        label: None,
+        failure_message: None,
+    }
 }
 
 /// Creates a `Rule` where any child `Rule` must be `Met`
@@ -219,7 +1491,11 @@ pub fn and(and: Vec<Condition>) -> Condition {
 /// * If the results contain only `NotMet` and `Unknown`, the result will be `Unknown`
 /// * Only results in `NotMet` if all children are `NotMet`
 pub fn or(or: Vec<Condition>) -> Condition {
-    Condition::Or { or }
+    Condition::Or {
+        or,
+        label: None,
+        failure_message: None,
+    }
 }
 
 /// Creates a `Rule` where `n` child `Rule`s must be `Met`
@@ -232,317 +1508,539 @@ pub fn at_least(
     Condition::AtLeast {
         should_minimum_meet,
         conditions,
+        unknown_policy: UnknownPolicy::Strict,
+        label: None,
+        failure_message: None,
     }
 }
 
-/// Creates a rule for string comparison
-pub fn string_equals(field: &str, val: &str) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::StringEquals(val.into()),
-        path: None,
+/// Creates a `Rule` met once the child `Rule` was met `at_least` times for
+/// the same entity within the last `window_secs`, see `Engine::run_keyed`
+pub fn frequency(of: Condition, at_least: u32, window_secs: u64) -> Condition {
+    Condition::Frequency {
+        of: Box::new(of),
+        at_least,
+        window_secs,
+        label: None,
+        failure_message: None,
     }
 }
 
-pub fn string_not_equals(field: &str, val: &str) -> Condition {
+fn leaf(field: &str, constraint: Constraint) -> Condition {
     Condition::Condition {
         field: field.into(),
-        constraint: Constraint::StringNotEquals(val.into()),
+        constraint: constraint.into(),
         path: None,
+        pointer: false,
+        path_syntax: PathSyntax::Pointer,
+        templated_value: false,
+        default: None,
+        label: None,
+        failure_message: None,
+        #[cfg(feature = "unicode")]
+        normalize: None,
     }
 }
 
+/// Creates a rule for string comparison
+pub fn string_equals(field: &str, val: &str) -> Condition {
+    leaf(field, Constraint::StringEquals(val.into()))
+}
+
+pub fn string_not_equals(field: &str, val: &str) -> Condition {
+    leaf(field, Constraint::StringNotEquals(val.into()))
+}
+
 pub fn string_contains(field: &str, val: &str) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::StringContains(val.into()),
-        path: None,
-    }
+    leaf(field, Constraint::StringContains(val.into()))
 }
 
 pub fn string_contains_all(field: &str, val: Vec<&str>) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::StringContainsAll(
+    leaf(
+        field,
+        Constraint::StringContainsAll(
             val.into_iter().map(ToOwned::to_owned).collect(),
         ),
-        path: None,
-    }
+    )
 }
 
 pub fn string_contains_any(field: &str, val: Vec<&str>) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::StringContainsAny(
+    leaf(
+        field,
+        Constraint::StringContainsAny(
             val.into_iter().map(ToOwned::to_owned).collect(),
         ),
-        path: None,
-    }
+    )
 }
 
 pub fn string_does_not_contain(field: &str, val: &str) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::StringDoesNotContain(val.into()),
-        path: None,
-    }
+    leaf(field, Constraint::StringDoesNotContain(val.into()))
 }
 
 pub fn string_does_not_contain_any(field: &str, val: Vec<&str>) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::StringDoesNotContainAny(
+    leaf(
+        field,
+        Constraint::StringDoesNotContainAny(
             val.into_iter().map(ToOwned::to_owned).collect(),
         ),
-        path: None,
-    }
+    )
 }
 
 pub fn string_in(field: &str, val: Vec<&str>) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::StringIn(
+    leaf(
+        field,
+        Constraint::StringIn(val.into_iter().map(ToOwned::to_owned).collect()),
+    )
+}
+
+pub fn string_not_in(field: &str, val: Vec<&str>) -> Condition {
+    leaf(
+        field,
+        Constraint::StringNotIn(
             val.into_iter().map(ToOwned::to_owned).collect(),
         ),
-        path: None,
-    }
+    )
+}
+
+pub fn string_fuzzy_matches(
+    field: &str,
+    val: &str,
+    max_distance: u32,
+) -> Condition {
+    leaf(
+        field,
+        Constraint::StringFuzzyMatches {
+            value: val.into(),
+            max_distance,
+            case_sensitive: false,
+        },
+    )
+}
+
+pub fn string_similarity_at_least(
+    field: &str,
+    val: &str,
+    ratio: f64,
+) -> Condition {
+    leaf(
+        field,
+        Constraint::StringSimilarityAtLeast {
+            value: val.into(),
+            ratio,
+            case_sensitive: false,
+        },
+    )
+}
+
+#[cfg(feature = "regex")]
+pub fn string_matches(field: &str, pattern: &str) -> Condition {
+    leaf(field, Constraint::StringMatches(pattern.into()))
+}
+
+#[cfg(feature = "regex")]
+pub fn string_matches_any(field: &str, patterns: Vec<&str>) -> Condition {
+    leaf(
+        field,
+        Constraint::StringMatchesAny(
+            patterns.into_iter().map(ToOwned::to_owned).collect(),
+        ),
+    )
 }
 
-pub fn string_not_in(field: &str, val: Vec<&str>) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::StringNotIn(
-            val.into_iter().map(ToOwned::to_owned).collect(),
+#[cfg(feature = "regex")]
+pub fn string_matches_none(field: &str, patterns: Vec<&str>) -> Condition {
+    leaf(
+        field,
+        Constraint::StringMatchesNone(
+            patterns.into_iter().map(ToOwned::to_owned).collect(),
         ),
-        path: None,
-    }
+    )
+}
+
+pub fn string_in_named_set(field: &str, name: &str) -> Condition {
+    leaf(field, Constraint::StringInNamedSet(name.into()))
+}
+
+pub fn string_not_in_named_set(field: &str, name: &str) -> Condition {
+    leaf(field, Constraint::StringNotInNamedSet(name.into()))
 }
 
 /// Creates a rule for int comparison.
 pub fn int_equals(field: &str, val: i64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntEquals(val),
-        path: None,
-    }
+    leaf(field, Constraint::IntEquals(val))
 }
 
 pub fn int_not_equals(field: &str, val: i64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntNotEquals(val),
-        path: None,
-    }
+    leaf(field, Constraint::IntNotEquals(val))
 }
 
 pub fn int_contains(field: &str, val: i64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntContains(val),
-        path: None,
-    }
+    leaf(field, Constraint::IntContains(val))
 }
 
 pub fn int_contains_all(field: &str, val: Vec<i64>) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntContainsAll(val),
-        path: None,
-    }
+    leaf(field, Constraint::IntContainsAll(val))
 }
 
 pub fn int_contains_any(field: &str, val: Vec<i64>) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntContainsAny(val),
-        path: None,
-    }
+    leaf(field, Constraint::IntContainsAny(val))
 }
 
 pub fn int_does_not_contain(field: &str, val: i64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntDoesNotContain(val),
-        path: None,
-    }
+    leaf(field, Constraint::IntDoesNotContain(val))
 }
 
 pub fn int_does_not_contain_any(field: &str, val: Vec<i64>) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntDoesNotContainAny(val),
-        path: None,
-    }
+    leaf(field, Constraint::IntDoesNotContainAny(val))
+}
+
+pub fn int_is_subset(field: &str, val: Vec<i64>) -> Condition {
+    leaf(field, Constraint::IntIsSubset(val))
+}
+
+pub fn int_is_superset(field: &str, val: Vec<i64>) -> Condition {
+    leaf(field, Constraint::IntIsSuperset(val))
 }
 
 pub fn int_in(field: &str, val: Vec<i64>) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntIn(val),
-        path: None,
-    }
+    leaf(field, Constraint::IntIn(val))
 }
 
 pub fn int_not_in(field: &str, val: Vec<i64>) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntNotIn(val),
-        path: None,
-    }
+    leaf(field, Constraint::IntNotIn(val))
+}
+
+pub fn int_in_named_set(field: &str, name: &str) -> Condition {
+    leaf(field, Constraint::IntInNamedSet(name.into()))
+}
+
+pub fn int_not_in_named_set(field: &str, name: &str) -> Condition {
+    leaf(field, Constraint::IntNotInNamedSet(name.into()))
 }
 
 pub fn int_in_range(field: &str, start: i64, end: i64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntInRange(start, end),
-        path: None,
-    }
+    leaf(field, Constraint::IntInRange(Some(start), Some(end)))
+}
+
+/// Creates a rule whose range has no upper bound, `[start, null]`
+pub fn int_at_least(field: &str, start: i64) -> Condition {
+    leaf(field, Constraint::IntInRange(Some(start), None))
+}
+
+/// Creates a rule whose range has no lower bound, `[null, end]`
+pub fn int_at_most(field: &str, end: i64) -> Condition {
+    leaf(field, Constraint::IntInRange(None, Some(end)))
 }
 
 pub fn int_not_in_range(field: &str, start: i64, end: i64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntNotInRange(start, end),
-        path: None,
-    }
+    leaf(field, Constraint::IntNotInRange(start, end))
 }
 
 pub fn int_less_than(field: &str, val: i64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntLessThan(val),
-        path: None,
-    }
+    leaf(field, Constraint::IntLessThan(val))
 }
 
 pub fn int_less_than_inclusive(field: &str, val: i64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntLessThanInclusive(val),
-        path: None,
-    }
+    leaf(field, Constraint::IntLessThanInclusive(val))
 }
 
 pub fn int_greater_than(field: &str, val: i64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntGreaterThan(val),
-        path: None,
-    }
+    leaf(field, Constraint::IntGreaterThan(val))
 }
 
 pub fn int_greater_than_inclusive(field: &str, val: i64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::IntGreaterThanInclusive(val),
-        path: None,
-    }
+    leaf(field, Constraint::IntGreaterThanInclusive(val))
+}
+
+/// Creates a rule for unsigned int comparison, for facts above `i64::MAX`
+pub fn uint_equals(field: &str, val: u64) -> Condition {
+    leaf(field, Constraint::UintEquals(val))
+}
+
+pub fn uint_not_equals(field: &str, val: u64) -> Condition {
+    leaf(field, Constraint::UintNotEquals(val))
+}
+
+pub fn uint_in(field: &str, val: Vec<u64>) -> Condition {
+    leaf(field, Constraint::UintIn(val))
+}
+
+pub fn uint_not_in(field: &str, val: Vec<u64>) -> Condition {
+    leaf(field, Constraint::UintNotIn(val))
+}
+
+pub fn uint_in_range(field: &str, start: u64, end: u64) -> Condition {
+    leaf(field, Constraint::UintInRange(start, end))
+}
+
+pub fn uint_not_in_range(field: &str, start: u64, end: u64) -> Condition {
+    leaf(field, Constraint::UintNotInRange(start, end))
+}
+
+pub fn uint_less_than(field: &str, val: u64) -> Condition {
+    leaf(field, Constraint::UintLessThan(val))
+}
+
+pub fn uint_less_than_inclusive(field: &str, val: u64) -> Condition {
+    leaf(field, Constraint::UintLessThanInclusive(val))
+}
+
+pub fn uint_greater_than(field: &str, val: u64) -> Condition {
+    leaf(field, Constraint::UintGreaterThan(val))
+}
+
+pub fn uint_greater_than_inclusive(field: &str, val: u64) -> Condition {
+    leaf(field, Constraint::UintGreaterThanInclusive(val))
 }
 
 /// Creates a rule for float comparison.
 pub fn float_equals(field: &str, val: f64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::FloatEquals(val),
-        path: None,
-    }
+    leaf(field, Constraint::FloatEquals(val))
 }
 
 pub fn float_not_equals(field: &str, val: f64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::FloatNotEquals(val),
-        path: None,
-    }
+    leaf(field, Constraint::FloatNotEquals(val))
+}
+
+pub fn float_equals_rounded(field: &str, val: f64, decimals: u32) -> Condition {
+    leaf(
+        field,
+        Constraint::FloatEqualsRounded {
+            value: val,
+            decimals,
+        },
+    )
 }
 
 pub fn float_contains(field: &str, val: f64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::FloatContains(val),
-        path: None,
-    }
+    leaf(field, Constraint::FloatContains(val))
 }
 
 pub fn float_does_not_contain(field: &str, val: f64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::FloatDoesNotContain(val),
-        path: None,
-    }
+    leaf(field, Constraint::FloatDoesNotContain(val))
+}
+
+pub fn float_is_subset(field: &str, val: Vec<f64>) -> Condition {
+    leaf(field, Constraint::FloatIsSubset(val))
+}
+
+pub fn float_is_superset(field: &str, val: Vec<f64>) -> Condition {
+    leaf(field, Constraint::FloatIsSuperset(val))
 }
 
 pub fn float_in(field: &str, val: Vec<f64>) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::FloatIn(val),
-        path: None,
-    }
+    leaf(field, Constraint::FloatIn(val))
 }
 
 pub fn float_not_in(field: &str, val: Vec<f64>) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::FloatNotIn(val),
-        path: None,
-    }
+    leaf(field, Constraint::FloatNotIn(val))
 }
 
 pub fn float_in_range(field: &str, start: f64, end: f64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::FloatInRange(start, end),
-        path: None,
-    }
+    leaf(field, Constraint::FloatInRange(Some(start), Some(end)))
+}
+
+pub fn float_at_least(field: &str, start: f64) -> Condition {
+    leaf(field, Constraint::FloatInRange(Some(start), None))
+}
+
+pub fn float_at_most(field: &str, end: f64) -> Condition {
+    leaf(field, Constraint::FloatInRange(None, Some(end)))
 }
 
 pub fn float_not_in_range(field: &str, start: f64, end: f64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::FloatNotInRange(start, end),
-        path: None,
-    }
+    leaf(field, Constraint::FloatNotInRange(start, end))
 }
 
 pub fn float_less_than(field: &str, val: f64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::FloatLessThan(val),
-        path: None,
-    }
+    leaf(field, Constraint::FloatLessThan(val))
 }
 
 pub fn float_less_than_inclusive(field: &str, val: f64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::FloatLessThanInclusive(val),
-        path: None,
-    }
+    leaf(field, Constraint::FloatLessThanInclusive(val))
 }
 
 pub fn float_greater_than(field: &str, val: f64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::FloatGreaterThan(val),
-        path: None,
-    }
+    leaf(field, Constraint::FloatGreaterThan(val))
 }
 
 pub fn float_greater_than_inclusive(field: &str, val: f64) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::FloatGreaterThanInclusive(val),
-        path: None,
-    }
+    leaf(field, Constraint::FloatGreaterThanInclusive(val))
+}
+
+/// Creates a rule comparing a percentile of a numeric array, e.g. its p95
+/// with `percentile_greater_than("response_times", 95.0, 800.0)`
+pub fn percentile_greater_than(
+    field: &str,
+    percentile: f64,
+    val: f64,
+) -> Condition {
+    leaf(
+        field,
+        Constraint::FloatPercentileGreaterThan {
+            percentile,
+            value: val,
+        },
+    )
+}
+
+pub fn percentile_less_than(
+    field: &str,
+    percentile: f64,
+    val: f64,
+) -> Condition {
+    leaf(
+        field,
+        Constraint::FloatPercentileLessThan {
+            percentile,
+            value: val,
+        },
+    )
+}
+
+/// Creates a rule for number comparison, whatever the JSON number types of
+/// the fact and the value. Preferred over the int and float rules
+pub fn number_equals(field: &str, val: Number) -> Condition {
+    leaf(field, Constraint::NumberEquals(val))
+}
+
+pub fn number_not_equals(field: &str, val: Number) -> Condition {
+    leaf(field, Constraint::NumberNotEquals(val))
+}
+
+pub fn number_in(field: &str, val: Vec<Number>) -> Condition {
+    leaf(field, Constraint::NumberIn(val))
+}
+
+pub fn number_not_in(field: &str, val: Vec<Number>) -> Condition {
+    leaf(field, Constraint::NumberNotIn(val))
+}
+
+pub fn number_in_range(field: &str, start: Number, end: Number) -> Condition {
+    leaf(field, Constraint::NumberInRange(start, end))
+}
+
+pub fn number_not_in_range(
+    field: &str,
+    start: Number,
+    end: Number,
+) -> Condition {
+    leaf(field, Constraint::NumberNotInRange(start, end))
+}
+
+pub fn number_less_than(field: &str, val: Number) -> Condition {
+    leaf(field, Constraint::NumberLessThan(val))
+}
+
+pub fn number_less_than_inclusive(field: &str, val: Number) -> Condition {
+    leaf(field, Constraint::NumberLessThanInclusive(val))
+}
+
+pub fn number_greater_than(field: &str, val: Number) -> Condition {
+    leaf(field, Constraint::NumberGreaterThan(val))
+}
+
+pub fn number_greater_than_inclusive(field: &str, val: Number) -> Condition {
+    leaf(field, Constraint::NumberGreaterThanInclusive(val))
 }
 
 /// Creates a rule for boolean comparison.
 pub fn bool_equals(field: &str, val: bool) -> Condition {
-    Condition::Condition {
-        field: field.into(),
-        constraint: Constraint::BoolEquals(val),
-        path: None,
-    }
+    leaf(field, Constraint::BoolEquals(val))
+}
+
+/// Creates a rule for deep equality with any JSON value, e.g.
+/// `value_equals("address", json!({"country": "FR", "zip": "75001"}))`
+pub fn value_equals(field: &str, val: Value) -> Condition {
+    leaf(field, Constraint::ValueEquals(val))
+}
+
+pub fn value_not_equals(field: &str, val: Value) -> Condition {
+    leaf(field, Constraint::ValueNotEquals(val))
+}
+
+pub fn value_in(field: &str, vals: Vec<Value>) -> Condition {
+    leaf(field, Constraint::ValueIn(vals))
+}
+
+pub fn datetime_within_last(field: &str, secs: i64) -> Condition {
+    leaf(field, Constraint::DatetimeWithinLast(secs))
+}
+
+pub fn datetime_older_than(field: &str, secs: i64) -> Condition {
+    leaf(field, Constraint::DatetimeOlderThan(secs))
+}
+
+/// Creates a rule for a duration longer than `val`, e.g. `"1h"`, see
+/// `Constraint::DurationGreaterThan`
+pub fn duration_greater_than(field: &str, val: &str) -> Condition {
+    leaf(field, Constraint::DurationGreaterThan(val.into()))
+}
+
+pub fn duration_less_than(field: &str, val: &str) -> Condition {
+    leaf(field, Constraint::DurationLessThan(val.into()))
+}
+
+pub fn duration_in_range(field: &str, start: &str, end: &str) -> Condition {
+    leaf(field, Constraint::DurationInRange(start.into(), end.into()))
+}
+
+pub fn array_all_unique(field: &str) -> Condition {
+    leaf(field, Constraint::ArrayAllUnique(true))
+}
+
+pub fn array_distinct_at_least(field: &str, n: usize) -> Condition {
+    leaf(field, Constraint::ArrayDistinctCountGreaterThanInclusive(n))
+}
+
+pub fn any_match(field: &str, condition: Condition) -> Condition {
+    leaf(field, Constraint::AnyMatch(Box::new(condition)))
+}
+
+pub fn none_match(field: &str, condition: Condition) -> Condition {
+    leaf(field, Constraint::NoneMatch(Box::new(condition)))
+}
+
+pub fn is_uuid(field: &str) -> Condition {
+    leaf(field, Constraint::IsUuid(true))
+}
+
+pub fn is_ulid(field: &str) -> Condition {
+    leaf(field, Constraint::IsUlid(true))
+}
+
+pub fn is_email(field: &str) -> Condition {
+    leaf(field, Constraint::IsEmail(true))
+}
+
+pub fn is_url(field: &str) -> Condition {
+    leaf(field, Constraint::IsUrl(true))
+}
+
+pub fn domain_equals(field: &str, val: &str) -> Condition {
+    leaf(field, Constraint::DomainEquals(val.into()))
+}
+
+pub fn domain_in(field: &str, val: Vec<&str>) -> Condition {
+    leaf(
+        field,
+        Constraint::DomainIn(val.into_iter().map(ToOwned::to_owned).collect()),
+    )
+}
+
+/// Creates a rule matching a hostname against a pattern, e.g.
+/// `domain_matches("host", "*.internal.example.com")`
+pub fn domain_matches(field: &str, pattern: &str) -> Condition {
+    leaf(field, Constraint::DomainMatchesWildcard(pattern.into()))
+}
+
+pub fn email_domain_in(field: &str, val: Vec<&str>) -> Condition {
+    leaf(
+        field,
+        Constraint::EmailDomainIn(
+            val.into_iter().map(ToOwned::to_owned).collect(),
+        ),
+    )
 }
 
 #[cfg(not(feature = "eval"))]
@@ -551,8 +2049,14 @@ mod tests {
     use super::{
         and, at_least, bool_equals, int_equals, int_in_range, or, string_equals,
     };
-    use crate::status::Status;
+    use crate::{
+        condition::{Condition, ConditionResult, EvalContext, UnknownPolicy},
+        constraint::NamedSets,
+        status::Status,
+    };
+    use chrono::Utc;
     use serde_json::{json, Value};
+    use std::collections::HashMap;
 
     fn get_test_data() -> Value {
         json!({
@@ -675,6 +2179,80 @@ mod tests {
         assert_eq!(res.status, Status::NotMet);
     }
 
+    #[test]
+    fn n_of_rules_unknown_policy() {
+        let map = get_test_data();
+        let at_least = |n, policy, children: &[Condition]| {
+            let mut node = at_least(n, children.to_vec());
+            if let Condition::AtLeast { unknown_policy, .. } = &mut node {
+                *unknown_policy = policy;
+            }
+            node
+        };
+        let met = int_equals("foo", 1);
+        let unknown = string_equals("quux", "bar");
+        let not_met = bool_equals("baz", false);
+
+        // 1 Met, 1 Unknown, 1 NotMet, at least 1, 2 and 3 of them
+        let children = [met.clone(), unknown.clone(), not_met.clone()];
+        for (n, strict, optimistic) in [
+            (1, Status::Met, Status::Met),
+            (2, Status::NotMet, Status::Unknown),
+            (3, Status::NotMet, Status::NotMet),
+        ] {
+            let res =
+                at_least(n, UnknownPolicy::Strict, &children).check_value(&map);
+            assert_eq!(res.status, strict, "at least {}", n);
+            assert_eq!(
+                res.name,
+                format!("At least meet {} of 3 (1 met, 1 unknown)", n)
+            );
+
+            let res = at_least(n, UnknownPolicy::Optimistic, &children)
+                .check_value(&map);
+            assert_eq!(res.status, optimistic, "at least {}", n);
+        }
+
+        // short circuiting doesn't change the outcome, whatever the order
+        let ctx = EvalContext {
+            sets: &NamedSets::default(),
+            #[cfg(feature = "regex")]
+            captures: &Default::default(),
+            variables: &HashMap::new(),
+            now: Utc::now(),
+            results: &HashMap::new(),
+            short_circuit: true,
+            trace: false,
+            #[cfg(feature = "async_predicate")]
+            predicates: &Default::default(),
+            plan: None,
+            frequencies: None,
+            frequency_run: None,
+            max_matched_values: None,
+            element_root: None,
+            lookups: None,
+        };
+        let orders = [
+            [&met, &unknown, &not_met, &unknown],
+            [&unknown, &not_met, &unknown, &met],
+            [&not_met, &not_met, &unknown, &unknown],
+            [&unknown, &unknown, &met, &not_met],
+        ];
+        for order in orders {
+            let children = order.map(Clone::clone);
+            for n in 0..=5 {
+                for policy in [UnknownPolicy::Strict, UnknownPolicy::Optimistic]
+                {
+                    let node = at_least(n, policy, &children);
+                    assert_eq!(
+                        node.check_value_with(&map, &ctx).status,
+                        node.check_value(&map).status,
+                    );
+                }
+            }
+        }
+    }
+
     #[test]
     fn string_equals_rule() {
         let map = get_test_data();
@@ -745,4 +2323,34 @@ mod tests {
         res = rule.check_value(&map);
         assert_eq!(res.status, Status::NotMet);
     }
+
+    #[test]
+    fn result_traversal() {
+        let map = get_test_data();
+        let root = and(vec![
+            or(vec![int_equals("foo", 2), string_equals("bar", "bar")]),
+            and(vec![bool_equals("baz", false), int_equals("quux", 1)]),
+            int_equals("foo", 1),
+        ]);
+        let res = root.check_value(&map);
+        assert_eq!(res.status, Status::NotMet);
+
+        let names = |rs: Vec<&ConditionResult>| {
+            rs.iter().map(|r| r.name.clone()).collect::<Vec<_>>()
+        };
+
+        assert_eq!(res.iter().count(), 8);
+        assert_eq!((&res).into_iter().count(), 8);
+        assert_eq!(names(res.leaves()), ["foo", "bar", "baz", "quux", "foo"]);
+        assert_eq!(names(res.failed_leaves()), ["foo", "baz"]);
+
+        let pruned = res.prune(Status::Unknown);
+        assert_eq!(pruned.children.len(), 1);
+        assert_eq!(names(pruned.leaves()), ["quux"]);
+
+        let pruned = res.prune(Status::NotMet);
+        assert_eq!(pruned.children.len(), 2);
+        assert_eq!(names(pruned.leaves()), ["foo", "baz"]);
+        assert_eq!(pruned.iter().count(), 5);
+    }
 }
@@ -1,10 +1,32 @@
-use crate::status::Status;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use crate::{
+    condition::{Condition, EvalContext},
+    status::Status,
+};
+use chrono::{DateTime, Duration, Utc};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use serde_json::{json, Number, Value};
+use std::{
+    borrow::Cow,
+    cmp::Ordering,
+    collections::{HashMap, HashSet},
+    convert::Infallible,
+};
 use strum::VariantNames;
-use strum_macros::EnumVariantNames;
+use strum_macros::{EnumVariantNames, IntoStaticStr};
 
-#[derive(Clone, Debug, Serialize, Deserialize, EnumVariantNames)]
+/// What a leaf compares its fact with. Two constraints are equal when their
+/// operator and value are, floats compared as IEEE 754 numbers: `-0.0`
+/// equals `0.0`, and NaN, which JSON can't hold anyway, equals nothing, not
+/// even itself
+#[derive(
+    Clone,
+    Debug,
+    PartialEq,
+    Serialize,
+    Deserialize,
+    EnumVariantNames,
+    IntoStaticStr,
+)]
 #[serde(rename_all = "snake_case")]
 #[strum(serialize_all = "snake_case")]
 #[serde(tag = "operator", content = "value")]
@@ -18,6 +40,28 @@ pub enum Constraint {
     StringDoesNotContainAny(Vec<String>),
     StringIn(Vec<String>),
     StringNotIn(Vec<String>),
+    /// At most `max_distance` edits away from the value, an edit inserting,
+    /// deleting or substituting a character, or transposing two adjacent
+    /// ones (Damerau–Levenshtein). Case insensitive unless `case_sensitive`.
+    /// Either side longer than `FUZZY_MAX_CHARS` characters is `NotMet`
+    StringFuzzyMatches {
+        value: String,
+        max_distance: u32,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        case_sensitive: bool,
+    },
+    /// The similarity with the value, from 0 to 1, is at least `ratio`: one
+    /// minus the edit distance of `StringFuzzyMatches` over the length of
+    /// the longer string, two empty strings being alike
+    StringSimilarityAtLeast {
+        value: String,
+        ratio: f64,
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        case_sensitive: bool,
+    },
+    /// In a set registered on the engine with `Engine::register_set`
+    StringInNamedSet(String),
+    StringNotInNamedSet(String),
     IntEquals(i64),
     IntNotEquals(i64),
     IntContains(i64),
@@ -25,43 +69,1067 @@ pub enum Constraint {
     IntContainsAny(Vec<i64>),
     IntDoesNotContain(i64),
     IntDoesNotContainAny(Vec<i64>),
+    /// Every element of the fact is in the list, duplicates aside. Facts
+    /// holding anything else than integers are `NotMet`
+    IntIsSubset(Vec<i64>),
+    /// Every element of the list is in the fact
+    IntIsSuperset(Vec<i64>),
     IntIn(Vec<i64>),
     IntNotIn(Vec<i64>),
-    IntInRange(i64, i64),
+    /// In a set registered on the engine with `Engine::register_int_set`
+    IntInNamedSet(String),
+    IntNotInNamedSet(String),
+    /// Within the bounds, both included, a `null` one leaving that side
+    /// open, e.g. `[10000, null]`. Both being `null` fails the rule to load
+    #[serde(deserialize_with = "deserialize_open_range")]
+    IntInRange(Option<i64>, Option<i64>),
     IntNotInRange(i64, i64),
     IntLessThan(i64),
     IntLessThanInclusive(i64),
     IntGreaterThan(i64),
     IntGreaterThanInclusive(i64),
+    /// Unsigned counterparts of the `Int*` constraints, for facts above
+    /// `i64::MAX` such as 64-bit hashes. The value is a plain JSON number,
+    /// e.g. `{ "operator": "uint_equals", "value": 18446744073709551615 }`,
+    /// and facts that are negative or not integers are `NotMet`
+    UintEquals(u64),
+    UintNotEquals(u64),
+    UintIn(Vec<u64>),
+    UintNotIn(Vec<u64>),
+    UintInRange(u64, u64),
+    UintNotInRange(u64, u64),
+    UintLessThan(u64),
+    UintLessThanInclusive(u64),
+    UintGreaterThan(u64),
+    UintGreaterThanInclusive(u64),
     FloatEquals(f64),
     FloatNotEquals(f64),
+    /// Equal once both sides are rounded to `decimals` decimal places,
+    /// halves away from zero, e.g. `0.125` to `0.13`. Beyond 15 decimals,
+    /// they're rounded to 15
+    FloatEqualsRounded {
+        value: f64,
+        decimals: u32,
+    },
     FloatContains(f64),
     FloatDoesNotContain(f64),
+    /// Same as `IntIsSubset`, elements comparing equal within
+    /// `f64::EPSILON`
+    FloatIsSubset(Vec<f64>),
+    FloatIsSuperset(Vec<f64>),
     FloatIn(Vec<f64>),
     FloatNotIn(Vec<f64>),
-    FloatInRange(f64, f64),
+    /// Same as `IntInRange`
+    #[serde(deserialize_with = "deserialize_open_range")]
+    FloatInRange(Option<f64>, Option<f64>),
     FloatNotInRange(f64, f64),
     FloatLessThan(f64),
     FloatLessThanInclusive(f64),
     FloatGreaterThan(f64),
     FloatGreaterThanInclusive(f64),
+    /// The `percentile` of the numbers of the array, linearly interpolated
+    /// between their closest ranks, is greater than the value, e.g.
+    /// `{ "percentile": 95, "value": 800 }`. A percentile outside of
+    /// (0, 100] fails the rule to load. Empty arrays, and arrays holding
+    /// anything else than numbers, are `NotMet`
+    FloatPercentileGreaterThan {
+        #[serde(deserialize_with = "deserialize_percentile")]
+        percentile: f64,
+        value: f64,
+    },
+    /// The `percentile` of the numbers of the array is less than the value
+    FloatPercentileLessThan {
+        #[serde(deserialize_with = "deserialize_percentile")]
+        percentile: f64,
+        value: f64,
+    },
+    /// Preferred over the `Int*` and `Float*` constraints, the `Number*`
+    /// ones take any JSON number on both sides, so `10` and `10.0` compare
+    /// equal. Integers are compared exactly, even past the precision of an
+    /// `f64`
+    NumberEquals(Number),
+    NumberNotEquals(Number),
+    NumberIn(Vec<Number>),
+    NumberNotIn(Vec<Number>),
+    NumberInRange(Number, Number),
+    NumberNotInRange(Number, Number),
+    NumberLessThan(Number),
+    NumberLessThanInclusive(Number),
+    NumberGreaterThan(Number),
+    NumberGreaterThanInclusive(Number),
     BoolEquals(bool),
+    /// Deeply equal to the value, whatever its JSON type: objects whatever
+    /// the order of their keys, arrays element by element in order, and
+    /// numbers by value, so `1` equals `1.0`
+    ValueEquals(Value),
+    ValueNotEquals(Value),
+    /// Deeply equal to one of the values, e.g. one of a few object shapes
+    ValueIn(Vec<Value>),
+    /// An RFC 3339 datetime at most this many seconds before now
+    DatetimeWithinLast(i64),
+    /// An RFC 3339 datetime more than this many seconds before now
+    DatetimeOlderThan(i64),
+    /// A duration longer than the value, both sides being ISO 8601
+    /// durations, e.g. `PT2H30M`, or humanized ones, e.g. `2h 30m`, and
+    /// integer facts seconds, see `parse_duration`. A value that doesn't
+    /// parse fails the rule to load, a fact that doesn't is `NotMet`
+    DurationGreaterThan(
+        #[serde(deserialize_with = "deserialize_duration")] String,
+    ),
+    /// A duration shorter than the value
+    DurationLessThan(
+        #[serde(deserialize_with = "deserialize_duration")] String,
+    ),
+    /// A duration within the bounds, inclusive
+    DurationInRange(
+        #[serde(deserialize_with = "deserialize_duration")] String,
+        #[serde(deserialize_with = "deserialize_duration")] String,
+    ),
+    /// Whether every element of the array is different from the others
+    ArrayAllUnique(bool),
+    ArrayDistinctCountGreaterThanInclusive(usize),
+    /// At least one element of the array meets the condition, which sees
+    /// the element as its facts, e.g. `{ "field": "trusted", "operator":
+    /// "bool_equals", "value": false }` against an array of devices. Facts
+    /// that aren't arrays are `NotMet`
+    AnyMatch(Box<Condition>),
+    /// No element of the array meets the condition, an empty array included
+    NoneMatch(Box<Condition>),
+    /// Whether the string is a UUID, hyphenated or not, in any case
+    IsUuid(bool),
+    /// Whether the string is a ULID
+    IsUlid(bool),
+    /// Whether the string looks like an email address, `local@domain.tld`,
+    /// without going through the whole of RFC 5322
+    IsEmail(bool),
+    /// Whether the string is an absolute URL, scheme included
+    IsUrl(bool),
+    /// The hostname is the domain, in any case, a trailing dot aside.
+    /// Internationalized domains are compared as they are written, their
+    /// punycode `xn--` form not being converted
+    DomainEquals(String),
+    /// The hostname is one of the domains
+    DomainIn(Vec<String>),
+    /// The hostname matches the pattern, whose leading `*.`, if any, stands
+    /// for exactly one label, e.g. `*.internal.example.com` matching
+    /// `db.internal.example.com` but neither `internal.example.com` nor
+    /// `a.db.internal.example.com`. A `*` anywhere else fails the rule to
+    /// load
+    DomainMatchesWildcard(
+        #[serde(deserialize_with = "deserialize_wildcard")] String,
+    ),
+    /// The domain of the email address, after its last `@`, is one of the
+    /// domains. Addresses without a local part or a domain are `NotMet`
+    EmailDomainIn(Vec<String>),
+    /// The regex matches somewhere in the string, anchor it with `^` and
+    /// `$` to match the whole of it. Its named groups are exposed to the
+    /// templates of the rule's events, see `RuleResult::captures`. A
+    /// pattern that doesn't compile is `Unknown`
+    #[cfg(feature = "regex")]
+    StringMatches(String),
+    /// One of the regexes matches somewhere in the string, the patterns
+    /// being matched together in a single pass over it. A pattern that
+    /// doesn't compile fails the rule to load
+    #[cfg(feature = "regex")]
+    StringMatchesAny(
+        #[serde(deserialize_with = "deserialize_patterns")] Vec<String>,
+    ),
+    /// None of the regexes matches anywhere in the string
+    #[cfg(feature = "regex")]
+    StringMatchesNone(
+        #[serde(deserialize_with = "deserialize_patterns")] Vec<String>,
+    ),
+}
+
+/// The constraint of a condition, whose value may be an engine variable,
+/// e.g. `{ "operator": "int_greater_than", "value": { "$var": "max_logins" } }`,
+/// looked up each time the condition is evaluated, see `Engine::set_variable`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum ValueOrVar {
+    Value(Constraint),
+    Var { operator: String, value: Var },
+}
+
+/// A reference to an engine variable, `{ "$var": "name" }`
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Var {
+    #[serde(rename = "$var")]
+    pub name: String,
+}
+
+impl From<Constraint> for ValueOrVar {
+    fn from(constraint: Constraint) -> Self {
+        ValueOrVar::Value(constraint)
+    }
+}
+
+impl<'de> Deserialize<'de> for ValueOrVar {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> Result<Self, D::Error> {
+        #[derive(Deserialize)]
+        struct Raw {
+            operator: String,
+            value: Value,
+        }
+
+        let Raw { operator, value } = Raw::deserialize(deserializer)?;
+        // only objects, as a struct also deserializes from a sequence, e.g.
+        // the single value list of `string_in`
+        let var = match value {
+            Value::Object(_) => Var::deserialize(&value).ok(),
+            _ => None,
+        };
+        match var {
+            Some(var) => {
+                if !Constraint::operators().contains(&operator.as_str()) {
+                    return Err(de::Error::unknown_variant(
+                        &operator,
+                        Constraint::operators(),
+                    ));
+                }
+                Ok(ValueOrVar::Var {
+                    operator,
+                    value: var,
+                })
+            }
+            None => Constraint::deserialize(
+                json!({ "operator": operator, "value": value }),
+            )
+            .map(ValueOrVar::Value)
+            .map_err(de::Error::custom),
+        }
+    }
+}
+
+impl ValueOrVar {
+    pub fn operator(&self) -> &str {
+        match self {
+            ValueOrVar::Value(constraint) => constraint.operator(),
+            ValueOrVar::Var { operator, .. } => operator,
+        }
+    }
+
+    /// The constraint, with its variable if any replaced by its value
+    pub(crate) fn resolve(
+        &self,
+        variables: &HashMap<String, Value>,
+    ) -> Result<Cow<'_, Constraint>, String> {
+        match self {
+            ValueOrVar::Value(constraint) => Ok(Cow::Borrowed(constraint)),
+            ValueOrVar::Var { operator, value } => {
+                let var = variables.get(&value.name).ok_or_else(|| {
+                    format!("Variable `{}` isn't defined", value.name)
+                })?;
+                Constraint::deserialize(
+                    json!({ "operator": operator, "value": var }),
+                )
+                .map(Cow::Owned)
+                .map_err(|e| {
+                    format!(
+                        "Variable `{}` doesn't fit `{}`: {}",
+                        value.name, operator, e
+                    )
+                })
+            }
+        }
+    }
+}
+
+/// Rounds the float to `decimals` decimal places, at most 15, halves away
+/// from zero
+pub(crate) fn round(v: f64, decimals: u32) -> f64 {
+    let scale = 10f64.powi(decimals.min(15) as i32);
+    (v * scale).round() / scale
+}
+
+/// The seconds of the `<number><unit>` components of an ISO 8601 duration
+/// part, the units coming in the given order, each at most once
+fn iso_components(part: &str, units: &[(char, f64)]) -> Option<f64> {
+    let mut units = units.iter();
+    let mut rest = part;
+    let mut secs = 0.0;
+    while !rest.is_empty() {
+        let end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+        let n: f64 = rest[..end].parse().ok()?;
+        let unit = rest[end..].chars().next()?;
+        let (_, factor) = units.find(|(u, _)| *u == unit)?;
+        secs += n * factor;
+        rest = &rest[end + 1..];
+    }
+    Some(secs)
+}
+
+/// The seconds of an ISO 8601 duration, e.g. `P1DT2H30M`. Years and months,
+/// whose length varies, aren't supported
+fn parse_iso_duration(s: &str) -> Option<f64> {
+    let rest = s.strip_prefix('P')?;
+    let (date, time) = match rest.split_once('T') {
+        Some((_, "")) => return None,
+        Some((date, time)) => (date, time),
+        None if rest.is_empty() => return None,
+        None => (rest, ""),
+    };
+    Some(
+        iso_components(date, &[('W', 604_800.0), ('D', 86_400.0)])?
+            + iso_components(time, &[('H', 3600.0), ('M', 60.0), ('S', 1.0)])?,
+    )
+}
+
+/// The seconds of a humanized duration, `<number><unit>` components
+/// optionally separated by spaces, e.g. `90m` or `2h 30m`
+fn parse_human_duration(s: &str) -> Option<f64> {
+    let mut rest = s.trim();
+    if rest.is_empty() {
+        return None;
+    }
+
+    let mut secs = 0.0;
+    while !rest.is_empty() {
+        let end = rest.find(|c: char| !(c.is_ascii_digit() || c == '.'))?;
+        let n: f64 = rest[..end].parse().ok()?;
+        let tail = rest[end..].trim_start();
+        let unit_end = tail
+            .find(|c: char| !c.is_ascii_alphabetic())
+            .unwrap_or(tail.len());
+        let factor = match &tail[..unit_end] {
+            "ms" | "msec" | "millis" => 0.001,
+            "s" | "sec" | "secs" | "second" | "seconds" => 1.0,
+            "m" | "min" | "mins" | "minute" | "minutes" => 60.0,
+            "h" | "hr" | "hrs" | "hour" | "hours" => 3600.0,
+            "d" | "day" | "days" => 86_400.0,
+            "w" | "week" | "weeks" => 604_800.0,
+            _ => return None,
+        };
+        secs += n * factor;
+        rest = tail[unit_end..].trim_start();
+    }
+    Some(secs)
+}
+
+/// The seconds of a duration, ISO 8601 when it starts with `P`, humanized
+/// otherwise
+pub fn parse_duration(s: &str) -> Option<f64> {
+    if s.starts_with('P') {
+        parse_iso_duration(s)
+    } else {
+        parse_human_duration(s)
+    }
+}
+
+fn deserialize_duration<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    match parse_duration(&s) {
+        Some(_) => Ok(s),
+        None => Err(de::Error::custom(format!("Invalid duration `{}`", s))),
+    }
+}
+
+/// The bounds of a range, either of which may be open, but not both
+fn deserialize_open_range<'de, D, T>(
+    deserializer: D,
+) -> Result<(Option<T>, Option<T>), D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    match <(Option<T>, Option<T>)>::deserialize(deserializer)? {
+        (None, None) => {
+            Err(de::Error::custom("A range needs at least one bound"))
+        }
+        bounds => Ok(bounds),
+    }
+}
+
+fn deserialize_wildcard<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<String, D::Error> {
+    let s = String::deserialize(deserializer)?;
+    match s.strip_prefix("*.") {
+        Some(parent) if !parent.is_empty() && !parent.contains('*') => Ok(s),
+        None if !s.contains('*') => Ok(s),
+        _ => Err(de::Error::custom(format!(
+            "Invalid wildcard `{}`, only a leading `*.` is supported",
+            s
+        ))),
+    }
+}
+
+/// The first of the regexes that doesn't compile, with its index
+#[cfg(feature = "regex")]
+pub(crate) fn check_patterns(patterns: &[String]) -> Result<(), String> {
+    for (i, pattern) in patterns.iter().enumerate() {
+        if let Err(e) = regex::Regex::new(pattern) {
+            return Err(format!("Invalid pattern #{} `{}`: {}", i, pattern, e));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(feature = "regex")]
+fn deserialize_patterns<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<String>, D::Error> {
+    let patterns = Vec::<String>::deserialize(deserializer)?;
+    check_patterns(&patterns).map_err(de::Error::custom)?;
+    Ok(patterns)
+}
+
+fn deserialize_percentile<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<f64, D::Error> {
+    let percentile = f64::deserialize(deserializer)?;
+    if percentile > 0.0 && percentile <= 100.0 {
+        Ok(percentile)
+    } else {
+        Err(de::Error::custom(format!(
+            "Percentile {} isn't within (0, 100]",
+            percentile
+        )))
+    }
+}
+
+/// The percentile of the numbers of the array, linearly interpolated between
+/// their closest ranks, `None` for an empty array, one holding anything else
+/// than numbers, or a percentile outside of (0, 100]
+fn percentile(v: &Value, percentile: f64) -> Option<f64> {
+    if !(percentile > 0.0 && percentile <= 100.0) {
+        return None;
+    }
+    let mut xs = v
+        .as_array()?
+        .iter()
+        .map(Value::as_f64)
+        .collect::<Option<Vec<_>>>()?;
+    if xs.is_empty() {
+        return None;
+    }
+    xs.sort_by(f64::total_cmp);
+
+    let rank = percentile / 100.0 * (xs.len() - 1) as f64;
+    let (below, above) = (rank.floor() as usize, rank.ceil() as usize);
+    Some(xs[below] + (rank - below as f64) * (xs[above] - xs[below]))
+}
+
+/// The longest strings, in characters, the fuzzy constraints compare, as
+/// their distance takes the product of both lengths to compute
+pub const FUZZY_MAX_CHARS: usize = 256;
+
+/// The optimal string alignment distance of two strings, `None` when either
+/// is longer than `FUZZY_MAX_CHARS`
+fn edit_distance(a: &str, b: &str, case_sensitive: bool) -> Option<usize> {
+    let chars = |s: &str| -> Option<Vec<char>> {
+        let chars: Vec<char> = if case_sensitive {
+            s.chars().collect()
+        } else {
+            s.chars().flat_map(char::to_lowercase).collect()
+        };
+        Some(chars).filter(|chars| chars.len() <= FUZZY_MAX_CHARS)
+    };
+    let (a, b) = (chars(a)?, chars(b)?);
+
+    // the rows of the matrix for b[..j], two and one above the current one
+    let mut before: Vec<usize> = vec![0; b.len() + 1];
+    let mut above: Vec<usize> = (0..=b.len()).collect();
+    let mut row = vec![0; b.len() + 1];
+    for i in 1..=a.len() {
+        row[0] = i;
+        for j in 1..=b.len() {
+            let cost = (a[i - 1] != b[j - 1]) as usize;
+            row[j] =
+                (above[j] + 1).min(row[j - 1] + 1).min(above[j - 1] + cost);
+            if i > 1 && j > 1 && a[i - 1] == b[j - 2] && a[i - 2] == b[j - 1] {
+                row[j] = row[j].min(before[j - 2] + 1);
+            }
+        }
+        std::mem::swap(&mut before, &mut above);
+        std::mem::swap(&mut above, &mut row);
+    }
+    Some(above[b.len()])
+}
+
+/// One minus the edit distance over the length of the longer string
+fn similarity(a: &str, b: &str, case_sensitive: bool) -> Option<f64> {
+    let distance = edit_distance(a, b, case_sensitive)?;
+    let len = a.chars().count().max(b.chars().count());
+    if len == 0 {
+        return Some(1.0);
+    }
+    Some(1.0 - distance as f64 / len as f64)
+}
+
+/// Large sets of values registered on the engine, which constraints refer to
+/// by name
+#[derive(Debug, Default, Clone)]
+pub(crate) struct NamedSets {
+    pub(crate) strings: HashMap<String, HashSet<String>>,
+    pub(crate) ints: HashMap<String, HashSet<i64>>,
+}
+
+impl NamedSets {
+    /// Name of the first set referenced by the constraint that isn't registered
+    pub(crate) fn missing<'a>(
+        &self,
+        constraint: &'a Constraint,
+    ) -> Option<&'a str> {
+        match constraint {
+            Constraint::StringInNamedSet(name)
+            | Constraint::StringNotInNamedSet(name)
+                if !self.strings.contains_key(name) =>
+            {
+                Some(name)
+            }
+            Constraint::IntInNamedSet(name)
+            | Constraint::IntNotInNamedSet(name)
+                if !self.ints.contains_key(name) =>
+            {
+                Some(name)
+            }
+            Constraint::AnyMatch(condition)
+            | Constraint::NoneMatch(condition) => {
+                condition.leaves().into_iter().find_map(|leaf| match leaf {
+                    Condition::Condition {
+                        constraint: ValueOrVar::Value(constraint),
+                        ..
+                    } => self.missing(constraint),
+                    _ => None,
+                })
+            }
+            _ => None,
+        }
+    }
+}
+
+/// A single `@` between a local part without whitespace and a domain of at
+/// least two dot separated labels
+fn is_email(s: &str) -> bool {
+    let (local, domain) = match s.split_once('@') {
+        Some(parts) => parts,
+        None => return false,
+    };
+
+    let label_ok = |label: &str| {
+        !label.is_empty()
+            && label.len() <= 63
+            && !label.starts_with('-')
+            && !label.ends_with('-')
+            && label.chars().all(|c| c.is_alphanumeric() || c == '-')
+    };
+
+    !local.is_empty()
+        && local.len() <= 64
+        && !local.contains(|c: char| c.is_whitespace() || c == '@')
+        && domain.len() <= 255
+        && domain.contains('.')
+        && domain.split('.').all(label_ok)
+}
+
+/// Whether the value is within the bounds, both included, a missing one
+/// leaving that side open
+fn in_open_range<T: PartialOrd>(
+    v: T,
+    start: Option<T>,
+    end: Option<T>,
+) -> bool {
+    start.is_none_or(|start| start <= v) && end.is_none_or(|end| v <= end)
+}
+
+/// The domain in lowercase, without its trailing dot
+fn normalize_domain(domain: &str) -> String {
+    domain.strip_suffix('.').unwrap_or(domain).to_lowercase()
+}
+
+/// Whether the hostname is the domain, see `DomainEquals`
+fn domain_equals(hostname: &str, domain: &str) -> bool {
+    let hostname = normalize_domain(hostname);
+    !hostname.is_empty() && hostname == normalize_domain(domain)
+}
+
+/// Whether the hostname matches the pattern, see `DomainMatchesWildcard`
+fn domain_matches_wildcard(hostname: &str, pattern: &str) -> bool {
+    match pattern.strip_prefix("*.") {
+        Some(parent) => normalize_domain(hostname).split_once('.').is_some_and(
+            |(label, rest)| {
+                !label.is_empty() && rest == normalize_domain(parent)
+            },
+        ),
+        None => domain_equals(hostname, pattern),
+    }
+}
+
+/// The domain of an email address, after its last `@`, unless either part
+/// is empty
+fn email_domain(email: &str) -> Option<&str> {
+    let (local, domain) = email.rsplit_once('@')?;
+    Some(domain).filter(|domain| {
+        !local.is_empty()
+            && !domain.is_empty()
+            && !domain.contains(char::is_whitespace)
+    })
+}
+
+/// Compares two JSON numbers by value, exactly when either is an integer
+fn compare_numbers(a: &Number, b: &Number) -> Option<Ordering> {
+    fn as_int(n: &Number) -> Option<i128> {
+        n.as_i64()
+            .map(i128::from)
+            .or_else(|| n.as_u64().map(i128::from))
+    }
+
+    /// Compares an integer with a float without rounding the integer
+    fn cmp_int_float(i: i128, f: f64) -> Option<Ordering> {
+        if f.is_nan() {
+            return None;
+        }
+
+        let floor = f.floor();
+        if floor >= i128::MAX as f64 {
+            return Some(Ordering::Less);
+        }
+        if floor < i128::MIN as f64 {
+            return Some(Ordering::Greater);
+        }
+
+        Some(match i.cmp(&(floor as i128)) {
+            Ordering::Equal if f > floor => Ordering::Less,
+            ord => ord,
+        })
+    }
+
+    match (as_int(a), as_int(b)) {
+        (Some(a), Some(b)) => Some(a.cmp(&b)),
+        (Some(a), None) => cmp_int_float(a, b.as_f64()?),
+        (None, Some(b)) => cmp_int_float(b, a.as_f64()?).map(Ordering::reverse),
+        (None, None) => a.as_f64()?.partial_cmp(&b.as_f64()?),
+    }
+}
+
+/// Deep equality of JSON values, numbers compared by value
+fn json_equals(a: &Value, b: &Value) -> bool {
+    match (a, b) {
+        (Value::Number(a), Value::Number(b)) => {
+            compare_numbers(a, b) == Some(Ordering::Equal)
+        }
+        (Value::Array(a), Value::Array(b)) => {
+            a.len() == b.len()
+                && a.iter().zip(b).all(|(a, b)| json_equals(a, b))
+        }
+        (Value::Object(a), Value::Object(b)) => {
+            a.len() == b.len()
+                && a.iter()
+                    .all(|(k, a)| b.get(k).is_some_and(|b| json_equals(a, b)))
+        }
+        (a, b) => a == b,
+    }
+}
+
+/// `Met` when the fact is in a set, or isn't for a negated constraint,
+/// `NotMet` when it's not of the set's type
+fn set_status(found: Option<bool>, negate: bool) -> Status {
+    match found {
+        Some(found) if found != negate => Status::Met,
+        _ => Status::NotMet,
+    }
+}
+
+/// The facts the conditions of `any_match` and `none_match` see for an
+/// element, the element with the whole facts under the alias when there's
+/// one, see `Engine::set_element_alias`
+fn element_facts<'v>(x: &'v Value, ctx: &EvalContext) -> Cow<'v, Value> {
+    match (ctx.element_root, x) {
+        (Some((alias, root)), Value::Object(members))
+            if !members.contains_key(alias) =>
+        {
+            let mut members = members.clone();
+            members.insert(alias.to_owned(), root.clone());
+            Cow::Owned(Value::Object(members))
+        }
+        _ => Cow::Borrowed(x),
+    }
 }
 
 impl Constraint {
-    fn value_as_str_array(v: &Value) -> Option<Vec<&str>> {
-        v.as_array()
-            .map(|x| x.iter().filter_map(|y| y.as_str()).collect::<Vec<_>>())
+    // The array helpers iterate the facts lazily rather than collecting
+    // them, as they may hold hundreds of thousands of elements
+
+    /// The string elements of an array, skipping the others
+    fn value_as_strs(
+        v: &Value,
+    ) -> Option<impl Iterator<Item = &str> + Clone + '_> {
+        Some(v.as_array()?.iter().filter_map(Value::as_str))
+    }
+
+    /// The integer elements of an array, skipping the others
+    fn value_as_i64s(
+        v: &Value,
+    ) -> Option<impl Iterator<Item = i64> + Clone + '_> {
+        Some(v.as_array()?.iter().filter_map(Value::as_i64))
     }
 
-    fn value_as_i64_array(v: &Value) -> Option<Vec<i64>> {
-        v.as_array()
-            .map(|x| x.iter().filter_map(|y| y.as_i64()).collect::<Vec<_>>())
+    /// The number elements of an array, skipping the others
+    fn value_as_f64s(
+        v: &Value,
+    ) -> Option<impl Iterator<Item = f64> + Clone + '_> {
+        Some(v.as_array()?.iter().filter_map(Value::as_f64))
     }
 
-    fn value_as_f64_array(v: &Value) -> Option<Vec<f64>> {
-        v.as_array()
-            .map(|x| x.iter().filter_map(|y| y.as_f64()).collect::<Vec<_>>())
+    fn f64_contains(mut v: impl Iterator<Item = f64>, num: f64) -> bool {
+        v.any(|x| (x - num).abs() < f64::EPSILON)
+    }
+
+    /// The named groups of a `StringMatches` regex matching the value, `None`
+    /// without any
+    #[cfg(feature = "regex")]
+    pub(crate) fn captures(
+        &self,
+        v: &Value,
+    ) -> Option<serde_json::Map<String, Value>> {
+        let (pattern, v) = match (self, v.as_str()) {
+            (Constraint::StringMatches(pattern), Some(v)) => (pattern, v),
+            _ => return None,
+        };
+        let regex = regex::Regex::new(pattern).ok()?;
+        let captures = regex.captures(v)?;
+
+        let named: serde_json::Map<_, _> = regex
+            .capture_names()
+            .flatten()
+            .filter_map(|name| {
+                let capture = captures.name(name)?;
+                Some((name.to_owned(), Value::from(capture.as_str())))
+            })
+            .collect();
+        Some(named).filter(|named| !named.is_empty())
+    }
+
+    /// Returns a copy of this constraint with every string operand (including
+    /// the elements of string vectors) passed through `f`
+    pub(crate) fn map_strings(&self, f: impl Fn(&str) -> String) -> Constraint {
+        match self.try_map_strings(|s| Ok::<_, Infallible>(f(s))) {
+            Ok(constraint) => constraint,
+            Err(e) => match e {},
+        }
+    }
+
+    /// Same as `map_strings`, stopping at the first error of `f`
+    pub(crate) fn try_map_strings<E>(
+        &self,
+        f: impl Fn(&str) -> Result<String, E>,
+    ) -> Result<Constraint, E> {
+        let map_all = |ss: &[String]| {
+            ss.iter().map(|s| f(s)).collect::<Result<Vec<_>, E>>()
+        };
+
+        Ok(match *self {
+            Constraint::StringEquals(ref s) => Constraint::StringEquals(f(s)?),
+            Constraint::StringNotEquals(ref s) => {
+                Constraint::StringNotEquals(f(s)?)
+            }
+            Constraint::StringContains(ref s) => {
+                Constraint::StringContains(f(s)?)
+            }
+            Constraint::StringContainsAll(ref ss) => {
+                Constraint::StringContainsAll(map_all(ss)?)
+            }
+            Constraint::StringContainsAny(ref ss) => {
+                Constraint::StringContainsAny(map_all(ss)?)
+            }
+            Constraint::StringDoesNotContain(ref s) => {
+                Constraint::StringDoesNotContain(f(s)?)
+            }
+            Constraint::StringDoesNotContainAny(ref ss) => {
+                Constraint::StringDoesNotContainAny(map_all(ss)?)
+            }
+            Constraint::StringIn(ref ss) => Constraint::StringIn(map_all(ss)?),
+            Constraint::StringNotIn(ref ss) => {
+                Constraint::StringNotIn(map_all(ss)?)
+            }
+            Constraint::StringFuzzyMatches {
+                ref value,
+                max_distance,
+                case_sensitive,
+            } => Constraint::StringFuzzyMatches {
+                value: f(value)?,
+                max_distance,
+                case_sensitive,
+            },
+            Constraint::StringSimilarityAtLeast {
+                ref value,
+                ratio,
+                case_sensitive,
+            } => Constraint::StringSimilarityAtLeast {
+                value: f(value)?,
+                ratio,
+                case_sensitive,
+            },
+            Constraint::DomainEquals(ref s) => Constraint::DomainEquals(f(s)?),
+            Constraint::DomainIn(ref ss) => Constraint::DomainIn(map_all(ss)?),
+            Constraint::DomainMatchesWildcard(ref s) => {
+                Constraint::DomainMatchesWildcard(f(s)?)
+            }
+            Constraint::EmailDomainIn(ref ss) => {
+                Constraint::EmailDomainIn(map_all(ss)?)
+            }
+            _ => self.clone(),
+        })
+    }
+
+    /// Whether each element of an array differs from the ones before it,
+    /// objects being compared regardless of their key order
+    fn value_as_distinct_flags(
+        v: &Value,
+    ) -> Option<impl Iterator<Item = bool> + '_> {
+        fn canonical(v: &Value) -> String {
+            match v {
+                Value::Object(map) => {
+                    let mut entries = map
+                        .iter()
+                        .map(|(k, v)| {
+                            format!(
+                                "{}:{}",
+                                Value::from(k.as_str()),
+                                canonical(v)
+                            )
+                        })
+                        .collect::<Vec<_>>();
+                    entries.sort();
+                    format!("{{{}}}", entries.join(","))
+                }
+                Value::Array(vs) => format!(
+                    "[{}]",
+                    vs.iter().map(canonical).collect::<Vec<_>>().join(",")
+                ),
+                v => v.to_string(),
+            }
+        }
+
+        let mut seen = HashSet::new();
+        Some(v.as_array()?.iter().map(move |x| seen.insert(canonical(x))))
+    }
+
+    /// The seconds of a duration fact, numbers being seconds already
+    fn value_as_secs(v: &Value) -> Option<f64> {
+        match v {
+            Value::Number(n) => n.as_f64(),
+            Value::String(s) => parse_duration(s),
+            _ => None,
+        }
+    }
+
+    fn value_as_datetime(v: &Value) -> Option<DateTime<Utc>> {
+        v.as_str()
+            .and_then(|x| DateTime::parse_from_rfc3339(x).ok())
+            .map(|x| x.with_timezone(&Utc))
+    }
+
+    /// Checks a time relative constraint as of `now`
+    fn check_datetime(&self, v: &Value, now: DateTime<Utc>) -> Status {
+        let v = match Self::value_as_datetime(v) {
+            None => return Status::NotMet,
+            Some(v) => v,
+        };
+
+        let met = match *self {
+            Constraint::DatetimeWithinLast(secs) => {
+                v <= now && now - v <= Duration::seconds(secs)
+            }
+            Constraint::DatetimeOlderThan(secs) => {
+                now - v > Duration::seconds(secs)
+            }
+            _ => unreachable!(),
+        };
+
+        if met {
+            Status::Met
+        } else {
+            Status::NotMet
+        }
+    }
+
+    /// The first `max` elements of an array fact the constraint looks for:
+    /// those it holds for the `*Contains*` constraints, those of the value
+    /// for the subsets and supersets, and those meeting the condition for
+    /// `AnyMatch` and `NoneMatch`. Empty for the other constraints
+    pub(crate) fn matched_values(
+        &self,
+        v: &Value,
+        ctx: &EvalContext,
+        max: usize,
+    ) -> Vec<Value> {
+        let close = |x: f64, ys: &[f64]| {
+            ys.iter().any(|y| (x - y).abs() < f64::EPSILON)
+        };
+        let matches: Box<dyn Fn(&Value) -> bool + '_> = match *self {
+            Constraint::StringContains(ref s)
+            | Constraint::StringDoesNotContain(ref s) => {
+                Box::new(move |x| x.as_str() == Some(s))
+            }
+            Constraint::StringContainsAll(ref ss)
+            | Constraint::StringContainsAny(ref ss)
+            | Constraint::StringDoesNotContainAny(ref ss) => {
+                Box::new(move |x| {
+                    x.as_str().is_some_and(|x| ss.iter().any(|s| s == x))
+                })
+            }
+            Constraint::IntContains(num)
+            | Constraint::IntDoesNotContain(num) => {
+                Box::new(move |x| x.as_i64() == Some(num))
+            }
+            Constraint::IntContainsAll(ref nums)
+            | Constraint::IntContainsAny(ref nums)
+            | Constraint::IntDoesNotContainAny(ref nums)
+            | Constraint::IntIsSubset(ref nums)
+            | Constraint::IntIsSuperset(ref nums) => {
+                Box::new(move |x| x.as_i64().is_some_and(|x| nums.contains(&x)))
+            }
+            Constraint::FloatContains(num)
+            | Constraint::FloatDoesNotContain(num) => {
+                Box::new(move |x| x.as_f64() == Some(num))
+            }
+            Constraint::FloatIsSubset(ref nums)
+            | Constraint::FloatIsSuperset(ref nums) => {
+                Box::new(move |x| x.as_f64().is_some_and(|x| close(x, nums)))
+            }
+            Constraint::AnyMatch(ref condition)
+            | Constraint::NoneMatch(ref condition) => Box::new(move |x| {
+                condition
+                    .check_value_with(&element_facts(x, ctx), ctx)
+                    .status
+                    == Status::Met
+            }),
+            _ => return Vec::new(),
+        };
+
+        match v.as_array() {
+            Some(xs) => xs
+                .iter()
+                .filter(|x| matches(x))
+                .take(max)
+                .cloned()
+                .collect(),
+            None => Vec::new(),
+        }
+    }
+
+    /// Same as `check_value`, looking up named sets and the current time in
+    /// `ctx`. A named set that isn't registered gives `Unknown`
+    pub(crate) fn check_value_with(
+        &self,
+        v: &Value,
+        ctx: &EvalContext,
+    ) -> Status {
+        match *self {
+            Constraint::StringInNamedSet(ref name)
+            | Constraint::StringNotInNamedSet(ref name) => {
+                let negate = matches!(self, Constraint::StringNotInNamedSet(_));
+
+                match (ctx.sets.strings.get(name), v.as_str()) {
+                    (None, _) => Status::Unknown,
+                    (Some(_), None) => Status::NotMet,
+                    (Some(set), Some(v)) => {
+                        if set.contains(v) != negate {
+                            Status::Met
+                        } else {
+                            Status::NotMet
+                        }
+                    }
+                }
+            }
+            Constraint::IntInNamedSet(ref name)
+            | Constraint::IntNotInNamedSet(ref name) => {
+                let negate = matches!(self, Constraint::IntNotInNamedSet(_));
+
+                match (ctx.sets.ints.get(name), v.as_i64()) {
+                    (None, _) => Status::Unknown,
+                    (Some(_), None) => Status::NotMet,
+                    (Some(set), Some(v)) => {
+                        if set.contains(&v) != negate {
+                            Status::Met
+                        } else {
+                            Status::NotMet
+                        }
+                    }
+                }
+            }
+            Constraint::DatetimeWithinLast(_)
+            | Constraint::DatetimeOlderThan(_) => {
+                self.check_datetime(v, ctx.now)
+            }
+            // the long lists of a compiled rule are hashed
+            Constraint::StringIn(ref ss) | Constraint::StringNotIn(ref ss) => {
+                match ctx.plan.and_then(|plan| plan.string_set(ss)) {
+                    Some(set) => set_status(
+                        v.as_str().map(|v| set.contains(v)),
+                        matches!(self, Constraint::StringNotIn(_)),
+                    ),
+                    None => self.check_value(v),
+                }
+            }
+            // as are the patterns of its regex sets
+            #[cfg(feature = "regex")]
+            Constraint::StringMatchesAny(ref patterns)
+            | Constraint::StringMatchesNone(ref patterns) => {
+                match ctx.plan.and_then(|plan| plan.regex_set(patterns)) {
+                    Some(set) => set_status(
+                        v.as_str().map(|v| set.is_match(v)),
+                        matches!(self, Constraint::StringMatchesNone(_)),
+                    ),
+                    None => self.check_value(v),
+                }
+            }
+            Constraint::IntIn(ref nums) | Constraint::IntNotIn(ref nums) => {
+                match ctx.plan.and_then(|plan| plan.int_set(nums)) {
+                    Some(set) => set_status(
+                        v.as_i64().map(|v| set.contains(&v)),
+                        matches!(self, Constraint::IntNotIn(_)),
+                    ),
+                    None => self.check_value(v),
+                }
+            }
+            Constraint::IntIsSubset(ref nums) => {
+                match (ctx.plan.and_then(|plan| plan.int_set(nums)), v) {
+                    (Some(set), Value::Array(xs)) => set_status(
+                        Some(xs.iter().all(|x| {
+                            x.as_i64().is_some_and(|x| set.contains(&x))
+                        })),
+                        false,
+                    ),
+                    (Some(_), _) => Status::NotMet,
+                    (None, _) => self.check_value(v),
+                }
+            }
+            Constraint::UintIn(ref nums) | Constraint::UintNotIn(ref nums) => {
+                match ctx.plan.and_then(|plan| plan.uint_set(nums)) {
+                    Some(set) => set_status(
+                        v.as_u64().map(|v| set.contains(&v)),
+                        matches!(self, Constraint::UintNotIn(_)),
+                    ),
+                    None => self.check_value(v),
+                }
+            }
+            Constraint::AnyMatch(ref condition)
+            | Constraint::NoneMatch(ref condition) => {
+                let any = match v.as_array() {
+                    None => return Status::NotMet,
+                    Some(xs) => xs.iter().fold(Status::NotMet, |any, x| {
+                        any | condition
+                            .check_value_with(&element_facts(x, ctx), ctx)
+                            .status
+                    }),
+                };
+
+                if matches!(self, Constraint::NoneMatch(_)) {
+                    !any
+                } else {
+                    any
+                }
+            }
+            _ => self.check_value(v),
+        }
     }
 
     pub fn check_value(&self, v: &Value) -> Status {
@@ -86,23 +1154,21 @@ impl Constraint {
                     }
                 }
             },
-            Constraint::StringContains(ref s) => {
-                match Self::value_as_str_array(v) {
-                    None => Status::NotMet,
-                    Some(v) => {
-                        if v.contains(&s.as_str()) {
-                            Status::Met
-                        } else {
-                            Status::NotMet
-                        }
+            Constraint::StringContains(ref s) => match Self::value_as_strs(v) {
+                None => Status::NotMet,
+                Some(mut xs) => {
+                    if xs.any(|x| x == s) {
+                        Status::Met
+                    } else {
+                        Status::NotMet
                     }
                 }
-            }
+            },
             Constraint::StringContainsAll(ref s) => {
-                match Self::value_as_str_array(v) {
+                match Self::value_as_strs(v) {
                     None => Status::NotMet,
-                    Some(v) => {
-                        if s.iter().all(|y| v.contains(&y.as_str())) {
+                    Some(xs) => {
+                        if s.iter().all(|y| xs.clone().any(|x| x == y)) {
                             Status::Met
                         } else {
                             Status::NotMet
@@ -111,10 +1177,10 @@ impl Constraint {
                 }
             }
             Constraint::StringContainsAny(ref s) => {
-                match Self::value_as_str_array(v) {
+                match Self::value_as_strs(v) {
                     None => Status::NotMet,
-                    Some(v) => {
-                        if s.iter().any(|y| v.contains(&y.as_str())) {
+                    Some(mut xs) => {
+                        if xs.any(|x| s.iter().any(|y| x == y)) {
                             Status::Met
                         } else {
                             Status::NotMet
@@ -123,10 +1189,10 @@ impl Constraint {
                 }
             }
             Constraint::StringDoesNotContain(ref s) => {
-                match Self::value_as_str_array(v) {
+                match Self::value_as_strs(v) {
                     None => Status::NotMet,
-                    Some(v) => {
-                        if !v.contains(&s.as_str()) {
+                    Some(mut xs) => {
+                        if xs.all(|x| x != s) {
                             Status::Met
                         } else {
                             Status::NotMet
@@ -135,10 +1201,10 @@ impl Constraint {
                 }
             }
             Constraint::StringDoesNotContainAny(ref s) => {
-                match Self::value_as_str_array(v) {
+                match Self::value_as_strs(v) {
                     None => Status::NotMet,
-                    Some(v) => {
-                        if s.iter().all(|y| !v.contains(&y.as_str())) {
+                    Some(mut xs) => {
+                        if xs.all(|x| s.iter().all(|y| x != y)) {
                             Status::Met
                         } else {
                             Status::NotMet
@@ -166,6 +1232,64 @@ impl Constraint {
                     }
                 }
             },
+            Constraint::StringFuzzyMatches {
+                ref value,
+                max_distance,
+                case_sensitive,
+            } => match v
+                .as_str()
+                .and_then(|v| edit_distance(v, value, case_sensitive))
+            {
+                Some(distance) if distance <= max_distance as usize => {
+                    Status::Met
+                }
+                _ => Status::NotMet,
+            },
+            Constraint::StringSimilarityAtLeast {
+                ref value,
+                ratio,
+                case_sensitive,
+            } => match v
+                .as_str()
+                .and_then(|v| similarity(v, value, case_sensitive))
+            {
+                Some(similarity) if similarity >= ratio => Status::Met,
+                _ => Status::NotMet,
+            },
+            #[cfg(feature = "regex")]
+            Constraint::StringMatches(ref pattern) => {
+                match (regex::Regex::new(pattern), v.as_str()) {
+                    (Err(_), _) => Status::Unknown,
+                    (Ok(_), None) => Status::NotMet,
+                    (Ok(regex), Some(v)) => {
+                        if regex.is_match(v) {
+                            Status::Met
+                        } else {
+                            Status::NotMet
+                        }
+                    }
+                }
+            }
+            #[cfg(feature = "regex")]
+            Constraint::StringMatchesAny(ref patterns)
+            | Constraint::StringMatchesNone(ref patterns) => {
+                match regex::RegexSet::new(patterns) {
+                    Err(_) => Status::Unknown,
+                    Ok(set) => set_status(
+                        v.as_str().map(|v| set.is_match(v)),
+                        matches!(self, Constraint::StringMatchesNone(_)),
+                    ),
+                }
+            }
+            // named sets live on the engine
+            Constraint::StringInNamedSet(_)
+            | Constraint::StringNotInNamedSet(_)
+            | Constraint::IntInNamedSet(_)
+            | Constraint::IntNotInNamedSet(_) => Status::Unknown,
+            // as do the rhai engine and the variables nested conditions need
+            Constraint::AnyMatch(_) | Constraint::NoneMatch(_) => {
+                Status::Unknown
+            }
             Constraint::IntEquals(num) => match v.as_i64() {
                 None => Status::NotMet,
                 Some(v) => {
@@ -186,10 +1310,10 @@ impl Constraint {
                     }
                 }
             },
-            Constraint::IntContains(num) => match Self::value_as_i64_array(v) {
+            Constraint::IntContains(num) => match Self::value_as_i64s(v) {
                 None => Status::NotMet,
-                Some(v) => {
-                    if v.contains(&num) {
+                Some(mut xs) => {
+                    if xs.any(|x| x == num) {
                         Status::Met
                     } else {
                         Status::NotMet
@@ -197,10 +1321,11 @@ impl Constraint {
                 }
             },
             Constraint::IntContainsAll(ref nums) => {
-                match Self::value_as_i64_array(v) {
+                match Self::value_as_i64s(v) {
                     None => Status::NotMet,
-                    Some(v) => {
-                        if nums.iter().all(|num| v.contains(&num)) {
+                    Some(xs) => {
+                        if nums.iter().all(|&num| xs.clone().any(|x| x == num))
+                        {
                             Status::Met
                         } else {
                             Status::NotMet
@@ -209,10 +1334,10 @@ impl Constraint {
                 }
             }
             Constraint::IntContainsAny(ref nums) => {
-                match Self::value_as_i64_array(v) {
+                match Self::value_as_i64s(v) {
                     None => Status::NotMet,
-                    Some(v) => {
-                        if nums.iter().any(|num| v.contains(&num)) {
+                    Some(mut xs) => {
+                        if xs.any(|x| nums.contains(&x)) {
                             Status::Met
                         } else {
                             Status::NotMet
@@ -221,10 +1346,10 @@ impl Constraint {
                 }
             }
             Constraint::IntDoesNotContain(num) => {
-                match Self::value_as_i64_array(v) {
+                match Self::value_as_i64s(v) {
                     None => Status::NotMet,
-                    Some(v) => {
-                        if !v.contains(&num) {
+                    Some(mut xs) => {
+                        if xs.all(|x| x != num) {
                             Status::Met
                         } else {
                             Status::NotMet
@@ -233,10 +1358,10 @@ impl Constraint {
                 }
             }
             Constraint::IntDoesNotContainAny(ref nums) => {
-                match Self::value_as_i64_array(v) {
+                match Self::value_as_i64s(v) {
                     None => Status::NotMet,
-                    Some(v) => {
-                        if nums.iter().all(|num| !v.contains(&num)) {
+                    Some(mut xs) => {
+                        if xs.all(|x| !nums.contains(&x)) {
                             Status::Met
                         } else {
                             Status::NotMet
@@ -244,36 +1369,57 @@ impl Constraint {
                     }
                 }
             }
-            Constraint::IntIn(ref nums) => match v.as_i64() {
+            Constraint::IntIsSubset(ref nums) => match v.as_array() {
                 None => Status::NotMet,
-                Some(v) => {
-                    if nums.iter().any(|&num| num == v) {
+                Some(xs) => {
+                    let nums: HashSet<i64> = nums.iter().copied().collect();
+                    if xs
+                        .iter()
+                        .all(|x| x.as_i64().is_some_and(|x| nums.contains(&x)))
+                    {
                         Status::Met
                     } else {
                         Status::NotMet
                     }
                 }
             },
-            Constraint::IntNotIn(ref nums) => match v.as_i64() {
+            Constraint::IntIsSuperset(ref nums) => {
+                match Self::value_as_i64s(v) {
+                    None => Status::NotMet,
+                    Some(xs) => {
+                        if nums.iter().all(|&num| xs.clone().any(|x| x == num))
+                        {
+                            Status::Met
+                        } else {
+                            Status::NotMet
+                        }
+                    }
+                }
+            }
+            Constraint::IntIn(ref nums) => match v.as_i64() {
                 None => Status::NotMet,
                 Some(v) => {
-                    if nums.iter().all(|&num| num != v) {
+                    if nums.contains(&v) {
                         Status::Met
                     } else {
                         Status::NotMet
                     }
                 }
             },
-            Constraint::IntInRange(start, end) => match v.as_i64() {
+            Constraint::IntNotIn(ref nums) => match v.as_i64() {
                 None => Status::NotMet,
                 Some(v) => {
-                    if start <= v && v <= end {
+                    if nums.iter().all(|&num| num != v) {
                         Status::Met
                     } else {
                         Status::NotMet
                     }
                 }
             },
+            Constraint::IntInRange(start, end) => set_status(
+                v.as_i64().map(|v| in_open_range(v, start, end)),
+                false,
+            ),
             Constraint::IntNotInRange(start, end) => match v.as_i64() {
                 None => Status::NotMet,
                 Some(v) => {
@@ -324,31 +1470,121 @@ impl Constraint {
                     }
                 }
             },
-            Constraint::FloatEquals(num) => match v.as_f64() {
+            Constraint::UintEquals(num) => match v.as_u64() {
                 None => Status::NotMet,
                 Some(v) => {
-                    if (v - num).abs() < f64::EPSILON {
+                    if v == num {
                         Status::Met
                     } else {
                         Status::NotMet
                     }
                 }
             },
-            Constraint::FloatNotEquals(num) => match v.as_f64() {
+            Constraint::UintNotEquals(num) => match v.as_u64() {
                 None => Status::NotMet,
                 Some(v) => {
-                    if (v - num).abs() > f64::EPSILON {
+                    if v != num {
+                        Status::Met
+                    } else {
+                        Status::NotMet
+                    }
+                }
+            },
+            Constraint::UintIn(ref nums) => match v.as_u64() {
+                None => Status::NotMet,
+                Some(v) => {
+                    if nums.contains(&v) {
+                        Status::Met
+                    } else {
+                        Status::NotMet
+                    }
+                }
+            },
+            Constraint::UintNotIn(ref nums) => match v.as_u64() {
+                None => Status::NotMet,
+                Some(v) => {
+                    if nums.iter().all(|&num| num != v) {
+                        Status::Met
+                    } else {
+                        Status::NotMet
+                    }
+                }
+            },
+            Constraint::UintInRange(start, end) => match v.as_u64() {
+                None => Status::NotMet,
+                Some(v) => {
+                    if start <= v && v <= end {
+                        Status::Met
+                    } else {
+                        Status::NotMet
+                    }
+                }
+            },
+            Constraint::UintNotInRange(start, end) => match v.as_u64() {
+                None => Status::NotMet,
+                Some(v) => {
+                    if start <= v && v <= end {
+                        Status::NotMet
+                    } else {
+                        Status::Met
+                    }
+                }
+            },
+            Constraint::UintLessThan(num) => match v.as_u64() {
+                None => Status::NotMet,
+                Some(v) => {
+                    if v < num {
+                        Status::Met
+                    } else {
+                        Status::NotMet
+                    }
+                }
+            },
+            Constraint::UintLessThanInclusive(num) => match v.as_u64() {
+                None => Status::NotMet,
+                Some(v) => {
+                    if v <= num {
+                        Status::Met
+                    } else {
+                        Status::NotMet
+                    }
+                }
+            },
+            Constraint::UintGreaterThan(num) => match v.as_u64() {
+                None => Status::NotMet,
+                Some(v) => {
+                    if v > num {
+                        Status::Met
+                    } else {
+                        Status::NotMet
+                    }
+                }
+            },
+            Constraint::UintGreaterThanInclusive(num) => match v.as_u64() {
+                None => Status::NotMet,
+                Some(v) => {
+                    if v >= num {
+                        Status::Met
+                    } else {
+                        Status::NotMet
+                    }
+                }
+            },
+            Constraint::FloatEquals(num) => match v.as_f64() {
+                None => Status::NotMet,
+                Some(v) => {
+                    if (v - num).abs() < f64::EPSILON {
                         Status::Met
                     } else {
                         Status::NotMet
                     }
                 }
             },
-            Constraint::FloatContains(num) => {
-                match Self::value_as_f64_array(v) {
+            Constraint::FloatEqualsRounded { value, decimals } => {
+                match v.as_f64() {
                     None => Status::NotMet,
                     Some(v) => {
-                        if v.contains(&num) {
+                        if round(v, decimals) == round(value, decimals) {
                             Status::Met
                         } else {
                             Status::NotMet
@@ -356,11 +1592,31 @@ impl Constraint {
                     }
                 }
             }
+            Constraint::FloatNotEquals(num) => match v.as_f64() {
+                None => Status::NotMet,
+                Some(v) => {
+                    if (v - num).abs() > f64::EPSILON {
+                        Status::Met
+                    } else {
+                        Status::NotMet
+                    }
+                }
+            },
+            Constraint::FloatContains(num) => match Self::value_as_f64s(v) {
+                None => Status::NotMet,
+                Some(mut xs) => {
+                    if xs.any(|x| x == num) {
+                        Status::Met
+                    } else {
+                        Status::NotMet
+                    }
+                }
+            },
             Constraint::FloatDoesNotContain(num) => {
-                match Self::value_as_f64_array(v) {
+                match Self::value_as_f64s(v) {
                     None => Status::NotMet,
-                    Some(v) => {
-                        if !v.contains(&num) {
+                    Some(mut xs) => {
+                        if xs.all(|x| x != num) {
                             Status::Met
                         } else {
                             Status::NotMet
@@ -368,36 +1624,59 @@ impl Constraint {
                     }
                 }
             }
-            Constraint::FloatIn(ref nums) => match v.as_f64() {
+            Constraint::FloatIsSubset(ref nums) => match v.as_array() {
                 None => Status::NotMet,
-                Some(v) => {
-                    if nums.iter().any(|&num| (v - num).abs() < f64::EPSILON) {
+                Some(xs) => {
+                    if xs.iter().all(|x| {
+                        x.as_f64().is_some_and(|x| {
+                            Self::f64_contains(nums.iter().copied(), x)
+                        })
+                    }) {
                         Status::Met
                     } else {
                         Status::NotMet
                     }
                 }
             },
-            Constraint::FloatNotIn(ref nums) => match v.as_f64() {
+            Constraint::FloatIsSuperset(ref nums) => {
+                match Self::value_as_f64s(v) {
+                    None => Status::NotMet,
+                    Some(xs) => {
+                        if nums
+                            .iter()
+                            .all(|&num| Self::f64_contains(xs.clone(), num))
+                        {
+                            Status::Met
+                        } else {
+                            Status::NotMet
+                        }
+                    }
+                }
+            }
+            Constraint::FloatIn(ref nums) => match v.as_f64() {
                 None => Status::NotMet,
                 Some(v) => {
-                    if nums.iter().all(|&num| (v - num).abs() > f64::EPSILON) {
+                    if nums.iter().any(|&num| (v - num).abs() < f64::EPSILON) {
                         Status::Met
                     } else {
                         Status::NotMet
                     }
                 }
             },
-            Constraint::FloatInRange(start, end) => match v.as_f64() {
+            Constraint::FloatNotIn(ref nums) => match v.as_f64() {
                 None => Status::NotMet,
                 Some(v) => {
-                    if start <= v && v <= end {
+                    if nums.iter().all(|&num| (v - num).abs() > f64::EPSILON) {
                         Status::Met
                     } else {
                         Status::NotMet
                     }
                 }
             },
+            Constraint::FloatInRange(start, end) => set_status(
+                v.as_f64().map(|v| in_open_range(v, start, end)),
+                false,
+            ),
             Constraint::FloatNotInRange(start, end) => match v.as_f64() {
                 None => Status::NotMet,
                 Some(v) => {
@@ -448,6 +1727,88 @@ impl Constraint {
                     }
                 }
             },
+            Constraint::FloatPercentileGreaterThan {
+                percentile: p,
+                value,
+            } => match percentile(v, p) {
+                Some(v) if v > value => Status::Met,
+                _ => Status::NotMet,
+            },
+            Constraint::FloatPercentileLessThan {
+                percentile: p,
+                value,
+            } => match percentile(v, p) {
+                Some(v) if v < value => Status::Met,
+                _ => Status::NotMet,
+            },
+            Constraint::NumberEquals(ref num)
+            | Constraint::NumberNotEquals(ref num)
+            | Constraint::NumberLessThan(ref num)
+            | Constraint::NumberLessThanInclusive(ref num)
+            | Constraint::NumberGreaterThan(ref num)
+            | Constraint::NumberGreaterThanInclusive(ref num) => {
+                let ord =
+                    match v.as_number().and_then(|v| compare_numbers(v, num)) {
+                        None => return Status::NotMet,
+                        Some(ord) => ord,
+                    };
+
+                let met = match *self {
+                    Constraint::NumberEquals(_) => ord.is_eq(),
+                    Constraint::NumberNotEquals(_) => ord.is_ne(),
+                    Constraint::NumberLessThan(_) => ord.is_lt(),
+                    Constraint::NumberLessThanInclusive(_) => ord.is_le(),
+                    Constraint::NumberGreaterThan(_) => ord.is_gt(),
+                    _ => ord.is_ge(),
+                };
+
+                if met {
+                    Status::Met
+                } else {
+                    Status::NotMet
+                }
+            }
+            Constraint::NumberIn(ref nums) => match v.as_number() {
+                None => Status::NotMet,
+                Some(v) => {
+                    if nums.iter().any(|num| {
+                        compare_numbers(v, num) == Some(Ordering::Equal)
+                    }) {
+                        Status::Met
+                    } else {
+                        Status::NotMet
+                    }
+                }
+            },
+            Constraint::NumberNotIn(ref nums) => match v.as_number() {
+                None => Status::NotMet,
+                Some(v) => {
+                    if nums.iter().all(|num| {
+                        compare_numbers(v, num) != Some(Ordering::Equal)
+                    }) {
+                        Status::Met
+                    } else {
+                        Status::NotMet
+                    }
+                }
+            },
+            Constraint::NumberInRange(ref start, ref end)
+            | Constraint::NumberNotInRange(ref start, ref end) => {
+                let in_range = match v.as_number() {
+                    None => return Status::NotMet,
+                    Some(v) => {
+                        compare_numbers(v, start).is_some_and(Ordering::is_ge)
+                            && compare_numbers(v, end)
+                                .is_some_and(Ordering::is_le)
+                    }
+                };
+
+                if in_range == matches!(self, Constraint::NumberInRange(..)) {
+                    Status::Met
+                } else {
+                    Status::NotMet
+                }
+            }
             Constraint::BoolEquals(b) => match v.as_bool() {
                 None => Status::NotMet,
                 Some(v) => {
@@ -458,12 +1819,128 @@ impl Constraint {
                     }
                 }
             },
+            Constraint::ValueEquals(ref x)
+            | Constraint::ValueNotEquals(ref x) => set_status(
+                Some(json_equals(v, x)),
+                matches!(self, Constraint::ValueNotEquals(_)),
+            ),
+            Constraint::ValueIn(ref xs) => {
+                set_status(Some(xs.iter().any(|x| json_equals(v, x))), false)
+            }
+            Constraint::DatetimeWithinLast(_)
+            | Constraint::DatetimeOlderThan(_) => {
+                self.check_datetime(v, Utc::now())
+            }
+            Constraint::DurationGreaterThan(ref d)
+            | Constraint::DurationLessThan(ref d) => {
+                match (Self::value_as_secs(v), parse_duration(d)) {
+                    (Some(v), Some(d)) => {
+                        let greater =
+                            matches!(self, Constraint::DurationGreaterThan(_));
+                        if (greater && v > d) || (!greater && v < d) {
+                            Status::Met
+                        } else {
+                            Status::NotMet
+                        }
+                    }
+                    _ => Status::NotMet,
+                }
+            }
+            Constraint::DurationInRange(ref start, ref end) => {
+                match (
+                    Self::value_as_secs(v),
+                    parse_duration(start),
+                    parse_duration(end),
+                ) {
+                    (Some(v), Some(start), Some(end))
+                        if start <= v && v <= end =>
+                    {
+                        Status::Met
+                    }
+                    _ => Status::NotMet,
+                }
+            }
+            Constraint::ArrayAllUnique(b) => {
+                match Self::value_as_distinct_flags(v) {
+                    None => Status::NotMet,
+                    Some(mut distinct) => {
+                        // stops at the first duplicate
+                        if distinct.all(|distinct| distinct) == b {
+                            Status::Met
+                        } else {
+                            Status::NotMet
+                        }
+                    }
+                }
+            }
+            Constraint::IsUuid(b)
+            | Constraint::IsUlid(b)
+            | Constraint::IsEmail(b)
+            | Constraint::IsUrl(b) => match v.as_str() {
+                None => Status::NotMet,
+                Some(s) => {
+                    let is_format = match *self {
+                        Constraint::IsUuid(_) => {
+                            uuid::Uuid::parse_str(s).is_ok()
+                        }
+                        Constraint::IsUlid(_) => {
+                            s.parse::<rusty_ulid::Ulid>().is_ok()
+                        }
+                        Constraint::IsEmail(_) => is_email(s),
+                        _ => url::Url::parse(s).is_ok(),
+                    };
+
+                    if is_format == b {
+                        Status::Met
+                    } else {
+                        Status::NotMet
+                    }
+                }
+            },
+            Constraint::DomainEquals(ref domain) => {
+                set_status(v.as_str().map(|s| domain_equals(s, domain)), false)
+            }
+            Constraint::DomainIn(ref domains) => set_status(
+                v.as_str()
+                    .map(|s| domains.iter().any(|d| domain_equals(s, d))),
+                false,
+            ),
+            Constraint::DomainMatchesWildcard(ref pattern) => set_status(
+                v.as_str().map(|s| domain_matches_wildcard(s, pattern)),
+                false,
+            ),
+            Constraint::EmailDomainIn(ref domains) => set_status(
+                v.as_str().and_then(email_domain).map(|domain| {
+                    domains.iter().any(|d| domain_equals(domain, d))
+                }),
+                false,
+            ),
+            Constraint::ArrayDistinctCountGreaterThanInclusive(n) => {
+                match Self::value_as_distinct_flags(v) {
+                    None => Status::NotMet,
+                    Some(distinct) => {
+                        // stops once `n` distinct elements are found
+                        if distinct.filter(|&distinct| distinct).take(n).count()
+                            == n
+                        {
+                            Status::Met
+                        } else {
+                            Status::NotMet
+                        }
+                    }
+                }
+            }
         }
     }
 
     pub fn operators() -> &'static [&'static str] {
         Constraint::VARIANTS
     }
+
+    /// The name of the constraint's operator, e.g. `string_equals`
+    pub fn operator(&self) -> &'static str {
+        self.into()
+    }
 }
 
 #[cfg(test)]
@@ -472,6 +1949,7 @@ mod tests {
 
     #[test]
     fn available_operators() {
-        assert_eq!(Constraint::operators().len(), 37);
+        let regex = cfg!(feature = "regex") as usize;
+        assert_eq!(Constraint::operators().len(), 90 + 3 * regex);
     }
 }
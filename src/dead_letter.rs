@@ -0,0 +1,110 @@
+//! The events that failed to dispatch, kept to be replayed, see
+//! `Engine::set_dead_letter_sink`.
+//!
+//! A dead letter holds the event as it was sent: its params rendered against
+//! the facts, and where it was sent to. Events the engine refused to send,
+//! e.g. for their callback url or their size, aren't dead letters, only the
+//! events whose trigger failed are.
+
+use crate::{
+    event::{render_params, Event},
+    Engine, Error,
+};
+use chrono::{DateTime, Utc};
+use serde_json::Value;
+use std::{collections::HashMap, sync::Arc};
+
+/// How many dead letters the engine keeps without a sink, the oldest ones
+/// being dropped first, see `Engine::take_dead_letters`
+pub const MAX_DEAD_LETTERS: usize = 1000;
+
+/// The params an event is sent to, in the order they are looked for
+const TARGET_PARAMS: &[&str] = &[
+    "callback_url",
+    "webhook_url",
+    "to",
+    "topic_arn",
+    "queue_url",
+    "subject",
+];
+
+/// An event that failed to dispatch
+#[derive(Debug, Clone, PartialEq)]
+pub struct DeadLetter {
+    /// The id of the rule, or of the group, the event belongs to
+    pub rule_id: Option<String>,
+    pub event_type: String,
+    /// The params of the event, rendered against the facts
+    pub params: HashMap<String, Value>,
+    /// Where the event was sent to, e.g. its `callback_url` or its `to`
+    /// recipients, when its type has one
+    pub target: Option<Value>,
+    pub error: String,
+    /// How many times the event was triggered, events being triggered once
+    pub attempts: u32,
+    /// When the dispatch failed, as told by the engine's clock
+    pub timestamp: DateTime<Utc>,
+}
+
+/// Called with every event failing to dispatch
+pub type DeadLetterSink = Arc<dyn Fn(DeadLetter) + Send + Sync>;
+
+impl DeadLetter {
+    pub(crate) fn new(
+        rule_id: Option<&str>,
+        event: &Event,
+        facts: &Value,
+        error: &Error,
+        timestamp: DateTime<Utc>,
+    ) -> Self {
+        let params = render_params(&event.params, facts);
+        Self {
+            rule_id: rule_id.map(ToOwned::to_owned),
+            event_type: event.ty.clone(),
+            target: TARGET_PARAMS
+                .iter()
+                .find_map(|param| params.get(*param))
+                .cloned(),
+            params,
+            error: error.to_string(),
+            attempts: 1,
+            timestamp,
+        }
+    }
+}
+
+impl Engine {
+    /// Hands the events failing to dispatch to the sink, in both error
+    /// modes, rather than keeping them for `Engine::take_dead_letters`
+    pub fn set_dead_letter_sink(&mut self, sink: DeadLetterSink) {
+        self.dead_letter_sink = Some(sink);
+    }
+
+    /// The events that failed to dispatch since the last call, oldest
+    /// first, at most `MAX_DEAD_LETTERS`. Always empty with a sink
+    pub fn take_dead_letters(&mut self) -> Vec<DeadLetter> {
+        self.dead_letters.drain(..).collect()
+    }
+
+    /// Hands the event that failed to the sink, or keeps it
+    pub(crate) fn dead_letter(
+        &mut self,
+        rule_id: Option<&str>,
+        event: &Event,
+        facts: &Value,
+        error: &Error,
+    ) {
+        let dead_letter =
+            DeadLetter::new(rule_id, event, facts, error, (self.now)());
+
+        match &self.dead_letter_sink {
+            Some(sink) => sink(dead_letter),
+            None => {
+                if self.dead_letters.len() == MAX_DEAD_LETTERS {
+                    self.dead_letters.pop_front();
+                }
+                self.dead_letters.push_back(dead_letter);
+            }
+        }
+    }
+}
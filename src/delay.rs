@@ -0,0 +1,253 @@
+//! Events dispatched some time after the run that met their rule, for
+//! conditions that must hold for a while ("CPU high for 5 minutes").
+//!
+//! Delayed events wait on the engine, which owns their event handlers, until
+//! they're due and `Engine::dispatch_delayed` or `Engine::wait_delayed` is
+//! called. No task sends them on its own: an event that's due stays pending
+//! until the caller polls either, e.g. from a loop around `wait_delayed`.
+//! Due times follow the tokio clock, so they can be driven by
+//! `tokio::time::pause` in tests.
+
+use crate::{
+    error::{Error, Result},
+    event::{template_context, with_template_context, CoalescenceEvent, Event},
+    rule::Rule,
+    status::Status,
+    tenant::tenant_key,
+    Engine, RuleKey,
+};
+use serde::Serialize;
+use serde_json::{value::to_value, Map, Value};
+use std::time::Duration;
+use tokio::time::{sleep_until, Instant};
+
+/// An event waiting for its delay to elapse
+#[derive(Debug, Clone)]
+pub struct DelayedEvent {
+    pub id: u64,
+    pub rule_id: Option<String>,
+    /// The tenant of the rule, see `Engine::add_rule_for`
+    pub tenant: Option<String>,
+    /// When the event is due, on the tokio clock
+    pub due: Instant,
+    pub(crate) event: CoalescenceEvent,
+    /// The rule to recheck before sending, if asked to
+    pub(crate) recheck: Option<Rule>,
+    /// The facts of the run that scheduled the event
+    pub(crate) facts: Value,
+    /// What its templates see besides the facts, e.g. the regex captures of
+    /// its rule
+    pub(crate) template_context: Map<String, Value>,
+}
+
+impl DelayedEvent {
+    pub fn event(&self) -> &Event {
+        &self.event.event
+    }
+
+    /// Whether the engine refused or failed to send the event, and why
+    pub fn error(&self) -> Option<&str> {
+        self.event.error.as_deref()
+    }
+
+    pub fn rate_limited(&self) -> bool {
+        self.event.rate_limited
+    }
+
+    /// Why an event interceptor dropped the event, if it did
+    pub fn dropped(&self) -> Option<&str> {
+        self.event.dropped.as_deref()
+    }
+
+    /// Whether the event was skipped for going over one of the `Limits` on
+    /// events
+    pub fn too_large(&self) -> bool {
+        self.event.too_large
+    }
+}
+
+impl Engine {
+    /// Queues an event for `delay_secs`, returning its id
+    pub(crate) fn schedule(
+        &mut self,
+        tenant: Option<&str>,
+        key: Option<RuleKey>,
+        rule_id: Option<&str>,
+        event: &CoalescenceEvent,
+        facts: &Value,
+        delay_secs: u64,
+    ) -> u64 {
+        let id = self.next_delayed_id;
+        self.next_delayed_id += 1;
+
+        // group events have no rule of their own to recheck
+        let recheck = key.filter(|_| event.recheck_before_send).map(|key| {
+            let rule = self.rule(key);
+            Rule {
+                id: rule.id.clone(),
+                conditions: rule.conditions.clone(),
+                events: Vec::new(),
+                facts_to_add: Map::new(),
+                enabled: true,
+                tags: Vec::new(),
+                sample: None,
+            }
+        });
+
+        let mut event = event.clone();
+        event.delay_secs = None;
+
+        self.delayed.push(DelayedEvent {
+            id,
+            rule_id: rule_id.map(ToOwned::to_owned),
+            tenant: tenant.map(ToOwned::to_owned),
+            due: Instant::now() + Duration::from_secs(delay_secs),
+            event,
+            recheck,
+            facts: facts.clone(),
+            template_context: template_context(),
+        });
+
+        id
+    }
+
+    /// The delayed events that haven't been dispatched yet
+    pub fn pending_delayed(&self) -> &[DelayedEvent] {
+        &self.delayed
+    }
+
+    /// Drops a pending delayed event, telling whether it was still pending
+    pub fn cancel_delayed(&mut self, id: u64) -> bool {
+        let len = self.delayed.len();
+        self.delayed.retain(|delayed| delayed.id != id);
+        self.delayed.len() != len
+    }
+
+    /// Sets the facts delayed events with `recheck_before_send` are checked
+    /// against, and rendered with, once due. Until it's called, they are
+    /// checked against the facts of the run that scheduled them
+    pub fn update_latest_facts<T: Serialize>(
+        &mut self,
+        facts: &T,
+    ) -> Result<()> {
+        self.latest_facts = Some(self.facts_root(to_value(facts)?)?);
+        Ok(())
+    }
+
+    /// When the next pending delayed event is due
+    pub fn next_delayed_due(&self) -> Option<Instant> {
+        self.delayed.iter().map(|delayed| delayed.due).min()
+    }
+
+    /// Sends the delayed events that are due, in the order they were
+    /// scheduled, dropping the rechecked ones whose rule isn't met anymore
+    /// and the ones whose coalescence group an event sent meanwhile claimed.
+    /// Returns the events that went through the dispatch, with their
+    /// outcome
+    pub async fn dispatch_delayed(&mut self) -> Result<Vec<DelayedEvent>> {
+        let now = Instant::now();
+        let (due, pending) = std::mem::take(&mut self.delayed)
+            .into_iter()
+            .partition::<Vec<_>, _>(|delayed| delayed.due <= now);
+        self.delayed = pending;
+
+        let mut dispatched = Vec::new();
+        let mut due = due.into_iter();
+        while let Some(mut delayed) = due.next() {
+            // the group is claimed by the first of its events sent, delayed
+            // or not, those scheduled meanwhile being dropped
+            let group = match (
+                &delayed.event.coalescence_group,
+                delayed.event.coalescence,
+            ) {
+                (Some(coalescence_group), Some(coalescence)) => Some((
+                    tenant_key(delayed.tenant.as_deref(), coalescence_group)
+                        .into_owned(),
+                    coalescence,
+                )),
+                _ => None,
+            };
+            if let Some((group, _)) = &group {
+                if self.coalescences.lock().unwrap().get(group).is_some_and(
+                    |(start, expiration)| {
+                        start.elapsed().as_secs() < *expiration
+                    },
+                ) {
+                    continue;
+                }
+            }
+
+            let facts = match (&delayed.recheck, &self.latest_facts) {
+                (Some(_), Some(latest)) => latest.clone(),
+                _ => delayed.facts.clone(),
+            };
+
+            if let Some(rule) = &delayed.recheck {
+                #[cfg(feature = "async_predicate")]
+                {
+                    self.predicate_results =
+                        self.await_predicates(&[rule], &facts).await;
+                }
+                let status = self
+                    .evaluate_rule(rule, None, &facts)
+                    .condition_result
+                    .status;
+                #[cfg(feature = "async_predicate")]
+                self.predicate_results.clear();
+                if status != Status::Met {
+                    continue;
+                }
+            }
+
+            let delivered = with_template_context(
+                delayed.template_context.clone(),
+                self.deliver_event(
+                    delayed.tenant.as_deref(),
+                    delayed.rule_id.as_deref(),
+                    &mut delayed.event,
+                    &facts,
+                    #[cfg(feature = "detached")]
+                    None,
+                ),
+            )
+            .await;
+            match delivered {
+                Ok(true) => {
+                    if let Some((group, coalescence)) = group {
+                        self.coalescences.lock().unwrap().insert(
+                            group,
+                            (crate::clock::Instant::now(), coalescence),
+                        );
+                    }
+                }
+                Ok(false) => {}
+                Err((event_type, source)) => {
+                    // the events after the failed one are left pending
+                    self.delayed.extend(due);
+                    return Err(Error::EventDispatch {
+                        rule_id: delayed.rule_id,
+                        event_type,
+                        source: Box::new(source),
+                        results: Vec::new(),
+                    });
+                }
+            }
+
+            dispatched.push(delayed);
+        }
+
+        Ok(dispatched)
+    }
+
+    /// Waits for the next pending delayed event to be due, then dispatches
+    /// it along with any other due one. Returns right away if there's none
+    pub async fn wait_delayed(&mut self) -> Result<Vec<DelayedEvent>> {
+        match self.next_delayed_due() {
+            Some(due) => {
+                sleep_until(due).await;
+                self.dispatch_delayed().await
+            }
+            None => Ok(Vec::new()),
+        }
+    }
+}
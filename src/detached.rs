@@ -0,0 +1,198 @@
+//! Runs whose events are triggered by a task of their own, see
+//! `Engine::run_detached`.
+//!
+//! The engine handles the events of the met rules as `run` does, checking,
+//! muting, rate limiting or coalescing them, but rather than triggering the
+//! remaining ones it hands them, along with their handlers, to a task. The
+//! task goes on however long the triggers take, whether or not the caller
+//! still awaits it.
+
+use crate::{
+    clock::Instant,
+    dead_letter::{DeadLetter, DeadLetterSink},
+    event::{with_template_context, Event, EventTrait},
+    Engine, NowProvider, Result, RuleResult,
+};
+
+use serde::Serialize;
+use serde_json::{value::to_value, Map, Value};
+use tokio::{runtime::Handle, task::JoinHandle};
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex, RwLock},
+};
+
+/// An event the engine checked, waiting for the detached task to trigger it
+pub(crate) struct DetachedTrigger {
+    pub(crate) rule_id: Option<String>,
+    pub(crate) handler: Arc<RwLock<dyn EventTrait>>,
+    /// Taken before the lock of the handler, see `Engine::handler_gates`
+    pub(crate) gate: Arc<tokio::sync::Mutex<()>>,
+    pub(crate) event: Event,
+    /// The facts the event is dispatched with
+    pub(crate) facts: Value,
+    /// What its templates see besides the facts
+    pub(crate) template_context: Map<String, Value>,
+    /// Identifies the sequence of its rule's dispatch, for the events with
+    /// a `dispatch_order`
+    pub(crate) sequence: Option<usize>,
+    /// The coalescence group the event claims once sent, with its
+    /// coalescence
+    pub(crate) group: Option<(String, u64)>,
+}
+
+/// What became of an event the detached task triggered
+#[derive(Debug, Clone, PartialEq)]
+pub struct EventOutcome {
+    /// The id of the rule, or of the group, the event belongs to
+    pub rule_id: Option<String>,
+    pub event: Event,
+    /// Why the trigger failed, or why the event was skipped
+    pub error: Option<String>,
+}
+
+impl Engine {
+    /// Same as `run`, but the events of the met rules are triggered by a
+    /// task of their own, which goes on even if the caller stops awaiting
+    /// it, and returns what became of them.
+    ///
+    /// The events are checked, muted, delayed, rate limited and coalesced
+    /// before `run_detached` returns, their coalescence groups being
+    /// claimed by the task once they're sent, so a trigger failing doesn't
+    /// suppress the next events of its group. Only the ones the engine
+    /// refuses follow the error mode: triggers failing are reported by the
+    /// task, and handed to the dead letter sink if there's one, never kept
+    /// for `Engine::take_dead_letters`. In a sequence, the events after a
+    /// failed one are skipped if `Engine::set_abort_sequence_on_error` says
+    /// so.
+    ///
+    /// The handlers of the events not being `Send` while they trigger, the
+    /// task runs on a blocking thread and needs a multi-threaded tokio
+    /// runtime. An event of a type the task is triggering waits for it,
+    /// without blocking the thread the engine runs on
+    pub async fn run_detached<T: Serialize>(
+        &mut self,
+        facts: &T,
+    ) -> Result<(Vec<RuleResult>, JoinHandle<Vec<EventOutcome>>)> {
+        let facts = self.facts_root(to_value(facts)?)?;
+        let run = self.evaluate_run(None, None, &facts).await?;
+        let facts = run.patched_facts.clone().unwrap_or(facts);
+
+        let mut triggers = Vec::new();
+        let dispatched = self
+            .dispatch_run(None, run, &facts, Some(&mut triggers))
+            .await;
+        // the events handed over before a `FailFast` error are sent all the
+        // same, as `run` would have
+        let task = self.spawn_triggers(triggers);
+
+        dispatched.map(|(rule_results, _)| (rule_results, task))
+    }
+
+    fn spawn_triggers(
+        &self,
+        triggers: Vec<DetachedTrigger>,
+    ) -> JoinHandle<Vec<EventOutcome>> {
+        let abort_sequence_on_error = self.abort_sequence_on_error;
+        let coalescences = self.coalescences.clone();
+        let dead_letter_sink = self.dead_letter_sink.clone();
+        let now = self.now.clone();
+        let runtime = Handle::current();
+
+        tokio::task::spawn_blocking(move || {
+            runtime.block_on(trigger_all(
+                triggers,
+                abort_sequence_on_error,
+                coalescences,
+                dead_letter_sink,
+                now,
+            ))
+        })
+    }
+}
+
+/// Triggers the events in turn
+// the task holds the locks of its handlers while it triggers them, behind
+// the gates of their types, which the engine awaits rather than blocking on
+// the locks
+#[allow(clippy::await_holding_lock)]
+async fn trigger_all(
+    triggers: Vec<DetachedTrigger>,
+    abort_sequence_on_error: bool,
+    coalescences: Arc<Mutex<HashMap<String, (Instant, u64)>>>,
+    dead_letter_sink: Option<DeadLetterSink>,
+    now: NowProvider,
+) -> Vec<EventOutcome> {
+    // the type of the first event failing in each sequence
+    let mut failed_in_sequence: HashMap<usize, String> = HashMap::new();
+    let mut outcomes = Vec::with_capacity(triggers.len());
+    for DetachedTrigger {
+        rule_id,
+        handler,
+        gate,
+        event,
+        facts,
+        template_context,
+        sequence,
+        group,
+    } in triggers
+    {
+        let failed = sequence
+            .and_then(|sequence| failed_in_sequence.get(&sequence))
+            .filter(|_| abort_sequence_on_error);
+        let error = match failed {
+            Some(failed) => {
+                Some(format!("Skipped after `{}` failed before it", failed))
+            }
+            None => {
+                let triggered =
+                    with_template_context(template_context, async {
+                        let _open = gate.lock().await;
+                        let triggered = handler
+                            .write()
+                            .unwrap()
+                            .trigger(&event.params, &facts)
+                            .await;
+                        if let (Err(e), Some(sink)) =
+                            (&triggered, &dead_letter_sink)
+                        {
+                            sink(DeadLetter::new(
+                                rule_id.as_deref(),
+                                &event,
+                                &facts,
+                                e,
+                                now(),
+                            ));
+                        }
+                        triggered
+                    })
+                    .await;
+                if let (Ok(()), Some((group, coalescence))) =
+                    (&triggered, group)
+                {
+                    coalescences
+                        .lock()
+                        .unwrap()
+                        .insert(group, (Instant::now(), coalescence));
+                }
+                triggered.err().map(|e| {
+                    if let Some(sequence) = sequence {
+                        failed_in_sequence
+                            .entry(sequence)
+                            .or_insert_with(|| event.ty.clone());
+                    }
+                    e.to_string()
+                })
+            }
+        };
+
+        outcomes.push(EventOutcome {
+            rule_id,
+            event,
+            error,
+        });
+    }
+
+    outcomes
+}
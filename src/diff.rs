@@ -0,0 +1,284 @@
+//! Structured diff between two rule sets, see `rules_diff`.
+//!
+//! Rules are matched by `id`, or by a fingerprint of their conditions and
+//! events when they have none, so reordering rules changes nothing. A rule
+//! whose `id` changed but not its content is reported as renamed.
+
+use crate::rule::Rule;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+use std::collections::HashMap;
+
+/// The keys of a condition node holding its children
+const CHILDREN_KEYS: &[&str] = &["and", "or", "not", "conditions", "of"];
+
+/// What changed between two rule sets, each list following the order of the
+/// rules in the set they come from
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct RuleSetDiff {
+    pub added: Vec<KeyedRule>,
+    pub removed: Vec<KeyedRule>,
+    pub renamed: Vec<RenamedRule>,
+    pub modified: Vec<RuleDiff>,
+    /// Keys of the rules left as they were
+    pub unchanged: Vec<String>,
+}
+
+impl RuleSetDiff {
+    /// Whether both rule sets hold the same rules, in whatever order
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.renamed.is_empty()
+            && self.modified.is_empty()
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct KeyedRule {
+    /// The `id` of the rule, or `#` followed by its fingerprint
+    pub key: String,
+    pub rule: Value,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RenamedRule {
+    pub old_id: String,
+    pub new_id: String,
+}
+
+/// How a rule present in both sets changed
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleDiff {
+    pub key: String,
+    pub conditions: Vec<NodeChange>,
+    pub events: Vec<NodeChange>,
+}
+
+/// A changed condition node or event. `old` is `None` for an added one and
+/// `new` for a removed one
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct NodeChange {
+    /// JSON pointer to the node, from the rule's `conditions` or `events`
+    pub path: String,
+    pub old: Option<Value>,
+    pub new: Option<Value>,
+}
+
+/// 64 bits FNV-1a, stable across platforms and releases
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(0xcbf2_9ce4_8422_2325, |hash, b| {
+        (hash ^ u64::from(*b)).wrapping_mul(0x0100_0000_01b3)
+    })
+}
+
+/// Copy of the value with the keys of every object sorted, objects keeping
+/// the order keys were inserted in when serde_json preserves it
+pub(crate) fn sorted(value: &Value) -> Value {
+    match value {
+        Value::Array(xs) => Value::Array(xs.iter().map(sorted).collect()),
+        Value::Object(m) => {
+            let mut entries: Vec<_> = m.iter().collect();
+            entries.sort_by_key(|(k, _)| *k);
+            Value::Object(
+                entries
+                    .into_iter()
+                    .map(|(k, v)| (k.clone(), sorted(v)))
+                    .collect(),
+            )
+        }
+        _ => value.clone(),
+    }
+}
+
+/// Fingerprint of a rule's conditions and events, its `id` aside
+fn fingerprint(rule: &Value) -> u64 {
+    let content = sorted(&json!([rule["conditions"], rule["events"]]));
+    fnv1a(content.to_string().as_bytes())
+}
+
+struct Entry<'r> {
+    key: String,
+    rule: &'r Rule,
+    value: Value,
+    fingerprint: u64,
+}
+
+fn entries(rules: &[Rule]) -> Vec<Entry<'_>> {
+    rules
+        .iter()
+        .map(|rule| {
+            let value = serde_json::to_value(rule).unwrap_or_default();
+            let fingerprint = fingerprint(&value);
+            let key = rule
+                .id
+                .clone()
+                .unwrap_or_else(|| format!("#{:016x}", fingerprint));
+            Entry {
+                key,
+                rule,
+                value,
+                fingerprint,
+            }
+        })
+        .collect()
+}
+
+fn keyed(entry: &Entry) -> KeyedRule {
+    KeyedRule {
+        key: entry.key.clone(),
+        rule: entry.value.clone(),
+    }
+}
+
+fn change(path: &str, old: Option<&Value>, new: Option<&Value>) -> NodeChange {
+    NodeChange {
+        path: path.to_owned(),
+        old: old.cloned(),
+        new: new.cloned(),
+    }
+}
+
+/// The fields of a combinator besides its children, `None` for a leaf
+fn combinator_attributes(node: &Value) -> Option<Map<String, Value>> {
+    let mut node = node.as_object()?.clone();
+    let kind = CHILDREN_KEYS.iter().find(|key| node.contains_key(**key))?;
+    node.remove(*kind);
+    Some(node)
+}
+
+fn diff_lists(
+    old: &[Value],
+    new: &[Value],
+    path: &str,
+    out: &mut Vec<NodeChange>,
+) {
+    for i in 0..old.len().max(new.len()) {
+        let path = format!("{}/{}", path, i);
+        match (old.get(i), new.get(i)) {
+            (Some(old), Some(new)) => diff_conditions(old, new, &path, out),
+            (old, new) => out.push(change(&path, old, new)),
+        }
+    }
+}
+
+/// Recurses into combinators of the same kind, and reports any other node
+/// that differs as a whole
+fn diff_conditions(
+    old: &Value,
+    new: &Value,
+    path: &str,
+    out: &mut Vec<NodeChange>,
+) {
+    if old == new {
+        return;
+    }
+
+    let kind = CHILDREN_KEYS
+        .iter()
+        .find(|key| old.get(**key).is_some() && new.get(**key).is_some());
+    let (kind, old_attributes, new_attributes) =
+        match (kind, combinator_attributes(old), combinator_attributes(new)) {
+            (Some(kind), Some(old), Some(new)) => (kind, old, new),
+            _ => {
+                out.push(change(path, Some(old), Some(new)));
+                return;
+            }
+        };
+
+    if old_attributes != new_attributes {
+        out.push(NodeChange {
+            path: path.to_owned(),
+            old: Some(Value::Object(old_attributes)),
+            new: Some(Value::Object(new_attributes)),
+        });
+    }
+
+    let path = format!("{}/{}", path, kind);
+    match (&old[*kind], &new[*kind]) {
+        (Value::Array(old), Value::Array(new)) => {
+            diff_lists(old, new, &path, out)
+        }
+        (old, new) => diff_conditions(old, new, &path, out),
+    }
+}
+
+fn diff_rule(key: &str, old: &Value, new: &Value) -> RuleDiff {
+    let mut conditions = Vec::new();
+    diff_conditions(
+        &old["conditions"],
+        &new["conditions"],
+        "",
+        &mut conditions,
+    );
+
+    let mut events = Vec::new();
+    let no_events = Vec::new();
+    let old_events = old["events"].as_array().unwrap_or(&no_events);
+    let new_events = new["events"].as_array().unwrap_or(&no_events);
+    for i in 0..old_events.len().max(new_events.len()) {
+        let (old, new) = (old_events.get(i), new_events.get(i));
+        if old != new {
+            events.push(change(&format!("/{}", i), old, new));
+        }
+    }
+
+    RuleDiff {
+        key: key.to_owned(),
+        conditions,
+        events,
+    }
+}
+
+/// Compares two rule sets, e.g. the rules deployed and the ones about to
+/// replace them
+pub fn rules_diff(old: &[Rule], new: &[Rule]) -> RuleSetDiff {
+    let old = entries(old);
+    let new = entries(new);
+    let old_by_key: HashMap<&str, &Entry> =
+        old.iter().rev().map(|e| (e.key.as_str(), e)).collect();
+    let new_by_key: HashMap<&str, &Entry> =
+        new.iter().rev().map(|e| (e.key.as_str(), e)).collect();
+
+    let mut diff = RuleSetDiff::default();
+    let mut added = Vec::new();
+    for entry in &new {
+        match old_by_key.get(entry.key.as_str()) {
+            Some(old) if old.value == entry.value => {
+                diff.unchanged.push(entry.key.clone())
+            }
+            Some(old) => diff.modified.push(diff_rule(
+                &entry.key,
+                &old.value,
+                &entry.value,
+            )),
+            None => added.push(entry),
+        }
+    }
+
+    for entry in &old {
+        if new_by_key.contains_key(entry.key.as_str()) {
+            continue;
+        }
+
+        // the same content under another id
+        let renamed = added.iter().position(|new| {
+            new.fingerprint == entry.fingerprint
+                && entry.rule.id.is_some()
+                && new.rule.id.is_some()
+        });
+        match renamed {
+            Some(i) => {
+                let new = added.remove(i);
+                diff.renamed.push(RenamedRule {
+                    old_id: entry.key.clone(),
+                    new_id: new.key.clone(),
+                });
+            }
+            None => diff.removed.push(keyed(entry)),
+        }
+    }
+    diff.added = added.into_iter().map(keyed).collect();
+
+    diff
+}
@@ -0,0 +1,62 @@
+//! Digests of evaluations, for snapshot tests to tell when the behaviour of
+//! the rules changed, see `Engine::evaluation_digest`.
+//!
+//! Results are hashed in a canonical form: the keys of every object sorted,
+//! event params rendered, and when and how fast rules were evaluated left
+//! out, so the same rules and facts always give the same digest.
+
+use crate::{
+    diff::sorted, error::Result, event::render_params, rule::RuleResult, Engine,
+};
+use serde::Serialize;
+use serde_json::{json, value::to_value, Value};
+use sha2::{Digest, Sha256};
+
+/// Hex encoded SHA-256 of the bytes
+fn sha256_hex(bytes: &[u8]) -> String {
+    Sha256::digest(bytes)
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect()
+}
+
+/// The result without its timings, keys sorted
+fn canonical(result: &RuleResult) -> Value {
+    let mut value = to_value(result).unwrap_or_default();
+    if let Some(result) = value.as_object_mut() {
+        result.remove("evaluated_at");
+        result.remove("duration_micros");
+    }
+    sorted(&value)
+}
+
+impl RuleResult {
+    /// Hex encoded SHA-256 of the result in canonical form, the same for
+    /// the same statuses and events whenever they were evaluated
+    pub fn canonical_hash(&self) -> String {
+        sha256_hex(canonical(self).to_string().as_bytes())
+    }
+}
+
+impl Engine {
+    /// Hex encoded SHA-256 of the evaluation of every rule against the
+    /// facts, met or not, group members included, with the params of their
+    /// events rendered. Nothing is dispatched, and async predicates are
+    /// `Unknown`, as in `evaluate`
+    pub fn evaluation_digest<T: Serialize>(&self, facts: &T) -> Result<String> {
+        let facts = self.facts_root(to_value(facts)?)?;
+        let results: Vec<Value> = self
+            .keyed_rules()
+            .map(|(rule_id, rule)| {
+                let mut result = self.evaluate_rule(rule, None, &facts);
+                for event in &mut result.events {
+                    event.event.params =
+                        render_params(&event.event.params, &facts);
+                }
+                json!([rule_id, canonical(&result)])
+            })
+            .collect();
+
+        Ok(sha256_hex(Value::Array(results).to_string().as_bytes()))
+    }
+}
@@ -0,0 +1,254 @@
+//! Facts encoded as MessagePack or CBOR rather than JSON, see
+//! `Engine::run_msgpack` and `Engine::run_cbor`.
+//!
+//! The facts are decoded straight into a JSON value, by a visitor mapping
+//! what JSON can't hold, rather than going through JSON text.
+
+use crate::{
+    error::{Error, Result},
+    Engine, RuleResult,
+};
+
+use base64::{engine::general_purpose::STANDARD, Engine as _};
+use serde::{
+    de::{self, MapAccess, SeqAccess, Visitor},
+    Deserialize, Deserializer,
+};
+use serde_json::{Map, Number, Value};
+
+use std::{convert::TryFrom, fmt};
+
+/// Facts deserialized from any self-describing format
+struct Facts(Value);
+
+impl<'de> Deserialize<'de> for Facts {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(FactsVisitor).map(Facts)
+    }
+}
+
+struct FactsVisitor;
+
+impl<'de> Visitor<'de> for FactsVisitor {
+    type Value = Value;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("facts")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<Value, E> {
+        Ok(Value::Bool(v))
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<Value, E> {
+        Ok(v.into())
+    }
+
+    fn visit_i128<E: de::Error>(
+        self,
+        v: i128,
+    ) -> std::result::Result<Value, E> {
+        match (i64::try_from(v), u64::try_from(v)) {
+            (Ok(v), _) => Ok(v.into()),
+            (_, Ok(v)) => Ok(v.into()),
+            _ => {
+                Err(E::custom(format!("Integer {} doesn't fit in 64 bits", v)))
+            }
+        }
+    }
+
+    fn visit_u128<E: de::Error>(
+        self,
+        v: u128,
+    ) -> std::result::Result<Value, E> {
+        u64::try_from(v).map(Value::from).map_err(|_| {
+            E::custom(format!("Integer {} doesn't fit in 64 bits", v))
+        })
+    }
+
+    fn visit_f64<E>(self, v: f64) -> std::result::Result<Value, E> {
+        Ok(Number::from_f64(v).map_or(Value::Null, Value::Number))
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<Value, E> {
+        Ok(Value::String(v.to_owned()))
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<Value, E> {
+        Ok(Value::String(v))
+    }
+
+    fn visit_bytes<E>(self, v: &[u8]) -> std::result::Result<Value, E> {
+        Ok(Value::String(STANDARD.encode(v)))
+    }
+
+    fn visit_none<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_unit<E>(self) -> std::result::Result<Value, E> {
+        Ok(Value::Null)
+    }
+
+    fn visit_some<D: Deserializer<'de>>(
+        self,
+        deserializer: D,
+    ) -> std::result::Result<Value, D::Error> {
+        Facts::deserialize(deserializer).map(|facts| facts.0)
+    }
+
+    fn visit_seq<A: SeqAccess<'de>>(
+        self,
+        mut seq: A,
+    ) -> std::result::Result<Value, A::Error> {
+        let mut xs = Vec::new();
+        while let Some(Facts(x)) = seq.next_element()? {
+            xs.push(x);
+        }
+        Ok(Value::Array(xs))
+    }
+
+    fn visit_map<A: MapAccess<'de>>(
+        self,
+        mut map: A,
+    ) -> std::result::Result<Value, A::Error> {
+        let mut members = Map::new();
+        while let Some((MapKey(key), Facts(v))) = map.next_entry()? {
+            members.insert(key, v);
+        }
+        Ok(Value::Object(members))
+    }
+}
+
+/// A map key, as the key of a JSON object
+struct MapKey(String);
+
+impl<'de> Deserialize<'de> for MapKey {
+    fn deserialize<D: Deserializer<'de>>(
+        deserializer: D,
+    ) -> std::result::Result<Self, D::Error> {
+        deserializer.deserialize_any(MapKeyVisitor).map(MapKey)
+    }
+}
+
+struct MapKeyVisitor;
+
+impl<'de> Visitor<'de> for MapKeyVisitor {
+    type Value = String;
+
+    fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("a string, integer or boolean map key")
+    }
+
+    fn visit_bool<E>(self, v: bool) -> std::result::Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_i64<E>(self, v: i64) -> std::result::Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_u64<E>(self, v: u64) -> std::result::Result<String, E> {
+        Ok(v.to_string())
+    }
+
+    fn visit_str<E>(self, v: &str) -> std::result::Result<String, E> {
+        Ok(v.to_owned())
+    }
+
+    fn visit_string<E>(self, v: String) -> std::result::Result<String, E> {
+        Ok(v)
+    }
+}
+
+/// Decodes MessagePack facts
+#[cfg(feature = "msgpack")]
+pub(crate) fn from_msgpack(bytes: &[u8]) -> Result<Value> {
+    let mut deserializer =
+        rmp_serde::Deserializer::new(std::io::Cursor::new(bytes));
+    let decoded = Facts::deserialize(&mut deserializer);
+    let offset = deserializer.position() as usize;
+    match decoded {
+        Ok(_) if offset < bytes.len() => Err(Error::MsgpackDecodeError {
+            offset: Some(offset),
+            message: "Trailing bytes after the facts".to_string(),
+        }),
+        Ok(Facts(facts)) => Ok(facts),
+        Err(e) => Err(Error::MsgpackDecodeError {
+            offset: Some(offset),
+            message: e.to_string(),
+        }),
+    }
+}
+
+/// Decodes CBOR facts
+#[cfg(feature = "cbor")]
+pub(crate) fn from_cbor(bytes: &[u8]) -> Result<Value> {
+    use ciborium::de::Error as CborError;
+
+    let mut rest = bytes;
+    let decoded = ciborium::de::from_reader::<Facts, _>(&mut rest);
+    let offset = bytes.len() - rest.len();
+    let (offset, message) = match decoded {
+        Ok(_) if !rest.is_empty() => {
+            (Some(offset), "Trailing bytes after the facts".to_string())
+        }
+        Ok(Facts(facts)) => return Ok(facts),
+        Err(CborError::Io(e)) => (Some(offset), e.to_string()),
+        Err(CborError::Syntax(offset)) => {
+            (Some(offset), "Invalid CBOR".to_string())
+        }
+        Err(CborError::Semantic(offset, message)) => (offset, message),
+        Err(CborError::RecursionLimitExceeded) => {
+            (None, "The facts are nested too deeply".to_string())
+        }
+    };
+    Err(Error::CborDecodeError { offset, message })
+}
+
+impl Engine {
+    /// Same as `run`, the facts being MessagePack. They're decoded straight
+    /// into JSON, what JSON can't hold being mapped as follows:
+    ///
+    /// - integers keep their width, signed or unsigned up to 64 bits, wider
+    ///   ones, e.g. CBOR bignums, failing to decode
+    /// - floats are widened to 64 bits, NaN and the infinities becoming
+    ///   `null` as `serde_json::to_value` makes them
+    /// - binary strings become their standard base64 encoding, padded
+    /// - integer and boolean map keys become their text, e.g. `"1"` or
+    ///   `"true"`, other keys failing to decode
+    /// - CBOR tags are left out, their value being kept, and MessagePack
+    ///   extension types fail to decode
+    ///
+    /// Facts that fail to decode, or are followed by more bytes, are an
+    /// `Error::MsgpackDecodeError`, with the offset of the byte it stopped
+    /// at
+    #[cfg(feature = "msgpack")]
+    pub async fn run_msgpack(
+        &mut self,
+        bytes: &[u8],
+    ) -> Result<Vec<RuleResult>> {
+        let facts = from_msgpack(bytes)?;
+        self.run_facts(None, None, facts, None::<&Value>)
+            .await
+            .map(|(rule_results, _)| rule_results)
+    }
+
+    /// Same as `run`, the facts being CBOR, mapped to JSON as
+    /// `Engine::run_msgpack` maps MessagePack. Facts that fail to decode,
+    /// or are followed by more bytes, are an `Error::CborDecodeError`, with
+    /// the offset of the byte it stopped at when known
+    #[cfg(feature = "cbor")]
+    pub async fn run_cbor(&mut self, bytes: &[u8]) -> Result<Vec<RuleResult>> {
+        let facts = from_cbor(bytes)?;
+        self.run_facts(None, None, facts, None::<&Value>)
+            .await
+            .map(|(rule_results, _)| rule_results)
+    }
+}
@@ -1,28 +1,109 @@
+use crate::rule::RuleResult;
 use serde_json::error::Error as SerializeJsonError;
 use thiserror::Error as ThisError;
 
 #[cfg(feature = "email")]
 use sendgrid::error::SendgridError;
 
+#[cfg(feature = "binary")]
+use rmp_serde::{
+    decode::Error as BinaryDecodeError, encode::Error as BinaryEncodeError,
+};
+
 #[cfg(feature = "callback")]
-use reqwest::{header::InvalidHeaderValue, Error as ReqwestError};
+use reqwest::header::InvalidHeaderValue;
+#[cfg(any(feature = "callback", feature = "discord", feature = "teams"))]
+use reqwest::Error as ReqwestError;
 
 #[derive(ThisError, Debug)]
 pub enum Error {
-    #[cfg(feature = "callback")]
+    #[cfg(any(feature = "callback", feature = "discord", feature = "teams"))]
     #[error("Reqwest Error: `{0:?}`")]
     ReqwestError(#[from] ReqwestError),
     #[cfg(feature = "callback")]
     #[error("Reqwest Invalid Header Error: `{0:?}`")]
     ReqwestInvalidHeaderError(#[from] InvalidHeaderValue),
+    #[error("Io Error: `{0:?}`")]
+    IoError(#[from] std::io::Error),
     #[error("Serialize Json Error: `{0:?}`")]
     SerializeJsonError(#[from] SerializeJsonError),
     #[cfg(feature = "email")]
     #[error("Send grid error: `{0:?}`")]
     SendgridError(#[from] SendgridError),
+    /// An AWS request failed, the SDK error, e.g. a
+    /// `SdkError<PublishError>`, being the source
+    #[cfg(feature = "aws")]
+    #[error("Aws error: `{0:?}`")]
+    AwsError(#[source] Box<dyn std::error::Error + Send + Sync>),
+    /// A NATS publish failed, or its subject or headers were invalid once
+    /// rendered
+    #[cfg(feature = "nats")]
+    #[error("Nats error: `{0}`")]
+    NatsError(String),
+    #[cfg(feature = "binary")]
+    #[error("Binary Encode Error: `{0:?}`")]
+    BinaryEncodeError(#[from] BinaryEncodeError),
+    #[cfg(feature = "binary")]
+    #[error("Binary Decode Error: `{0:?}`")]
+    BinaryDecodeError(#[from] BinaryDecodeError),
+    #[cfg(feature = "binary")]
+    #[error("Unsupported binary format version: `{0}`")]
+    BinaryVersionError(u8),
+    /// The facts of `Engine::run_msgpack` failed to decode
+    #[cfg(feature = "msgpack")]
+    #[error("MessagePack decode error{}: `{message}`", at_offset(.offset))]
+    MsgpackDecodeError {
+        /// The offset of the byte decoding stopped at
+        offset: Option<usize>,
+        message: String,
+    },
+    /// The facts of `Engine::run_cbor` failed to decode
+    #[cfg(feature = "cbor")]
+    #[error("CBOR decode error{}: `{message}`", at_offset(.offset))]
+    CborDecodeError {
+        /// The offset of the byte decoding stopped at, when known
+        offset: Option<usize>,
+        message: String,
+    },
     // TODO make this error nicer!
     #[error("Event error: `{0}`")]
     EventError(String),
+    #[error("Validation error: `{0}`")]
+    ValidationError(String),
+    /// An event failed to dispatch in `ErrorMode::FailFast`
+    #[error("Event dispatch error for `{event_type}`: {source}")]
+    EventDispatch {
+        /// The id of the rule, or of the group, the event belongs to
+        rule_id: Option<String>,
+        event_type: String,
+        source: Box<Error>,
+        /// The met rules, with the events dispatched up to the failure
+        results: Vec<RuleResult>,
+    },
+    #[error("Compact format error: `{0}`")]
+    CompactFormatError(String),
+    #[error("Limit error: `{0}`")]
+    LimitError(String),
+    #[error("Migration error: `{0}`")]
+    MigrationError(String),
+    #[error("Unsupported engine state version: `{0}`")]
+    StateVersionError(u32),
+    #[error("Sql error: `{0}`")]
+    SqlError(String),
+    /// The sources of `Engine::run_multi` conflict
+    #[error("Source error: `{0}`")]
+    SourceError(String),
+    #[error("Unknown fields: `{0:?}`")]
+    UnknownFieldsError(Vec<String>),
+    /// The facts aren't an object, with `Engine::set_strict_facts_root`,
+    /// their JSON type
+    #[error("Invalid facts root: `{0}`")]
+    InvalidFactsRoot(String),
 }
 
 pub type Result<T> = std::result::Result<T, Error>;
+
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+fn at_offset(offset: &Option<usize>) -> String {
+    offset.map_or_else(String::new, |offset| format!(" at byte {}", offset))
+}
@@ -0,0 +1,291 @@
+use crate::{
+    event::{render_value, CoalescenceEvent, EscapeMode, EventTrait},
+    Error,
+};
+
+use async_trait::async_trait;
+use erased_serde::Serialize as ErasedSerialize;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use std::collections::HashMap;
+
+pub(crate) const EVENT_TYPE: &str = "apply_json_patch";
+
+/// An operation of the `patch` param of an `apply_json_patch` event, its
+/// paths being JSON pointers
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+pub enum PatchOp {
+    /// Sets a member, or inserts an element, `-` appending to an array
+    Add {
+        path: String,
+        value: Value,
+    },
+    Remove {
+        path: String,
+    },
+    /// Sets a member or an element that exists
+    Replace {
+        path: String,
+        value: Value,
+    },
+    Move {
+        from: String,
+        path: String,
+    },
+    Copy {
+        from: String,
+        path: String,
+    },
+    /// Fails the patch unless the value at the path equals this one
+    Test {
+        path: String,
+        value: Value,
+    },
+}
+
+impl PatchOp {
+    fn paths(&self) -> impl Iterator<Item = &String> {
+        let (from, path) = match self {
+            PatchOp::Add { path, .. }
+            | PatchOp::Remove { path }
+            | PatchOp::Replace { path, .. }
+            | PatchOp::Test { path, .. } => (None, path),
+            PatchOp::Move { from, path } | PatchOp::Copy { from, path } => {
+                (Some(from), path)
+            }
+        };
+        from.into_iter().chain(Some(path))
+    }
+
+    /// The operation with its templates rendered against the facts
+    fn render(&self, facts: &Value) -> Self {
+        let render = |value| render_value(value, facts, EscapeMode::None);
+        match self {
+            PatchOp::Add { path, value } => PatchOp::Add {
+                path: path.clone(),
+                value: render(value),
+            },
+            PatchOp::Replace { path, value } => PatchOp::Replace {
+                path: path.clone(),
+                value: render(value),
+            },
+            PatchOp::Test { path, value } => PatchOp::Test {
+                path: path.clone(),
+                value: render(value),
+            },
+            op => op.clone(),
+        }
+    }
+}
+
+/// What became of the patch of one of the `apply_json_patch` events of a
+/// met rule
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PatchOutcome {
+    /// Whether the facts were patched
+    pub applied: bool,
+    /// Why they weren't, e.g. a `test` operation failing
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+/// The `patch` param, checking its paths are JSON pointers and that no
+/// value is moved into itself
+pub(crate) fn patch(
+    params: &HashMap<String, Value>,
+) -> Result<Vec<PatchOp>, String> {
+    let patch = params.get("patch").ok_or("'patch' is missing.")?;
+    let ops = Vec::<PatchOp>::deserialize(patch)
+        .map_err(|e| format!("Invalid 'patch': {}", e))?;
+
+    for op in &ops {
+        if let Some(path) = op
+            .paths()
+            .find(|path| !path.is_empty() && !path.starts_with('/'))
+        {
+            return Err(format!("Invalid 'patch' path `{}`", path));
+        }
+        if let PatchOp::Move { from, path } = op {
+            if path.starts_with(&format!("{}/", from)) {
+                return Err(format!(
+                    "Invalid 'patch': `{}` can't be moved into `{}`",
+                    from, path
+                ));
+            }
+        }
+    }
+
+    Ok(ops)
+}
+
+/// The facts patched by the `patch` param, its templates rendered against
+/// `template_facts`, or why they couldn't be
+pub(crate) fn apply(
+    params: &HashMap<String, Value>,
+    facts: &Value,
+    template_facts: &Value,
+) -> Result<Value, String> {
+    let mut patched = facts.clone();
+    for op in patch(params)? {
+        apply_op(&mut patched, op.render(template_facts))?;
+    }
+    Ok(patched)
+}
+
+/// Whether an `apply_json_patch` event is among the events
+pub(crate) fn has_patch(events: &[CoalescenceEvent]) -> bool {
+    events.iter().any(|event| event.event.ty == EVENT_TYPE)
+}
+
+/// The pointer to the parent of the path and the unescaped last token
+fn split(path: &str) -> Result<(&str, String), String> {
+    let (parent, last) = path
+        .rsplit_once('/')
+        .ok_or_else(|| format!("`{}` has no parent", path))?;
+    Ok((parent, last.replace("~1", "/").replace("~0", "~")))
+}
+
+/// The index an array token points to, without leading zeros
+fn index(token: &str, path: &str) -> Result<usize, String> {
+    match token.parse() {
+        Ok(i) if token == "0" || !token.starts_with('0') => Ok(i),
+        _ => Err(format!("`{}` isn't an array index", path)),
+    }
+}
+
+fn parent_mut<'a>(
+    facts: &'a mut Value,
+    parent: &str,
+    path: &str,
+) -> Result<&'a mut Value, String> {
+    facts
+        .pointer_mut(parent)
+        .ok_or_else(|| format!("The parent of `{}` doesn't exist", path))
+}
+
+fn add(facts: &mut Value, path: &str, value: Value) -> Result<(), String> {
+    if path.is_empty() {
+        *facts = value;
+        return Ok(());
+    }
+
+    let (parent, token) = split(path)?;
+    match parent_mut(facts, parent, path)? {
+        Value::Object(obj) => {
+            obj.insert(token, value);
+        }
+        Value::Array(xs) if token == "-" => xs.push(value),
+        Value::Array(xs) => {
+            let i = index(&token, path)?;
+            if i > xs.len() {
+                return Err(format!("`{}` is out of bounds", path));
+            }
+            xs.insert(i, value);
+        }
+        _ => return Err(format!("The parent of `{}` isn't a container", path)),
+    }
+    Ok(())
+}
+
+fn remove(facts: &mut Value, path: &str) -> Result<Value, String> {
+    let missing = || format!("`{}` doesn't exist", path);
+    let (parent, token) = split(path)?;
+    match parent_mut(facts, parent, path)? {
+        Value::Object(obj) => obj.remove(&token).ok_or_else(missing),
+        Value::Array(xs) => {
+            let i = index(&token, path)?;
+            if i < xs.len() {
+                Ok(xs.remove(i))
+            } else {
+                Err(missing())
+            }
+        }
+        _ => Err(missing()),
+    }
+}
+
+fn apply_op(facts: &mut Value, op: PatchOp) -> Result<(), String> {
+    let missing = |path: &str| format!("`{}` doesn't exist", path);
+    match op {
+        PatchOp::Add { path, value } => add(facts, &path, value),
+        PatchOp::Remove { path } => remove(facts, &path).map(|_| ()),
+        PatchOp::Replace { path, value } => {
+            let target =
+                facts.pointer_mut(&path).ok_or_else(|| missing(&path))?;
+            *target = value;
+            Ok(())
+        }
+        PatchOp::Move { from, path } => {
+            let value = match from.as_str() {
+                "" => std::mem::take(facts),
+                _ => remove(facts, &from)?,
+            };
+            add(facts, &path, value)
+        }
+        PatchOp::Copy { from, path } => {
+            let value = facts
+                .pointer(&from)
+                .cloned()
+                .ok_or_else(|| missing(&from))?;
+            add(facts, &path, value)
+        }
+        PatchOp::Test { path, value } => match facts.pointer(&path) {
+            Some(actual) if *actual == value => Ok(()),
+            Some(actual) => Err(format!(
+                "Test failed, `{}` is {} rather than {}",
+                path, actual, value
+            )),
+            None => Err(missing(&path)),
+        },
+    }
+}
+
+/// Patches the facts with its `patch` param, a JSON Patch, RFC 6902, whose
+/// string values are templates rendered against the facts and the engine's
+/// variables, under `vars`, e.g.
+///
+/// ```json
+/// [
+///     { "op": "add", "path": "/risk_level", "value": "{{ level }}" },
+///     { "op": "copy", "from": "/user/id", "path": "/subject" }
+/// ]
+/// ```
+///
+/// The engine applies the patches of the met rules as it evaluates them, in
+/// rule order, so the rules after them see the patched facts, which
+/// `run_with_info` returns as `RunInfo::patched_facts` and the events are
+/// dispatched with. A patch is applied whole or not at all: an operation
+/// failing, e.g. a `test` one, leaves the facts as they were, the reason
+/// being recorded in `RuleResult::patches`. The event itself does nothing
+/// once dispatched
+#[derive(Debug)]
+pub struct ApplyJsonPatch {
+    ty: String,
+}
+
+#[async_trait]
+impl EventTrait for ApplyJsonPatch {
+    fn new() -> Self {
+        Self {
+            ty: EVENT_TYPE.to_string(),
+        }
+    }
+
+    fn get_type(&self) -> &str {
+        &self.ty
+    }
+
+    fn validate(&self, params: &HashMap<String, Value>) -> Result<(), String> {
+        patch(params).map(|_| ())
+    }
+
+    async fn trigger(
+        &mut self,
+        _params: &HashMap<String, Value>,
+        _facts: &(dyn ErasedSerialize + Sync),
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
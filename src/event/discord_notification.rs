@@ -0,0 +1,88 @@
+use crate::{
+    event::{render_params, EventTrait},
+    Error,
+};
+
+use async_trait::async_trait;
+use erased_serde::Serialize;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+
+pub(crate) const EVENT_TYPE: &str = "discord_notification";
+
+/// Posts the rendered `title` and `message` as an embed to a Discord
+/// webhook, colored with the optional `embed_color` (an RGB integer)
+#[derive(Debug, Clone)]
+pub struct DiscordNotification {
+    ty: String,
+    client: Client,
+}
+
+impl DiscordNotification {
+    /// The JSON body posted to the webhook, from rendered params
+    fn payload(params: &HashMap<String, Value>) -> Value {
+        let mut embed = json!({
+            "title": params["title"],
+            "description": params["message"],
+        });
+        if let Some(color) = params.get("embed_color") {
+            embed["color"] = color.clone();
+        }
+
+        json!({ "embeds": [embed] })
+    }
+}
+
+#[async_trait]
+impl EventTrait for DiscordNotification {
+    fn new() -> Self {
+        Self {
+            ty: EVENT_TYPE.to_string(),
+            client: Client::new(),
+        }
+    }
+
+    fn get_type(&self) -> &str {
+        &self.ty
+    }
+
+    fn validate(&self, params: &HashMap<String, Value>) -> Result<(), String> {
+        if !(params.contains_key("webhook_url")
+            && params.contains_key("title")
+            && params.contains_key("message"))
+        {
+            return Err(
+                "At least one of 'webhook_url', 'title', 'message' is missing."
+                    .to_string(),
+            );
+        }
+
+        if !params.get("embed_color").is_none_or(Value::is_u64) {
+            return Err("'embed_color' must be a positive integer.".to_string());
+        }
+
+        Ok(())
+    }
+
+    async fn trigger(
+        &mut self,
+        params: &HashMap<String, Value>,
+        facts: &(dyn Serialize + Sync),
+    ) -> Result<(), Error> {
+        let params = render_params(params, &serde_json::to_value(facts)?);
+        let webhook_url = params["webhook_url"].as_str().ok_or_else(|| {
+            Error::EventError("'webhook_url' must be a string.".to_string())
+        })?;
+
+        self.client
+            .post(webhook_url)
+            .json(&Self::payload(&params))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
@@ -1,4 +1,7 @@
-use crate::{event::EventTrait, Error};
+use crate::{
+    event::{render_template, template_facts, EscapeMode, EventTrait},
+    Error,
+};
 
 use async_trait::async_trait;
 use erased_serde::Serialize;
@@ -10,6 +13,28 @@ use serde_json::Value;
 
 use std::collections::HashMap;
 
+/// Renders the title and the message of an email against the facts. The
+/// message of a `text/html` email, see its `content_type` param, has every
+/// interpolated value HTML escaped whatever its `escape_mode`
+pub(crate) fn render_email(
+    params: &HashMap<String, Value>,
+    facts: &Value,
+) -> (String, String) {
+    let mode = EscapeMode::from_params(params).unwrap_or_default();
+    let html = is_html(params);
+    let facts = template_facts(params, facts);
+    let render = |key: &str, force_html: bool| {
+        let template = params[key].to_string();
+        render_template(&template, &facts, mode, force_html).unwrap_or(template)
+    };
+
+    (render("title", false), render("message", html))
+}
+
+fn is_html(params: &HashMap<String, Value>) -> bool {
+    params.get("content_type").and_then(Value::as_str) == Some("text/html")
+}
+
 #[derive(Debug, Clone)]
 pub struct EmailNotification {
     ty: String,
@@ -54,28 +79,16 @@ impl EventTrait for EmailNotification {
 
         let tos = params.get("to").unwrap().as_array().unwrap();
         let from = params.get("from").unwrap().to_string();
-        let mut title = params.get("title").unwrap().to_string();
-        let mut message = params.get("message").unwrap().to_string();
 
         let value = serde_json::from_str::<Value>(
             &serde_json::to_string(facts).unwrap(),
         )
         .unwrap();
-        if let Ok(tmpl) = mustache::compile_str(&message)
-            .and_then(|template| template.render_to_string(&value))
-        {
-            message = tmpl;
-        }
-
-        if let Ok(tmpl) = mustache::compile_str(&title)
-            .and_then(|template| template.render_to_string(&value))
-        {
-            title = tmpl;
-        }
+        let (title, message) = render_email(params, &value);
 
         let personalization = {
             let mut p =
-                Personalization::new(SendGridEmail::new(&tos[0].to_string()));
+                Personalization::new(SendGridEmail::new(tos[0].to_string()));
             for to in tos.iter().skip(1) {
                 p = p.add_to(SendGridEmail::new(to.to_string()));
             }
@@ -86,7 +99,11 @@ impl EventTrait for EmailNotification {
             .set_subject(&title)
             .add_content(
                 Content::new()
-                    .set_content_type("text/plain")
+                    .set_content_type(if is_html(params) {
+                        "text/html"
+                    } else {
+                        "text/plain"
+                    })
                     .set_value(message),
             )
             .add_personalization(personalization);
@@ -96,3 +113,51 @@ impl EventTrait for EmailNotification {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::render_email;
+    use serde_json::{json, Value};
+    use std::collections::HashMap;
+
+    fn params(content_type: &str, escape_mode: &str) -> HashMap<String, Value> {
+        serde_json::from_value(json!({
+            "to": ["someone@example.com"],
+            "from": "rules@example.com",
+            "title": "{{ team }}",
+            "message": "{{ team }} / {{{ team }}} / {{& team }}",
+            "content_type": content_type,
+            "escape_mode": escape_mode
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn escape_modes() {
+        let facts = json!({ "team": "R&D <core>" });
+
+        let (title, message) =
+            render_email(&params("text/plain", "html"), &facts);
+        assert_eq!(title, "\"R&amp;D &lt;core&gt;\"");
+        assert_eq!(
+            message,
+            "\"R&amp;D &lt;core&gt; / R&D <core> / R&D <core>\""
+        );
+
+        let (title, message) =
+            render_email(&params("text/plain", "none"), &facts);
+        assert_eq!(title, "\"R&D <core>\"");
+        assert_eq!(message, "\"R&D <core> / R&D <core> / R&D <core>\"");
+
+        // html content is escaped whatever the mode
+        for escape_mode in &["html", "none"] {
+            let (title, message) =
+                render_email(&params("text/html", escape_mode), &facts);
+            assert_eq!(title.contains('<'), *escape_mode == "none");
+            assert_eq!(
+                message,
+                "\"R&amp;D &lt;core&gt; / R&amp;D &lt;core&gt; / R&amp;D &lt;core&gt;\""
+            );
+        }
+    }
+}
@@ -1,34 +1,489 @@
-use crate::error::Error;
+use crate::{constraint::round, error::Error};
 
 use async_trait::async_trait;
 use erased_serde::Serialize as ErasedSerialize;
-use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::{Map, Value};
 
-use std::collections::HashMap;
+use std::{
+    borrow::Cow,
+    cell::RefCell,
+    collections::{BTreeMap, HashMap, HashSet},
+    future::{poll_fn, Future},
+    pin::pin,
+};
 
+pub mod apply_json_patch;
+#[cfg(feature = "discord")]
+pub mod discord_notification;
 #[cfg(feature = "email")]
 pub mod email_notification;
+#[cfg(feature = "nats")]
+pub mod nats_publish;
 #[cfg(feature = "callback")]
 pub mod post_callback;
+#[cfg(feature = "aws")]
+pub mod sns_publish;
+#[cfg(feature = "aws")]
+pub mod sqs_send;
+#[cfg(feature = "teams")]
+pub mod teams_notification;
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct CoalescenceEvent {
     pub(crate) coalescence: Option<u64>,
     pub(crate) coalescence_group: Option<String>,
+    /// Set on results when the event was suppressed by the rate limit of its
+    /// type
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) rate_limited: bool,
+    /// Set on results when the engine refused to dispatch the event, or it
+    /// failed to in `ErrorMode::BestEffort`, with the reason why
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) error: Option<String>,
+    /// Set on results when an event interceptor dropped the event, with the
+    /// reason it gave
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) dropped: Option<String>,
+    /// Set on results when the event was skipped for going over the rendered
+    /// message, callback payload or per rule fan-out bounds of `Limits`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) too_large: bool,
+    /// Set on results when the event was skipped for a mute, see
+    /// `Engine::mute`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) muted: bool,
+    /// Why the event was muted, the mute's scope and end
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) mute_reason: Option<String>,
+    /// Dispatch the event ahead of the other events of its rule, after the
+    /// ones of a lower order, see `Engine::set_abort_sequence_on_error`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) dispatch_order: Option<u32>,
+    /// Dispatch the event this many seconds after the run. It's queued on
+    /// the engine, no task sending it once due: it's only sent when the
+    /// caller next polls `Engine::dispatch_delayed` or
+    /// `Engine::wait_delayed`
+    #[cfg(feature = "delay")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) delay_secs: Option<u64>,
+    /// Drop a delayed event if, once due, its rule isn't met by the latest
+    /// facts anymore, see `Engine::update_latest_facts`
+    #[cfg(feature = "delay")]
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub(crate) recheck_before_send: bool,
+    /// Set on results when the event was delayed, to its id among the
+    /// pending delayed events
+    #[cfg(feature = "delay")]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub(crate) delayed_id: Option<u64>,
     #[serde(flatten)]
     pub(crate) event: Event,
 }
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Event {
     #[serde(rename = "type")]
     pub ty: String,
+    #[serde(serialize_with = "serialize_sorted")]
     pub params: HashMap<String, Value>,
 }
 
+/// The params telling receivers why a rule fired, rendered against the facts
+/// before the event is dispatched
+const REASON_PARAMS: [&str; 2] = ["reason_code", "severity"];
+
+impl Event {
+    /// The `reason_code` param, a stable code for receivers to branch on
+    /// rather than parsing a message
+    pub fn reason_code(&self) -> Option<&str> {
+        self.params.get("reason_code").and_then(Value::as_str)
+    }
+
+    /// The `severity` param, see `EngineOptions::allowed_severities`
+    pub fn severity(&self) -> Option<&str> {
+        self.params.get("severity").and_then(Value::as_str)
+    }
+
+    /// Checks the `reason_code` and `severity` params are strings, and the
+    /// severity one of the allowed ones, if any, unless it's a template
+    pub(crate) fn check_reason(
+        &self,
+        allowed_severities: Option<&HashSet<String>>,
+    ) -> Result<(), String> {
+        for param in REASON_PARAMS {
+            if self.params.get(param).is_some_and(|v| !v.is_string()) {
+                return Err(format!("'{}' must be a string.", param));
+            }
+        }
+
+        match (self.severity(), allowed_severities) {
+            (Some(severity), Some(allowed))
+                if !severity.contains("{{") && !allowed.contains(severity) =>
+            {
+                Err(format!("Severity `{}` isn't allowed.", severity))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Renders the `reason_code` and `severity` params against the facts, so
+    /// every event type receives them rendered
+    pub(crate) fn render_reason(&mut self, facts: &Value) {
+        let mode = EscapeMode::from_params(&self.params).unwrap_or_default();
+        let facts = template_facts(&self.params, facts);
+        for param in REASON_PARAMS {
+            if let Some(Value::String(s)) = self.params.get_mut(param) {
+                if let Ok(rendered) = render_template(s, &facts, mode, false) {
+                    *s = rendered;
+                }
+            }
+        }
+    }
+}
+
+/// Serializes the params with their keys sorted, so an event always
+/// serializes the same
+fn serialize_sorted<S: Serializer>(
+    params: &HashMap<String, Value>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    params
+        .iter()
+        .collect::<BTreeMap<_, _>>()
+        .serialize(serializer)
+}
+
+/// An event dispatched by the engine, as seen by `Engine::subscribe` subscribers
+#[cfg(feature = "broadcast")]
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EventEnvelope {
+    pub rule_id: Option<String>,
+    /// The event, with its string params rendered against the facts
+    pub event: Event,
+    /// When the event was dispatched, in milliseconds since the unix epoch
+    pub timestamp: u64,
+}
+
+/// How the values interpolated in an event's templates are escaped, from its
+/// `escape_mode` param
+#[derive(
+    Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum EscapeMode {
+    /// `{{ value }}` is HTML escaped, `{{{ value }}}` and `{{& value }}`
+    /// aren't
+    #[default]
+    Html,
+    /// Nothing is escaped, for plain text channels
+    None,
+}
+
+impl EscapeMode {
+    /// The `escape_mode` param, `Html` when missing
+    pub(crate) fn from_params(
+        params: &HashMap<String, Value>,
+    ) -> Result<Self, String> {
+        params
+            .get("escape_mode")
+            .map_or(Ok(Self::default()), |mode| {
+                Self::deserialize(mode).map_err(|_| {
+                    "'escape_mode' must be \"html\" or \"none\".".to_string()
+                })
+            })
+    }
+}
+
+/// How the floats interpolated in an event's templates are formatted, from
+/// its `number_format` param, e.g. `{"precision": 2}` renders `0.1 + 0.2`
+/// as `0.30` rather than `0.30000000000000004`. Integers are left as they
+/// are
+#[derive(
+    Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize,
+)]
+#[serde(deny_unknown_fields)]
+pub struct NumberFormat {
+    /// Decimal places, halves being rounded away from zero
+    pub precision: u32,
+}
+
+impl NumberFormat {
+    /// The `number_format` param, if any
+    pub(crate) fn from_params(
+        params: &HashMap<String, Value>,
+    ) -> Result<Option<Self>, String> {
+        params
+            .get("number_format")
+            .map(|format| {
+                Self::deserialize(format).map_err(|_| {
+                    "'number_format' must be like {\"precision\": 2}."
+                        .to_string()
+                })
+            })
+            .transpose()
+    }
+
+    /// Copy of the facts with every float formatted as a string
+    fn format(&self, facts: &Value) -> Value {
+        match facts {
+            Value::Number(n) if n.is_f64() => {
+                let v = n.as_f64().unwrap_or_default();
+                Value::String(format!(
+                    "{:.*}",
+                    self.precision as usize,
+                    round(v, self.precision)
+                ))
+            }
+            Value::Array(xs) => {
+                Value::Array(xs.iter().map(|x| self.format(x)).collect())
+            }
+            Value::Object(m) => Value::Object(
+                m.iter().map(|(k, x)| (k.clone(), self.format(x))).collect(),
+            ),
+            _ => facts.clone(),
+        }
+    }
+}
+
+/// The facts with the event's `app_data` param under `app_data`, as in
+/// `{{ app_data.support_url }}`. A fact named `app_data` wins, the name
+/// being reserved for it
+pub(crate) fn with_app_data<'a>(
+    params: &HashMap<String, Value>,
+    facts: &'a Value,
+) -> Cow<'a, Value> {
+    match (params.get("app_data"), facts) {
+        (Some(app_data), Value::Object(facts))
+            if !facts.contains_key("app_data") =>
+        {
+            let mut facts = facts.clone();
+            facts.insert("app_data".to_string(), app_data.clone());
+            Cow::Owned(Value::Object(facts))
+        }
+        _ => Cow::Borrowed(facts),
+    }
+}
+
+thread_local! {
+    /// What the templates rendered while polling `with_template_context`
+    /// see besides the facts
+    static TEMPLATE_CONTEXT: RefCell<Map<String, Value>> =
+        RefCell::new(Map::new());
+}
+
+/// The template context of the future being polled on this thread, set
+/// back to the one outside it once polled, even if polling panicked
+struct ContextScope {
+    outer: Option<Map<String, Value>>,
+}
+
+impl ContextScope {
+    fn enter(context: Map<String, Value>) -> Self {
+        Self {
+            outer: Some(TEMPLATE_CONTEXT.with(|c| c.replace(context))),
+        }
+    }
+
+    fn exit(mut self) -> Map<String, Value> {
+        let outer = self.outer.take().unwrap_or_default();
+        TEMPLATE_CONTEXT.with(|c| c.replace(outer))
+    }
+}
+
+impl Drop for ContextScope {
+    fn drop(&mut self) {
+        if let Some(outer) = self.outer.take() {
+            TEMPLATE_CONTEXT.with(|c| c.replace(outer));
+        }
+    }
+}
+
+/// Runs the future with the context exposed to the templates of the events
+/// it renders, e.g. a rule's regex captures under `_captures`. Unlike the
+/// facts, the context is never handed to the events, so it's never sent
+/// anywhere
+pub(crate) async fn with_template_context<F: Future>(
+    mut context: Map<String, Value>,
+    fut: F,
+) -> F::Output {
+    if context.is_empty() {
+        return fut.await;
+    }
+
+    let mut fut = pin!(fut);
+    poll_fn(move |cx| {
+        let scope = ContextScope::enter(std::mem::take(&mut context));
+        let polled = fut.as_mut().poll(cx);
+        context = scope.exit();
+        polled
+    })
+    .await
+}
+
+/// The template context of the events being dispatched, see
+/// `with_template_context`, for the ones triggered later
+pub(crate) fn template_context() -> Map<String, Value> {
+    TEMPLATE_CONTEXT.with(|c| c.borrow().clone())
+}
+
+/// The facts with the template context, facts of the same name winning
+fn with_context(facts: Cow<'_, Value>) -> Cow<'_, Value> {
+    TEMPLATE_CONTEXT.with(|context| {
+        let context = context.borrow();
+        let missing = match &*facts {
+            Value::Object(facts) => {
+                context.keys().any(|key| !facts.contains_key(key))
+            }
+            _ => false,
+        };
+        if !missing {
+            return facts;
+        }
+
+        let mut facts = facts.into_owned();
+        if let Some(facts) = facts.as_object_mut() {
+            for (key, v) in context.iter() {
+                facts.entry(key.clone()).or_insert_with(|| v.clone());
+            }
+        }
+        Cow::Owned(facts)
+    })
+}
+
+/// The facts as the templates of an event see them, with its `app_data` and
+/// the template context, their floats formatted by its `number_format`, if
+/// any
+pub(crate) fn template_facts<'a>(
+    params: &HashMap<String, Value>,
+    facts: &'a Value,
+) -> Cow<'a, Value> {
+    let facts = with_context(with_app_data(params, facts));
+    match NumberFormat::from_params(params) {
+        Ok(Some(format)) => Cow::Owned(format.format(&facts)),
+        _ => facts,
+    }
+}
+
+/// Rewrites every variable tag of a mustache template as an unescaped one,
+/// `{{& value }}`, or as an escaped one, `{{ value }}`
+fn rewrite_tags(template: &str, escape: bool) -> String {
+    let mut rewritten = String::with_capacity(template.len() + 8);
+    let mut rest = template;
+    while let Some(start) = rest.find("{{") {
+        rewritten.push_str(&rest[..start]);
+        rest = &rest[start + 2..];
+        rewritten.push_str("{{");
+
+        if let Some(triple) = rest.strip_prefix('{') {
+            // `{{{ value }}}`
+            if let Some(end) = triple.find("}}}") {
+                rewritten.push_str(if escape { "" } else { "&" });
+                rewritten.push_str(&triple[..end]);
+                rewritten.push_str("}}");
+                rest = &triple[end + 3..];
+            }
+            continue;
+        }
+
+        let tag = rest.trim_start();
+        match tag.chars().next() {
+            Some('&') if escape => rest = &tag[1..],
+            Some('#' | '^' | '/' | '!' | '>' | '=' | '&') | None => {}
+            Some(_) if !escape => rewritten.push('&'),
+            Some(_) => {}
+        }
+    }
+    rewritten.push_str(rest);
+    rewritten
+}
+
+/// Renders a template against the facts, escaping the interpolated values
+/// according to the mode, or HTML escaping all of them with `force_html`
+pub(crate) fn render_template(
+    template: &str,
+    facts: &Value,
+    mode: EscapeMode,
+    force_html: bool,
+) -> Result<String, mustache::Error> {
+    let rewritten;
+    let template = if force_html {
+        rewritten = rewrite_tags(template, true);
+        &rewritten
+    } else if mode == EscapeMode::None {
+        rewritten = rewrite_tags(template, false);
+        &rewritten
+    } else {
+        template
+    };
+
+    mustache::compile_str(template)
+        .and_then(|template| template.render_to_string(facts))
+}
+
+/// Renders every string in the value, however deeply nested, against the
+/// facts. Strings that fail to render are kept as is
+pub(crate) fn render_value(
+    v: &Value,
+    facts: &Value,
+    mode: EscapeMode,
+) -> Value {
+    match v {
+        Value::String(s) => Value::String(
+            render_template(s, facts, mode, false)
+                .unwrap_or_else(|_| s.clone()),
+        ),
+        Value::Array(xs) => Value::Array(
+            xs.iter().map(|x| render_value(x, facts, mode)).collect(),
+        ),
+        Value::Object(m) => Value::Object(
+            m.iter()
+                .map(|(k, x)| (k.clone(), render_value(x, facts, mode)))
+                .collect(),
+        ),
+        _ => v.clone(),
+    }
+}
+
+/// Renders every string in the params, however deeply nested, against the
+/// facts, escaping values as told by their `escape_mode` and formatting
+/// floats as told by their `number_format`. Strings that fail to render are
+/// kept as is
+pub(crate) fn render_params(
+    params: &HashMap<String, Value>,
+    facts: &Value,
+) -> HashMap<String, Value> {
+    let mode = EscapeMode::from_params(params).unwrap_or_default();
+    let facts = template_facts(params, facts);
+    params
+        .iter()
+        .map(|(k, v)| (k.clone(), render_value(v, &facts, mode)))
+        .collect()
+}
+
+/// What an `EventInterceptor` wants done with an event
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum InterceptDecision {
+    Proceed,
+    /// Don't dispatch the event, for the given reason
+    Drop(String),
+}
+
+/// Sees, and may change or veto, every event right before the engine
+/// dispatches it, see `Engine::add_event_interceptor`
+#[async_trait]
+pub trait EventInterceptor: Send + Sync {
+    /// `rule_id` is empty for rules without an id
+    async fn before_dispatch(
+        &self,
+        event: &mut Event,
+        facts: &Value,
+        rule_id: &str,
+    ) -> InterceptDecision;
+}
+
 #[async_trait]
-pub trait EventTrait {
+pub trait EventTrait: Send + Sync {
     fn new() -> Self
     where
         Self: Sized;
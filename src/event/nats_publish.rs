@@ -0,0 +1,328 @@
+use crate::{
+    event::{render_params, EventTrait},
+    Error,
+};
+
+use async_trait::async_trait;
+use erased_serde::Serialize;
+use serde_json::{json, Value};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufStream},
+    net::TcpStream,
+};
+
+use std::collections::HashMap;
+
+pub(crate) const EVENT_TYPE: &str = "nats_publish";
+
+const DEFAULT_PORT: u16 = 4222;
+
+/// Publishes the callback payload, `{"event": params, "facts": facts}`, its
+/// params rendered, to the `subject` NATS subject, along with the optional
+/// `reply` subject and `headers`, an object of rendered strings. Subjects
+/// are templates, checked once rendered: dot separated tokens, without
+/// whitespace nor wildcards.
+///
+/// The connection to the server of `EngineOptions::nats_url` is made on the
+/// first publish, and made again on the next one once a publish failed.
+/// Only plain `nats://` urls are supported, with the credentials, if any, as
+/// their `user:password@` or `token@` part
+#[derive(Debug)]
+pub struct NatsPublish {
+    ty: String,
+    url: Option<String>,
+    connection: Option<Connection>,
+}
+
+impl NatsPublish {
+    /// Publishes to the server of the url
+    pub fn with_url(url: &str) -> Self {
+        Self {
+            ty: EVENT_TYPE.to_string(),
+            url: Some(url.to_string()),
+            connection: None,
+        }
+    }
+}
+
+fn nats_error(e: impl ToString) -> Error {
+    Error::NatsError(e.to_string())
+}
+
+/// Whether the subject may be published to: dot separated tokens, none
+/// empty nor a wildcard, without whitespace
+pub(crate) fn is_valid_subject(subject: &str) -> bool {
+    !subject.is_empty()
+        && !subject.contains(char::is_whitespace)
+        && subject
+            .split('.')
+            .all(|token| !token.is_empty() && token != "*" && token != ">")
+}
+
+/// The `PUB`, or with headers `HPUB`, message publishing the payload
+pub(crate) fn publish_message(
+    subject: &str,
+    reply: Option<&str>,
+    headers: &[(String, String)],
+    payload: &[u8],
+) -> Vec<u8> {
+    let reply = reply.map(|reply| format!(" {}", reply)).unwrap_or_default();
+    let mut message = Vec::with_capacity(payload.len() + 64);
+    if headers.is_empty() {
+        message.extend(
+            format!("PUB {}{} {}\r\n", subject, reply, payload.len())
+                .into_bytes(),
+        );
+    } else {
+        let mut block = String::from("NATS/1.0\r\n");
+        for (name, value) in headers {
+            block.push_str(&format!("{}: {}\r\n", name, value));
+        }
+        block.push_str("\r\n");
+        message.extend(
+            format!(
+                "HPUB {}{} {} {}\r\n",
+                subject,
+                reply,
+                block.len(),
+                block.len() + payload.len()
+            )
+            .into_bytes(),
+        );
+        message.extend(block.into_bytes());
+    }
+    message.extend_from_slice(payload);
+    message.extend_from_slice(b"\r\n");
+    message
+}
+
+/// The rendered `headers` param, refusing names and values that would break
+/// the header block
+fn headers(
+    rendered: &HashMap<String, Value>,
+) -> Result<Vec<(String, String)>, Error> {
+    let mut headers = Vec::new();
+    if let Some(obj) = rendered.get("headers").and_then(Value::as_object) {
+        for (name, value) in obj {
+            let value = value.as_str().unwrap_or_default();
+            if name.is_empty()
+                || name.contains(|c: char| c == ':' || c.is_whitespace())
+                || value.contains(['\r', '\n'])
+            {
+                return Err(nats_error(format!("Invalid header `{}`", name)));
+            }
+            headers.push((name.clone(), value.to_string()));
+        }
+    }
+    Ok(headers)
+}
+
+/// A connection to a NATS server, speaking just enough of the protocol to
+/// publish
+#[derive(Debug)]
+struct Connection {
+    stream: BufStream<TcpStream>,
+}
+
+impl Connection {
+    async fn connect(url: &str) -> Result<Self, Error> {
+        let address = url.strip_prefix("nats://").unwrap_or(url);
+        if address.contains("://") {
+            return Err(nats_error(format!("Unsupported url `{}`", url)));
+        }
+        let (credentials, address) = match address.rsplit_once('@') {
+            Some((credentials, address)) => (Some(credentials), address),
+            None => (None, address),
+        };
+        let address = address.trim_end_matches('/');
+        let address = if address.contains(':') {
+            address.to_string()
+        } else {
+            format!("{}:{}", address, DEFAULT_PORT)
+        };
+
+        let stream = TcpStream::connect(&address).await.map_err(nats_error)?;
+        let mut connection = Connection {
+            stream: BufStream::new(stream),
+        };
+        let info = connection.read_line().await?;
+        if !info.starts_with("INFO") {
+            return Err(nats_error(format!("Unexpected greeting `{}`", info)));
+        }
+
+        let mut options = json!({
+            "verbose": false,
+            "pedantic": false,
+            "headers": true,
+            "lang": "rust",
+            "name": env!("CARGO_PKG_NAME"),
+            "version": env!("CARGO_PKG_VERSION"),
+        });
+        match credentials.map(|credentials| credentials.split_once(':')) {
+            Some(Some((user, pass))) => {
+                options["user"] = json!(user);
+                options["pass"] = json!(pass);
+            }
+            Some(None) => options["auth_token"] = json!(credentials),
+            None => {}
+        }
+        connection
+            .send(format!("CONNECT {}\r\n", options).as_bytes())
+            .await?;
+        Ok(connection)
+    }
+
+    async fn read_line(&mut self) -> Result<String, Error> {
+        let mut line = String::new();
+        if self.stream.read_line(&mut line).await.map_err(nats_error)? == 0 {
+            return Err(nats_error("Connection closed by the server"));
+        }
+        Ok(line.trim_end().to_string())
+    }
+
+    /// Writes the message followed by a `PING`, and waits for the `PONG`
+    /// telling the server processed it, or for its error
+    async fn send(&mut self, message: &[u8]) -> Result<(), Error> {
+        self.stream.write_all(message).await.map_err(nats_error)?;
+        self.stream
+            .write_all(b"PING\r\n")
+            .await
+            .map_err(nats_error)?;
+        self.stream.flush().await.map_err(nats_error)?;
+
+        loop {
+            let line = self.read_line().await?;
+            if line == "PONG" {
+                return Ok(());
+            } else if line == "PING" {
+                self.stream
+                    .write_all(b"PONG\r\n")
+                    .await
+                    .map_err(nats_error)?;
+                self.stream.flush().await.map_err(nats_error)?;
+            } else if let Some(e) = line.strip_prefix("-ERR") {
+                return Err(nats_error(e.trim().trim_matches('\'')));
+            }
+        }
+    }
+}
+
+#[async_trait]
+impl EventTrait for NatsPublish {
+    fn new() -> Self {
+        Self {
+            ty: EVENT_TYPE.to_string(),
+            url: None,
+            connection: None,
+        }
+    }
+
+    fn get_type(&self) -> &str {
+        &self.ty
+    }
+
+    fn validate(&self, params: &HashMap<String, Value>) -> Result<(), String> {
+        if !params.get("subject").is_some_and(Value::is_string) {
+            return Err("'subject' is missing.".to_string());
+        }
+        if params.get("reply").is_some_and(|reply| !reply.is_string()) {
+            return Err("'reply' must be a string.".to_string());
+        }
+
+        let headers_ok = params.get("headers").is_none_or(|headers| {
+            headers
+                .as_object()
+                .is_some_and(|obj| obj.values().all(Value::is_string))
+        });
+        if !headers_ok {
+            return Err("'headers' must be an object of strings.".to_string());
+        }
+
+        Ok(())
+    }
+
+    async fn trigger(
+        &mut self,
+        params: &HashMap<String, Value>,
+        facts: &(dyn Serialize + Sync),
+    ) -> Result<(), Error> {
+        let rendered = render_params(params, &serde_json::to_value(facts)?);
+
+        let subject = rendered["subject"].as_str().unwrap_or_default();
+        if !is_valid_subject(subject) {
+            return Err(nats_error(format!("Invalid subject `{}`", subject)));
+        }
+        let reply = rendered.get("reply").and_then(Value::as_str);
+        if let Some(reply) = reply.filter(|reply| !is_valid_subject(reply)) {
+            return Err(nats_error(format!(
+                "Invalid reply subject `{}`",
+                reply
+            )));
+        }
+        let headers = headers(&rendered)?;
+        let payload = serde_json::to_vec(&json!({
+            "event": rendered,
+            "facts": facts,
+        }))?;
+        let message = publish_message(subject, reply, &headers, &payload);
+
+        let mut connection = match self.connection.take() {
+            Some(connection) => connection,
+            None => {
+                let url = self.url.as_deref().ok_or_else(|| {
+                    nats_error("No NATS url, see `EngineOptions::nats_url`")
+                })?;
+                Connection::connect(url).await?
+            }
+        };
+        // a connection that failed is dropped, the next publish reconnects
+        connection.send(&message).await?;
+        self.connection = Some(connection);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{is_valid_subject, publish_message};
+
+    #[test]
+    fn subjects() {
+        for subject in ["orders", "orders.created", "orders.eu-west.v2"] {
+            assert!(is_valid_subject(subject), "{}", subject);
+        }
+        for subject in [
+            "",
+            "orders.",
+            ".orders",
+            "orders..created",
+            "orders.*",
+            "a.>",
+        ] {
+            assert!(!is_valid_subject(subject), "{}", subject);
+        }
+        assert!(!is_valid_subject("orders created"));
+        assert!(!is_valid_subject("orders\r\n.created"));
+    }
+
+    #[test]
+    fn publish_messages() {
+        assert_eq!(
+            publish_message("orders", None, &[], b"{}"),
+            b"PUB orders 2\r\n{}\r\n"
+        );
+        assert_eq!(
+            publish_message("orders", Some("inbox.1"), &[], b"{}"),
+            b"PUB orders inbox.1 2\r\n{}\r\n"
+        );
+
+        let headers = [("Tenant".to_string(), "acme".to_string())];
+        // `NATS/1.0\r\nTenant: acme\r\n\r\n` is 26 bytes
+        assert_eq!(
+            publish_message("orders", None, &headers, b"{}"),
+            b"HPUB orders 26 28\r\nNATS/1.0\r\nTenant: acme\r\n\r\n{}\r\n"
+                .to_vec()
+        );
+    }
+}
@@ -1,25 +1,412 @@
-use crate::{event::EventTrait, Error};
+use crate::{
+    event::{render_template, template_facts, EscapeMode, EventTrait},
+    transport::{
+        EventTransport, OutboundBody, OutboundRequest, ReqwestTransport,
+    },
+    Error,
+};
 
 use async_trait::async_trait;
 use erased_serde::Serialize;
-use reqwest::Client;
+use flate2::write::GzEncoder;
+use reqwest::Url;
+use serde::Deserialize;
 use serde_json::{json, Value};
+use uuid::Uuid;
 
-use std::collections::HashMap;
+use std::{
+    collections::HashMap,
+    fmt,
+    io::Write,
+    net::{IpAddr, Ipv4Addr, SocketAddr},
+    sync::Arc,
+};
 
-#[derive(Debug, Clone)]
+pub(crate) const EVENT_TYPE: &str = "post_to_callback_url";
+
+/// Where rendered `callback_url`s may point to, see
+/// `Engine::set_callback_url_policy`. The default allows any url
+#[derive(Debug, Default, Clone, serde::Serialize, Deserialize)]
+#[serde(default)]
+pub struct CallbackUrlPolicy {
+    /// Refuse anything but `https` urls
+    pub https_only: bool,
+    /// Hosts the url may point to, compared case insensitively
+    pub allowed_hosts: Vec<String>,
+    /// Prefixes the url may start with. With neither hosts nor prefixes,
+    /// any url is allowed
+    pub allowed_prefixes: Vec<String>,
+    /// Refuse hosts that are, or resolve to, loopback, private, link local
+    /// or otherwise non public addresses
+    pub deny_private_ips: bool,
+}
+
+fn is_private_v4(ip: Ipv4Addr) -> bool {
+    ip.is_private()
+        || ip.is_loopback()
+        || ip.is_link_local()
+        || ip.is_unspecified()
+        || ip.is_broadcast()
+        // shared address space, RFC 6598
+        || (ip.octets()[0] == 100 && ip.octets()[1] & 0xc0 == 64)
+}
+
+fn is_private(ip: IpAddr) -> bool {
+    match ip {
+        IpAddr::V4(ip) => is_private_v4(ip),
+        IpAddr::V6(ip) => {
+            ip.is_loopback()
+                || ip.is_unspecified()
+                || ip.is_unique_local()
+                || ip.is_unicast_link_local()
+                || ip.to_ipv4_mapped().is_some_and(is_private_v4)
+        }
+    }
+}
+
+impl CallbackUrlPolicy {
+    /// Whether it allows any url, the default
+    pub(crate) fn allows_any(&self) -> bool {
+        !self.https_only
+            && !self.deny_private_ips
+            && self.allowed_hosts.is_empty()
+            && self.allowed_prefixes.is_empty()
+    }
+
+    /// Checks a rendered url, resolving its host if private addresses are
+    /// denied. The addresses it resolved to, all of them checked, are
+    /// returned for the request to connect to, so that resolving the host
+    /// again can't lead it elsewhere
+    pub(crate) async fn check(
+        &self,
+        url: &str,
+    ) -> Result<Vec<SocketAddr>, String> {
+        if self.allows_any() {
+            return Ok(Vec::new());
+        }
+
+        let parsed = Url::parse(url)
+            .map_err(|e| format!("Invalid callback url `{}`: {}", url, e))?;
+
+        if self.https_only && parsed.scheme() != "https" {
+            return Err(format!("Callback url `{}` isn't https", url));
+        }
+
+        let host = parsed.host_str().unwrap_or_default();
+        let allowed = (self.allowed_hosts.is_empty()
+            && self.allowed_prefixes.is_empty())
+            || self
+                .allowed_hosts
+                .iter()
+                .any(|h| h.eq_ignore_ascii_case(host))
+            || self
+                .allowed_prefixes
+                .iter()
+                .any(|p| url.starts_with(p.as_str()));
+        if !allowed {
+            return Err(format!("Callback url `{}` isn't allowed", url));
+        }
+
+        if self.deny_private_ips {
+            let port = parsed.port_or_known_default().unwrap_or(443);
+            // `host_str` keeps the brackets around IPv6 addresses
+            let host = host.trim_start_matches('[').trim_end_matches(']');
+            let addrs: Vec<_> = tokio::net::lookup_host((host, port))
+                .await
+                .map_err(|e| {
+                    format!("Couldn't resolve callback url `{}`: {}", url, e)
+                })?
+                .collect();
+
+            if let Some(addr) = addrs.iter().find(|addr| is_private(addr.ip()))
+            {
+                return Err(format!(
+                    "Callback url `{}` resolves to the private address {}",
+                    url,
+                    addr.ip()
+                ));
+            }
+            return Ok(addrs);
+        }
+
+        Ok(Vec::new())
+    }
+}
+
+/// How the payload is encoded, from the `content_type` param
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum ContentType {
+    /// `{"event": params, "facts": facts}`
+    #[default]
+    Json,
+    /// `application/x-www-form-urlencoded` pairs, `event.<param>` and
+    /// `facts.<fact>`, values other than strings being JSON encoded
+    Form,
+}
+
+impl ContentType {
+    /// The `content_type` param, `Json` when missing
+    fn from_params(params: &HashMap<String, Value>) -> Result<Self, String> {
+        params
+            .get("content_type")
+            .map_or(Ok(Self::default()), |content_type| {
+                Self::deserialize(content_type).map_err(|_| {
+                    "'content_type' must be \"json\" or \"form\".".to_string()
+                })
+            })
+    }
+}
+
+/// How the body is compressed, from the `compress` param
+#[derive(Debug, Default, Eq, PartialEq, Copy, Clone, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Compression {
+    #[default]
+    None,
+    /// Gzip, sent with `Content-Encoding: gzip`
+    Gzip,
+}
+
+impl Compression {
+    /// The `compress` param, `None` when missing
+    fn from_params(params: &HashMap<String, Value>) -> Result<Self, String> {
+        params
+            .get("compress")
+            .map_or(Ok(Self::default()), |compress| {
+                Self::deserialize(compress).map_err(|_| {
+                    "'compress' must be \"gzip\" or \"none\".".to_string()
+                })
+            })
+    }
+}
+
+/// The `max_facts_bytes` param, the size of the JSON encoded facts beyond
+/// which the payload leaves them out
+fn max_facts_bytes(
+    params: &HashMap<String, Value>,
+) -> Result<Option<usize>, String> {
+    match params.get("max_facts_bytes") {
+        None => Ok(None),
+        Some(max) => {
+            max.as_u64().map(|max| Some(max as usize)).ok_or_else(|| {
+                "'max_facts_bytes' must be a non negative integer.".to_string()
+            })
+        }
+    }
+}
+
+/// How many times a failed request is retried, the `retries` param being
+/// capped to it
+pub(crate) const MAX_CALLBACK_RETRIES: u64 = 10;
+
+/// The `retries` param, how many times a request that fails, or isn't
+/// answered with a success, is sent again right away. 0 when missing
+fn retries(params: &HashMap<String, Value>) -> Result<u64, String> {
+    match params.get("retries") {
+        None => Ok(0),
+        Some(retries) => retries
+            .as_u64()
+            .filter(|&retries| retries <= MAX_CALLBACK_RETRIES)
+            .ok_or_else(|| {
+                format!(
+                    "'retries' must be an integer from 0 to {}.",
+                    MAX_CALLBACK_RETRIES
+                )
+            }),
+    }
+}
+
+/// The `stable_across_retries` param, `false` when missing
+fn stable_across_retries(
+    params: &HashMap<String, Value>,
+) -> Result<bool, String> {
+    match params.get("stable_across_retries") {
+        None => Ok(false),
+        Some(stable) => stable.as_bool().ok_or_else(|| {
+            "'stable_across_retries' must be a boolean.".to_string()
+        }),
+    }
+}
+
+/// Resolves the `idempotency_key` param of an event about to be dispatched,
+/// so that the attempts to send it, and the dead letter it may become, all
+/// carry the same key: its template is rendered against the facts, which
+/// also hold the id of the rule under `_rule_id` and the nonce of the run
+/// under `_run_nonce`. Without a template, a UUID v4 is drawn for the event
+/// if `stable_across_retries` is `true`, the key being drawn again for
+/// every attempt otherwise
+pub(crate) fn resolve_idempotency_key(
+    params: &mut HashMap<String, Value>,
+    facts: &Value,
+    rule_id: Option<&str>,
+    run_nonce: &str,
+) {
+    let key = match params.get("idempotency_key") {
+        Some(Value::String(template)) => {
+            let mut facts = template_facts(params, facts).into_owned();
+            if let Some(obj) = facts.as_object_mut() {
+                obj.insert("_rule_id".to_string(), json!(rule_id));
+                obj.insert("_run_nonce".to_string(), json!(run_nonce));
+            }
+            let mode = EscapeMode::from_params(params).unwrap_or_default();
+            // kept as is if it fails to render, as the callback url is
+            render_template(template, &facts, mode, false)
+                .unwrap_or_else(|_| template.clone())
+        }
+        None if stable_across_retries(params) == Ok(true) => {
+            Uuid::new_v4().to_string()
+        }
+        _ => return,
+    };
+    params.insert("idempotency_key".to_string(), Value::String(key));
+}
+
+/// The facts, or `{"facts_truncated": true, "facts_size": N}` in their
+/// place when their JSON encoding is over `max` bytes
+fn sized_facts(facts: Value, max: Option<usize>) -> Value {
+    let max = match max {
+        Some(max) => max,
+        None => return facts,
+    };
+    let size = serde_json::to_vec(&facts).map_or(0, |bytes| bytes.len());
+    if size <= max {
+        facts
+    } else {
+        json!({ "facts_truncated": true, "facts_size": size })
+    }
+}
+
+/// The body, encoded as it would be sent, gzipped
+fn gzip(body: &OutboundBody) -> Result<Vec<u8>, Error> {
+    let encoded = match body {
+        OutboundBody::Json(body) => serde_json::to_vec(body)?,
+        OutboundBody::Form(pairs) => {
+            url::form_urlencoded::Serializer::new(String::new())
+                .extend_pairs(pairs)
+                .finish()
+                .into_bytes()
+        }
+        OutboundBody::Bytes(bytes) => bytes.clone(),
+    };
+    let mut encoder =
+        GzEncoder::new(Vec::new(), flate2::Compression::default());
+    encoder
+        .write_all(&encoded)
+        .and_then(|_| encoder.finish())
+        .map_err(|e| {
+            Error::EventError(format!("Couldn't gzip the body: {}", e))
+        })
+}
+
+/// The `payload_version` param, wrapping the payload in a versioned
+/// envelope and sent as the `X-Payload-Version` header
+fn payload_version(
+    params: &HashMap<String, Value>,
+) -> Result<Option<&str>, String> {
+    match params.get("payload_version") {
+        None => Ok(None),
+        Some(Value::String(version)) => Ok(Some(version)),
+        Some(_) => Err("'payload_version' must be a string.".to_string()),
+    }
+}
+
+/// The top level facts the `form_facts` param selects, all of them when
+/// missing
+fn form_facts<'a>(
+    params: &HashMap<String, Value>,
+    facts: &'a Value,
+) -> Vec<(&'a String, &'a Value)> {
+    let facts = match facts.as_object() {
+        Some(facts) => facts,
+        None => return Vec::new(),
+    };
+
+    match params.get("form_facts").and_then(Value::as_array) {
+        Some(selected) => facts
+            .iter()
+            .filter(|(key, _)| selected.iter().any(|s| s == key.as_str()))
+            .collect(),
+        None => facts.iter().collect(),
+    }
+}
+
+fn form_value(v: &Value) -> String {
+    match v {
+        Value::String(s) => s.clone(),
+        v => v.to_string(),
+    }
+}
+
+/// The pairs of a form encoded payload, params sorted by name
+fn form_pairs(
+    params: &HashMap<String, Value>,
+    facts: &Value,
+    version: Option<&str>,
+) -> Vec<(String, String)> {
+    let mut sorted: Vec<_> = params.iter().collect();
+    sorted.sort_by_key(|(key, _)| *key);
+
+    version
+        .map(|version| ("version".to_string(), version.to_string()))
+        .into_iter()
+        .chain(
+            sorted
+                .into_iter()
+                .map(|(key, v)| (format!("event.{}", key), form_value(v))),
+        )
+        .chain(
+            form_facts(params, facts)
+                .into_iter()
+                .map(|(key, v)| (format!("facts.{}", key), form_value(v))),
+        )
+        .collect()
+}
+
+/// Renders the `callback_url` param against the facts, keeping it as is if
+/// it fails to render
+pub(crate) fn render_callback_url(
+    params: &HashMap<String, Value>,
+    facts: &Value,
+) -> Option<String> {
+    let callback_url = params.get("callback_url")?.as_str()?;
+    let mode = EscapeMode::from_params(params).unwrap_or_default();
+    let facts = template_facts(params, facts);
+
+    Some(
+        render_template(callback_url, &facts, mode, false)
+            .unwrap_or_else(|_| callback_url.to_string()),
+    )
+}
+
+#[derive(Clone)]
 pub struct PostCallback {
     ty: String,
-    client: Client,
+    transport: Arc<dyn EventTransport>,
+}
+
+impl PostCallback {
+    /// Sends its requests with the transport, see `Engine::with_transport`
+    pub(crate) fn with_transport(transport: Arc<dyn EventTransport>) -> Self {
+        Self {
+            ty: EVENT_TYPE.to_string(),
+            transport,
+        }
+    }
+}
+
+impl fmt::Debug for PostCallback {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PostCallback")
+            .field("ty", &self.ty)
+            .finish_non_exhaustive()
+    }
 }
 
 #[async_trait]
 impl EventTrait for PostCallback {
     fn new() -> Self {
-        Self {
-            ty: "post_to_callback_url".to_string(),
-            client: Client::new(),
-        }
+        Self::with_transport(Arc::new(ReqwestTransport::default()))
     }
 
     fn get_type(&self) -> &str {
@@ -30,6 +417,25 @@ impl EventTrait for PostCallback {
         if !params.contains_key("callback_url") {
             return Err("'callback_url' is missing.".to_string());
         }
+        ContentType::from_params(params)?;
+        Compression::from_params(params)?;
+        payload_version(params)?;
+        max_facts_bytes(params)?;
+        retries(params)?;
+        stable_across_retries(params)?;
+        if params
+            .get("idempotency_key")
+            .is_some_and(|key| !key.is_string())
+        {
+            return Err("'idempotency_key' must be a string.".to_string());
+        }
+        if params.get("form_facts").is_some_and(|selected| {
+            !selected
+                .as_array()
+                .is_some_and(|selected| selected.iter().all(Value::is_string))
+        }) {
+            return Err("'form_facts' must be a list of strings.".to_string());
+        }
 
         Ok(())
     }
@@ -39,32 +445,90 @@ impl EventTrait for PostCallback {
         params: &HashMap<String, Value>,
         facts: &(dyn Serialize + Sync),
     ) -> Result<(), Error> {
-        let mut callback_url = params
-            .get("callback_url")
-            .unwrap()
-            .as_str()
-            .unwrap()
-            .to_string();
-
         let value = serde_json::from_str::<Value>(
             &serde_json::to_string(facts).unwrap(),
         )
         .unwrap();
-        if let Ok(tmpl) = mustache::compile_str(&callback_url)
-            .and_then(|template| template.render_to_string(&value))
-        {
-            callback_url = tmpl;
-        }
+        let callback_url = render_callback_url(params, &value).unwrap();
+        let content_type =
+            ContentType::from_params(params).map_err(Error::EventError)?;
+        let version = payload_version(params).map_err(Error::EventError)?;
+        let compression =
+            Compression::from_params(params).map_err(Error::EventError)?;
+        let max_facts_bytes =
+            max_facts_bytes(params).map_err(Error::EventError)?;
+        let value = sized_facts(value, max_facts_bytes);
 
-        self.client
-            .post(callback_url)
-            .json(&json!({
-                "event": params,
-                "facts": facts,
-            }))
-            .send()
-            .await?;
+        let retries = retries(params).map_err(Error::EventError)?;
 
-        Ok(())
+        // the key of the event if the engine resolved it, or one per attempt
+        let stable_key = params.get("idempotency_key").and_then(Value::as_str);
+        let mut attempt = 0;
+        loop {
+            let idempotency_key = stable_key
+                .map_or_else(|| Uuid::new_v4().to_string(), ToOwned::to_owned);
+            let mut headers = vec![
+                (
+                    "Content-Type".to_string(),
+                    match content_type {
+                        ContentType::Json => "application/json",
+                        ContentType::Form => {
+                            "application/x-www-form-urlencoded"
+                        }
+                    }
+                    .to_string(),
+                ),
+                ("Idempotency-Key".to_string(), idempotency_key.clone()),
+            ];
+            if let Some(version) = version {
+                headers.push((
+                    "X-Payload-Version".to_string(),
+                    version.to_string(),
+                ));
+            }
+            let mut body = match (content_type, version) {
+                (ContentType::Json, None) => OutboundBody::Json(json!({
+                    "event": params,
+                    "facts": value,
+                })),
+                (ContentType::Json, Some(version)) => {
+                    OutboundBody::Json(json!({
+                        "version": version,
+                        "event": params,
+                        "facts": value,
+                        "idempotency_key": idempotency_key,
+                    }))
+                }
+                (ContentType::Form, version) => {
+                    OutboundBody::Form(form_pairs(params, &value, version))
+                }
+            };
+            if compression == Compression::Gzip {
+                headers
+                    .push(("Content-Encoding".to_string(), "gzip".to_string()));
+                body = OutboundBody::Bytes(gzip(&body)?);
+            }
+
+            let sent = self
+                .transport
+                .send(OutboundRequest {
+                    method: "POST".to_string(),
+                    url: callback_url.clone(),
+                    headers,
+                    body,
+                })
+                .await
+                .and_then(|response| match response.is_success() {
+                    true => Ok(()),
+                    false => Err(Error::EventError(format!(
+                        "Callback url `{}` responded with status {}",
+                        callback_url, response.status
+                    ))),
+                });
+            if sent.is_ok() || attempt == retries {
+                return sent;
+            }
+            attempt += 1;
+        }
     }
 }
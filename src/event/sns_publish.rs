@@ -0,0 +1,121 @@
+use crate::{
+    event::{render_params, EventTrait},
+    Error,
+};
+
+use async_trait::async_trait;
+use aws_config::BehaviorVersion;
+use aws_sdk_sns::{types::MessageAttributeValue, Client};
+use erased_serde::Serialize;
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+
+pub(crate) const EVENT_TYPE: &str = "sns_publish";
+
+/// Publishes the callback payload, `{"event": params, "facts": facts}`, to
+/// the `topic_arn` SNS topic, along with the optional `message_attributes`,
+/// an object of rendered string attributes.
+///
+/// Credentials come from the default AWS provider chain, unless a client is
+/// given with `SnsPublish::with_client`
+#[derive(Debug, Clone)]
+pub struct SnsPublish {
+    ty: String,
+    /// Configured from the environment on the first publish, if not given
+    client: Option<Client>,
+}
+
+impl SnsPublish {
+    /// Publishes through the given client rather than one configured from
+    /// the environment
+    pub fn with_client(client: Client) -> Self {
+        Self {
+            ty: EVENT_TYPE.to_string(),
+            client: Some(client),
+        }
+    }
+}
+
+#[async_trait]
+impl EventTrait for SnsPublish {
+    fn new() -> Self {
+        Self {
+            ty: EVENT_TYPE.to_string(),
+            client: None,
+        }
+    }
+
+    fn get_type(&self) -> &str {
+        &self.ty
+    }
+
+    fn validate(&self, params: &HashMap<String, Value>) -> Result<(), String> {
+        if !params.get("topic_arn").is_some_and(Value::is_string) {
+            return Err("'topic_arn' is missing.".to_string());
+        }
+
+        let attributes_ok =
+            params.get("message_attributes").is_none_or(|attributes| {
+                attributes
+                    .as_object()
+                    .is_some_and(|obj| obj.values().all(Value::is_string))
+            });
+        if !attributes_ok {
+            return Err("'message_attributes' must be an object of strings."
+                .to_string());
+        }
+
+        Ok(())
+    }
+
+    async fn trigger(
+        &mut self,
+        params: &HashMap<String, Value>,
+        facts: &(dyn Serialize + Sync),
+    ) -> Result<(), Error> {
+        let rendered = render_params(params, &serde_json::to_value(facts)?);
+
+        let mut attributes = HashMap::new();
+        if let Some(obj) = rendered
+            .get("message_attributes")
+            .and_then(Value::as_object)
+        {
+            for (name, value) in obj {
+                let value = MessageAttributeValue::builder()
+                    .data_type("String")
+                    .string_value(value.as_str().unwrap_or_default())
+                    .build()
+                    .map_err(|e| Error::AwsError(e.into()))?;
+                attributes.insert(name.clone(), value);
+            }
+        }
+
+        if self.client.is_none() {
+            let config =
+                aws_config::load_defaults(BehaviorVersion::latest()).await;
+            self.client = Some(Client::new(&config));
+        }
+
+        self.client
+            .as_ref()
+            .unwrap()
+            .publish()
+            .topic_arn(params["topic_arn"].as_str().unwrap_or_default())
+            .message(
+                json!({
+                    "event": params,
+                    "facts": facts,
+                })
+                .to_string(),
+            )
+            .set_message_attributes(
+                Some(attributes).filter(|attributes| !attributes.is_empty()),
+            )
+            .send()
+            .await
+            .map_err(|e| Error::AwsError(e.into()))?;
+
+        Ok(())
+    }
+}
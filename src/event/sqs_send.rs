@@ -0,0 +1,102 @@
+use crate::{
+    event::{render_params, EventTrait},
+    Error,
+};
+
+use async_trait::async_trait;
+use aws_config::BehaviorVersion;
+use aws_sdk_sqs::Client;
+use erased_serde::Serialize;
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+
+pub(crate) const EVENT_TYPE: &str = "sqs_send";
+
+/// Sends the callback payload, `{"event": params, "facts": facts}`, to the
+/// `queue_url` SQS queue, in the rendered `message_group_id` group for FIFO
+/// queues.
+///
+/// Credentials come from the default AWS provider chain, unless a client is
+/// given with `SqsSend::with_client`
+#[derive(Debug, Clone)]
+pub struct SqsSend {
+    ty: String,
+    /// Configured from the environment on the first send, if not given
+    client: Option<Client>,
+}
+
+impl SqsSend {
+    /// Sends through the given client rather than one configured from the
+    /// environment
+    pub fn with_client(client: Client) -> Self {
+        Self {
+            ty: EVENT_TYPE.to_string(),
+            client: Some(client),
+        }
+    }
+}
+
+#[async_trait]
+impl EventTrait for SqsSend {
+    fn new() -> Self {
+        Self {
+            ty: EVENT_TYPE.to_string(),
+            client: None,
+        }
+    }
+
+    fn get_type(&self) -> &str {
+        &self.ty
+    }
+
+    fn validate(&self, params: &HashMap<String, Value>) -> Result<(), String> {
+        if !params.get("queue_url").is_some_and(Value::is_string) {
+            return Err("'queue_url' is missing.".to_string());
+        }
+
+        if !params.get("message_group_id").is_none_or(Value::is_string) {
+            return Err("'message_group_id' must be a string.".to_string());
+        }
+
+        Ok(())
+    }
+
+    async fn trigger(
+        &mut self,
+        params: &HashMap<String, Value>,
+        facts: &(dyn Serialize + Sync),
+    ) -> Result<(), Error> {
+        let rendered = render_params(params, &serde_json::to_value(facts)?);
+
+        if self.client.is_none() {
+            let config =
+                aws_config::load_defaults(BehaviorVersion::latest()).await;
+            self.client = Some(Client::new(&config));
+        }
+
+        self.client
+            .as_ref()
+            .unwrap()
+            .send_message()
+            .queue_url(params["queue_url"].as_str().unwrap_or_default())
+            .message_body(
+                json!({
+                    "event": params,
+                    "facts": facts,
+                })
+                .to_string(),
+            )
+            .set_message_group_id(
+                rendered
+                    .get("message_group_id")
+                    .and_then(Value::as_str)
+                    .map(ToOwned::to_owned),
+            )
+            .send()
+            .await
+            .map_err(|e| Error::AwsError(e.into()))?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,92 @@
+use crate::{
+    event::{render_params, EventTrait},
+    Error,
+};
+
+use async_trait::async_trait;
+use erased_serde::Serialize;
+use reqwest::Client;
+use serde_json::{json, Value};
+
+use std::collections::HashMap;
+
+pub(crate) const EVENT_TYPE: &str = "teams_notification";
+
+/// Posts the rendered `title` and `message` as a MessageCard to a Microsoft
+/// Teams incoming webhook, colored with the optional `theme_color` (a hex
+/// string such as `"0076D7"`)
+#[derive(Debug, Clone)]
+pub struct TeamsNotification {
+    ty: String,
+    client: Client,
+}
+
+impl TeamsNotification {
+    /// The JSON body posted to the webhook, from rendered params
+    fn payload(params: &HashMap<String, Value>) -> Value {
+        let mut card = json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "summary": params["title"],
+            "title": params["title"],
+            "text": params["message"],
+        });
+        if let Some(color) = params.get("theme_color") {
+            card["themeColor"] = color.clone();
+        }
+
+        card
+    }
+}
+
+#[async_trait]
+impl EventTrait for TeamsNotification {
+    fn new() -> Self {
+        Self {
+            ty: EVENT_TYPE.to_string(),
+            client: Client::new(),
+        }
+    }
+
+    fn get_type(&self) -> &str {
+        &self.ty
+    }
+
+    fn validate(&self, params: &HashMap<String, Value>) -> Result<(), String> {
+        if !(params.contains_key("webhook_url")
+            && params.contains_key("title")
+            && params.contains_key("message"))
+        {
+            return Err(
+                "At least one of 'webhook_url', 'title', 'message' is missing."
+                    .to_string(),
+            );
+        }
+
+        if !params.get("theme_color").is_none_or(Value::is_string) {
+            return Err("'theme_color' must be a string.".to_string());
+        }
+
+        Ok(())
+    }
+
+    async fn trigger(
+        &mut self,
+        params: &HashMap<String, Value>,
+        facts: &(dyn Serialize + Sync),
+    ) -> Result<(), Error> {
+        let params = render_params(params, &serde_json::to_value(facts)?);
+        let webhook_url = params["webhook_url"].as_str().ok_or_else(|| {
+            Error::EventError("'webhook_url' must be a string.".to_string())
+        })?;
+
+        self.client
+            .post(webhook_url)
+            .json(&Self::payload(&params))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
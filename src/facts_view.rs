@@ -0,0 +1,314 @@
+//! Facts parsed ahead of a run by something else than serde_json, see
+//! `Engine::run_view`.
+//!
+//! The rules are evaluated against `Value`s, so a view is converted, but
+//! only the top level facts the rules may read are, as the rule index tells
+//! them. The whole document is only converted when something else reads
+//! it: a met rule's events, the run hooks, the facts size limit, or rules
+//! whose facts can't be told, e.g. expressions. With the `simd` feature,
+//! `simd_json::OwnedValue` documents are views.
+
+use crate::{
+    condition::{nested_path, Condition, PathSyntax},
+    error::Result,
+    index::{first_token, top_level_keys},
+    rule::Rule,
+    Engine, RuleResult,
+};
+use serde_json::{Map, Value};
+
+/// Undoes the escaping of a JSON pointer's reference token
+fn unescape_token(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+/// The array index a reference token is, as `Value::pointer` reads them
+fn parse_index(token: &str) -> Option<usize> {
+    if token.starts_with('+') || (token.starts_with('0') && token.len() != 1) {
+        return None;
+    }
+    token.parse().ok()
+}
+
+/// Read access to a JSON document, its nodes being views themselves
+pub trait FactsView {
+    /// The member of an object, `None` for other nodes
+    fn get(&self, key: &str) -> Option<&Self>;
+
+    /// The element of an array, `None` for other nodes
+    fn index(&self, i: usize) -> Option<&Self>;
+
+    fn is_null(&self) -> bool;
+
+    fn as_bool(&self) -> Option<bool>;
+
+    fn as_i64(&self) -> Option<i64>;
+
+    fn as_u64(&self) -> Option<u64>;
+
+    fn as_f64(&self) -> Option<f64>;
+
+    fn as_str(&self) -> Option<&str>;
+
+    /// The elements of an array, `None` for other nodes
+    fn array_iter(&self) -> Option<Box<dyn Iterator<Item = &Self> + '_>>;
+
+    /// The members of an object, `None` for other nodes
+    fn object_iter(
+        &self,
+    ) -> Option<Box<dyn Iterator<Item = (&str, &Self)> + '_>>;
+
+    /// The node the JSON pointer addresses, as `Value::pointer` finds it
+    fn pointer(&self, pointer: &str) -> Option<&Self> {
+        if pointer.is_empty() {
+            return Some(self);
+        }
+        let tokens = pointer.strip_prefix('/')?;
+
+        tokens
+            .split('/')
+            .map(unescape_token)
+            .try_fold(self, |node, token| {
+                node.get(&token)
+                    .or_else(|| node.index(parse_index(&token)?))
+            })
+    }
+
+    /// The node as a `Value`
+    fn to_value(&self) -> Value {
+        if let Some(b) = self.as_bool() {
+            Value::Bool(b)
+        } else if let Some(i) = self.as_i64() {
+            Value::from(i)
+        } else if let Some(u) = self.as_u64() {
+            Value::from(u)
+        } else if let Some(f) = self.as_f64() {
+            Value::from(f)
+        } else if let Some(s) = self.as_str() {
+            Value::String(s.to_owned())
+        } else if let Some(xs) = self.array_iter() {
+            Value::Array(xs.map(FactsView::to_value).collect())
+        } else if let Some(m) = self.object_iter() {
+            Value::Object(
+                m.map(|(k, x)| (k.to_owned(), x.to_value())).collect(),
+            )
+        } else {
+            Value::Null
+        }
+    }
+}
+
+impl FactsView for Value {
+    fn get(&self, key: &str) -> Option<&Self> {
+        self.as_object()?.get(key)
+    }
+
+    fn index(&self, i: usize) -> Option<&Self> {
+        self.as_array()?.get(i)
+    }
+
+    fn is_null(&self) -> bool {
+        Value::is_null(self)
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        Value::as_bool(self)
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        Value::as_i64(self)
+    }
+
+    fn as_u64(&self) -> Option<u64> {
+        Value::as_u64(self)
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        Value::as_f64(self)
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        Value::as_str(self)
+    }
+
+    fn array_iter(&self) -> Option<Box<dyn Iterator<Item = &Self> + '_>> {
+        Some(Box::new(self.as_array()?.iter()))
+    }
+
+    fn object_iter(
+        &self,
+    ) -> Option<Box<dyn Iterator<Item = (&str, &Self)> + '_>> {
+        Some(Box::new(
+            self.as_object()?.iter().map(|(k, x)| (k.as_str(), x)),
+        ))
+    }
+
+    fn pointer(&self, pointer: &str) -> Option<&Self> {
+        Value::pointer(self, pointer)
+    }
+
+    fn to_value(&self) -> Value {
+        self.clone()
+    }
+}
+
+#[cfg(feature = "simd")]
+impl FactsView for simd_json::OwnedValue {
+    fn get(&self, key: &str) -> Option<&Self> {
+        use simd_json::prelude::*;
+        self.as_object()?.get(key)
+    }
+
+    fn index(&self, i: usize) -> Option<&Self> {
+        use simd_json::prelude::*;
+        self.as_array()?.get(i)
+    }
+
+    fn is_null(&self) -> bool {
+        simd_json::prelude::ValueAsScalar::as_null(self).is_some()
+    }
+
+    fn as_bool(&self) -> Option<bool> {
+        simd_json::prelude::ValueAsScalar::as_bool(self)
+    }
+
+    fn as_i64(&self) -> Option<i64> {
+        simd_json::prelude::ValueAsScalar::as_i64(self)
+    }
+
+    fn as_u64(&self) -> Option<u64> {
+        simd_json::prelude::ValueAsScalar::as_u64(self)
+    }
+
+    fn as_f64(&self) -> Option<f64> {
+        simd_json::prelude::ValueAsScalar::cast_f64(self)
+    }
+
+    fn as_str(&self) -> Option<&str> {
+        simd_json::prelude::ValueAsScalar::as_str(self)
+    }
+
+    fn array_iter(&self) -> Option<Box<dyn Iterator<Item = &Self> + '_>> {
+        use simd_json::prelude::*;
+        Some(Box::new(self.as_array()?.iter()))
+    }
+
+    fn object_iter(
+        &self,
+    ) -> Option<Box<dyn Iterator<Item = (&str, &Self)> + '_>> {
+        use simd_json::prelude::*;
+        Some(Box::new(
+            self.as_object()?.iter().map(|(k, x)| (k.as_str(), x)),
+        ))
+    }
+}
+
+/// The top level facts the rule may read, `None` when they can't be told or
+/// its templated values or coalescence groups may read any
+fn read_keys(rule: &Rule) -> Option<Vec<String>> {
+    let templated = rule.conditions.leaves().into_iter().any(|leaf| {
+        matches!(
+            leaf,
+            Condition::Condition {
+                templated_value: true,
+                ..
+            }
+        )
+    });
+    if templated
+        || rule
+            .events
+            .iter()
+            .any(|event| event.coalescence_group.is_some())
+    {
+        return None;
+    }
+
+    let mut keys = top_level_keys(&rule.conditions)?;
+    if let Some(sample) = &rule.sample {
+        let field = &sample.key_field;
+        keys.push(field.clone());
+        keys.push(first_token(&nested_path(field, PathSyntax::Pointer))?);
+    }
+    Some(keys)
+}
+
+impl Engine {
+    /// Same as `run`, against facts parsed ahead, e.g. by simd-json. Only
+    /// the top level facts the rules may read are converted to `Value`s,
+    /// unless something reads them all: the events of the met rules, the run
+    /// hooks, the facts size limit, or rules whose facts can't be told
+    pub async fn run_view<V: FactsView + Sync>(
+        &mut self,
+        facts: &V,
+    ) -> Result<Vec<RuleResult>> {
+        let (projected, complete) = self.view_facts(facts);
+        Ok(self
+            .run_facts(None, None, projected, complete.then_some(facts))
+            .await?
+            .0)
+    }
+
+    /// Same as `evaluate`, against facts parsed ahead as `run_view` reads
+    /// them
+    pub fn evaluate_view<V: FactsView>(
+        &self,
+        facts: &V,
+    ) -> Result<Vec<RuleResult>> {
+        self.evaluate_facts(self.view_facts(facts).0)
+    }
+
+    /// The facts of the view the rules without a tenant may read, and
+    /// whether some were left out
+    fn view_facts<V: FactsView>(&self, view: &V) -> (Value, bool) {
+        let keys = match self.read_keys() {
+            Some(keys)
+                if self.run_hooks.before.is_none()
+                    && self.run_hooks.after.is_none()
+                    && self.limits.max_facts_bytes.is_none()
+                    && view.object_iter().is_some() =>
+            {
+                keys
+            }
+            _ => return (view.to_value(), false),
+        };
+
+        let facts: Map<String, Value> = keys
+            .into_iter()
+            .filter_map(|key| {
+                let fact = view.get(&key)?.to_value();
+                Some((key, fact))
+            })
+            .collect();
+        (Value::Object(facts), true)
+    }
+
+    /// The top level facts the rules without a tenant, and the groups, may
+    /// read, `None` when it can't be told
+    fn read_keys(&self) -> Option<Vec<String>> {
+        // the elements of arrays may read any fact through the alias
+        if self.element_alias.is_some()
+            || self
+                .rule_groups
+                .iter()
+                .flat_map(|group| &group.events)
+                .any(|event| event.coalescence_group.is_some())
+        {
+            return None;
+        }
+
+        let mut keys = Vec::new();
+        for rule in self
+            .rules_in_scope(None)
+            .into_iter()
+            .map(|i| &self.rules[i])
+            .chain(self.rule_groups.iter().flat_map(|group| &group.rules))
+            .filter(|rule| rule.enabled)
+        {
+            keys.extend(read_keys(rule)?);
+        }
+        keys.sort();
+        keys.dedup();
+        Some(keys)
+    }
+}
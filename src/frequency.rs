@@ -0,0 +1,141 @@
+//! Conditions met a number of times within a sliding window of time, across
+//! runs, see `Condition::Frequency`.
+//!
+//! The engine keeps, for every frequency node and entity, the last times the
+//! node's condition was met, up to the number it must reach, so a window
+//! holds at most `at_least` timestamps. Windows are evicted least recently
+//! used first once there are more than `Engine::set_frequency_max_keys`.
+
+use std::{
+    collections::{BTreeMap, HashMap, VecDeque},
+    sync::Mutex,
+};
+
+/// How many windows the engine keeps unless set otherwise, see
+/// `Engine::set_frequency_max_keys`
+pub const DEFAULT_FREQUENCY_MAX_KEYS: usize = 10_000;
+
+/// The run being evaluated, whose frequency nodes met are recorded
+#[derive(Debug, Clone)]
+pub(crate) struct FrequencyRun {
+    /// The entity of the run, see `Engine::run_keyed`
+    pub(crate) entity: String,
+    pub(crate) id: u64,
+}
+
+/// A frequency node, serialized, and an entity
+type WindowKey = (String, String);
+
+#[derive(Debug)]
+struct Window {
+    /// When the condition was met, in milliseconds, oldest first
+    met_at: VecDeque<i64>,
+    /// The last run that recorded into the window, so a node shared by
+    /// several rules counts once per run
+    run: u64,
+    /// When the window was last used, see `Windows::lru`
+    used: u64,
+}
+
+#[derive(Debug, Default)]
+struct Windows {
+    by_key: HashMap<WindowKey, Window>,
+    /// The keys of the windows by when they were last used
+    lru: BTreeMap<u64, WindowKey>,
+    tick: u64,
+}
+
+impl Windows {
+    fn evict(&mut self, max_keys: usize) {
+        while self.by_key.len() > max_keys {
+            let (_, key) = match self.lru.pop_first() {
+                Some(oldest) => oldest,
+                None => return,
+            };
+            self.by_key.remove(&key);
+        }
+    }
+}
+
+#[derive(Debug)]
+pub(crate) struct FrequencyTracker {
+    max_keys: usize,
+    windows: Mutex<Windows>,
+}
+
+impl Default for FrequencyTracker {
+    fn default() -> Self {
+        Self {
+            max_keys: DEFAULT_FREQUENCY_MAX_KEYS,
+            windows: Mutex::default(),
+        }
+    }
+}
+
+impl FrequencyTracker {
+    pub(crate) fn set_max_keys(&mut self, max_keys: usize) {
+        self.max_keys = max_keys;
+        self.windows.get_mut().unwrap().evict(max_keys);
+    }
+
+    pub(crate) fn clear(&mut self) {
+        *self.windows.get_mut().unwrap() = Windows::default();
+    }
+
+    /// Keeps the histories of the entities `f` returns `true` for
+    pub(crate) fn retain_entities(&mut self, f: impl Fn(&str) -> bool) {
+        let windows = self.windows.get_mut().unwrap();
+        windows.by_key.retain(|(_, entity), _| f(entity));
+        windows.lru.retain(|_, (_, entity)| f(entity));
+    }
+
+    /// The times the node was met within the window ending `now`, this time
+    /// included when `met`, recording it when evaluating a run
+    pub(crate) fn count(
+        &self,
+        node: &str,
+        run: Option<&FrequencyRun>,
+        met: bool,
+        now: i64,
+        window_ms: i64,
+        at_least: usize,
+    ) -> usize {
+        let since = now.saturating_sub(window_ms);
+        let within = |window: &Window| {
+            window.met_at.iter().filter(|t| **t > since).count()
+        };
+
+        let mut windows = self.windows.lock().unwrap();
+        let entity = run.map_or("", |run| &run.entity);
+        let key = (node.to_owned(), entity.to_owned());
+        let run = match run {
+            Some(run) if met => run.id,
+            _ => {
+                let seen = windows.by_key.get(&key).map_or(0, within);
+                return seen + usize::from(met);
+            }
+        };
+
+        windows.tick += 1;
+        let tick = windows.tick;
+        let window = windows.by_key.entry(key.clone()).or_insert(Window {
+            met_at: VecDeque::new(),
+            run: 0,
+            used: tick,
+        });
+        if window.run != run {
+            window.run = run;
+            window.met_at.push_back(now);
+            while window.met_at.len() > at_least.max(1) {
+                window.met_at.pop_front();
+            }
+        }
+        let count = within(window);
+        let used = std::mem::replace(&mut window.used, tick);
+
+        windows.lru.remove(&used);
+        windows.lru.insert(tick, key);
+        windows.evict(self.max_keys);
+        count
+    }
+}
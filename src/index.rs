@@ -0,0 +1,117 @@
+//! Index of the rules by the top level facts they refer to, see
+//! `Engine::set_rule_index`.
+//!
+//! A leaf whose fact is missing is `Unknown` unless it has a `default`, so a
+//! rule whose leaves all refer to top level keys the facts don't have is
+//! evaluated as if the facts were empty. The rules which can't be met that
+//! way are only evaluated when the facts have one of their keys, the others
+//! always are, as are those with expressions, scripts or async predicates,
+//! whose facts can't be told without running them.
+
+use crate::{
+    condition::{nested_path, Condition},
+    status::Status,
+};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// Undoes `escape_token`
+fn unescape_token(token: &str) -> String {
+    token.replace("~1", "/").replace("~0", "~")
+}
+
+/// The first reference token of a JSON pointer, `None` for the whole
+/// document
+pub(crate) fn first_token(pointer: &str) -> Option<String> {
+    let pointer = pointer.strip_prefix('/')?;
+    Some(unescape_token(
+        pointer.split('/').next().unwrap_or_default(),
+    ))
+}
+
+/// The top level keys of the facts a rule may read, `None` when they can't
+/// be told
+pub(crate) fn top_level_keys(conditions: &Condition) -> Option<Vec<String>> {
+    let mut keys = Vec::new();
+    for (_, node) in conditions.nodes() {
+        match node {
+            Condition::Condition {
+                default: Some(_), ..
+            } => return None,
+            Condition::Condition {
+                field,
+                pointer,
+                path_syntax,
+                ..
+            } => {
+                if *pointer {
+                    keys.push(first_token(field)?);
+                } else {
+                    // a top level key named after the field comes first
+                    keys.push(field.clone());
+                    keys.push(first_token(&nested_path(field, *path_syntax))?);
+                }
+            }
+            Condition::And { .. }
+            | Condition::Or { .. }
+            | Condition::Not { .. }
+            | Condition::AtLeast { .. } => {}
+            #[allow(unreachable_patterns)]
+            _ => return None,
+        }
+    }
+
+    keys.sort();
+    keys.dedup();
+    if keys.is_empty() {
+        None
+    } else {
+        Some(keys)
+    }
+}
+
+#[derive(Debug, Default)]
+pub(crate) struct RuleIndex {
+    /// Indices of the rules by the top level keys they refer to
+    by_key: HashMap<String, Vec<usize>>,
+    /// Indices of the rules evaluated whatever the facts
+    always: Vec<usize>,
+}
+
+impl RuleIndex {
+    /// Indexes the rule at index `i`, given its status against empty facts
+    pub(crate) fn insert(
+        &mut self,
+        i: usize,
+        conditions: &Condition,
+        empty_status: Status,
+    ) {
+        match top_level_keys(conditions) {
+            Some(keys) if empty_status != Status::Met => {
+                for key in keys {
+                    self.by_key.entry(key).or_default().push(i);
+                }
+            }
+            _ => self.always.push(i),
+        }
+    }
+
+    /// Indices of the rules worth evaluating against the facts, in order,
+    /// every rule when they aren't an object
+    pub(crate) fn candidates(&self, facts: &Value, rules: usize) -> Vec<usize> {
+        let facts = match facts.as_object() {
+            Some(facts) => facts,
+            None => return (0..rules).collect(),
+        };
+
+        let mut candidates = self.always.clone();
+        for key in facts.keys() {
+            if let Some(rules) = self.by_key.get(key) {
+                candidates.extend(rules);
+            }
+        }
+        candidates.sort_unstable();
+        candidates.dedup();
+        candidates
+    }
+}
@@ -60,77 +60,497 @@
 //!
 //! [1]: enum.Rule.html#method.check
 
+#[cfg(feature = "async_predicate")]
+mod async_predicate;
+mod backtest;
+#[cfg(feature = "binary")]
+mod binary;
+#[cfg(feature = "broadcast")]
+mod broadcast;
+pub mod catalog;
+mod clock;
+mod compact;
+mod compiled;
 mod condition;
 mod constraint;
+mod dead_letter;
+#[cfg(feature = "delay")]
+mod delay;
+#[cfg(feature = "detached")]
+mod detached;
+pub mod diff;
+mod digest;
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+mod encoded_facts;
 mod error;
 mod event;
+mod facts_view;
+mod frequency;
+mod index;
+mod limits;
+mod lint;
+mod lookup;
+#[cfg(feature = "lua")]
+mod lua;
+mod migrations;
+mod mute;
+#[cfg(feature = "unicode")]
+mod normalization;
+mod otel;
+mod persistence;
+mod pipeline;
+mod planning;
+mod rate_limit;
+mod report;
 mod rule;
+mod sampling;
+#[cfg(feature = "schema")]
+mod schema;
+mod sources;
+mod sql;
 mod status;
+mod strict;
+mod summary;
+mod tenant;
+#[cfg(feature = "test_util")]
+pub mod test_util;
+pub mod testkit;
+#[cfg(feature = "callback")]
+mod transport;
+#[cfg(feature = "wasm")]
+mod wasm;
 
-pub use crate::{condition::*, constraint::*, event::*, rule::*, status::*};
+pub use crate::{
+    condition::*,
+    constraint::*,
+    event::*,
+    limits::{Limits, TRUNCATION_MARKER},
+    rule::*,
+    status::*,
+};
 
+#[cfg(feature = "async_predicate")]
+pub use crate::async_predicate::{
+    AsyncPredicateFn, DEFAULT_ASYNC_PREDICATE_TIMEOUT,
+};
+pub use crate::backtest::BacktestReport;
+#[cfg(feature = "binary")]
+pub use crate::binary::BINARY_FORMAT_VERSION;
+#[cfg(feature = "broadcast")]
+pub use crate::broadcast::Subscription;
+pub use crate::compact::COMPACT_FORMAT_VERSION;
+pub use crate::compiled::EngineOptions;
+pub use crate::dead_letter::{DeadLetter, DeadLetterSink, MAX_DEAD_LETTERS};
+#[cfg(feature = "delay")]
+pub use crate::delay::DelayedEvent;
+#[cfg(feature = "detached")]
+pub use crate::detached::EventOutcome;
+pub use crate::event::apply_json_patch::{PatchOp, PatchOutcome};
+#[cfg(feature = "callback")]
+pub use crate::event::post_callback::CallbackUrlPolicy;
+pub use crate::facts_view::FactsView;
+pub use crate::frequency::DEFAULT_FREQUENCY_MAX_KEYS;
+pub use crate::lint::{LintKind, LintSeverity, LintWarning};
+#[cfg(feature = "lua")]
+pub use crate::lua::{LUA_INSTRUCTION_LIMIT, LUA_MEMORY_LIMIT};
+pub use crate::migrations::{migrate_rule_value, SCHEMA_VERSION};
+pub use crate::mute::{Mute, MuteScope};
+#[cfg(feature = "unicode")]
+pub use crate::normalization::Normalization;
+pub use crate::otel::{AttrValue, DEFAULT_OTEL_MAX_ATTRIBUTES};
+#[cfg(feature = "delay")]
+pub use crate::persistence::DelayedEventState;
+pub use crate::persistence::{
+    EngineState, RateLimitState, ENGINE_STATE_VERSION,
+};
+pub use crate::pipeline::{Pipeline, StageResult};
+pub use crate::planning::{EvaluationPlan, RuleDecision};
+pub use crate::report::RenderOptions;
+pub use crate::sampling::{sample_bucket, Sample};
+#[cfg(feature = "schema")]
+pub use crate::schema::FieldMismatch;
+pub use crate::sql::{SqlDialect, SqlParam, SqlWhere};
+pub use crate::summary::EngineSummary;
+#[cfg(feature = "callback")]
+pub use crate::transport::{
+    EventTransport, OutboundBody, OutboundRequest, ReqwestTransport,
+    TransportResponse,
+};
+#[cfg(feature = "wasm")]
+pub use crate::wasm::evaluate;
 #[cfg(feature = "eval")]
 pub use rhai::{serde::from_dynamic, Map};
 
+#[cfg(feature = "async_predicate")]
+use crate::condition::PredicateResults;
+#[cfg(feature = "regex")]
+use crate::constraint::check_patterns;
+use crate::{
+    compiled::RulePlan,
+    condition::EvalContext,
+    constraint::NamedSets,
+    frequency::{FrequencyRun, FrequencyTracker},
+    index::RuleIndex,
+    lookup::LookupCache,
+    rate_limit::TokenBucket,
+    tenant::{tenant_event_type, tenant_key},
+};
 #[cfg(feature = "eval")]
 use rhai::{
     def_package,
     packages::{
-        ArithmeticPackage, BasicArrayPackage, BasicMapPackage, LogicPackage,
+        ArithmeticPackage, BasicArrayPackage, BasicMapPackage,
+        BasicMathPackage, BasicStringPackage, LogicPackage, MoreStringPackage,
         Package,
     },
-    Engine as RhaiEngine,
+    Engine as RhaiEngine, EvalAltResult, Position,
+};
+use serde_json::{value::to_value, Value};
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    time::Duration,
 };
-use serde_json::value::to_value;
-use std::{collections::HashMap, time::Instant};
 
+use crate::event::apply_json_patch::ApplyJsonPatch;
+#[cfg(feature = "discord")]
+use crate::event::discord_notification::DiscordNotification;
 #[cfg(feature = "email")]
 use crate::event::email_notification::EmailNotification;
+#[cfg(feature = "nats")]
+use crate::event::nats_publish::NatsPublish;
 #[cfg(feature = "callback")]
-use crate::event::post_callback::PostCallback;
+use crate::event::post_callback::{
+    render_callback_url, resolve_idempotency_key, PostCallback,
+    EVENT_TYPE as POST_CALLBACK_TYPE,
+};
+#[cfg(feature = "teams")]
+use crate::event::teams_notification::TeamsNotification;
+#[cfg(feature = "aws")]
+use crate::event::{sns_publish::SnsPublish, sqs_send::SqsSend};
 
+use crate::clock::{Instant, SystemTime, UNIX_EPOCH};
+#[cfg(feature = "detached")]
+use crate::detached::DetachedTrigger;
 pub use crate::error::*;
-use serde::Serialize;
-use std::{rc::Rc, sync::RwLock};
+use chrono::{DateTime, Utc};
+use futures_util::future::join_all;
+use serde::{Deserialize, Serialize};
+use std::sync::{Arc, Mutex, RwLock};
 
 #[cfg(feature = "eval")]
-def_package!(rhai:JsonRulesEnginePackage:"Package for json-rules-engine", lib, {
-    ArithmeticPackage::init(lib);
-    LogicPackage::init(lib);
-    BasicArrayPackage::init(lib);
-    BasicMapPackage::init(lib);
-});
-
-#[derive(Default)]
+def_package! {
+    /// Package for json-rules-engine: arithmetic, logic, math, and the
+    /// methods of strings, arrays and maps, e.g.
+    /// `facts.name.to_lower().contains("jiang")`. Nothing reaching out of
+    /// the expression, such as `eval` or file IO, is loaded
+    pub JsonRulesEnginePackage(lib) {
+        ArithmeticPackage::init(lib);
+        LogicPackage::init(lib);
+        BasicMathPackage::init(lib);
+        BasicStringPackage::init(lib);
+        MoreStringPackage::init(lib);
+        BasicArrayPackage::init(lib);
+        BasicMapPackage::init(lib);
+
+        #[cfg(all(feature = "regex", feature = "eval"))]
+        lib.set_native_fn("matches_regex", matches_regex);
+    }
+}
+
+/// Whether the regex matches somewhere in the string, failing the
+/// expression when it doesn't compile. Anchor it with `^` and `$` to match
+/// the whole string
+#[cfg(all(feature = "regex", feature = "eval"))]
+fn matches_regex(
+    string: &str,
+    pattern: &str,
+) -> std::result::Result<bool, Box<EvalAltResult>> {
+    let regex = regex::Regex::new(pattern).map_err(|e| {
+        EvalAltResult::ErrorRuntime(e.to_string().into(), Position::NONE)
+    })?;
+    Ok(regex.is_match(string))
+}
+
+/// The met rules of an evaluation, see `Engine::evaluate_value`
+struct Evaluation {
+    met_rule_results: Vec<(RuleKey, RuleResult)>,
+    group_results: Vec<GroupResult>,
+    rules_evaluated: usize,
+    /// The facts once patched by the met rules, if any was
+    patched_facts: Option<Value>,
+}
+
+/// A run whose met rules' events aren't dispatched yet, see
+/// `Engine::evaluate_run`
+pub(crate) struct EvaluatedRun {
+    started_at: u64,
+    start: Instant,
+    keys: Vec<RuleKey>,
+    pub(crate) rule_results: Vec<RuleResult>,
+    group_results: Vec<GroupResult>,
+    rules_evaluated: usize,
+    skipped_disabled: usize,
+    pub(crate) patched_facts: Option<Value>,
+}
+
+/// Tells the engine what time it is, see `Engine::set_now_provider`
+pub type NowProvider = Arc<dyn Fn() -> DateTime<Utc> + Send + Sync>;
+
+/// Called with the facts of every run and evaluation, before the rules are
+/// evaluated, see `Engine::set_run_hooks`
+pub type BeforeRunHook = Arc<dyn Fn(&Value) + Send + Sync>;
+
+/// Called with the facts and the met rules of every run and evaluation, once
+/// the events were dispatched, see `Engine::set_run_hooks`
+pub type AfterRunHook = Arc<dyn Fn(&Value, &[RuleResult]) + Send + Sync>;
+
+/// Hooks around runs and evaluations, e.g. to audit them
+#[derive(Clone, Default)]
+pub struct RunHooks {
+    pub before: Option<BeforeRunHook>,
+    pub after: Option<AfterRunHook>,
+}
+
+/// Runs a hook, a panicking one being ignored
+fn call_hook(hook: impl FnOnce()) {
+    let _ = std::panic::catch_unwind(std::panic::AssertUnwindSafe(hook));
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|d| d.as_millis() as u64)
+        .unwrap_or_default()
+}
+
+/// Summary of a whole `Engine::run_with_info` call
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct RunInfo {
+    /// When the run started, in milliseconds since the unix epoch
+    pub started_at: u64,
+    /// Time spent evaluating rules and dispatching their events
+    pub total_duration: Duration,
+    /// Rules and group members evaluated, leaving out the rules the index
+    /// skipped, see `Engine::set_rule_index`, the disabled ones and those
+    /// sampled out, see `Rule::sample`
+    pub rules_evaluated: usize,
+    /// Disabled rules and group members left out, see `Rule::enabled`
+    #[serde(default)]
+    pub skipped_disabled: usize,
+    /// Rule groups emitting `OncePerRun` that had at least one member met
+    pub group_results: Vec<GroupResult>,
+    /// The tenant whose rules were run, see `Engine::run_for`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// The facts once patched by the `apply_json_patch` events of the met
+    /// rules, if any was applied, see `ApplyJsonPatch`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub patched_facts: Option<Value>,
+}
+
+/// What a run does when an event fails to dispatch
+#[derive(
+    Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum ErrorMode {
+    /// Keep dispatching the other events, recording the error on the failed
+    /// one in the results
+    BestEffort,
+    /// Stop at the first failed event and fail the run with
+    /// `Error::EventDispatch`
+    #[default]
+    FailFast,
+}
+
+/// How many envelopes a subscriber may fall behind before it starts missing
+/// them
+#[cfg(feature = "broadcast")]
+pub const BROADCAST_CAPACITY: usize = 1024;
+
+/// Where a rule lives on the engine: the index of its group, if any, and its
+/// index in there
+type RuleKey = (Option<usize>, usize);
+
+/// Merges `overrides` into `base`, recursing into objects present in both
+fn deep_merge(base: &mut Value, overrides: &Value) {
+    match (base, overrides) {
+        (Value::Object(base), Value::Object(overrides)) => {
+            for (k, v) in overrides {
+                deep_merge(base.entry(k.clone()).or_insert(Value::Null), v);
+            }
+        }
+        (base, overrides) => *base = overrides.clone(),
+    }
+}
+
 pub struct Engine {
     rules: Vec<Rule>,
-    events: HashMap<String, Rc<RwLock<dyn EventTrait>>>,
+    /// The plans of the rules, by index, compiled by `Engine::build` or
+    /// only hashing their long lists and compiling their patterns, see
+    /// `RulePlan::lists_and_patterns`
+    plans: Vec<Option<RulePlan>>,
+    /// Set when the rules are indexed, see `Engine::set_rule_index`
+    rule_index: Option<RuleIndex>,
+    /// Indices of the rules of each tenant, see `Engine::add_rule_for`
+    tenants: HashMap<String, Vec<usize>>,
+    rule_groups: Vec<RuleGroup>,
+    events: HashMap<String, Arc<RwLock<dyn EventTrait>>>,
     #[cfg(feature = "eval")]
     rhai_engine: RhaiEngine,
-    coalescences: HashMap<String, (Instant, u64)>,
+    #[cfg(feature = "eval")]
+    eval_flatten_scope: bool,
+    /// When each coalescence group was claimed, and for how many seconds.
+    /// Shared with the detached tasks, which claim the groups of the events
+    /// they send
+    coalescences: Arc<Mutex<HashMap<String, (Instant, u64)>>>,
+    /// Taken, asynchronously, before the lock of the handler of each event
+    /// type, so the engine awaits the detached tasks triggering a type
+    /// rather than blocking its thread on the lock
+    #[cfg(feature = "detached")]
+    handler_gates: Mutex<HashMap<String, Arc<tokio::sync::Mutex<()>>>>,
+    rate_limits: HashMap<String, TokenBucket>,
+    sets: NamedSets,
+    variables: HashMap<String, Value>,
+    now: NowProvider,
+    limits: Limits,
+    error_mode: ErrorMode,
+    trace: bool,
+    /// See `Engine::set_collect_matches`
+    collect_matches: bool,
+    /// See `Engine::set_element_alias`
+    element_alias: Option<String>,
+    /// See `Engine::set_root_wrap_key`
+    root_wrap_key: Option<String>,
+    /// See `Engine::set_strict_facts_root`
+    strict_facts_root: bool,
+    /// See `Engine::set_abort_sequence_on_error`
+    abort_sequence_on_error: bool,
+    /// See `Engine::set_sequential_event_dispatch`
+    sequential_event_dispatch: bool,
+    /// See `Engine::set_merge_sources`
+    merge_sources: bool,
+    /// See `Engine::mute`
+    mutes: Vec<Mute>,
+    interceptors: Vec<Arc<dyn EventInterceptor>>,
+    /// See `Engine::set_dead_letter_sink`
+    dead_letter_sink: Option<DeadLetterSink>,
+    /// See `Engine::take_dead_letters`
+    dead_letters: VecDeque<DeadLetter>,
+    /// See `EngineOptions::allowed_severities`
+    allowed_severities: Option<HashSet<String>>,
+    run_hooks: RunHooks,
+    #[cfg(feature = "async_predicate")]
+    async_predicates: HashMap<String, AsyncPredicateFn>,
+    #[cfg(feature = "async_predicate")]
+    async_predicate_timeout: Duration,
+    /// Outcome of the predicate calls of the rules being evaluated
+    #[cfg(feature = "async_predicate")]
+    predicate_results: PredicateResults,
+    /// The history of the frequency nodes, see `Condition::Frequency`
+    frequencies: FrequencyTracker,
+    /// The run being evaluated, whose frequency nodes are recorded
+    frequency_run: Option<FrequencyRun>,
+    runs: u64,
+    #[cfg(feature = "delay")]
+    delayed: Vec<DelayedEvent>,
+    #[cfg(feature = "delay")]
+    next_delayed_id: u64,
+    #[cfg(feature = "delay")]
+    latest_facts: Option<Value>,
+    #[cfg(feature = "callback")]
+    default_app_data: serde_json::Map<String, Value>,
+    /// Shared with the transport of the `post_to_callback_url` event the
+    /// engine registers, which checks the url again when sending
+    #[cfg(feature = "callback")]
+    callback_url_policy: Arc<RwLock<CallbackUrlPolicy>>,
+    /// Drawn for each run, the `_run_nonce` of the templated idempotency
+    /// keys of its `post_to_callback_url` events
+    #[cfg(feature = "callback")]
+    run_nonce: String,
+    #[cfg(feature = "broadcast")]
+    broadcast: tokio::sync::broadcast::Sender<EventEnvelope>,
+    /// The envelopes subscribers missed, see `Engine::lagged_envelopes`
+    #[cfg(feature = "broadcast")]
+    lagged_envelopes: Arc<std::sync::atomic::AtomicU64>,
+}
+
+impl Default for Engine {
+    fn default() -> Self {
+        Self::new()
+    }
 }
 
 impl Engine {
     pub fn new() -> Self {
-        #[allow(unused_mut)]
-        let mut events: HashMap<_, Rc<RwLock<dyn EventTrait>>> = HashMap::new();
+        let mut events: HashMap<_, Arc<RwLock<dyn EventTrait>>> =
+            HashMap::new();
+        #[cfg(feature = "callback")]
+        let callback_url_policy: Arc<RwLock<CallbackUrlPolicy>> =
+            Arc::default();
+
+        {
+            let event = Arc::new(RwLock::new(ApplyJsonPatch::new()));
+            let key = event.read().unwrap().get_type().to_string();
+            events.insert(key, event);
+        }
 
         #[cfg(feature = "callback")]
         {
-            let event = Rc::new(RwLock::new(PostCallback::new()));
+            let event =
+                Arc::new(RwLock::new(PostCallback::with_transport(Arc::new(
+                    ReqwestTransport::with_policy(callback_url_policy.clone()),
+                ))));
             let key = event.read().unwrap().get_type().to_string();
             events.insert(key, event);
         }
 
         #[cfg(feature = "email")]
         {
-            let event = Rc::new(RwLock::new(EmailNotification::new()));
+            let event = Arc::new(RwLock::new(EmailNotification::new()));
+            let key = event.read().unwrap().get_type().to_string();
+            events.insert(key, event);
+        }
+
+        #[cfg(feature = "discord")]
+        {
+            let event = Arc::new(RwLock::new(DiscordNotification::new()));
+            let key = event.read().unwrap().get_type().to_string();
+            events.insert(key, event);
+        }
+
+        #[cfg(feature = "teams")]
+        {
+            let event = Arc::new(RwLock::new(TeamsNotification::new()));
+            let key = event.read().unwrap().get_type().to_string();
+            events.insert(key, event);
+        }
+
+        #[cfg(feature = "aws")]
+        {
+            let event = Arc::new(RwLock::new(SnsPublish::new()));
+            let key = event.read().unwrap().get_type().to_string();
+            events.insert(key, event);
+
+            let event = Arc::new(RwLock::new(SqsSend::new()));
+            let key = event.read().unwrap().get_type().to_string();
+            events.insert(key, event);
+        }
+
+        #[cfg(feature = "nats")]
+        {
+            let event = Arc::new(RwLock::new(NatsPublish::new()));
             let key = event.read().unwrap().get_type().to_string();
             events.insert(key, event);
         }
 
         Self {
             rules: Vec::new(),
+            plans: Vec::new(),
+            rule_index: None,
+            tenants: HashMap::new(),
+            rule_groups: Vec::new(),
             #[cfg(feature = "eval")]
             rhai_engine: {
                 let mut engine = RhaiEngine::new_raw();
@@ -139,104 +559,1660 @@ impl Engine {
                 );
                 engine
             },
-            coalescences: HashMap::new(),
+            #[cfg(feature = "eval")]
+            eval_flatten_scope: false,
+            coalescences: Arc::default(),
+            #[cfg(feature = "detached")]
+            handler_gates: Mutex::default(),
+            rate_limits: HashMap::new(),
+            sets: NamedSets::default(),
+            variables: HashMap::new(),
+            now: Arc::new(Utc::now),
+            limits: Limits::default(),
+            error_mode: ErrorMode::default(),
+            trace: false,
+            collect_matches: false,
+            element_alias: None,
+            root_wrap_key: None,
+            strict_facts_root: false,
+            abort_sequence_on_error: false,
+            sequential_event_dispatch: false,
+            merge_sources: false,
+            mutes: Vec::new(),
+            interceptors: Vec::new(),
+            dead_letter_sink: None,
+            dead_letters: VecDeque::new(),
+            allowed_severities: None,
+            run_hooks: RunHooks::default(),
+            #[cfg(feature = "async_predicate")]
+            async_predicates: HashMap::new(),
+            #[cfg(feature = "async_predicate")]
+            async_predicate_timeout: DEFAULT_ASYNC_PREDICATE_TIMEOUT,
+            #[cfg(feature = "async_predicate")]
+            predicate_results: PredicateResults::new(),
+            frequencies: FrequencyTracker::default(),
+            frequency_run: None,
+            runs: 0,
+            #[cfg(feature = "delay")]
+            delayed: Vec::new(),
+            #[cfg(feature = "delay")]
+            next_delayed_id: 0,
+            #[cfg(feature = "delay")]
+            latest_facts: None,
+            #[cfg(feature = "callback")]
+            default_app_data: serde_json::Map::new(),
+            #[cfg(feature = "callback")]
+            callback_url_policy,
+            #[cfg(feature = "callback")]
+            run_nonce: String::new(),
+            #[cfg(feature = "broadcast")]
+            broadcast: tokio::sync::broadcast::channel(BROADCAST_CAPACITY).0,
+            #[cfg(feature = "broadcast")]
+            lagged_envelopes: Arc::default(),
             events,
         }
     }
 
+    /// Builds an engine with the rules compiled ahead of their evaluation:
+    /// their expressions parsed, their templates compiled and the JSON
+    /// pointers of their fields computed, rather than on every run.
+    ///
+    /// Every rule is validated as by `try_add_rule`, and the templates in
+    /// their event params are checked to compile. The failures of all the
+    /// rules are returned at once, along with the index of their rule
+    pub fn build(
+        rules: Vec<Rule>,
+        options: EngineOptions,
+    ) -> std::result::Result<Self, Vec<(usize, Error)>> {
+        let mut engine = Self::new();
+        engine.limits = options.limits;
+        engine.sets.strings = options.sets;
+        engine.sets.ints = options.int_sets;
+        engine.allowed_severities = options.allowed_severities;
+        engine.root_wrap_key = options.root_wrap_key;
+        engine.strict_facts_root = options.strict_facts_root;
+        #[cfg(feature = "eval")]
+        {
+            engine.eval_flatten_scope = options.eval_flatten_scope;
+        }
+        #[cfg(feature = "async_predicate")]
+        {
+            engine.async_predicates = options.async_predicates;
+        }
+        #[cfg(feature = "nats")]
+        if let Some(url) = &options.nats_url {
+            engine.add_event(Arc::new(RwLock::new(NatsPublish::with_url(url))));
+        }
+
+        let mut errors = Vec::new();
+        for (i, rule) in rules.into_iter().enumerate() {
+            let plan = engine.validate_rule(&rule, i).and_then(|()| {
+                RulePlan::compile(
+                    &rule,
+                    #[cfg(feature = "eval")]
+                    &engine.rhai_engine,
+                )
+            });
+            match plan {
+                Ok(plan) => {
+                    engine.rules.push(rule);
+                    engine.plans.push(Some(plan));
+                }
+                Err(e) => errors.push((i, e)),
+            }
+        }
+
+        engine.set_rule_index(options.enable_rule_index);
+
+        if errors.is_empty() {
+            Ok(engine)
+        } else {
+            Err(errors)
+        }
+    }
+
     pub fn add_rule(&mut self, rule: Rule) {
-        self.rules.push(rule)
+        self.plans.push(Some(RulePlan::lists_and_patterns(&rule)));
+        self.rules.push(rule);
+        self.index_rules(self.rules.len() - 1);
+    }
+
+    /// Same as `add_rule`, but refuses rules referencing named sets or async
+    /// predicates that aren't registered yet, exceeding the engine's
+    /// `Limits`, with patterns that don't compile, or with events whose
+    /// `reason_code` or `severity` isn't a string or whose severity isn't
+    /// allowed
+    pub fn try_add_rule(&mut self, rule: Rule) -> Result<()> {
+        self.validate_rule(&rule, self.rules_count())?;
+        self.add_rule(rule);
+        Ok(())
+    }
+
+    /// Checks the rule as `try_add_rule` does, `rules` being the number of
+    /// rules the engine has before it
+    fn validate_rule(&self, rule: &Rule, rules: usize) -> Result<()> {
+        self.limits.check_rule(rule, rules)?;
+
+        if let Some(name) =
+            rule.conditions
+                .leaves()
+                .into_iter()
+                .find_map(|leaf| match leaf {
+                    Condition::Condition {
+                        constraint: ValueOrVar::Value(constraint),
+                        ..
+                    } => self.sets.missing(constraint),
+                    _ => None,
+                })
+        {
+            return Err(Error::ValidationError(format!(
+                "Named set `{}` isn't registered",
+                name
+            )));
+        }
+
+        #[cfg(feature = "regex")]
+        for leaf in rule.conditions.leaves() {
+            if let Condition::Condition {
+                constraint:
+                    ValueOrVar::Value(
+                        Constraint::StringMatchesAny(patterns)
+                        | Constraint::StringMatchesNone(patterns),
+                    ),
+                templated_value: false,
+                ..
+            } = leaf
+            {
+                check_patterns(patterns).map_err(Error::ValidationError)?;
+            }
+        }
+
+        for event in &rule.events {
+            event
+                .event
+                .check_reason(self.allowed_severities.as_ref())
+                .map_err(Error::ValidationError)?;
+            if event.event.ty == apply_json_patch::EVENT_TYPE {
+                apply_json_patch::patch(&event.event.params)
+                    .map_err(Error::ValidationError)?;
+            }
+        }
+
+        #[cfg(feature = "async_predicate")]
+        if let Some(name) = self.missing_predicate(rule) {
+            return Err(Error::ValidationError(format!(
+                "Async predicate `{}` isn't registered",
+                name
+            )));
+        }
+
+        Ok(())
+    }
+
+    /// Same as `try_add_rule`, deserializing the rule with
+    /// `Rule::from_value_strict`
+    pub fn try_add_rule_strict(&mut self, rule: Value) -> Result<()> {
+        self.try_add_rule(Rule::from_value_strict(rule)?)
+    }
+
+    fn rules_count(&self) -> usize {
+        self.rules.len()
+            + self
+                .rule_groups
+                .iter()
+                .map(|group| group.rules.len())
+                .sum::<usize>()
     }
 
     pub fn add_rules(&mut self, rules: Vec<Rule>) {
-        self.rules.extend(rules)
+        let from = self.rules.len();
+        self.plans.extend(
+            rules
+                .iter()
+                .map(|rule| Some(RulePlan::lists_and_patterns(rule))),
+        );
+        self.rules.extend(rules);
+        self.index_rules(from);
+    }
+
+    pub fn add_rule_group(&mut self, group: RuleGroup) {
+        self.rule_groups.push(group)
     }
 
     pub fn load_rules(&mut self, rules: Vec<Rule>) {
+        self.plans = rules
+            .iter()
+            .map(|rule| Some(RulePlan::lists_and_patterns(rule)))
+            .collect();
         self.rules = rules;
+        self.tenants.clear();
+        self.reset_rule_index();
+    }
+
+    /// Enables or disables the rules, group members included, of the id,
+    /// see `Rule::enabled`. Returns whether there were any
+    pub fn set_rule_enabled(&mut self, id: &str, enabled: bool) -> bool {
+        let mut found = false;
+        for rule in self
+            .rules
+            .iter_mut()
+            .chain(self.rule_groups.iter_mut().flat_map(|g| &mut g.rules))
+            .filter(|rule| rule.id.as_deref() == Some(id))
+        {
+            rule.enabled = enabled;
+            found = true;
+        }
+        self.reset_rule_index();
+        found
     }
 
     pub fn clear(&mut self) {
         self.rules.clear();
+        self.plans.clear();
+        self.rule_groups.clear();
+        self.tenants.clear();
+        self.frequencies.clear();
+        self.reset_rule_index();
+    }
+
+    /// Indexes the rules by the top level facts they refer to, so `run` and
+    /// `evaluate` skip the rules whose facts are all missing, which
+    /// couldn't be met anyway. Worth it with many rules each reading a few
+    /// of many facts. Rules with expressions, scripts, async predicates or
+    /// `default`s are always evaluated, as are rule groups. Off by default
+    pub fn set_rule_index(&mut self, enable: bool) {
+        self.rule_index = None;
+        if enable {
+            self.reset_rule_index_with(RuleIndex::default());
+        }
+    }
+
+    fn reset_rule_index(&mut self) {
+        if self.rule_index.is_some() {
+            self.reset_rule_index_with(RuleIndex::default());
+        }
+    }
+
+    fn reset_rule_index_with(&mut self, index: RuleIndex) {
+        self.rule_index = Some(index);
+        self.index_rules(0);
+    }
+
+    /// Indexes the rules from index `from` on, when the rules are indexed
+    fn index_rules(&mut self, from: usize) {
+        if self.rule_index.is_none() {
+            return;
+        }
+
+        // disabled rules are left out, so never candidates
+        let empty = Value::Object(serde_json::Map::new());
+        let statuses: Vec<_> = (from..self.rules.len())
+            .filter(|&i| self.rules[i].enabled)
+            .map(|i| {
+                let plan = self.plans.get(i).and_then(Option::as_ref);
+                let rule_result =
+                    self.evaluate_rule(&self.rules[i], plan, &empty);
+                (i, rule_result.condition_result.status)
+            })
+            .collect();
+
+        if let Some(index) = &mut self.rule_index {
+            for (i, status) in statuses {
+                index.insert(i, &self.rules[i].conditions, status);
+            }
+        }
+    }
+
+    /// Registers a set of strings for `string_in_named_set` and
+    /// `string_not_in_named_set` constraints
+    pub fn register_set(&mut self, name: &str, set: HashSet<String>) {
+        self.sets.strings.insert(name.to_string(), set);
+    }
+
+    /// Registers a set of integers for `int_in_named_set` and
+    /// `int_not_in_named_set` constraints
+    pub fn register_int_set(&mut self, name: &str, set: HashSet<i64>) {
+        self.sets.ints.insert(name.to_string(), set);
+    }
+
+    /// Sets the value of a variable, which constraints refer to with
+    /// `{ "$var": name }` as their value. Conditions referring to a variable
+    /// that isn't set are `Unknown`. The templates of the coalescence groups,
+    /// of `facts_to_add` and of the events' params see the variables under
+    /// `vars`, unless a fact is named so. The events themselves don't
+    /// receive them, so they're never sent
+    pub fn set_variable(&mut self, name: &str, value: Value) {
+        self.variables.insert(name.to_string(), value);
+    }
+
+    /// Replaces a registered set of strings
+    pub fn update_set(
+        &mut self,
+        name: &str,
+        set: HashSet<String>,
+    ) -> Result<()> {
+        match self.sets.strings.get_mut(name) {
+            Some(s) => {
+                *s = set;
+                Ok(())
+            }
+            None => Err(Error::ValidationError(format!(
+                "Named set `{}` isn't registered",
+                name
+            ))),
+        }
+    }
+
+    /// Replaces a registered set of integers
+    pub fn update_int_set(
+        &mut self,
+        name: &str,
+        set: HashSet<i64>,
+    ) -> Result<()> {
+        match self.sets.ints.get_mut(name) {
+            Some(s) => {
+                *s = set;
+                Ok(())
+            }
+            None => Err(Error::ValidationError(format!(
+                "Named set `{}` isn't registered",
+                name
+            ))),
+        }
     }
 
     #[cfg(feature = "eval")]
-    pub fn add_function(&mut self, fname: &str, f: fn(Map) -> bool) {
+    pub fn add_function(
+        &mut self,
+        fname: &str,
+        f: impl Fn(Map) -> bool + Send + Sync + 'static,
+    ) {
         self.rhai_engine.register_fn(fname, f);
     }
 
-    pub fn add_event(&mut self, f: Rc<RwLock<dyn EventTrait>>) {
+    /// Exposes the top level facts to `expr` conditions as variables of
+    /// their own, besides `facts`, so `age > 20` works as `facts.age > 20`
+    /// does, see `Condition::Eval`. They are constants, so it's off by
+    /// default for expressions assigning to variables of the same names
+    #[cfg(feature = "eval")]
+    pub fn set_eval_flatten_scope(&mut self, flatten_scope: bool) {
+        self.eval_flatten_scope = flatten_scope;
+    }
+
+    /// Registers a function whose `Err` fails the evaluation of the
+    /// expression calling it, making that condition `Unknown` instead of
+    /// `NotMet`, with the `Err` as the `error` of its result
+    #[cfg(feature = "eval")]
+    pub fn add_fallible_function(
+        &mut self,
+        fname: &str,
+        f: impl Fn(Map) -> std::result::Result<bool, String> + Send + Sync + 'static,
+    ) {
+        self.rhai_engine.register_fn(
+            fname,
+            move |p: Map| -> std::result::Result<bool, Box<EvalAltResult>> {
+                f(p).map_err(|e| {
+                    EvalAltResult::ErrorRuntime(e.into(), Position::NONE).into()
+                })
+            },
+        );
+    }
+
+    /// Dispatches at most `max` events of `event_type` `per` period across
+    /// runs, whatever rule or group they come from. Events over the budget
+    /// are suppressed, after coalescence, and marked `rate_limited` in the
+    /// results
+    pub fn set_rate_limit(
+        &mut self,
+        event_type: &str,
+        max: u32,
+        per: Duration,
+    ) {
+        self.rate_limits
+            .retain(|key, _| tenant_event_type(key) != Some(event_type));
+        self.rate_limits
+            .insert(event_type.to_string(), TokenBucket::new(max, per));
+    }
+
+    /// Sets what a run does when an event fails to dispatch, `FailFast` by
+    /// default
+    pub fn set_error_mode(&mut self, error_mode: ErrorMode) {
+        self.error_mode = error_mode;
+    }
+
+    /// Skips the events of a rule's sequence, those with a `dispatch_order`,
+    /// after the first one failing in `ErrorMode::BestEffort`, e.g. not to
+    /// comment on a ticket that wasn't created. They're marked with the
+    /// error. The sequence is dispatched first, one event after the other in
+    /// ascending order, then the other events, concurrently unless
+    /// `Engine::set_sequential_event_dispatch`. Off by default
+    pub fn set_abort_sequence_on_error(&mut self, abort: bool) {
+        self.abort_sequence_on_error = abort;
+    }
+
+    /// Dispatches the events of a rule without a `dispatch_order` one after
+    /// the other, in declaration order, after its sequence, rather than
+    /// concurrently. Off by default
+    pub fn set_sequential_event_dispatch(&mut self, sequential: bool) {
+        self.sequential_event_dispatch = sequential;
+    }
+
+    /// Evaluates every condition, rather than stopping at the first child
+    /// deciding the status of an `and`, `or` or `at_least` node, and
+    /// annotates the condition results with whether they would have been
+    /// evaluated otherwise, and in which order they were. Off by default
+    pub fn set_trace(&mut self, trace: bool) {
+        self.trace = trace;
+    }
+
+    /// Collects the elements of the array facts the leaves matched, e.g.
+    /// which devices `any_match` found untrusted, in the `matched_values`
+    /// of their results, at most `Limits::max_matched_values` per leaf. The
+    /// templates of the rule's events see them by the field of their leaf
+    /// under `_matched_values`, as in `{{ _matched_values.devices }}`. Off
+    /// by default
+    pub fn set_collect_matches(&mut self, collect_matches: bool) {
+        self.collect_matches = collect_matches;
+    }
+
+    /// Lets the conditions of `any_match` and `none_match` still reach the
+    /// whole facts from the element they evaluate, as its member of this
+    /// key, e.g. `_root` for `{ "field": "_root/country", ... }` or
+    /// `{{ _root.country }}` in a templated value. Elements that aren't
+    /// objects, or have a member of that key, are left as they are. Each
+    /// element evaluated gets a copy of the facts, so it's off by default
+    pub fn set_element_alias(&mut self, alias: Option<String>) {
+        self.element_alias = alias;
+    }
+
+    /// Wraps facts that aren't an object, e.g. an array of orders, as the
+    /// member of this key of an object the rules are evaluated against, so
+    /// that `{ "field": "items", "operator": "any_match", ... }` reaches
+    /// them. Objects are left as they are
+    pub fn set_root_wrap_key(&mut self, key: Option<String>) {
+        self.root_wrap_key = key;
+    }
+
+    /// Fails the evaluations and runs against facts that aren't an object
+    /// with `Error::InvalidFactsRoot`, unless they are wrapped, see
+    /// `Engine::set_root_wrap_key`. Off by default, their fields being
+    /// missing
+    pub fn set_strict_facts_root(&mut self, strict: bool) {
+        self.strict_facts_root = strict;
+    }
+
+    /// The facts the rules are evaluated against, see
+    /// `Engine::set_root_wrap_key` and `Engine::set_strict_facts_root`
+    pub(crate) fn facts_root(&self, facts: Value) -> Result<Value> {
+        if facts.is_object() {
+            return Ok(facts);
+        }
+
+        match &self.root_wrap_key {
+            Some(key) => {
+                let mut root = serde_json::Map::new();
+                root.insert(key.clone(), facts);
+                Ok(Value::Object(root))
+            }
+            None if self.strict_facts_root => Err(Error::InvalidFactsRoot(
+                match facts {
+                    Value::Null => "null",
+                    Value::Bool(_) => "boolean",
+                    Value::Number(_) => "number",
+                    Value::String(_) => "string",
+                    Value::Array(_) => "array",
+                    Value::Object(_) => "object",
+                }
+                .to_string(),
+            )),
+            None => Ok(facts),
+        }
+    }
+
+    /// Bounds the rules `try_add_rule` accepts, the facts `run` and
+    /// `evaluate` accept, and the events `run` dispatches. Events over the
+    /// bounds are skipped and marked `too_large` in the results
+    pub fn set_limits(&mut self, limits: Limits) {
+        self.limits = limits;
+    }
+
+    /// Replaces the clock used by time relative constraints and exposed to
+    /// `expr` conditions as `now_ts` (epoch seconds) and `now_iso`. It's
+    /// called once per rule evaluation, and defaults to `Utc::now`
+    pub fn set_now_provider(&mut self, now: NowProvider) {
+        self.now = now;
+    }
+
+    /// Sets the `app_data` merged into every `post_to_callback_url` event
+    /// before it's posted. Keys set by the event itself win, objects present
+    /// on both sides are merged recursively. The templates of the event,
+    /// its `callback_url` included, see it under `app_data`
+    #[cfg(feature = "callback")]
+    pub fn set_default_app_data(
+        &mut self,
+        app_data: serde_json::Map<String, Value>,
+    ) {
+        self.default_app_data = app_data;
+    }
+
+    /// Restricts where `post_to_callback_url` events may post to. Their
+    /// `callback_url` is checked once rendered against the facts, and the
+    /// events it's refused for aren't dispatched, the reason being recorded
+    /// as their `error` in the results. Unless the policy allows any url,
+    /// the requests don't follow redirects, and are sent to the addresses
+    /// the host was checked to resolve to rather than resolving it again
+    #[cfg(feature = "callback")]
+    pub fn set_callback_url_policy(&mut self, policy: CallbackUrlPolicy) {
+        *self.callback_url_policy.write().unwrap() = policy;
+    }
+
+    /// Runs the interceptor on every event that isn't coalesced or rate
+    /// limited, right before it's dispatched. Interceptors run in the order
+    /// they were added, and the first one to drop an event stops it
+    pub fn add_event_interceptor(
+        &mut self,
+        interceptor: Arc<dyn EventInterceptor>,
+    ) {
+        self.interceptors.push(interceptor);
+    }
+
+    /// Sets the hooks called around every `run` and `evaluate`: `before`
+    /// with the facts, serialized and within the `Limits`, and `after` with
+    /// the facts and the met rules, once their events were dispatched, or
+    /// failed to. A panicking hook doesn't stop the run
+    pub fn set_run_hooks(&mut self, hooks: RunHooks) {
+        self.run_hooks = hooks;
+    }
+
+    /// Calls the `before` run hook, if any
+    fn before_run(&self, facts: &Value) {
+        if let Some(before) = &self.run_hooks.before {
+            call_hook(|| before(facts));
+        }
+    }
+
+    /// Calls the `after` run hook, if any
+    fn after_run(&self, facts: &Value, rule_results: &[RuleResult]) {
+        if let Some(after) = &self.run_hooks.after {
+            call_hook(|| after(facts, rule_results));
+        }
+    }
+
+    pub fn add_event(&mut self, f: Arc<RwLock<dyn EventTrait>>) {
         let key = f.read().unwrap().get_type().to_string();
         self.events.insert(key, f);
     }
 
-    pub async fn run<T: Serialize>(
+    fn evaluate_rule(
+        &self,
+        rule: &Rule,
+        plan: Option<&RulePlan>,
+        facts: &Value,
+    ) -> RuleResult {
+        self.evaluate_rule_with(rule, plan, facts, self.trace, None)
+    }
+
+    fn evaluate_rule_with(
+        &self,
+        rule: &Rule,
+        plan: Option<&RulePlan>,
+        facts: &Value,
+        trace: bool,
+        lookups: Option<&LookupCache>,
+    ) -> RuleResult {
+        let evaluated_at = now_millis();
+        let start = Instant::now();
+
+        let mut rule_result = rule.check_value_with(
+            facts,
+            &EvalContext {
+                #[cfg(feature = "eval")]
+                rhai_engine: &self.rhai_engine,
+                #[cfg(feature = "eval")]
+                flatten_scope: self.eval_flatten_scope,
+                sets: &self.sets,
+                #[cfg(feature = "regex")]
+                captures: &std::cell::RefCell::default(),
+                variables: &self.variables,
+                now: (self.now)(),
+                results: &HashMap::new(),
+                short_circuit: true,
+                trace,
+                #[cfg(feature = "async_predicate")]
+                predicates: &self.predicate_results,
+                plan,
+                frequencies: Some(&self.frequencies),
+                frequency_run: self.frequency_run.as_ref(),
+                max_matched_values: self.collect_matches.then(|| {
+                    self.limits.max_matched_values.unwrap_or(usize::MAX)
+                }),
+                element_root: self
+                    .element_alias
+                    .as_deref()
+                    .map(|alias| (alias, facts)),
+                lookups,
+            },
+        );
+
+        rule_result.evaluated_at = evaluated_at;
+        rule_result.duration_micros = start.elapsed().as_micros() as u64;
+        rule_result
+    }
+
+    /// The status of the rule's conditions, without building its result,
+    /// for the rules whose result is only kept when met
+    fn rule_status(
+        &self,
+        rule: &Rule,
+        plan: Option<&RulePlan>,
+        facts: &Value,
+        lookups: Option<&LookupCache>,
+    ) -> Status {
+        rule.conditions.check_status_with(
+            facts,
+            &EvalContext {
+                #[cfg(feature = "eval")]
+                rhai_engine: &self.rhai_engine,
+                #[cfg(feature = "eval")]
+                flatten_scope: self.eval_flatten_scope,
+                sets: &self.sets,
+                #[cfg(feature = "regex")]
+                captures: &std::cell::RefCell::default(),
+                variables: &self.variables,
+                now: (self.now)(),
+                results: &HashMap::new(),
+                short_circuit: true,
+                trace: false,
+                #[cfg(feature = "async_predicate")]
+                predicates: &self.predicate_results,
+                plan,
+                frequencies: Some(&self.frequencies),
+                frequency_run: self.frequency_run.as_ref(),
+                max_matched_values: None,
+                element_root: self
+                    .element_alias
+                    .as_deref()
+                    .map(|alias| (alias, facts)),
+                lookups,
+            },
+        )
+    }
+
+    /// Validates a single event, returning the handler of its type
+    fn event_handler(
+        &self,
+        event: &Event,
+    ) -> Result<&Arc<RwLock<dyn EventTrait>>> {
+        let e = self.events.get(&event.ty).ok_or_else(|| {
+            Error::EventError("Event type doesn't exist".to_string())
+        })?;
+        EscapeMode::from_params(&event.params).map_err(Error::EventError)?;
+        NumberFormat::from_params(&event.params).map_err(Error::EventError)?;
+        event
+            .check_reason(self.allowed_severities.as_ref())
+            .map_err(Error::EventError)?;
+
+        e.read()
+            .unwrap()
+            .validate(&event.params)
+            .map_err(Error::EventError)?;
+        Ok(e)
+    }
+
+    /// The gate of the handler of an event type, see `handler_gates`
+    #[cfg(feature = "detached")]
+    fn handler_gate(&self, ty: &str) -> Arc<tokio::sync::Mutex<()>> {
+        self.handler_gates
+            .lock()
+            .unwrap()
+            .entry(ty.to_string())
+            .or_default()
+            .clone()
+    }
+
+    /// Validates and triggers a single event
+    // an event's lock is only ever taken for writing here, by the engine
+    // that owns it through `&mut self`, or by its detached tasks, both
+    // behind the gate of its type
+    #[allow(clippy::await_holding_lock)]
+    async fn trigger_event(&self, event: &Event, facts: &Value) -> Result<()> {
+        #[cfg(feature = "detached")]
+        let gate = self.handler_gate(&event.ty);
+        #[cfg(feature = "detached")]
+        let _open = gate.lock().await;
+        let e = self.event_handler(event)?;
+        e.write().unwrap().trigger(&event.params, facts).await
+    }
+
+    /// The engine's variables under `vars`, for the template context of the
+    /// events it dispatches, see `with_template_context`
+    fn vars_context(&self) -> serde_json::Map<String, Value> {
+        let mut context = serde_json::Map::new();
+        if !self.variables.is_empty() {
+            context.insert(
+                "vars".to_string(),
+                self.variables
+                    .iter()
+                    .map(|(k, v)| (k.clone(), v.clone()))
+                    .collect(),
+            );
+        }
+        context
+    }
+
+    /// Drops the events suppressed by their coalescence group, skips the
+    /// muted ones, delays the delayed ones and delivers the remaining ones:
+    /// the sequence first, one event after the other in ascending
+    /// `dispatch_order`, then the other events, concurrently unless
+    /// `Engine::set_sequential_event_dispatch`. In `FailFast` mode, the
+    /// first event failing stops the dispatch, and its type is returned
+    /// along with the error, the events triggered concurrently with it
+    /// having been triggered all the same
+    #[allow(unused_variables)]
+    async fn dispatch_events(
         &mut self,
-        facts: &T,
-    ) -> Result<Vec<RuleResult>> {
-        let facts = to_value(facts)?;
-        let mut met_rule_results: Vec<RuleResult> = self
-            .rules
-            .iter()
-            .map(|rule| {
-                rule.check_value(
-                    &facts,
-                    #[cfg(feature = "eval")]
-                    &self.rhai_engine,
-                )
-            })
-            .filter(|rule_result| {
-                rule_result.condition_result.status == Status::Met
-            })
+        tenant: Option<&str>,
+        key: Option<RuleKey>,
+        rule_id: Option<&str>,
+        events: &mut Vec<CoalescenceEvent>,
+        facts: &Value,
+        #[cfg(feature = "detached")] mut detached: Option<
+            &mut Vec<DetachedTrigger>,
+        >,
+    ) -> std::result::Result<(), (String, Error)> {
+        // filter the events, the first one of a coalescence group claiming
+        // it. The group is only recorded once its event was triggered, so
+        // neither an event held back or failing, nor a run dropped before
+        // dispatching it, suppresses the next ones
+        let mut claimed = HashSet::new();
+        let mut groups = Vec::new();
+        events.retain(|event| {
+            let group = match (&event.coalescence_group, event.coalescence) {
+                (Some(coalescence_group), Some(coalescence)) => Some((
+                    tenant_key(tenant, coalescence_group).into_owned(),
+                    coalescence,
+                )),
+                _ => None,
+            };
+            if let Some((group, _)) = &group {
+                if self.coalescences.lock().unwrap().contains_key(group)
+                    || !claimed.insert(group.clone())
+                {
+                    return false;
+                }
+            }
+
+            groups.push(group);
+            true
+        });
+
+        // the sequence, ties in declaration order, then the other events,
+        // which the detached task triggers one after the other anyway
+        let mut serial: Vec<usize> = (0..events.len())
+            .filter(|&i| events[i].dispatch_order.is_some())
             .collect();
+        serial.sort_by_key(|&i| events[i].dispatch_order);
+        let unordered =
+            (0..events.len()).filter(|&i| events[i].dispatch_order.is_none());
+        #[cfg(feature = "detached")]
+        let concurrent = !self.sequential_event_dispatch && detached.is_none();
+        #[cfg(not(feature = "detached"))]
+        let concurrent = !self.sequential_event_dispatch;
+        let concurrent: Vec<usize> = if concurrent {
+            unordered.collect()
+        } else {
+            serial.extend(unordered);
+            Vec::new()
+        };
+
+        // the events over the limit are the last ones to be dispatched
+        let max_events = self.limits.max_events_per_rule_per_run;
+        let mut dispatched = 0;
+
+        #[cfg(feature = "detached")]
+        let sequence = detached.as_ref().map(|triggers| triggers.len());
+        let mut failed_in_sequence: Option<String> = None;
+        for i in serial {
+            let event = &mut events[i];
+            #[cfg(feature = "detached")]
+            let queued = detached.as_ref().map(|triggers| triggers.len());
+            if max_events.is_some_and(|max| dispatched >= max) {
+                event.too_large = true;
+            } else if let (Some(failed), Some(_)) =
+                (&failed_in_sequence, event.dispatch_order)
+            {
+                event.error = Some(format!(
+                    "Skipped after `{}` failed before it",
+                    failed
+                ));
+            } else {
+                dispatched += 1;
+                let triggered = self
+                    .dispatch_event(
+                        tenant,
+                        key,
+                        rule_id,
+                        event,
+                        facts,
+                        #[cfg(feature = "detached")]
+                        detached.as_deref_mut(),
+                    )
+                    .await?;
+                let group = groups[i].take().filter(|_| triggered);
+                // handed to the detached task, which claims it once sent
+                #[cfg(feature = "detached")]
+                let group = match (detached.as_deref_mut(), queued) {
+                    (Some(triggers), Some(queued)) => {
+                        if let Some(trigger) = triggers.get_mut(queued) {
+                            trigger.group = group;
+                        }
+                        None
+                    }
+                    _ => group,
+                };
+                if let Some((group, coalescence)) = group {
+                    self.coalescences
+                        .lock()
+                        .unwrap()
+                        .insert(group, (Instant::now(), coalescence));
+                }
+            }
+
+            // the events of a sequence are triggered by the detached task,
+            // which skips those after a failed one
+            #[cfg(feature = "detached")]
+            if let (Some(triggers), Some(queued), Some(_)) =
+                (detached.as_deref_mut(), queued, event.dispatch_order)
+            {
+                if let Some(trigger) = triggers.get_mut(queued) {
+                    trigger.sequence = sequence;
+                }
+            }
+
+            if self.abort_sequence_on_error
+                && event.dispatch_order.is_some()
+                && event.error.is_some()
+                && failed_in_sequence.is_none()
+            {
+                failed_in_sequence = Some(event.event.ty.clone());
+            }
+        }
 
-        self.coalescences.retain(|_k, (start, expiration)| {
-            start.elapsed().as_secs() < *expiration
+        let mut ready = Vec::new();
+        for i in concurrent {
+            let event = &mut events[i];
+            if max_events.is_some_and(|max| dispatched >= max) {
+                event.too_large = true;
+                continue;
+            }
+            dispatched += 1;
+            if !self.hold_event(tenant, key, rule_id, event, facts)
+                && self.prepare_event(tenant, rule_id, event, facts).await
+            {
+                ready.push(i);
+            }
+        }
+
+        // a handler being locked while it triggers, the events of a type are
+        // triggered one after the other, the types concurrently
+        let (engine, pending) = (&*self, &*events);
+        let mut by_type: Vec<(&str, Vec<usize>)> = Vec::new();
+        for &i in &ready {
+            let ty = pending[i].event.ty.as_str();
+            match by_type.iter_mut().find(|(t, _)| *t == ty) {
+                Some((_, is)) => is.push(i),
+                None => by_type.push((ty, vec![i])),
+            }
+        }
+        let mut triggered: Vec<(usize, Result<()>)> =
+            join_all(by_type.into_iter().map(|(_, is)| async move {
+                let mut triggered = Vec::with_capacity(is.len());
+                for i in is {
+                    let event = &pending[i].event;
+                    let outcome = engine.trigger_event(event, facts).await;
+                    let failed = outcome.is_err();
+                    triggered.push((i, outcome));
+                    if failed && engine.error_mode == ErrorMode::FailFast {
+                        break;
+                    }
+                }
+                triggered
+            }))
+            .await
+            .into_iter()
+            .flatten()
+            .collect();
+        triggered.sort_by_key(|(i, _)| *i);
+
+        let mut failed = None;
+        for (i, triggered) in triggered {
+            let event = &mut events[i];
+            let ok = triggered.is_ok();
+            if let Err(e) = self.settle_event(rule_id, event, facts, triggered)
+            {
+                failed.get_or_insert(e);
+            } else if ok {
+                if let Some((group, coalescence)) = groups[i].take() {
+                    self.coalescences
+                        .lock()
+                        .unwrap()
+                        .insert(group, (Instant::now(), coalescence));
+                }
+            }
+        }
+
+        failed.map_or(Ok(()), Err)
+    }
+
+    /// Marks the event if it's muted, and schedules it if it's delayed.
+    /// Returns whether it was held back either way
+    #[allow(unused_variables)]
+    fn hold_event(
+        &mut self,
+        tenant: Option<&str>,
+        key: Option<RuleKey>,
+        rule_id: Option<&str>,
+        event: &mut CoalescenceEvent,
+        facts: &Value,
+    ) -> bool {
+        event.mute_reason = self.mute_reason(key, rule_id, &event.event.ty);
+        if event.mute_reason.is_some() {
+            event.muted = true;
+            return true;
+        }
+
+        #[cfg(feature = "delay")]
+        if let Some(delay_secs) = event.delay_secs {
+            event.delayed_id = Some(
+                self.schedule(tenant, key, rule_id, event, facts, delay_secs),
+            );
+            return true;
+        }
+
+        false
+    }
+
+    /// Skips the event if it's muted, schedules it if it's delayed, and
+    /// delivers it otherwise. Returns whether it was triggered
+    async fn dispatch_event(
+        &mut self,
+        tenant: Option<&str>,
+        key: Option<RuleKey>,
+        rule_id: Option<&str>,
+        event: &mut CoalescenceEvent,
+        facts: &Value,
+        #[cfg(feature = "detached")] detached: Option<
+            &mut Vec<DetachedTrigger>,
+        >,
+    ) -> std::result::Result<bool, (String, Error)> {
+        if self.hold_event(tenant, key, rule_id, event, facts) {
+            return Ok(false);
+        }
+
+        self.deliver_event(
+            tenant,
+            rule_id,
+            event,
+            facts,
+            #[cfg(feature = "detached")]
+            detached,
+        )
+        .await
+    }
+
+    /// Rate limits, checks and triggers a single event. Returns whether it
+    /// was triggered, handing it to the detached task counting as such
+    async fn deliver_event(
+        &mut self,
+        tenant: Option<&str>,
+        rule_id: Option<&str>,
+        event: &mut CoalescenceEvent,
+        facts: &Value,
+        #[cfg(feature = "detached")] detached: Option<
+            &mut Vec<DetachedTrigger>,
+        >,
+    ) -> std::result::Result<bool, (String, Error)> {
+        if !self.prepare_event(tenant, rule_id, event, facts).await {
+            return Ok(false);
+        }
+
+        #[cfg(feature = "detached")]
+        let triggered = match detached {
+            Some(triggers) => {
+                // validated behind the gate, a task may be triggering it
+                let gate = self.handler_gate(&event.event.ty);
+                let open = gate.lock().await;
+                let handler = self.event_handler(&event.event).cloned();
+                drop(open);
+                handler.map(|handler| {
+                    triggers.push(DetachedTrigger {
+                        rule_id: rule_id.map(ToOwned::to_owned),
+                        handler,
+                        gate,
+                        event: event.event.clone(),
+                        facts: facts.clone(),
+                        template_context: template_context(),
+                        sequence: None,
+                        group: None,
+                    })
+                })
+            }
+            None => self.trigger_event(&event.event, facts).await,
+        };
+        #[cfg(not(feature = "detached"))]
+        let triggered = self.trigger_event(&event.event, facts).await;
+
+        let failed = triggered.is_err();
+        self.settle_event(rule_id, event, facts, triggered)
+            .map(|()| !failed)
+    }
+
+    /// Rate limits the event, runs the interceptors and checks it against
+    /// the callback url policy and the limits. Returns whether it's to be
+    /// triggered, the event being marked with why not otherwise
+    #[allow(unused_variables)]
+    async fn prepare_event(
+        &mut self,
+        tenant: Option<&str>,
+        rule_id: Option<&str>,
+        event: &mut CoalescenceEvent,
+        facts: &Value,
+    ) -> bool {
+        event.rate_limited = self
+            .rate_limit(tenant, &event.event.ty)
+            .is_some_and(|bucket| !bucket.try_take());
+        if event.rate_limited {
+            return false;
+        }
+
+        event.event.render_reason(facts);
+
+        for interceptor in &self.interceptors {
+            let decision = interceptor
+                .before_dispatch(
+                    &mut event.event,
+                    facts,
+                    rule_id.unwrap_or_default(),
+                )
+                .await;
+            if let InterceptDecision::Drop(reason) = decision {
+                event.dropped = Some(reason);
+                return false;
+            }
+        }
+
+        // merged ahead of the url policy, the url may be rendered from it
+        #[cfg(feature = "callback")]
+        if event.event.ty == POST_CALLBACK_TYPE
+            && !self.default_app_data.is_empty()
+        {
+            let mut app_data = Value::Object(self.default_app_data.clone());
+            if let Some(overrides) = event.event.params.get("app_data") {
+                deep_merge(&mut app_data, overrides);
+            }
+            event.event.params.insert("app_data".to_string(), app_data);
+        }
+
+        #[cfg(feature = "callback")]
+        if event.event.ty == POST_CALLBACK_TYPE {
+            resolve_idempotency_key(
+                &mut event.event.params,
+                facts,
+                rule_id,
+                &self.run_nonce,
+            );
+            if let Some(url) = render_callback_url(&event.event.params, facts) {
+                let policy = self.callback_url_policy.read().unwrap().clone();
+                if let Err(e) = policy.check(&url).await {
+                    event.error = Some(e);
+                    return false;
+                }
+            }
+        }
+
+        if !self.limits.check_messages(&mut event.event.params, facts) {
+            event.too_large = true;
+            return false;
+        }
+
+        #[cfg(feature = "callback")]
+        if event.event.ty == POST_CALLBACK_TYPE
+            && !self
+                .limits
+                .check_callback_payload(&event.event.params, facts)
+        {
+            event.too_large = true;
+            return false;
+        }
+
+        // nobody listening isn't an error
+        #[cfg(feature = "broadcast")]
+        let _ = self.broadcast.send(EventEnvelope {
+            rule_id: rule_id.map(ToOwned::to_owned),
+            event: Event {
+                ty: event.event.ty.clone(),
+                params: render_params(&event.event.params, facts),
+            },
+            timestamp: now_millis(),
         });
 
-        for rule_result in met_rule_results.iter_mut() {
-            // filter the events
-            let mut cole = self.coalescences.clone();
-            rule_result.events.retain(|event| {
-                if let (Some(coalescence_group), Some(coalescence)) =
-                    (&event.coalescence_group, event.coalescence)
+        true
+    }
+
+    /// Hands a failed trigger to the dead letters, then marks the event
+    /// with the error in `BestEffort` mode, or returns it in `FailFast` mode
+    fn settle_event(
+        &mut self,
+        rule_id: Option<&str>,
+        event: &mut CoalescenceEvent,
+        facts: &Value,
+        triggered: Result<()>,
+    ) -> std::result::Result<(), (String, Error)> {
+        if let Err(e) = triggered {
+            self.dead_letter(rule_id, &event.event, facts, &e);
+            match self.error_mode {
+                ErrorMode::BestEffort => event.error = Some(e.to_string()),
+                ErrorMode::FailFast => {
+                    return Err((event.event.ty.clone(), e));
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Evaluates the rules against the facts and returns the met ones,
+    /// without dispatching any event or touching the coalescence state, so
+    /// it can be shared between threads behind an `Arc<Engine>`
+    pub fn evaluate<T: Serialize>(&self, facts: &T) -> Result<Vec<RuleResult>> {
+        self.evaluate_facts(to_value(facts)?)
+    }
+
+    fn evaluate_facts(&self, facts: Value) -> Result<Vec<RuleResult>> {
+        let facts = self.facts_root(facts)?;
+        self.limits.check_facts(&facts)?;
+        self.before_run(&facts);
+        let rule_results: Vec<_> = self
+            .evaluate_value(&facts, None)
+            .met_rule_results
+            .into_iter()
+            .map(|(_, rule_result)| rule_result)
+            .collect();
+        self.after_run(&facts, &rule_results);
+        Ok(rule_results)
+    }
+
+    /// Evaluates a condition that belongs to no rule against the facts, as
+    /// the engine evaluates its rules' conditions: with its functions, named
+    /// sets, variables, clock, `Engine::set_eval_flatten_scope` and
+    /// `Engine::set_element_alias`. Frequency nodes see the engine's history
+    /// without adding to it, and async predicates, only awaited by `run`,
+    /// are `Unknown`
+    pub fn check_condition(
+        &self,
+        condition: &Condition,
+        facts: &Value,
+    ) -> ConditionResult {
+        condition.check_value_with(
+            facts,
+            &EvalContext {
+                #[cfg(feature = "eval")]
+                rhai_engine: &self.rhai_engine,
+                #[cfg(feature = "eval")]
+                flatten_scope: self.eval_flatten_scope,
+                sets: &self.sets,
+                #[cfg(feature = "regex")]
+                captures: &std::cell::RefCell::default(),
+                variables: &self.variables,
+                now: (self.now)(),
+                results: &HashMap::new(),
+                short_circuit: true,
+                trace: self.trace,
+                #[cfg(feature = "async_predicate")]
+                predicates: &PredicateResults::new(),
+                plan: None,
+                frequencies: Some(&self.frequencies),
+                frequency_run: None,
+                max_matched_values: self.collect_matches.then(|| {
+                    self.limits.max_matched_values.unwrap_or(usize::MAX)
+                }),
+                element_root: self
+                    .element_alias
+                    .as_deref()
+                    .map(|alias| (alias, facts)),
+                lookups: None,
+            },
+        )
+    }
+
+    fn rule(&self, key: RuleKey) -> &Rule {
+        match key {
+            (None, i) => &self.rules[i],
+            (Some(group), i) => &self.rule_groups[group].rules[i],
+        }
+    }
+
+    /// The met rules of the tenant, or the rules without one, along with
+    /// their keys, the `OncePerRun` groups with at least one met member, the
+    /// number of rules evaluated, and the facts once patched by the met
+    /// rules. Groups have no tenant
+    fn evaluate_value(
+        &self,
+        facts: &Value,
+        tenant: Option<&str>,
+    ) -> Evaluation {
+        let in_scope: Vec<_> = self
+            .rules_in_scope(tenant)
+            .into_iter()
+            .filter(|&i| self.rules[i].enabled)
+            .collect();
+        // the index picks the candidates from the facts before any patch
+        let patching = in_scope
+            .iter()
+            .any(|&i| apply_json_patch::has_patch(&self.rules[i].events));
+        let candidates: Vec<_> = match &self.rule_index {
+            Some(index) if !patching => index
+                .candidates(facts, self.rules.len())
+                .into_iter()
+                .filter(|i| in_scope.binary_search(i).is_ok())
+                .collect(),
+            _ => in_scope,
+        };
+        let rule_groups = match tenant {
+            Some(_) => &[][..],
+            None => &self.rule_groups[..],
+        };
+        let lookups = LookupCache::new(
+            facts,
+            candidates
+                .iter()
+                .map(|&i| &self.rules[i])
+                .chain(rule_groups.iter().flat_map(|group| &group.rules)),
+        );
+        let lookups = Some(&lookups);
+        let mut facts = Cow::Borrowed(facts);
+        let mut rules_evaluated = 0;
+        // only the met rules' results are built, the status of the others
+        // is enough to leave them out. The clock may have moved in between
+        let mut met_rule_results: Vec<(RuleKey, RuleResult)> = Vec::new();
+        for i in candidates {
+            let rule = &self.rules[i];
+            if !rule.sampled_in(&facts) {
+                continue;
+            }
+            rules_evaluated += 1;
+            let plan = self.plans.get(i).and_then(Option::as_ref);
+            if self.rule_status(rule, plan, &facts, lookups) != Status::Met {
+                continue;
+            }
+            let mut rule_result = self
+                .evaluate_rule_with(rule, plan, &facts, self.trace, lookups);
+            if rule_result.condition_result.status == Status::Met {
+                self.apply_patches(rule, &mut rule_result, &mut facts);
+                met_rule_results.push(((None, i), rule_result));
+            }
+        }
+
+        let mut group_results = Vec::new();
+        for (g, group) in rule_groups.iter().enumerate() {
+            let mut matched_rules = Vec::new();
+            let mut member_results = Vec::new();
+            for (i, rule) in group.rules.iter().enumerate() {
+                if !rule.enabled || !rule.sampled_in(&facts) {
+                    continue;
+                }
+                rules_evaluated += 1;
+                if self.rule_status(rule, None, &facts, lookups) != Status::Met
                 {
-                    if cole.contains_key(coalescence_group) {
-                        return false;
-                    } else {
-                        cole.insert(
-                            coalescence_group.clone(),
-                            (Instant::now(), coalescence),
-                        );
+                    continue;
+                }
+                let mut rule_result = self.evaluate_rule_with(
+                    rule, None, &facts, self.trace, lookups,
+                );
+                if rule_result.condition_result.status == Status::Met {
+                    self.apply_patches(rule, &mut rule_result, &mut facts);
+                    matched_rules
+                        .push(rule.id.clone().unwrap_or_else(|| i.to_string()));
+                    member_results.push(((Some(g), i), rule_result));
+                }
+            }
+
+            if member_results.is_empty() {
+                continue;
+            }
+
+            let group_facts = rule::with_vars(&facts, &self.variables);
+
+            match group.emit {
+                GroupEmit::PerRule => {
+                    for (_, rule_result) in &mut member_results {
+                        rule_result.events.extend(render_events(
+                            &group.events,
+                            &group_facts,
+                            None,
+                        ));
                     }
                 }
+                GroupEmit::OncePerRun => group_results.push(GroupResult {
+                    id: group.id.clone(),
+                    matched_rules,
+                    events: render_events(&group.events, &group_facts, None),
+                }),
+            }
+
+            met_rule_results.extend(member_results);
+        }
+
+        Evaluation {
+            met_rule_results,
+            group_results,
+            rules_evaluated,
+            patched_facts: match facts {
+                Cow::Owned(facts) => Some(facts),
+                Cow::Borrowed(_) => None,
+            },
+        }
+    }
 
-                true
+    /// Patches the facts with the `apply_json_patch` events of the met rule,
+    /// recording the outcome of each patch in its result
+    fn apply_patches(
+        &self,
+        rule: &Rule,
+        rule_result: &mut RuleResult,
+        facts: &mut Cow<Value>,
+    ) {
+        for event in &rule.events {
+            if event.event.ty != apply_json_patch::EVENT_TYPE {
+                continue;
+            }
+            let patched = apply_json_patch::apply(
+                &event.event.params,
+                facts,
+                &rule::with_vars(facts, &self.variables),
+            );
+            rule_result.patches.push(match patched {
+                Ok(patched) => {
+                    *facts = Cow::Owned(patched);
+                    PatchOutcome {
+                        applied: true,
+                        error: None,
+                    }
+                }
+                Err(e) => PatchOutcome {
+                    applied: false,
+                    error: Some(e),
+                },
             });
+        }
+    }
 
-            self.coalescences = cole;
-
-            // TODO run all the async events in parallel
-            // run the events
-            for event in &rule_result.events {
-                let e =
-                    self.events.get_mut(&event.event.ty).ok_or_else(|| {
-                        Error::EventError(
-                            "Event type doesn't exist".to_string(),
-                        )
-                    })?;
-
-                e.read()
-                    .unwrap()
-                    .validate(&event.event.params)
-                    .map_err(Error::EventError)?;
-                e.write()
-                    .unwrap()
-                    .trigger(&event.event.params, &facts)
-                    .await?;
+    pub async fn run<T: Serialize>(
+        &mut self,
+        facts: &T,
+    ) -> Result<Vec<RuleResult>> {
+        self.run_with_info(facts)
+            .await
+            .map(|(rule_results, _)| rule_results)
+    }
+
+    /// Same as `run`, but also reports when the run started, how long it
+    /// took, how many rules were evaluated and which rule groups fired
+    pub async fn run_with_info<T: Serialize>(
+        &mut self,
+        facts: &T,
+    ) -> Result<(Vec<RuleResult>, RunInfo)> {
+        self.run_scoped(None, None, facts).await
+    }
+
+    /// Same as `run`, the frequency conditions counting the times they were
+    /// met for the entity the key identifies, e.g. a user id, apart from
+    /// the others. `run` counts them all as one entity
+    pub async fn run_keyed<T: Serialize>(
+        &mut self,
+        key: &str,
+        facts: &T,
+    ) -> Result<Vec<RuleResult>> {
+        Ok(self.run_scoped(None, Some(key), facts).await?.0)
+    }
+
+    /// Bounds how many histories the frequency conditions keep, one per
+    /// node and entity, the least recently used being dropped first.
+    /// `DEFAULT_FREQUENCY_MAX_KEYS` by default
+    pub fn set_frequency_max_keys(&mut self, max_keys: usize) {
+        self.frequencies.set_max_keys(max_keys);
+    }
+
+    /// Runs the rules of the tenant, or the rules without one, counting the
+    /// frequency conditions met for the entity
+    async fn run_scoped<T: Serialize>(
+        &mut self,
+        tenant: Option<&str>,
+        entity: Option<&str>,
+        facts: &T,
+    ) -> Result<(Vec<RuleResult>, RunInfo)> {
+        self.run_facts(tenant, entity, to_value(facts)?, None::<&Value>)
+            .await
+    }
+
+    /// Runs the rules against the facts. When they were projected on the
+    /// ones the rules read, see `run_view`, `complete` holds all of them for
+    /// the events
+    async fn run_facts<V: FactsView + Sync>(
+        &mut self,
+        tenant: Option<&str>,
+        entity: Option<&str>,
+        facts: Value,
+        complete: Option<&V>,
+    ) -> Result<(Vec<RuleResult>, RunInfo)> {
+        let facts = self.facts_root(facts)?;
+        let run = self.evaluate_run(tenant, entity, &facts).await?;
+        let facts = match complete {
+            Some(complete)
+                if !run.rule_results.is_empty()
+                    || !run.group_results.is_empty() =>
+            {
+                complete.to_value()
+            }
+            _ => run.patched_facts.clone().unwrap_or(facts),
+        };
+        self.dispatch_run(
+            tenant,
+            run,
+            &facts,
+            #[cfg(feature = "detached")]
+            None,
+        )
+        .await
+    }
+
+    /// Evaluates the rules of the tenant, or the rules without one, against
+    /// the facts, leaving the events of the met ones to `dispatch_run`
+    pub(crate) async fn evaluate_run(
+        &mut self,
+        tenant: Option<&str>,
+        entity: Option<&str>,
+        facts: &Value,
+    ) -> Result<EvaluatedRun> {
+        let started_at = now_millis();
+        let start = Instant::now();
+
+        self.limits.check_facts(facts)?;
+        self.before_run(facts);
+        #[cfg(feature = "async_predicate")]
+        {
+            let groups = match tenant {
+                Some(_) => &[][..],
+                None => &self.rule_groups[..],
+            };
+            let rules: Vec<&Rule> = self
+                .rules_in_scope(tenant)
+                .into_iter()
+                .map(|i| &self.rules[i])
+                .chain(groups.iter().flat_map(|g| &g.rules))
+                .filter(|rule| rule.enabled)
+                .collect();
+            self.predicate_results = self.await_predicates(&rules, facts).await;
+        }
+        self.runs += 1;
+        #[cfg(feature = "callback")]
+        {
+            self.run_nonce = uuid::Uuid::new_v4().to_string();
+        }
+        self.frequency_run = Some(FrequencyRun {
+            entity: tenant_key(tenant, entity.unwrap_or_default()).into_owned(),
+            id: self.runs,
+        });
+        let Evaluation {
+            met_rule_results,
+            group_results,
+            rules_evaluated,
+            patched_facts,
+        } = self.evaluate_value(facts, tenant);
+        let skipped_disabled = self.disabled_in_scope(tenant);
+        self.frequency_run = None;
+        #[cfg(feature = "async_predicate")]
+        self.predicate_results.clear();
+        let (keys, rule_results) = met_rule_results.into_iter().unzip();
+
+        Ok(EvaluatedRun {
+            started_at,
+            start,
+            keys,
+            rule_results,
+            group_results,
+            rules_evaluated,
+            skipped_disabled,
+            patched_facts,
+        })
+    }
+
+    /// Dispatches the events of the rules met by the run, and reports about
+    /// it
+    pub(crate) async fn dispatch_run(
+        &mut self,
+        tenant: Option<&str>,
+        run: EvaluatedRun,
+        facts: &Value,
+        #[cfg(feature = "detached")] mut detached: Option<
+            &mut Vec<DetachedTrigger>,
+        >,
+    ) -> Result<(Vec<RuleResult>, RunInfo)> {
+        let EvaluatedRun {
+            started_at,
+            start,
+            keys,
+            rule_results: mut met_rule_results,
+            mut group_results,
+            rules_evaluated,
+            skipped_disabled,
+            patched_facts,
+        } = run;
+
+        self.coalescences
+            .lock()
+            .unwrap()
+            .retain(|_k, (start, expiration)| {
+                start.elapsed().as_secs() < *expiration
+            });
+        self.expire_mutes();
+
+        let mut failure = None;
+        for (key, rule_result) in keys.into_iter().zip(&mut met_rule_results) {
+            // expose the engine's variables, the rule's regex captures and
+            // its matched values to its templates, and to them only
+            let mut context = self.vars_context();
+            #[cfg(feature = "regex")]
+            if !rule_result.captures.is_empty() {
+                context.insert(
+                    "_captures".to_string(),
+                    Value::Object(rule_result.captures.clone()),
+                );
+            }
+            if self.collect_matches {
+                let matched =
+                    rule::matched_values(&rule_result.condition_result);
+                if !matched.is_empty() {
+                    context.insert(
+                        "_matched_values".to_string(),
+                        Value::Object(matched),
+                    );
+                }
+            }
+            let dispatched = with_template_context(
+                context,
+                self.dispatch_events(
+                    tenant,
+                    Some(key),
+                    rule_result.rule_id.as_deref(),
+                    &mut rule_result.events,
+                    facts,
+                    #[cfg(feature = "detached")]
+                    detached.as_deref_mut(),
+                ),
+            )
+            .await;
+            if let Err((event_type, source)) = dispatched {
+                failure =
+                    Some((rule_result.rule_id.clone(), event_type, source));
+                break;
             }
         }
 
-        Ok(met_rule_results)
+        for group_result in group_results.iter_mut() {
+            if failure.is_some() {
+                break;
+            }
+
+            // expose the matched members to the group's templates
+            let mut group_facts = facts.clone();
+            if let Some(obj) = group_facts.as_object_mut() {
+                obj.insert(
+                    "_matched_rules".to_string(),
+                    to_value(&group_result.matched_rules)?,
+                );
+            }
+
+            let dispatched = with_template_context(
+                self.vars_context(),
+                self.dispatch_events(
+                    None,
+                    None,
+                    Some(&group_result.id),
+                    &mut group_result.events,
+                    &group_facts,
+                    #[cfg(feature = "detached")]
+                    detached.as_deref_mut(),
+                ),
+            )
+            .await;
+            if let Err((event_type, source)) = dispatched {
+                failure =
+                    Some((Some(group_result.id.clone()), event_type, source));
+            }
+        }
+
+        self.after_run(facts, &met_rule_results);
+
+        if let Some((rule_id, event_type, source)) = failure {
+            return Err(Error::EventDispatch {
+                rule_id,
+                event_type,
+                source: Box::new(source),
+                results: met_rule_results,
+            });
+        }
+
+        let run_info = RunInfo {
+            started_at,
+            total_duration: start.elapsed(),
+            rules_evaluated,
+            skipped_disabled,
+            group_results,
+            tenant: tenant.map(ToOwned::to_owned),
+            patched_facts,
+        };
+
+        Ok((met_rule_results, run_info))
     }
 }
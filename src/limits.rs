@@ -0,0 +1,189 @@
+use crate::{
+    error::{Error, Result},
+    event::render_params,
+    rule::Rule,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+};
+
+/// Ends the strings `Limits::truncate_messages` truncates
+pub const TRUNCATION_MARKER: &str = "…";
+
+/// Bounds on the rules and facts an engine accepts, and on the events it
+/// dispatches, see `Engine::set_limits`. `None` leaves the matching dimension
+/// unbounded
+#[derive(
+    Debug, Default, Clone, Copy, Eq, PartialEq, Serialize, Deserialize,
+)]
+pub struct Limits {
+    /// Conditions in a single rule, combinators included
+    pub max_conditions_per_rule: Option<usize>,
+    /// Nesting depth of a rule's conditions, a lone leaf being 1 deep
+    pub max_condition_depth: Option<usize>,
+    /// Rules on the engine, rule group members included
+    pub max_rules: Option<usize>,
+    /// Size of the facts serialized as JSON
+    pub max_facts_bytes: Option<usize>,
+    /// Size of every string of an event's params, once rendered against the
+    /// facts
+    pub max_rendered_message_bytes: Option<usize>,
+    /// Size of the body a `post_to_callback_url` event posts, serialized as
+    /// JSON
+    pub max_callback_payload_bytes: Option<usize>,
+    /// Events a rule dispatches, or delays, in a single run, counted in the
+    /// order they're dispatched, its sequence first
+    pub max_events_per_rule_per_run: Option<usize>,
+    /// Elements of an array fact a leaf collects, see
+    /// `Engine::set_collect_matches`
+    pub max_matched_values: Option<usize>,
+    /// Truncates rendered strings over `max_rendered_message_bytes`, ending
+    /// them with `TRUNCATION_MARKER`, rather than skipping their event
+    #[serde(default)]
+    pub truncate_messages: bool,
+}
+
+/// Counts the bytes written to it, and drops them
+struct ByteCounter(usize);
+
+impl Write for ByteCounter {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0 += buf.len();
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+fn json_size(value: &Value) -> Result<usize> {
+    let mut counter = ByteCounter(0);
+    serde_json::to_writer(&mut counter, value)?;
+    Ok(counter.0)
+}
+
+fn longest_string(value: &Value) -> usize {
+    match value {
+        Value::String(s) => s.len(),
+        Value::Array(xs) => xs.iter().map(longest_string).max().unwrap_or(0),
+        Value::Object(m) => m.values().map(longest_string).max().unwrap_or(0),
+        _ => 0,
+    }
+}
+
+/// Truncates the strings over `max` bytes so that, marker included, they fit
+fn truncate(value: &mut Value, max: usize) {
+    match value {
+        Value::String(s) if s.len() > max => {
+            let mut end = max.saturating_sub(TRUNCATION_MARKER.len());
+            while !s.is_char_boundary(end) {
+                end -= 1;
+            }
+            s.truncate(end);
+            s.push_str(TRUNCATION_MARKER);
+        }
+        Value::Array(xs) => xs.iter_mut().for_each(|x| truncate(x, max)),
+        Value::Object(m) => m.values_mut().for_each(|x| truncate(x, max)),
+        _ => {}
+    }
+}
+
+fn exceeded(what: &str, found: usize, max: usize) -> Error {
+    Error::LimitError(format!(
+        "{} is {}, more than the maximum of {}",
+        what, found, max
+    ))
+}
+
+impl Limits {
+    /// Checks a rule about to be added to an engine already holding `rules`
+    pub(crate) fn check_rule(&self, rule: &Rule, rules: usize) -> Result<()> {
+        if let Some(max) = self.max_rules {
+            if rules >= max {
+                return Err(exceeded("The number of rules", rules + 1, max));
+            }
+        }
+
+        if self.max_conditions_per_rule.is_none()
+            && self.max_condition_depth.is_none()
+        {
+            return Ok(());
+        }
+
+        let (count, depth) = rule
+            .conditions
+            .nodes()
+            .fold((0, 0), |(count, depth), (d, _)| (count + 1, depth.max(d)));
+
+        if let Some(max) = self.max_conditions_per_rule {
+            if count > max {
+                return Err(exceeded("The number of conditions", count, max));
+            }
+        }
+
+        if let Some(max) = self.max_condition_depth {
+            if depth > max {
+                return Err(exceeded(
+                    "The depth of the conditions",
+                    depth,
+                    max,
+                ));
+            }
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn check_facts(&self, facts: &Value) -> Result<()> {
+        if let Some(max) = self.max_facts_bytes {
+            let size = json_size(facts)?;
+            if size > max {
+                return Err(exceeded("The size of the facts", size, max));
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Whether the rendered strings of an event's params fit. When
+    /// `truncate_messages` is set they always do, the params holding strings
+    /// too large being replaced with their truncated rendering
+    pub(crate) fn check_messages(
+        &self,
+        params: &mut HashMap<String, Value>,
+        facts: &Value,
+    ) -> bool {
+        let max = match self.max_rendered_message_bytes {
+            Some(max) => max,
+            None => return true,
+        };
+
+        for (key, mut rendered) in render_params(params, facts) {
+            if longest_string(&rendered) > max {
+                if !self.truncate_messages {
+                    return false;
+                }
+                truncate(&mut rendered, max);
+                params.insert(key, rendered);
+            }
+        }
+
+        true
+    }
+
+    /// Whether the body a `post_to_callback_url` event posts fits
+    pub(crate) fn check_callback_payload(
+        &self,
+        params: &HashMap<String, Value>,
+        facts: &Value,
+    ) -> bool {
+        self.max_callback_payload_bytes.is_none_or(|max| {
+            json_size(&json!({ "event": params, "facts": facts }))
+                .is_ok_and(|size| size <= max)
+        })
+    }
+}
@@ -0,0 +1,416 @@
+//! Static checks of a rule's conditions, see `Rule::lint`.
+//!
+//! The checks only read the rule, so they can't tell what facts it will
+//! see: a warning points at conditions that can't be met, or are met
+//! whatever the facts, or at parts that change nothing, whatever the facts.
+//! Leaves with a `path`, a `default`, templated values or variables aren't
+//! compared with others, as their values can't be told ahead.
+
+use crate::{condition::Condition, rule::Rule, Constraint, ValueOrVar};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::HashMap;
+
+/// How bad what a lint found is
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintSeverity {
+    /// Redundant, or met whatever the facts
+    Warning,
+    /// Never met, whatever the facts
+    Error,
+}
+
+/// What a lint found
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum LintKind {
+    /// Numeric bounds on the same field, in the same `and`, no value meets
+    ContradictoryRange,
+    /// A list operator given the same value more than once
+    DuplicateValues,
+    /// An `and`, `or` or `at_least` without children
+    EmptyCombinator,
+    /// An `at_least` of 0, or of more than its children
+    AtLeastThreshold,
+    /// A child equal to one of its siblings before it
+    IdenticalSiblings,
+    /// An `expr` reading nothing, so always met or never
+    ConstantExpression,
+}
+
+/// A finding of `Rule::lint`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct LintWarning {
+    /// JSON pointer to the node, from the rule's `conditions`, as in
+    /// `/and/1/or/0`, the whole tree being `""`
+    pub path: String,
+    pub severity: LintSeverity,
+    pub kind: LintKind,
+    pub message: String,
+}
+
+/// The operator suffixes of the constraints holding a list of values, the
+/// ranges, holding a pair, aside
+const LIST_SUFFIXES: &[&str] = &[
+    "_in",
+    "_contains_all",
+    "_contains_any",
+    "_does_not_contain_any",
+    "_is_subset",
+    "_is_superset",
+];
+
+/// One end of a range, and whether it's included
+type Bound = (f64, bool);
+
+/// The values a field may take to meet the numeric bounds put on it
+#[derive(Debug, Default, Clone, Copy)]
+struct Range {
+    low: Option<Bound>,
+    high: Option<Bound>,
+    /// Bounded by an `int` or `uint` operator, which only integers meet
+    integer: bool,
+}
+
+impl Range {
+    fn raise(&mut self, low: Bound) {
+        if self.low.is_none_or(|current| tighter_low(low, current)) {
+            self.low = Some(low);
+        }
+    }
+
+    fn lower(&mut self, high: Bound) {
+        if self.high.is_none_or(|current| tighter_high(high, current)) {
+            self.high = Some(high);
+        }
+    }
+
+    fn is_empty(&self) -> bool {
+        let (low, high) = match (self.low, self.high) {
+            (Some(low), Some(high)) => (low, high),
+            _ => return false,
+        };
+        if self.integer {
+            let low = if low.1 {
+                low.0.ceil()
+            } else {
+                low.0.floor() + 1.0
+            };
+            let high = if high.1 {
+                high.0.floor()
+            } else {
+                high.0.ceil() - 1.0
+            };
+            return low > high;
+        }
+        low.0 > high.0 || low.0 == high.0 && !(low.1 && high.1)
+    }
+}
+
+fn tighter_low(a: Bound, b: Bound) -> bool {
+    a.0 > b.0 || a.0 == b.0 && !a.1
+}
+
+fn tighter_high(a: Bound, b: Bound) -> bool {
+    a.0 < b.0 || a.0 == b.0 && !a.1
+}
+
+fn number(n: &serde_json::Number) -> f64 {
+    n.as_f64().unwrap_or_default()
+}
+
+/// Narrows the range to the values meeting the constraint, returning
+/// whether it's a numeric bound at all
+fn bound(range: &mut Range, constraint: &Constraint) -> bool {
+    use Constraint::*;
+
+    let (low, high) = match *constraint {
+        IntEquals(v) => (Some((v as f64, true)), Some((v as f64, true))),
+        IntInRange(a, b) => {
+            (a.map(|a| (a as f64, true)), b.map(|b| (b as f64, true)))
+        }
+        IntGreaterThan(v) => (Some((v as f64, false)), None),
+        IntGreaterThanInclusive(v) => (Some((v as f64, true)), None),
+        IntLessThan(v) => (None, Some((v as f64, false))),
+        IntLessThanInclusive(v) => (None, Some((v as f64, true))),
+        UintEquals(v) => (Some((v as f64, true)), Some((v as f64, true))),
+        UintInRange(a, b) => (Some((a as f64, true)), Some((b as f64, true))),
+        UintGreaterThan(v) => (Some((v as f64, false)), None),
+        UintGreaterThanInclusive(v) => (Some((v as f64, true)), None),
+        UintLessThan(v) => (Some((0.0, true)), Some((v as f64, false))),
+        UintLessThanInclusive(v) => (Some((0.0, true)), Some((v as f64, true))),
+        FloatEquals(v) => (Some((v, true)), Some((v, true))),
+        FloatInRange(a, b) => (a.map(|a| (a, true)), b.map(|b| (b, true))),
+        FloatGreaterThan(v) => (Some((v, false)), None),
+        FloatGreaterThanInclusive(v) => (Some((v, true)), None),
+        FloatLessThan(v) => (None, Some((v, false))),
+        FloatLessThanInclusive(v) => (None, Some((v, true))),
+        NumberEquals(ref v) => {
+            (Some((number(v), true)), Some((number(v), true)))
+        }
+        NumberInRange(ref a, ref b) => {
+            (Some((number(a), true)), Some((number(b), true)))
+        }
+        NumberGreaterThan(ref v) => (Some((number(v), false)), None),
+        NumberGreaterThanInclusive(ref v) => (Some((number(v), true)), None),
+        NumberLessThan(ref v) => (None, Some((number(v), false))),
+        NumberLessThanInclusive(ref v) => (None, Some((number(v), true))),
+        _ => return false,
+    };
+
+    let name: &'static str = constraint.into();
+    range.integer |= name.starts_with("int_") || name.starts_with("uint_");
+    if let Some(low) = low {
+        range.raise(low);
+    }
+    if let Some(high) = high {
+        range.lower(high);
+    }
+    true
+}
+
+/// The field and the constraint of a leaf whose value is known ahead
+fn plain_leaf(condition: &Condition) -> Option<(&str, &Constraint)> {
+    match condition {
+        Condition::Condition {
+            field,
+            constraint: ValueOrVar::Value(constraint),
+            path: None,
+            templated_value: false,
+            default: None,
+            ..
+        } => Some((field, constraint)),
+        _ => None,
+    }
+}
+
+/// The values the list operator is given more than once, in order
+fn duplicate_values(constraint: &Constraint) -> Vec<Value> {
+    let name: &'static str = constraint.into();
+    if !LIST_SUFFIXES.iter().any(|suffix| name.ends_with(suffix)) {
+        return Vec::new();
+    }
+    let values = match serde_json::to_value(constraint) {
+        Ok(Value::Object(mut constraint)) => match constraint.remove("value") {
+            Some(Value::Array(values)) => values,
+            _ => return Vec::new(),
+        },
+        _ => return Vec::new(),
+    };
+
+    let mut duplicates = Vec::new();
+    for (i, value) in values.iter().enumerate() {
+        if values[..i].contains(value) && !duplicates.contains(value) {
+            duplicates.push(value.clone());
+        }
+    }
+    duplicates
+}
+
+/// The value the expression always gives, when it reads nothing: neither
+/// the facts nor the time nor the other conditions' results
+#[cfg(feature = "eval")]
+fn constant_value(expr: &str) -> Option<bool> {
+    use rhai::packages::Package;
+
+    let mut engine = rhai::Engine::new_raw();
+    engine.register_global_module(
+        crate::JsonRulesEnginePackage::new().as_shared_module(),
+    );
+    engine.set_strict_variables(true);
+    let ast = engine.compile(expr).ok()?;
+    engine.eval_ast::<bool>(&ast).ok()
+}
+
+/// The key a combinator's children are under
+fn children_key(condition: &Condition) -> Option<&'static str> {
+    match condition {
+        Condition::And { .. } => Some("and"),
+        Condition::Or { .. } => Some("or"),
+        Condition::AtLeast { .. } => Some("conditions"),
+        Condition::Not { .. } => Some("not"),
+        Condition::Frequency { .. } => Some("of"),
+        _ => None,
+    }
+}
+
+struct Linter {
+    warnings: Vec<LintWarning>,
+}
+
+impl Linter {
+    fn warn(
+        &mut self,
+        path: &str,
+        severity: LintSeverity,
+        kind: LintKind,
+        message: String,
+    ) {
+        self.warnings.push(LintWarning {
+            path: path.to_owned(),
+            severity,
+            kind,
+            message,
+        });
+    }
+
+    fn lint(&mut self, condition: &Condition, path: &str) {
+        match condition {
+            Condition::And { and: children, .. } => {
+                if children.is_empty() {
+                    self.warn(
+                        path,
+                        LintSeverity::Warning,
+                        LintKind::EmptyCombinator,
+                        "An empty `and` is always met".to_owned(),
+                    );
+                }
+                self.lint_ranges(children, path);
+            }
+            Condition::Or { or: children, .. } if children.is_empty() => {
+                self.warn(
+                    path,
+                    LintSeverity::Error,
+                    LintKind::EmptyCombinator,
+                    "An empty `or` is never met".to_owned(),
+                );
+            }
+            Condition::AtLeast {
+                should_minimum_meet,
+                conditions,
+                ..
+            } => {
+                if conditions.is_empty() {
+                    self.warn(
+                        path,
+                        LintSeverity::Error,
+                        LintKind::EmptyCombinator,
+                        "An empty `at_least` is never met".to_owned(),
+                    );
+                } else if *should_minimum_meet == 0 {
+                    self.warn(
+                        path,
+                        LintSeverity::Warning,
+                        LintKind::AtLeastThreshold,
+                        "At least 0 of its conditions is always met".to_owned(),
+                    );
+                } else if *should_minimum_meet > conditions.len() {
+                    self.warn(
+                        path,
+                        LintSeverity::Error,
+                        LintKind::AtLeastThreshold,
+                        format!(
+                            "At least {} of {} conditions is never met",
+                            should_minimum_meet,
+                            conditions.len()
+                        ),
+                    );
+                }
+            }
+            Condition::Condition { field, .. } => {
+                if let Some((_, constraint)) = plain_leaf(condition) {
+                    for value in duplicate_values(constraint) {
+                        self.warn(
+                            path,
+                            LintSeverity::Warning,
+                            LintKind::DuplicateValues,
+                            format!(
+                                "`{}` lists {} more than once",
+                                field, value
+                            ),
+                        );
+                    }
+                }
+            }
+            #[cfg(feature = "eval")]
+            Condition::Eval { expr, .. } => {
+                if let Some(value) = constant_value(expr) {
+                    self.warn(
+                        path,
+                        if value {
+                            LintSeverity::Warning
+                        } else {
+                            LintSeverity::Error
+                        },
+                        LintKind::ConstantExpression,
+                        format!(
+                            "`{}` reads nothing, it's {} met",
+                            expr,
+                            if value { "always" } else { "never" }
+                        ),
+                    );
+                }
+            }
+            _ => {}
+        }
+
+        let key = match children_key(condition) {
+            Some(key) => key,
+            None => return,
+        };
+        let children = condition.children().unwrap_or_default();
+        // a single child isn't listed, its path has no index
+        if matches!(key, "not" | "of") {
+            let path = format!("{}/{}", path, key);
+            return self.lint(&children[0], &path);
+        }
+        for (i, child) in children.iter().enumerate() {
+            let path = format!("{}/{}/{}", path, key, i);
+            if let Some(j) = children[..i].iter().position(|c| c == child) {
+                self.warn(
+                    &path,
+                    LintSeverity::Warning,
+                    LintKind::IdenticalSiblings,
+                    format!("Same as its sibling {}", j),
+                );
+            }
+            self.lint(child, &path);
+        }
+    }
+
+    /// Reports the fields of the `and` whose numeric bounds no value meets
+    fn lint_ranges(&mut self, children: &[Condition], path: &str) {
+        let mut fields: Vec<&str> = Vec::new();
+        let mut ranges: HashMap<&str, Range> = HashMap::new();
+        for (field, constraint) in children.iter().filter_map(plain_leaf) {
+            let mut range = ranges.get(field).copied().unwrap_or_default();
+            if bound(&mut range, constraint) {
+                if !ranges.contains_key(field) {
+                    fields.push(field);
+                }
+                ranges.insert(field, range);
+            }
+        }
+
+        for field in fields {
+            if ranges[field].is_empty() {
+                self.warn(
+                    path,
+                    LintSeverity::Error,
+                    LintKind::ContradictoryRange,
+                    format!(
+                        "No value of `{}` meets all of its bounds, the `and` \
+                         is never met",
+                        field
+                    ),
+                );
+            }
+        }
+    }
+}
+
+impl Rule {
+    /// Static checks of the rule's conditions: contradictory numeric bounds
+    /// within an `and`, values listed twice, empty combinators, `at_least`
+    /// thresholds of 0 or of more than their children, siblings equal to
+    /// one another, and expressions reading nothing. Depth-first, each
+    /// node's findings before its children's
+    pub fn lint(&self) -> Vec<LintWarning> {
+        let mut linter = Linter {
+            warnings: Vec::new(),
+        };
+        linter.lint(&self.conditions, "");
+        linter.warnings
+    }
+}
@@ -0,0 +1,144 @@
+//! The facts the leaves of a run's rules address, resolved once per run.
+//!
+//! Rules often test the same few fields, each leaf resolving its JSON
+//! pointer again. The pointers the active rules' leaves address are
+//! resolved once before they're evaluated, and the leaves read their node
+//! from the cache. Leaves evaluated against other facts, e.g. the elements
+//! of `any_match` or the facts once patched, resolve their pointer as they
+//! would without it.
+
+use crate::{
+    condition::{node_path, Condition},
+    rule::Rule,
+};
+
+use serde_json::Value;
+
+use std::collections::HashMap;
+
+/// The nodes of the facts at the pointers of the rules' leaves
+pub(crate) struct LookupCache<'v> {
+    facts: &'v Value,
+    nodes: HashMap<String, Option<&'v Value>>,
+}
+
+impl<'v> LookupCache<'v> {
+    /// Resolves the pointers the leaves of the rules address in the facts,
+    /// those of the conditions on the elements of an array aside
+    pub(crate) fn new<'r>(
+        facts: &'v Value,
+        rules: impl IntoIterator<Item = &'r Rule>,
+    ) -> Self {
+        let mut nodes = HashMap::new();
+        for rule in rules {
+            for leaf in rule.conditions.leaves() {
+                if let Condition::Condition {
+                    field,
+                    pointer,
+                    path_syntax,
+                    ..
+                } = leaf
+                {
+                    nodes
+                        .entry(node_path(field, *pointer, *path_syntax, facts))
+                        .or_insert_with_key(|path| facts.pointer(path));
+                }
+            }
+        }
+
+        Self { facts, nodes }
+    }
+
+    /// The node at the pointer, `None` unless it was resolved in these very
+    /// facts
+    pub(crate) fn get(
+        &self,
+        facts: &Value,
+        pointer: &str,
+    ) -> Option<Option<&'v Value>> {
+        if !std::ptr::eq(facts, self.facts) {
+            return None;
+        }
+        self.nodes.get(pointer).copied()
+    }
+
+    #[cfg(test)]
+    fn len(&self) -> usize {
+        self.nodes.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::LookupCache;
+    use crate::rule::Rule;
+
+    use serde_json::json;
+
+    #[test]
+    fn resolved_once() {
+        let rules: Vec<Rule> = serde_json::from_value(json!([
+            {
+                "conditions": {
+                    "and": [
+                        {
+                            "field": "user/age",
+                            "operator": "int_greater_than",
+                            "value": 17
+                        },
+                        {
+                            "field": "user/country",
+                            "operator": "string_equals",
+                            "value": "FR"
+                        }
+                    ]
+                },
+                "events": []
+            },
+            {
+                "conditions": {
+                    "or": [
+                        {
+                            "field": "/user/age",
+                            "operator": "int_less_than",
+                            "value": 65
+                        },
+                        {
+                            "field": "user.plan",
+                            "operator": "string_equals",
+                            "value": "pro",
+                            "path_syntax": "dotted"
+                        },
+                        {
+                            "field": "devices",
+                            "operator": "any_match",
+                            "value": {
+                                "field": "trusted",
+                                "operator": "bool_equals",
+                                "value": false
+                            }
+                        }
+                    ]
+                },
+                "events": []
+            }
+        ]))
+        .unwrap();
+        let facts = json!({
+            "user": { "age": 27, "country": "FR" },
+            "devices": [{ "trusted": true }]
+        });
+
+        let cache = LookupCache::new(&facts, &rules);
+        // `/user/age` once for both of its spellings, `trusted` being
+        // evaluated against the elements
+        assert_eq!(cache.len(), 4);
+        assert_eq!(cache.get(&facts, "/user/age"), Some(Some(&json!(27))));
+        assert_eq!(cache.get(&facts, "/user/plan"), Some(None));
+        assert_eq!(cache.get(&facts, "/trusted"), None);
+
+        // other facts, however alike, resolve their own pointers
+        let copy = facts.clone();
+        assert_eq!(cache.get(&copy, "/user/age"), None);
+    }
+}
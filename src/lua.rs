@@ -0,0 +1,111 @@
+//! Lua scripts as conditions, see `Condition::LuaEval`.
+//!
+//! Scripts run in a sandbox without the `io`, `os` and `package` libraries,
+//! one per thread, where each script is compiled once and cached by its
+//! source. Every run is bounded by `LUA_MEMORY_LIMIT` and `LUA_INSTRUCTION_LIMIT`.
+
+use mlua::{
+    Error as LuaError, Function, HookTriggers, Lua, LuaOptions, LuaSerdeExt,
+    RegistryKey, StdLib, Value as LuaValue,
+};
+use serde_json::Value;
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    rc::Rc,
+};
+
+/// Bytes a script may allocate, its compiled code and the facts included
+pub const LUA_MEMORY_LIMIT: usize = 16 * 1024 * 1024;
+
+/// Virtual machine instructions a single run of a script may execute
+pub const LUA_INSTRUCTION_LIMIT: u64 = 1_000_000;
+
+/// Instructions between two checks of the instruction budget
+const HOOK_PERIOD: u32 = 1_000;
+
+struct Sandbox {
+    lua: Lua,
+    /// Hook periods left to the running script
+    budget: Rc<Cell<u64>>,
+    scripts: HashMap<String, RegistryKey>,
+}
+
+impl Sandbox {
+    fn new() -> mlua::Result<Self> {
+        let lua = Lua::new_with(
+            StdLib::TABLE | StdLib::STRING | StdLib::MATH | StdLib::UTF8,
+            LuaOptions::default(),
+        )?;
+        lua.set_memory_limit(LUA_MEMORY_LIMIT)?;
+
+        let budget = Rc::new(Cell::new(0));
+        let left = budget.clone();
+        lua.set_hook(
+            HookTriggers::new().every_nth_instruction(HOOK_PERIOD),
+            move |_, _| match left.get() {
+                0 => Err(LuaError::runtime("instruction limit exceeded")),
+                n => {
+                    left.set(n - 1);
+                    Ok(())
+                }
+            },
+        );
+
+        Ok(Self {
+            lua,
+            budget,
+            scripts: HashMap::new(),
+        })
+    }
+
+    fn eval(&mut self, script: &str, facts: &Value) -> Result<bool, String> {
+        if !self.scripts.contains_key(script) {
+            let function = self
+                .lua
+                .load(script)
+                .into_function()
+                .map_err(|e| e.to_string())?;
+            let key = self
+                .lua
+                .create_registry_value(function)
+                .map_err(|e| e.to_string())?;
+            self.scripts.insert(script.to_owned(), key);
+        }
+        let function: Function = self
+            .lua
+            .registry_value(&self.scripts[script])
+            .map_err(|e| e.to_string())?;
+
+        self.budget
+            .set(LUA_INSTRUCTION_LIMIT / u64::from(HOOK_PERIOD));
+        let facts = self.lua.to_value(facts).map_err(|e| e.to_string())?;
+        self.lua
+            .globals()
+            .set("facts", facts)
+            .map_err(|e| e.to_string())?;
+
+        match function.call::<_, LuaValue>(()) {
+            Ok(LuaValue::Boolean(met)) => Ok(met),
+            Ok(other) => {
+                Err(format!("expected a boolean, got {}", other.type_name()))
+            }
+            Err(e) => Err(e.to_string()),
+        }
+    }
+}
+
+thread_local! {
+    static SANDBOX: RefCell<Option<Sandbox>> = const { RefCell::new(None) };
+}
+
+/// Runs the script with the facts as `facts`, returning its boolean result
+pub(crate) fn eval(script: &str, facts: &Value) -> Result<bool, String> {
+    SANDBOX.with(|sandbox| {
+        let mut sandbox = sandbox.borrow_mut();
+        if sandbox.is_none() {
+            *sandbox = Some(Sandbox::new().map_err(|e| e.to_string())?);
+        }
+        sandbox.as_mut().unwrap().eval(script, facts)
+    })
+}
@@ -0,0 +1,147 @@
+//! Migrations of rules persisted by older versions of the crate.
+//!
+//! The schema versions, and the crate versions writing them, are:
+//!
+//! - `0` (0.1): operators named after the `Constraint` variants, e.g.
+//!   `StringEquals`, with `LessThan`, `LessThanInclusive`, `GreaterThan`
+//!   and `GreaterThanInclusive` comparing integers, and a single `event`
+//! - `1` (0.2 to 0.3): the integer comparisons are named `IntLessThan`, ...
+//! - `2` (0.4 to 0.6): operators are snake case, e.g. `string_equals`
+//! - `3` (0.7 onwards): a list of `events`
+//!
+//! Conditions and event params kept their shape across versions.
+
+use crate::{
+    error::{Error, Result},
+    rule::Rule,
+};
+use serde_json::Value;
+use std::convert::TryFrom;
+
+/// The schema version of the rules this version of the crate reads
+pub const SCHEMA_VERSION: u32 = 3;
+
+fn invalid(what: &str) -> Error {
+    Error::MigrationError(format!("Expected {}", what))
+}
+
+/// Applies `f` to the operator of every leaf of the conditions
+fn map_operators(
+    conditions: &mut Value,
+    f: &dyn Fn(&str) -> String,
+) -> Result<()> {
+    let node = conditions
+        .as_object_mut()
+        .ok_or_else(|| invalid("conditions to be objects"))?;
+
+    if let Some(Value::String(operator)) = node.get_mut("operator") {
+        *operator = f(operator);
+    }
+    for key in &["and", "or", "conditions"] {
+        if let Some(children) = node.get_mut(*key) {
+            for child in children
+                .as_array_mut()
+                .ok_or_else(|| invalid("a list of conditions"))?
+            {
+                map_operators(child, f)?;
+            }
+        }
+    }
+    if let Some(child) = node.get_mut("not") {
+        map_operators(child, f)?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn snake_case(operator: &str) -> String {
+    let mut snake = String::with_capacity(operator.len() + 4);
+    for (i, c) in operator.chars().enumerate() {
+        if c.is_ascii_uppercase() {
+            if i > 0 {
+                snake.push('_');
+            }
+            snake.push(c.to_ascii_lowercase());
+        } else {
+            snake.push(c);
+        }
+    }
+    snake
+}
+
+/// Migrates a rule from one schema version to the next
+fn migrate_once(rule: &mut Value, from_version: u32) -> Result<()> {
+    let rule = rule
+        .as_object_mut()
+        .ok_or_else(|| invalid("the rule to be an object"))?;
+
+    match from_version {
+        0 => {
+            if let Some(conditions) = rule.get_mut("conditions") {
+                map_operators(conditions, &|operator| match operator {
+                    "LessThan"
+                    | "LessThanInclusive"
+                    | "GreaterThan"
+                    | "GreaterThanInclusive" => format!("Int{}", operator),
+                    _ => operator.to_owned(),
+                })?;
+            }
+        }
+        1 => {
+            if let Some(conditions) = rule.get_mut("conditions") {
+                map_operators(conditions, &snake_case)?;
+            }
+        }
+        2 => {
+            if let Some(event) = rule.remove("event") {
+                rule.insert("events".to_string(), Value::Array(vec![event]));
+            }
+        }
+        _ => unreachable!(),
+    }
+
+    Ok(())
+}
+
+/// Migrates a rule written in the schema version `from_version` to the
+/// current one, `SCHEMA_VERSION`
+pub fn migrate_rule_value(
+    mut value: Value,
+    from_version: u32,
+) -> Result<Value> {
+    if from_version > SCHEMA_VERSION {
+        return Err(Error::MigrationError(format!(
+            "Unknown schema version {}, the latest being {}",
+            from_version, SCHEMA_VERSION
+        )));
+    }
+
+    for version in from_version..SCHEMA_VERSION {
+        migrate_once(&mut value, version)?;
+    }
+
+    Ok(value)
+}
+
+impl Rule {
+    /// Same as `serde_json::from_value`, but first migrates the rule from
+    /// the schema version in its `schema_version` key, if any, see
+    /// `migrate_rule_value`
+    pub fn from_versioned_value(mut value: Value) -> Result<Self> {
+        let from_version = match value
+            .as_object_mut()
+            .and_then(|rule| rule.remove("schema_version"))
+        {
+            Some(version) => version
+                .as_u64()
+                .and_then(|version| u32::try_from(version).ok())
+                .ok_or_else(|| invalid("`schema_version` to be an integer"))?,
+            None => SCHEMA_VERSION,
+        };
+
+        Ok(serde_json::from_value(migrate_rule_value(
+            value,
+            from_version,
+        )?)?)
+    }
+}
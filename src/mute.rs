@@ -0,0 +1,115 @@
+//! Events silenced for a while without touching the rules, e.g. during a
+//! deploy, see `Engine::mute`.
+//!
+//! The rules of a muted scope are still evaluated and their results still
+//! produced, only the events matching a mute aren't dispatched, and are
+//! marked `muted` in the results with the reason why. Mutes end on their
+//! own, as told by the engine's clock, see `Engine::set_now_provider`.
+
+use crate::{Engine, RuleKey};
+use chrono::{DateTime, Utc};
+use std::fmt;
+
+/// The events a mute silences
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum MuteScope {
+    /// Every event
+    All,
+    /// The events of the rules tagged so, see `Rule::tags`
+    Tag(String),
+    /// The events of the rules, or the group, of this id
+    RuleId(String),
+    /// The events of this type
+    EventType(String),
+}
+
+impl MuteScope {
+    fn matches(
+        &self,
+        tags: &[String],
+        rule_id: Option<&str>,
+        ty: &str,
+    ) -> bool {
+        match self {
+            MuteScope::All => true,
+            MuteScope::Tag(tag) => tags.contains(tag),
+            MuteScope::RuleId(id) => rule_id == Some(id.as_str()),
+            MuteScope::EventType(event_type) => event_type == ty,
+        }
+    }
+}
+
+impl fmt::Display for MuteScope {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MuteScope::All => write!(f, "all events"),
+            MuteScope::Tag(tag) => write!(f, "tag {}", tag),
+            MuteScope::RuleId(id) => write!(f, "rule {}", id),
+            MuteScope::EventType(ty) => write!(f, "event type {}", ty),
+        }
+    }
+}
+
+/// A mute in effect, see `Engine::active_mutes`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Mute {
+    pub scope: MuteScope,
+    /// When the events of the scope are dispatched again
+    pub until: DateTime<Utc>,
+}
+
+impl Engine {
+    /// Skips the events of the scope until then. Muting a scope already
+    /// muted moves its end
+    pub fn mute(&mut self, scope: MuteScope, until: DateTime<Utc>) {
+        self.expire_mutes();
+        match self.mutes.iter_mut().find(|mute| mute.scope == scope) {
+            Some(mute) => mute.until = until,
+            None => self.mutes.push(Mute { scope, until }),
+        }
+    }
+
+    /// Ends the mute of the scope ahead of time, returning whether there
+    /// was one
+    pub fn unmute(&mut self, scope: &MuteScope) -> bool {
+        let len = self.mutes.len();
+        self.mutes.retain(|mute| mute.scope != *scope);
+        self.mutes.len() != len
+    }
+
+    /// The mutes that haven't ended yet, in the order they were added
+    pub fn active_mutes(&self) -> Vec<Mute> {
+        let now = (self.now)();
+        self.mutes
+            .iter()
+            .filter(|mute| mute.until > now)
+            .cloned()
+            .collect()
+    }
+
+    /// Forgets the mutes that ended
+    pub(crate) fn expire_mutes(&mut self) {
+        let now = (self.now)();
+        self.mutes.retain(|mute| mute.until > now);
+    }
+
+    /// Why the event of the rule is muted, if it is
+    pub(crate) fn mute_reason(
+        &self,
+        key: Option<RuleKey>,
+        rule_id: Option<&str>,
+        ty: &str,
+    ) -> Option<String> {
+        let tags = key.map_or(&[][..], |key| &self.rule(key).tags);
+        self.mutes
+            .iter()
+            .find(|mute| mute.scope.matches(tags, rule_id, ty))
+            .map(|mute| {
+                format!(
+                    "{} muted until {}",
+                    mute.scope,
+                    mute.until.to_rfc3339()
+                )
+            })
+    }
+}
@@ -0,0 +1,38 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use unicode_normalization::UnicodeNormalization;
+
+/// Unicode normalization form applied to both the rule value and the fact
+/// before a string constraint is checked
+#[derive(Debug, Eq, PartialEq, Copy, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Normalization {
+    /// Canonical composition
+    Nfc,
+    /// Compatibility composition
+    Nfkc,
+    /// Compatibility decomposition
+    Nfkd,
+}
+
+impl Normalization {
+    pub fn normalize_str(&self, s: &str) -> String {
+        match self {
+            Normalization::Nfc => s.nfc().collect(),
+            Normalization::Nfkc => s.nfkc().collect(),
+            Normalization::Nfkd => s.nfkd().collect(),
+        }
+    }
+
+    /// Normalizes a string, or every string element of an array, leaving
+    /// anything else untouched
+    pub fn normalize_value(&self, v: &Value) -> Value {
+        match v {
+            Value::String(s) => Value::String(self.normalize_str(s)),
+            Value::Array(xs) => Value::Array(
+                xs.iter().map(|x| self.normalize_value(x)).collect(),
+            ),
+            _ => v.clone(),
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Rule results flattened into OpenTelemetry span attributes, see
+//! `RuleResult::to_otel_attributes`.
+//!
+//! Only key/value pairs are produced, so no OpenTelemetry crate is needed:
+//! `AttrValue` maps onto their `Value` one variant to one. A result gives
+//! its counts first, then the name of every failed condition, depth-first,
+//! then the type of every event:
+//!
+//! ```text
+//! rules.fraud_check.status = "met"
+//! rules.fraud_check.conditions.total = 3
+//! rules.fraud_check.conditions.met = 1
+//! rules.fraud_check.conditions.failed = 2
+//! rules.fraud_check.conditions.unknown = 0
+//! rules.fraud_check.duration_micros = 12
+//! rules.fraud_check.events.total = 1
+//! rules.fraud_check.events.dispatched = 1
+//! rules.fraud_check.events.suppressed = 0
+//! rules.fraud_check.conditions.failed.0 = "amount"
+//! rules.fraud_check.conditions.failed.1 = "country"
+//! rules.fraud_check.events.0.type = "message"
+//! ```
+
+use crate::{report::suppressions, rule::RuleResult, status::Status};
+
+/// How many attributes `RuleResult::to_otel_attributes` produces at most
+pub const DEFAULT_OTEL_MAX_ATTRIBUTES: usize = 64;
+
+/// The value of an attribute
+#[derive(Debug, Clone, PartialEq)]
+pub enum AttrValue {
+    Str(String),
+    I64(i64),
+    F64(f64),
+    Bool(bool),
+}
+
+fn status_str(status: Status) -> &'static str {
+    match status {
+        Status::Met => "met",
+        Status::NotMet => "not_met",
+        Status::Unknown => "unknown",
+    }
+}
+
+impl RuleResult {
+    /// The result as span attributes under `prefix`, keyed by the rule's id,
+    /// `unnamed` without one, see the `otel` module. At most
+    /// `DEFAULT_OTEL_MAX_ATTRIBUTES` are returned
+    pub fn to_otel_attributes(&self, prefix: &str) -> Vec<(String, AttrValue)> {
+        self.to_otel_attributes_with(prefix, DEFAULT_OTEL_MAX_ATTRIBUTES)
+    }
+
+    /// Same as `to_otel_attributes`, returning at most `max_attributes`,
+    /// the first ones kept
+    pub fn to_otel_attributes_with(
+        &self,
+        prefix: &str,
+        max_attributes: usize,
+    ) -> Vec<(String, AttrValue)> {
+        let root = format!(
+            "{}.{}",
+            prefix,
+            self.rule_id.as_deref().unwrap_or("unnamed")
+        );
+        let key = |suffix: &str| format!("{}.{}", root, suffix);
+        let count = |n: usize| AttrValue::I64(n as i64);
+
+        let leaves: Vec<_> = self
+            .condition_result
+            .iter()
+            .filter(|node| node.children.is_empty())
+            .collect();
+        let leaves_with = |status: Status| {
+            leaves.iter().filter(|leaf| leaf.status == status).count()
+        };
+        let suppressed = self
+            .events
+            .iter()
+            .filter(|event| !suppressions(event).is_empty())
+            .count();
+
+        let mut attributes = vec![
+            (
+                key("status"),
+                AttrValue::Str(
+                    status_str(self.condition_result.status).to_owned(),
+                ),
+            ),
+            (key("conditions.total"), count(leaves.len())),
+            (key("conditions.met"), count(leaves_with(Status::Met))),
+            (key("conditions.failed"), count(leaves_with(Status::NotMet))),
+            (
+                key("conditions.unknown"),
+                count(leaves_with(Status::Unknown)),
+            ),
+            (
+                key("duration_micros"),
+                AttrValue::I64(self.duration_micros as i64),
+            ),
+            (key("events.total"), count(self.events.len())),
+            (
+                key("events.dispatched"),
+                count(self.events.len() - suppressed),
+            ),
+            (key("events.suppressed"), count(suppressed)),
+        ];
+        attributes.extend(
+            leaves
+                .iter()
+                .filter(|leaf| leaf.status == Status::NotMet)
+                .enumerate()
+                .map(|(i, leaf)| {
+                    (
+                        key(&format!("conditions.failed.{}", i)),
+                        AttrValue::Str(leaf.name.clone()),
+                    )
+                }),
+        );
+        attributes.extend(self.events.iter().enumerate().map(|(i, event)| {
+            (
+                key(&format!("events.{}.type", i)),
+                AttrValue::Str(event.event.ty.clone()),
+            )
+        }));
+
+        attributes.truncate(max_attributes);
+        attributes
+    }
+}
@@ -0,0 +1,267 @@
+//! Snapshots of the engine's state, so coalesced, rate limited and delayed
+//! events don't fire again, or get lost, after a restart.
+//!
+//! `Instant`s can't outlive the process, so times are saved as wall clock
+//! times, as told by the engine's now provider, and turned back into
+//! `Instant`s on load, the time spent in between being taken off.
+//!
+//! `save_coalescence` and `restore_coalescence` only handle coalescence
+//! groups, in a file, while `export_state` and `import_state` handle every
+//! piece of state as one `EngineState`.
+
+use crate::{
+    clock::Instant,
+    error::{Error, Result},
+    tenant::tenant_event_type,
+    Engine,
+};
+#[cfg(feature = "delay")]
+use crate::{event::CoalescenceEvent, rule::Rule};
+use serde::{Deserialize, Serialize};
+#[cfg(feature = "delay")]
+use serde_json::Value;
+use std::{
+    collections::HashMap, convert::TryFrom, fs, io::ErrorKind, path::Path,
+    time::Duration,
+};
+
+/// The version of the `EngineState`s this version of the crate writes and
+/// reads
+pub const ENGINE_STATE_VERSION: u32 = 1;
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CoalescenceSnapshot {
+    /// When the snapshot was taken, in seconds since the unix epoch
+    saved_at: i64,
+    /// Seconds left to every coalescence group
+    groups: HashMap<String, u64>,
+}
+
+impl Engine {
+    /// Writes the coalescence groups still suppressing events to `path`, see
+    /// `export_state` for the rest of the state
+    pub fn save_coalescence(&self, path: impl AsRef<Path>) -> Result<()> {
+        let snapshot = CoalescenceSnapshot {
+            saved_at: (self.now)().timestamp(),
+            groups: self
+                .coalescences
+                .lock()
+                .unwrap()
+                .iter()
+                .map(|(group, (start, expiration))| {
+                    (
+                        group.clone(),
+                        expiration.saturating_sub(start.elapsed().as_secs()),
+                    )
+                })
+                .filter(|(_, remaining)| *remaining > 0)
+                .collect(),
+        };
+
+        fs::write(path, serde_json::to_vec(&snapshot)?)?;
+        Ok(())
+    }
+
+    /// Replaces the coalescence state with the one saved to `path`,
+    /// discarding the groups that expired since. A missing file leaves the
+    /// state untouched, a corrupt one is an error
+    pub fn restore_coalescence(
+        &mut self,
+        path: impl AsRef<Path>,
+    ) -> Result<()> {
+        let bytes = match fs::read(path) {
+            Ok(bytes) => bytes,
+            Err(e) if e.kind() == ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let snapshot: CoalescenceSnapshot = serde_json::from_slice(&bytes)?;
+
+        let elapsed = ((self.now)().timestamp() - snapshot.saved_at).max(0);
+        let restored_at = Instant::now();
+        *self.coalescences.lock().unwrap() = snapshot
+            .groups
+            .into_iter()
+            .filter_map(|(group, remaining)| {
+                remaining
+                    .checked_sub(elapsed as u64)
+                    .filter(|remaining| *remaining > 0)
+                    .map(|remaining| (group, (restored_at, remaining)))
+            })
+            .collect();
+
+        Ok(())
+    }
+}
+
+/// The state of an engine, see `Engine::export_state`. Times are in
+/// milliseconds since the unix epoch
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct EngineState {
+    /// `ENGINE_STATE_VERSION` when exported
+    pub version: u32,
+    /// When the state was exported
+    pub exported_at: i64,
+    /// When every coalescence group stops suppressing events
+    pub coalescences: HashMap<String, i64>,
+    /// The buckets of the rate limits, by event type
+    pub rate_limits: HashMap<String, RateLimitState>,
+    #[cfg(feature = "delay")]
+    #[serde(default)]
+    pub delayed: Vec<DelayedEventState>,
+    #[cfg(feature = "delay")]
+    #[serde(default)]
+    pub next_delayed_id: u64,
+}
+
+/// The tokens left to a rate limit when the state was exported
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RateLimitState {
+    pub tokens: f64,
+}
+
+/// A pending delayed event
+#[cfg(feature = "delay")]
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct DelayedEventState {
+    pub id: u64,
+    pub rule_id: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    /// When the event is due
+    pub due_at: i64,
+    pub event: CoalescenceEvent,
+    pub recheck: Option<Rule>,
+    pub facts: Value,
+    /// What the templates of the event see besides the facts
+    #[serde(default, skip_serializing_if = "serde_json::Map::is_empty")]
+    pub template_context: serde_json::Map<String, Value>,
+}
+
+fn millis(duration: Duration) -> i64 {
+    i64::try_from(duration.as_millis()).unwrap_or(i64::MAX)
+}
+
+impl Engine {
+    /// The coalescence groups still suppressing events, the tokens left to
+    /// the rate limits and the pending delayed events, to be restored with
+    /// `import_state`, e.g. after a restart
+    pub fn export_state(&self) -> EngineState {
+        let exported_at = (self.now)().timestamp_millis();
+
+        EngineState {
+            version: ENGINE_STATE_VERSION,
+            exported_at,
+            coalescences: self
+                .coalescences
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|(group, (start, expiration))| {
+                    let left = Duration::from_secs(*expiration)
+                        .checked_sub(start.elapsed())
+                        .filter(|left| !left.is_zero())?;
+                    Some((group.clone(), exported_at + millis(left)))
+                })
+                .collect(),
+            rate_limits: self
+                .rate_limits
+                .iter()
+                .map(|(ty, bucket)| {
+                    let tokens = bucket.tokens();
+                    (ty.clone(), RateLimitState { tokens })
+                })
+                .collect(),
+            #[cfg(feature = "delay")]
+            delayed: {
+                let now = tokio::time::Instant::now();
+                self.delayed
+                    .iter()
+                    .map(|delayed| DelayedEventState {
+                        id: delayed.id,
+                        rule_id: delayed.rule_id.clone(),
+                        tenant: delayed.tenant.clone(),
+                        due_at: exported_at
+                            + millis(
+                                delayed.due.saturating_duration_since(now),
+                            ),
+                        event: delayed.event.clone(),
+                        recheck: delayed.recheck.clone(),
+                        facts: delayed.facts.clone(),
+                        template_context: delayed.template_context.clone(),
+                    })
+                    .collect()
+            },
+            #[cfg(feature = "delay")]
+            next_delayed_id: self.next_delayed_id,
+        }
+    }
+
+    /// Replaces the state of the engine with one exported by `export_state`,
+    /// dropping the coalescence groups that expired since and refilling the
+    /// rate limits for the time elapsed. Delayed events that became due
+    /// meanwhile are due right away.
+    ///
+    /// Only the rate limits set on this engine are restored, the others are
+    /// ignored
+    pub fn import_state(&mut self, state: EngineState) -> Result<()> {
+        if state.version != ENGINE_STATE_VERSION {
+            return Err(Error::StateVersionError(state.version));
+        }
+
+        let now = (self.now)().timestamp_millis();
+        let elapsed =
+            Duration::from_millis((now - state.exported_at).max(0) as u64);
+        let restored_at = Instant::now();
+
+        *self.coalescences.lock().unwrap() = state
+            .coalescences
+            .into_iter()
+            .filter(|(_, expires_at)| *expires_at > now)
+            .map(|(group, expires_at)| {
+                // coalescence is counted in whole seconds
+                let left = ((expires_at - now) as u64).div_ceil(1000);
+                (group, (restored_at, left))
+            })
+            .collect();
+
+        // the buckets of tenants are only made on their first event
+        for key in state.rate_limits.keys() {
+            if let Some(ty) = tenant_event_type(key) {
+                if let Some(bucket) = self.rate_limits.get(ty) {
+                    let bucket = bucket.refilled_copy();
+                    self.rate_limits.entry(key.clone()).or_insert(bucket);
+                }
+            }
+        }
+        for (ty, bucket) in &mut self.rate_limits {
+            if let Some(saved) = state.rate_limits.get(ty) {
+                bucket.restore(saved.tokens, elapsed);
+            }
+        }
+
+        #[cfg(feature = "delay")]
+        {
+            let restored_at = tokio::time::Instant::now();
+            self.delayed = state
+                .delayed
+                .into_iter()
+                .map(|delayed| crate::delay::DelayedEvent {
+                    id: delayed.id,
+                    rule_id: delayed.rule_id,
+                    tenant: delayed.tenant,
+                    due: restored_at
+                        + Duration::from_millis(
+                            (delayed.due_at - now).max(0) as u64
+                        ),
+                    event: delayed.event,
+                    recheck: delayed.recheck,
+                    facts: delayed.facts,
+                    template_context: delayed.template_context,
+                })
+                .collect();
+            self.next_delayed_id = state.next_delayed_id;
+        }
+
+        Ok(())
+    }
+}
@@ -0,0 +1,120 @@
+//! Engines run one after the other, see `Pipeline`.
+//!
+//! Every stage is an engine of its own, run against the facts the stages
+//! before it added to through the `facts_to_add` of their met rules, or
+//! patched through their `apply_json_patch` events, e.g. enrichment rules,
+//! then decision rules, then notification rules.
+
+use crate::{error::Result, Engine, RuleResult};
+use serde::Serialize;
+use serde_json::{to_value, Value};
+
+/// What a stage of a `Pipeline` did
+#[derive(Debug)]
+pub struct StageResult {
+    pub name: String,
+    /// The met rules of the stage, as `Engine::run` returns them
+    pub rule_results: Vec<RuleResult>,
+    /// The facts once the stage added its own
+    pub facts: Value,
+}
+
+/// Stages of rules run in order, each seeing the facts added by the met
+/// rules of the stages before it
+#[derive(Default)]
+pub struct Pipeline {
+    stages: Vec<(String, Engine)>,
+    defer_dispatch: bool,
+}
+
+impl Pipeline {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Appends a stage, run after the ones added before it
+    pub fn add_stage(&mut self, name: &str, engine: Engine) {
+        self.stages.push((name.to_owned(), engine));
+    }
+
+    /// The engine of the stage, e.g. to add rules to it
+    pub fn stage_mut(&mut self, name: &str) -> Option<&mut Engine> {
+        self.stages
+            .iter_mut()
+            .find(|(stage, _)| stage == name)
+            .map(|(_, engine)| engine)
+    }
+
+    /// Dispatches the events of every stage once the last one was
+    /// evaluated, so a failing stage dispatches nothing. By default each
+    /// stage dispatches its events before the next one runs
+    pub fn set_defer_dispatch(&mut self, defer_dispatch: bool) {
+        self.defer_dispatch = defer_dispatch;
+    }
+
+    /// Runs the stages in order. The facts added by a stage are merged over
+    /// the facts before the next one runs, the rules of the stage adding
+    /// them in order, so the last one wins. The events of a stage see the
+    /// facts it added. Stops at the first stage failing
+    pub async fn run<T: Serialize>(
+        &mut self,
+        facts: &T,
+    ) -> Result<Vec<StageResult>> {
+        let mut facts = to_value(facts)?;
+        let mut evaluated = Vec::with_capacity(self.stages.len());
+        let mut stage_results = Vec::with_capacity(self.stages.len());
+
+        for (name, engine) in &mut self.stages {
+            facts = engine.facts_root(facts)?;
+            let run = engine.evaluate_run(None, None, &facts).await?;
+            if let Some(patched) = &run.patched_facts {
+                facts = patched.clone();
+            }
+            if let Some(obj) = facts.as_object_mut() {
+                for rule_result in &run.rule_results {
+                    obj.extend(rule_result.facts_to_add.clone());
+                }
+            }
+
+            if self.defer_dispatch {
+                evaluated.push((name.clone(), run, facts.clone()));
+                continue;
+            }
+            let (rule_results, _) = engine
+                .dispatch_run(
+                    None,
+                    run,
+                    &facts,
+                    #[cfg(feature = "detached")]
+                    None,
+                )
+                .await?;
+            stage_results.push(StageResult {
+                name: name.clone(),
+                rule_results,
+                facts: facts.clone(),
+            });
+        }
+
+        for ((name, run, facts), (_, engine)) in
+            evaluated.into_iter().zip(&mut self.stages)
+        {
+            let (rule_results, _) = engine
+                .dispatch_run(
+                    None,
+                    run,
+                    &facts,
+                    #[cfg(feature = "detached")]
+                    None,
+                )
+                .await?;
+            stage_results.push(StageResult {
+                name,
+                rule_results,
+                facts,
+            });
+        }
+
+        Ok(stage_results)
+    }
+}
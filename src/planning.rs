@@ -0,0 +1,103 @@
+//! What facts to gather before the rules can be decided, see
+//! `Engine::required_fields` and `Engine::plan`.
+
+use crate::{rule::Rule, status::Status, Engine};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use std::collections::BTreeMap;
+
+/// Whether a rule can be decided from partial facts
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleDecision {
+    /// The `id` of the rule, or its position among the engine's rules,
+    /// group members last
+    pub rule_id: String,
+    /// The status of the rule against the partial facts, decided unless
+    /// `Unknown`
+    pub status: Status,
+    /// JSON pointers to the missing facts which could decide the rule,
+    /// empty once decided. Some may turn out not to be needed, e.g. the
+    /// second of two facts an `or` needs when the first meets it
+    pub missing_fields: Vec<String>,
+}
+
+/// How far partial facts go towards deciding the engine's rules
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct EvaluationPlan {
+    pub rules: Vec<RuleDecision>,
+}
+
+impl EvaluationPlan {
+    /// Whether every rule is decided
+    pub fn is_decided(&self) -> bool {
+        self.rules.iter().all(|rule| rule.status != Status::Unknown)
+    }
+
+    /// The missing facts of all the undecided rules, each once
+    pub fn missing_fields(&self) -> Vec<String> {
+        let mut missing = Vec::new();
+        for field in self.rules.iter().flat_map(|rule| &rule.missing_fields) {
+            if !missing.contains(field) {
+                missing.push(field.clone());
+            }
+        }
+        missing
+    }
+}
+
+impl Engine {
+    /// The engine's enabled rules along with their ids, or their positions
+    pub(crate) fn keyed_rules(&self) -> impl Iterator<Item = (String, &Rule)> {
+        self.rules
+            .iter()
+            .chain(self.rule_groups.iter().flat_map(|group| &group.rules))
+            .enumerate()
+            .filter(|(_, rule)| rule.enabled)
+            .map(|(i, rule)| {
+                (rule.id.clone().unwrap_or_else(|| i.to_string()), rule)
+            })
+    }
+
+    /// The facts the rules refer to, as JSON pointers, along with the rules
+    /// referring to each, see `Condition::referenced_fields`
+    pub fn required_fields(&self) -> BTreeMap<String, Vec<String>> {
+        let mut required: BTreeMap<String, Vec<String>> = BTreeMap::new();
+        for (rule_id, rule) in self.keyed_rules() {
+            for field in rule.referenced_fields() {
+                let rule_ids = required.entry(field.pointer).or_default();
+                if !rule_ids.contains(&rule_id) {
+                    rule_ids.push(rule_id.clone());
+                }
+            }
+        }
+        required
+    }
+
+    /// Evaluates the rules against partial facts, telling for each whether
+    /// it's already decided, e.g. an `and` with a child not met whatever
+    /// the facts it misses, or which missing facts could decide it
+    pub fn plan(&self, partial_facts: &Value) -> EvaluationPlan {
+        let rules = self
+            .keyed_rules()
+            .map(|(rule_id, rule)| {
+                let condition_result = self
+                    .evaluate_rule_with(rule, None, partial_facts, true, None)
+                    .condition_result;
+                let mut missing_fields = Vec::new();
+                rule.conditions.missing_fields(
+                    &condition_result,
+                    partial_facts,
+                    &mut missing_fields,
+                );
+
+                RuleDecision {
+                    rule_id,
+                    status: condition_result.status,
+                    missing_fields,
+                }
+            })
+            .collect();
+
+        EvaluationPlan { rules }
+    }
+}
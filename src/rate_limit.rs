@@ -0,0 +1,75 @@
+use crate::clock::Instant;
+use std::time::Duration;
+
+/// Token bucket holding up to `max` tokens, refilled at `max` tokens `per`
+/// period
+#[derive(Debug, Clone)]
+pub(crate) struct TokenBucket {
+    max: u32,
+    per: Duration,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucket {
+    pub(crate) fn new(max: u32, per: Duration) -> Self {
+        Self {
+            max,
+            per,
+            tokens: max as f64,
+            refilled_at: Instant::now(),
+        }
+    }
+
+    /// A full bucket with the same limit
+    pub(crate) fn refilled_copy(&self) -> Self {
+        Self::new(self.max, self.per)
+    }
+
+    /// Takes a token if there's one left. An empty period refills
+    /// instantly, so never runs out
+    pub(crate) fn try_take(&mut self) -> bool {
+        if self.per.is_zero() {
+            return true;
+        }
+
+        let now = Instant::now();
+        self.tokens = self.refilled(now);
+        self.refilled_at = now;
+
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The tokens the bucket holds once refilled up to `now`
+    fn refilled(&self, now: Instant) -> f64 {
+        if self.per.is_zero() {
+            return self.max as f64;
+        }
+
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        (self.tokens + elapsed / self.per.as_secs_f64() * self.max as f64)
+            .min(self.max as f64)
+    }
+
+    /// The tokens left right now
+    pub(crate) fn tokens(&self) -> f64 {
+        self.refilled(Instant::now())
+    }
+
+    /// Puts back the tokens the bucket had `elapsed` ago, refilling it for
+    /// the time since
+    pub(crate) fn restore(&mut self, tokens: f64, elapsed: Duration) {
+        self.tokens = tokens.max(0.0);
+        self.refilled_at = Instant::now();
+        if !self.per.is_zero() {
+            self.tokens += elapsed.as_secs_f64() / self.per.as_secs_f64()
+                * self.max as f64;
+        }
+        self.tokens = self.tokens.min(self.max as f64);
+    }
+}
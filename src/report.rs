@@ -0,0 +1,192 @@
+//! Human readable trees of condition results, for developing rules locally.
+//!
+//! A result renders as one line per node, its children drawn below it with
+//! box-drawing characters and its status marked `✓` (met), `✗` (not met) or
+//! `?` (unknown):
+//!
+//! ```text
+//! ✓ And
+//! ├── ✓ name
+//! └── ✓ Or
+//!     ├── ✗ age
+//!     └── ✓ action
+//! ```
+//!
+//! The output only depends on the result and the options, so it can be
+//! snapshotted.
+
+use crate::{
+    condition::{nested_path, top_level_path, ConditionResult, PathSyntax},
+    event::CoalescenceEvent,
+    rule::RuleResult,
+    status::Status,
+};
+use serde_json::Value;
+use std::io::IsTerminal;
+
+const GREEN: &str = "\x1b[32m";
+const RED: &str = "\x1b[31m";
+const YELLOW: &str = "\x1b[33m";
+const DIM: &str = "\x1b[2m";
+const RESET: &str = "\x1b[0m";
+
+/// How `ConditionResult::render_tree` renders a result
+#[derive(Debug, Clone, Copy)]
+pub struct RenderOptions<'a> {
+    /// Colors the markers with ANSI escape codes. Defaults to whether the
+    /// standard output is a terminal
+    pub color: bool,
+    /// The facts the result was checked against, to show the value of the
+    /// fact each leaf refers to. Leaves are named after their field, looked
+    /// up as a top level key first, then as a path of either syntax
+    pub facts: Option<&'a Value>,
+}
+
+impl Default for RenderOptions<'_> {
+    fn default() -> Self {
+        Self {
+            color: std::io::stdout().is_terminal(),
+            facts: None,
+        }
+    }
+}
+
+impl RenderOptions<'_> {
+    fn paint(&self, color: &str, s: &str) -> String {
+        if self.color {
+            format!("{}{}{}", color, s, RESET)
+        } else {
+            s.to_owned()
+        }
+    }
+
+    fn marker(&self, status: Status) -> String {
+        match status {
+            Status::Met => self.paint(GREEN, "✓"),
+            Status::NotMet => self.paint(RED, "✗"),
+            Status::Unknown => self.paint(YELLOW, "?"),
+        }
+    }
+
+    /// The fact a leaf named `name` refers to
+    fn value(&self, name: &str) -> Option<&Value> {
+        let facts = self.facts?;
+        std::iter::once(top_level_path(name))
+            .chain(
+                [PathSyntax::Pointer, PathSyntax::Dotted]
+                    .iter()
+                    .map(|syntax| nested_path(name, *syntax)),
+            )
+            .find_map(|path| facts.pointer(&path))
+    }
+}
+
+/// Why the event wasn't dispatched as the run went, if it wasn't
+pub(crate) fn suppressions(event: &CoalescenceEvent) -> Vec<String> {
+    let mut suppressions = Vec::new();
+    if event.rate_limited {
+        suppressions.push("rate limited".to_owned());
+    }
+    if event.too_large {
+        suppressions.push("too large".to_owned());
+    }
+    if let Some(reason) = &event.mute_reason {
+        suppressions.push(reason.clone());
+    }
+    if let Some(reason) = &event.dropped {
+        suppressions.push(format!("dropped: {}", reason));
+    }
+    if let Some(error) = &event.error {
+        suppressions.push(format!("error: {}", error));
+    }
+    #[cfg(feature = "delay")]
+    if let Some(id) = event.delayed_id {
+        suppressions.push(format!("delayed #{}", id));
+    }
+    suppressions
+}
+
+/// Appends the lines of the node and its children, `prefix` leading the
+/// lines of the children
+fn render_node(
+    result: &ConditionResult,
+    opts: &RenderOptions,
+    prefix: &str,
+    out: &mut String,
+) {
+    out.push_str(&opts.marker(result.status));
+    out.push(' ');
+    out.push_str(&result.name);
+    if result.children.is_empty() {
+        if let Some(value) = opts.value(&result.name) {
+            out.push_str(&opts.paint(DIM, &format!(" = {}", value)));
+        }
+    }
+    if result.used_default {
+        out.push_str(&opts.paint(DIM, " (default)"));
+    }
+    if !result.evaluated {
+        out.push_str(&opts.paint(DIM, " (skipped)"));
+    }
+    if let Some(error) = &result.error {
+        out.push_str(&opts.paint(RED, &format!(" ({})", error)));
+    }
+    out.push('\n');
+
+    for (i, child) in result.children.iter().enumerate() {
+        let last = i + 1 == result.children.len();
+        out.push_str(prefix);
+        out.push_str(if last { "└── " } else { "├── " });
+        let prefix =
+            format!("{}{}", prefix, if last { "    " } else { "│   " });
+        render_node(child, opts, &prefix, out);
+    }
+}
+
+impl ConditionResult {
+    /// The result as a tree, one line per node, see the `report` module
+    pub fn render_tree(&self, opts: RenderOptions) -> String {
+        let mut out = String::new();
+        render_node(self, &opts, "", &mut out);
+        out
+    }
+}
+
+impl RuleResult {
+    /// The rule's condition result as a tree, followed by its events with
+    /// their `message` param and why they weren't dispatched, if they
+    /// weren't
+    pub fn render(&self) -> String {
+        self.render_with(RenderOptions::default())
+    }
+
+    /// Same as `render`, with the tree rendered as told by the options
+    pub fn render_with(&self, opts: RenderOptions) -> String {
+        let mut out =
+            format!("rule {}\n", self.rule_id.as_deref().unwrap_or("(no id)"));
+        out.push_str(&self.condition_result.render_tree(opts));
+
+        if self.events.is_empty() {
+            out.push_str("events: none\n");
+            return out;
+        }
+        out.push_str("events:\n");
+        for (i, event) in self.events.iter().enumerate() {
+            let last = i + 1 == self.events.len();
+            out.push_str(if last { "└── " } else { "├── " });
+            out.push_str(&event.event.ty);
+            if let Some(Value::String(message)) =
+                event.event.params.get("message")
+            {
+                out.push_str(&format!(": {}", message));
+            }
+            for suppressed in suppressions(event) {
+                out.push_str(
+                    &opts.paint(YELLOW, &format!(" [{}]", suppressed)),
+                );
+            }
+            out.push('\n');
+        }
+        out
+    }
+}
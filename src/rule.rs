@@ -1,55 +1,316 @@
 use crate::{
-    condition::{Condition, ConditionResult},
-    event::CoalescenceEvent,
+    compiled::{render, RulePlan},
+    condition::{
+        deserialize_conditions, Condition, ConditionResult, EvalContext,
+        FieldRef,
+    },
+    constraint::NamedSets,
+    event::{
+        apply_json_patch::PatchOutcome, render_value, with_app_data,
+        CoalescenceEvent, EscapeMode,
+    },
+    sampling::Sample,
+    status::Status,
 };
+use chrono::Utc;
 #[cfg(feature = "eval")]
 use rhai::Engine;
 use serde::{Deserialize, Serialize};
-use serde_json::Value;
+use serde_json::{Map, Value};
+#[cfg(feature = "regex")]
+use std::cell::RefCell;
+use std::{borrow::Cow, collections::HashMap};
 
-#[derive(Clone, Debug, Serialize, Deserialize)]
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct Rule {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub id: Option<String>,
+    #[serde(deserialize_with = "deserialize_conditions")]
     pub conditions: Condition,
     pub events: Vec<CoalescenceEvent>,
+    /// Facts a `Pipeline` adds for its next stages when the rule is met, their
+    /// strings rendered against the facts as templates, unescaped
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub facts_to_add: Map<String, Value>,
+    /// Disabled rules are kept but never evaluated, see
+    /// `Engine::set_rule_enabled`
+    #[serde(default = "enabled_default", skip_serializing_if = "is_enabled")]
+    pub enabled: bool,
+    /// Labels to address rules by, e.g. to mute their events with
+    /// `MuteScope::Tag`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub tags: Vec<String>,
+    /// Applies the rule to a share of the facts only, see `Sample`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sample: Option<Sample>,
+}
+
+fn enabled_default() -> bool {
+    true
+}
+
+fn is_enabled(enabled: &bool) -> bool {
+    *enabled
 }
 
 impl Rule {
+    /// Every fact the rule's conditions refer to, see
+    /// `Condition::referenced_fields`
+    pub fn referenced_fields(&self) -> Vec<FieldRef> {
+        self.conditions.referenced_fields()
+    }
+
     pub fn check_value(
         &self,
         info: &Value,
         #[cfg(feature = "eval")] rhai_engine: &Engine,
     ) -> RuleResult {
-        let condition_result = self.conditions.check_value(
+        self.check_value_with(
             info,
-            #[cfg(feature = "eval")]
-            rhai_engine,
-        );
+            &EvalContext {
+                #[cfg(feature = "eval")]
+                rhai_engine,
+                #[cfg(feature = "eval")]
+                flatten_scope: false,
+                sets: &NamedSets::default(),
+                #[cfg(feature = "regex")]
+                captures: &RefCell::default(),
+                variables: &HashMap::new(),
+                now: Utc::now(),
+                results: &HashMap::new(),
+                short_circuit: false,
+                trace: false,
+                #[cfg(feature = "async_predicate")]
+                predicates: &Default::default(),
+                plan: None,
+                frequencies: None,
+                frequency_run: None,
+                max_matched_values: None,
+                element_root: None,
+                lookups: None,
+            },
+        )
+    }
 
-        let mut events = self.events.clone();
+    pub(crate) fn check_value_with(
+        &self,
+        info: &Value,
+        ctx: &EvalContext,
+    ) -> RuleResult {
+        let condition_result = self.conditions.check_value_with(info, ctx);
 
-        for CoalescenceEvent {
-            coalescence_group, ..
-        } in &mut events
-        {
-            if let Some(coalescence_group) = coalescence_group {
-                if let Ok(new_coalescence_group) =
-                    &mut mustache::compile_str(coalescence_group)
-                        .and_then(|template| template.render_to_string(info))
-                {
-                    *coalescence_group = new_coalescence_group.clone();
-                }
-            }
-        }
+        #[cfg(feature = "regex")]
+        let captures = ctx.captures.take();
+        #[cfg(feature = "regex")]
+        let info = &*with_captures(info, &captures);
+        let info = match ctx.max_matched_values {
+            Some(_) => with_matched_values(info, &condition_result),
+            None => Cow::Borrowed(info),
+        };
+        let info = &*info;
+        let info = &*with_vars(info, ctx.variables);
+        let events = render_events(&self.events, info, ctx.plan);
+        let facts_to_add = match condition_result.status {
+            Status::Met if !self.facts_to_add.is_empty() => self
+                .facts_to_add
+                .iter()
+                .map(|(k, v)| {
+                    (k.clone(), render_value(v, info, EscapeMode::None))
+                })
+                .collect(),
+            _ => Map::new(),
+        };
 
         RuleResult {
+            rule_id: self.id.clone(),
             condition_result,
             events,
+            facts_to_add,
+            patches: Vec::new(),
+            evaluated_at: 0,
+            duration_micros: 0,
+            #[cfg(feature = "regex")]
+            captures,
+        }
+    }
+}
+
+/// The facts with the regex captures of a rule under `_captures`, for the
+/// templates rendered while evaluating it
+#[cfg(feature = "regex")]
+pub(crate) fn with_captures<'v>(
+    facts: &'v Value,
+    captures: &Map<String, Value>,
+) -> Cow<'v, Value> {
+    match facts {
+        Value::Object(facts) if !captures.is_empty() => {
+            let mut facts = facts.clone();
+            facts.insert(
+                "_captures".to_string(),
+                Value::Object(captures.clone()),
+            );
+            Cow::Owned(Value::Object(facts))
+        }
+        _ => Cow::Borrowed(facts),
+    }
+}
+
+/// The elements of the array facts the leaves of a result collected, by
+/// their field, see `Engine::set_collect_matches`
+pub(crate) fn matched_values(result: &ConditionResult) -> Map<String, Value> {
+    let mut matched = Map::new();
+    for leaf in result.iter().filter(|node| !node.matched_values.is_empty()) {
+        let values = matched
+            .entry(leaf.name.clone())
+            .or_insert_with(|| Value::Array(Vec::new()));
+        if let Value::Array(values) = values {
+            values.extend(leaf.matched_values.iter().cloned());
+        }
+    }
+    matched
+}
+
+/// The facts with the elements the leaves of a rule collected under
+/// `_matched_values`, for the templates rendered while evaluating it
+pub(crate) fn with_matched_values<'v>(
+    facts: &'v Value,
+    result: &ConditionResult,
+) -> Cow<'v, Value> {
+    let matched = matched_values(result);
+    match facts {
+        Value::Object(facts) if !matched.is_empty() => {
+            let mut facts = facts.clone();
+            facts.insert("_matched_values".to_string(), Value::Object(matched));
+            Cow::Owned(Value::Object(facts))
         }
+        _ => Cow::Borrowed(facts),
     }
 }
 
-#[derive(Debug, Serialize, Deserialize)]
+/// The facts with the engine's variables under `vars`, as in
+/// `{{ vars.support_url }}`, for the templates rendered while evaluating a
+/// rule. A fact named `vars` wins, the name being reserved for them
+pub(crate) fn with_vars<'v>(
+    facts: &'v Value,
+    variables: &HashMap<String, Value>,
+) -> Cow<'v, Value> {
+    match facts {
+        Value::Object(facts)
+            if !variables.is_empty() && !facts.contains_key("vars") =>
+        {
+            let mut facts = facts.clone();
+            facts.insert(
+                "vars".to_string(),
+                Value::Object(
+                    variables
+                        .iter()
+                        .map(|(k, v)| (k.clone(), v.clone()))
+                        .collect(),
+                ),
+            );
+            Cow::Owned(Value::Object(facts))
+        }
+        _ => Cow::Borrowed(facts),
+    }
+}
+
+/// Clones the events, rendering their coalescence groups against the facts
+/// and their `app_data`
+pub(crate) fn render_events(
+    events: &[CoalescenceEvent],
+    info: &Value,
+    plan: Option<&RulePlan>,
+) -> Vec<CoalescenceEvent> {
+    let mut events = events.to_vec();
+
+    for CoalescenceEvent {
+        coalescence_group,
+        event,
+        ..
+    } in &mut events
+    {
+        if let Some(coalescence_group) = coalescence_group {
+            let info = with_app_data(&event.params, info);
+            if let Ok(new_coalescence_group) =
+                &mut render(plan, coalescence_group, &info)
+            {
+                *coalescence_group = new_coalescence_group.clone();
+            }
+        }
+    }
+
+    events
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
 pub struct RuleResult {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub rule_id: Option<String>,
     pub condition_result: ConditionResult,
     pub events: Vec<CoalescenceEvent>,
+    /// When the rule was evaluated, in milliseconds since the unix epoch
+    #[serde(default)]
+    pub evaluated_at: u64,
+    /// How long evaluating the rule's conditions took
+    #[serde(default)]
+    pub duration_micros: u64,
+    /// The named groups of the `string_matches` regexes the facts matched,
+    /// by the label of their leaf, or its field without one, e.g.
+    /// `{"order_id": {"region": "EU"}}`. The templates of the events see
+    /// them under `_captures`, as in `{{ _captures.order_id.region }}`
+    #[cfg(feature = "regex")]
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub captures: Map<String, Value>,
+    /// The rule's `facts_to_add` rendered against the facts, when met
+    #[serde(default, skip_serializing_if = "Map::is_empty")]
+    pub facts_to_add: Map<String, Value>,
+    /// What became of the patches of its `apply_json_patch` events, in
+    /// order, when met, see `ApplyJsonPatch`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub patches: Vec<PatchOutcome>,
+}
+
+impl RuleResult {
+    /// The failure messages of the conditions not met, depth-first, as
+    /// rendered against the facts. Conditions without a `failure_message`
+    /// have none
+    pub fn failure_messages(&self) -> Vec<String> {
+        self.condition_result
+            .iter()
+            .filter_map(|result| result.failure_message.clone())
+            .collect()
+    }
+}
+
+/// How the events of a `RuleGroup` are emitted
+#[derive(
+    Debug, Default, Eq, PartialEq, Copy, Clone, Serialize, Deserialize,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum GroupEmit {
+    /// The group's events are added to every met member rule
+    #[default]
+    PerRule,
+    /// The group's events fire at most once per run, if any member is met
+    OncePerRun,
+}
+
+/// Rules sharing a single set of events
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct RuleGroup {
+    pub id: String,
+    pub rules: Vec<Rule>,
+    #[serde(default)]
+    pub events: Vec<CoalescenceEvent>,
+    #[serde(default)]
+    pub emit: GroupEmit,
+}
+
+/// Events fired by a `OncePerRun` group, along with the ids of its met
+/// members (or their index in the group when they have no id)
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct GroupResult {
+    pub id: String,
+    pub matched_rules: Vec<String>,
+    pub events: Vec<CoalescenceEvent>,
 }
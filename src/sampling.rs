@@ -0,0 +1,71 @@
+//! Rules applied to a share of the facts only, e.g. to roll a new rule out
+//! to 10% of the users, see `Rule::sample`.
+//!
+//! A rule is applied or not depending on the value of its `key_field` only,
+//! so the same user always gets the same decision, and the users a rule
+//! applies to at 10% are still among those it applies to at 20%. The value,
+//! a string as is and anything else as compact JSON, is hashed with 64-bit
+//! FNV-1a, and the hash modulo 10000 over 100 gives its bucket, from 0 to
+//! 100 excluded. The rule applies when the bucket is under `percent`. This
+//! mapping is part of the format of the rules, and won't change between
+//! releases.
+
+use crate::{
+    condition::{node_path, PathSyntax},
+    rule::Rule,
+};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+const FNV_OFFSET_BASIS: u64 = 0xcbf2_9ce4_8422_2325;
+const FNV_PRIME: u64 = 0x0000_0100_0000_01b3;
+
+/// Applies a rule to a share of the facts, see the `sampling` module
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Sample {
+    /// The share of the keys the rule applies to, from 0 to 100
+    pub percent: f64,
+    /// The fact telling whether the rule applies, addressed as the field of
+    /// a leaf is
+    pub key_field: String,
+    /// Applies the rule to the facts without the key, or with a null one.
+    /// They're left out by default
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub include_missing: bool,
+}
+
+fn fnv1a(bytes: &[u8]) -> u64 {
+    bytes.iter().fold(FNV_OFFSET_BASIS, |hash, &byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(FNV_PRIME)
+    })
+}
+
+/// The bucket of a key, from 0 to 100 excluded, see the `sampling` module
+pub fn sample_bucket(key: &Value) -> f64 {
+    let hash = match key {
+        Value::String(s) => fnv1a(s.as_bytes()),
+        _ => fnv1a(key.to_string().as_bytes()),
+    };
+    (hash % 10_000) as f64 / 100.0
+}
+
+impl Sample {
+    /// Whether the rule applies to the facts
+    pub fn includes(&self, facts: &Value) -> bool {
+        let path =
+            node_path(&self.key_field, false, PathSyntax::Pointer, facts);
+        match facts.pointer(&path) {
+            None | Some(Value::Null) => self.include_missing,
+            Some(key) => sample_bucket(key) < self.percent,
+        }
+    }
+}
+
+impl Rule {
+    /// Whether the rule applies to the facts, always without a `sample`
+    pub(crate) fn sampled_in(&self, facts: &Value) -> bool {
+        self.sample
+            .as_ref()
+            .is_none_or(|sample| sample.includes(facts))
+    }
+}
@@ -0,0 +1,310 @@
+use crate::{
+    condition::PathSyntax, Condition, Constraint, Engine, Rule, ValueOrVar,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+/// A field referenced by a rule that the facts type doesn't declare, or
+/// declares with a type the constraint can't be met with
+#[derive(Clone, Debug, Eq, PartialEq, Serialize, Deserialize)]
+pub struct FieldMismatch {
+    /// The rule's id, or its index when it has none
+    pub rule_id: String,
+    pub pointer: String,
+    /// The JSON type the constraint expects
+    pub expected: String,
+    /// The JSON types the facts type declares, or `missing`
+    pub found: String,
+}
+
+/// The JSON type a fact must have for the constraint to be met, `None` when
+/// facts of any type may be
+fn expected_type(constraint: &Constraint) -> Option<&'static str> {
+    Some(match constraint {
+        Constraint::StringEquals(_)
+        | Constraint::StringNotEquals(_)
+        | Constraint::StringIn(_)
+        | Constraint::StringNotIn(_)
+        | Constraint::StringFuzzyMatches { .. }
+        | Constraint::StringSimilarityAtLeast { .. }
+        | Constraint::StringInNamedSet(_)
+        | Constraint::StringNotInNamedSet(_)
+        | Constraint::DatetimeWithinLast(_)
+        | Constraint::DatetimeOlderThan(_)
+        | Constraint::DurationGreaterThan(_)
+        | Constraint::DurationLessThan(_)
+        | Constraint::DurationInRange(_, _)
+        | Constraint::IsUuid(_)
+        | Constraint::IsUlid(_)
+        | Constraint::IsEmail(_)
+        | Constraint::IsUrl(_)
+        | Constraint::DomainEquals(_)
+        | Constraint::DomainIn(_)
+        | Constraint::DomainMatchesWildcard(_)
+        | Constraint::EmailDomainIn(_) => "string",
+        #[cfg(feature = "regex")]
+        Constraint::StringMatches(_)
+        | Constraint::StringMatchesAny(_)
+        | Constraint::StringMatchesNone(_) => "string",
+        Constraint::IntEquals(_)
+        | Constraint::IntNotEquals(_)
+        | Constraint::IntIn(_)
+        | Constraint::IntNotIn(_)
+        | Constraint::IntInNamedSet(_)
+        | Constraint::IntNotInNamedSet(_)
+        | Constraint::IntInRange(_, _)
+        | Constraint::IntNotInRange(_, _)
+        | Constraint::IntLessThan(_)
+        | Constraint::IntLessThanInclusive(_)
+        | Constraint::IntGreaterThan(_)
+        | Constraint::IntGreaterThanInclusive(_)
+        | Constraint::UintEquals(_)
+        | Constraint::UintNotEquals(_)
+        | Constraint::UintIn(_)
+        | Constraint::UintNotIn(_)
+        | Constraint::UintInRange(_, _)
+        | Constraint::UintNotInRange(_, _)
+        | Constraint::UintLessThan(_)
+        | Constraint::UintLessThanInclusive(_)
+        | Constraint::UintGreaterThan(_)
+        | Constraint::UintGreaterThanInclusive(_) => "integer",
+        Constraint::FloatEquals(_)
+        | Constraint::FloatNotEquals(_)
+        | Constraint::FloatEqualsRounded { .. }
+        | Constraint::FloatIn(_)
+        | Constraint::FloatNotIn(_)
+        | Constraint::FloatInRange(_, _)
+        | Constraint::FloatNotInRange(_, _)
+        | Constraint::FloatLessThan(_)
+        | Constraint::FloatLessThanInclusive(_)
+        | Constraint::FloatGreaterThan(_)
+        | Constraint::FloatGreaterThanInclusive(_)
+        | Constraint::NumberEquals(_)
+        | Constraint::NumberNotEquals(_)
+        | Constraint::NumberIn(_)
+        | Constraint::NumberNotIn(_)
+        | Constraint::NumberInRange(_, _)
+        | Constraint::NumberNotInRange(_, _)
+        | Constraint::NumberLessThan(_)
+        | Constraint::NumberLessThanInclusive(_)
+        | Constraint::NumberGreaterThan(_)
+        | Constraint::NumberGreaterThanInclusive(_) => "number",
+        Constraint::StringContains(_)
+        | Constraint::StringContainsAll(_)
+        | Constraint::StringContainsAny(_)
+        | Constraint::StringDoesNotContain(_)
+        | Constraint::StringDoesNotContainAny(_)
+        | Constraint::IntContains(_)
+        | Constraint::IntContainsAll(_)
+        | Constraint::IntContainsAny(_)
+        | Constraint::IntDoesNotContain(_)
+        | Constraint::IntDoesNotContainAny(_)
+        | Constraint::IntIsSubset(_)
+        | Constraint::IntIsSuperset(_)
+        | Constraint::FloatContains(_)
+        | Constraint::FloatDoesNotContain(_)
+        | Constraint::FloatIsSubset(_)
+        | Constraint::FloatIsSuperset(_)
+        | Constraint::FloatPercentileGreaterThan { .. }
+        | Constraint::FloatPercentileLessThan { .. }
+        | Constraint::ArrayAllUnique(_)
+        | Constraint::ArrayDistinctCountGreaterThanInclusive(_)
+        | Constraint::AnyMatch(_)
+        | Constraint::NoneMatch(_) => "array",
+        Constraint::BoolEquals(_) => "boolean",
+        Constraint::ValueEquals(value) => match value {
+            Value::Bool(_) => "boolean",
+            Value::Number(_) => "number",
+            Value::String(_) => "string",
+            Value::Array(_) => "array",
+            Value::Object(_) => "object",
+            Value::Null => return None,
+        },
+        Constraint::ValueNotEquals(_) | Constraint::ValueIn(_) => return None,
+    })
+}
+
+/// Follows a local `$ref`, if any
+fn resolve<'a>(root: &'a Value, schema: &'a Value) -> &'a Value {
+    match schema.get("$ref").and_then(Value::as_str) {
+        Some(r) if r.starts_with('#') => root
+            .pointer(&r[1..])
+            .map_or(schema, |schema| resolve(root, schema)),
+        _ => schema,
+    }
+}
+
+fn alternatives<'a>(
+    root: &'a Value,
+    schema: &'a Value,
+) -> impl Iterator<Item = &'a Value> {
+    ["anyOf", "oneOf", "allOf"]
+        .iter()
+        .filter_map(move |k| schema.get(*k).and_then(Value::as_array))
+        .flatten()
+        .map(move |schema| resolve(root, schema))
+}
+
+/// The non null types a schema allows. Empty means anything goes
+fn types<'a>(root: &'a Value, schema: &'a Value) -> Vec<&'a str> {
+    let schema = resolve(root, schema);
+
+    let mut types = match schema.get("type") {
+        Some(Value::String(ty)) => vec![ty.as_str()],
+        Some(Value::Array(tys)) => {
+            tys.iter().filter_map(Value::as_str).collect()
+        }
+        _ => alternatives(root, schema)
+            .flat_map(|schema| types(root, schema))
+            .collect(),
+    };
+    types.retain(|ty| *ty != "null");
+    types.dedup();
+    types
+}
+
+fn child<'a>(
+    root: &'a Value,
+    schema: &'a Value,
+    token: &str,
+) -> Option<&'a Value> {
+    let schema = resolve(root, schema);
+
+    if let Some(property) = schema.get("properties").and_then(|p| p.get(token))
+    {
+        return Some(property);
+    }
+
+    if token.parse::<usize>().is_ok() {
+        if let Some(items) = schema.get("items") {
+            return Some(items);
+        }
+    }
+
+    alternatives(root, schema).find_map(|schema| child(root, schema, token))
+}
+
+/// Splits a leaf's field into pointer tokens, the same way it's resolved
+/// against facts
+fn tokens(
+    field: &str,
+    pointer: bool,
+    path_syntax: PathSyntax,
+    root: &Value,
+) -> Vec<String> {
+    if pointer {
+        return field
+            .split('/')
+            .skip(1)
+            .map(|token| token.replace("~1", "/").replace("~0", "~"))
+            .collect();
+    }
+
+    if child(root, root, field).is_some() {
+        return vec![field.to_owned()];
+    }
+
+    match path_syntax {
+        PathSyntax::Pointer => field
+            .trim_start_matches('/')
+            .split('/')
+            .map(ToOwned::to_owned)
+            .collect(),
+        PathSyntax::Dotted => field.split('.').map(ToOwned::to_owned).collect(),
+    }
+}
+
+fn validate_rule(
+    rule_id: String,
+    rule: &Rule,
+    root: &Value,
+) -> Vec<FieldMismatch> {
+    let mut mismatches = Vec::new();
+
+    for leaf in rule.conditions.leaves() {
+        if let Condition::Condition {
+            field,
+            constraint,
+            path,
+            pointer,
+            path_syntax,
+            ..
+        } = leaf
+        {
+            // a variable's value is only known when evaluated
+            let constraint = match constraint {
+                ValueOrVar::Value(constraint) => constraint,
+                ValueOrVar::Var { .. } => continue,
+            };
+            let expected = match expected_type(constraint) {
+                Some(expected) => expected,
+                None => continue,
+            };
+            let node = tokens(field, *pointer, *path_syntax, root)
+                .iter()
+                .try_fold(root, |schema, token| child(root, schema, token));
+
+            let found = match node {
+                None => "missing".to_string(),
+                // a json path reshapes the field before the constraint
+                // sees it
+                Some(_) if path.is_some() => continue,
+                Some(node) => {
+                    let found = types(root, node);
+                    let compatible = found.is_empty()
+                        || found.contains(&expected)
+                        || (expected == "number" && found.contains(&"integer"))
+                        // durations may be given in seconds as well
+                        || (matches!(
+                            constraint,
+                            Constraint::DurationGreaterThan(_)
+                                | Constraint::DurationLessThan(_)
+                                | Constraint::DurationInRange(_, _)
+                        ) && found.contains(&"integer"));
+                    if compatible {
+                        continue;
+                    }
+                    found.join(" | ")
+                }
+            };
+
+            mismatches.push(FieldMismatch {
+                rule_id: rule_id.clone(),
+                pointer: field.clone(),
+                expected: expected.to_string(),
+                found,
+            });
+        }
+    }
+
+    mismatches
+}
+
+impl Engine {
+    /// Checks that every field referenced by the engine's rules is declared
+    /// by `T`, with a type its constraint can be met with
+    pub fn validate_rules_against<T: JsonSchema>(&self) -> Vec<FieldMismatch> {
+        let root = schemars::schema_for!(T).to_value();
+
+        // group members without an id are named after their group, so they
+        // can't be mistaken for the engine's own rules
+        let rules =
+            self.rules
+                .iter()
+                .enumerate()
+                .map(|(i, rule)| (i.to_string(), rule))
+                .chain(self.rule_groups.iter().flat_map(|group| {
+                    group.rules.iter().enumerate().map(move |(i, rule)| {
+                        (format!("{}/{}", group.id, i), rule)
+                    })
+                }));
+
+        rules
+            .flat_map(|(index, rule)| {
+                let rule_id = rule.id.clone().unwrap_or(index);
+                validate_rule(rule_id, rule, &root)
+            })
+            .collect()
+    }
+}
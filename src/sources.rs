@@ -0,0 +1,65 @@
+//! Facts split across sources, e.g. a user profile, a session and a device,
+//! see `Engine::run_multi`.
+//!
+//! Each source is mounted under its name in the facts the rules run
+//! against, so conditions address `profile/age` or `session/ip`, templates
+//! `{{profile.name}}`, and where a fact came from isn't lost. Rules written
+//! against the sources merged into one document keep working once their top
+//! level keys are merged in too, see `Engine::set_merge_sources`.
+
+use crate::{
+    error::{Error, Result},
+    Engine, RuleResult,
+};
+use serde_json::{Map, Value};
+
+impl Engine {
+    /// Also merges the top level keys of the sources `run_multi` mounts
+    /// into the facts, beside the mounts, the later sources overriding the
+    /// earlier ones. A key named after a mount fails the run. Off by default
+    pub fn set_merge_sources(&mut self, merge_sources: bool) {
+        self.merge_sources = merge_sources;
+    }
+
+    /// Same as `run`, against the sources mounted under their names, e.g.
+    /// `{"profile": {..}, "session": {..}}`. Two sources of the same name
+    /// fail the run with `Error::SourceError`
+    pub async fn run_multi(
+        &mut self,
+        sources: &[(&str, &Value)],
+    ) -> Result<Vec<RuleResult>> {
+        let facts = self.mount_sources(sources)?;
+        Ok(self.run_facts(None, None, facts, None::<&Value>).await?.0)
+    }
+
+    /// The facts the sources make, as `run_multi` runs against them
+    fn mount_sources(&self, sources: &[(&str, &Value)]) -> Result<Value> {
+        let mut mounts = Map::new();
+        for (name, source) in sources {
+            if mounts.insert(name.to_string(), (*source).clone()).is_some() {
+                return Err(Error::SourceError(format!(
+                    "`{}` is mounted twice",
+                    name
+                )));
+            }
+        }
+        if !self.merge_sources {
+            return Ok(Value::Object(mounts));
+        }
+
+        let mut facts = Map::new();
+        for (name, source) in sources {
+            for (key, fact) in source.as_object().into_iter().flatten() {
+                if mounts.contains_key(key) {
+                    return Err(Error::SourceError(format!(
+                        "`{}` of `{}` is named after a source",
+                        key, name
+                    )));
+                }
+                facts.insert(key.clone(), fact.clone());
+            }
+        }
+        facts.extend(mounts);
+        Ok(Value::Object(facts))
+    }
+}
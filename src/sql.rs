@@ -0,0 +1,421 @@
+//! Conditions as parameterized SQL `WHERE` clauses, to pre-filter rows in a
+//! database before running the engine on them.
+//!
+//! Only equality, membership, range and comparison constraints on mapped
+//! columns can be expressed. `Condition::to_sql` refuses anything else, while
+//! `Condition::to_sql_lossy` widens it to match any row, so that the clause
+//! keeps every row the engine could still match.
+
+use crate::{
+    condition::Condition,
+    constraint::{Constraint, ValueOrVar},
+    error::{Error, Result},
+};
+use serde::Serialize;
+use serde_json::Number;
+
+/// How parameters are written in the generated SQL
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SqlDialect {
+    /// `$1`, `$2`, ...
+    Postgres,
+    /// `?`
+    MySql,
+}
+
+/// A parameter of a generated clause, in placeholder order
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(untagged)]
+pub enum SqlParam {
+    Bool(bool),
+    Int(i64),
+    Uint(u64),
+    Float(f64),
+    String(String),
+}
+
+impl From<&Number> for SqlParam {
+    fn from(n: &Number) -> Self {
+        match (n.as_i64(), n.as_u64()) {
+            (Some(i), _) => SqlParam::Int(i),
+            (None, Some(u)) => SqlParam::Uint(u),
+            _ => SqlParam::Float(n.as_f64().unwrap_or_default()),
+        }
+    }
+}
+
+/// A clause generated by `Condition::to_sql_lossy`
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct SqlWhere {
+    pub clause: String,
+    pub params: Vec<SqlParam>,
+    /// Whether parts of the condition couldn't be expressed and were
+    /// dropped, the clause then matching more rows than the condition
+    pub lossy: bool,
+}
+
+/// The SQL shape of a constraint
+enum Predicate {
+    Compare(&'static str, SqlParam),
+    In(bool, Vec<SqlParam>),
+    Between(bool, SqlParam, SqlParam),
+}
+
+fn predicate(constraint: &Constraint) -> Option<Predicate> {
+    use Predicate::*;
+    use SqlParam::*;
+
+    Some(match constraint {
+        Constraint::StringEquals(s) => Compare("=", String(s.clone())),
+        Constraint::StringNotEquals(s) => Compare("<>", String(s.clone())),
+        Constraint::StringIn(ss) => {
+            In(false, ss.iter().cloned().map(String).collect())
+        }
+        Constraint::StringNotIn(ss) => {
+            In(true, ss.iter().cloned().map(String).collect())
+        }
+        Constraint::IntEquals(i) => Compare("=", Int(*i)),
+        Constraint::IntNotEquals(i) => Compare("<>", Int(*i)),
+        Constraint::IntIn(is) => {
+            In(false, is.iter().copied().map(Int).collect())
+        }
+        Constraint::IntNotIn(is) => {
+            In(true, is.iter().copied().map(Int).collect())
+        }
+        Constraint::IntInRange(start, end) => match (start, end) {
+            (Some(start), Some(end)) => Between(false, Int(*start), Int(*end)),
+            (Some(start), None) => Compare(">=", Int(*start)),
+            (None, Some(end)) => Compare("<=", Int(*end)),
+            (None, None) => return None,
+        },
+        Constraint::IntNotInRange(start, end) => {
+            Between(true, Int(*start), Int(*end))
+        }
+        Constraint::IntLessThan(i) => Compare("<", Int(*i)),
+        Constraint::IntLessThanInclusive(i) => Compare("<=", Int(*i)),
+        Constraint::IntGreaterThan(i) => Compare(">", Int(*i)),
+        Constraint::IntGreaterThanInclusive(i) => Compare(">=", Int(*i)),
+        Constraint::UintEquals(u) => Compare("=", Uint(*u)),
+        Constraint::UintNotEquals(u) => Compare("<>", Uint(*u)),
+        Constraint::UintIn(us) => {
+            In(false, us.iter().copied().map(Uint).collect())
+        }
+        Constraint::UintNotIn(us) => {
+            In(true, us.iter().copied().map(Uint).collect())
+        }
+        Constraint::UintInRange(start, end) => {
+            Between(false, Uint(*start), Uint(*end))
+        }
+        Constraint::UintNotInRange(start, end) => {
+            Between(true, Uint(*start), Uint(*end))
+        }
+        Constraint::UintLessThan(u) => Compare("<", Uint(*u)),
+        Constraint::UintLessThanInclusive(u) => Compare("<=", Uint(*u)),
+        Constraint::UintGreaterThan(u) => Compare(">", Uint(*u)),
+        Constraint::UintGreaterThanInclusive(u) => Compare(">=", Uint(*u)),
+        Constraint::FloatEquals(f) => Compare("=", Float(*f)),
+        Constraint::FloatNotEquals(f) => Compare("<>", Float(*f)),
+        Constraint::FloatIn(fs) => {
+            In(false, fs.iter().copied().map(Float).collect())
+        }
+        Constraint::FloatNotIn(fs) => {
+            In(true, fs.iter().copied().map(Float).collect())
+        }
+        Constraint::FloatInRange(start, end) => match (start, end) {
+            (Some(start), Some(end)) => {
+                Between(false, Float(*start), Float(*end))
+            }
+            (Some(start), None) => Compare(">=", Float(*start)),
+            (None, Some(end)) => Compare("<=", Float(*end)),
+            (None, None) => return None,
+        },
+        Constraint::FloatNotInRange(start, end) => {
+            Between(true, Float(*start), Float(*end))
+        }
+        Constraint::FloatLessThan(f) => Compare("<", Float(*f)),
+        Constraint::FloatLessThanInclusive(f) => Compare("<=", Float(*f)),
+        Constraint::FloatGreaterThan(f) => Compare(">", Float(*f)),
+        Constraint::FloatGreaterThanInclusive(f) => Compare(">=", Float(*f)),
+        Constraint::NumberEquals(n) => Compare("=", n.into()),
+        Constraint::NumberNotEquals(n) => Compare("<>", n.into()),
+        Constraint::NumberIn(ns) => {
+            In(false, ns.iter().map(Into::into).collect())
+        }
+        Constraint::NumberNotIn(ns) => {
+            In(true, ns.iter().map(Into::into).collect())
+        }
+        Constraint::NumberInRange(start, end) => {
+            Between(false, start.into(), end.into())
+        }
+        Constraint::NumberNotInRange(start, end) => {
+            Between(true, start.into(), end.into())
+        }
+        Constraint::NumberLessThan(n) => Compare("<", n.into()),
+        Constraint::NumberLessThanInclusive(n) => Compare("<=", n.into()),
+        Constraint::NumberGreaterThan(n) => Compare(">", n.into()),
+        Constraint::NumberGreaterThanInclusive(n) => Compare(">=", n.into()),
+        Constraint::BoolEquals(b) => Compare("=", Bool(*b)),
+        _ => return None,
+    })
+}
+
+struct SqlBuilder<'a> {
+    dialect: SqlDialect,
+    column_mapper: &'a dyn Fn(&str) -> Option<String>,
+    params: Vec<SqlParam>,
+    /// Drop what can't be expressed rather than failing
+    lossy: bool,
+    dropped: bool,
+}
+
+impl SqlBuilder<'_> {
+    fn param(&mut self, param: SqlParam) -> String {
+        self.params.push(param);
+        match self.dialect {
+            SqlDialect::Postgres => format!("${}", self.params.len()),
+            SqlDialect::MySql => "?".to_owned(),
+        }
+    }
+
+    fn unsupported(&mut self, reason: String) -> Result<Option<String>> {
+        if self.lossy {
+            self.dropped = true;
+            Ok(None)
+        } else {
+            Err(Error::SqlError(reason))
+        }
+    }
+
+    /// The clause for a node, `None` if it was dropped and matches any row
+    fn node(&mut self, condition: &Condition) -> Result<Option<String>> {
+        // the parameters of a dropped node must go with it
+        let start = self.params.len();
+        let clause = self.clause(condition)?;
+        if clause.is_none() {
+            self.params.truncate(start);
+        }
+        Ok(clause)
+    }
+
+    fn clause(&mut self, condition: &Condition) -> Result<Option<String>> {
+        match condition {
+            Condition::And { and, .. } => {
+                let mut clauses = Vec::new();
+                for c in and {
+                    clauses.extend(self.node(c)?);
+                }
+                Ok(match clauses.len() {
+                    0 if !and.is_empty() => None,
+                    0 => Some("TRUE".to_owned()),
+                    1 => clauses.pop(),
+                    _ => Some(format!("({})", clauses.join(" AND "))),
+                })
+            }
+            Condition::Or { or, .. } => {
+                let mut clauses = Vec::new();
+                for c in or {
+                    match self.node(c)? {
+                        Some(clause) => clauses.push(clause),
+                        None => return Ok(None),
+                    }
+                }
+                Ok(match clauses.len() {
+                    0 => Some("FALSE".to_owned()),
+                    1 => clauses.pop(),
+                    _ => Some(format!("({})", clauses.join(" OR "))),
+                })
+            }
+            Condition::Not { not, .. } => {
+                // negating a widened clause would narrow it instead
+                let dropped = std::mem::replace(&mut self.dropped, false);
+                let clause = self.node(not)?;
+                let widened = self.dropped;
+                self.dropped |= dropped;
+
+                Ok(clause
+                    .filter(|_| !widened)
+                    .map(|clause| format!("NOT ({})", clause)))
+            }
+            Condition::AtLeast {
+                should_minimum_meet,
+                conditions,
+                ..
+            } => {
+                if *should_minimum_meet == 0 {
+                    return Ok(Some("TRUE".to_owned()));
+                }
+
+                let mut terms = Vec::new();
+                for c in conditions {
+                    // a dropped condition counts as met
+                    terms.push(match self.node(c)? {
+                        Some(clause) => {
+                            format!("CASE WHEN {} THEN 1 ELSE 0 END", clause)
+                        }
+                        None => "1".to_owned(),
+                    });
+                }
+                if terms.is_empty() {
+                    return Ok(Some("FALSE".to_owned()));
+                }
+
+                Ok(Some(format!(
+                    "({}) >= {}",
+                    terms.join(" + "),
+                    should_minimum_meet
+                )))
+            }
+            Condition::Condition {
+                field,
+                constraint,
+                path,
+                templated_value,
+                default,
+                ..
+            } => {
+                if path.is_some() || *templated_value {
+                    return self.unsupported(format!(
+                        "`{}` is reshaped or templated",
+                        field
+                    ));
+                }
+                if default.is_some() {
+                    return self
+                        .unsupported(format!("`{}` has a default", field));
+                }
+                #[cfg(feature = "unicode")]
+                if let Condition::Condition {
+                    normalize: Some(_), ..
+                } = condition
+                {
+                    return self
+                        .unsupported(format!("`{}` is normalized", field));
+                }
+
+                let column = match (self.column_mapper)(field) {
+                    Some(column) => column,
+                    None => {
+                        return self
+                            .unsupported(format!("No column for `{}`", field))
+                    }
+                };
+                let constraint = match constraint {
+                    ValueOrVar::Value(constraint) => constraint,
+                    ValueOrVar::Var { value, .. } => {
+                        return self.unsupported(format!(
+                            "`{}` compares to the variable `{}`",
+                            field, value.name
+                        ))
+                    }
+                };
+                let predicate = match predicate(constraint) {
+                    Some(predicate) => predicate,
+                    None => {
+                        return self.unsupported(format!(
+                            "`{}` has a constraint SQL can't express",
+                            field
+                        ))
+                    }
+                };
+
+                Ok(Some(match predicate {
+                    Predicate::Compare(op, param) => {
+                        format!("{} {} {}", column, op, self.param(param))
+                    }
+                    Predicate::In(false, params) if params.is_empty() => {
+                        "FALSE".to_owned()
+                    }
+                    Predicate::In(true, params) if params.is_empty() => {
+                        format!("{} IS NOT NULL", column)
+                    }
+                    Predicate::In(negate, params) => {
+                        let placeholders = params
+                            .into_iter()
+                            .map(|param| self.param(param))
+                            .collect::<Vec<_>>();
+                        format!(
+                            "{} {}IN ({})",
+                            column,
+                            if negate { "NOT " } else { "" },
+                            placeholders.join(", ")
+                        )
+                    }
+                    Predicate::Between(negate, start, end) => format!(
+                        "{} {}BETWEEN {} AND {}",
+                        column,
+                        if negate { "NOT " } else { "" },
+                        self.param(start),
+                        self.param(end)
+                    ),
+                }))
+            }
+            // the history of a frequency node lives on the engine
+            Condition::Frequency { .. } => self.unsupported(
+                "`frequency` conditions can't be expressed".into(),
+            ),
+            #[cfg(feature = "eval")]
+            Condition::Eval { .. } => {
+                self.unsupported("`expr` conditions can't be expressed".into())
+            }
+            #[cfg(feature = "lua")]
+            Condition::LuaEval { .. } => self
+                .unsupported("`script` conditions can't be expressed".into()),
+            #[cfg(feature = "async_predicate")]
+            Condition::AsyncPredicate { .. } => self.unsupported(
+                "`async_predicate` conditions can't be expressed".into(),
+            ),
+        }
+    }
+}
+
+impl Condition {
+    /// A parameterized SQL `WHERE` clause matching the rows this condition
+    /// is met by, columns being named by `column_mapper` from fields, and
+    /// used verbatim so it must quote them if needed. Fails if any part of
+    /// the condition can't be expressed, see `Condition::to_sql_lossy`
+    pub fn to_sql(
+        &self,
+        dialect: SqlDialect,
+        column_mapper: &dyn Fn(&str) -> Option<String>,
+    ) -> Result<(String, Vec<SqlParam>)> {
+        let mut builder = SqlBuilder {
+            dialect,
+            column_mapper,
+            params: Vec::new(),
+            lossy: false,
+            dropped: false,
+        };
+        let clause = builder.node(self)?.unwrap_or_else(|| "TRUE".to_owned());
+
+        Ok((clause, builder.params))
+    }
+
+    /// Same as `Condition::to_sql`, but drops what can't be expressed, a
+    /// dropped node matching any row, so the clause keeps every row the
+    /// condition could be met by
+    pub fn to_sql_lossy(
+        &self,
+        dialect: SqlDialect,
+        column_mapper: &dyn Fn(&str) -> Option<String>,
+    ) -> SqlWhere {
+        let mut builder = SqlBuilder {
+            dialect,
+            column_mapper,
+            params: Vec::new(),
+            lossy: true,
+            dropped: false,
+        };
+        // nothing fails when dropping
+        let clause = builder
+            .node(self)
+            .ok()
+            .flatten()
+            .unwrap_or_else(|| "TRUE".to_owned());
+
+        SqlWhere {
+            clause,
+            params: builder.params,
+            lossy: builder.dropped,
+        }
+    }
+}
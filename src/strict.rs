@@ -0,0 +1,175 @@
+//! Strict deserialization of rules.
+//!
+//! serde ignores unknown fields, and `#[serde(deny_unknown_fields)]` doesn't
+//! work together with the untagged `Condition` and its flattened
+//! `Constraint`, so the raw value is walked instead, looking for keys none of
+//! the rule types know about.
+
+use crate::{
+    error::{Error, Result},
+    rule::Rule,
+};
+use serde_json::Value;
+
+const RULE_KEYS: &[&str] = &[
+    "id",
+    "conditions",
+    "events",
+    "facts_to_add",
+    "enabled",
+    "tags",
+    "sample",
+];
+const EVENT_KEYS: &[&str] = &[
+    "type",
+    "params",
+    "coalescence",
+    "coalescence_group",
+    #[cfg(feature = "delay")]
+    "delay_secs",
+    #[cfg(feature = "delay")]
+    "recheck_before_send",
+];
+const LEAF_KEYS: &[&str] = &[
+    "field",
+    "operator",
+    "value",
+    "path",
+    "pointer",
+    "path_syntax",
+    "templated_value",
+    "default",
+    "label",
+    "failure_message",
+    #[cfg(feature = "unicode")]
+    "normalize",
+];
+
+fn unknown_keys(
+    v: &Value,
+    known: &[&str],
+    pointer: &str,
+    unknown: &mut Vec<String>,
+) {
+    if let Some(obj) = v.as_object() {
+        unknown.extend(
+            obj.keys()
+                .filter(|k| !known.contains(&k.as_str()))
+                .map(|k| {
+                    format!(
+                        "{}/{}",
+                        pointer,
+                        k.replace('~', "~0").replace('/', "~1")
+                    )
+                }),
+        );
+    }
+}
+
+fn check_condition(v: &Value, pointer: &str, unknown: &mut Vec<String>) {
+    let obj = match v.as_object() {
+        Some(obj) => obj,
+        None => return,
+    };
+
+    let children = |key: &str, unknown: &mut Vec<String>| {
+        if let Some(cs) = obj.get(key).and_then(Value::as_array) {
+            for (i, c) in cs.iter().enumerate() {
+                check_condition(
+                    c,
+                    &format!("{}/{}/{}", pointer, key, i),
+                    unknown,
+                );
+            }
+        }
+    };
+
+    if obj.contains_key("and") {
+        unknown_keys(v, &["and", "label", "failure_message"], pointer, unknown);
+        children("and", unknown);
+    } else if obj.contains_key("or") {
+        unknown_keys(v, &["or", "label", "failure_message"], pointer, unknown);
+        children("or", unknown);
+    } else if obj.contains_key("not") {
+        unknown_keys(v, &["not", "label", "failure_message"], pointer, unknown);
+        check_condition(&obj["not"], &format!("{}/not", pointer), unknown);
+    } else if obj.contains_key("of") {
+        unknown_keys(
+            v,
+            &["of", "at_least", "window_secs", "label", "failure_message"],
+            pointer,
+            unknown,
+        );
+        check_condition(&obj["of"], &format!("{}/of", pointer), unknown);
+    } else if obj.contains_key("should_minimum_meet")
+        || obj.contains_key("conditions")
+    {
+        unknown_keys(
+            v,
+            &[
+                "should_minimum_meet",
+                "conditions",
+                "unknown_policy",
+                "label",
+                "failure_message",
+            ],
+            pointer,
+            unknown,
+        );
+        children("conditions", unknown);
+    } else if cfg!(feature = "eval") && obj.contains_key("expr") {
+        unknown_keys(v, &["expr", "label"], pointer, unknown);
+    } else if cfg!(feature = "lua") && obj.contains_key("script") {
+        unknown_keys(v, &["script", "label"], pointer, unknown);
+    } else if cfg!(feature = "async_predicate")
+        && obj.contains_key("async_predicate")
+    {
+        unknown_keys(
+            v,
+            &["async_predicate", "args", "label"],
+            pointer,
+            unknown,
+        );
+    } else {
+        unknown_keys(v, LEAF_KEYS, pointer, unknown);
+        if let Some("any_match" | "none_match") =
+            obj.get("operator").and_then(Value::as_str)
+        {
+            check_condition(
+                &obj["value"],
+                &format!("{}/value", pointer),
+                unknown,
+            );
+        }
+    }
+}
+
+impl Rule {
+    /// Same as `serde_json::from_value`, but refuses keys none of the rule
+    /// types know about, typically misspelled ones, reporting every one of
+    /// them with its JSON pointer
+    pub fn from_value_strict(value: Value) -> Result<Self> {
+        let mut unknown = Vec::new();
+
+        unknown_keys(&value, RULE_KEYS, "", &mut unknown);
+        if let Some(conditions) = value.get("conditions") {
+            check_condition(conditions, "/conditions", &mut unknown);
+        }
+        if let Some(events) = value.get("events").and_then(Value::as_array) {
+            for (i, event) in events.iter().enumerate() {
+                unknown_keys(
+                    event,
+                    EVENT_KEYS,
+                    &format!("/events/{}", i),
+                    &mut unknown,
+                );
+            }
+        }
+
+        if !unknown.is_empty() {
+            return Err(Error::UnknownFieldsError(unknown));
+        }
+
+        Ok(serde_json::from_value(value)?)
+    }
+}
@@ -0,0 +1,217 @@
+//! Short descriptions of an engine and its rules, for logs and health
+//! endpoints.
+//!
+//! `Engine`'s `Debug` lists the ids of the rules rather than their trees, and
+//! the names of the variables rather than their values, as they may hold
+//! tokens or other secrets that don't belong in logs. The params of events,
+//! where credentials usually are, aren't printed either by the one line
+//! `Display` of `Rule` and `Condition`, e.g.
+//!
+//! ```text
+//! rule adult: (age int_greater_than_inclusive 18 and country string_in ["FR","DE"]) -> message
+//! ```
+
+use crate::{condition::Condition, constraint::ValueOrVar, rule::Rule, Engine};
+use serde::Serialize;
+use serde_json::Value;
+use std::{collections::BTreeMap, fmt};
+
+/// The cargo features the crate was compiled with
+const FEATURES: &[(&str, bool)] = &[
+    ("async_predicate", cfg!(feature = "async_predicate")),
+    ("aws", cfg!(feature = "aws")),
+    ("binary", cfg!(feature = "binary")),
+    ("broadcast", cfg!(feature = "broadcast")),
+    ("callback", cfg!(feature = "callback")),
+    ("delay", cfg!(feature = "delay")),
+    ("discord", cfg!(feature = "discord")),
+    ("email", cfg!(feature = "email")),
+    ("eval", cfg!(feature = "eval")),
+    ("lua", cfg!(feature = "lua")),
+    ("path", cfg!(feature = "path")),
+    ("regex", cfg!(feature = "regex")),
+    ("schema", cfg!(feature = "schema")),
+    ("simd", cfg!(feature = "simd")),
+    ("teams", cfg!(feature = "teams")),
+    ("test_util", cfg!(feature = "test_util")),
+    ("unicode", cfg!(feature = "unicode")),
+    ("wasm", cfg!(feature = "wasm")),
+];
+
+/// What an engine holds, see `Engine::summary`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct EngineSummary {
+    /// Rules on the engine, rule group members included
+    pub rule_count: usize,
+    /// The events of the rules and the groups, by type
+    pub event_counts_by_type: BTreeMap<String, usize>,
+    /// The cargo features the crate was compiled with
+    pub features_enabled: Vec<&'static str>,
+}
+
+impl Engine {
+    /// Counts of the rules and their events, e.g. for a health endpoint
+    pub fn summary(&self) -> EngineSummary {
+        let mut event_counts_by_type = BTreeMap::new();
+        let events = self
+            .all_rules()
+            .flat_map(|rule| &rule.events)
+            .chain(self.rule_groups.iter().flat_map(|group| &group.events));
+        for event in events {
+            *event_counts_by_type
+                .entry(event.event.ty.clone())
+                .or_insert(0) += 1;
+        }
+
+        EngineSummary {
+            rule_count: self.all_rules().count(),
+            event_counts_by_type,
+            features_enabled: FEATURES
+                .iter()
+                .filter(|(_, enabled)| *enabled)
+                .map(|(feature, _)| *feature)
+                .collect(),
+        }
+    }
+
+    /// The rules, then the members of the groups
+    fn all_rules(&self) -> impl Iterator<Item = &Rule> {
+        self.rules
+            .iter()
+            .chain(self.rule_groups.iter().flat_map(|group| &group.rules))
+    }
+}
+
+impl fmt::Debug for Engine {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let mut event_types: Vec<_> = self.events.keys().collect();
+        event_types.sort();
+        let mut variables: Vec<_> = self.variables.keys().collect();
+        variables.sort();
+
+        f.debug_struct("Engine")
+            .field("rules", &self.rules.len())
+            .field(
+                "rule_ids",
+                &self
+                    .rules
+                    .iter()
+                    .map(|rule| rule.id.as_deref())
+                    .collect::<Vec<_>>(),
+            )
+            .field(
+                "rule_groups",
+                &self
+                    .rule_groups
+                    .iter()
+                    .map(|group| &group.id)
+                    .collect::<Vec<_>>(),
+            )
+            .field("event_types", &event_types)
+            .field("variables", &variables)
+            .field("limits", &self.limits)
+            .field("error_mode", &self.error_mode)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Writes the conditions separated by `separator`
+fn join(
+    f: &mut fmt::Formatter<'_>,
+    conditions: &[Condition],
+    separator: &str,
+) -> fmt::Result {
+    for (i, condition) in conditions.iter().enumerate() {
+        if i > 0 {
+            f.write_str(separator)?;
+        }
+        write!(f, "{}", condition)?;
+    }
+    Ok(())
+}
+
+impl fmt::Display for Condition {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Condition::And { and, .. } => {
+                f.write_str("(")?;
+                join(f, and, " and ")?;
+                f.write_str(")")
+            }
+            Condition::Or { or, .. } => {
+                f.write_str("(")?;
+                join(f, or, " or ")?;
+                f.write_str(")")
+            }
+            Condition::Not { not, .. } => write!(f, "not {}", not),
+            Condition::AtLeast {
+                should_minimum_meet,
+                conditions,
+                ..
+            } => {
+                write!(f, "at least {} of (", should_minimum_meet)?;
+                join(f, conditions, ", ")?;
+                f.write_str(")")
+            }
+            Condition::Frequency {
+                of,
+                at_least,
+                window_secs,
+                ..
+            } => write!(
+                f,
+                "{} at least {} times in {}s",
+                of, at_least, window_secs
+            ),
+            Condition::Condition {
+                field, constraint, ..
+            } => {
+                write!(f, "{} {} ", field, constraint.operator())?;
+                match constraint {
+                    ValueOrVar::Value(constraint) => {
+                        let value = serde_json::to_value(constraint)
+                            .ok()
+                            .and_then(|mut c| {
+                                c.get_mut("value").map(Value::take)
+                            })
+                            .unwrap_or(Value::Null);
+                        write!(f, "{}", value)
+                    }
+                    ValueOrVar::Var { value, .. } => {
+                        write!(f, "${}", value.name)
+                    }
+                }
+            }
+            #[cfg(feature = "eval")]
+            Condition::Eval { expr, .. } => write!(f, "expr `{}`", expr),
+            #[cfg(feature = "lua")]
+            Condition::LuaEval { .. } => f.write_str("lua script"),
+            #[cfg(feature = "async_predicate")]
+            Condition::AsyncPredicate { name, .. } => {
+                write!(f, "async predicate {}", name)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "rule {}: {}",
+            self.id.as_deref().unwrap_or("(no id)"),
+            self.conditions
+        )?;
+        if !self.events.is_empty() {
+            f.write_str(" ->")?;
+            for (i, event) in self.events.iter().enumerate() {
+                let separator = if i == 0 { " " } else { ", " };
+                write!(f, "{}{}", separator, event.event.ty)?;
+            }
+        }
+        if !self.enabled {
+            f.write_str(" (disabled)")?;
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,177 @@
+//! Rules of several tenants sharing one engine, see `Engine::add_rule_for`.
+//!
+//! A tenant's rules only run through `Engine::run_for`, and `Engine::run`
+//! only runs the rules without a tenant. Coalescence groups and rate limits
+//! are kept apart per tenant, their keys being prefixed with the tenant, so
+//! two tenants whose events coalesce in groups of the same name don't
+//! suppress each other's events.
+
+use crate::{error::Result, rule::Rule, Engine, RuleResult, RunInfo};
+use serde::Serialize;
+use std::{borrow::Cow, collections::HashSet};
+
+/// Separates the tenant from the key it prefixes, a control character no
+/// tenant nor coalescence group is expected to hold
+const TENANT_SEPARATOR: char = '\u{1f}';
+
+/// The key of a coalescence group or rate limit for the tenant
+pub(crate) fn tenant_key<'k>(
+    tenant: Option<&str>,
+    key: &'k str,
+) -> Cow<'k, str> {
+    match tenant {
+        Some(tenant) => {
+            Cow::Owned(format!("{}{}{}", tenant, TENANT_SEPARATOR, key))
+        }
+        None => Cow::Borrowed(key),
+    }
+}
+
+/// The event type of a tenant's rate limit key, `None` for the key of a
+/// rate limit without a tenant
+pub(crate) fn tenant_event_type(key: &str) -> Option<&str> {
+    key.split_once(TENANT_SEPARATOR).map(|(_, ty)| ty)
+}
+
+/// Whether the key of a coalescence group or rate limit is the tenant's
+fn is_tenant_key(tenant: &str, key: &str) -> bool {
+    key.split_once(TENANT_SEPARATOR)
+        .is_some_and(|(prefix, _)| prefix == tenant)
+}
+
+impl Engine {
+    /// Adds a rule only `run_for` the tenant evaluates
+    pub fn add_rule_for(&mut self, tenant: &str, rule: Rule) {
+        self.add_rule(rule);
+        self.tenants
+            .entry(tenant.to_owned())
+            .or_default()
+            .push(self.rules.len() - 1);
+    }
+
+    /// Runs the rules of the tenant against the facts, as `run` does the
+    /// rules without a tenant. Rule groups have no tenant, so aren't run
+    pub async fn run_for<T: Serialize>(
+        &mut self,
+        tenant: &str,
+        facts: &T,
+    ) -> Result<Vec<RuleResult>> {
+        Ok(self.run_for_with_info(tenant, facts).await?.0)
+    }
+
+    /// Same as `run_for`, but also reports about the run as `run_with_info`
+    /// does
+    pub async fn run_for_with_info<T: Serialize>(
+        &mut self,
+        tenant: &str,
+        facts: &T,
+    ) -> Result<(Vec<RuleResult>, RunInfo)> {
+        self.run_scoped(Some(tenant), None, facts).await
+    }
+
+    /// The tenants having rules, in no particular order
+    pub fn tenants(&self) -> impl Iterator<Item = &str> {
+        self.tenants.keys().map(String::as_str)
+    }
+
+    /// Drops the rules of the tenant, along with its coalescence groups,
+    /// rate limits, frequency histories and delayed events. Returns the
+    /// number of rules dropped
+    pub fn remove_tenant(&mut self, tenant: &str) -> usize {
+        let removed: HashSet<usize> = match self.tenants.remove(tenant) {
+            Some(indices) => indices.into_iter().collect(),
+            None => return 0,
+        };
+
+        // the indices of the rules left shift down past the ones removed
+        let mut new_indices = Vec::with_capacity(self.rules.len());
+        let mut next = 0;
+        for i in 0..self.rules.len() {
+            new_indices.push(next);
+            if !removed.contains(&i) {
+                next += 1;
+            }
+        }
+        let mut i = 0;
+        self.rules.retain(|_| {
+            i += 1;
+            !removed.contains(&(i - 1))
+        });
+        let mut i = 0;
+        self.plans.retain(|_| {
+            i += 1;
+            !removed.contains(&(i - 1))
+        });
+        for indices in self.tenants.values_mut() {
+            for i in indices.iter_mut() {
+                *i = new_indices[*i];
+            }
+        }
+        self.reset_rule_index();
+
+        self.coalescences
+            .lock()
+            .unwrap()
+            .retain(|key, _| !is_tenant_key(tenant, key));
+        self.rate_limits
+            .retain(|key, _| !is_tenant_key(tenant, key));
+        self.frequencies
+            .retain_entities(|entity| !is_tenant_key(tenant, entity));
+        #[cfg(feature = "delay")]
+        self.delayed
+            .retain(|delayed| delayed.tenant.as_deref() != Some(tenant));
+
+        removed.len()
+    }
+
+    /// Indices of the rules of the tenant, or of those without one, in order
+    pub(crate) fn rules_in_scope(&self, tenant: Option<&str>) -> Vec<usize> {
+        match tenant {
+            Some(tenant) => {
+                self.tenants.get(tenant).cloned().unwrap_or_default()
+            }
+            None if self.tenants.is_empty() => (0..self.rules.len()).collect(),
+            None => {
+                let tenanted: HashSet<usize> =
+                    self.tenants.values().flatten().copied().collect();
+                (0..self.rules.len())
+                    .filter(|i| !tenanted.contains(i))
+                    .collect()
+            }
+        }
+    }
+
+    /// The number of disabled rules of the tenant, or of those without one,
+    /// along with the disabled group members, groups having no tenant
+    pub(crate) fn disabled_in_scope(&self, tenant: Option<&str>) -> usize {
+        let members = match tenant {
+            Some(_) => 0,
+            None => self
+                .rule_groups
+                .iter()
+                .flat_map(|group| &group.rules)
+                .filter(|rule| !rule.enabled)
+                .count(),
+        };
+        self.rules_in_scope(tenant)
+            .into_iter()
+            .filter(|&i| !self.rules[i].enabled)
+            .count()
+            + members
+    }
+
+    /// The rate limit of the event type for the tenant, made from the one
+    /// set on the engine on the tenant's first event of the type
+    pub(crate) fn rate_limit(
+        &mut self,
+        tenant: Option<&str>,
+        ty: &str,
+    ) -> Option<&mut crate::rate_limit::TokenBucket> {
+        let key = tenant_key(tenant, ty);
+        if !self.rate_limits.contains_key(key.as_ref()) {
+            let bucket = self.rate_limits.get(ty)?.refilled_copy();
+            self.rate_limits.insert(key.to_string(), bucket);
+        }
+        self.rate_limits.get_mut(key.as_ref())
+    }
+}
@@ -0,0 +1,219 @@
+//! Helpers to assert on the events rules dispatch, behind the `test_util`
+//! feature.
+//!
+//! `MockCallbackServer` records the bodies `post_to_callback_url` events post
+//! to it, and `CapturingEvent` records the events triggered in process.
+
+use crate::{error::Error, event::EventTrait};
+
+use async_trait::async_trait;
+use erased_serde::Serialize as ErasedSerialize;
+use serde_json::Value;
+use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+use std::collections::HashMap;
+
+/// A callback received by a `MockCallbackServer`
+#[derive(Clone, Debug, PartialEq)]
+pub struct CallbackPayload {
+    /// The params of the event
+    pub event: Value,
+    pub facts: Value,
+    /// The `app_data` param of the event, if any
+    pub app_data: Option<Value>,
+    /// The headers of the request, with lowercase names
+    pub headers: HashMap<String, String>,
+}
+
+impl CallbackPayload {
+    fn from_request(request: &wiremock::Request) -> Option<Self> {
+        let mut body = request.body_json::<Value>().ok()?;
+        let event = body.get_mut("event").map(Value::take)?;
+        let facts = body.get_mut("facts").map(Value::take).unwrap_or_default();
+        let app_data = event.get("app_data").cloned();
+        let headers = request
+            .headers
+            .iter()
+            .filter_map(|(name, value)| {
+                Some((
+                    name.as_str().to_owned(),
+                    value.to_str().ok()?.to_owned(),
+                ))
+            })
+            .collect();
+
+        Some(Self {
+            event,
+            facts,
+            app_data,
+            headers,
+        })
+    }
+
+    /// The event param with this name
+    pub fn param(&self, name: &str) -> Option<&Value> {
+        self.event.get(name)
+    }
+
+    /// The event param with this name, if it's a string
+    pub fn str_param(&self, name: &str) -> Option<&str> {
+        self.param(name).and_then(Value::as_str)
+    }
+
+    /// The `type` param of the event
+    pub fn event_type(&self) -> Option<&str> {
+        self.str_param("type")
+    }
+
+    /// The `title` param of the event
+    pub fn title(&self) -> Option<&str> {
+        self.str_param("title")
+    }
+
+    /// The fact at this json pointer, e.g. `/user/name`
+    pub fn fact(&self, pointer: &str) -> Option<&Value> {
+        self.facts.pointer(pointer)
+    }
+
+    /// The header with this name, case insensitive
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .get(&name.to_ascii_lowercase())
+            .map(String::as_str)
+    }
+
+    /// Whether the event has this `type` param and a `title` param containing
+    /// `title_contains`, when given. Used by `assert_event_sent!`
+    pub fn matches(
+        &self,
+        event_type: Option<&str>,
+        title_contains: Option<&str>,
+    ) -> bool {
+        event_type.is_none_or(|ty| self.event_type() == Some(ty))
+            && title_contains.is_none_or(|needle| {
+                self.title().is_some_and(|title| title.contains(needle))
+            })
+    }
+}
+
+/// A local http server answering every `POST` with `200 OK` and recording the
+/// callbacks it receives
+pub struct MockCallbackServer {
+    server: MockServer,
+}
+
+impl MockCallbackServer {
+    pub async fn start() -> Self {
+        let server = MockServer::start().await;
+        Mock::given(method("POST"))
+            .respond_with(ResponseTemplate::new(200))
+            .mount(&server)
+            .await;
+
+        Self { server }
+    }
+
+    /// The url to use as `callback_url` in rules
+    pub fn url(&self) -> String {
+        format!("{}/callback", self.server.uri())
+    }
+
+    /// The callbacks received so far, in order. Requests that aren't
+    /// callbacks are left out
+    pub async fn received(&self) -> Vec<CallbackPayload> {
+        self.server
+            .received_requests()
+            .await
+            .unwrap_or_default()
+            .iter()
+            .filter_map(CallbackPayload::from_request)
+            .collect()
+    }
+}
+
+/// Asserts a `MockCallbackServer` received a callback with the given `type`
+/// param and a `title` param containing `title_contains`, both optional
+///
+/// ```ignore
+/// assert_event_sent!(server, type = "info", title_contains = "John");
+/// ```
+#[macro_export]
+macro_rules! assert_event_sent {
+    ($server:expr $(, type = $ty:expr)? $(, title_contains = $title:expr)? $(,)?) => {{
+        let event_type: Option<&str> = None $(.or(Some($ty)))?;
+        let title_contains: Option<&str> = None $(.or(Some($title)))?;
+        let received = $server.received().await;
+        assert!(
+            received
+                .iter()
+                .any(|payload| payload.matches(event_type, title_contains)),
+            "no callback with type {:?} and title containing {:?} among {:#?}",
+            event_type,
+            title_contains,
+            received,
+        );
+    }};
+}
+
+/// An event triggered in a `CapturingEvent`
+#[derive(Clone, Debug, PartialEq)]
+pub struct CapturedEvent {
+    pub params: HashMap<String, Value>,
+    pub facts: Value,
+}
+
+/// An event recording every trigger, for assertions in process. Keep a clone
+/// of the `Arc` given to `Engine::add_event` to read them
+pub struct CapturingEvent {
+    ty: String,
+    captured: Vec<CapturedEvent>,
+}
+
+impl CapturingEvent {
+    /// A capturing event of another type than `capturing_event`
+    pub fn with_type(ty: impl Into<String>) -> Self {
+        Self {
+            ty: ty.into(),
+            captured: Vec::new(),
+        }
+    }
+
+    /// The events triggered so far, in order
+    pub fn captured(&self) -> &[CapturedEvent] {
+        &self.captured
+    }
+
+    /// Forgets the events triggered so far
+    pub fn clear(&mut self) {
+        self.captured.clear();
+    }
+}
+
+#[async_trait]
+impl EventTrait for CapturingEvent {
+    fn new() -> Self {
+        Self::with_type("capturing_event")
+    }
+
+    fn get_type(&self) -> &str {
+        &self.ty
+    }
+
+    fn validate(&self, _params: &HashMap<String, Value>) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn trigger(
+        &mut self,
+        params: &HashMap<String, Value>,
+        facts: &(dyn ErasedSerialize + Sync),
+    ) -> Result<(), Error> {
+        let facts = serde_json::to_value(facts)?;
+        self.captured.push(CapturedEvent {
+            params: params.clone(),
+            facts,
+        });
+
+        Ok(())
+    }
+}
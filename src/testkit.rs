@@ -0,0 +1,592 @@
+//! Acceptance specs of rule sets, written as data, see `Engine::run_specs`.
+//!
+//! A spec gives facts and what the engine should make of them, e.g.
+//!
+//! ```json
+//! {
+//!     "name": "adults get a welcome email",
+//!     "facts": { "name": "Cheng JIANG", "age": 27 },
+//!     "expect": [
+//!         { "kind": "rule_status", "rule": "adult", "status": "Met" },
+//!         {
+//!             "kind": "leaf_status",
+//!             "rule": "adult",
+//!             "label": "old_enough",
+//!             "status": "Met"
+//!         },
+//!         {
+//!             "kind": "event",
+//!             "rule": "adult",
+//!             "event_type": "email_notification",
+//!             "params": { "title": "Welcome Cheng JIANG" }
+//!         }
+//!     ]
+//! }
+//! ```
+//!
+//! Specs deserialize with serde, from the JSON above or from any format a
+//! serde deserializer of the caller reads.
+
+use crate::{
+    condition::{Condition, ConditionResult},
+    event::{render_params, CoalescenceEvent, EventTrait},
+    rule::{Rule, RuleResult},
+    status::Status,
+    Engine, Error,
+};
+
+use async_trait::async_trait;
+use erased_serde::Serialize as ErasedSerialize;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Map, Value};
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, RwLock},
+};
+
+/// Facts, and what the engine should make of them
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleSpec {
+    pub name: String,
+    pub facts: Value,
+    #[serde(default)]
+    pub expect: Vec<Expectation>,
+}
+
+/// What a spec expects of a rule, by its id
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Expectation {
+    /// The status of the rule's conditions
+    RuleStatus { rule: String, status: Status },
+    /// The status of the rule's condition with the label
+    LeafStatus {
+        rule: String,
+        label: String,
+        status: Status,
+    },
+    /// The met rule dispatched an event of the type whose rendered params
+    /// include these ones, e.g. its `title` or its `message`
+    Event {
+        rule: String,
+        event_type: String,
+        #[serde(default)]
+        params: Map<String, Value>,
+    },
+    /// The met rule has an event of the type, and none of them was
+    /// dispatched: coalesced, muted, rate limited, dropped by an
+    /// interceptor, too large or refused
+    Suppressed { rule: String, event_type: String },
+}
+
+/// An expectation a spec didn't meet
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpecFailure {
+    pub expectation: Expectation,
+    pub expected: Value,
+    pub actual: Value,
+    /// What differs, e.g. "Rule `adult` is NotMet rather than Met"
+    pub message: String,
+}
+
+/// How a spec fared
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SpecResult {
+    pub name: String,
+    pub failures: Vec<SpecFailure>,
+    /// Why the facts couldn't be run, e.g. an event failing in
+    /// `ErrorMode::FailFast`, in which case no expectation was checked
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl SpecResult {
+    pub fn passed(&self) -> bool {
+        self.error.is_none() && self.failures.is_empty()
+    }
+}
+
+/// How the specs fared, in the order they ran
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct SpecReport {
+    pub results: Vec<SpecResult>,
+}
+
+impl SpecReport {
+    pub fn passed(&self) -> bool {
+        self.results.iter().all(SpecResult::passed)
+    }
+
+    /// The specs that didn't pass
+    pub fn failed(&self) -> Vec<&SpecResult> {
+        self.results
+            .iter()
+            .filter(|result| !result.passed())
+            .collect()
+    }
+}
+
+/// Stands in for a registered event while specs run, validating the params
+/// as it does, without dispatching anything
+struct DryEvent {
+    ty: String,
+    event: Option<Arc<RwLock<dyn EventTrait>>>,
+}
+
+#[async_trait]
+impl EventTrait for DryEvent {
+    fn new() -> Self {
+        Self {
+            ty: "dry_event".to_string(),
+            event: None,
+        }
+    }
+
+    fn get_type(&self) -> &str {
+        &self.ty
+    }
+
+    fn validate(&self, params: &HashMap<String, Value>) -> Result<(), String> {
+        match &self.event {
+            Some(event) => event.read().unwrap().validate(params),
+            None => Ok(()),
+        }
+    }
+
+    async fn trigger(
+        &mut self,
+        _params: &HashMap<String, Value>,
+        _facts: &(dyn ErasedSerialize + Sync),
+    ) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+/// Whether the event went through to its trigger
+fn dispatched(event: &CoalescenceEvent) -> bool {
+    !event.muted
+        && !event.rate_limited
+        && !event.too_large
+        && event.dropped.is_none()
+        && event.error.is_none()
+}
+
+/// The status of the condition with the label, given the condition's
+/// traced status and the results of its children. A `not` takes the results
+/// of its child's children as its own
+fn labeled_status(
+    condition: &Condition,
+    status: Status,
+    children: &[ConditionResult],
+    label: &str,
+) -> Option<Status> {
+    if condition.label() == Some(label) {
+        return Some(status);
+    }
+    match condition {
+        Condition::Not { not, .. } => {
+            labeled_status(not, !status, children, label)
+        }
+        _ => condition
+            .children()
+            .unwrap_or_default()
+            .iter()
+            .zip(children)
+            .find_map(|(condition, result)| {
+                labeled_status(
+                    condition,
+                    result.status,
+                    &result.children,
+                    label,
+                )
+            }),
+    }
+}
+
+/// Whether the rendered params hold the expected ones
+fn params_match(
+    rendered: &HashMap<String, Value>,
+    expected: &Map<String, Value>,
+) -> bool {
+    expected
+        .iter()
+        .all(|(name, value)| rendered.get(name) == Some(value))
+}
+
+/// What a run made of a spec's facts
+struct SpecRun<'a> {
+    facts: Value,
+    rule_results: &'a [RuleResult],
+}
+
+impl Engine {
+    /// Runs the facts of each spec in turn and checks its expectations,
+    /// every event going through the engine as it would, its params
+    /// validated, without any being dispatched, nor handed to the run hooks,
+    /// the dead letter sink or the subscribers, nor delayed.
+    ///
+    /// The specs share the engine's state, e.g. its coalescence groups and
+    /// rate limits, so a spec may expect an event suppressed after the
+    /// specs before it: run them on an engine of their own. The statuses of
+    /// the rules and labeled conditions are evaluated again, traced, against
+    /// the facts of the run, once patched
+    pub async fn run_specs(&mut self, specs: &[RuleSpec]) -> SpecReport {
+        let dry_events = self
+            .events
+            .iter()
+            .map(|(ty, event)| {
+                let dry: Arc<RwLock<dyn EventTrait>> =
+                    Arc::new(RwLock::new(DryEvent {
+                        ty: ty.clone(),
+                        event: Some(event.clone()),
+                    }));
+                (ty.clone(), dry)
+            })
+            .collect();
+        let events = std::mem::replace(&mut self.events, dry_events);
+        let run_hooks = std::mem::take(&mut self.run_hooks);
+        let dead_letter_sink = self.dead_letter_sink.take();
+        let dead_letters = std::mem::take(&mut self.dead_letters);
+        #[cfg(feature = "broadcast")]
+        let broadcast = std::mem::replace(
+            &mut self.broadcast,
+            tokio::sync::broadcast::channel(1).0,
+        );
+
+        let mut report = SpecReport::default();
+        for spec in specs {
+            #[cfg(feature = "delay")]
+            let delayed = self.delayed.len();
+            let result = match self.run_with_info(&spec.facts).await {
+                Ok((rule_results, run_info)) => {
+                    let facts = match run_info.patched_facts {
+                        Some(facts) => Ok(facts),
+                        None => self.facts_root(spec.facts.clone()),
+                    };
+                    facts.map(|facts| {
+                        let run = SpecRun {
+                            facts,
+                            rule_results: &rule_results,
+                        };
+                        spec.expect
+                            .iter()
+                            .filter_map(|expectation| {
+                                self.check(&run, expectation)
+                            })
+                            .collect()
+                    })
+                }
+                Err(e) => Err(e),
+            };
+            #[cfg(feature = "delay")]
+            self.delayed.truncate(delayed);
+
+            report.results.push(match result {
+                Ok(failures) => SpecResult {
+                    name: spec.name.clone(),
+                    failures,
+                    error: None,
+                },
+                Err(e) => SpecResult {
+                    name: spec.name.clone(),
+                    failures: Vec::new(),
+                    error: Some(e.to_string()),
+                },
+            });
+        }
+
+        self.events = events;
+        self.run_hooks = run_hooks;
+        self.dead_letter_sink = dead_letter_sink;
+        self.dead_letters = dead_letters;
+        #[cfg(feature = "broadcast")]
+        {
+            self.broadcast = broadcast;
+        }
+        report
+    }
+
+    /// The rule with the id, among the rules and the members of the groups
+    fn spec_rule(&self, id: &str) -> Option<&Rule> {
+        self.rules
+            .iter()
+            .chain(self.rule_groups.iter().flat_map(|group| &group.rules))
+            .find(|rule| rule.id.as_deref() == Some(id))
+    }
+
+    /// The failure of the expectation, if it isn't met
+    fn check(
+        &self,
+        run: &SpecRun,
+        expectation: &Expectation,
+    ) -> Option<SpecFailure> {
+        let failure = |expected: Value, actual: Value, message: String| {
+            Some(SpecFailure {
+                expectation: expectation.clone(),
+                expected,
+                actual,
+                message,
+            })
+        };
+        let (rule_id, event_type) = match expectation {
+            Expectation::RuleStatus { rule, .. }
+            | Expectation::LeafStatus { rule, .. } => (rule, None),
+            Expectation::Event {
+                rule, event_type, ..
+            }
+            | Expectation::Suppressed { rule, event_type } => {
+                (rule, Some(event_type))
+            }
+        };
+        let rule = match self.spec_rule(rule_id) {
+            Some(rule) => rule,
+            None => {
+                return failure(
+                    json!(rule_id),
+                    Value::Null,
+                    format!("Rule `{}` doesn't exist", rule_id),
+                )
+            }
+        };
+        let met = run
+            .rule_results
+            .iter()
+            .find(|result| result.rule_id.as_deref() == Some(rule_id));
+
+        match expectation {
+            Expectation::RuleStatus { status, .. } => {
+                let actual = match met {
+                    Some(_) => Status::Met,
+                    None => {
+                        self.evaluate_rule_with(
+                            rule, None, &run.facts, true, None,
+                        )
+                        .condition_result
+                        .status
+                    }
+                };
+                if actual == *status {
+                    return None;
+                }
+                failure(
+                    json!(status),
+                    json!(actual),
+                    format!(
+                        "Rule `{}` is {:?} rather than {:?}",
+                        rule_id, actual, status
+                    ),
+                )
+            }
+            Expectation::LeafStatus { label, status, .. } => {
+                let result =
+                    self.evaluate_rule_with(rule, None, &run.facts, true, None);
+                match labeled_status(
+                    &rule.conditions,
+                    result.condition_result.status,
+                    &result.condition_result.children,
+                    label,
+                ) {
+                    Some(actual) if actual == *status => None,
+                    Some(actual) => failure(
+                        json!(status),
+                        json!(actual),
+                        format!(
+                            "Condition `{}` of rule `{}` is {:?} rather than {:?}",
+                            label, rule_id, actual, status
+                        ),
+                    ),
+                    None => failure(
+                        json!(status),
+                        Value::Null,
+                        format!(
+                            "Rule `{}` has no condition labeled `{}`",
+                            rule_id, label
+                        ),
+                    ),
+                }
+            }
+            Expectation::Event { params, .. } => {
+                let event_type = event_type.unwrap();
+                let rendered: Vec<_> = met
+                    .into_iter()
+                    .flat_map(|result| &result.events)
+                    .filter(|event| {
+                        event.event.ty == *event_type && dispatched(event)
+                    })
+                    .map(|event| render_params(&event.event.params, &run.facts))
+                    .collect();
+                if rendered
+                    .iter()
+                    .any(|rendered| params_match(rendered, params))
+                {
+                    return None;
+                }
+                let message = match (met, rendered.is_empty()) {
+                    (None, _) => format!("Rule `{}` wasn't met", rule_id),
+                    (Some(_), true) => format!(
+                        "Rule `{}` dispatched no `{}` event",
+                        rule_id, event_type
+                    ),
+                    (Some(_), false) => format!(
+                        "No `{}` event of rule `{}` has the params",
+                        event_type, rule_id
+                    ),
+                };
+                failure(Value::Object(params.clone()), json!(rendered), message)
+            }
+            Expectation::Suppressed { .. } => {
+                let event_type = event_type.unwrap();
+                let result = match met {
+                    Some(result) => result,
+                    None => {
+                        return failure(
+                            json!(event_type),
+                            Value::Null,
+                            format!("Rule `{}` wasn't met", rule_id),
+                        )
+                    }
+                };
+                if !rule
+                    .events
+                    .iter()
+                    .any(|event| event.event.ty == *event_type)
+                {
+                    return failure(
+                        json!(event_type),
+                        Value::Null,
+                        format!(
+                            "Rule `{}` has no `{}` event",
+                            rule_id, event_type
+                        ),
+                    );
+                }
+                let dispatched: Vec<_> = result
+                    .events
+                    .iter()
+                    .filter(|event| {
+                        event.event.ty == *event_type && dispatched(event)
+                    })
+                    .map(|event| json!(event.event.params))
+                    .collect();
+                if dispatched.is_empty() {
+                    None
+                } else {
+                    failure(
+                        json!(event_type),
+                        json!(dispatched),
+                        format!(
+                            "A `{}` event of rule `{}` was dispatched",
+                            event_type, rule_id
+                        ),
+                    )
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{labeled_status, params_match, Expectation};
+    use crate::{status::Status, Condition};
+
+    use serde_json::json;
+
+    #[test]
+    fn expectations() {
+        let expectation: Expectation = serde_json::from_value(json!({
+            "kind": "leaf_status",
+            "rule": "adult",
+            "label": "old_enough",
+            "status": "NotMet"
+        }))
+        .unwrap();
+        assert_eq!(
+            expectation,
+            Expectation::LeafStatus {
+                rule: "adult".into(),
+                label: "old_enough".into(),
+                status: Status::NotMet,
+            }
+        );
+
+        let expectation: Expectation = serde_json::from_value(json!({
+            "kind": "event",
+            "rule": "adult",
+            "event_type": "email_notification"
+        }))
+        .unwrap();
+        assert!(matches!(
+            expectation,
+            Expectation::Event { params, .. } if params.is_empty()
+        ));
+        assert!(serde_json::from_value::<Expectation>(json!({
+            "kind": "rule_met",
+            "rule": "adult"
+        }))
+        .is_err());
+    }
+
+    #[test]
+    fn labeled_statuses() {
+        let condition: Condition = serde_json::from_value(json!({
+            "and": [
+                {
+                    "field": "name",
+                    "operator": "string_equals",
+                    "value": "Cheng JIANG",
+                    "label": "named"
+                },
+                {
+                    "not": {
+                        "field": "age",
+                        "operator": "int_less_than",
+                        "value": 18,
+                        "label": "minor"
+                    },
+                    "label": "adult"
+                }
+            ]
+        }))
+        .unwrap();
+        let result = condition.check_value(
+            &json!({ "name": "Cheng JIANG", "age": 27 }),
+            #[cfg(feature = "eval")]
+            &rhai::Engine::new(),
+        );
+
+        let status = |label| {
+            labeled_status(&condition, result.status, &result.children, label)
+        };
+        assert_eq!(status("named"), Some(Status::Met));
+        assert_eq!(status("adult"), Some(Status::Met));
+        assert_eq!(status("minor"), Some(Status::NotMet));
+        assert_eq!(status("missing"), None);
+    }
+
+    #[test]
+    fn matching_params() {
+        let rendered = serde_json::from_value(json!({
+            "title": "Welcome",
+            "to": "a@b.c"
+        }))
+        .unwrap();
+        let expected =
+            |params: serde_json::Value| params.as_object().unwrap().clone();
+
+        assert!(params_match(&rendered, &expected(json!({}))));
+        assert!(params_match(
+            &rendered,
+            &expected(json!({ "title": "Welcome" }))
+        ));
+        assert!(!params_match(
+            &rendered,
+            &expected(json!({ "title": "Bye" }))
+        ));
+        assert!(!params_match(
+            &rendered,
+            &expected(json!({ "message": "Hi" }))
+        ));
+    }
+}
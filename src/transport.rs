@@ -0,0 +1,167 @@
+//! The HTTP requests of `post_to_callback_url` events sent by something
+//! else than the engine's reqwest client, e.g. a proxy library, see
+//! `Engine::with_transport`.
+//!
+//! The event builds the whole request, the transport only sends it: its
+//! method, url and headers, `Content-Type` among them, and its body. A
+//! response whose status isn't a success fails the event, as does an error
+//! of the transport, which is returned as is.
+
+use crate::{
+    event::post_callback::{CallbackUrlPolicy, PostCallback},
+    Engine, Error,
+};
+
+use async_trait::async_trait;
+use reqwest::{redirect, Client, Url};
+use serde_json::Value;
+
+use std::sync::{Arc, RwLock};
+
+/// The body of an outbound request
+#[derive(Debug, Clone, PartialEq)]
+pub enum OutboundBody {
+    /// Sent as `application/json`
+    Json(Value),
+    /// Sent as `application/x-www-form-urlencoded`, the pairs in order
+    Form(Vec<(String, String)>),
+    /// Sent as is, already encoded, e.g. compressed, as the request's
+    /// `Content-Type` and `Content-Encoding` headers tell
+    Bytes(Vec<u8>),
+}
+
+/// A request an event wants sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct OutboundRequest {
+    /// Upper case, e.g. `POST`
+    pub method: String,
+    pub url: String,
+    /// In the order they're set, `Content-Type` first
+    pub headers: Vec<(String, String)>,
+    pub body: OutboundBody,
+}
+
+/// What the receiver of a request answered
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TransportResponse {
+    pub status: u16,
+    pub body: String,
+}
+
+impl TransportResponse {
+    /// Whether the status is a 2xx one
+    pub fn is_success(&self) -> bool {
+        (200..300).contains(&self.status)
+    }
+}
+
+/// Sends the HTTP requests of events, see `Engine::with_transport`
+#[async_trait]
+pub trait EventTransport: Send + Sync {
+    async fn send(
+        &self,
+        request: OutboundRequest,
+    ) -> Result<TransportResponse, Error>;
+}
+
+/// The transport events use by default, sending requests with reqwest. A
+/// status other than a success is a `Error::ReqwestError`
+#[derive(Debug, Clone, Default)]
+pub struct ReqwestTransport {
+    client: Client,
+    /// The callback url policy of the engine, checked again right before
+    /// sending, see `ReqwestTransport::client_for`
+    policy: Option<Arc<RwLock<CallbackUrlPolicy>>>,
+}
+
+impl ReqwestTransport {
+    pub fn new(client: Client) -> Self {
+        Self {
+            client,
+            policy: None,
+        }
+    }
+
+    /// The transport of the engine's own `post_to_callback_url` event,
+    /// enforcing its callback url policy
+    pub(crate) fn with_policy(policy: Arc<RwLock<CallbackUrlPolicy>>) -> Self {
+        Self {
+            client: Client::default(),
+            policy: Some(policy),
+        }
+    }
+
+    /// The client sending a request to the url. Under a policy allowing
+    /// anything but any url, redirects aren't followed, and the host is
+    /// connected to at the addresses the policy checked rather than
+    /// resolved again
+    async fn client_for(&self, url: &str) -> Result<Client, Error> {
+        let policy = match &self.policy {
+            Some(policy) => policy.read().unwrap().clone(),
+            None => return Ok(self.client.clone()),
+        };
+        if policy.allows_any() {
+            return Ok(self.client.clone());
+        }
+
+        let addrs = policy.check(url).await.map_err(Error::EventError)?;
+        let mut builder = Client::builder().redirect(redirect::Policy::none());
+        if let Some(host) = Url::parse(url)
+            .ok()
+            .and_then(|url| url.host_str().map(ToOwned::to_owned))
+        {
+            if !addrs.is_empty() {
+                builder = builder.resolve_to_addrs(&host, &addrs);
+            }
+        }
+        Ok(builder.build()?)
+    }
+}
+
+#[async_trait]
+impl EventTransport for ReqwestTransport {
+    async fn send(
+        &self,
+        request: OutboundRequest,
+    ) -> Result<TransportResponse, Error> {
+        let method = request.method.parse().map_err(|_| {
+            Error::EventError(format!(
+                "Invalid request method `{}`",
+                request.method
+            ))
+        })?;
+        let client = self.client_for(&request.url).await?;
+        let mut builder = client.request(method, &request.url);
+        for (name, value) in &request.headers {
+            builder = builder.header(name, value);
+        }
+        builder = match &request.body {
+            OutboundBody::Json(body) => builder.json(body),
+            OutboundBody::Form(pairs) => builder.form(pairs),
+            OutboundBody::Bytes(bytes) => builder.body(bytes.clone()),
+        };
+
+        let response = builder.send().await?.error_for_status()?;
+        Ok(TransportResponse {
+            status: response.status().as_u16(),
+            body: response.text().await?,
+        })
+    }
+}
+
+impl Engine {
+    /// Sends the requests of `post_to_callback_url` events with the
+    /// transport rather than reqwest, replacing the event registered under
+    /// that type. The callback url policy is then only checked by the
+    /// engine, following redirects or resolving the host again being up to
+    /// the transport
+    pub fn with_transport(
+        mut self,
+        transport: Arc<dyn EventTransport>,
+    ) -> Self {
+        self.add_event(Arc::new(RwLock::new(PostCallback::with_transport(
+            transport,
+        ))));
+        self
+    }
+}
@@ -0,0 +1,18 @@
+//! The evaluation core for JavaScript, through wasm-bindgen, e.g. to check
+//! rules in the browser as they're edited. Nothing is dispatched, so no
+//! network stack is needed.
+
+use crate::{rule::Rule, Engine};
+use serde_json::Value;
+use wasm_bindgen::prelude::*;
+
+/// Checks the JSON rule against the JSON facts and returns its
+/// `RuleResult` as JSON, whether the rule is met or not. Rules and facts
+/// that don't parse are thrown as errors
+#[wasm_bindgen]
+pub fn evaluate(rule_json: &str, facts_json: &str) -> Result<String, JsError> {
+    let rule: Rule = serde_json::from_str(rule_json)?;
+    let facts: Value = serde_json::from_str(facts_json)?;
+    let rule_result = Engine::new().evaluate_rule(&rule, None, &facts);
+    Ok(serde_json::to_string(&rule_result)?)
+}
@@ -1,11 +1,19 @@
+#![cfg(not(target_arch = "wasm32"))]
+#![allow(dead_code)]
 use async_trait::async_trait;
 use erased_serde::Serialize as ErasedSerialize;
 #[cfg(feature = "eval")]
 use json_rules_engine::{from_dynamic, Map};
-use json_rules_engine::{Engine, Error, EventTrait, Rule, Status};
+use json_rules_engine::{
+    Engine, Error, EventTrait, GroupEmit, Rule, RuleGroup, RuleResult, Status,
+};
 use serde::{Deserialize, Serialize};
 use serde_json::{json, Value};
-use std::{collections::HashMap, rc::Rc, sync::RwLock};
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, RwLock},
+    time::{Duration, Instant},
+};
 
 #[tokio::test]
 async fn basic_met() {
@@ -59,6 +67,132 @@ async fn basic_met() {
     assert_eq!(rule_results[0].condition_result.status, Status::Met)
 }
 
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+#[derive(Serialize)]
+struct EncodedFacts {
+    name: String,
+    age: u8,
+    action: String,
+}
+
+#[cfg(any(feature = "msgpack", feature = "cbor"))]
+fn encoded_facts_engine() -> (Engine, EncodedFacts) {
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "name",
+                    "operator": "string_equals",
+                    "value": "Cheng JIANG"
+                },
+                {
+                    "field": "age",
+                    "operator": "int_in_range",
+                    "value": [20, 25]
+                },
+                {
+                    "field": "blob",
+                    "operator": "string_equals",
+                    "value": "AQID"
+                }
+            ]
+        },
+        "events": []
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+
+    let facts = EncodedFacts {
+        name: "Cheng JIANG".to_string(),
+        age: 24,
+        action: "coding in rust".to_string(),
+    };
+
+    (engine, facts)
+}
+
+#[cfg(feature = "msgpack")]
+#[tokio::test]
+async fn msgpack_facts() {
+    let (mut engine, facts) = encoded_facts_engine();
+
+    let mut bytes = rmp_serde::to_vec_named(&facts).unwrap();
+    // a fourth member, `blob`, holding the binary string [1, 2, 3]
+    bytes[0] += 1;
+    bytes.extend_from_slice(&[0xa4, b'b', b'l', b'o', b'b', 0xc4, 3, 1, 2, 3]);
+
+    let rule_results = engine.run_msgpack(&bytes).await.unwrap();
+    assert_eq!(rule_results[0].condition_result.status, Status::Met);
+
+    let truncated = &bytes[..bytes.len() - 2];
+    assert!(matches!(
+        engine.run_msgpack(truncated).await,
+        Err(Error::MsgpackDecodeError {
+            offset: Some(_),
+            ..
+        })
+    ));
+
+    let mut trailing = bytes.clone();
+    trailing.push(0xc0);
+    assert!(matches!(
+        engine.run_msgpack(&trailing).await,
+        Err(Error::MsgpackDecodeError { offset: Some(offset), .. })
+            if offset == bytes.len()
+    ));
+}
+
+#[cfg(feature = "cbor")]
+#[tokio::test]
+async fn cbor_facts() {
+    use ciborium::value::Value as Cbor;
+
+    let (mut engine, facts) = encoded_facts_engine();
+
+    let mut members = match Cbor::serialized(&facts).unwrap() {
+        Cbor::Map(members) => members,
+        _ => unreachable!(),
+    };
+    members.push((Cbor::Text("blob".into()), Cbor::Bytes(vec![1, 2, 3])));
+    let mut bytes = Vec::new();
+    ciborium::ser::into_writer(&Cbor::Map(members.clone()), &mut bytes)
+        .unwrap();
+
+    let rule_results = engine.run_cbor(&bytes).await.unwrap();
+    assert_eq!(rule_results[0].condition_result.status, Status::Met);
+
+    let truncated = &bytes[..bytes.len() - 2];
+    assert!(matches!(
+        engine.run_cbor(truncated).await,
+        Err(Error::CborDecodeError {
+            offset: Some(_),
+            ..
+        })
+    ));
+
+    let mut trailing = bytes.clone();
+    trailing.push(0xf6);
+    assert!(matches!(
+        engine.run_cbor(&trailing).await,
+        Err(Error::CborDecodeError { offset: Some(offset), .. })
+            if offset == bytes.len()
+    ));
+
+    // a bignum, 2 ^ 64, doesn't fit in 64 bits
+    members.push((
+        Cbor::Text("id".into()),
+        Cbor::Tag(2, Box::new(Cbor::Bytes(vec![1, 0, 0, 0, 0, 0, 0, 0, 0]))),
+    ));
+    let mut bignum = Vec::new();
+    ciborium::ser::into_writer(&Cbor::Map(members), &mut bignum).unwrap();
+    assert!(matches!(
+        engine.run_cbor(&bignum).await,
+        Err(Error::CborDecodeError { .. })
+    ));
+}
+
 #[tokio::test]
 async fn basic_not_met() {
     #[derive(Deserialize, Serialize)]
@@ -171,6 +305,102 @@ async fn custom_function() {
     assert_eq!(rule_results[0].condition_result.status, Status::Met)
 }
 
+#[cfg(feature = "lua")]
+#[tokio::test]
+async fn lua_script() {
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "name",
+                    "operator": "string_equals",
+                    "value": "Cheng JIANG"
+                },
+                {
+                    "field": "age",
+                    "operator": "int_in_range",
+                    "value": [20, 25]
+                },
+                {
+                    "and": [
+                        {
+                            "script": "return facts.age > 20 and facts.age <= 25",
+                        },
+                        {
+                            "script": r#"
+                                local function in_range(f)
+                                    return f.age > 20 and f.age <= 25
+                                end
+                                return in_range(facts)
+                            "#,
+                        },
+                    ]
+                },
+                {
+                    "field": "action",
+                    "operator": "string_equals",
+                    "value": "coding in rust"
+                }
+            ]
+        },
+        "events": [
+        ]
+    });
+
+    let rule: Rule = serde_json::from_str::<Rule>(
+        &serde_json::to_string(&rule_json).unwrap(),
+    )
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+
+    let facts = json!({
+        "name": "Cheng JIANG",
+        "age": 24,
+        "action": "coding in rust",
+    });
+
+    let rule_results = engine.run(&facts).await.unwrap();
+
+    assert_eq!(rule_results[0].condition_result.status, Status::Met);
+
+    let check = |script: &str| {
+        let rule: Rule = serde_json::from_value(json!({
+            "conditions": { "script": script },
+            "events": []
+        }))
+        .unwrap();
+        rule.check_value(
+            &facts,
+            #[cfg(feature = "eval")]
+            &rhai::Engine::new(),
+        )
+        .condition_result
+    };
+
+    assert_eq!(check("return facts.age > 30").status, Status::NotMet);
+    assert_eq!(check("return facts.age > 30").error, None);
+
+    // non booleans, errors and runaway scripts are unknown, with the reason
+    for script in [
+        "return facts.age",
+        "return facts.missing.field",
+        "error('nope')",
+        "while true do end",
+        "local t = {} for i = 1, 1e9 do t[i] = i end return true",
+        "return os.time() > 0",
+    ] {
+        let res = check(script);
+        assert_eq!(res.status, Status::Unknown, "{}", script);
+        assert!(res.error.is_some(), "{}", script);
+    }
+    assert!(check("while true do end")
+        .error
+        .unwrap()
+        .contains("instruction limit exceeded"));
+}
+
 #[cfg(feature = "callback")]
 #[tokio::test]
 async fn post_callback_event() {
@@ -334,7 +564,7 @@ async fn custom_event() {
     let mut engine = Engine::new();
     engine.add_rule(rule);
 
-    let custom_event = Rc::new(RwLock::new(CustomEvent::new()));
+    let custom_event = Arc::new(RwLock::new(CustomEvent::new()));
     engine.add_event(custom_event.clone());
 
     let facts = json!({
@@ -396,6 +626,107 @@ async fn test_a_pointer() {
     assert_eq!(rule_results[0].condition_result.status, Status::Met)
 }
 
+#[cfg(feature = "async_predicate")]
+#[tokio::test]
+async fn async_predicates() {
+    use json_rules_engine::AsyncPredicateFn;
+    use std::time::Duration;
+
+    let in_cache: AsyncPredicateFn = Arc::new(|input: Value| {
+        Box::pin(async move {
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            let key = input["facts"]["user"].as_str().ok_or("no user")?;
+            Ok(input["args"]["keys"]
+                .as_array()
+                .is_some_and(|keys| keys.contains(&json!(key))))
+        })
+    });
+    let slow: AsyncPredicateFn = Arc::new(|_| {
+        Box::pin(async {
+            tokio::time::sleep(Duration::from_secs(60)).await;
+            Ok(true)
+        })
+    });
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "age",
+                    "operator": "int_greater_than",
+                    "value": 18
+                },
+                {
+                    "async_predicate": "in_cache",
+                    "args": { "keys": ["alice", "bob"] }
+                }
+            ]
+        },
+        "events": []
+    }))
+    .unwrap();
+    // met whatever the predicates say, to look at their results
+    let probe: Rule = serde_json::from_value(json!({
+        "id": "probe",
+        "conditions": {
+            "or": [
+                { "async_predicate": "slow" },
+                {
+                    "async_predicate": "in_cache",
+                    "args": { "keys": ["alice", "bob"] }
+                },
+                {
+                    "field": "age",
+                    "operator": "int_greater_than",
+                    "value": 18
+                }
+            ]
+        },
+        "events": []
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    assert!(matches!(
+        engine.try_add_rule(rule.clone()),
+        Err(Error::ValidationError(_))
+    ));
+    engine.add_async_predicate("in_cache", in_cache);
+    engine.add_async_predicate("slow", slow);
+    engine.set_async_predicate_timeout(Duration::from_millis(100));
+    engine.try_add_rule(rule).unwrap();
+
+    let rule_results = engine
+        .run(&json!({ "age": 24, "user": "alice" }))
+        .await
+        .unwrap();
+    assert_eq!(rule_results.len(), 1);
+    assert_eq!(
+        rule_results[0].condition_result.children[1].status,
+        Status::Met
+    );
+
+    let rule_results = engine
+        .run(&json!({ "age": 24, "user": "carol" }))
+        .await
+        .unwrap();
+    assert!(rule_results.is_empty());
+
+    // sync evaluation can't await the predicates
+    let rule_results = engine.evaluate(&json!({ "age": 24, "user": "alice" }));
+    assert!(rule_results.unwrap().is_empty());
+
+    engine.try_add_rule(probe).unwrap();
+    let rule_results = engine.run(&json!({ "age": 24 })).await.unwrap();
+    assert_eq!(rule_results.len(), 1);
+    let children = &rule_results[0].condition_result.children;
+    assert_eq!(children[0].status, Status::Unknown);
+    assert!(children[0].error.as_deref().unwrap().contains("timed out"));
+    assert_eq!(children[1].status, Status::Unknown);
+    assert_eq!(children[1].error.as_deref(), Some("no user"));
+    assert_eq!(children[2].status, Status::Met);
+}
+
 #[cfg(feature = "path")]
 #[tokio::test]
 async fn test_a_pointer_and_path() {
@@ -446,3 +777,9199 @@ async fn test_a_pointer_and_path() {
 
     assert_eq!(rule_results[0].condition_result.status, Status::Met)
 }
+
+#[cfg(feature = "unicode")]
+#[tokio::test]
+async fn test_normalize_nfc() {
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "city",
+                    "operator": "string_equals",
+                    // "e" followed by a combining acute accent
+                    "value": "Gene\u{301}ve",
+                    "normalize": "nfc"
+                },
+                {
+                    "field": "aliases",
+                    "operator": "string_contains",
+                    "value": "Caf\u{e9}",
+                    "normalize": "nfc"
+                }
+            ]
+        },
+        "events": [
+        ]
+    });
+
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+
+    let facts = json!({
+        "city": "Gen\u{e9}ve",
+        "aliases": ["Cafe\u{301}", "Bistro"],
+    });
+
+    let rule_results = engine.run(&facts).await.unwrap();
+
+    assert_eq!(rule_results[0].condition_result.status, Status::Met)
+}
+
+#[cfg(feature = "unicode")]
+#[tokio::test]
+async fn test_normalize_nfkc() {
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "word",
+                    "operator": "string_in",
+                    "value": ["file", "folder"],
+                    "normalize": "nfkc"
+                },
+            ]
+        },
+        "events": [
+        ]
+    });
+
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule.clone());
+
+    // "fi" ligature only matches under a compatibility normalization
+    let facts = json!({ "word": "\u{fb01}le" });
+
+    let rule_results = engine.run(&facts).await.unwrap();
+
+    assert_eq!(rule_results[0].condition_result.status, Status::Met);
+
+    let mut rule_json = serde_json::to_value(&rule).unwrap();
+    rule_json["conditions"]["and"][0]["normalize"] = json!("nfc");
+
+    let mut engine = Engine::new();
+    engine.add_rule(serde_json::from_value(rule_json).unwrap());
+
+    let rule_results = engine.run(&facts).await.unwrap();
+
+    assert_eq!(rule_results.len(), 0);
+}
+
+#[tokio::test]
+async fn run_with_info() {
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "age",
+                    "operator": "int_in_range",
+                    "value": [20, 25]
+                },
+            ]
+        },
+        "events": [
+        ]
+    });
+
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rules(vec![rule.clone(), rule.clone(), rule]);
+
+    let facts = json!({
+        "age": 24,
+    });
+
+    let (rule_results, run_info) = engine.run_with_info(&facts).await.unwrap();
+
+    assert_eq!(run_info.rules_evaluated, 3);
+    assert_eq!(rule_results.len(), 3);
+    assert!(run_info.started_at > 0);
+
+    let mut last_evaluated_at = run_info.started_at;
+    for rule_result in &rule_results {
+        assert!(rule_result.evaluated_at >= last_evaluated_at);
+        last_evaluated_at = rule_result.evaluated_at;
+    }
+
+    let evaluation_micros: u64 =
+        rule_results.iter().map(|r| r.duration_micros).sum();
+    assert!(evaluation_micros <= run_info.total_duration.as_micros() as u64);
+
+    // results stored before these fields existed still parse
+    let mut stored = serde_json::to_value(&rule_results[0]).unwrap();
+    stored.as_object_mut().unwrap().remove("evaluated_at");
+    stored.as_object_mut().unwrap().remove("duration_micros");
+    let rule_result: RuleResult = serde_json::from_value(stored).unwrap();
+    assert_eq!(rule_result.evaluated_at, 0);
+}
+
+#[cfg(feature = "binary")]
+#[test]
+fn binary_round_trip() {
+    use json_rules_engine::{Condition, Constraint};
+
+    let constraints = vec![
+        Constraint::StringEquals("a".into()),
+        Constraint::StringNotEquals("a".into()),
+        Constraint::StringContains("a".into()),
+        Constraint::StringContainsAll(vec!["a".into(), "b".into()]),
+        Constraint::StringContainsAny(vec!["a".into(), "b".into()]),
+        Constraint::StringDoesNotContain("a".into()),
+        Constraint::StringDoesNotContainAny(vec!["a".into(), "b".into()]),
+        Constraint::StringIn(vec!["a".into(), "b".into()]),
+        Constraint::StringNotIn(vec!["a".into(), "b".into()]),
+        Constraint::StringFuzzyMatches {
+            value: "a".into(),
+            max_distance: 1,
+            case_sensitive: true,
+        },
+        Constraint::StringSimilarityAtLeast {
+            value: "a".into(),
+            ratio: 0.5,
+            case_sensitive: false,
+        },
+        Constraint::StringInNamedSet("a".into()),
+        Constraint::StringNotInNamedSet("a".into()),
+        Constraint::IntEquals(1),
+        Constraint::IntNotEquals(1),
+        Constraint::IntContains(1),
+        Constraint::IntContainsAll(vec![1, 2]),
+        Constraint::IntContainsAny(vec![1, 2]),
+        Constraint::IntDoesNotContain(1),
+        Constraint::IntDoesNotContainAny(vec![1, 2]),
+        Constraint::IntIsSubset(vec![1, 2]),
+        Constraint::IntIsSuperset(vec![1, 2]),
+        Constraint::IntIn(vec![1, 2]),
+        Constraint::IntNotIn(vec![1, 2]),
+        Constraint::IntInNamedSet("a".into()),
+        Constraint::IntNotInNamedSet("a".into()),
+        Constraint::IntInRange(Some(1), Some(2)),
+        Constraint::IntNotInRange(1, 2),
+        Constraint::IntLessThan(1),
+        Constraint::IntLessThanInclusive(1),
+        Constraint::IntGreaterThan(1),
+        Constraint::IntGreaterThanInclusive(1),
+        Constraint::UintEquals(u64::MAX),
+        Constraint::UintNotEquals(u64::MAX),
+        Constraint::UintIn(vec![1, u64::MAX]),
+        Constraint::UintNotIn(vec![1, u64::MAX]),
+        Constraint::UintInRange(1, u64::MAX),
+        Constraint::UintNotInRange(1, u64::MAX),
+        Constraint::UintLessThan(u64::MAX),
+        Constraint::UintLessThanInclusive(u64::MAX),
+        Constraint::UintGreaterThan(u64::MAX),
+        Constraint::UintGreaterThanInclusive(u64::MAX),
+        Constraint::FloatEquals(1.5),
+        Constraint::FloatNotEquals(1.5),
+        Constraint::FloatEqualsRounded {
+            value: 1.5,
+            decimals: 2,
+        },
+        Constraint::FloatContains(1.5),
+        Constraint::FloatDoesNotContain(1.5),
+        Constraint::FloatIsSubset(vec![1.5, 2.5]),
+        Constraint::FloatIsSuperset(vec![1.5, 2.5]),
+        Constraint::FloatIn(vec![1.5, 2.5]),
+        Constraint::FloatNotIn(vec![1.5, 2.5]),
+        Constraint::FloatInRange(Some(1.5), None),
+        Constraint::FloatNotInRange(1.5, 2.5),
+        Constraint::FloatLessThan(1.5),
+        Constraint::FloatLessThanInclusive(1.5),
+        Constraint::FloatGreaterThan(1.5),
+        Constraint::FloatGreaterThanInclusive(1.5),
+        Constraint::NumberEquals(1.into()),
+        Constraint::NumberNotEquals(1.into()),
+        Constraint::NumberIn(vec![1.into(), u64::MAX.into()]),
+        Constraint::NumberNotIn(vec![1.into(), u64::MAX.into()]),
+        Constraint::NumberInRange((-1).into(), 2.into()),
+        Constraint::NumberNotInRange((-1).into(), 2.into()),
+        Constraint::NumberLessThan(1.into()),
+        Constraint::NumberLessThanInclusive(1.into()),
+        Constraint::NumberGreaterThan(1.into()),
+        Constraint::NumberGreaterThanInclusive(1.into()),
+        Constraint::BoolEquals(true),
+        Constraint::ValueEquals(json!({ "a": [1, "b"] })),
+        Constraint::ValueNotEquals(json!(null)),
+        Constraint::ValueIn(vec![json!(1), json!({ "a": 1 })]),
+        Constraint::DatetimeWithinLast(60),
+        Constraint::DatetimeOlderThan(60),
+        Constraint::DurationGreaterThan("PT1H".into()),
+        Constraint::DurationLessThan("90m".into()),
+        Constraint::DurationInRange("1h".into(), "2h 30m".into()),
+        Constraint::FloatPercentileGreaterThan {
+            percentile: 95.0,
+            value: 800.0,
+        },
+        Constraint::FloatPercentileLessThan {
+            percentile: 50.0,
+            value: 1.5,
+        },
+        Constraint::ArrayAllUnique(true),
+        Constraint::ArrayDistinctCountGreaterThanInclusive(2),
+        Constraint::AnyMatch(Box::new(json_rules_engine::bool_equals(
+            "trusted", false,
+        ))),
+        Constraint::NoneMatch(Box::new(json_rules_engine::bool_equals(
+            "trusted", false,
+        ))),
+        Constraint::IsUuid(true),
+        Constraint::IsUlid(false),
+        Constraint::IsEmail(true),
+        Constraint::IsUrl(false),
+        Constraint::DomainEquals("example.com".into()),
+        Constraint::DomainIn(vec!["example.com".into()]),
+        Constraint::DomainMatchesWildcard("*.example.com".into()),
+        Constraint::EmailDomainIn(vec!["example.com".into()]),
+        #[cfg(feature = "regex")]
+        Constraint::StringMatches("^ORD-".into()),
+        #[cfg(feature = "regex")]
+        Constraint::StringMatchesAny(vec!["^ORD-".into(), "bot".into()]),
+        #[cfg(feature = "regex")]
+        Constraint::StringMatchesNone(vec!["^ORD-".into(), "bot".into()]),
+    ];
+    // a new constraint variant must be added above
+    assert_eq!(constraints.len(), Constraint::operators().len());
+
+    let leaves = constraints
+        .into_iter()
+        .map(|constraint| {
+            serde_json::from_value::<Condition>(json!({
+                "field": "foo",
+                "path": "$.bar",
+                "operator": serde_json::to_value(&constraint).unwrap()["operator"],
+                "value": serde_json::to_value(&constraint).unwrap()["value"],
+            }))
+            .unwrap()
+        })
+        .collect::<Vec<_>>();
+
+    #[allow(unused_mut)]
+    let mut at_least =
+        vec![json!({ "field": "foo", "operator": "int_equals", "value": 1 })];
+    #[cfg(feature = "eval")]
+    at_least.push(json!({ "expr": "facts.foo == 1" }));
+
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                { "or": leaves },
+                { "not": { "field": "foo", "operator": "bool_equals", "value": false } },
+                {
+                    "should_minimum_meet": 1,
+                    "conditions": at_least
+                },
+            ]
+        },
+        "events": [
+            {
+                "type": "custom_event",
+                "coalescence": 60,
+                "coalescence_group": "group",
+                "params": {
+                    "name": "Cheng JIANG",
+                }
+            }
+        ]
+    });
+
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+    let bytes = rule.to_bytes().unwrap();
+
+    assert!(bytes.len() < serde_json::to_vec(&rule).unwrap().len());
+    assert_eq!(
+        serde_json::to_value(Rule::from_bytes(&bytes).unwrap()).unwrap(),
+        serde_json::to_value(&rule).unwrap()
+    );
+
+    let rule_result = rule.check_value(
+        &json!({ "foo": 1 }),
+        #[cfg(feature = "eval")]
+        &rhai::Engine::new(),
+    );
+    let bytes = rule_result.to_bytes().unwrap();
+
+    assert_eq!(
+        serde_json::to_value(RuleResult::from_bytes(&bytes).unwrap()).unwrap(),
+        serde_json::to_value(&rule_result).unwrap()
+    );
+
+    let mut bytes = bytes;
+    bytes[0] = json_rules_engine::BINARY_FORMAT_VERSION + 1;
+    assert!(RuleResult::from_bytes(&bytes).is_err());
+}
+
+#[cfg(feature = "eval")]
+#[tokio::test]
+async fn custom_closure_function() {
+    struct Config {
+        min_age: i64,
+    }
+
+    let config = Config { min_age: 21 };
+    let min_age = config.min_age;
+
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                {
+                    "expr": "old_enough(facts)",
+                },
+            ]
+        },
+        "events": [
+        ]
+    });
+
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.add_function("old_enough", move |p: Map| {
+        p.get("age")
+            .and_then(|age| age.as_int().ok())
+            .is_some_and(|age| age >= min_age)
+    });
+
+    let rule_results = engine.run(&json!({ "age": 24 })).await.unwrap();
+    assert_eq!(rule_results[0].condition_result.status, Status::Met);
+
+    let rule_results = engine.run(&json!({ "age": 18 })).await.unwrap();
+    assert_eq!(rule_results.len(), 0);
+}
+
+#[cfg(feature = "eval")]
+#[test]
+fn check_condition() {
+    let mut engine = Engine::new();
+    engine.add_function("old_enough", |p: Map| {
+        p.get("age")
+            .and_then(|age| age.as_int().ok())
+            .is_some_and(|age| age >= 21)
+    });
+    engine.register_set("names", ["Cheng JIANG".to_string()].into());
+    engine.set_variable("min_age", json!(18));
+
+    let condition: json_rules_engine::Condition =
+        serde_json::from_value(json!({
+            "and": [
+                { "expr": "old_enough(facts)" },
+                {
+                    "field": "name",
+                    "operator": "string_in_named_set",
+                    "value": "names"
+                },
+                {
+                    "field": "age",
+                    "operator": "int_greater_than",
+                    "value": { "$var": "min_age" }
+                }
+            ]
+        }))
+        .unwrap();
+
+    let facts = json!({ "name": "Cheng JIANG", "age": 24 });
+    let result = engine.check_condition(&condition, &facts);
+    assert_eq!(result.status, Status::Met);
+    // the engine's function, set and variable aren't known outside of it
+    assert_eq!(
+        condition.check_value(&facts, &rhai::Engine::new()).status,
+        Status::Unknown
+    );
+
+    let facts = json!({ "name": "Cheng JIANG", "age": 20 });
+    let result = engine.check_condition(&condition, &facts);
+    assert_eq!(result.status, Status::NotMet);
+    assert_eq!(result.children[0].status, Status::NotMet);
+}
+
+#[cfg(feature = "eval")]
+#[tokio::test]
+async fn custom_fallible_function() {
+    let rule_json = json!({
+        "conditions": {
+            "not": {
+                "expr": "has_name(facts)",
+            }
+        },
+        "events": [
+        ]
+    });
+
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+    let conditions = rule.conditions.clone();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.add_fallible_function("has_name", |p: Map| match p.get("name") {
+        Some(name) => Ok(!name.is_unit()),
+        None => Err("'name' is missing.".to_string()),
+    });
+
+    // an error makes the expression Unknown, so negating it doesn't match
+    let rule_results = engine.run(&json!({ "age": 24 })).await.unwrap();
+    assert_eq!(rule_results.len(), 0);
+
+    let result = engine.check_condition(&conditions, &json!({ "age": 24 }));
+    assert_eq!(result.status, Status::Unknown);
+    assert_eq!(result.error.as_deref(), Some("'name' is missing."));
+
+    let rule_results = engine.run(&json!({ "name": () })).await.unwrap();
+    assert_eq!(rule_results[0].condition_result.status, Status::Met);
+}
+
+#[cfg(feature = "eval")]
+#[tokio::test]
+async fn eval_string_methods() {
+    let mut engine = Engine::new();
+    let exprs = [
+        r#"facts.name.to_lower().contains("jiang")"#,
+        r#"facts.name.starts_with("Cheng") && !facts.name.ends_with("x")"#,
+        r#"facts.name.len() == 11"#,
+        r#"facts.name.split(" ").len() == 2"#,
+        r#"facts.name.sub_string(0, 5) == "Cheng""#,
+        r#"abs(facts.balance) > 10 && floor(facts.ratio) == 0"#,
+    ];
+    for (i, expr) in exprs.iter().enumerate() {
+        engine.add_rule(
+            serde_json::from_value(json!({
+                "id": i.to_string(),
+                "conditions": { "expr": expr },
+                "events": []
+            }))
+            .unwrap(),
+        );
+    }
+
+    let facts = json!({ "name": "Cheng JIANG", "balance": -42, "ratio": 0.5 });
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert_eq!(rule_results.len(), exprs.len());
+
+    // still unknown without a name, rather than failing the run
+    let rule_results = engine
+        .evaluate(&json!({ "balance": -42, "ratio": 0.5 }))
+        .unwrap();
+    assert_eq!(rule_results.len(), 1);
+}
+
+#[cfg(all(feature = "regex", feature = "eval"))]
+#[tokio::test]
+async fn eval_matches_regex() {
+    let rule = |expr: &str| -> Rule {
+        serde_json::from_value(json!({
+            "conditions": { "expr": expr },
+            "events": []
+        }))
+        .unwrap()
+    };
+
+    let facts = json!({ "email": "cheng@example.com" });
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule(
+        r#"matches_regex(facts.email, "^[a-z]+@example\\.com$")"#,
+    ));
+    assert_eq!(engine.run(&facts).await.unwrap().len(), 1);
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule(r#"facts.email.matches_regex("^admin@")"#));
+    assert!(engine.run(&facts).await.unwrap().is_empty());
+
+    // an invalid regex makes the condition unknown, so negating it too
+    let mut engine = Engine::new();
+    engine.add_rule(rule(r#"!matches_regex(facts.email, "(")"#));
+    assert!(engine.run(&facts).await.unwrap().is_empty());
+}
+
+#[derive(Debug, Clone)]
+struct CountingEvent {
+    ty: String,
+    triggered: Vec<Value>,
+}
+
+#[async_trait]
+impl EventTrait for CountingEvent {
+    fn new() -> Self {
+        Self {
+            ty: "counting_event".into(),
+            triggered: Vec::new(),
+        }
+    }
+
+    fn get_type(&self) -> &str {
+        &self.ty
+    }
+
+    fn validate(
+        &self,
+        _params: &HashMap<String, serde_json::Value>,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn trigger(
+        &mut self,
+        _params: &HashMap<String, serde_json::Value>,
+        facts: &(dyn ErasedSerialize + Sync),
+    ) -> Result<(), Error> {
+        self.triggered.push(
+            serde_json::from_str(&serde_json::to_string(facts).unwrap())
+                .unwrap(),
+        );
+
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn rule_group_once_per_run() {
+    let group_json = json!({
+        "id": "compliance",
+        "emit": "once_per_run",
+        "rules": [
+            {
+                "id": "too_young",
+                "conditions": {
+                    "field": "age",
+                    "operator": "int_less_than",
+                    "value": 25
+                },
+                "events": []
+            },
+            {
+                "id": "rust",
+                "conditions": {
+                    "field": "action",
+                    "operator": "string_equals",
+                    "value": "coding in rust"
+                },
+                "events": []
+            },
+            {
+                "id": "python",
+                "conditions": {
+                    "field": "action",
+                    "operator": "string_equals",
+                    "value": "coding in python"
+                },
+                "events": []
+            }
+        ],
+        "events": [
+            {
+                "type": "counting_event",
+                "params": {}
+            }
+        ]
+    });
+
+    let group: RuleGroup = serde_json::from_value(group_json).unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule_group(group.clone());
+
+    let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting_event.clone());
+
+    let facts = json!({
+        "age": 24,
+        "action": "coding in rust",
+    });
+
+    let (rule_results, run_info) = engine.run_with_info(&facts).await.unwrap();
+
+    assert_eq!(rule_results.len(), 2);
+    assert_eq!(run_info.rules_evaluated, 3);
+    assert_eq!(
+        run_info.group_results[0].matched_rules,
+        ["too_young", "rust"]
+    );
+
+    let triggered = counting_event.read().unwrap().triggered.clone();
+    assert_eq!(triggered.len(), 1);
+    assert_eq!(triggered[0]["_matched_rules"], json!(["too_young", "rust"]));
+
+    // the same group emitting per rule fires once per met member
+    let mut group = group;
+    group.emit = GroupEmit::PerRule;
+
+    let mut engine = Engine::new();
+    engine.add_rule_group(group);
+
+    let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting_event.clone());
+
+    engine.run(&facts).await.unwrap();
+
+    assert_eq!(counting_event.read().unwrap().triggered.len(), 2);
+}
+
+#[test]
+fn evaluate_from_threads() {
+    let rule_json = json!({
+        "conditions": {
+            "field": "age",
+            "operator": "int_in_range",
+            "value": [20, 25]
+        },
+        "events": [
+            {
+                "type": "counting_event",
+                "coalescence": 60,
+                "coalescence_group": "{{ age }}",
+                "params": {}
+            }
+        ]
+    });
+
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+
+    let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting_event.clone());
+
+    let engine = Arc::new(engine);
+
+    let handles = (0..8)
+        .map(|i| {
+            let engine = engine.clone();
+            std::thread::spawn(move || {
+                let rule_results =
+                    engine.evaluate(&json!({ "age": 18 + i })).unwrap();
+                (i, rule_results)
+            })
+        })
+        .collect::<Vec<_>>();
+
+    for handle in handles {
+        let (i, rule_results) = handle.join().unwrap();
+        if (2..=7).contains(&i) {
+            assert_eq!(rule_results.len(), 1);
+            // coalescence groups are still rendered, and never suppress
+            let event = serde_json::to_value(&rule_results[0].events[0]);
+            assert_eq!(
+                event.unwrap()["coalescence_group"],
+                json!(format!("{}", 18 + i))
+            );
+        } else {
+            assert_eq!(rule_results.len(), 0);
+        }
+    }
+
+    assert_eq!(counting_event.read().unwrap().triggered.len(), 0);
+}
+
+#[tokio::test]
+async fn test_escaped_fields() {
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "content/type",
+                    "operator": "string_equals",
+                    "value": "a"
+                },
+                {
+                    "field": "a~b",
+                    "operator": "string_equals",
+                    "value": "b"
+                },
+                {
+                    "field": "/headers/x~1forwarded~0for",
+                    "operator": "string_equals",
+                    "value": "c",
+                    "pointer": true
+                },
+                {
+                    "field": "person.name",
+                    "operator": "string_equals",
+                    "value": "Cheng JIANG",
+                    "path_syntax": "dotted"
+                },
+                {
+                    "field": "version.major",
+                    "operator": "int_equals",
+                    "value": 1,
+                    "path_syntax": "dotted"
+                },
+                {
+                    "field": "person/age",
+                    "operator": "int_equals",
+                    "value": 24
+                },
+            ]
+        },
+        "events": [
+        ]
+    });
+
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+
+    let facts = json!({
+        "content/type": "a",
+        "a~b": "b",
+        "headers": {
+            "x/forwarded~for": "c",
+        },
+        "person": {
+            "name": "Cheng JIANG",
+            "age": 24,
+        },
+        "version.major": 1,
+    });
+
+    let rule_results = engine.run(&facts).await.unwrap();
+
+    assert_eq!(rule_results[0].condition_result.status, Status::Met)
+}
+
+#[tokio::test]
+async fn named_sets() {
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "device_id",
+                    "operator": "string_not_in_named_set",
+                    "value": "denied_devices"
+                },
+                {
+                    "field": "port",
+                    "operator": "int_in_named_set",
+                    "value": "allowed_ports"
+                }
+            ]
+        },
+        "events": [
+        ]
+    });
+
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+
+    let mut engine = Engine::new();
+    assert!(engine.try_add_rule(rule.clone()).is_err());
+    assert!(engine.update_set("denied_devices", HashSet::new()).is_err());
+
+    engine.register_set(
+        "denied_devices",
+        (0..400_000).map(|i| format!("device-{}", i)).collect(),
+    );
+    assert!(engine.try_add_rule(rule.clone()).is_err());
+
+    engine.register_int_set("allowed_ports", HashSet::from([80, 443]));
+    engine.try_add_rule(rule).unwrap();
+
+    let facts = json!({
+        "device_id": "device-400000",
+        "port": 443,
+    });
+
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert_eq!(rule_results[0].condition_result.status, Status::Met);
+
+    let rule_results = engine
+        .run(&json!({ "device_id": "device-42", "port": 443 }))
+        .await
+        .unwrap();
+    assert_eq!(rule_results.len(), 0);
+
+    engine
+        .update_set(
+            "denied_devices",
+            HashSet::from(["device-400000".to_string()]),
+        )
+        .unwrap();
+
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert_eq!(rule_results.len(), 0);
+}
+
+#[test]
+fn missing_named_set_is_unknown() {
+    let condition = json_rules_engine::string_in_named_set("name", "names");
+
+    let result = condition.check_value(
+        &json!({ "name": "Cheng JIANG" }),
+        #[cfg(feature = "eval")]
+        &rhai::Engine::new(),
+    );
+
+    assert_eq!(result.status, Status::Unknown);
+}
+
+#[test]
+fn check_status_matches_check_value() {
+    use json_rules_engine::Condition;
+
+    /// xorshift, for trees that are the same on every run
+    fn random(seed: &mut u64, n: u64) -> u64 {
+        *seed ^= *seed << 13;
+        *seed ^= *seed >> 7;
+        *seed ^= *seed << 17;
+        *seed % n
+    }
+
+    fn children(seed: &mut u64, depth: u32) -> Vec<Value> {
+        (0..1 + random(seed, 4))
+            .map(|_| tree(seed, depth - 1))
+            .collect()
+    }
+
+    fn tree(seed: &mut u64, depth: u32) -> Value {
+        let kind = if depth == 0 { 0 } else { random(seed, 6) };
+        match kind {
+            0 | 1 => {
+                let field =
+                    ["a", "b", "text", "missing"][random(seed, 4) as usize];
+                json!({
+                    "field": field,
+                    "operator": "int_less_than",
+                    "value": random(seed, 10)
+                })
+            }
+            2 => json!({ "and": children(seed, depth) }),
+            3 => json!({ "or": children(seed, depth) }),
+            4 => json!({ "not": tree(seed, depth - 1) }),
+            _ => {
+                let conditions = children(seed, depth);
+                let at_least = random(seed, conditions.len() as u64 + 1);
+                let policy = ["strict", "optimistic"][random(seed, 2) as usize];
+                json!({
+                    "should_minimum_meet": at_least,
+                    "conditions": conditions,
+                    "unknown_policy": policy
+                })
+            }
+        }
+    }
+
+    let mut seed = 0x2545_f491_4f6c_dd1d_u64;
+    for _ in 0..500 {
+        let condition: Condition =
+            serde_json::from_value(tree(&mut seed, 4)).unwrap();
+        let facts = json!({
+            "a": random(&mut seed, 10),
+            "b": random(&mut seed, 10),
+            "text": "3"
+        });
+
+        assert_eq!(
+            condition.check_status(
+                &facts,
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            ),
+            condition
+                .check_value(
+                    &facts,
+                    #[cfg(feature = "eval")]
+                    &rhai::Engine::new(),
+                )
+                .status,
+            "{}",
+            serde_json::to_string(&condition).unwrap()
+        );
+    }
+}
+
+#[test]
+fn numeric_subset_and_superset() {
+    use json_rules_engine::{
+        float_is_subset, float_is_superset, int_is_subset, int_is_superset,
+    };
+
+    let facts = json!({
+        "ports": [443, 80, 443, 80],
+        "mixed": [80, "8080"],
+        "ratios": [0.5, 0.25, 0.5],
+        "empty": [],
+        "port": 80,
+    });
+    let status = |condition: json_rules_engine::Condition| {
+        condition
+            .check_value(
+                &facts,
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .status
+    };
+
+    assert_eq!(status(int_is_subset("ports", vec![80, 443])), Status::Met);
+    assert_eq!(
+        status(int_is_subset("ports", vec![22, 80, 443])),
+        Status::Met
+    );
+    assert_eq!(status(int_is_subset("ports", vec![80])), Status::NotMet);
+    assert_eq!(status(int_is_subset("mixed", vec![80])), Status::NotMet);
+    assert_eq!(status(int_is_subset("empty", vec![80])), Status::Met);
+    assert_eq!(status(int_is_subset("port", vec![80])), Status::NotMet);
+
+    assert_eq!(status(int_is_superset("ports", vec![443])), Status::Met);
+    assert_eq!(status(int_is_superset("ports", vec![])), Status::Met);
+    assert_eq!(
+        status(int_is_superset("ports", vec![80, 22])),
+        Status::NotMet
+    );
+    assert_eq!(status(int_is_superset("mixed", vec![80])), Status::Met);
+
+    assert_eq!(
+        status(float_is_subset("ratios", vec![0.25, 0.5, 0.75])),
+        Status::Met
+    );
+    assert_eq!(status(float_is_subset("ratios", vec![0.5])), Status::NotMet);
+    assert_eq!(
+        status(float_is_superset("ratios", vec![0.1 + 0.15, 0.5])),
+        Status::Met
+    );
+    assert_eq!(
+        status(float_is_superset("ratios", vec![0.75])),
+        Status::NotMet
+    );
+}
+
+#[test]
+fn large_array_constraints() {
+    use json_rules_engine::{
+        array_all_unique, array_distinct_at_least, float_contains,
+        float_is_subset, int_contains, int_contains_all, int_contains_any,
+        int_does_not_contain, int_does_not_contain_any, int_in, int_is_subset,
+        int_is_superset, int_not_in, string_contains, string_contains_all,
+        string_contains_any, string_does_not_contain_any, string_in,
+        string_not_in, uint_in, uint_not_in, Condition,
+    };
+
+    const LEN: i64 = 10_000;
+    let tags: Vec<String> = (0..LEN).map(|i| format!("tag-{}", i)).collect();
+    let names: Vec<&str> = tags.iter().take(20).map(String::as_str).collect();
+    let facts = json!({
+        "ids": (0..LEN).collect::<Vec<_>>(),
+        "tags": tags,
+        "mixed": [1, "2", 3],
+        "dups": [1, 2, 1, {"a": 1, "b": 2}, {"b": 2, "a": 1}],
+        "ratios": [0.5, 0.25],
+        "id": LEN - 1,
+        "name": "tag-19",
+    });
+
+    // long lists, hashed as the rules are added, and short ones, scanned
+    let cases: Vec<(Condition, Status)> = vec![
+        (int_contains("ids", LEN - 1), Status::Met),
+        (int_contains("ids", LEN), Status::NotMet),
+        (int_contains("id", LEN - 1), Status::NotMet),
+        (int_does_not_contain("ids", LEN), Status::Met),
+        (int_contains_all("ids", vec![0, LEN - 1]), Status::Met),
+        (int_contains_all("ids", vec![0, LEN]), Status::NotMet),
+        (int_contains_any("ids", vec![LEN, 5]), Status::Met),
+        (
+            int_does_not_contain_any("ids", vec![LEN, 5]),
+            Status::NotMet,
+        ),
+        (int_does_not_contain_any("ids", vec![LEN]), Status::Met),
+        (int_is_subset("ids", (0..LEN).collect()), Status::Met),
+        (int_is_subset("ids", (1..LEN).collect()), Status::NotMet),
+        (int_is_subset("mixed", (0..LEN).collect()), Status::NotMet),
+        (int_is_subset("id", (0..LEN).collect()), Status::NotMet),
+        (int_is_superset("ids", vec![3, LEN - 1]), Status::Met),
+        (int_is_superset("ids", vec![3, LEN]), Status::NotMet),
+        (string_contains("tags", "tag-9999"), Status::Met),
+        (
+            string_contains_all("tags", vec!["tag-0", "tag-1"]),
+            Status::Met,
+        ),
+        (
+            string_contains_all("tags", vec!["tag-0", "x"]),
+            Status::NotMet,
+        ),
+        (string_contains_any("tags", vec!["x", "tag-1"]), Status::Met),
+        (
+            string_does_not_contain_any("tags", vec!["x", "y"]),
+            Status::Met,
+        ),
+        (float_contains("ratios", 0.25), Status::Met),
+        (
+            float_is_subset("ratios", vec![0.25, 0.5, 0.75]),
+            Status::Met,
+        ),
+        (
+            float_is_subset("mixed", vec![1.0, 2.0, 3.0]),
+            Status::NotMet,
+        ),
+        (array_all_unique("ids"), Status::Met),
+        (array_all_unique("dups"), Status::NotMet),
+        (array_distinct_at_least("dups", 3), Status::Met),
+        (array_distinct_at_least("dups", 4), Status::NotMet),
+        (array_distinct_at_least("dups", 0), Status::Met),
+        (int_in("id", (0..LEN).collect()), Status::Met),
+        (int_in("id", (0..LEN - 1).collect()), Status::NotMet),
+        (int_in("name", (0..LEN).collect()), Status::NotMet),
+        (int_not_in("id", (0..LEN).collect()), Status::NotMet),
+        (int_not_in("id", (0..LEN - 1).collect()), Status::Met),
+        (int_not_in("name", (0..LEN).collect()), Status::NotMet),
+        (uint_in("id", (0..LEN as u64).collect()), Status::Met),
+        (uint_not_in("id", (0..LEN as u64).collect()), Status::NotMet),
+        (string_in("name", names.clone()), Status::Met),
+        (string_in("name", names[..19].to_vec()), Status::NotMet),
+        (string_in("id", names.clone()), Status::NotMet),
+        (string_not_in("name", names.clone()), Status::NotMet),
+        (string_not_in("name", names[..19].to_vec()), Status::Met),
+    ];
+
+    for (conditions, status) in cases {
+        let rule = Rule {
+            id: None,
+            conditions,
+            events: Vec::new(),
+            facts_to_add: Default::default(),
+            enabled: true,
+            tags: Vec::new(),
+            sample: None,
+        };
+        let met = status == Status::Met;
+
+        let mut engine = Engine::new();
+        engine.add_rule(rule.clone());
+        assert_eq!(
+            !engine.evaluate(&facts).unwrap().is_empty(),
+            met,
+            "{:?}",
+            rule.conditions
+        );
+
+        let engine =
+            Engine::build(vec![rule.clone()], Default::default()).unwrap();
+        assert_eq!(
+            !engine.evaluate(&facts).unwrap().is_empty(),
+            met,
+            "built {:?}",
+            rule.conditions
+        );
+    }
+}
+
+#[test]
+fn float_equals_rounded() {
+    use json_rules_engine::float_equals_rounded;
+
+    let status = |ratio: f64, value: f64, decimals: u32| {
+        float_equals_rounded("ratio", value, decimals)
+            .check_value(
+                &json!({ "ratio": ratio }),
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .status
+    };
+
+    assert_eq!(status(0.1 + 0.2, 0.3, 2), Status::Met);
+    // halves are rounded away from zero
+    assert_eq!(status(0.125, 0.13, 2), Status::Met);
+    assert_eq!(status(0.125, 0.12, 2), Status::NotMet);
+    assert_eq!(status(-0.125, -0.13, 2), Status::Met);
+    assert_eq!(status(0.1249, 0.12, 2), Status::Met);
+    assert_eq!(status(0.125, 0.1, 1), Status::Met);
+    assert_eq!(status(2.5, 3.0, 0), Status::Met);
+    assert_eq!(status(0.3, 0.30000001, 20), Status::NotMet);
+
+    let condition: json_rules_engine::Condition =
+        serde_json::from_value(json!({
+            "field": "ratio",
+            "operator": "float_equals_rounded",
+            "value": { "value": 0.3, "decimals": 2 }
+        }))
+        .unwrap();
+    assert_eq!(
+        condition
+            .check_value(
+                &json!({ "ratio": 0.30000000000000004 }),
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .status,
+        Status::Met
+    );
+}
+
+#[test]
+fn float_percentiles() {
+    use json_rules_engine::{
+        percentile_greater_than, percentile_less_than, Condition,
+    };
+
+    let status = |condition: Condition, times: Value| {
+        condition
+            .check_value(
+                &json!({ "response_times": times }),
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .status
+    };
+    let greater = |percentile, value, times: Value| {
+        status(
+            percentile_greater_than("response_times", percentile, value),
+            times,
+        )
+    };
+    let less = |percentile, value, times: Value| {
+        status(
+            percentile_less_than("response_times", percentile, value),
+            times,
+        )
+    };
+
+    // sorted, 120 300 450 900 1000: p50 is the 3rd, p95 at rank 3.8 is
+    // 900 + 0.8 * (1000 - 900) = 980, p10 at rank 0.4 is 120 + 0.4 * 180
+    let times = || json!([900, 120, 1000, 450, 300]);
+    assert_eq!(greater(50.0, 449.5, times()), Status::Met);
+    assert_eq!(greater(50.0, 450.5, times()), Status::NotMet);
+    assert_eq!(less(50.0, 450.5, times()), Status::Met);
+    assert_eq!(greater(95.0, 979.5, times()), Status::Met);
+    assert_eq!(greater(95.0, 980.5, times()), Status::NotMet);
+    assert_eq!(less(95.0, 980.5, times()), Status::Met);
+    assert_eq!(less(10.0, 192.5, times()), Status::Met);
+    assert_eq!(less(10.0, 191.5, times()), Status::NotMet);
+    assert_eq!(greater(100.0, 999.5, times()), Status::Met);
+    assert_eq!(greater(100.0, 1000.0, times()), Status::NotMet);
+    assert_eq!(greater(95.0, 0.3, json!([0.25, 0.5])), Status::Met);
+
+    // a single element is every percentile
+    for percentile in [1.0, 50.0, 100.0] {
+        assert_eq!(greater(percentile, 41.5, json!([42])), Status::Met);
+        assert_eq!(less(percentile, 42.5, json!([42])), Status::Met);
+        assert_eq!(greater(percentile, 42.0, json!([42])), Status::NotMet);
+    }
+
+    for times in [json!([]), json!([1, "2"]), json!(3)] {
+        assert_eq!(greater(50.0, 0.0, times.clone()), Status::NotMet);
+        assert_eq!(less(50.0, 10.0, times), Status::NotMet);
+    }
+
+    let condition = |percentile: f64| {
+        serde_json::from_value::<Condition>(json!({
+            "field": "response_times",
+            "operator": "float_percentile_greater_than",
+            "value": { "percentile": percentile, "value": 800 }
+        }))
+    };
+    assert_eq!(status(condition(95.0).unwrap(), times()), Status::Met);
+    assert!(condition(100.0).is_ok());
+    assert!(condition(0.0).is_err());
+    assert!(condition(-5.0).is_err());
+    assert!(condition(100.5).is_err());
+
+    // loading a whole rule tells why
+    let e = serde_json::from_value::<Rule>(json!({
+        "conditions": {
+            "and": [{
+                "field": "response_times",
+                "operator": "float_percentile_less_than",
+                "value": { "percentile": 150, "value": 800 }
+            }]
+        },
+        "events": []
+    }))
+    .unwrap_err();
+    assert_eq!(e.to_string(), "Percentile 150 isn't within (0, 100]");
+}
+
+#[test]
+fn open_ranges() {
+    use json_rules_engine::{
+        float_at_least, float_at_most, int_at_least, int_at_most, Condition,
+        Constraint,
+    };
+
+    let status = |condition: &Condition, amount: Value| {
+        condition
+            .check_value(
+                &json!({ "amount": amount }),
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .status
+    };
+    let leaf = |operator: &str, value: Value| -> Condition {
+        serde_json::from_value(json!({
+            "field": "amount",
+            "operator": operator,
+            "value": value
+        }))
+        .unwrap()
+    };
+
+    let above = leaf("int_in_range", json!([10000, null]));
+    assert_eq!(status(&above, json!(9999)), Status::NotMet);
+    assert_eq!(status(&above, json!(10000)), Status::Met);
+    assert_eq!(status(&above, json!(i64::MAX)), Status::Met);
+    assert_eq!(above, int_at_least("amount", 10000));
+
+    let below = leaf("int_in_range", json!([null, 25]));
+    assert_eq!(status(&below, json!(i64::MIN)), Status::Met);
+    assert_eq!(status(&below, json!(25)), Status::Met);
+    assert_eq!(status(&below, json!(26)), Status::NotMet);
+    assert_eq!(status(&below, json!("25")), Status::NotMet);
+    assert_eq!(below, int_at_most("amount", 25));
+
+    let above = leaf("float_in_range", json!([0.5, null]));
+    assert_eq!(status(&above, json!(0.5)), Status::Met);
+    assert_eq!(status(&above, json!(0.49)), Status::NotMet);
+    assert_eq!(above, float_at_least("amount", 0.5));
+
+    let below = leaf("float_in_range", json!([null, 0.5]));
+    assert_eq!(status(&below, json!(-1e300)), Status::Met);
+    assert_eq!(status(&below, json!(0.51)), Status::NotMet);
+    assert_eq!(below, float_at_most("amount", 0.5));
+
+    // closed ranges are as they were
+    let closed = leaf("int_in_range", json!([20, 25]));
+    assert_eq!(status(&closed, json!(19)), Status::NotMet);
+    assert_eq!(status(&closed, json!(25)), Status::Met);
+
+    for operator in ["int_in_range", "float_in_range"] {
+        assert!(serde_json::from_value::<Condition>(json!({
+            "field": "amount",
+            "operator": operator,
+            "value": [null, null]
+        }))
+        .is_err());
+        let e = serde_json::from_value::<Constraint>(json!({
+            "operator": operator,
+            "value": [null, null]
+        }))
+        .unwrap_err();
+        assert!(e.to_string().contains("at least one bound"), "{}", e);
+        let e = serde_json::from_value::<Rule>(json!({
+            "conditions": {
+                "not": {
+                    "field": "amount",
+                    "operator": operator,
+                    "value": [null, null]
+                }
+            },
+            "events": []
+        }))
+        .unwrap_err();
+        assert_eq!(e.to_string(), "A range needs at least one bound");
+    }
+
+    // an open side serializes as null
+    for (constraint, value) in [
+        (Constraint::IntInRange(Some(20), None), json!([20, null])),
+        (Constraint::IntInRange(None, Some(25)), json!([null, 25])),
+        (
+            Constraint::FloatInRange(Some(0.5), None),
+            json!([0.5, null]),
+        ),
+        (
+            Constraint::FloatInRange(None, Some(0.5)),
+            json!([null, 0.5]),
+        ),
+    ] {
+        let serialized = serde_json::to_value(&constraint).unwrap();
+        assert_eq!(serialized["value"], value);
+        assert_eq!(
+            serde_json::from_value::<Constraint>(serialized).unwrap(),
+            constraint
+        );
+    }
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": int_at_least("amount", 10000),
+        "events": []
+    }))
+    .unwrap();
+    let round_trip: Rule =
+        serde_json::from_str(&serde_json::to_string(&rule).unwrap()).unwrap();
+    assert_eq!(round_trip, rule);
+}
+
+#[test]
+fn domain_constraints() {
+    use json_rules_engine::{
+        domain_equals, domain_in, domain_matches, email_domain_in, Condition,
+    };
+
+    let status = |condition: Condition, fact: Value| {
+        condition
+            .check_value(
+                &json!({ "host": fact }),
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .status
+    };
+
+    let equals = |fact| status(domain_equals("host", "Example.com"), fact);
+    assert_eq!(equals(json!("example.com")), Status::Met);
+    assert_eq!(equals(json!("EXAMPLE.COM.")), Status::Met);
+    assert_eq!(equals(json!("www.example.com")), Status::NotMet);
+    assert_eq!(equals(json!("")), Status::NotMet);
+    assert_eq!(equals(json!(42)), Status::NotMet);
+
+    let allowed = || domain_in("host", vec!["example.com", "xn--bcher-kva.ch"]);
+    assert_eq!(status(allowed(), json!("XN--BCHER-KVA.CH")), Status::Met);
+    // internationalized domains aren't converted to punycode
+    assert_eq!(status(allowed(), json!("bücher.ch")), Status::NotMet);
+    assert_eq!(status(allowed(), json!("example.org")), Status::NotMet);
+
+    let wildcard =
+        |fact| status(domain_matches("host", "*.internal.example.com"), fact);
+    assert_eq!(wildcard(json!("db.internal.example.com")), Status::Met);
+    assert_eq!(wildcard(json!("DB.Internal.Example.com.")), Status::Met);
+    // the wildcard stands for a single label
+    assert_eq!(wildcard(json!("internal.example.com")), Status::NotMet);
+    assert_eq!(wildcard(json!("a.db.internal.example.com")), Status::NotMet);
+    assert_eq!(wildcard(json!(".internal.example.com")), Status::NotMet);
+    assert_eq!(wildcard(json!("dbinternal.example.com")), Status::NotMet);
+    assert_eq!(
+        status(domain_matches("host", "example.com"), json!("example.com")),
+        Status::Met
+    );
+    for pattern in ["*", "*.", "a.*.example.com", "*example.com", "*.*.com"] {
+        let leaf = json!({
+            "field": "host",
+            "operator": "domain_matches_wildcard",
+            "value": pattern
+        });
+        assert!(
+            serde_json::from_value::<Condition>(leaf).is_err(),
+            "{}",
+            pattern
+        );
+    }
+    let e = serde_json::from_value::<Rule>(json!({
+        "conditions": {
+            "or": [{
+                "field": "host",
+                "operator": "domain_matches_wildcard",
+                "value": "a.*.example.com"
+            }]
+        },
+        "events": []
+    }))
+    .unwrap_err();
+    assert_eq!(
+        e.to_string(),
+        "Invalid wildcard `a.*.example.com`, only a leading `*.` is supported"
+    );
+
+    let email = |fact| {
+        status(
+            email_domain_in("host", vec!["example.com", "corp.example"]),
+            fact,
+        )
+    };
+    assert_eq!(email(json!("jane@Example.com")), Status::Met);
+    // the domain is after the last `@`
+    assert_eq!(email(json!("\"a@b\"@corp.example")), Status::Met);
+    assert_eq!(email(json!("jane@evil.com@example.com")), Status::Met);
+    assert_eq!(email(json!("jane@example.com@evil.com")), Status::NotMet);
+    assert_eq!(email(json!("jane@mail.example.com")), Status::NotMet);
+    for malformed in ["example.com", "@example.com", "jane@", "jane@.", ""] {
+        assert_eq!(email(json!(malformed)), Status::NotMet, "{}", malformed);
+    }
+}
+
+#[test]
+fn string_fuzzy_matches() {
+    use json_rules_engine::{
+        string_fuzzy_matches, string_similarity_at_least, Condition,
+        FUZZY_MAX_CHARS,
+    };
+
+    let status = |condition: Condition, merchant: &str| {
+        condition
+            .check_value(
+                &json!({ "merchant": merchant }),
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .status
+    };
+    let fuzzy = |merchant: &str, max_distance: u32| {
+        status(
+            string_fuzzy_matches("merchant", "Starbucks", max_distance),
+            merchant,
+        )
+    };
+
+    assert_eq!(fuzzy("Starbucks", 0), Status::Met);
+    // case insensitive by default
+    assert_eq!(fuzzy("STARBUCKS", 0), Status::Met);
+    // a transposition is a single edit
+    assert_eq!(fuzzy("Starbukcs", 1), Status::Met);
+    assert_eq!(fuzzy("Starbukcs", 0), Status::NotMet);
+    // typos
+    assert_eq!(fuzzy("Starbuks", 1), Status::Met);
+    assert_eq!(fuzzy("Stabrucks", 2), Status::Met);
+    assert_eq!(fuzzy("Strabukcs", 1), Status::NotMet);
+    assert_eq!(fuzzy("Walmart", 3), Status::NotMet);
+
+    let similar = |merchant: &str, ratio: f64| {
+        status(
+            string_similarity_at_least("merchant", "Starbucks", ratio),
+            merchant,
+        )
+    };
+    assert_eq!(similar("starbucks", 1.0), Status::Met);
+    assert_eq!(similar("Starbuks", 0.85), Status::Met);
+    assert_eq!(similar("Starbuks", 0.9), Status::NotMet);
+    assert_eq!(similar("Walmart", 0.5), Status::NotMet);
+    assert_eq!(
+        status(string_similarity_at_least("merchant", "", 1.0), ""),
+        Status::Met
+    );
+
+    // past the length cap, strings aren't compared
+    let long = "a".repeat(FUZZY_MAX_CHARS + 1);
+    assert_eq!(
+        status(string_fuzzy_matches("merchant", &long, 10), &long),
+        Status::NotMet
+    );
+
+    let condition: Condition = serde_json::from_value(json!({
+        "field": "merchant",
+        "operator": "string_fuzzy_matches",
+        "value": {
+            "value": "Starbucks",
+            "max_distance": 1,
+            "case_sensitive": true
+        }
+    }))
+    .unwrap();
+    assert_eq!(status(condition.clone(), "Starbuks"), Status::Met);
+    assert_eq!(status(condition, "starbuks"), Status::NotMet);
+}
+
+#[test]
+fn duration_constraints() {
+    use json_rules_engine::{
+        duration_greater_than, duration_in_range, duration_less_than,
+        parse_duration, Condition,
+    };
+
+    let status = |condition: Condition, duration: Value| {
+        condition
+            .check_value(
+                &json!({ "duration": duration }),
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .status
+    };
+
+    // ISO 8601 and humanized durations, on either side
+    for fact in vec![
+        json!("PT2H30M"),
+        json!("P0DT2H30M"),
+        json!("PT9000S"),
+        json!("PT2.5H"),
+        json!("2h 30m"),
+        json!("2h30m"),
+        json!("150 minutes"),
+        json!("9000s"),
+        json!(9000),
+    ] {
+        for (value, expected) in [("1h", Status::Met), ("PT3H", Status::NotMet)]
+        {
+            assert_eq!(
+                status(duration_greater_than("duration", value), fact.clone()),
+                expected,
+                "{} > {}",
+                fact,
+                value
+            );
+        }
+        assert_eq!(
+            status(duration_less_than("duration", "P1D"), fact.clone()),
+            Status::Met
+        );
+        assert_eq!(
+            status(duration_in_range("duration", "2h 30m", "PT150M"), fact),
+            Status::Met
+        );
+    }
+    assert_eq!(parse_duration("P1W"), Some(604_800.0));
+    assert_eq!(parse_duration("1d 500ms"), Some(86_400.5));
+
+    // facts that don't parse aren't met
+    for fact in [
+        json!("2 fortnights"),
+        json!("P1Y"),
+        json!("PT"),
+        json!("PT30M2H"),
+        json!("90"),
+        json!(""),
+        json!(true),
+    ] {
+        assert_eq!(
+            status(duration_greater_than("duration", "0s"), fact.clone()),
+            Status::NotMet,
+            "{}",
+            fact
+        );
+        assert_eq!(
+            status(duration_less_than("duration", "P1D"), fact),
+            Status::NotMet
+        );
+    }
+
+    // values that don't fail the rule to load
+    let condition = |operator: &str, value: Value| {
+        serde_json::from_value::<Condition>(json!({
+            "field": "duration",
+            "operator": operator,
+            "value": value
+        }))
+    };
+    assert!(condition("duration_greater_than", json!("1h")).is_ok());
+    assert!(condition("duration_greater_than", json!("an hour")).is_err());
+    assert!(condition("duration_less_than", json!("P1M")).is_err());
+    assert!(condition("duration_in_range", json!(["1h", "2h"])).is_ok());
+    assert!(condition("duration_in_range", json!(["1h", "soon"])).is_err());
+
+    // nor in a rule, whose error tells which
+    let e = serde_json::from_value::<Rule>(json!({
+        "conditions": {
+            "and": [
+                { "field": "name", "operator": "string_equals", "value": "a" },
+                {
+                    "not": {
+                        "field": "duration",
+                        "operator": "duration_less_than",
+                        "value": "an hour"
+                    }
+                }
+            ]
+        },
+        "events": []
+    }))
+    .unwrap_err();
+    assert_eq!(e.to_string(), "Invalid duration `an hour`");
+}
+
+#[test]
+fn value_equals() {
+    use json_rules_engine::{value_equals, value_in, value_not_equals};
+
+    let facts = json!({
+        "address": { "zip": "75001", "country": "FR", "lines": ["1 rue", 2] },
+        "mixed": [1, "a", null, { "b": true }],
+        "amount": 1,
+        "ratio": 0.5,
+        "payment": { "kind": "card" },
+    });
+    let status = |condition: json_rules_engine::Condition| {
+        condition
+            .check_value(
+                &facts,
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .status
+    };
+
+    // objects, whatever the order of their keys
+    let address =
+        json!({ "country": "FR", "lines": ["1 rue", 2], "zip": "75001" });
+    assert_eq!(
+        status(value_equals("address", address.clone())),
+        Status::Met
+    );
+    assert_eq!(
+        status(value_equals(
+            "address",
+            json!({ "country": "FR", "zip": "75001" })
+        )),
+        Status::NotMet
+    );
+    assert_eq!(status(value_not_equals("address", address)), Status::NotMet);
+
+    // arrays, in order
+    assert_eq!(
+        status(value_equals("mixed", json!([1, "a", null, { "b": true }]))),
+        Status::Met
+    );
+    assert_eq!(
+        status(value_equals("mixed", json!(["a", 1, null, { "b": true }]))),
+        Status::NotMet
+    );
+
+    // numbers, by value, nested ones included
+    assert_eq!(status(value_equals("amount", json!(1.0))), Status::Met);
+    assert_eq!(status(value_equals("amount", json!("1"))), Status::NotMet);
+    assert_eq!(status(value_equals("ratio", json!(0.5))), Status::Met);
+    assert_eq!(
+        status(value_equals(
+            "mixed",
+            json!([1.0, "a", null, { "b": true }])
+        )),
+        Status::Met
+    );
+    assert_eq!(status(value_not_equals("amount", json!(2))), Status::Met);
+
+    // one shape among a few
+    let shapes = || {
+        vec![
+            json!({ "kind": "card", "last4": "4242" }),
+            json!({ "kind": "card" }),
+            json!("cash"),
+        ]
+    };
+    assert_eq!(status(value_in("payment", shapes())), Status::Met);
+    assert_eq!(status(value_in("address", shapes())), Status::NotMet);
+
+    // and from JSON rules
+    let condition: json_rules_engine::Condition =
+        serde_json::from_value(json!({
+            "field": "address",
+            "operator": "value_in",
+            "value": [{ "zip": "75001", "country": "FR", "lines": ["1 rue", 2.0] }]
+        }))
+        .unwrap();
+    assert_eq!(status(condition), Status::Met);
+}
+
+#[cfg(all(feature = "regex", feature = "broadcast"))]
+#[tokio::test]
+async fn string_matches_captures() {
+    let rule = |id: &str, order_id: &str| -> Rule {
+        serde_json::from_value(json!({
+            "id": id,
+            "conditions": {
+                "and": [
+                    {
+                        "field": "order_id",
+                        "operator": "string_matches",
+                        "value": order_id
+                    },
+                    {
+                        "field": "customer",
+                        "operator": "string_matches",
+                        "value": "^(?P<first>\\w+) (?P<last>\\w+)$",
+                        "label": "name"
+                    }
+                ]
+            },
+            "events": [
+                {
+                    "type": "message",
+                    "params": {
+                        "message": "Order from region {{ _captures.order_id.region }} by {{ _captures.name.last }}"
+                    }
+                }
+            ]
+        }))
+        .unwrap()
+    };
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule("orders", "^ORD-(?P<region>\\w\\w)-\\d+$"));
+    engine.add_rule(rule("refunds", "^REF-(?P<region>\\w\\w)-\\d+$"));
+    let message = Arc::new(RwLock::new(CountingEvent {
+        ty: "message".into(),
+        triggered: Vec::new(),
+    }));
+    engine.add_event(message.clone());
+    let mut rx = engine.subscribe();
+
+    let facts = json!({ "order_id": "ORD-EU-42", "customer": "Cheng JIANG" });
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert_eq!(rule_results.len(), 1);
+    assert_eq!(
+        serde_json::to_value(&rule_results[0].captures).unwrap(),
+        json!({
+            "order_id": { "region": "EU" },
+            "name": { "first": "Cheng", "last": "JIANG" }
+        })
+    );
+
+    let envelope = rx.recv().await.unwrap();
+    assert_eq!(envelope.rule_id.as_deref(), Some("orders"));
+    assert_eq!(
+        envelope.event.params["message"],
+        json!("Order from region EU by JIANG")
+    );
+    assert!(rx.try_recv().is_err());
+    // the event itself receives the facts as they are
+    assert_eq!(message.read().unwrap().triggered, vec![facts.clone()]);
+
+    // captures don't outlive the evaluation of their rule
+    let rule_results = engine
+        .evaluate(&json!({ "order_id": "REF-US-1", "customer": "Cheng" }))
+        .unwrap();
+    assert!(rule_results.is_empty());
+    let rule_results = engine
+        .evaluate(&json!({ "order_id": "REF-US-1", "customer": "A B" }))
+        .unwrap();
+    assert_eq!(rule_results[0].rule_id.as_deref(), Some("refunds"));
+    assert_eq!(
+        rule_results[0].captures["order_id"],
+        json!({ "region": "US" })
+    );
+
+    let invalid = rule("invalid", "(");
+    let status = invalid
+        .check_value(
+            &facts,
+            #[cfg(feature = "eval")]
+            &rhai::Engine::new(),
+        )
+        .condition_result
+        .status;
+    assert_eq!(status, Status::Unknown);
+}
+
+#[cfg(feature = "regex")]
+#[test]
+fn string_matches_any() {
+    use json_rules_engine::Constraint;
+
+    let rule = |operator: &str| -> Rule {
+        serde_json::from_value(json!({
+            "conditions": {
+                "field": "user_agent",
+                "operator": operator,
+                "value": ["(?i)bot\\b", "^curl/", "spider"]
+            },
+            "events": []
+        }))
+        .unwrap()
+    };
+    let any = rule("string_matches_any");
+    let none = rule("string_matches_none");
+    let engine =
+        Engine::build(vec![any.clone(), none.clone()], Default::default())
+            .unwrap();
+    let mut added = Engine::new();
+    added.add_rule(any.clone());
+    added.add_rule(none.clone());
+
+    for (user_agent, matched) in [
+        (json!("Mozilla/5.0 (compatible; Googlebot/2.1)"), true),
+        (json!("RoboticsWeekly/1.0"), false),
+        (json!("Mozilla/5.0 (compatible; bingBOT)"), true),
+        (json!("curl/8.4.0"), true),
+        (json!("Baiduspider-render/2.0"), true),
+        (json!("Mozilla/5.0 (X11; Linux x86_64; rv:109.0)"), false),
+    ] {
+        let facts = json!({ "user_agent": user_agent });
+        let status = |rule: &Rule| {
+            rule.check_value(
+                &facts,
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .condition_result
+            .status
+        };
+        let expected = |met: bool| match met {
+            true => Status::Met,
+            false => Status::NotMet,
+        };
+        assert_eq!(status(&any), expected(matched), "{}", user_agent);
+        assert_eq!(status(&none), expected(!matched), "{}", user_agent);
+
+        // the regex sets compiled by `Engine::build`, or as the rules are
+        // added, agree
+        assert_eq!(engine.evaluate(&facts).unwrap().len(), 1);
+        assert_eq!(added.evaluate(&facts).unwrap().len(), 1);
+    }
+
+    // neither matches what isn't a string
+    let facts = json!({ "user_agent": 42 });
+    for rule in [&any, &none] {
+        let status = rule
+            .check_value(
+                &facts,
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .condition_result
+            .status;
+        assert_eq!(status, Status::NotMet);
+    }
+    assert!(engine.evaluate(&facts).unwrap().is_empty());
+
+    // the pattern that doesn't compile is named by its index
+    let e = serde_json::from_value::<Constraint>(json!({
+        "operator": "string_matches_any",
+        "value": ["bot", "(spider", "crawler"]
+    }))
+    .unwrap_err();
+    assert!(e.to_string().contains("pattern #1 `(spider`"), "{}", e);
+    let e = serde_json::from_value::<Rule>(json!({
+        "conditions": {
+            "and": [{
+                "field": "user_agent",
+                "operator": "string_matches_any",
+                "value": ["bot", "(spider", "crawler"]
+            }]
+        },
+        "events": []
+    }))
+    .unwrap_err();
+    assert!(e.to_string().contains("pattern #1 `(spider`"), "{}", e);
+
+    let rule = Rule {
+        id: None,
+        conditions: json_rules_engine::string_matches_none(
+            "user_agent",
+            vec!["bot", "["],
+        ),
+        events: Vec::new(),
+        facts_to_add: Default::default(),
+        enabled: true,
+        tags: Vec::new(),
+        sample: None,
+    };
+    assert!(Engine::build(vec![rule.clone()], Default::default()).is_err());
+    let e = Engine::new().try_add_rule(rule).unwrap_err();
+    assert!(e.to_string().contains("pattern #1 `[`"), "{}", e);
+}
+
+#[tokio::test]
+async fn frequency_conditions() {
+    use std::sync::Mutex;
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "of": {
+                "field": "failed_login",
+                "operator": "bool_equals",
+                "value": true
+            },
+            "at_least": 3,
+            "window_secs": 600
+        },
+        "events": [{ "type": "counting_event", "params": {} }]
+    }))
+    .unwrap();
+
+    let t0 = chrono::Utc::now();
+    let clock = Arc::new(Mutex::new(t0));
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    let now = clock.clone();
+    engine.set_now_provider(Arc::new(move || *now.lock().unwrap()));
+    let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting_event.clone());
+
+    let failed = json!({ "failed_login": true });
+    let at = |minutes: i64| {
+        *clock.lock().unwrap() = t0 + chrono::Duration::minutes(minutes);
+    };
+    let met = |results: Vec<RuleResult>| results.len();
+
+    // the third failure within 10 minutes
+    at(0);
+    assert_eq!(met(engine.run_keyed("alice", &failed).await.unwrap()), 0);
+    at(4);
+    assert_eq!(met(engine.run_keyed("alice", &failed).await.unwrap()), 0);
+    // other entities and successes don't count
+    assert_eq!(met(engine.run_keyed("bob", &failed).await.unwrap()), 0);
+    let success = json!({ "failed_login": false });
+    assert_eq!(met(engine.run_keyed("alice", &success).await.unwrap()), 0);
+    at(8);
+    assert_eq!(met(engine.run_keyed("alice", &failed).await.unwrap()), 1);
+    assert_eq!(counting_event.read().unwrap().triggered.len(), 1);
+
+    // the failures at 0 and 4 fell out of the window
+    at(15);
+    assert_eq!(met(engine.run_keyed("alice", &failed).await.unwrap()), 0);
+    at(25);
+    assert_eq!(met(engine.run_keyed("alice", &failed).await.unwrap()), 0);
+
+    // the least recently used history is evicted past the max
+    engine.set_frequency_max_keys(1);
+    at(26);
+    assert_eq!(met(engine.run_keyed("bob", &failed).await.unwrap()), 0);
+    at(27);
+    assert_eq!(met(engine.run_keyed("alice", &failed).await.unwrap()), 0);
+
+    engine
+        .set_frequency_max_keys(json_rules_engine::DEFAULT_FREQUENCY_MAX_KEYS);
+    at(28);
+    assert_eq!(met(engine.run_keyed("alice", &failed).await.unwrap()), 0);
+    at(29);
+    assert_eq!(met(engine.run_keyed("alice", &failed).await.unwrap()), 1);
+}
+
+#[tokio::test]
+async fn render_condition_tree() {
+    use json_rules_engine::RenderOptions;
+
+    let rule: Rule = serde_json::from_value(json!({
+        "id": "adult_in_paris",
+        "conditions": {
+            "and": [
+                {
+                    "field": "name",
+                    "operator": "string_equals",
+                    "value": "Cheng JIANG"
+                },
+                {
+                    "or": [
+                        {
+                            "field": "age",
+                            "operator": "int_greater_than",
+                            "value": 30
+                        },
+                        {
+                            "field": "address.city",
+                            "operator": "string_equals",
+                            "value": "Paris",
+                            "path_syntax": "dotted"
+                        }
+                    ]
+                },
+                {
+                    "field": "vip",
+                    "operator": "bool_equals",
+                    "value": true,
+                    "default": true
+                }
+            ]
+        },
+        "events": [
+            {
+                "type": "counting_event",
+                "params": { "message": "Welcome" }
+            }
+        ]
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.add_event(Arc::new(RwLock::new(CountingEvent::new())));
+    let facts = json!({
+        "name": "Cheng JIANG",
+        "age": 24,
+        "address": { "city": "Paris" }
+    });
+    let rule_results = engine.run(&facts).await.unwrap();
+
+    let opts = RenderOptions {
+        color: false,
+        facts: Some(&facts),
+    };
+    let tree = rule_results[0].condition_result.render_tree(opts);
+    assert_eq!(
+        tree,
+        "\
+✓ And
+├── ✓ name = \"Cheng JIANG\"
+├── ✓ Or
+│   ├── ✗ age = 24
+│   └── ✓ address.city = \"Paris\"
+└── ✓ vip (default)
+"
+    );
+
+    let rendered = rule_results[0].render_with(RenderOptions {
+        color: false,
+        facts: None,
+    });
+    assert!(rendered.starts_with("rule adult_in_paris\n✓ And\n├── ✓ name\n"));
+    assert!(rendered.ends_with("events:\n└── counting_event: Welcome\n"));
+
+    let colored = rule_results[0].condition_result.render_tree(RenderOptions {
+        color: true,
+        facts: None,
+    });
+    assert!(colored.starts_with("\x1b[32m✓\x1b[0m And\n"));
+}
+
+/// Rules over every kind of fact, for comparing `run` and `run_view`
+fn facts_view_rules() -> Vec<Rule> {
+    serde_json::from_value(json!([
+        {
+            "id": "by_name",
+            "conditions": {
+                "and": [
+                    {
+                        "field": "name",
+                        "operator": "string_equals",
+                        "value": "Cheng JIANG"
+                    },
+                    {
+                        "field": "address/city",
+                        "operator": "string_in",
+                        "value": ["Paris", "Lyon"]
+                    }
+                ]
+            },
+            "events": [{ "type": "counting_event", "params": {} }]
+        },
+        {
+            "id": "by_numbers",
+            "conditions": {
+                "or": [
+                    {
+                        "field": "age",
+                        "operator": "int_in_range",
+                        "value": [20, 25]
+                    },
+                    {
+                        "field": "score",
+                        "operator": "float_greater_than",
+                        "value": 0.5
+                    },
+                    {
+                        "field": "visits",
+                        "operator": "uint_greater_than",
+                        "value": 18446744073709551000u64
+                    }
+                ]
+            },
+            "events": []
+        },
+        {
+            "id": "by_arrays",
+            "conditions": {
+                "and": [
+                    {
+                        "field": "tags",
+                        "operator": "string_contains",
+                        "value": "vip"
+                    },
+                    {
+                        "field": "orders/0/paid",
+                        "operator": "bool_equals",
+                        "value": true
+                    },
+                    {
+                        "field": "missing",
+                        "operator": "string_equals",
+                        "value": "x"
+                    }
+                ]
+            },
+            "events": []
+        }
+    ]))
+    .unwrap()
+}
+
+const FACTS_VIEW_DOCUMENTS: &[&str] = &[
+    r#"{"name": "Cheng JIANG", "age": 24, "address": {"city": "Paris"}}"#,
+    r#"{"name": "Cheng JIANG", "address": {"city": "Nice"}, "score": 0.75}"#,
+    r#"{"age": -3, "score": 1e3, "visits": 18446744073709551615}"#,
+    r#"{"tags": ["new", "vip"], "orders": [{"paid": true}], "unread": null}"#,
+    r#"{"address/city": "Lyon", "name": "Cheng JIANG", "address": 4}"#,
+    r#"[1, 2, 3]"#,
+];
+
+/// The results of the run, without the timings
+fn untimed(rule_results: Vec<RuleResult>) -> Vec<Value> {
+    rule_results
+        .into_iter()
+        .map(|rule_result| {
+            let mut v = serde_json::to_value(rule_result).unwrap();
+            v["evaluated_at"] = Value::Null;
+            v["duration_micros"] = Value::Null;
+            v
+        })
+        .collect()
+}
+
+#[tokio::test]
+async fn run_view_values() {
+    for document in FACTS_VIEW_DOCUMENTS {
+        let facts: Value = serde_json::from_str(document).unwrap();
+
+        let mut engine = Engine::new();
+        engine.add_rules(facts_view_rules());
+        let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+        engine.add_event(counting_event.clone());
+        let expected = untimed(engine.run(&facts).await.unwrap());
+        let expected_events = counting_event.read().unwrap().triggered.clone();
+
+        let mut engine = Engine::new();
+        engine.add_rules(facts_view_rules());
+        let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+        engine.add_event(counting_event.clone());
+        let rule_results = engine.run_view(&facts).await.unwrap();
+        assert_eq!(untimed(rule_results), expected, "{}", document);
+        // the events see all the facts, not only the ones the rules read
+        assert_eq!(counting_event.read().unwrap().triggered, expected_events);
+        assert_eq!(
+            untimed(engine.evaluate_view(&facts).unwrap()),
+            expected,
+            "{}",
+            document
+        );
+    }
+}
+
+#[cfg(feature = "simd")]
+#[tokio::test]
+async fn run_view_simd_json() {
+    use json_rules_engine::FactsView;
+
+    for document in FACTS_VIEW_DOCUMENTS {
+        let facts: Value = serde_json::from_str(document).unwrap();
+        let mut bytes = document.as_bytes().to_vec();
+        let view = simd_json::to_owned_value(&mut bytes).unwrap();
+        assert_eq!(view.to_value(), facts, "{}", document);
+        for pointer in ["", "/address/city", "/orders/0/paid", "/orders/00"] {
+            assert_eq!(
+                view.pointer(pointer).map(FactsView::to_value),
+                facts.pointer(pointer).cloned(),
+                "{} {}",
+                document,
+                pointer
+            );
+        }
+
+        let mut engine = Engine::new();
+        engine.add_rules(facts_view_rules());
+        let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+        engine.add_event(counting_event.clone());
+        let expected = untimed(engine.run(&facts).await.unwrap());
+        let expected_events = counting_event.read().unwrap().triggered.clone();
+
+        let mut engine = Engine::new();
+        engine.add_rules(facts_view_rules());
+        let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+        engine.add_event(counting_event.clone());
+        let rule_results = engine.run_view(&view).await.unwrap();
+        assert_eq!(untimed(rule_results), expected, "{}", document);
+        assert_eq!(counting_event.read().unwrap().triggered, expected_events);
+    }
+}
+
+#[cfg(feature = "broadcast")]
+#[tokio::test]
+async fn number_format() {
+    let event = |number_format: Value| {
+        let mut event = json!({
+            "type": "counting_event",
+            "params": {
+                "message": "{{ name }}: {{ ratio }} of {{ total }}, {{ stats.mean }}"
+            }
+        });
+        if !number_format.is_null() {
+            event["params"]["number_format"] = number_format;
+        }
+        event
+    };
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": { "and": [] },
+        "events": [
+            event(Value::Null),
+            event(json!({ "precision": 2 })),
+            event(json!({ "precision": 0 })),
+        ]
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.add_event(Arc::new(RwLock::new(CountingEvent::new())));
+    let mut receiver = engine.subscribe();
+    engine
+        .run(&json!({
+            "name": "errors",
+            "ratio": 0.1 + 0.2,
+            "total": 12,
+            "stats": { "mean": 0.125 }
+        }))
+        .await
+        .unwrap();
+
+    for expected in &[
+        "errors: 0.30000000000000004 of 12, 0.125",
+        "errors: 0.30 of 12, 0.13",
+        "errors: 0 of 12, 0",
+    ] {
+        let envelope = receiver.recv().await.unwrap();
+        assert_eq!(envelope.event.params["message"], *expected);
+    }
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": { "and": [] },
+        "events": [event(json!({ "decimals": 2 }))]
+    }))
+    .unwrap();
+    assert!(Engine::build(vec![rule], Default::default()).is_err());
+}
+
+#[test]
+fn any_and_none_match() {
+    use json_rules_engine::{any_match, bool_equals, none_match};
+
+    let facts = json!({
+        "devices": [
+            { "os": "ios", "trusted": true },
+            { "os": "android", "trusted": false },
+            { "os": "linux" },
+        ],
+        "trusted_devices": [{ "os": "ios", "trusted": true }],
+        "empty": [],
+        "device": { "os": "ios", "trusted": false },
+    });
+    let status = |condition: json_rules_engine::Condition| {
+        condition
+            .check_value(
+                &facts,
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .status
+    };
+    let untrusted = || bool_equals("trusted", false);
+
+    assert_eq!(status(any_match("devices", untrusted())), Status::Met);
+    assert_eq!(status(none_match("devices", untrusted())), Status::NotMet);
+    assert_eq!(
+        status(any_match("trusted_devices", untrusted())),
+        Status::NotMet
+    );
+    assert_eq!(
+        status(none_match("trusted_devices", untrusted())),
+        Status::Met
+    );
+    assert_eq!(status(any_match("empty", untrusted())), Status::NotMet);
+    assert_eq!(status(none_match("empty", untrusted())), Status::Met);
+    assert_eq!(status(any_match("device", untrusted())), Status::NotMet);
+    assert_eq!(status(none_match("device", untrusted())), Status::NotMet);
+
+    // the device without `trusted` leaves it open, unless defaulted
+    let untrusted_linux = json!({
+        "field": "devices",
+        "operator": "none_match",
+        "value": {
+            "and": [
+                { "field": "os", "operator": "string_equals", "value": "linux" },
+                { "field": "trusted", "operator": "bool_equals", "value": false },
+            ]
+        }
+    });
+    let condition: json_rules_engine::Condition =
+        serde_json::from_value(untrusted_linux.clone()).unwrap();
+    assert_eq!(status(condition), Status::Unknown);
+
+    let mut defaulted = untrusted_linux;
+    defaulted["value"]["and"][1]["default"] = json!(false);
+    let condition: json_rules_engine::Condition =
+        serde_json::from_value(defaulted.clone()).unwrap();
+    assert_eq!(status(condition), Status::NotMet);
+
+    // the nested condition is checked for unknown keys as well
+    let mut misspelt = defaulted;
+    misspelt["value"]["and"][0]["pathsyntax"] = json!("dotted");
+    match Rule::from_value_strict(json!({
+        "conditions": misspelt,
+        "events": [{ "type": "counting_event", "params": {} }],
+    })) {
+        Err(Error::UnknownFieldsError(fields)) => {
+            assert_eq!(fields, ["/conditions/value/and/0/pathsyntax"])
+        }
+        _ => panic!("unknown fields weren't reported"),
+    }
+
+    // and for the named sets it refers to
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "devices",
+            "operator": "any_match",
+            "value": {
+                "field": "os",
+                "operator": "string_in_named_set",
+                "value": "blocked_os"
+            }
+        },
+        "events": [{ "type": "counting_event", "params": {} }],
+    }))
+    .unwrap();
+    let mut engine = Engine::new();
+    assert!(engine.try_add_rule(rule.clone()).is_err());
+    engine.register_set(
+        "blocked_os",
+        vec!["linux".to_string()].into_iter().collect(),
+    );
+    assert!(engine.try_add_rule(rule).is_ok());
+}
+
+#[test]
+fn rules_clone_and_compare() {
+    use json_rules_engine::{Condition, Constraint};
+
+    let template: Rule = serde_json::from_value(json!({
+        "id": "large_order",
+        "conditions": {
+            "and": [
+                { "field": "amount", "operator": "float_in_range", "value": [100.5, 1e6] },
+                { "field": "country", "operator": "string_in", "value": ["FR", "DE"] },
+            ]
+        },
+        "events": [{ "type": "counting_event", "params": { "to": "ops" } }]
+    }))
+    .unwrap();
+
+    // a rule survives a round trip, and is customized per tenant
+    let round_trip: Rule =
+        serde_json::from_value(serde_json::to_value(&template).unwrap())
+            .unwrap();
+    assert_eq!(round_trip, template);
+    let mut tenant = template.clone();
+    tenant.id = Some("large_order_acme".into());
+    assert_ne!(tenant, template);
+    tenant.id = template.id.clone();
+    tenant.tags.push("acme".into());
+    assert_ne!(tenant, template);
+
+    // floats compare as numbers
+    assert_eq!(
+        Constraint::FloatInRange(Some(100.5), Some(1e6)),
+        Constraint::FloatInRange(Some(100.5), Some(1_000_000.0))
+    );
+    assert_eq!(Constraint::FloatEquals(0.0), Constraint::FloatEquals(-0.0));
+    assert_ne!(
+        Constraint::FloatEquals(f64::NAN),
+        Constraint::FloatEquals(f64::NAN)
+    );
+    assert_ne!(
+        Constraint::FloatLessThan(1.0),
+        Constraint::FloatLessThanInclusive(1.0)
+    );
+    let leaf = |value: f64| -> Condition {
+        serde_json::from_value(json!({
+            "field": "amount",
+            "operator": "float_equals",
+            "value": value
+        }))
+        .unwrap()
+    };
+    assert_eq!(leaf(1.5), leaf(1.5));
+    assert_ne!(leaf(1.5), leaf(2.5));
+
+    // and so do results
+    let result = template.check_value(
+        &json!({ "amount": 200.0, "country": "FR" }),
+        #[cfg(feature = "eval")]
+        &rhai::Engine::new(),
+    );
+    assert_eq!(result.clone(), result);
+    assert_eq!(result.condition_result.status, Status::Met);
+    assert_eq!(result.events, template.events);
+}
+
+#[test]
+fn rule_lint() {
+    use json_rules_engine::{
+        and, at_least, float_greater_than, float_less_than, int_greater_than,
+        int_in_range, int_less_than, or, string_equals, string_in, Condition,
+        LintKind, LintSeverity,
+    };
+
+    let lint = |conditions: Condition| {
+        Rule {
+            id: None,
+            conditions,
+            events: Vec::new(),
+            facts_to_add: Default::default(),
+            enabled: true,
+            tags: Vec::new(),
+            sample: None,
+        }
+        .lint()
+        .into_iter()
+        .map(|warning| (warning.path, warning.kind, warning.severity))
+        .collect::<Vec<_>>()
+    };
+    let error =
+        |path: &str, kind| vec![(path.to_owned(), kind, LintSeverity::Error)];
+    let warning =
+        |path: &str, kind| vec![(path.to_owned(), kind, LintSeverity::Warning)];
+
+    // contradictory ranges, integers being discrete
+    assert_eq!(
+        lint(and(vec![
+            int_greater_than("age", 30),
+            int_less_than("age", 20)
+        ])),
+        error("", LintKind::ContradictoryRange)
+    );
+    assert_eq!(
+        lint(and(vec![
+            int_greater_than("age", 19),
+            int_less_than("age", 20)
+        ])),
+        error("", LintKind::ContradictoryRange)
+    );
+    assert!(lint(and(vec![
+        float_greater_than("age", 19.0),
+        float_less_than("age", 20.0)
+    ]))
+    .is_empty());
+    assert!(lint(and(vec![
+        int_greater_than("age", 30),
+        int_less_than("height", 20)
+    ]))
+    .is_empty());
+    assert!(lint(or(vec![
+        int_greater_than("age", 30),
+        int_less_than("age", 20)
+    ]))
+    .is_empty());
+
+    // duplicate list values, ranges aside
+    assert_eq!(
+        lint(string_in("country", vec!["FR", "DE", "FR"])),
+        warning("", LintKind::DuplicateValues)
+    );
+    assert!(lint(string_in("country", vec!["FR", "DE"])).is_empty());
+    assert!(lint(int_in_range("age", 5, 5)).is_empty());
+
+    // empty combinators, nested
+    assert_eq!(lint(and(vec![])), warning("", LintKind::EmptyCombinator));
+    assert_eq!(
+        lint(and(vec![or(vec![]), string_equals("name", "Cheng JIANG")])),
+        error("/and/0", LintKind::EmptyCombinator)
+    );
+
+    // at_least thresholds
+    let children = || {
+        vec![
+            string_equals("name", "Cheng"),
+            string_equals("name", "JIANG"),
+        ]
+    };
+    assert_eq!(
+        lint(at_least(0, children())),
+        warning("", LintKind::AtLeastThreshold)
+    );
+    assert_eq!(
+        lint(at_least(3, children())),
+        error("", LintKind::AtLeastThreshold)
+    );
+    assert!(lint(at_least(2, children())).is_empty());
+
+    // identical siblings, the later one reported
+    let mut siblings = children();
+    siblings.push(string_equals("name", "Cheng"));
+    assert_eq!(
+        lint(or(siblings)),
+        warning("/or/2", LintKind::IdenticalSiblings)
+    );
+    assert!(lint(or(children())).is_empty());
+
+    // constant expressions
+    #[cfg(feature = "eval")]
+    {
+        let expr = |expr: &str| {
+            serde_json::from_value::<Condition>(json!({ "expr": expr }))
+                .unwrap()
+        };
+        assert_eq!(
+            lint(expr("1 + 1 == 2")),
+            warning("", LintKind::ConstantExpression)
+        );
+        assert_eq!(
+            lint(expr("1 > 2")),
+            error("", LintKind::ConstantExpression)
+        );
+        assert!(lint(expr("facts.age > 1")).is_empty());
+        assert!(lint(expr("now_ts > 1")).is_empty());
+    }
+}
+
+#[test]
+fn element_alias() {
+    let facts = json!({
+        "currency": "EUR",
+        "country": "FR",
+        "items": [
+            { "sku": "a", "quantity": 2, "unit_price": 100, "currency": "EUR" },
+            { "sku": "b", "quantity": 3, "unit_price": 200, "currency": "USD" },
+        ]
+    });
+    let rule = |id: &str, condition: Value| -> Rule {
+        serde_json::from_value(json!({
+            "id": id,
+            "conditions": {
+                "field": "items",
+                "operator": "any_match",
+                "value": condition
+            },
+            "events": [{ "type": "counting_event", "params": {} }]
+        }))
+        .unwrap()
+    };
+    let met = |engine: &Engine| -> Vec<String> {
+        engine
+            .evaluate(&facts)
+            .unwrap()
+            .into_iter()
+            .filter_map(|result| result.rule_id)
+            .collect()
+    };
+
+    #[allow(unused_mut)]
+    let mut rules = vec![
+        // the element is the root of templates
+        rule(
+            "own_sku",
+            json!({
+                "field": "sku",
+                "operator": "string_equals",
+                "value": "{{ sku }}",
+                "templated_value": true
+            }),
+        ),
+        // and the whole facts are under the alias
+        rule(
+            "home_currency",
+            json!({
+                "field": "currency",
+                "operator": "string_equals",
+                "value": "{{ _root.currency }}",
+                "templated_value": true
+            }),
+        ),
+        rule(
+            "shipped_to_france",
+            json!({
+                "and": [
+                    { "field": "_root/country", "operator": "string_equals", "value": "FR" },
+                    { "field": "sku", "operator": "string_equals", "value": "b" },
+                ]
+            }),
+        ),
+    ];
+    #[cfg(feature = "eval")]
+    rules.push(rule(
+        "large_line",
+        json!({ "expr": "facts.quantity * facts.unit_price > 500" }),
+    ));
+    #[cfg(feature = "eval")]
+    rules.push(rule(
+        "french_bulk_line",
+        json!({ "expr": "facts._root.country == \"FR\" && facts.quantity > 2" }),
+    ));
+
+    let mut engine = Engine::new();
+    engine.add_event(Arc::new(RwLock::new(CountingEvent::new())));
+    engine.add_rules(rules);
+
+    // without an alias, the whole facts are out of reach
+    #[allow(unused_mut)]
+    let mut expected = vec!["own_sku"];
+    #[cfg(feature = "eval")]
+    expected.push("large_line");
+    assert_eq!(met(&engine), expected);
+
+    engine.set_element_alias(Some("_root".into()));
+    #[allow(unused_mut)]
+    let mut expected = vec!["own_sku", "home_currency", "shipped_to_france"];
+    #[cfg(feature = "eval")]
+    expected.extend(["large_line", "french_bulk_line"]);
+    assert_eq!(met(&engine), expected);
+}
+
+#[tokio::test]
+async fn non_object_facts_root() {
+    use json_rules_engine::EngineOptions;
+
+    let rules: Vec<Rule> = serde_json::from_value(json!([
+        {
+            "id": "large_order",
+            "conditions": {
+                "field": "items",
+                "operator": "any_match",
+                "value": {
+                    "field": "amount",
+                    "operator": "int_greater_than",
+                    "value": 100
+                }
+            },
+            "events": [{ "type": "counting_event", "params": {} }]
+        },
+        {
+            "id": "high_score",
+            "conditions": {
+                "field": "items",
+                "operator": "int_greater_than",
+                "value": 90
+            },
+            "events": [{ "type": "counting_event", "params": {} }]
+        }
+    ]))
+    .unwrap();
+    let array = json!([{ "amount": 20 }, { "amount": 150 }]);
+    let scalar = json!(95);
+    let met = |results: Vec<RuleResult>| -> Vec<String> {
+        results
+            .into_iter()
+            .filter(|result| result.condition_result.status == Status::Met)
+            .filter_map(|result| result.rule_id)
+            .collect()
+    };
+
+    // by default, the fields of non object facts are missing
+    let mut engine = Engine::new();
+    engine.add_rules(rules.clone());
+    let event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(event.clone());
+    assert!(met(engine.run(&array).await.unwrap()).is_empty());
+    assert!(met(engine.evaluate(&scalar).unwrap()).is_empty());
+
+    // wrapped, the rules and the events see them under the key
+    engine.set_root_wrap_key(Some("items".into()));
+    assert_eq!(met(engine.run(&array).await.unwrap()), ["large_order"]);
+    assert_eq!(met(engine.evaluate(&scalar).unwrap()), ["high_score"]);
+    assert_eq!(
+        event.read().unwrap().triggered,
+        vec![json!({ "items": array })]
+    );
+    // objects are left as they are
+    assert!(met(engine.evaluate(&json!({ "items": 3 })).unwrap()).is_empty());
+
+    // strict, they fail the evaluation unless wrapped
+    engine.set_strict_facts_root(true);
+    assert_eq!(met(engine.evaluate(&array).unwrap()), ["large_order"]);
+    engine.set_root_wrap_key(None);
+    for facts in [&array, &scalar, &json!("text"), &Value::Null] {
+        assert!(matches!(
+            engine.evaluate(facts),
+            Err(Error::InvalidFactsRoot(_))
+        ));
+    }
+    match engine.run(&array).await {
+        Err(Error::InvalidFactsRoot(ty)) => assert_eq!(ty, "array"),
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert!(engine.evaluate(&json!({ "items": 95 })).is_ok());
+
+    // as set up by the engine options
+    let engine = Engine::build(
+        rules,
+        EngineOptions {
+            root_wrap_key: Some("items".into()),
+            ..Default::default()
+        },
+    )
+    .unwrap_or_else(|_| panic!("rules failed to build"));
+    assert_eq!(met(engine.evaluate(&scalar).unwrap()), ["high_score"]);
+}
+
+#[test]
+fn referenced_fields() {
+    use json_rules_engine::FieldRef;
+
+    #[allow(unused_mut)]
+    let mut conditions = vec![
+        json!({ "field": "name", "operator": "string_equals", "value": "a" }),
+        json!({
+            "field": "/user/roles",
+            "pointer": true,
+            "operator": "string_contains",
+            "value": "admin"
+        }),
+        json!({
+            "field": "user.age",
+            "path_syntax": "dotted",
+            "operator": "int_greater_than",
+            "value": { "$var": "min_age" }
+        }),
+        json!({
+            "not": {
+                "field": "devices",
+                "operator": "any_match",
+                "value": {
+                    "field": "trusted",
+                    "operator": "bool_equals",
+                    "value": false
+                }
+            }
+        }),
+    ];
+    #[cfg(feature = "eval")]
+    conditions.push(json!({
+        "expr": "facts.user.age > 18 && facts.name != myfacts.x \
+                 && facts.user.age < 99 && facts[\"country\"] == \"FR\""
+    }));
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": { "or": conditions },
+        "events": [],
+    }))
+    .unwrap();
+
+    let leaf = |pointer: &str, operator: &str| FieldRef {
+        pointer: pointer.into(),
+        operator: Some(operator.into()),
+        approximate: false,
+    };
+    #[allow(unused_mut)]
+    let mut expected = vec![
+        leaf("/name", "string_equals"),
+        leaf("/user/roles", "string_contains"),
+        leaf("/user/age", "int_greater_than"),
+        leaf("/devices", "any_match"),
+    ];
+    // `facts["country"]` goes unnoticed
+    #[cfg(feature = "eval")]
+    expected.extend(["/user/age", "/name"].iter().map(|pointer| FieldRef {
+        pointer: pointer.to_string(),
+        operator: None,
+        approximate: true,
+    }));
+
+    assert_eq!(rule.referenced_fields(), expected);
+    assert_eq!(
+        serde_json::to_value(&expected[0]).unwrap(),
+        json!({ "pointer": "/name", "operator": "string_equals" })
+    );
+}
+
+#[cfg(feature = "broadcast")]
+#[tokio::test]
+async fn broadcast_events() {
+    let rule_json = json!({
+        "id": "rust_coder",
+        "conditions": {
+            "field": "action",
+            "operator": "string_equals",
+            "value": "coding in rust"
+        },
+        "events": [
+            {
+                "type": "counting_event",
+                "params": {
+                    "message": "{{ name }} is {{ action }}",
+                }
+            },
+            {
+                "type": "counting_event",
+                "coalescence": 60,
+                "coalescence_group": "{{ name }}",
+                "params": {}
+            },
+        ]
+    });
+
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.add_event(Arc::new(RwLock::new(CountingEvent::new())));
+
+    let mut receiver = engine.subscribe();
+
+    let facts = json!({
+        "name": "Cheng JIANG",
+        "action": "coding in rust",
+    });
+
+    engine.run(&facts).await.unwrap();
+    // suppressed by its coalescence group this time
+    engine.run(&facts).await.unwrap();
+
+    let envelope = receiver.recv().await.unwrap();
+    assert_eq!(envelope.rule_id.as_deref(), Some("rust_coder"));
+    assert_eq!(envelope.event.ty, "counting_event");
+    assert_eq!(
+        envelope.event.params["message"],
+        json!("Cheng JIANG is coding in rust")
+    );
+    assert!(envelope.timestamp > 0);
+
+    assert!(receiver.recv().await.unwrap().event.params.is_empty());
+    let envelope = receiver.recv().await.unwrap();
+    assert!(envelope.event.params.contains_key("message"));
+    assert!(receiver.try_recv().is_err());
+}
+
+#[cfg(feature = "broadcast")]
+#[tokio::test]
+async fn broadcast_lagged() {
+    use json_rules_engine::BROADCAST_CAPACITY;
+    use tokio::sync::broadcast::error::{RecvError, TryRecvError};
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": { "and": [] },
+        "events": [
+            { "type": "counting_event", "params": { "run": "{{ run }}" } }
+        ]
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.add_event(Arc::new(RwLock::new(CountingEvent::new())));
+    let mut receiver = engine.subscribe();
+    for run in 0..BROADCAST_CAPACITY + 5 {
+        engine.run(&json!({ "run": run })).await.unwrap();
+    }
+    assert_eq!(engine.lagged_envelopes(), 0);
+
+    // the oldest envelopes were overwritten
+    assert!(matches!(receiver.recv().await, Err(RecvError::Lagged(5))));
+    assert_eq!(engine.lagged_envelopes(), 5);
+    let envelope = receiver.recv().await.unwrap();
+    assert_eq!(envelope.event.params["run"], "5");
+
+    // counted over all the subscribers
+    let mut other = engine.subscribe();
+    for run in 0..BROADCAST_CAPACITY + 2 {
+        engine.run(&json!({ "run": run })).await.unwrap();
+    }
+    assert!(matches!(other.try_recv(), Err(TryRecvError::Lagged(2))));
+    assert_eq!(engine.lagged_envelopes(), 7);
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn post_callback_default_app_data() {
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let rule_json = json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [
+            {
+                "type": "post_to_callback_url",
+                "params": {
+                    "callback_url": format!("{}/hook", server.uri()),
+                    "app_data": {
+                        "environment": "staging",
+                        "region": {
+                            "zone": "b"
+                        }
+                    }
+                }
+            }
+        ]
+    });
+
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.set_default_app_data(
+        json!({
+            "service": "json-rules-engine",
+            "environment": "production",
+            "region": {
+                "name": "eu-west-1",
+                "zone": "a"
+            }
+        })
+        .as_object()
+        .unwrap()
+        .clone(),
+    );
+
+    let rule_results =
+        engine.run(&json!({ "name": "Cheng JIANG" })).await.unwrap();
+
+    let expected = json!({
+        "service": "json-rules-engine",
+        "environment": "staging",
+        "region": {
+            "name": "eu-west-1",
+            "zone": "b"
+        }
+    });
+
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["event"]["app_data"], expected);
+
+    let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+    assert_eq!(event["params"]["app_data"], expected);
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn post_callback_compression_and_size() {
+    use flate2::read::GzDecoder;
+    use std::io::Read;
+    use wiremock::{
+        matchers::{header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/gzip"))
+        .and(header("Content-Encoding", "gzip"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/plain"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let event = |path: &str, params: Value| {
+        let mut params = params;
+        params["callback_url"] = json!(format!("{}/{}", server.uri(), path));
+        json!({ "type": "post_to_callback_url", "params": params })
+    };
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [
+            event("gzip", json!({ "compress": "gzip" })),
+            event("gzip", json!({
+                "compress": "gzip",
+                "content_type": "form",
+                "form_facts": ["name"]
+            })),
+            event("plain", json!({ "compress": "none", "max_facts_bytes": 8 })),
+            event("plain", json!({ "max_facts_bytes": 1024 })),
+        ]
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    let facts = json!({ "name": "Cheng JIANG", "age": 27 });
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert!(rule_results[0].events.iter().all(|event| {
+        serde_json::to_value(event).unwrap()["error"].is_null()
+    }));
+
+    let requests = server.received_requests().await.unwrap();
+    let gunzip = |body: &[u8]| {
+        let mut decoded = String::new();
+        GzDecoder::new(body).read_to_string(&mut decoded).unwrap();
+        decoded
+    };
+
+    let body: Value = serde_json::from_str(&gunzip(&requests[0].body)).unwrap();
+    assert_eq!(body["facts"], facts);
+    assert_eq!(body["event"]["compress"], "gzip");
+    assert!(gunzip(&requests[1].body).ends_with("&facts.name=Cheng+JIANG"));
+
+    // the facts over `max_facts_bytes` are left out
+    let body: Value = serde_json::from_slice(&requests[2].body).unwrap();
+    assert_eq!(
+        body["facts"],
+        json!({
+            "facts_truncated": true,
+            "facts_size": facts.to_string().len()
+        })
+    );
+    assert!(requests[2].headers.get("Content-Encoding").is_none());
+    let body: Value = serde_json::from_slice(&requests[3].body).unwrap();
+    assert_eq!(body["facts"], facts);
+
+    // and invalid params fail the event
+    let mut engine = Engine::new();
+    engine.add_rule(
+        serde_json::from_value(json!({
+            "conditions": {
+                "field": "name",
+                "operator": "string_equals",
+                "value": "Cheng JIANG"
+            },
+            "events": [event("plain", json!({ "compress": "brotli" }))]
+        }))
+        .unwrap(),
+    );
+    assert!(engine.run(&facts).await.is_err());
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn templates_see_app_data_and_vars() {
+    use json_rules_engine::ErrorMode;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/down"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&server)
+        .await;
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [
+            {
+                "type": "post_to_callback_url",
+                "coalescence_group": "{{ vars.region }}-{{ app_data.brand }}",
+                "params": {
+                    "callback_url": "{{ app_data.base_url }}/hook",
+                    "app_data": { "brand": "acme" }
+                }
+            },
+            {
+                "type": "post_to_callback_url",
+                "params": {
+                    "callback_url": "{{ app_data.base_url }}/down",
+                    "message": "See {{ app_data.support_url }} ({{ vars.region }})"
+                }
+            }
+        ],
+        "facts_to_add": { "tier": "{{ vars.tier }}" }
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.set_error_mode(ErrorMode::BestEffort);
+    engine.set_variable("region", json!("eu"));
+    engine.set_variable("tier", json!("gold"));
+    engine.set_default_app_data(
+        json!({
+            "base_url": server.uri(),
+            "support_url": "https://help.example.com"
+        })
+        .as_object()
+        .unwrap()
+        .clone(),
+    );
+
+    // the engine's variables, the event's app_data and the default one
+    let rule_results =
+        engine.run(&json!({ "name": "Cheng JIANG" })).await.unwrap();
+    assert_eq!(rule_results[0].facts_to_add["tier"], "gold");
+    let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+    assert_eq!(event["coalescence_group"], "eu-acme");
+    let dead_letters = engine.take_dead_letters();
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(
+        dead_letters[0].params["message"],
+        "See https://help.example.com (eu)"
+    );
+    // which the callbacks don't send
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["facts"], json!({ "name": "Cheng JIANG" }));
+
+    // facts win over the namespaces
+    let facts = json!({
+        "name": "Cheng JIANG",
+        "vars": { "tier": "silver", "region": "us" },
+        "app_data": {
+            "base_url": server.uri(),
+            "support_url": "https://facts.example.com"
+        }
+    });
+    let rule_results = engine.evaluate(&facts).unwrap();
+    assert_eq!(rule_results[0].facts_to_add["tier"], "silver");
+    engine.run(&facts).await.unwrap();
+    assert_eq!(
+        engine.take_dead_letters()[0].params["message"],
+        "See https://facts.example.com (us)"
+    );
+}
+
+#[cfg(feature = "nats")]
+#[tokio::test]
+async fn nats_publish() {
+    use json_rules_engine::{EngineOptions, ErrorMode};
+    use tokio::{
+        io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+        net::TcpListener,
+    };
+
+    // a server answering the pings, returning what it received once the
+    // connection and the publish were both pinged
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(async move {
+        let (stream, _) = listener.accept().await.unwrap();
+        let (reader, mut writer) = stream.into_split();
+        writer.write_all(b"INFO {}\r\n").await.unwrap();
+        let mut lines = BufReader::new(reader).lines();
+        let mut received = Vec::new();
+        let mut pings = 0;
+        while pings < 2 {
+            let line = lines.next_line().await.unwrap().unwrap();
+            if line == "PING" {
+                pings += 1;
+                writer.write_all(b"PONG\r\n").await.unwrap();
+            } else {
+                received.push(line);
+            }
+        }
+        received
+    });
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [{
+            "type": "nats_publish",
+            "params": {
+                "subject": "orders.{{ country }}",
+                "reply": "inbox.1",
+                "headers": { "Tenant": "{{ tenant }}" }
+            }
+        }]
+    }))
+    .unwrap();
+    let mut engine = Engine::build(
+        vec![rule],
+        EngineOptions {
+            nats_url: Some(format!("nats://127.0.0.1:{}", port)),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    engine.set_error_mode(ErrorMode::BestEffort);
+
+    let facts =
+        json!({ "name": "Cheng JIANG", "country": "fr", "tenant": "acme" });
+    let rule_results = engine.run(&facts).await.unwrap();
+    let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+    assert!(event["error"].is_null(), "{}", event);
+
+    let received = server.await.unwrap();
+    assert!(received[0].starts_with("CONNECT {"));
+    assert!(received[1].starts_with("HPUB orders.fr inbox.1 "));
+    assert_eq!(received[2..5], ["NATS/1.0", "Tenant: acme", ""]);
+    let payload: Value = serde_json::from_str(&received[5]).unwrap();
+    assert_eq!(payload["event"]["subject"], "orders.fr");
+    assert_eq!(payload["facts"], facts);
+
+    // refused once rendered, before publishing
+    let facts =
+        json!({ "name": "Cheng JIANG", "country": "*", "tenant": "acme" });
+    let rule_results = engine.run(&facts).await.unwrap();
+    let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+    assert!(event["error"]
+        .as_str()
+        .unwrap()
+        .contains("Invalid subject `orders.*`"));
+}
+
+/// Against a real server, e.g. `NATS_URL=nats://localhost:4222`
+#[cfg(feature = "nats")]
+#[tokio::test]
+async fn nats_publish_server() {
+    use json_rules_engine::{EngineOptions, ErrorMode};
+
+    let url = match std::env::var("NATS_URL") {
+        Ok(url) => url,
+        Err(_) => return,
+    };
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [{
+            "type": "nats_publish",
+            "params": { "subject": "json_rules_engine.test" }
+        }]
+    }))
+    .unwrap();
+    let mut engine = Engine::build(
+        vec![rule],
+        EngineOptions {
+            nats_url: Some(url),
+            ..Default::default()
+        },
+    )
+    .unwrap();
+    engine.set_error_mode(ErrorMode::BestEffort);
+
+    // the connection is reused
+    for _ in 0..2 {
+        let rule_results =
+            engine.run(&json!({ "name": "Cheng JIANG" })).await.unwrap();
+        let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+        assert!(event["error"].is_null(), "{}", event);
+    }
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn event_reason_codes() {
+    use json_rules_engine::EngineOptions;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(2)
+        .mount(&server)
+        .await;
+
+    let rule = |params: Value| -> Rule {
+        let mut params = params;
+        params["callback_url"] = json!(format!("{}/hook", server.uri()));
+        serde_json::from_value(json!({
+            "conditions": {
+                "field": "name",
+                "operator": "string_equals",
+                "value": "Cheng JIANG"
+            },
+            "events": [{ "type": "post_to_callback_url", "params": params }]
+        }))
+        .unwrap()
+    };
+    let with_reason = rule(json!({
+        "reason_code": "blocked_{{ country }}",
+        "severity": "high"
+    }));
+    let without_reason = rule(json!({}));
+    let event = |rule: &Rule| -> json_rules_engine::Event {
+        serde_json::from_value(serde_json::to_value(&rule.events[0]).unwrap())
+            .unwrap()
+    };
+    assert_eq!(event(&without_reason).reason_code(), None);
+    assert_eq!(event(&with_reason).severity(), Some("high"));
+
+    let options = EngineOptions {
+        allowed_severities: Some(
+            ["low", "high"].iter().map(|s| s.to_string()).collect(),
+        ),
+        ..Default::default()
+    };
+    let mut engine = Engine::build(
+        vec![with_reason.clone(), without_reason.clone()],
+        options.clone(),
+    )
+    .unwrap();
+    let rule_results = engine
+        .run(&json!({ "name": "Cheng JIANG", "country": "fr" }))
+        .await
+        .unwrap();
+
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["event"]["reason_code"], "blocked_fr");
+    assert_eq!(body["event"]["severity"], "high");
+    let body: Value = serde_json::from_slice(&requests[1].body).unwrap();
+    assert!(body["event"].get("reason_code").is_none());
+    assert!(body["event"].get("severity").is_none());
+    let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+    assert_eq!(event["params"]["reason_code"], "blocked_fr");
+
+    // severities outside the allowed ones are refused at load
+    let urgent = rule(json!({ "severity": "urgent" }));
+    let errors = Engine::build(vec![with_reason, urgent], options.clone())
+        .err()
+        .unwrap();
+    assert_eq!(errors.len(), 1);
+    assert_eq!(errors[0].0, 1);
+    let not_a_string = rule(json!({ "reason_code": 42 }));
+    assert!(Engine::build(vec![not_a_string], options).is_err());
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn post_callback_payload_versions() {
+    use wiremock::{
+        matchers::{header, method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/json"))
+        .and(header("content-type", "application/json"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/form"))
+        .and(header("content-type", "application/x-www-form-urlencoded"))
+        .and(header("x-payload-version", "1"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let event = |params: Value| json!({ "type": "post_to_callback_url", "params": params });
+    let rule_json = json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [
+            event(json!({ "callback_url": format!("{}/json", server.uri()) })),
+            event(json!({
+                "callback_url": format!("{}/json", server.uri()),
+                "payload_version": "1"
+            })),
+            event(json!({
+                "callback_url": format!("{}/form", server.uri()),
+                "payload_version": "1",
+                "content_type": "form",
+                "form_facts": ["name", "profile"],
+                "tags": ["a", "b"]
+            })),
+        ]
+    });
+
+    let mut engine = Engine::new();
+    engine.add_rule(serde_json::from_value(rule_json).unwrap());
+    let facts = json!({
+        "name": "Cheng JIANG",
+        "profile": { "age": 27 },
+        "token": "secret"
+    });
+    engine.run(&facts).await.unwrap();
+
+    let mut requests = server.received_requests().await.unwrap();
+    requests.sort_by_key(|r| r.headers.contains_key("x-payload-version"));
+    assert_eq!(requests.len(), 3);
+
+    // without a version, the body is left as it always was
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body.as_object().unwrap().len(), 2);
+    assert_eq!(body["facts"], facts);
+
+    let (json, form) = if requests[1].url.path() == "/json" {
+        (&requests[1], &requests[2])
+    } else {
+        (&requests[2], &requests[1])
+    };
+    assert_eq!(json.headers["x-payload-version"], "1");
+    let body: Value = serde_json::from_slice(&json.body).unwrap();
+    assert_eq!(body["version"], "1");
+    assert_eq!(body["event"]["payload_version"], "1");
+    assert_eq!(body["facts"], facts);
+
+    let form = String::from_utf8(form.body.clone()).unwrap();
+    assert!(form.starts_with("version=1&event.callback_url=http"));
+    assert!(form.contains("&event.content_type=form&"));
+    assert!(form.contains("&event.tags=%5B%22a%22%2C%22b%22%5D"));
+    assert!(form.ends_with(
+        "&facts.name=Cheng+JIANG&facts.profile=%7B%22age%22%3A27%7D"
+    ));
+    assert!(!form.contains("secret"));
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn post_callback_payload_params_are_validated() {
+    for (param, value) in &[
+        ("content_type", json!("xml")),
+        ("payload_version", json!(1)),
+        ("form_facts", json!("name")),
+    ] {
+        let mut params = json!({ "callback_url": "http://localhost" });
+        params[param] = value.clone();
+        let mut engine = Engine::new();
+        engine.add_rule(
+            serde_json::from_value(json!({
+                "conditions": { "and": [] },
+                "events": [{ "type": "post_to_callback_url", "params": params }]
+            }))
+            .unwrap(),
+        );
+        let err = engine.run(&json!({})).await.unwrap_err();
+        assert!(err.to_string().contains(param), "{}", err);
+    }
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn post_callback_escape_modes() {
+    use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    for (escape_mode, expected) in &[
+        (None, vec![("q", "a"), ("amp;b", "")]),
+        (Some("none"), vec![("q", "a"), ("b", "")]),
+    ] {
+        let mut params = json!({
+            "callback_url": format!("{}/hook?q={{{{ q }}}}", server.uri())
+        });
+        if let Some(escape_mode) = escape_mode {
+            params["escape_mode"] = json!(escape_mode);
+        }
+        let rule: Rule = serde_json::from_value(json!({
+            "conditions": {
+                "field": "q",
+                "operator": "string_equals",
+                "value": "a&b"
+            },
+            "events": [
+                { "type": "post_to_callback_url", "params": params }
+            ]
+        }))
+        .unwrap();
+
+        let mut engine = Engine::new();
+        engine.add_rule(rule);
+        engine.run(&json!({ "q": "a&b" })).await.unwrap();
+
+        let requests = server.received_requests().await.unwrap();
+        let query: Vec<(String, String)> = requests
+            .last()
+            .unwrap()
+            .url
+            .query_pairs()
+            .into_owned()
+            .collect();
+        assert_eq!(
+            query,
+            expected
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<Vec<_>>()
+        );
+    }
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "q",
+            "operator": "string_equals",
+            "value": "a&b"
+        },
+        "events": [
+            {
+                "type": "post_to_callback_url",
+                "params": {
+                    "callback_url": server.uri(),
+                    "escape_mode": "markdown"
+                }
+            }
+        ]
+    }))
+    .unwrap();
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    assert!(matches!(
+        engine.run(&json!({ "q": "a&b" })).await,
+        Err(Error::EventDispatch { source, .. })
+            if matches!(*source, Error::EventError(_))
+    ));
+}
+
+#[cfg(feature = "schema")]
+#[test]
+fn validate_rules_against_struct() {
+    #[derive(schemars::JsonSchema)]
+    struct Facts {
+        name: String,
+        age: u8,
+        address: Address,
+    }
+
+    #[derive(schemars::JsonSchema)]
+    struct Address {
+        city: String,
+    }
+
+    let rule_json = json!({
+        "id": "adult",
+        "conditions": {
+            "and": [
+                {
+                    "field": "name",
+                    "operator": "string_equals",
+                    "value": "Cheng JIANG"
+                },
+                {
+                    "field": "nmae",
+                    "operator": "string_equals",
+                    "value": "Cheng JIANG"
+                },
+                {
+                    "field": "name",
+                    "operator": "int_equals",
+                    "value": 3
+                },
+                {
+                    "field": "age",
+                    "operator": "float_greater_than",
+                    "value": 18.0
+                },
+                {
+                    "field": "address.city",
+                    "path_syntax": "dotted",
+                    "operator": "string_equals",
+                    "value": "Paris"
+                }
+            ]
+        },
+        "events": []
+    });
+
+    let mut engine = Engine::new();
+    engine.add_rule(serde_json::from_value(rule_json).unwrap());
+
+    let mismatches = engine.validate_rules_against::<Facts>();
+
+    assert_eq!(
+        serde_json::to_value(&mismatches).unwrap(),
+        json!([
+            {
+                "rule_id": "adult",
+                "pointer": "nmae",
+                "expected": "string",
+                "found": "missing"
+            },
+            {
+                "rule_id": "adult",
+                "pointer": "name",
+                "expected": "integer",
+                "found": "string"
+            }
+        ])
+    );
+}
+
+#[test]
+fn pinned_now_datetime_constraints() {
+    let now = chrono::DateTime::parse_from_rfc3339("2024-01-01T01:00:00Z")
+        .unwrap()
+        .with_timezone(&chrono::Utc);
+
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "last_login",
+                    "operator": "datetime_within_last",
+                    "value": 3600
+                },
+                {
+                    "field": "created_at",
+                    "operator": "datetime_older_than",
+                    "value": 86400
+                }
+            ]
+        },
+        "events": []
+    });
+
+    let mut engine = Engine::new();
+    engine.add_rule(serde_json::from_value(rule_json).unwrap());
+    engine.set_now_provider(Arc::new(move || now));
+
+    let met = |facts: Value| !engine.evaluate(&facts).unwrap().is_empty();
+
+    assert!(met(json!({
+        "last_login": "2024-01-01T00:30:00Z",
+        "created_at": "2023-06-01T00:00:00+02:00"
+    })));
+    assert!(!met(json!({
+        "last_login": "2023-12-31T23:30:00Z",
+        "created_at": "2023-06-01T00:00:00+02:00"
+    })));
+    assert!(!met(json!({
+        "last_login": "2024-01-01T00:30:00Z",
+        "created_at": "2023-12-31T12:00:00Z"
+    })));
+}
+
+#[cfg(feature = "eval")]
+#[test]
+fn pinned_now_eval_scope() {
+    let now = chrono::DateTime::parse_from_rfc3339("2024-01-01T01:00:00Z")
+        .unwrap()
+        .with_timezone(&chrono::Utc);
+
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                {
+                    "expr": "facts.expires_at > now_ts"
+                },
+                {
+                    "expr": "now_iso == \"2024-01-01T01:00:00+00:00\""
+                }
+            ]
+        },
+        "events": []
+    });
+
+    let mut engine = Engine::new();
+    engine.add_rule(serde_json::from_value(rule_json).unwrap());
+    engine.set_now_provider(Arc::new(move || now));
+
+    let met = |facts: Value| !engine.evaluate(&facts).unwrap().is_empty();
+
+    assert!(met(json!({ "expires_at": 1704070801 })));
+    assert!(!met(json!({ "expires_at": 1704070800 })));
+}
+
+#[cfg(feature = "eval")]
+#[test]
+fn eval_labeled_results() {
+    let rule_json = json!({
+        "conditions": {
+            "or": [
+                {
+                    "expr": "results[\"kyc_passed\"] == ()"
+                },
+                {
+                    "field": "kyc",
+                    "operator": "string_equals",
+                    "value": "passed",
+                    "label": "kyc_passed"
+                },
+                {
+                    "or": [
+                        {
+                            "field": "country",
+                            "operator": "string_in",
+                            "value": ["XX", "YY"]
+                        },
+                        {
+                            "field": "watchlist",
+                            "operator": "bool_equals",
+                            "value": true
+                        }
+                    ],
+                    "label": "sanctioned"
+                },
+                {
+                    "field": "score",
+                    "operator": "int_greater_than",
+                    "value": 50,
+                    "label": "score"
+                },
+                {
+                    "expr": "results[\"kyc_passed\"] && !results[\"sanctioned\"]"
+                },
+                {
+                    "and": [
+                        {
+                            "expr": "results[\"score\"] == ()"
+                        }
+                    ]
+                }
+            ]
+        },
+        "events": []
+    });
+
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+    let rhai_engine = rhai::Engine::new();
+    let statuses = |facts: Value| {
+        rule.check_value(&facts, &rhai_engine)
+            .condition_result
+            .children
+            .iter()
+            .map(|c| c.status)
+            .collect::<Vec<_>>()
+    };
+
+    // labels are only visible to the conditions evaluated after them, in
+    // the same node or a nested one
+    assert_eq!(
+        statuses(
+            json!({ "kyc": "passed", "country": "FR", "watchlist": false })
+        ),
+        [
+            Status::Met,
+            Status::Met,
+            Status::NotMet,
+            Status::Unknown,
+            Status::Met,
+            Status::Met
+        ]
+    );
+    assert_eq!(
+        statuses(json!({ "kyc": "passed", "country": "XX", "score": 80 }))[4..],
+        [Status::NotMet, Status::NotMet]
+    );
+    assert_eq!(
+        statuses(
+            json!({ "kyc": "pending", "country": "FR", "watchlist": false })
+        )[4],
+        Status::NotMet
+    );
+
+    // short circuiting skips a labeled condition along with the expressions
+    // after it that could read it, so the engine, stopping at the first
+    // child here, agrees with `check_value`
+    let engine = Engine::new();
+    for facts in [
+        json!({ "kyc": "passed", "country": "FR", "watchlist": false }),
+        json!({ "kyc": "passed", "country": "XX", "score": 80 }),
+        json!({ "kyc": "pending", "country": "FR", "watchlist": false }),
+    ] {
+        let fast = engine.check_condition(&rule.conditions, &facts);
+        assert_eq!(fast.children.len(), 1);
+        assert_eq!(
+            fast.status,
+            rule.check_value(&facts, &rhai_engine)
+                .condition_result
+                .status
+        );
+    }
+}
+
+#[test]
+fn limits() {
+    use json_rules_engine::{and, int_equals, Limits};
+
+    let deep = |depth: usize| {
+        let mut condition = int_equals("foo", 1);
+        for _ in 1..depth {
+            condition = and(vec![condition]);
+        }
+        Rule {
+            id: None,
+            conditions: condition,
+            events: Vec::new(),
+            facts_to_add: Default::default(),
+            enabled: true,
+            tags: Vec::new(),
+            sample: None,
+        }
+    };
+
+    // evaluating a very deep tree doesn't overflow the stack
+    let mut engine = Engine::new();
+    engine.try_add_rule(deep(10_000)).unwrap();
+    let result = engine.evaluate(&json!({ "foo": 1 })).unwrap();
+    assert_eq!(result.len(), 1);
+    assert_eq!(result[0].condition_result.iter().count(), 10_000);
+
+    let mut engine = Engine::new();
+    engine.set_limits(Limits {
+        max_conditions_per_rule: Some(3),
+        max_condition_depth: Some(2),
+        max_rules: Some(1),
+        max_facts_bytes: Some(16),
+        ..Limits::default()
+    });
+
+    let err = engine.try_add_rule(deep(3)).unwrap_err();
+    assert!(matches!(err, Error::LimitError(_)));
+    assert_eq!(
+        err.to_string(),
+        "Limit error: `The depth of the conditions is 3, more than the maximum of 2`"
+    );
+    let wide = Rule {
+        id: None,
+        conditions: and(vec![
+            int_equals("foo", 1),
+            int_equals("bar", 1),
+            int_equals("baz", 1),
+        ]),
+        events: Vec::new(),
+        facts_to_add: Default::default(),
+        enabled: true,
+        tags: Vec::new(),
+        sample: None,
+    };
+    assert!(matches!(
+        engine.try_add_rule(wide),
+        Err(Error::LimitError(_))
+    ));
+
+    engine.try_add_rule(deep(2)).unwrap();
+    assert!(matches!(
+        engine.try_add_rule(deep(1)),
+        Err(Error::LimitError(_))
+    ));
+
+    assert_eq!(engine.evaluate(&json!({ "foo": 1 })).unwrap().len(), 1);
+    assert!(matches!(
+        engine.evaluate(&json!({ "foo": 1, "bar": 1 })),
+        Err(Error::LimitError(_))
+    ));
+}
+
+#[tokio::test]
+async fn event_limits() {
+    use json_rules_engine::{Limits, TRUNCATION_MARKER};
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_not_equals",
+            "value": ""
+        },
+        "events": [
+            {
+                "type": "counting_event",
+                "params": {
+                    "message": "Hello {{name}}!",
+                    "level": "info"
+                }
+            },
+            {
+                "type": "counting_event",
+                "params": {
+                    "message": "Bye"
+                }
+            }
+        ]
+    }))
+    .unwrap();
+    let facts = json!({ "name": "é".repeat(20) });
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule.clone());
+    let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting_event.clone());
+    engine.set_limits(Limits {
+        max_rendered_message_bytes: Some(16),
+        ..Limits::default()
+    });
+
+    let rule_results = engine.run(&facts).await.unwrap();
+    let events = serde_json::to_value(&rule_results[0].events).unwrap();
+    assert_eq!(events[0]["too_large"], json!(true));
+    assert_eq!(events[1].get("too_large"), None);
+    assert_eq!(counting_event.read().unwrap().triggered.len(), 1);
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule.clone());
+    let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting_event.clone());
+    engine.set_limits(Limits {
+        max_rendered_message_bytes: Some(16),
+        truncate_messages: true,
+        ..Limits::default()
+    });
+
+    let rule_results = engine.run(&facts).await.unwrap();
+    let events = serde_json::to_value(&rule_results[0].events).unwrap();
+    assert_eq!(events[0].get("too_large"), None);
+    let message = events[0]["params"]["message"].as_str().unwrap();
+    assert!(message.len() <= 16);
+    assert_eq!(
+        message,
+        format!("Hello {}{}", "é".repeat(3), TRUNCATION_MARKER)
+    );
+    assert_eq!(events[0]["params"]["level"], json!("info"));
+    assert_eq!(counting_event.read().unwrap().triggered.len(), 2);
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting_event.clone());
+    engine.set_limits(Limits {
+        max_events_per_rule_per_run: Some(1),
+        ..Limits::default()
+    });
+
+    let rule_results = engine.run(&facts).await.unwrap();
+    let events = serde_json::to_value(&rule_results[0].events).unwrap();
+    assert_eq!(events[0].get("too_large"), None);
+    assert_eq!(events[1]["too_large"], json!(true));
+    assert_eq!(counting_event.read().unwrap().triggered.len(), 1);
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn callback_payload_limit() {
+    use json_rules_engine::Limits;
+    use wiremock::{matchers::method, Mock, MockServer, ResponseTemplate};
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "bio",
+            "operator": "string_not_equals",
+            "value": ""
+        },
+        "events": [
+            {
+                "type": "post_to_callback_url",
+                "params": {
+                    "callback_url": server.uri()
+                }
+            }
+        ]
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.set_limits(Limits {
+        max_callback_payload_bytes: Some(256),
+        ..Limits::default()
+    });
+
+    let rule_results = engine.run(&json!({ "bio": "rust" })).await.unwrap();
+    let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+    assert_eq!(event.get("too_large"), None);
+
+    let rule_results = engine
+        .run(&json!({ "bio": "rust".repeat(100) }))
+        .await
+        .unwrap();
+    let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+    assert_eq!(event["too_large"], json!(true));
+}
+
+#[test]
+fn schema_migrations() {
+    use json_rules_engine::{migrate_rule_value, SCHEMA_VERSION};
+
+    let event = json!({
+        "type": "post_to_callback_url",
+        "params": {
+            "callback_url": "http://example.com/people/coding_in_rust",
+            "type": "info",
+            "title": "Another person is coding in rust",
+            "message": "{{name}} is coding in rust"
+        }
+    });
+    // the shapes written by 0.1, 0.2 to 0.3, 0.4 to 0.6 and 0.7 onwards
+    let fixtures = vec![
+        json!({
+            "schema_version": 0,
+            "conditions": {
+                "and": [
+                    { "field": "name", "operator": "StringEquals", "value": "Cheng JIANG" },
+                    { "field": "age", "operator": "GreaterThanInclusive", "value": 18 },
+                    {
+                        "should_minimum_meet": 1,
+                        "conditions": [
+                            { "field": "action", "operator": "StringIn", "value": ["coding in rust", "coding in go"] },
+                            { "field": "age", "operator": "LessThan", "value": 10 }
+                        ]
+                    }
+                ]
+            },
+            "event": event
+        }),
+        json!({
+            "schema_version": 1,
+            "conditions": {
+                "and": [
+                    { "field": "name", "operator": "StringEquals", "value": "Cheng JIANG" },
+                    { "field": "age", "operator": "IntGreaterThanInclusive", "value": 18 },
+                    {
+                        "should_minimum_meet": 1,
+                        "conditions": [
+                            { "field": "action", "operator": "StringIn", "value": ["coding in rust", "coding in go"] },
+                            { "field": "age", "operator": "IntLessThan", "value": 10 }
+                        ]
+                    }
+                ]
+            },
+            "event": event
+        }),
+        json!({
+            "schema_version": 2,
+            "conditions": {
+                "and": [
+                    { "field": "name", "operator": "string_equals", "value": "Cheng JIANG" },
+                    { "field": "age", "operator": "int_greater_than_inclusive", "value": 18 },
+                    {
+                        "should_minimum_meet": 1,
+                        "conditions": [
+                            { "field": "action", "operator": "string_in", "value": ["coding in rust", "coding in go"] },
+                            { "field": "age", "operator": "int_less_than", "value": 10 }
+                        ]
+                    }
+                ]
+            },
+            "event": event
+        }),
+        json!({
+            "conditions": {
+                "and": [
+                    { "field": "name", "operator": "string_equals", "value": "Cheng JIANG" },
+                    { "field": "age", "operator": "int_greater_than_inclusive", "value": 18 },
+                    {
+                        "should_minimum_meet": 1,
+                        "conditions": [
+                            { "field": "action", "operator": "string_in", "value": ["coding in rust", "coding in go"] },
+                            { "field": "age", "operator": "int_less_than", "value": 10 }
+                        ]
+                    }
+                ]
+            },
+            "events": [event]
+        }),
+    ];
+
+    let current = fixtures.last().unwrap().clone();
+    for (version, fixture) in fixtures.iter().enumerate() {
+        let mut migrated = fixture.clone();
+        migrated.as_object_mut().unwrap().remove("schema_version");
+        assert_eq!(
+            migrate_rule_value(migrated, version as u32).unwrap(),
+            current
+        );
+    }
+
+    let current: Rule = serde_json::from_value(current).unwrap();
+    for fixture in fixtures {
+        let rule = Rule::from_versioned_value(fixture).unwrap();
+        assert_eq!(
+            serde_json::to_value(&rule).unwrap(),
+            serde_json::to_value(&current).unwrap()
+        );
+
+        for facts in &[
+            json!({ "name": "Cheng JIANG", "age": 24, "action": "coding in rust" }),
+            json!({ "name": "Cheng JIANG", "age": 24, "action": "coding in c" }),
+            json!({ "name": "Cheng JIANG", "action": "coding in go" }),
+        ] {
+            assert_eq!(
+                serde_json::to_value(
+                    &rule
+                        .check_value(
+                            facts,
+                            #[cfg(feature = "eval")]
+                            &rhai::Engine::new()
+                        )
+                        .condition_result
+                )
+                .unwrap(),
+                serde_json::to_value(
+                    &current
+                        .check_value(
+                            facts,
+                            #[cfg(feature = "eval")]
+                            &rhai::Engine::new()
+                        )
+                        .condition_result
+                )
+                .unwrap()
+            );
+        }
+    }
+
+    assert!(matches!(
+        migrate_rule_value(json!({}), SCHEMA_VERSION + 1),
+        Err(Error::MigrationError(_))
+    ));
+    assert!(matches!(
+        Rule::from_versioned_value(json!({ "schema_version": "two" })),
+        Err(Error::MigrationError(_))
+    ));
+}
+
+#[test]
+fn rule_set_diff() {
+    use json_rules_engine::diff::{rules_diff, NodeChange, RenamedRule};
+
+    let rule = |id: Option<&str>, age: i64, title: &str| -> Rule {
+        serde_json::from_value(json!({
+            "id": id,
+            "conditions": {
+                "and": [
+                    {
+                        "field": "name",
+                        "operator": "string_equals",
+                        "value": "Cheng JIANG"
+                    },
+                    {
+                        "field": "age",
+                        "operator": "int_greater_than",
+                        "value": age
+                    }
+                ]
+            },
+            "events": [
+                {
+                    "type": "post_to_callback_url",
+                    "params": {
+                        "callback_url": "http://example.com/people",
+                        "title": title
+                    }
+                }
+            ]
+        }))
+        .unwrap()
+    };
+
+    let old = vec![
+        rule(Some("adults"), 18, "Adult"),
+        rule(Some("seniors"), 65, "Senior"),
+        rule(None, 30, "Thirties"),
+        rule(Some("teens"), 12, "Teen"),
+        rule(None, 40, "Forties"),
+    ];
+
+    // reordered rules are unchanged
+    let mut reordered = old.clone();
+    reordered.reverse();
+    let diff = rules_diff(&old, &reordered);
+    assert!(diff.is_empty());
+    assert_eq!(diff.unchanged.len(), 5);
+
+    let new = vec![
+        rule(None, 30, "Thirties"),
+        rule(Some("seniors"), 67, "Retiree"),
+        rule(Some("grown_ups"), 18, "Adult"),
+        rule(Some("kids"), 6, "Kid"),
+    ];
+    let diff = rules_diff(&old, &new);
+
+    assert_eq!(diff.unchanged, [diff.unchanged[0].clone()]);
+    assert!(diff.unchanged[0].starts_with('#'));
+    assert_eq!(
+        diff.renamed,
+        [RenamedRule {
+            old_id: "adults".to_string(),
+            new_id: "grown_ups".to_string(),
+        }]
+    );
+    assert_eq!(diff.added.len(), 1);
+    assert_eq!(diff.added[0].key, "kids");
+    assert_eq!(
+        diff.removed
+            .iter()
+            .map(|r| r.key.as_str())
+            .collect::<Vec<_>>(),
+        ["teens", diff.removed[1].key.as_str()]
+    );
+    assert_eq!(
+        diff.removed[1].rule["events"][0]["params"]["title"],
+        "Forties"
+    );
+
+    assert_eq!(diff.modified.len(), 1);
+    let modified = &diff.modified[0];
+    assert_eq!(modified.key, "seniors");
+    assert_eq!(
+        modified.conditions,
+        [NodeChange {
+            path: "/and/1".to_string(),
+            old: Some(json!({
+                "field": "age",
+                "operator": "int_greater_than",
+                "value": 65,
+                "path": null
+            })),
+            new: Some(json!({
+                "field": "age",
+                "operator": "int_greater_than",
+                "value": 67,
+                "path": null
+            })),
+        }]
+    );
+    assert_eq!(modified.events.len(), 1);
+    assert_eq!(modified.events[0].path, "/0");
+    assert_eq!(
+        modified.events[0].new.as_ref().unwrap()["params"]["title"],
+        "Retiree"
+    );
+
+    // the diff renders as JSON
+    let rendered = serde_json::to_value(&diff).unwrap();
+    assert_eq!(rendered["modified"][0]["conditions"][0]["path"], "/and/1");
+}
+
+#[test]
+fn templated_values() {
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "shipping_country",
+                    "operator": "string_equals",
+                    "value": "{{ billing_country }}",
+                    "templated_value": true
+                },
+                {
+                    "field": "currency",
+                    "operator": "string_in",
+                    "value": ["{{ billing_currency }}", "USD"],
+                    "templated_value": true
+                }
+            ]
+        },
+        "events": []
+    });
+
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+    let statuses = |facts: Value| {
+        rule.check_value(
+            &facts,
+            #[cfg(feature = "eval")]
+            &rhai::Engine::new(),
+        )
+        .condition_result
+        .children
+        .iter()
+        .map(|c| c.status)
+        .collect::<Vec<_>>()
+    };
+
+    let facts = json!({
+        "shipping_country": "FR",
+        "billing_country": "FR",
+        "currency": "EUR",
+        "billing_currency": "EUR"
+    });
+    assert_eq!(statuses(facts), [Status::Met, Status::Met]);
+
+    let facts = json!({
+        "shipping_country": "FR",
+        "billing_country": "DE",
+        "currency": "USD",
+        "billing_currency": "EUR"
+    });
+    assert_eq!(statuses(facts), [Status::NotMet, Status::Met]);
+
+    let facts = json!({
+        "shipping_country": "FR",
+        "billing_country": "FR",
+        "currency": "GBP",
+        "billing_currency": "EUR"
+    });
+    assert_eq!(statuses(facts), [Status::Met, Status::NotMet]);
+
+    // without the flag, values are compared verbatim
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "code",
+            "operator": "string_equals",
+            "value": "{{ code }}"
+        },
+        "events": []
+    }))
+    .unwrap();
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    assert_eq!(
+        engine
+            .evaluate(&json!({ "code": "{{ code }}" }))
+            .unwrap()
+            .len(),
+        1
+    );
+
+    // values failing to render are unknown
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "code",
+            "operator": "string_equals",
+            "value": "{{ code",
+            "templated_value": true
+        },
+        "events": []
+    }))
+    .unwrap();
+    let result = rule.check_value(
+        &json!({ "code": "A" }),
+        #[cfg(feature = "eval")]
+        &rhai::Engine::new(),
+    );
+    assert_eq!(result.condition_result.status, Status::Unknown);
+}
+
+#[test]
+fn array_uniqueness() {
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "coupons",
+                    "operator": "array_all_unique",
+                    "value": true
+                },
+                {
+                    "field": "coupons",
+                    "operator": "array_distinct_count_greater_than_inclusive",
+                    "value": 3
+                }
+            ]
+        },
+        "events": []
+    });
+
+    let mut engine = Engine::new();
+    engine.add_rule(serde_json::from_value(rule_json).unwrap());
+
+    let met = |facts: Value| !engine.evaluate(&facts).unwrap().is_empty();
+
+    assert!(met(json!({ "coupons": ["A", "B", "C"] })));
+    assert!(!met(json!({ "coupons": ["A", "B", "A"] })));
+    assert!(!met(json!({ "coupons": ["A", "B"] })));
+    assert!(!met(json!({ "coupons": "A" })));
+
+    // strings, ints and floats never equal each other
+    assert!(met(json!({ "coupons": ["1", 1, 1.5] })));
+    assert!(!met(json!({ "coupons": ["1", 1, 1] })));
+    // objects are equal regardless of their key order
+    assert!(!met(json!({
+        "coupons": [{ "a": 1, "b": 2 }, { "b": 2, "a": 1 }, "C", "D"]
+    })));
+    assert!(met(json!({
+        "coupons": [{ "a": 1, "b": 2 }, { "a": 2, "b": 1 }, "C"]
+    })));
+}
+
+#[test]
+fn uint_facts() {
+    let hash: u64 = 18_000_000_000_000_000_000;
+    assert!(hash > i64::MAX as u64);
+
+    let mut engine = Engine::new();
+    engine.add_rule(
+        serde_json::from_value(json!({
+            "conditions": {
+                "and": [
+                    {
+                        "field": "hash",
+                        "operator": "uint_equals",
+                        "value": hash
+                    },
+                    {
+                        "field": "hash",
+                        "operator": "uint_in_range",
+                        "value": [i64::MAX as u64 + 1, u64::MAX]
+                    },
+                    {
+                        "field": "parent",
+                        "operator": "uint_in",
+                        "value": [1, u64::MAX]
+                    },
+                    {
+                        "field": "hash",
+                        "operator": "uint_greater_than",
+                        "value": 42
+                    }
+                ]
+            },
+            "events": []
+        }))
+        .unwrap(),
+    );
+
+    let met = |facts: Value| !engine.evaluate(&facts).unwrap().is_empty();
+
+    assert!(met(json!({ "hash": hash, "parent": u64::MAX })));
+    assert!(met(json!({ "hash": hash, "parent": 1 })));
+    assert!(!met(json!({ "hash": hash + 1, "parent": 1 })));
+    assert!(!met(json!({ "hash": hash, "parent": 2 })));
+    // negative and fractional facts never match
+    assert!(!met(json!({ "hash": hash, "parent": -1 })));
+    assert!(!met(json!({ "hash": hash, "parent": 1.0 })));
+
+    // the signed constraints can't see past `i64::MAX`
+    let mut engine = Engine::new();
+    engine.add_rule(
+        serde_json::from_value(json!({
+            "conditions": {
+                "field": "hash",
+                "operator": "int_greater_than",
+                "value": 0
+            },
+            "events": []
+        }))
+        .unwrap(),
+    );
+    assert!(engine
+        .evaluate(&json!({ "hash": hash }))
+        .unwrap()
+        .is_empty());
+}
+
+#[test]
+fn format_constraints() {
+    let met = |operator: &str, value: bool, fact: Value| {
+        let mut engine = Engine::new();
+        engine.add_rule(
+            serde_json::from_value(json!({
+                "conditions": {
+                    "field": "id",
+                    "operator": operator,
+                    "value": value
+                },
+                "events": []
+            }))
+            .unwrap(),
+        );
+        !engine.evaluate(&json!({ "id": fact })).unwrap().is_empty()
+    };
+
+    for uuid in [
+        "67e55044-10b1-426f-9247-bb680e5fe0c8",
+        "67E55044-10B1-426F-9247-BB680E5FE0C8",
+        "67e5504410b1426f9247bb680e5fe0c8",
+    ] {
+        assert!(met("is_uuid", true, json!(uuid)), "{}", uuid);
+        assert!(!met("is_uuid", false, json!(uuid)), "{}", uuid);
+    }
+    for not_uuid in ["67e55044-10b1-426f-9247-bb680e5fe0c", "", "uuid"] {
+        assert!(!met("is_uuid", true, json!(not_uuid)), "{}", not_uuid);
+        assert!(met("is_uuid", false, json!(not_uuid)), "{}", not_uuid);
+    }
+
+    assert!(met("is_ulid", true, json!("01ARZ3NDEKTSV4RRFFQ69G5FAV")));
+    // `U` isn't in the Crockford alphabet
+    assert!(met("is_ulid", false, json!("01ARZ3NDEKTSV4RRFFQ69G5FAU")));
+    assert!(met("is_ulid", false, json!("01ARZ3NDEKTSV4RRFFQ69G5FA")));
+
+    for email in ["jdoe@example.com", "j.doe+tag@mail.example.co.uk"] {
+        assert!(met("is_email", true, json!(email)), "{}", email);
+    }
+    for not_email in [
+        "jdoe",
+        "jdoe@",
+        "@example.com",
+        "jdoe@example",
+        "j doe@example.com",
+        "jdoe@@example.com",
+        "jdoe@-example.com",
+        "jdoe@example..com",
+    ] {
+        assert!(met("is_email", false, json!(not_email)), "{}", not_email);
+    }
+
+    assert!(met("is_url", true, json!("https://example.com/a?b=c")));
+    assert!(met("is_url", true, json!("ftp://user@host:21")));
+    // urls need a scheme
+    assert!(met("is_url", false, json!("example.com/a")));
+    assert!(met("is_url", false, json!("//example.com")));
+
+    // non strings are never met, whichever way
+    for operator in ["is_uuid", "is_ulid", "is_email", "is_url"] {
+        assert!(!met(operator, true, json!(42)));
+        assert!(!met(operator, false, json!(42)));
+        assert!(!met(operator, false, json!(null)));
+    }
+}
+
+#[test]
+fn number_facts() {
+    // 2^53 + 1 rounds to 2^53 as an `f64`
+    let big: i64 = 9_007_199_254_740_993;
+
+    let met = |condition: Value, fact: Value| {
+        let mut engine = Engine::new();
+        engine.add_rule(
+            serde_json::from_value(json!({
+                "conditions": condition,
+                "events": []
+            }))
+            .unwrap(),
+        );
+        !engine.evaluate(&json!({ "n": fact })).unwrap().is_empty()
+    };
+    let op = |operator: &str, value: Value| json!({ "field": "n", "operator": operator, "value": value });
+
+    // ints and floats on either side
+    assert!(met(op("number_equals", json!(10)), json!(10.0)));
+    assert!(met(op("number_equals", json!(10.0)), json!(10)));
+    assert!(met(op("number_greater_than", json!(1)), json!(1.5)));
+    assert!(met(op("number_less_than", json!(1.5)), json!(1)));
+    assert!(met(op("number_in", json!([1, 2.5, u64::MAX])), json!(2.5)));
+    assert!(met(op("number_in", json!([1, 2.5])), json!(1.0)));
+    assert!(met(op("number_not_in", json!([1, 2.5])), json!(2)));
+    assert!(met(op("number_in_range", json!([-1, 2.5])), json!(-1.0)));
+    assert!(met(op("number_in_range", json!([-1.5, 2])), json!(2)));
+    assert!(!met(op("number_in_range", json!([-1.5, 2])), json!(2.1)));
+    assert!(met(op("number_not_in_range", json!([0, 1])), json!(-0.5)));
+    assert!(met(
+        op("number_greater_than_inclusive", json!(-1)),
+        json!(u64::MAX)
+    ));
+    assert!(met(
+        op("number_less_than_inclusive", json!(-1.0)),
+        json!(-1)
+    ));
+
+    // integers past `f64` precision are compared exactly
+    assert!(!met(op("number_equals", json!(big - 1)), json!(big)));
+    assert!(met(op("number_not_equals", json!(big - 1)), json!(big)));
+    assert!(met(op("number_greater_than", json!(big - 1)), json!(big)));
+    assert!(met(
+        op("number_greater_than", json!((big - 1) as f64)),
+        json!(big)
+    ));
+    assert!(met(
+        op("number_less_than", json!(big)),
+        json!((big - 1) as f64)
+    ));
+    assert!(met(
+        op("number_equals", json!(big - 1)),
+        json!((big - 1) as f64)
+    ));
+
+    // non numbers never match
+    assert!(!met(op("number_equals", json!(1)), json!("1")));
+    assert!(!met(op("number_not_equals", json!(1)), json!("2")));
+    assert!(!met(op("number_not_in", json!([1])), json!(null)));
+}
+
+#[tokio::test]
+async fn rate_limited_events() {
+    let rule_json = json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [
+            {
+                "type": "counting_event",
+                "params": {}
+            }
+        ]
+    });
+
+    let mut engine = Engine::new();
+    engine.add_rule(serde_json::from_value(rule_json).unwrap());
+    engine.set_rate_limit("counting_event", 10, Duration::from_secs(60));
+
+    let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting_event.clone());
+
+    let mut rate_limited = 0;
+    for _ in 0..15 {
+        let rule_results =
+            engine.run(&json!({ "name": "Cheng JIANG" })).await.unwrap();
+        let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+        if event["rate_limited"] == json!(true) {
+            rate_limited += 1;
+        }
+    }
+
+    assert_eq!(counting_event.read().unwrap().triggered.len(), 10);
+    assert_eq!(rate_limited, 5);
+}
+
+#[tokio::test]
+async fn tenants_are_isolated() {
+    let rule = |id: &str| -> Rule {
+        serde_json::from_value(json!({
+            "id": id,
+            "conditions": {
+                "field": "name",
+                "operator": "string_equals",
+                "value": "Cheng JIANG"
+            },
+            "events": [
+                {
+                    "type": "counting_event",
+                    "coalescence": 60,
+                    "coalescence_group": "alerts",
+                    "params": {}
+                }
+            ]
+        }))
+        .unwrap()
+    };
+
+    let mut engine = Engine::new();
+    engine.add_rule_for("acme", rule("acme"));
+    engine.add_rule_for("globex", rule("globex"));
+    engine.add_rule(rule("shared"));
+    engine.set_rate_limit("counting_event", 1, Duration::from_secs(60));
+
+    let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting_event.clone());
+
+    let facts = json!({ "name": "Cheng JIANG" });
+    let (rule_results, info) =
+        engine.run_for_with_info("acme", &facts).await.unwrap();
+    assert_eq!(rule_results.len(), 1);
+    assert_eq!(rule_results[0].rule_id.as_deref(), Some("acme"));
+    assert_eq!(info.rules_evaluated, 1);
+    assert_eq!(info.tenant.as_deref(), Some("acme"));
+
+    // same coalescence group and rate limit, but another tenant
+    let rule_results = engine.run_for("globex", &facts).await.unwrap();
+    assert_eq!(rule_results[0].rule_id.as_deref(), Some("globex"));
+    assert_eq!(counting_event.read().unwrap().triggered.len(), 2);
+
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert_eq!(rule_results.len(), 1);
+    assert_eq!(rule_results[0].rule_id.as_deref(), Some("shared"));
+    assert_eq!(counting_event.read().unwrap().triggered.len(), 3);
+
+    // the tenant's own group still coalesces
+    let rule_results = engine.run_for("acme", &facts).await.unwrap();
+    assert!(rule_results[0].events.is_empty());
+
+    assert_eq!(engine.remove_tenant("acme"), 1);
+    assert_eq!(engine.remove_tenant("acme"), 0);
+    assert!(engine.run_for("acme", &facts).await.unwrap().is_empty());
+    let rule_results = engine.run_for("globex", &facts).await.unwrap();
+    assert_eq!(rule_results[0].rule_id.as_deref(), Some("globex"));
+
+    // a tenant added back starts afresh
+    engine.add_rule_for("acme", rule("acme"));
+    let rule_results = engine.run_for("acme", &facts).await.unwrap();
+    assert_eq!(rule_results[0].events.len(), 1);
+    assert_eq!(counting_event.read().unwrap().triggered.len(), 4);
+}
+
+#[test]
+fn strict_rules() {
+    let rule_json = json!({
+        "id": "adult",
+        "oops": true,
+        "conditions": {
+            "and": [
+                {
+                    "field": "name",
+                    "operator": "string_equals",
+                    "value": "Cheng JIANG"
+                },
+                {
+                    "not": {
+                        "field": "age",
+                        "operator": "int_less_than",
+                        "value": 18,
+                        "pathsyntax": "dotted"
+                    }
+                }
+            ]
+        },
+        "events": [
+            {
+                "type": "counting_event",
+                "params": {
+                    "anything": "goes"
+                },
+                "coallescence": 60
+            }
+        ]
+    });
+
+    // lenient by default
+    assert!(serde_json::from_value::<Rule>(rule_json.clone()).is_ok());
+
+    match Rule::from_value_strict(rule_json.clone()) {
+        Err(Error::UnknownFieldsError(fields)) => assert_eq!(
+            fields,
+            [
+                "/oops",
+                "/conditions/and/1/not/pathsyntax",
+                "/events/0/coallescence"
+            ]
+        ),
+        _ => panic!("unknown fields weren't reported"),
+    }
+
+    let mut engine = Engine::new();
+    assert!(engine.try_add_rule_strict(rule_json).is_err());
+
+    let rule_json = json!({
+        "conditions": {
+            "should_minimum_meet": 1,
+            "conditions": [
+                {
+                    "field": "name",
+                    "operator": "string_equals",
+                    "value": "Cheng JIANG",
+                    "path_syntax": "dotted"
+                }
+            ]
+        },
+        "events": [
+            {
+                "type": "counting_event",
+                "params": {},
+                "coalescence": 60,
+                "coalescence_group": "name"
+            }
+        ]
+    });
+
+    assert!(engine.try_add_rule_strict(rule_json).is_ok());
+}
+
+#[test]
+fn failure_messages() {
+    let rule = Rule::from_value_strict(json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "total",
+                    "operator": "float_greater_than_inclusive",
+                    "value": 50.0,
+                    "failure_message": "Your order must be at least €50 for free shipping, it's €{{ total }}"
+                },
+                {
+                    "or": [
+                        {
+                            "field": "country",
+                            "operator": "string_in",
+                            "value": ["fr", "de"],
+                            "failure_message": "We don't ship to {{ country }} for free"
+                        },
+                        {
+                            "field": "member",
+                            "operator": "bool_equals",
+                            "value": true,
+                            "failure_message": "Members ship for free"
+                        }
+                    ],
+                    "failure_message": "Free shipping is for some countries or members"
+                },
+                {
+                    "field": "express",
+                    "operator": "bool_equals",
+                    "value": false,
+                    "failure_message": "Never shown, express is false"
+                }
+            ],
+            "failure_message": "No free shipping"
+        },
+        "events": []
+    }))
+    .unwrap();
+
+    let check = |facts: Value| {
+        rule.check_value(
+            &facts,
+            #[cfg(feature = "eval")]
+            &rhai::Engine::new(),
+        )
+    };
+
+    let rule_result = check(json!({
+        "total": 42.5,
+        "country": "us",
+        "member": false,
+        "express": false
+    }));
+    assert_eq!(rule_result.condition_result.status, Status::NotMet);
+    assert_eq!(
+        rule_result.failure_messages(),
+        vec![
+            "No free shipping",
+            "Your order must be at least €50 for free shipping, it's €42.5",
+            "Free shipping is for some countries or members",
+            "We don't ship to us for free",
+            "Members ship for free",
+        ]
+    );
+
+    // met nodes have none
+    let rule_result = check(json!({
+        "total": 60.0,
+        "country": "us",
+        "member": true,
+        "express": false
+    }));
+    assert_eq!(rule_result.condition_result.status, Status::Met);
+    assert_eq!(
+        rule_result.failure_messages(),
+        vec!["We don't ship to us for free"]
+    );
+}
+
+#[tokio::test]
+async fn pipeline_stages() {
+    use json_rules_engine::Pipeline;
+
+    let stage = |rules: Value| {
+        let mut engine = Engine::new();
+        engine.add_rules(serde_json::from_value(rules).unwrap());
+        let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+        engine.add_event(counting_event.clone());
+        (engine, counting_event)
+    };
+    let (enrichment, _) = stage(json!([{
+        "id": "adult",
+        "conditions": {
+            "field": "age",
+            "operator": "int_greater_than_inclusive",
+            "value": 18
+        },
+        "events": [],
+        "facts_to_add": { "adult": true, "segment": "adult_{{ country }}" }
+    }]));
+    let (decision, _) = stage(json!([{
+        "id": "eligible",
+        "conditions": {
+            "and": [
+                { "field": "adult", "operator": "bool_equals", "value": true },
+                {
+                    "field": "segment",
+                    "operator": "string_equals",
+                    "value": "adult_fr"
+                }
+            ]
+        },
+        "events": [],
+        "facts_to_add": { "offer": "wine_tasting" }
+    }]));
+    let (notification, notified) = stage(json!([{
+        "id": "notify",
+        "conditions": {
+            "field": "offer",
+            "operator": "string_equals",
+            "value": "wine_tasting"
+        },
+        "events": [{ "type": "counting_event", "params": {} }]
+    }]));
+
+    let mut pipeline = Pipeline::new();
+    pipeline.add_stage("enrichment", enrichment);
+    pipeline.add_stage("decision", decision);
+    pipeline.add_stage("notification", notification);
+
+    let stage_results = pipeline
+        .run(&json!({ "age": 30, "country": "fr" }))
+        .await
+        .unwrap();
+    let names: Vec<_> = stage_results.iter().map(|s| s.name.as_str()).collect();
+    assert_eq!(names, vec!["enrichment", "decision", "notification"]);
+    assert!(stage_results.iter().all(|s| s.rule_results.len() == 1));
+    assert_eq!(
+        stage_results[0].rule_results[0].facts_to_add,
+        *json!({ "adult": true, "segment": "adult_fr" })
+            .as_object()
+            .unwrap()
+    );
+    assert_eq!(stage_results[1].facts["offer"], "wine_tasting");
+    // the events see the facts the stages before added
+    let triggered = notified.read().unwrap().triggered.clone();
+    assert_eq!(triggered.len(), 1);
+    assert_eq!(triggered[0]["segment"], "adult_fr");
+
+    // without the enrichment, nothing is decided
+    let stage_results = pipeline
+        .run(&json!({ "age": 12, "country": "fr" }))
+        .await
+        .unwrap();
+    assert!(stage_results.iter().all(|s| s.rule_results.is_empty()));
+    assert_eq!(notified.read().unwrap().triggered.len(), 1);
+
+    // a failing stage stops the pipeline, the stages before it having
+    // dispatched their events unless deferred
+    let (enrichment, enriched) = stage(json!([{
+        "conditions": {
+            "field": "age",
+            "operator": "int_greater_than",
+            "value": 0
+        },
+        "events": [{ "type": "counting_event", "params": {} }]
+    }]));
+    let mut pipeline = Pipeline::new();
+    pipeline.add_stage("enrichment", enrichment);
+    pipeline.add_stage("notification", Engine::new());
+    pipeline.stage_mut("notification").unwrap().set_limits(
+        json_rules_engine::Limits {
+            max_facts_bytes: Some(16),
+            ..Default::default()
+        },
+    );
+    let facts = json!({ "age": 30, "country": "fr" });
+    assert!(pipeline.run(&facts).await.is_err());
+    assert_eq!(enriched.read().unwrap().triggered.len(), 1);
+    pipeline.set_defer_dispatch(true);
+    assert!(pipeline.run(&facts).await.is_err());
+    assert_eq!(enriched.read().unwrap().triggered.len(), 1);
+}
+
+#[tokio::test]
+async fn apply_json_patch() {
+    let rules: Vec<Rule> = serde_json::from_value(json!([
+        {
+            "id": "score",
+            "conditions": {
+                "field": "score",
+                "operator": "int_greater_than",
+                "value": 80
+            },
+            "events": [
+                {
+                    "type": "apply_json_patch",
+                    "params": {
+                        "patch": [
+                            { "op": "add", "path": "/risk_level", "value": "high" },
+                            {
+                                "op": "add",
+                                "path": "/flags/-",
+                                "value": "scored {{ score }}"
+                            }
+                        ]
+                    }
+                }
+            ]
+        },
+        {
+            "id": "review",
+            "conditions": {
+                "field": "risk_level",
+                "operator": "string_equals",
+                "value": "high"
+            },
+            "events": [
+                {
+                    "type": "apply_json_patch",
+                    "params": {
+                        "patch": [
+                            { "op": "add", "path": "/review", "value": {} },
+                            { "op": "move", "from": "/score", "path": "/review/score" },
+                            { "op": "replace", "path": "/flags/0", "value": "{{ vars.team }}" }
+                        ]
+                    }
+                },
+                {
+                    "type": "apply_json_patch",
+                    "params": {
+                        "patch": [
+                            { "op": "test", "path": "/risk_level", "value": "low" },
+                            { "op": "remove", "path": "/risk_level" }
+                        ]
+                    }
+                }
+            ]
+        }
+    ]))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    for rule in rules {
+        engine.try_add_rule(rule).unwrap();
+    }
+    engine.set_variable("team", json!("fraud"));
+
+    let facts = json!({ "score": 92, "flags": ["new"] });
+    let (rule_results, run_info) = engine.run_with_info(&facts).await.unwrap();
+
+    // the second rule was met by the facts the first one patched
+    assert_eq!(rule_results.len(), 2);
+    assert_eq!(
+        run_info.patched_facts,
+        Some(json!({
+            "risk_level": "high",
+            "flags": ["fraud", "scored 92"],
+            "review": { "score": 92 }
+        }))
+    );
+    assert_eq!(
+        rule_results[0].patches,
+        vec![json_rules_engine::PatchOutcome {
+            applied: true,
+            error: None
+        }]
+    );
+    // a failing test skips its whole patch
+    assert_eq!(rule_results[1].patches.len(), 2);
+    assert!(rule_results[1].patches[0].applied);
+    assert!(!rule_results[1].patches[1].applied);
+    assert!(rule_results[1].patches[1]
+        .error
+        .as_deref()
+        .unwrap()
+        .starts_with("Test failed"));
+
+    // nothing patched, nothing returned
+    let (rule_results, run_info) =
+        engine.run_with_info(&json!({ "score": 10 })).await.unwrap();
+    assert!(rule_results.is_empty());
+    assert_eq!(run_info.patched_facts, None);
+
+    // invalid patches are refused when the rule is added
+    for patch in [
+        json!([{ "op": "add", "path": "risk_level", "value": 1 }]),
+        json!([{ "op": "increment", "path": "/score" }]),
+        json!([{ "op": "move", "from": "/a", "path": "/a/b" }]),
+        json!({ "op": "remove", "path": "/score" }),
+    ] {
+        let rule: Rule = serde_json::from_value(json!({
+            "conditions": { "and": [] },
+            "events": [
+                { "type": "apply_json_patch", "params": { "patch": patch } }
+            ]
+        }))
+        .unwrap();
+        assert!(matches!(
+            engine.try_add_rule(rule),
+            Err(Error::ValidationError(_))
+        ));
+    }
+}
+
+#[tokio::test]
+async fn cached_lookups() {
+    use json_rules_engine::EngineOptions;
+
+    // leaves sharing fields, spelled every way a field can address a fact
+    let leaf = |i: usize| {
+        let n = i % 20;
+        match i % 6 {
+            0 => json!({
+                "field": format!("user/f{}", n),
+                "operator": "int_greater_than",
+                "value": i % 7
+            }),
+            1 => json!({
+                "field": format!("/user/f{}", n),
+                "operator": "int_less_than",
+                "value": i % 9
+            }),
+            2 => json!({
+                "field": format!("user.f{}", n),
+                "operator": "int_in_range",
+                "value": [1, 5],
+                "path_syntax": "dotted"
+            }),
+            3 => json!({
+                "field": "flat/key",
+                "operator": "string_equals",
+                "value": "top level"
+            }),
+            4 => json!({
+                "field": format!("missing/f{}", n),
+                "operator": "int_equals",
+                "value": 3,
+                "default": 3
+            }),
+            _ => json!({
+                "field": "devices",
+                "operator": "any_match",
+                "value": {
+                    "field": format!("f{}", n),
+                    "operator": "int_equals",
+                    "value": n % 4
+                }
+            }),
+        }
+    };
+    let rules: Vec<Rule> = (0..300)
+        .map(|i| {
+            let conditions = if i % 2 == 0 {
+                json!({ "and": [leaf(i), leaf(i + 1)] })
+            } else {
+                json!({ "or": [leaf(i), leaf(i + 3)] })
+            };
+            serde_json::from_value(json!({
+                "id": format!("rule-{}", i),
+                "conditions": conditions,
+                "events": []
+            }))
+            .unwrap()
+        })
+        .collect();
+
+    let user: serde_json::Map<String, Value> =
+        (0..20).map(|n| (format!("f{}", n), json!(n % 8))).collect();
+    let device: serde_json::Map<String, Value> =
+        (0..20).map(|n| (format!("f{}", n), json!(n % 5))).collect();
+    let facts = json!({
+        "user": user,
+        "flat/key": "top level",
+        "flat": { "key": "nested" },
+        "devices": [device, { "f1": 1, "f2": 2 }]
+    });
+
+    let met_ids = |rule_results: Vec<RuleResult>| {
+        let mut ids: Vec<_> = rule_results
+            .into_iter()
+            .filter_map(|rule_result| rule_result.rule_id)
+            .collect();
+        ids.sort();
+        ids
+    };
+    let mut expected: Vec<_> = rules
+        .iter()
+        .filter(|rule| {
+            rule.check_value(
+                &facts,
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .condition_result
+            .status
+                == Status::Met
+        })
+        .filter_map(|rule| rule.id.clone())
+        .collect();
+    expected.sort();
+    assert!(!expected.is_empty() && expected.len() < rules.len());
+
+    let mut engine = Engine::new();
+    for rule in &rules {
+        engine.add_rule(rule.clone());
+    }
+    assert_eq!(met_ids(engine.run(&facts).await.unwrap()), expected);
+
+    let built = Engine::build(rules, EngineOptions::default())
+        .unwrap_or_else(|_| panic!("rules failed to build"));
+    assert_eq!(met_ids(built.evaluate(&facts).unwrap()), expected);
+
+    // the rules after a patch see the patched facts, not the cached ones
+    let mut engine = Engine::new();
+    engine.add_rule(
+        serde_json::from_value(json!({
+            "id": "patch",
+            "conditions": {
+                "field": "user/f1",
+                "operator": "int_equals",
+                "value": 1
+            },
+            "events": [{
+                "type": "apply_json_patch",
+                "params": {
+                    "patch": [{ "op": "replace", "path": "/user/f1", "value": 9 }]
+                }
+            }]
+        }))
+        .unwrap(),
+    );
+    engine.add_rule(
+        serde_json::from_value(json!({
+            "id": "patched",
+            "conditions": {
+                "field": "user/f1",
+                "operator": "int_equals",
+                "value": 9
+            },
+            "events": []
+        }))
+        .unwrap(),
+    );
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert_eq!(met_ids(rule_results), ["patch", "patched"]);
+}
+
+#[tokio::test]
+async fn run_multi_sources() {
+    let profile = json!({
+        "name": "Cheng JIANG",
+        "age": 24,
+        "address": { "city": "Paris" },
+    });
+    let session = json!({ "ip": "10.0.0.1" });
+    let device = json!({ "os": "linux", "screen": { "width": 1920 } });
+    let sources = [
+        ("profile", &profile),
+        ("session", &session),
+        ("device", &device),
+    ];
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "profile/address/city",
+                    "operator": "string_equals",
+                    "value": "Paris"
+                },
+                {
+                    "field": "session.ip",
+                    "path_syntax": "dotted",
+                    "operator": "string_equals",
+                    "value": "10.0.0.1"
+                },
+                {
+                    "field": "/device/screen/width",
+                    "pointer": true,
+                    "operator": "int_greater_than",
+                    "value": 1024
+                }
+            ]
+        },
+        "events": [{ "type": "counting_event", "params": {} }],
+        "facts_to_add": { "greeting": "Hi {{profile.name}} on {{device.os}}" }
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    let counting = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting.clone());
+    engine.add_rule(rule);
+
+    let rule_results = engine.run_multi(&sources).await.unwrap();
+    assert_eq!(rule_results.len(), 1);
+    assert_eq!(
+        rule_results[0].facts_to_add["greeting"],
+        json!("Hi Cheng JIANG on linux")
+    );
+    assert_eq!(
+        counting.read().unwrap().triggered,
+        vec![json!({
+            "profile": profile,
+            "session": session,
+            "device": device,
+        })]
+    );
+
+    // rules addressing the top level keys only see them once merged
+    let mut engine = Engine::new();
+    engine.add_rule(
+        serde_json::from_value(json!({
+            "conditions": {
+                "and": [
+                    { "field": "age", "operator": "int_equals", "value": 24 },
+                    {
+                        "field": "profile/age",
+                        "operator": "int_equals",
+                        "value": 24
+                    }
+                ]
+            },
+            "events": []
+        }))
+        .unwrap(),
+    );
+    assert!(engine.run_multi(&sources).await.unwrap().is_empty());
+    engine.set_merge_sources(true);
+    assert_eq!(engine.run_multi(&sources).await.unwrap().len(), 1);
+
+    // conflicting mounts
+    assert!(matches!(
+        engine
+            .run_multi(&[("profile", &profile), ("profile", &session)])
+            .await,
+        Err(Error::SourceError(_))
+    ));
+    let named_after_a_source = json!({ "session": "abc" });
+    assert!(matches!(
+        engine
+            .run_multi(&[
+                ("profile", &named_after_a_source),
+                ("session", &session),
+            ])
+            .await,
+        Err(Error::SourceError(_))
+    ));
+}
+
+#[tokio::test]
+async fn disabled_rules() {
+    let rules: Vec<Rule> = serde_json::from_value(json!([
+        {
+            "id": "adult",
+            "conditions": {
+                "field": "age",
+                "operator": "int_greater_than_inclusive",
+                "value": 18
+            },
+            "events": [{ "type": "counting_event", "params": {} }]
+        },
+        {
+            "id": "retired",
+            "conditions": {
+                "field": "age",
+                "operator": "int_greater_than_inclusive",
+                "value": 65
+            },
+            "events": [{ "type": "counting_event", "params": {} }],
+            "enabled": false
+        }
+    ]))
+    .unwrap();
+    assert!(rules[0].enabled);
+    assert!(!rules[1].enabled);
+    // kept when exported, left out when enabled as by default
+    let exported = serde_json::to_value(&rules).unwrap();
+    assert_eq!(exported[0].get("enabled"), None);
+    assert_eq!(exported[1]["enabled"], json!(false));
+    assert!(Rule::from_value_strict(exported[1].clone()).is_ok());
+
+    let mut engine = Engine::new();
+    let counting = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting.clone());
+    engine.load_rules(rules);
+    engine.set_rule_index(true);
+    let facts = json!({ "age": 70 });
+
+    let (rule_results, info) = engine.run_with_info(&facts).await.unwrap();
+    assert_eq!(rule_results.len(), 1);
+    assert_eq!(rule_results[0].rule_id.as_deref(), Some("adult"));
+    assert_eq!(info.rules_evaluated, 1);
+    assert_eq!(info.skipped_disabled, 1);
+    assert_eq!(counting.read().unwrap().triggered.len(), 1);
+    assert_eq!(engine.evaluate(&facts).unwrap().len(), 1);
+    let plan = engine.plan(&json!({}));
+    assert_eq!(plan.rules.len(), 1);
+    assert_eq!(plan.rules[0].rule_id, "adult");
+
+    // toggled at runtime
+    assert!(engine.set_rule_enabled("retired", true));
+    assert!(engine.set_rule_enabled("adult", false));
+    assert!(!engine.set_rule_enabled("missing", false));
+    let (rule_results, info) = engine.run_with_info(&facts).await.unwrap();
+    assert_eq!(rule_results.len(), 1);
+    assert_eq!(rule_results[0].rule_id.as_deref(), Some("retired"));
+    assert_eq!(info.skipped_disabled, 1);
+    assert_eq!(counting.read().unwrap().triggered.len(), 2);
+
+    // nothing fires once every rule is disabled
+    engine.set_rule_enabled("retired", false);
+    let (rule_results, info) = engine.run_with_info(&facts).await.unwrap();
+    assert!(rule_results.is_empty());
+    assert_eq!(info.rules_evaluated, 0);
+    assert_eq!(info.skipped_disabled, 2);
+    assert_eq!(counting.read().unwrap().triggered.len(), 2);
+
+    // disabled group members
+    let mut engine = Engine::new();
+    let counting = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting.clone());
+    engine.add_rule_group(
+        serde_json::from_value(json!({
+            "id": "ages",
+            "rules": [{
+                "id": "member",
+                "conditions": {
+                    "field": "age",
+                    "operator": "int_greater_than",
+                    "value": 18
+                },
+                "events": [],
+                "enabled": false
+            }],
+            "events": [{ "type": "counting_event", "params": {} }]
+        }))
+        .unwrap(),
+    );
+    assert!(engine.run(&facts).await.unwrap().is_empty());
+    assert_eq!(counting.read().unwrap().triggered.len(), 0);
+    engine.set_rule_enabled("member", true);
+    assert_eq!(engine.run(&facts).await.unwrap().len(), 1);
+    assert_eq!(counting.read().unwrap().triggered.len(), 1);
+}
+
+#[tokio::test]
+async fn muted_events() {
+    use json_rules_engine::MuteScope;
+    use std::sync::Mutex;
+
+    let rules: Vec<Rule> = serde_json::from_value(json!([
+        {
+            "id": "deploy",
+            "conditions": { "field": "up", "operator": "bool_equals", "value": true },
+            "events": [{ "type": "counting_event", "params": {} }],
+            "tags": ["ops"]
+        },
+        {
+            "id": "billing",
+            "conditions": { "field": "up", "operator": "bool_equals", "value": true },
+            "events": [{ "type": "other_event", "params": {} }]
+        }
+    ]))
+    .unwrap();
+    assert_eq!(rules[0].tags, vec!["ops".to_string()]);
+    assert!(
+        Rule::from_value_strict(serde_json::to_value(&rules[0]).unwrap())
+            .is_ok()
+    );
+
+    let t0 = chrono::Utc::now();
+    let clock = Arc::new(Mutex::new(t0));
+    let mut engine = Engine::new();
+    let now = clock.clone();
+    engine.set_now_provider(Arc::new(move || *now.lock().unwrap()));
+    let counting = Arc::new(RwLock::new(CountingEvent::new()));
+    let other = Arc::new(RwLock::new(CountingEvent {
+        ty: "other_event".into(),
+        triggered: Vec::new(),
+    }));
+    engine.add_event(counting.clone());
+    engine.add_event(other.clone());
+    engine.load_rules(rules);
+    let facts = json!({ "up": true });
+
+    // which of the deploy and billing events each scope mutes
+    let scopes = vec![
+        (MuteScope::All, true, true),
+        (MuteScope::Tag("ops".into()), true, false),
+        (MuteScope::RuleId("billing".into()), false, true),
+        (MuteScope::EventType("other_event".into()), false, true),
+    ];
+    for (scope, deploy_muted, billing_muted) in scopes {
+        let until = t0 + chrono::Duration::minutes(30);
+        engine.mute(scope.clone(), until);
+        assert_eq!(engine.active_mutes().len(), 1);
+
+        let before = (
+            counting.read().unwrap().triggered.len(),
+            other.read().unwrap().triggered.len(),
+        );
+        let rule_results = engine.run(&facts).await.unwrap();
+        // the rules are still met
+        assert_eq!(rule_results.len(), 2);
+        for (rule_result, muted) in
+            rule_results.iter().zip([deploy_muted, billing_muted])
+        {
+            let event = serde_json::to_value(&rule_result.events[0]).unwrap();
+            if muted {
+                assert_eq!(event["muted"], json!(true));
+                assert_eq!(
+                    event["mute_reason"],
+                    json!(format!(
+                        "{} muted until {}",
+                        scope,
+                        until.to_rfc3339()
+                    ))
+                );
+            } else {
+                assert_eq!(event.get("muted"), None);
+            }
+        }
+        assert_eq!(
+            counting.read().unwrap().triggered.len(),
+            before.0 + !deploy_muted as usize
+        );
+        assert_eq!(
+            other.read().unwrap().triggered.len(),
+            before.1 + !billing_muted as usize
+        );
+
+        assert!(engine.unmute(&scope));
+        assert!(!engine.unmute(&scope));
+    }
+
+    // mutes end on their own
+    engine.mute(MuteScope::All, t0 + chrono::Duration::minutes(30));
+    engine.mute(
+        MuteScope::Tag("ops".into()),
+        t0 + chrono::Duration::hours(2),
+    );
+    *clock.lock().unwrap() = t0 + chrono::Duration::hours(1);
+    assert_eq!(engine.active_mutes()[0].scope, MuteScope::Tag("ops".into()));
+    assert_eq!(engine.active_mutes().len(), 1);
+    let muted = |rule_results: &[RuleResult]| -> Vec<bool> {
+        rule_results
+            .iter()
+            .map(|r| {
+                serde_json::to_value(&r.events[0]).unwrap()["muted"]
+                    == json!(true)
+            })
+            .collect()
+    };
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert_eq!(muted(&rule_results), vec![true, false]);
+
+    *clock.lock().unwrap() = t0 + chrono::Duration::hours(3);
+    assert!(engine.active_mutes().is_empty());
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert_eq!(muted(&rule_results), vec![false, false]);
+}
+
+#[tokio::test]
+async fn otel_attributes() {
+    use json_rules_engine::{AttrValue, MuteScope};
+
+    let rule: Rule = serde_json::from_value(json!({
+        "id": "fraud_check",
+        "conditions": {
+            "or": [
+                { "field": "amount", "operator": "int_greater_than", "value": 1000 },
+                { "field": "country", "operator": "string_in", "value": ["KP", "IR"] },
+                { "field": "flagged", "operator": "bool_equals", "value": true }
+            ]
+        },
+        "events": [
+            { "type": "counting_event", "params": {} },
+            { "type": "other_event", "params": {} }
+        ]
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_event(Arc::new(RwLock::new(CountingEvent::new())));
+    engine.add_event(Arc::new(RwLock::new(CountingEvent {
+        ty: "other_event".into(),
+        triggered: Vec::new(),
+    })));
+    engine.add_rule(rule);
+    engine.mute(
+        MuteScope::EventType("other_event".into()),
+        chrono::Utc::now() + chrono::Duration::hours(1),
+    );
+
+    let facts = json!({ "amount": 10, "country": "FR", "flagged": true });
+    let mut rule_results = engine.run(&facts).await.unwrap();
+    let rule_result = &mut rule_results[0];
+    rule_result.duration_micros = 12;
+
+    let s = |s: &str| AttrValue::Str(s.into());
+    let expected = vec![
+        ("rules.fraud_check.status", s("met")),
+        ("rules.fraud_check.conditions.total", AttrValue::I64(3)),
+        ("rules.fraud_check.conditions.met", AttrValue::I64(1)),
+        ("rules.fraud_check.conditions.failed", AttrValue::I64(2)),
+        ("rules.fraud_check.conditions.unknown", AttrValue::I64(0)),
+        ("rules.fraud_check.duration_micros", AttrValue::I64(12)),
+        ("rules.fraud_check.events.total", AttrValue::I64(2)),
+        ("rules.fraud_check.events.dispatched", AttrValue::I64(1)),
+        ("rules.fraud_check.events.suppressed", AttrValue::I64(1)),
+        ("rules.fraud_check.conditions.failed.0", s("amount")),
+        ("rules.fraud_check.conditions.failed.1", s("country")),
+        ("rules.fraud_check.events.0.type", s("counting_event")),
+        ("rules.fraud_check.events.1.type", s("other_event")),
+    ]
+    .into_iter()
+    .map(|(k, v)| (k.to_owned(), v))
+    .collect::<Vec<_>>();
+
+    assert_eq!(rule_result.to_otel_attributes("rules"), expected);
+    assert_eq!(
+        rule_result.to_otel_attributes_with("rules", 3),
+        expected[..3].to_vec()
+    );
+
+    rule_result.rule_id = None;
+    assert_eq!(
+        rule_result.to_otel_attributes("rules")[0].0,
+        "rules.unnamed.status"
+    );
+}
+
+#[tokio::test]
+async fn collect_matches() {
+    let rule: Rule = serde_json::from_value(json!({
+        "id": "untrusted",
+        "conditions": {
+            "and": [
+                {
+                    "field": "ports",
+                    "operator": "int_contains_any",
+                    "value": [22, 23, 3389]
+                },
+                {
+                    "field": "devices",
+                    "operator": "any_match",
+                    "value": {
+                        "field": "trusted",
+                        "operator": "bool_equals",
+                        "value": false
+                    }
+                }
+            ]
+        },
+        "events": [{
+            "type": "counting_event",
+            "params": {
+                "message": "Untrusted: {{#_matched_values.devices}}{{id}} {{/_matched_values.devices}}"
+            }
+        }]
+    }))
+    .unwrap();
+    let facts = json!({
+        "ports": [80, 22, 443, 3389, 22],
+        "devices": [
+            { "id": "laptop", "trusted": true },
+            { "id": "phone", "trusted": false },
+            { "id": "tv", "trusted": false }
+        ]
+    });
+
+    let mut engine = Engine::new();
+    let counting = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting.clone());
+    engine.add_rule(rule);
+
+    // off by default
+    let rule_results = engine.run(&facts).await.unwrap();
+    let leaves = &rule_results[0].condition_result.children;
+    assert!(leaves.iter().all(|leaf| leaf.matched_values.is_empty()));
+    assert_eq!(
+        counting.read().unwrap().triggered[0].get("_matched_values"),
+        None
+    );
+
+    engine.set_collect_matches(true);
+    #[cfg(feature = "broadcast")]
+    let mut rx = engine.subscribe();
+    let rule_results = engine.run(&facts).await.unwrap();
+    let leaves = &rule_results[0].condition_result.children;
+    assert_eq!(
+        leaves[0].matched_values,
+        vec![json!(22), json!(3389), json!(22)]
+    );
+    assert_eq!(
+        leaves[1].matched_values,
+        vec![
+            json!({ "id": "phone", "trusted": false }),
+            json!({ "id": "tv", "trusted": false })
+        ]
+    );
+    // exposed to the templates of the events, not to the events
+    #[cfg(feature = "broadcast")]
+    assert_eq!(
+        rx.try_recv().unwrap().event.params["message"],
+        "Untrusted: phone tv "
+    );
+    assert_eq!(counting.read().unwrap().triggered[1], facts);
+
+    engine.set_limits(json_rules_engine::Limits {
+        max_matched_values: Some(1),
+        ..Default::default()
+    });
+    let rule_results = engine.run(&facts).await.unwrap();
+    let leaves = &rule_results[0].condition_result.children;
+    assert_eq!(leaves[0].matched_values, vec![json!(22)]);
+    assert_eq!(
+        leaves[1].matched_values,
+        vec![json!({ "id": "phone", "trusted": false })]
+    );
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn matched_values_not_posted() {
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(ResponseTemplate::new(200))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "ports",
+            "operator": "int_contains_any",
+            "value": [22, 3389]
+        },
+        "events": [{
+            "type": "post_to_callback_url",
+            "params": {
+                "callback_url": format!("{}/hook", server.uri())
+            }
+        }]
+    }))
+    .unwrap();
+    let facts = json!({ "ports": [80, 22] });
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.set_collect_matches(true);
+    engine.run(&facts).await.unwrap();
+
+    // the body holds the facts as they are
+    let requests = server.received_requests().await.unwrap();
+    let body: Value = serde_json::from_slice(&requests[0].body).unwrap();
+    assert_eq!(body["facts"], facts);
+}
+
+#[test]
+fn engine_debug_and_summary() {
+    let rule: Rule = serde_json::from_value(json!({
+        "id": "adult",
+        "conditions": {
+            "and": [
+                {
+                    "field": "age",
+                    "operator": "int_greater_than_inclusive",
+                    "value": 18
+                },
+                {
+                    "not": {
+                        "field": "country",
+                        "operator": "string_in",
+                        "value": { "$var": "blocked" }
+                    }
+                }
+            ]
+        },
+        "events": [
+            {
+                "type": "counting_event",
+                "params": { "api_key": "SG.secret-api-key" }
+            },
+            { "type": "counting_event", "params": {} }
+        ]
+    }))
+    .unwrap();
+    assert_eq!(
+        rule.to_string(),
+        "rule adult: (age int_greater_than_inclusive 18 and not country \
+         string_in $blocked) -> counting_event, counting_event"
+    );
+    assert_eq!(
+        rule.conditions.to_string(),
+        "(age int_greater_than_inclusive 18 and not country string_in \
+         $blocked)"
+    );
+
+    let mut engine = Engine::new();
+    engine.add_event(Arc::new(RwLock::new(CountingEvent::new())));
+    engine.add_rule(rule);
+    engine.set_variable("blocked", json!(["SG.secret-api-key"]));
+
+    let debug = format!("{:?}", engine);
+    assert!(!debug.contains("SG.secret-api-key"), "{}", debug);
+    assert!(debug.contains("rule_ids: [Some(\"adult\")]"), "{}", debug);
+    assert!(debug.contains("variables: [\"blocked\"]"), "{}", debug);
+
+    let summary = engine.summary();
+    assert_eq!(summary.rule_count, 1);
+    assert_eq!(
+        summary.event_counts_by_type,
+        vec![("counting_event".to_string(), 2)]
+            .into_iter()
+            .collect()
+    );
+    assert_eq!(
+        summary.features_enabled.contains(&"eval"),
+        cfg!(feature = "eval")
+    );
+}
+
+#[test]
+fn sampled_rules() {
+    use json_rules_engine::sample_bucket;
+
+    // the buckets are part of the format, see the `sampling` module
+    assert_eq!(sample_bucket(&json!("user-42")), 74.19);
+    assert_eq!(sample_bucket(&json!(42)), 86.91);
+
+    let rule = |percent: f64, include_missing: bool| -> Rule {
+        serde_json::from_value(json!({
+            "id": "new_rule",
+            "conditions": {
+                "field": "amount",
+                "operator": "int_greater_than",
+                "value": 100
+            },
+            "events": [],
+            "sample": {
+                "percent": percent,
+                "key_field": "user/id",
+                "include_missing": include_missing
+            }
+        }))
+        .unwrap()
+    };
+    let mut engine = Engine::new();
+    engine.add_rule(rule(10.0, false));
+    let applies = |engine: &Engine, user: Value| {
+        let facts = json!({ "amount": 500, "user": { "id": user } });
+        !engine.evaluate(&facts).unwrap().is_empty()
+    };
+
+    // the same key always gets the same decision
+    for i in 0..100 {
+        let user = json!(format!("user-{}", i));
+        let applied = applies(&engine, user.clone());
+        assert_eq!(applies(&engine, user.clone()), applied);
+        assert_eq!(applied, sample_bucket(&user) < 10.0);
+    }
+
+    // roughly the share of the keys
+    let applied = (0..10_000)
+        .filter(|i| applies(&engine, json!(format!("user-{}", i))))
+        .count();
+    assert!((850..1150).contains(&applied), "{}", applied);
+
+    // raising the share keeps the keys the rule applied to
+    let mut wider = Engine::new();
+    wider.add_rule(rule(20.0, false));
+    for i in 0..1000 {
+        let user = json!(format!("user-{}", i));
+        if applies(&engine, user.clone()) {
+            assert!(applies(&wider, user));
+        }
+    }
+
+    // facts without the key
+    let facts = json!({ "amount": 500 });
+    assert!(engine.evaluate(&facts).unwrap().is_empty());
+    let mut included = Engine::new();
+    included.add_rule(rule(10.0, true));
+    assert_eq!(included.evaluate(&facts).unwrap().len(), 1);
+    assert_eq!(
+        included
+            .evaluate(&json!({ "amount": 500, "user": { "id": null } }))
+            .unwrap()
+            .len(),
+        1
+    );
+
+    let mut all = Engine::new();
+    all.add_rule(rule(100.0, false));
+    assert!(applies(&all, json!("anyone")));
+    let mut none = Engine::new();
+    none.add_rule(rule(0.0, true));
+    assert!(!applies(&none, json!("anyone")));
+    assert_eq!(none.evaluate(&facts).unwrap().len(), 1);
+}
+
+#[test]
+fn catalog() {
+    use json_rules_engine::catalog;
+
+    let operators = catalog::operators();
+    let names: Vec<_> = operators
+        .iter()
+        .map(|info| info.operator.as_str())
+        .collect();
+    assert_eq!(names, json_rules_engine::Constraint::operators());
+
+    let contains =
+        &operators[names.iter().position(|n| *n == "int_contains").unwrap()];
+    assert_eq!(contains.value, "integer");
+    assert_eq!(contains.fact_types, vec!["array"]);
+    assert!(contains.supports_arrays);
+    assert!(operators.iter().all(|info| !info.description.is_empty()));
+
+    let keys: Vec<_> = catalog::combinators().iter().map(|c| c.key).collect();
+    assert_eq!(keys, vec!["and", "or", "not", "conditions", "of"]);
+
+    let event_types: Vec<_> =
+        catalog::event_types().iter().map(|e| e.ty).collect();
+    assert_eq!(
+        event_types.contains(&"email_notification"),
+        cfg!(feature = "email")
+    );
+    assert_eq!(
+        event_types.contains(&"post_to_callback_url"),
+        cfg!(feature = "callback")
+    );
+}
+
+#[test]
+fn evaluation_digest() {
+    let rules = |min_age: i64| -> Vec<Rule> {
+        serde_json::from_value(json!([
+            {
+                "id": "adult",
+                "conditions": {
+                    "and": [
+                        {
+                            "field": "name",
+                            "operator": "string_equals",
+                            "value": "Cheng JIANG"
+                        },
+                        {
+                            "field": "age",
+                            "operator": "int_greater_than_inclusive",
+                            "value": min_age
+                        }
+                    ]
+                },
+                "events": [
+                    {
+                        "type": "post_to_callback_url",
+                        "params": {
+                            "callback_url": "http://example.com/{{ city }}",
+                            "a": 1,
+                            "b": 2,
+                            "c": 3,
+                            "d": { "e": 4, "f": 5 }
+                        }
+                    }
+                ]
+            },
+            {
+                "conditions": {
+                    "field": "country",
+                    "operator": "string_in",
+                    "value": ["FR", "CN"]
+                },
+                "events": []
+            }
+        ]))
+        .unwrap()
+    };
+    let digest = |rules: Vec<Rule>, facts: &Value| {
+        let mut engine = Engine::new();
+        engine.add_rules(rules);
+        engine.evaluation_digest(facts).unwrap()
+    };
+
+    let facts = json!({ "name": "Cheng JIANG", "age": 24 });
+    let expected = digest(rules(18), &facts);
+    assert_eq!(expected.len(), 64);
+    for _ in 0..10 {
+        assert_eq!(digest(rules(18), &facts), expected);
+    }
+
+    // a changed constraint changes the digest once it changes a status
+    assert_eq!(digest(rules(24), &facts), expected);
+    assert_ne!(digest(rules(25), &facts), expected);
+    // the params are rendered against the facts
+    let moved = json!({ "name": "Cheng JIANG", "age": 24, "city": "Paris" });
+    assert_ne!(digest(rules(18), &moved), expected);
+
+    let rule = &rules(18)[0];
+    let hash = rule
+        .check_value(
+            &facts,
+            #[cfg(feature = "eval")]
+            &rhai::Engine::new(),
+        )
+        .canonical_hash();
+    std::thread::sleep(Duration::from_millis(2));
+    let mut rule_result = rule.check_value(
+        &facts,
+        #[cfg(feature = "eval")]
+        &rhai::Engine::new(),
+    );
+    rule_result.evaluated_at += 1000;
+    rule_result.duration_micros += 1000;
+    assert_eq!(rule_result.canonical_hash(), hash);
+}
+
+#[cfg(any(feature = "discord", feature = "teams"))]
+async fn webhook_body(event: Value, status: u16) -> (Result<(), Error>, Value) {
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/webhook"))
+        .respond_with(ResponseTemplate::new(status))
+        .expect(1)
+        .mount(&server)
+        .await;
+
+    let mut event = event;
+    event["params"]["webhook_url"] =
+        Value::String(format!("{}/webhook", server.uri()));
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [event]
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+
+    let res = engine
+        .run(&json!({ "name": "Cheng JIANG", "age": 24, "team": "R&D <core>" }))
+        .await
+        .map(|_| ());
+
+    let requests = server.received_requests().await.unwrap();
+    (res, serde_json::from_slice(&requests[0].body).unwrap())
+}
+
+#[cfg(feature = "discord")]
+#[tokio::test]
+async fn discord_notification_event() {
+    let event = json!({
+        "type": "discord_notification",
+        "params": {
+            "title": "{{ name }} is coding",
+            "message": "Age: {{ age }}",
+            "embed_color": 5814783
+        }
+    });
+
+    let (res, body) = webhook_body(event.clone(), 204).await;
+    assert!(res.is_ok());
+    assert_eq!(
+        body,
+        json!({
+            "embeds": [
+                {
+                    "title": "Cheng JIANG is coding",
+                    "description": "Age: 24",
+                    "color": 5814783
+                }
+            ]
+        })
+    );
+
+    let (res, _) = webhook_body(event, 400).await;
+    assert!(matches!(
+        res,
+        Err(Error::EventDispatch { source, .. })
+            if matches!(*source, Error::ReqwestError(_))
+    ));
+}
+
+#[cfg(feature = "teams")]
+#[tokio::test]
+async fn teams_notification_event() {
+    let event = json!({
+        "type": "teams_notification",
+        "params": {
+            "title": "{{ name }} is coding",
+            "message": "Age: {{ age }}",
+            "theme_color": "0076D7"
+        }
+    });
+
+    let (res, body) = webhook_body(event.clone(), 200).await;
+    assert!(res.is_ok());
+    assert_eq!(
+        body,
+        json!({
+            "@type": "MessageCard",
+            "@context": "https://schema.org/extensions",
+            "summary": "Cheng JIANG is coding",
+            "title": "Cheng JIANG is coding",
+            "text": "Age: 24",
+            "themeColor": "0076D7"
+        })
+    );
+
+    let (res, _) = webhook_body(event, 500).await;
+    assert!(matches!(
+        res,
+        Err(Error::EventDispatch { source, .. })
+            if matches!(*source, Error::ReqwestError(_))
+    ));
+}
+
+#[cfg(any(feature = "discord", feature = "teams"))]
+#[tokio::test]
+async fn webhook_escape_modes() {
+    let message = |ty: &str, escape_mode: Option<&str>| {
+        let mut event = json!({
+            "type": ty,
+            "params": {
+                "title": "{{ name }}",
+                "message": "{{ team }} / {{{ team }}}"
+            }
+        });
+        if let Some(escape_mode) = escape_mode {
+            event["params"]["escape_mode"] = json!(escape_mode);
+        }
+        event
+    };
+    let escaped = "R&amp;D &lt;core&gt; / R&D <core>";
+    let raw = "R&D <core> / R&D <core>";
+
+    #[cfg(feature = "discord")]
+    for (escape_mode, expected) in &[
+        (None, escaped),
+        (Some("html"), escaped),
+        (Some("none"), raw),
+    ] {
+        let (res, body) =
+            webhook_body(message("discord_notification", *escape_mode), 204)
+                .await;
+        assert!(res.is_ok());
+        assert_eq!(body["embeds"][0]["description"], *expected);
+    }
+
+    #[cfg(feature = "teams")]
+    for (escape_mode, expected) in &[
+        (None, escaped),
+        (Some("html"), escaped),
+        (Some("none"), raw),
+    ] {
+        let (res, body) =
+            webhook_body(message("teams_notification", *escape_mode), 200)
+                .await;
+        assert!(res.is_ok());
+        assert_eq!(body["text"], *expected);
+    }
+}
+
+#[tokio::test]
+async fn coalescence_snapshot() {
+    let path = std::env::temp_dir()
+        .join(format!("coalescence-{}.json", std::process::id()));
+    let t0 = chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+        .unwrap()
+        .with_timezone(&chrono::Utc);
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [
+            {
+                "type": "counting_event",
+                "params": {},
+                "coalescence": 60,
+                "coalescence_group": "{{ name }}"
+            }
+        ]
+    }))
+    .unwrap();
+    let facts = json!({ "name": "Cheng JIANG" });
+
+    // runs once on an engine restored after `elapsed` seconds, returning
+    // how many times the event fired
+    let run_after = |elapsed: i64| {
+        let rule = rule.clone();
+        let path = path.clone();
+        let facts = facts.clone();
+        async move {
+            let mut engine = Engine::new();
+            engine.add_rule(rule);
+            engine.set_now_provider(Arc::new(move || {
+                t0 + chrono::Duration::seconds(elapsed)
+            }));
+            let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+            engine.add_event(counting_event.clone());
+
+            engine.restore_coalescence(&path).unwrap();
+            engine.run(&facts).await.unwrap();
+
+            let triggered = counting_event.read().unwrap().triggered.len();
+            triggered
+        }
+    };
+
+    // a missing snapshot is a no-op
+    let _ = std::fs::remove_file(&path);
+    assert_eq!(run_after(0).await, 1);
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule.clone());
+    engine.set_now_provider(Arc::new(move || t0));
+    engine.add_event(Arc::new(RwLock::new(CountingEvent::new())));
+    engine.run(&facts).await.unwrap();
+    engine.save_coalescence(&path).unwrap();
+
+    let snapshot: Value =
+        serde_json::from_slice(&std::fs::read(&path).unwrap()).unwrap();
+    assert_eq!(snapshot["groups"], json!({ "Cheng JIANG": 60 }));
+
+    assert_eq!(run_after(30).await, 0);
+    assert_eq!(run_after(59).await, 0);
+    assert_eq!(run_after(60).await, 1);
+    assert_eq!(run_after(3600).await, 1);
+
+    std::fs::write(&path, "{ not json").unwrap();
+    assert!(matches!(
+        Engine::new().restore_coalescence(&path),
+        Err(Error::SerializeJsonError(_))
+    ));
+
+    std::fs::remove_file(&path).unwrap();
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn callback_url_policy() {
+    use json_rules_engine::CallbackUrlPolicy;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let address = server.address().to_string();
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "host",
+            "operator": "string_not_equals",
+            "value": ""
+        },
+        "events": [
+            {
+                "type": "post_to_callback_url",
+                "params": {
+                    "callback_url": "http://{{ host }}/hook"
+                }
+            }
+        ]
+    }))
+    .unwrap();
+
+    // returns the error recorded on the event, if any
+    let run = |policy: CallbackUrlPolicy, host: &str| {
+        let rule = rule.clone();
+        let facts = json!({ "host": host });
+        async move {
+            let mut engine = Engine::new();
+            engine.add_rule(rule);
+            engine.set_callback_url_policy(policy);
+
+            let rule_results = engine.run(&facts).await.unwrap();
+            let event =
+                serde_json::to_value(&rule_results[0].events[0]).unwrap();
+            event["error"].as_str().map(ToOwned::to_owned)
+        }
+    };
+
+    let allowlist = CallbackUrlPolicy {
+        allowed_hosts: vec!["127.0.0.1".to_string()],
+        ..Default::default()
+    };
+    assert_eq!(run(allowlist.clone(), &address).await, None);
+    assert_eq!(
+        run(allowlist, "metadata.internal").await.unwrap(),
+        "Callback url `http://metadata.internal/hook` isn't allowed"
+    );
+
+    let prefixes = CallbackUrlPolicy {
+        allowed_prefixes: vec![format!("http://{}/", address)],
+        ..Default::default()
+    };
+    assert_eq!(run(prefixes, &address).await, None);
+
+    let https_only = CallbackUrlPolicy {
+        https_only: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        run(https_only, &address).await.unwrap(),
+        format!("Callback url `http://{}/hook` isn't https", address)
+    );
+
+    let public_only = CallbackUrlPolicy {
+        deny_private_ips: true,
+        ..Default::default()
+    };
+    assert_eq!(
+        run(public_only.clone(), "169.254.169.254").await.unwrap(),
+        "Callback url `http://169.254.169.254/hook` resolves to the private \
+         address 169.254.169.254"
+    );
+    assert!(run(public_only.clone(), &address).await.is_some());
+    assert!(run(public_only, "[::1]").await.is_some());
+
+    // only the allowed urls were posted to
+    assert_eq!(server.received_requests().await.unwrap().len(), 2);
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn callback_url_policy_redirects() {
+    use json_rules_engine::CallbackUrlPolicy;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    let address = server.address().to_string();
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(
+            ResponseTemplate::new(302).insert_header(
+                "Location",
+                format!("http://{}/landing", address),
+            ),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(path("/landing"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "host",
+            "operator": "string_not_equals",
+            "value": ""
+        },
+        "events": [
+            {
+                "type": "post_to_callback_url",
+                "params": {
+                    "callback_url": "http://{{ host }}/hook"
+                }
+            }
+        ]
+    }))
+    .unwrap();
+    let facts = json!({ "host": address });
+
+    let landed = || async {
+        server
+            .received_requests()
+            .await
+            .unwrap()
+            .iter()
+            .filter(|request| request.url.path() == "/landing")
+            .count()
+    };
+
+    // followed without a policy
+    let mut engine = Engine::new();
+    engine.add_rule(rule.clone());
+    let rule_results = engine.run(&facts).await.unwrap();
+    let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+    assert_eq!(event["error"], Value::Null);
+    assert_eq!(landed().await, 1);
+
+    // not under one, the redirect failing the event
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.set_callback_url_policy(CallbackUrlPolicy {
+        allowed_hosts: vec!["127.0.0.1".to_string()],
+        ..Default::default()
+    });
+    let error = engine.run(&facts).await.unwrap_err().to_string();
+    assert!(error.contains("responded with status 302"));
+    assert_eq!(landed().await, 1);
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn custom_transport() {
+    use json_rules_engine::{
+        EventTransport, OutboundBody, OutboundRequest, TransportResponse,
+    };
+    use std::sync::Mutex;
+
+    struct Recording {
+        status: Option<u16>,
+        requests: Mutex<Vec<OutboundRequest>>,
+    }
+
+    #[async_trait]
+    impl EventTransport for Recording {
+        async fn send(
+            &self,
+            request: OutboundRequest,
+        ) -> Result<TransportResponse, Error> {
+            self.requests.lock().unwrap().push(request);
+            match self.status {
+                Some(status) => Ok(TransportResponse {
+                    status,
+                    body: String::new(),
+                }),
+                None => Err(Error::EventError("proxy unreachable".into())),
+            }
+        }
+    }
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [
+            {
+                "type": "post_to_callback_url",
+                "params": {
+                    "callback_url": "https://example.com/{{ name }}",
+                    "payload_version": "2"
+                }
+            },
+            {
+                "type": "post_to_callback_url",
+                "params": {
+                    "callback_url": "https://example.com/form",
+                    "content_type": "form",
+                    "form_facts": ["age"]
+                }
+            }
+        ]
+    }))
+    .unwrap();
+    let facts = json!({ "name": "Cheng JIANG", "age": 27 });
+    let engine_with = |status| {
+        let transport = Arc::new(Recording {
+            status,
+            requests: Mutex::new(Vec::new()),
+        });
+        let mut engine = Engine::new().with_transport(transport.clone());
+        engine.add_rule(rule.clone());
+        (engine, transport)
+    };
+
+    let (mut engine, transport) = engine_with(Some(204));
+    engine.run(&facts).await.unwrap();
+    let requests = transport.requests.lock().unwrap().clone();
+    assert_eq!(requests.len(), 2);
+    assert_eq!(requests[0].method, "POST");
+    assert_eq!(requests[0].url, "https://example.com/Cheng JIANG");
+    let idempotency_key = requests[0].headers[1].1.clone();
+    assert_eq!(
+        requests[0].headers,
+        vec![
+            ("Content-Type".to_string(), "application/json".to_string()),
+            ("Idempotency-Key".to_string(), idempotency_key),
+            ("X-Payload-Version".to_string(), "2".to_string()),
+        ]
+    );
+    match &requests[0].body {
+        OutboundBody::Json(body) => {
+            assert_eq!(body["version"], "2");
+            assert_eq!(body["facts"], facts);
+        }
+        body => panic!("unexpected body {:?}", body),
+    }
+    assert_eq!(
+        requests[1].headers[0].1,
+        "application/x-www-form-urlencoded"
+    );
+    match &requests[1].body {
+        OutboundBody::Form(pairs) => assert_eq!(
+            pairs.last().unwrap(),
+            &("facts.age".to_string(), "27".to_string())
+        ),
+        body => panic!("unexpected body {:?}", body),
+    }
+
+    // the errors of the transport are returned as is
+    let (mut engine, transport) = engine_with(None);
+    assert!(matches!(
+        engine.run(&facts).await,
+        Err(Error::EventDispatch { source, .. })
+            if source.to_string().contains("proxy unreachable")
+    ));
+    assert_eq!(transport.requests.lock().unwrap().len(), 1);
+
+    // as is a status other than a success
+    let (mut engine, _) = engine_with(Some(503));
+    engine.set_error_mode(json_rules_engine::ErrorMode::BestEffort);
+    let results = engine.run(&facts).await.unwrap();
+    let result = serde_json::to_value(&results[0]).unwrap();
+    assert!(result["events"][0]["error"]
+        .as_str()
+        .unwrap()
+        .contains("status 503"));
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn idempotency_keys() {
+    use json_rules_engine::{
+        ErrorMode, EventTransport, OutboundBody, OutboundRequest,
+        TransportResponse,
+    };
+    use std::sync::Mutex;
+
+    /// Answers 503 to the first requests, then 200
+    struct Flaky {
+        failures: Mutex<usize>,
+        requests: Mutex<Vec<OutboundRequest>>,
+    }
+
+    #[async_trait]
+    impl EventTransport for Flaky {
+        async fn send(
+            &self,
+            request: OutboundRequest,
+        ) -> Result<TransportResponse, Error> {
+            self.requests.lock().unwrap().push(request);
+            let mut failures = self.failures.lock().unwrap();
+            let status = match *failures {
+                0 => 200,
+                _ => 503,
+            };
+            *failures = failures.saturating_sub(1);
+            Ok(TransportResponse {
+                status,
+                body: String::new(),
+            })
+        }
+    }
+
+    let facts = json!({ "order_id": 42 });
+    // runs a rule with the event once, its first sends failing, returning
+    // the idempotency key of each attempt
+    let run = |params: Value, failures: usize| {
+        let facts = facts.clone();
+        async move {
+            let transport = Arc::new(Flaky {
+                failures: Mutex::new(failures),
+                requests: Mutex::new(Vec::new()),
+            });
+            let mut engine = Engine::new().with_transport(transport.clone());
+            engine.set_error_mode(ErrorMode::BestEffort);
+            let mut params = params;
+            params["callback_url"] = json!("https://example.com/orders");
+            params["payload_version"] = json!("2");
+            engine.add_rule(
+                serde_json::from_value(json!({
+                    "id": "order_paid",
+                    "conditions": { "and": [] },
+                    "events": [
+                        { "type": "post_to_callback_url", "params": params }
+                    ]
+                }))
+                .unwrap(),
+            );
+            let rule_results = engine.run(&facts).await.unwrap();
+
+            let requests = transport.requests.lock().unwrap().clone();
+            let keys: Vec<String> = requests
+                .iter()
+                .map(|request| {
+                    let header = request
+                        .headers
+                        .iter()
+                        .find(|(name, _)| name == "Idempotency-Key")
+                        .unwrap()
+                        .1
+                        .clone();
+                    match &request.body {
+                        OutboundBody::Json(body) => {
+                            assert_eq!(body["idempotency_key"], header)
+                        }
+                        body => panic!("unexpected body {:?}", body),
+                    }
+                    header
+                })
+                .collect();
+            (keys, rule_results, engine.take_dead_letters())
+        }
+    };
+    let is_uuid = |key: &str| uuid::Uuid::parse_str(key).is_ok();
+
+    // a key per attempt by default
+    let (keys, _, _) = run(json!({ "retries": 2 }), 2).await;
+    assert_eq!(keys.len(), 3);
+    assert!(keys.iter().all(|key| is_uuid(key)));
+    assert_eq!(keys.iter().collect::<HashSet<_>>().len(), 3);
+
+    // one for all of them when stable, the event holding it
+    let stable = json!({ "retries": 2, "stable_across_retries": true });
+    let (keys, rule_results, _) = run(stable.clone(), 2).await;
+    assert_eq!(keys.len(), 3);
+    assert!(is_uuid(&keys[0]));
+    assert!(keys.iter().all(|key| *key == keys[0]));
+    let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+    assert_eq!(event["params"]["idempotency_key"], keys[0]);
+
+    // the dead letter of an event failing every attempt keeps it, for it
+    // to be replayed with the same key
+    let (keys, _, dead_letters) = run(stable, 3).await;
+    assert_eq!(keys.len(), 3);
+    assert!(keys.iter().all(|key| *key == keys[0]));
+    assert_eq!(dead_letters.len(), 1);
+    assert_eq!(dead_letters[0].params["idempotency_key"], keys[0]);
+
+    // templated over the facts, the rule id and the run's nonce
+    let (keys, _, _) = run(
+        json!({
+            "retries": 1,
+            "idempotency_key": "{{ _rule_id }}-{{ order_id }}-{{ _run_nonce }}"
+        }),
+        1,
+    )
+    .await;
+    assert_eq!(keys.len(), 2);
+    assert_eq!(keys[0], keys[1]);
+    let nonce = keys[0].strip_prefix("order_paid-42-").unwrap();
+    assert!(is_uuid(nonce), "{}", nonce);
+
+    // no retries unless asked for
+    let (keys, _, dead_letters) = run(json!({}), 1).await;
+    assert_eq!(keys.len(), 1);
+    assert_eq!(dead_letters.len(), 1);
+
+    let (keys, rule_results, _) = run(json!({ "retries": 11 }), 0).await;
+    assert!(keys.is_empty());
+    let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+    assert!(event["error"]
+        .as_str()
+        .unwrap()
+        .contains("'retries' must be an integer from 0 to 10."));
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn dead_letters() {
+    use chrono::{TimeZone, Utc};
+    use json_rules_engine::{DeadLetter, ErrorMode};
+    use std::sync::Mutex;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/fail"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/ok"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let rule: Rule = serde_json::from_value(json!({
+        "id": "vip_signup",
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [
+            {
+                "type": "post_to_callback_url",
+                "params": {
+                    "callback_url": format!("{}/fail", server.uri()),
+                    "message": "Welcome {{ name }}"
+                }
+            },
+            {
+                "type": "post_to_callback_url",
+                "params": { "callback_url": format!("{}/ok", server.uri()) }
+            }
+        ]
+    }))
+    .unwrap();
+    let facts = json!({ "name": "Cheng JIANG" });
+    let now = Utc.with_ymd_and_hms(2024, 5, 1, 12, 0, 0).unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.set_now_provider(Arc::new(move || now));
+    engine.set_error_mode(ErrorMode::BestEffort);
+    engine.run(&facts).await.unwrap();
+
+    // only the failed event is kept, rendered
+    let dead_letters = engine.take_dead_letters();
+    assert_eq!(dead_letters.len(), 1);
+    let DeadLetter {
+        rule_id,
+        event_type,
+        params,
+        target,
+        error,
+        attempts,
+        timestamp,
+    } = &dead_letters[0];
+    assert_eq!(rule_id.as_deref(), Some("vip_signup"));
+    assert_eq!(event_type, "post_to_callback_url");
+    assert_eq!(params["message"], "Welcome Cheng JIANG");
+    assert_eq!(
+        target.as_ref(),
+        Some(&json!(format!("{}/fail", server.uri())))
+    );
+    assert!(error.contains("503"), "{}", error);
+    assert_eq!(*attempts, 1);
+    assert_eq!(*timestamp, now);
+    assert!(engine.take_dead_letters().is_empty());
+
+    // failing fast as well
+    engine.set_error_mode(ErrorMode::FailFast);
+    assert!(engine.run(&facts).await.is_err());
+    assert_eq!(engine.take_dead_letters().len(), 1);
+
+    // handed to the sink rather than kept
+    let sunk = Arc::new(Mutex::new(Vec::new()));
+    let sink = sunk.clone();
+    engine.set_dead_letter_sink(Arc::new(move |dead_letter| {
+        sink.lock().unwrap().push(dead_letter)
+    }));
+    assert!(engine.run(&facts).await.is_err());
+    assert!(engine.take_dead_letters().is_empty());
+    assert_eq!(*sunk.lock().unwrap(), dead_letters);
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn coalescence_after_dispatch() {
+    use json_rules_engine::ErrorMode;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    // the first callback hangs, the next ones answer right away
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(
+            ResponseTemplate::new(200).set_delay(Duration::from_secs(60)),
+        )
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/fail"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&server)
+        .await;
+
+    let rule = |callback_path: &str| -> Rule {
+        serde_json::from_value(json!({
+            "conditions": {
+                "field": "name",
+                "operator": "string_equals",
+                "value": "Cheng JIANG"
+            },
+            "events": [
+                {
+                    "type": "post_to_callback_url",
+                    "params": {
+                        "callback_url":
+                            format!("{}{}", server.uri(), callback_path)
+                    },
+                    "coalescence": 60,
+                    "coalescence_group": "{{ name }}"
+                }
+            ]
+        }))
+        .unwrap()
+    };
+    let facts = json!({ "name": "Cheng JIANG" });
+    let received = |callback_path: &'static str| {
+        let server = &server;
+        async move {
+            server
+                .received_requests()
+                .await
+                .unwrap()
+                .iter()
+                .filter(|request| request.url.path() == callback_path)
+                .count()
+        }
+    };
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule("/hook"));
+
+    // the run is dropped while the callback is in flight
+    let run = engine.run(&facts);
+    assert!(tokio::time::timeout(Duration::from_millis(500), run)
+        .await
+        .is_err());
+    assert_eq!(received("/hook").await, 1);
+
+    // the group wasn't recorded, the next run sends the event
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert_eq!(rule_results[0].events.len(), 1);
+    assert_eq!(received("/hook").await, 2);
+
+    // which coalesces the ones after it
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert!(rule_results[0].events.is_empty());
+    assert_eq!(received("/hook").await, 2);
+
+    // a failed event doesn't suppress the next one
+    let mut engine = Engine::new();
+    engine.add_rule(rule("/fail"));
+    engine.set_error_mode(ErrorMode::BestEffort);
+    engine.run(&facts).await.unwrap();
+    engine.run(&facts).await.unwrap();
+    assert_eq!(received("/fail").await, 2);
+}
+
+#[tokio::test]
+async fn coalescence_after_held_back_events() {
+    use json_rules_engine::MuteScope;
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [
+            {
+                "type": "counting_event",
+                "params": {},
+                "coalescence": 60,
+                "coalescence_group": "{{ name }}"
+            }
+        ]
+    }))
+    .unwrap();
+    let facts = json!({ "name": "Cheng JIANG" });
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule.clone());
+    let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting_event.clone());
+    let triggered = || counting_event.read().unwrap().triggered.len();
+
+    // a muted event doesn't claim its group
+    let scope = MuteScope::EventType("counting_event".to_string());
+    engine.mute(
+        scope.clone(),
+        chrono::Utc::now() + chrono::Duration::hours(1),
+    );
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert_eq!(
+        serde_json::to_value(&rule_results[0].events[0]).unwrap()["muted"],
+        true
+    );
+    assert_eq!(triggered(), 0);
+
+    engine.unmute(&scope);
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert_eq!(rule_results[0].events.len(), 1);
+    assert_eq!(triggered(), 1);
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert!(rule_results[0].events.is_empty());
+    assert_eq!(triggered(), 1);
+
+    // nor does a rate limited one, dispatched one event after the other
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.set_sequential_event_dispatch(true);
+    engine.add_event(counting_event.clone());
+    engine.set_rate_limit("counting_event", 0, Duration::from_secs(60));
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert_eq!(
+        serde_json::to_value(&rule_results[0].events[0]).unwrap()
+            ["rate_limited"],
+        true
+    );
+    assert_eq!(triggered(), 1);
+
+    engine.set_rate_limit("counting_event", 1, Duration::from_secs(60));
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert_eq!(rule_results[0].events.len(), 1);
+    assert_eq!(triggered(), 2);
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert!(rule_results[0].events.is_empty());
+    assert_eq!(triggered(), 2);
+}
+
+#[cfg(all(feature = "callback", feature = "detached"))]
+#[tokio::test(flavor = "multi_thread")]
+async fn run_detached() {
+    use json_rules_engine::{DeadLetter, EventOutcome};
+    use std::sync::Mutex;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/slow"))
+        .respond_with(
+            ResponseTemplate::new(200).set_delay(Duration::from_millis(300)),
+        )
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/fail"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&server)
+        .await;
+
+    let callback = |callback_path: &str, dispatch_order: u32| {
+        json!({
+            "type": "post_to_callback_url",
+            "params": {
+                "callback_url": format!("{}{}", server.uri(), callback_path)
+            },
+            "dispatch_order": dispatch_order
+        })
+    };
+    let rule: Rule = serde_json::from_value(json!({
+        "id": "welcome",
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [callback("/slow", 1)]
+    }))
+    .unwrap();
+    let facts = json!({ "name": "Cheng JIANG" });
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+
+    // the caller doesn't wait for the callback, which lands all the same
+    let (rule_results, task) = engine.run_detached(&facts).await.unwrap();
+    assert_eq!(rule_results.len(), 1);
+    drop(task);
+    let mut attempts = 0;
+    while server.received_requests().await.unwrap().is_empty() {
+        attempts += 1;
+        assert!(attempts < 100, "the callback never landed");
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+
+    // awaited, the task reports what became of the events
+    let (_, task) = engine.run_detached(&facts).await.unwrap();
+    let outcomes = task.await.unwrap();
+    assert_eq!(outcomes.len(), 1);
+    let EventOutcome {
+        rule_id,
+        event,
+        error,
+    } = &outcomes[0];
+    assert_eq!(rule_id.as_deref(), Some("welcome"));
+    assert_eq!(event.ty, "post_to_callback_url");
+    assert_eq!(*error, None);
+
+    // failed triggers go to the sink, and abort their sequence if asked to
+    let rule: Rule = serde_json::from_value(json!({
+        "id": "sequence",
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [callback("/slow", 2), callback("/fail", 1)]
+    }))
+    .unwrap();
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.set_abort_sequence_on_error(true);
+    let sunk = Arc::new(Mutex::new(Vec::<DeadLetter>::new()));
+    let sink = sunk.clone();
+    engine.set_dead_letter_sink(Arc::new(move |dead_letter| {
+        sink.lock().unwrap().push(dead_letter)
+    }));
+
+    let (_, task) = engine.run_detached(&facts).await.unwrap();
+    let outcomes = task.await.unwrap();
+    let errors: Vec<_> = outcomes
+        .iter()
+        .map(|outcome| outcome.error.as_deref().unwrap_or_default())
+        .collect();
+    assert!(errors[0].contains("503"), "{}", errors[0]);
+    assert_eq!(
+        errors[1],
+        "Skipped after `post_to_callback_url` failed before it"
+    );
+    let sunk = sunk.lock().unwrap();
+    assert_eq!(sunk.len(), 1);
+    assert_eq!(sunk[0].rule_id.as_deref(), Some("sequence"));
+    assert!(engine.take_dead_letters().is_empty());
+}
+
+#[cfg(all(feature = "callback", feature = "detached"))]
+#[tokio::test(flavor = "multi_thread")]
+async fn run_detached_coalescence() {
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    // the first callback fails, the next ones go through
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(ResponseTemplate::new(503))
+        .up_to_n_times(1)
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/hook"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [
+            {
+                "type": "post_to_callback_url",
+                "params": {
+                    "callback_url": format!("{}/hook", server.uri())
+                },
+                "coalescence": 60,
+                "coalescence_group": "{{ name }}"
+            }
+        ]
+    }))
+    .unwrap();
+    let facts = json!({ "name": "Cheng JIANG" });
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+
+    // the failed trigger doesn't claim the group
+    let (rule_results, task) = engine.run_detached(&facts).await.unwrap();
+    assert_eq!(rule_results[0].events.len(), 1);
+    assert!(task.await.unwrap()[0].error.is_some());
+
+    let (rule_results, task) = engine.run_detached(&facts).await.unwrap();
+    assert_eq!(rule_results[0].events.len(), 1);
+    assert_eq!(task.await.unwrap()[0].error, None);
+
+    // the one sent does
+    let (rule_results, task) = engine.run_detached(&facts).await.unwrap();
+    assert!(rule_results[0].events.is_empty());
+    assert!(task.await.unwrap().is_empty());
+}
+
+#[cfg(feature = "detached")]
+#[test]
+fn run_detached_shares_handlers() {
+    let (tx, rx) = std::sync::mpsc::channel();
+    // the thread driving the runtime, whose timer the task needs, mustn't
+    // be blocked on the lock of a handler the task is triggering
+    std::thread::spawn(move || {
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap();
+        let triggered = runtime.block_on(async {
+            let rule: Rule = serde_json::from_value(json!({
+                "conditions": {
+                    "field": "cpu",
+                    "operator": "int_greater_than",
+                    "value": 90
+                },
+                "events": [{ "type": "slow", "params": {} }]
+            }))
+            .unwrap();
+            let mut engine = Engine::new();
+            engine.add_rule(rule);
+            let slow = Arc::new(RwLock::new(SlowEvent::new()));
+            engine.add_event(slow.clone());
+            let facts = json!({ "cpu": 95 });
+
+            let (_, task) = engine.run_detached(&facts).await.unwrap();
+            tokio::time::sleep(Duration::from_millis(20)).await;
+            engine.run(&facts).await.unwrap();
+            task.await.unwrap();
+            let spans = slow.read().unwrap().spans.lock().unwrap().len();
+            spans
+        });
+        tx.send(triggered).unwrap();
+    });
+
+    assert_eq!(rx.recv_timeout(Duration::from_secs(10)), Ok(2));
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn event_dispatch_order() {
+    use json_rules_engine::ErrorMode;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/down"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let callback = |to: &str, order: Option<u32>| {
+        let mut event = json!({
+            "type": "post_to_callback_url",
+            "params": { "callback_url": format!("{}/{}", server.uri(), to) }
+        });
+        if let Some(order) = order {
+            event["dispatch_order"] = json!(order);
+        }
+        event
+    };
+    let rule = |create: &str| -> Rule {
+        serde_json::from_value(json!({
+            "conditions": {
+                "field": "name",
+                "operator": "string_equals",
+                "value": "Cheng JIANG"
+            },
+            "events": [
+                callback("notify", None),
+                callback("comment", Some(2)),
+                callback(create, Some(1)),
+                callback("close", Some(2))
+            ]
+        }))
+        .unwrap()
+    };
+    let facts = json!({ "name": "Cheng JIANG" });
+    let received = |requests: Vec<wiremock::Request>| -> Vec<String> {
+        requests.iter().map(|r| r.url.path().to_owned()).collect()
+    };
+
+    // the sequence first, ties in declaration order
+    let mut engine = Engine::new();
+    engine.add_rule(rule("create"));
+    engine.run(&facts).await.unwrap();
+    assert_eq!(
+        received(server.received_requests().await.unwrap()),
+        vec!["/create", "/comment", "/close", "/notify"]
+    );
+
+    // the rest of the sequence is skipped once an event of it failed
+    server.reset().await;
+    Mock::given(method("POST"))
+        .and(path("/down"))
+        .respond_with(ResponseTemplate::new(503))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+    let mut engine = Engine::new();
+    engine.add_rule(rule("down"));
+    engine.set_error_mode(ErrorMode::BestEffort);
+    engine.set_abort_sequence_on_error(true);
+    let results = engine.run(&facts).await.unwrap();
+    assert_eq!(
+        received(server.received_requests().await.unwrap()),
+        vec!["/down", "/notify"]
+    );
+    let errors: Vec<_> = results[0]
+        .events
+        .iter()
+        .map(|event| serde_json::to_value(event).unwrap()["error"].clone())
+        .collect();
+    assert!(errors[0].is_null());
+    assert!(errors[1]
+        .as_str()
+        .unwrap()
+        .starts_with("Skipped after `post_to_callback_url` failed"));
+    assert!(errors[2].as_str().unwrap().contains("503"));
+    assert!(errors[3].as_str().unwrap().starts_with("Skipped"));
+}
+
+/// Sleeps when triggered, recording when it started and when it ended
+struct SlowEvent {
+    ty: String,
+    spans: Arc<std::sync::Mutex<Vec<(String, Instant, Instant)>>>,
+}
+
+#[async_trait]
+impl EventTrait for SlowEvent {
+    fn new() -> Self {
+        Self {
+            ty: "slow".into(),
+            spans: Default::default(),
+        }
+    }
+
+    fn get_type(&self) -> &str {
+        &self.ty
+    }
+
+    fn validate(
+        &self,
+        _params: &HashMap<String, serde_json::Value>,
+    ) -> Result<(), String> {
+        Ok(())
+    }
+
+    async fn trigger(
+        &mut self,
+        _params: &HashMap<String, serde_json::Value>,
+        _facts: &(dyn ErasedSerialize + Sync),
+    ) -> Result<(), Error> {
+        let start = Instant::now();
+        tokio::time::sleep(Duration::from_millis(100)).await;
+        self.spans.lock().unwrap().push((
+            self.ty.clone(),
+            start,
+            Instant::now(),
+        ));
+        Ok(())
+    }
+}
+
+#[tokio::test]
+async fn concurrent_event_dispatch() {
+    use json_rules_engine::Limits;
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [
+            { "type": "a", "params": {} },
+            { "type": "b", "params": {} },
+            { "type": "c", "params": {}, "dispatch_order": 1 }
+        ]
+    }))
+    .unwrap();
+    let facts = json!({ "name": "Cheng JIANG" });
+    let slow_engine = || {
+        let spans = Arc::new(std::sync::Mutex::new(Vec::new()));
+        let mut engine = Engine::new();
+        engine.add_rule(rule.clone());
+        for ty in ["a", "b", "c"] {
+            engine.add_event(Arc::new(RwLock::new(SlowEvent {
+                ty: ty.into(),
+                spans: spans.clone(),
+            })));
+        }
+        (engine, spans)
+    };
+    let span = |spans: &[(String, Instant, Instant)], ty: &str| {
+        spans
+            .iter()
+            .find(|(t, _, _)| t == ty)
+            .map(|(_, start, end)| (*start, *end))
+            .unwrap()
+    };
+
+    // the sequence first, then the other events at once
+    let (mut engine, spans) = slow_engine();
+    engine.run(&facts).await.unwrap();
+    let spans = spans.lock().unwrap().clone();
+    let ((_, c_end), (a_start, a_end), (b_start, b_end)) =
+        (span(&spans, "c"), span(&spans, "a"), span(&spans, "b"));
+    assert!(c_end <= a_start && c_end <= b_start);
+    assert!(b_start < a_end && a_start < b_end);
+
+    // or one after the other, in declaration order
+    let (mut engine, spans) = slow_engine();
+    engine.set_sequential_event_dispatch(true);
+    engine.run(&facts).await.unwrap();
+    let spans = spans.lock().unwrap().clone();
+    let order: Vec<_> = spans.iter().map(|(ty, _, _)| ty.as_str()).collect();
+    assert_eq!(order, ["c", "a", "b"]);
+    assert!(spans.windows(2).all(|w| w[0].2 <= w[1].1));
+
+    // the events over the limit are the last ones dispatched, rather than
+    // the last ones declared
+    let (mut engine, spans) = slow_engine();
+    engine.set_limits(Limits {
+        max_events_per_rule_per_run: Some(2),
+        ..Limits::default()
+    });
+    let rule_results = engine.run(&facts).await.unwrap();
+    let events = serde_json::to_value(&rule_results[0].events).unwrap();
+    assert_eq!(events[0].get("too_large"), None);
+    assert_eq!(events[1]["too_large"], json!(true));
+    assert_eq!(events[2].get("too_large"), None);
+    assert_eq!(spans.lock().unwrap().len(), 2);
+}
+
+#[cfg(feature = "callback")]
+#[tokio::test]
+async fn error_modes() {
+    use json_rules_engine::ErrorMode;
+    use wiremock::{
+        matchers::{method, path},
+        Mock, MockServer, ResponseTemplate,
+    };
+
+    let server = MockServer::start().await;
+    Mock::given(method("POST"))
+        .and(path("/fail"))
+        .respond_with(ResponseTemplate::new(500))
+        .mount(&server)
+        .await;
+    Mock::given(method("POST"))
+        .and(path("/ok"))
+        .respond_with(ResponseTemplate::new(200))
+        .mount(&server)
+        .await;
+
+    let rule = |id: &str, endpoint: &str| -> Rule {
+        serde_json::from_value(json!({
+            "id": id,
+            "conditions": {
+                "field": "name",
+                "operator": "string_equals",
+                "value": "Cheng JIANG"
+            },
+            "events": [
+                {
+                    "type": "post_to_callback_url",
+                    "params": {
+                        "callback_url": format!("{}/{}", server.uri(), endpoint)
+                    }
+                }
+            ]
+        }))
+        .unwrap()
+    };
+    let facts = json!({ "name": "Cheng JIANG" });
+
+    let mut engine = Engine::new();
+    engine.add_rules(vec![rule("failing", "fail"), rule("working", "ok")]);
+
+    match engine.run(&facts).await {
+        Err(Error::EventDispatch {
+            rule_id,
+            event_type,
+            source,
+            results,
+        }) => {
+            assert_eq!(rule_id.as_deref(), Some("failing"));
+            assert_eq!(event_type, "post_to_callback_url");
+            assert!(matches!(*source, Error::ReqwestError(_)));
+            assert_eq!(results.len(), 2);
+        }
+        res => panic!("unexpected result: {:?}", res),
+    }
+    // the dispatch stopped at the failed event
+    assert_eq!(server.received_requests().await.unwrap().len(), 1);
+
+    engine.set_error_mode(ErrorMode::BestEffort);
+    let rule_results = engine.run(&facts).await.unwrap();
+    let errors = rule_results
+        .iter()
+        .map(|r| serde_json::to_value(&r.events[0]).unwrap()["error"].clone())
+        .collect::<Vec<_>>();
+    assert!(errors[0].as_str().unwrap().contains("500"));
+    assert_eq!(errors[1], Value::Null);
+    assert_eq!(server.received_requests().await.unwrap().len(), 3);
+}
+
+#[test]
+fn compact_condition_result() {
+    use json_rules_engine::{
+        and, at_least, bool_equals, int_equals, or, string_equals,
+        ConditionResult,
+    };
+
+    let condition = and(vec![
+        or(vec![int_equals("foo", 2), string_equals("bar", "bar")]),
+        at_least(1, vec![bool_equals("baz", false), int_equals("quux", 1)]),
+        int_equals("foo", 1),
+    ]);
+    let result = condition.check_value(
+        &json!({ "foo": 1, "bar": "bar", "baz": true }),
+        #[cfg(feature = "eval")]
+        &rhai::Engine::new(),
+    );
+
+    let compact = result.to_compact_value();
+    assert_eq!(
+        compact,
+        json!([
+            1,
+            [
+                "And",
+                1,
+                [
+                    ["Or", 0, [["foo", 1], ["bar", 0]]],
+                    [
+                        "At least meet 1 of 2 (0 met, 1 unknown)",
+                        1,
+                        [["baz", 1], ["quux", 2]]
+                    ],
+                    ["foo", 0]
+                ]
+            ]
+        ])
+    );
+
+    let verbose = serde_json::to_string(&result).unwrap();
+    let round_trip = ConditionResult::from_compact_value(&compact).unwrap();
+    assert_eq!(serde_json::to_string(&round_trip).unwrap(), verbose);
+    assert!(
+        serde_json::to_string(&compact).unwrap().len() * 10 < verbose.len() * 4
+    );
+
+    assert!(matches!(
+        ConditionResult::from_compact_value(&json!([2, ["foo", 0]])),
+        Err(Error::CompactFormatError(_))
+    ));
+    assert!(matches!(
+        ConditionResult::from_compact_value(&json!([1, ["foo", 3]])),
+        Err(Error::CompactFormatError(_))
+    ));
+}
+
+#[cfg(feature = "delay")]
+#[tokio::test(start_paused = true)]
+async fn delayed_events() {
+    let rule: Rule = serde_json::from_value(json!({
+        "id": "cpu_high",
+        "conditions": {
+            "field": "cpu",
+            "operator": "int_greater_than",
+            "value": 90
+        },
+        "events": [
+            {
+                "type": "counting_event",
+                "params": {},
+                "delay_secs": 300,
+                "recheck_before_send": true
+            }
+        ]
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting_event.clone());
+    let triggered = || counting_event.read().unwrap().triggered.clone();
+
+    // the condition doesn't hold anymore once due
+    let rule_results = engine.run(&json!({ "cpu": 95 })).await.unwrap();
+    let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+    assert_eq!(event["delayed_id"], 0);
+    assert_eq!(engine.pending_delayed().len(), 1);
+    assert_eq!(
+        engine.pending_delayed()[0].rule_id.as_deref(),
+        Some("cpu_high")
+    );
+
+    engine.update_latest_facts(&json!({ "cpu": 50 })).unwrap();
+    let start = tokio::time::Instant::now();
+    assert!(engine.wait_delayed().await.unwrap().is_empty());
+    assert_eq!(start.elapsed(), Duration::from_secs(300));
+    assert!(engine.pending_delayed().is_empty());
+    assert!(triggered().is_empty());
+
+    // the condition still holds once due
+    engine.run(&json!({ "cpu": 95 })).await.unwrap();
+    tokio::time::advance(Duration::from_secs(100)).await;
+    assert!(engine.dispatch_delayed().await.unwrap().is_empty());
+    assert_eq!(engine.pending_delayed().len(), 1);
+
+    // due, but only sent once polled
+    engine.update_latest_facts(&json!({ "cpu": 97 })).unwrap();
+    tokio::time::advance(Duration::from_secs(200)).await;
+    tokio::time::sleep(Duration::from_secs(60)).await;
+    assert!(triggered().is_empty());
+    assert_eq!(engine.pending_delayed().len(), 1);
+    let dispatched = engine.dispatch_delayed().await.unwrap();
+    assert_eq!(dispatched.len(), 1);
+    assert_eq!(dispatched[0].event().ty, "counting_event");
+    assert_eq!(triggered(), [json!({ "cpu": 97 })]);
+
+    // cancelled events are never sent
+    engine.run(&json!({ "cpu": 95 })).await.unwrap();
+    let id = engine.pending_delayed()[0].id;
+    assert!(engine.cancel_delayed(id));
+    assert!(!engine.cancel_delayed(id));
+    assert!(engine.wait_delayed().await.unwrap().is_empty());
+    assert_eq!(triggered().len(), 1);
+}
+
+#[cfg(feature = "delay")]
+#[tokio::test(start_paused = true)]
+async fn delayed_events_coalesce() {
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "cpu",
+            "operator": "int_greater_than",
+            "value": 90
+        },
+        "events": [
+            {
+                "type": "counting_event",
+                "params": {},
+                "delay_secs": 60,
+                "coalescence": 3600,
+                "coalescence_group": "cpu"
+            }
+        ]
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting_event.clone());
+    let facts = json!({ "cpu": 95 });
+
+    // the events scheduled before the first one is sent are dropped then
+    for _ in 0..5 {
+        engine.run(&facts).await.unwrap();
+    }
+    assert_eq!(engine.pending_delayed().len(), 5);
+    tokio::time::advance(Duration::from_secs(60)).await;
+    assert_eq!(engine.dispatch_delayed().await.unwrap().len(), 1);
+    assert!(engine.pending_delayed().is_empty());
+    assert_eq!(counting_event.read().unwrap().triggered.len(), 1);
+
+    // and the ones after it are coalesced
+    let rule_results = engine.run(&facts).await.unwrap();
+    assert!(rule_results[0].events.is_empty());
+    assert!(engine.pending_delayed().is_empty());
+}
+
+#[tokio::test]
+async fn event_interceptors() {
+    use json_rules_engine::{Event, EventInterceptor, InterceptDecision};
+
+    struct CorrelationId;
+
+    #[async_trait]
+    impl EventInterceptor for CorrelationId {
+        async fn before_dispatch(
+            &self,
+            event: &mut Event,
+            facts: &Value,
+            rule_id: &str,
+        ) -> InterceptDecision {
+            let id = format!("{}-{}", rule_id, facts["request"]);
+            event.params.insert("correlation_id".to_string(), json!(id));
+            InterceptDecision::Proceed
+        }
+    }
+
+    struct MutedTenants(HashSet<String>);
+
+    #[async_trait]
+    impl EventInterceptor for MutedTenants {
+        async fn before_dispatch(
+            &self,
+            _event: &mut Event,
+            facts: &Value,
+            _rule_id: &str,
+        ) -> InterceptDecision {
+            match facts["tenant"].as_str() {
+                Some(tenant) if self.0.contains(tenant) => {
+                    InterceptDecision::Drop(format!("{} is muted", tenant))
+                }
+                _ => InterceptDecision::Proceed,
+            }
+        }
+    }
+
+    let mut engine = Engine::new();
+    engine.add_rule(
+        serde_json::from_value(json!({
+            "id": "signup",
+            "conditions": {
+                "field": "tenant",
+                "operator": "string_not_equals",
+                "value": ""
+            },
+            "events": [
+                {
+                    "type": "counting_event",
+                    "params": {}
+                }
+            ]
+        }))
+        .unwrap(),
+    );
+    engine.add_event_interceptor(Arc::new(CorrelationId));
+    engine.add_event_interceptor(Arc::new(MutedTenants(
+        vec!["quiet".to_string()].into_iter().collect(),
+    )));
+
+    let counting_event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(counting_event.clone());
+
+    let rule_results = engine
+        .run(&json!({ "tenant": "loud", "request": 1 }))
+        .await
+        .unwrap();
+    let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+    assert_eq!(event["params"]["correlation_id"], json!("signup-1"));
+    assert!(event.get("dropped").is_none());
+    assert_eq!(counting_event.read().unwrap().triggered.len(), 1);
+
+    let rule_results = engine
+        .run(&json!({ "tenant": "quiet", "request": 2 }))
+        .await
+        .unwrap();
+    let event = serde_json::to_value(&rule_results[0].events[0]).unwrap();
+    // interceptors run in order, so the id was set before the veto
+    assert_eq!(event["params"]["correlation_id"], json!("signup-2"));
+    assert_eq!(event["dropped"], json!("quiet is muted"));
+    assert_eq!(counting_event.read().unwrap().triggered.len(), 1);
+}
+
+#[cfg(feature = "aws")]
+#[tokio::test]
+async fn aws_events() {
+    use aws_sdk_sns::{
+        error::SdkError,
+        operation::publish::{PublishError, PublishOutput},
+        types::error::NotFoundException,
+    };
+    use aws_sdk_sqs::operation::send_message::SendMessageOutput;
+    use aws_smithy_mocks::{mock, mock_client};
+    use json_rules_engine::{sns_publish::SnsPublish, sqs_send::SqsSend};
+
+    let rule_json = json!({
+        "id": "large_order",
+        "conditions": {
+            "field": "total",
+            "operator": "int_greater_than",
+            "value": 1000
+        },
+        "events": [
+            {
+                "type": "sns_publish",
+                "params": {
+                    "topic_arn": "arn:aws:sns:us-east-1:123456789012:orders",
+                    "message_attributes": {
+                        "tenant": "{{ tenant }}"
+                    }
+                }
+            },
+            {
+                "type": "sqs_send",
+                "params": {
+                    "queue_url": "https://sqs.us-east-1.amazonaws.com/123456789012/orders.fifo",
+                    "message_group_id": "{{ tenant }}-{{ order }}"
+                }
+            }
+        ]
+    });
+    let facts = json!({ "total": 1200, "tenant": "acme", "order": 42 });
+    let payload = |event: usize| {
+        json!({
+            "event": rule_json["events"][event]["params"],
+            "facts": facts,
+        })
+    };
+
+    let sns_payload = payload(0);
+    let publish = mock!(aws_sdk_sns::Client::publish)
+        .match_requests(move |req| {
+            req.topic_arn() == Some("arn:aws:sns:us-east-1:123456789012:orders")
+                && req
+                    .message_attributes()
+                    .and_then(|attributes| attributes.get("tenant"))
+                    .and_then(|tenant| tenant.string_value())
+                    == Some("acme")
+                && req.message().map(|m| serde_json::from_str(m).unwrap())
+                    == Some(sns_payload.clone())
+        })
+        .then_output(|| PublishOutput::builder().message_id("1").build());
+    let sqs_payload = payload(1);
+    let send = mock!(aws_sdk_sqs::Client::send_message)
+        .match_requests(move |req| {
+            req.queue_url()
+                == Some(
+                    "https://sqs.us-east-1.amazonaws.com/123456789012/orders.fifo",
+                )
+                && req.message_group_id() == Some("acme-42")
+                && req.message_body().map(|m| serde_json::from_str(m).unwrap())
+                    == Some(sqs_payload.clone())
+        })
+        .then_output(|| SendMessageOutput::builder().message_id("1").build());
+
+    let mut engine = Engine::new();
+    engine.add_rule(serde_json::from_value(rule_json.clone()).unwrap());
+    engine.add_event(Arc::new(RwLock::new(SnsPublish::with_client(
+        mock_client!(aws_sdk_sns, [&publish]),
+    ))));
+    engine.add_event(Arc::new(RwLock::new(SqsSend::with_client(
+        mock_client!(aws_sdk_sqs, [&send]),
+    ))));
+
+    engine.run(&facts).await.unwrap();
+    assert_eq!(publish.num_calls(), 1);
+    assert_eq!(send.num_calls(), 1);
+
+    // the SDK error is kept as the source
+    let not_found = mock!(aws_sdk_sns::Client::publish).then_error(|| {
+        PublishError::NotFoundException(
+            NotFoundException::builder().message("no topic").build(),
+        )
+    });
+    engine.add_event(Arc::new(RwLock::new(SnsPublish::with_client(
+        mock_client!(aws_sdk_sns, [&not_found]),
+    ))));
+    // one after the other, the failure stops the dispatch before the queue
+    engine.set_sequential_event_dispatch(true);
+
+    match engine.run(&facts).await {
+        Err(Error::EventDispatch { source, .. }) => match *source {
+            Error::AwsError(e) => {
+                let e = e.downcast_ref::<SdkError<PublishError>>().unwrap();
+                assert!(e
+                    .as_service_error()
+                    .is_some_and(PublishError::is_not_found_exception));
+            }
+            e => panic!("unexpected error: {:?}", e),
+        },
+        res => panic!("unexpected result: {:?}", res),
+    }
+    assert_eq!(not_found.num_calls(), 1);
+    assert_eq!(send.num_calls(), 1);
+}
+
+#[test]
+fn evaluation_trace() {
+    let mut engine = Engine::new();
+    engine.add_rule(
+        serde_json::from_value(json!({
+            "conditions": {
+                "and": [
+                    {
+                        "or": [
+                            {
+                                "field": "age",
+                                "operator": "int_greater_than",
+                                "value": 18
+                            },
+                            {
+                                "field": "guardian",
+                                "operator": "bool_equals",
+                                "value": true
+                            }
+                        ]
+                    },
+                    {
+                        "should_minimum_meet": 1,
+                        "conditions": [
+                            {
+                                "field": "country",
+                                "operator": "string_equals",
+                                "value": "FR"
+                            },
+                            {
+                                "field": "country",
+                                "operator": "string_equals",
+                                "value": "DE"
+                            }
+                        ]
+                    },
+                    {
+                        "field": "name",
+                        "operator": "string_equals",
+                        "value": "Cheng JIANG"
+                    }
+                ]
+            },
+            "events": []
+        }))
+        .unwrap(),
+    );
+    let facts = json!({
+        "age": 24,
+        "guardian": true,
+        "country": "FR",
+        "name": "Cheng JIANG"
+    });
+
+    // the `or` and `at_least` nodes are decided by their first children
+    let fast = engine.evaluate(&facts).unwrap().remove(0).condition_result;
+    assert_eq!(fast.status, Status::Met);
+    assert_eq!(fast.iter().count(), 6);
+    assert_eq!(fast.children[0].children.len(), 1);
+    assert_eq!(fast.children[1].children.len(), 1);
+    assert!(fast.iter().all(|r| r.evaluated && r.order.is_none()));
+
+    engine.set_trace(true);
+    let traced = engine.evaluate(&facts).unwrap().remove(0).condition_result;
+    assert_eq!(traced.status, fast.status);
+    assert_eq!(traced.iter().count(), 8);
+    assert_eq!(
+        traced
+            .iter()
+            .map(|r| (r.name.as_str(), r.status, r.evaluated, r.order))
+            .collect::<Vec<_>>(),
+        [
+            ("And", Status::Met, true, Some(0)),
+            ("Or", Status::Met, true, Some(1)),
+            ("age", Status::Met, true, Some(2)),
+            ("guardian", Status::Met, false, Some(3)),
+            (
+                "At least meet 1 of 2 (1 met, 0 unknown)",
+                Status::Met,
+                true,
+                Some(4)
+            ),
+            ("country", Status::Met, true, Some(5)),
+            ("country", Status::NotMet, false, Some(6)),
+            ("name", Status::Met, true, Some(7)),
+        ]
+    );
+
+    // the annotations are only serialized when tracing
+    let fast = serde_json::to_value(&fast).unwrap();
+    assert!(fast.get("evaluated").is_none() && fast.get("order").is_none());
+    let traced = serde_json::to_value(&traced).unwrap();
+    assert_eq!(
+        traced["children"][0]["children"][1]["evaluated"],
+        json!(false)
+    );
+    assert_eq!(traced["children"][0]["children"][1]["order"], json!(3));
+}
+
+#[test]
+fn engine_variables() {
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "failed_logins",
+                    "operator": "int_greater_than_inclusive",
+                    "value": { "$var": "max_failed_logins" }
+                },
+                {
+                    "field": "country",
+                    "operator": "string_in",
+                    "value": { "$var": "blocked_countries" }
+                }
+            ]
+        },
+        "events": []
+    });
+    let rule: Rule = serde_json::from_value(rule_json).unwrap();
+    let serialized = serde_json::to_value(&rule).unwrap();
+    assert_eq!(
+        serialized["conditions"]["and"][1],
+        json!({
+            "field": "country",
+            "operator": "string_in",
+            "value": { "$var": "blocked_countries" },
+            "path": null
+        })
+    );
+
+    // variables are resolved when evaluated
+    let facts = json!({ "failed_logins": 5, "country": "XX" });
+    let result = rule
+        .check_value(
+            &facts,
+            #[cfg(feature = "eval")]
+            &rhai::Engine::new(),
+        )
+        .condition_result;
+    assert_eq!(result.status, Status::Unknown);
+    assert_eq!(
+        result.children[0].error.as_deref(),
+        Some("Variable `max_failed_logins` isn't defined")
+    );
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    let met = |engine: &Engine| !engine.evaluate(&facts).unwrap().is_empty();
+
+    assert!(!met(&engine));
+    engine.set_variable("max_failed_logins", json!(5));
+    engine.set_variable("blocked_countries", json!(["XX", "YY"]));
+    assert!(met(&engine));
+
+    engine.set_variable("max_failed_logins", json!(10));
+    assert!(!met(&engine));
+    engine.set_variable("max_failed_logins", json!(3));
+    assert!(met(&engine));
+    engine.set_variable("blocked_countries", json!(["YY"]));
+    assert!(!met(&engine));
+
+    // a value the operator can't take is unknown as well
+    engine.set_variable("blocked_countries", json!("XX"));
+    assert!(!met(&engine));
+
+    // the operator is still checked when loading the rule
+    assert!(serde_json::from_value::<Rule>(json!({
+        "conditions": {
+            "field": "failed_logins",
+            "operator": "int_greater_than_or_so",
+            "value": { "$var": "max_failed_logins" }
+        },
+        "events": []
+    }))
+    .is_err());
+
+    // a list of a single string isn't a variable
+    let condition: json_rules_engine::Condition =
+        serde_json::from_value(json!({
+            "field": "country",
+            "operator": "string_in",
+            "value": ["XX"]
+        }))
+        .unwrap();
+    assert_eq!(
+        condition
+            .check_value(
+                &facts,
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .status,
+        Status::Met
+    );
+}
+
+#[test]
+fn sql_where_clause() {
+    use json_rules_engine::{Condition, SqlDialect, SqlParam};
+
+    let condition: Condition = serde_json::from_value(json!({
+        "and": [
+            {
+                "field": "country",
+                "operator": "string_equals",
+                "value": "FR"
+            },
+            {
+                "or": [
+                    {
+                        "field": "age",
+                        "operator": "int_in_range",
+                        "value": [18, 25]
+                    },
+                    {
+                        "field": "vip",
+                        "operator": "bool_equals",
+                        "value": true
+                    }
+                ]
+            },
+            {
+                "not": {
+                    "field": "plan",
+                    "operator": "string_in",
+                    "value": ["free", "trial"]
+                }
+            },
+            {
+                "should_minimum_meet": 1,
+                "conditions": [
+                    {
+                        "field": "score",
+                        "operator": "float_greater_than",
+                        "value": 0.5
+                    },
+                    {
+                        "field": "id",
+                        "operator": "uint_equals",
+                        "value": 7
+                    }
+                ]
+            }
+        ]
+    }))
+    .unwrap();
+    let columns = |field: &str| match field {
+        "nickname" => None,
+        field => Some(format!("users.{}", field)),
+    };
+
+    let (clause, params) =
+        condition.to_sql(SqlDialect::Postgres, &columns).unwrap();
+    assert_eq!(
+        clause,
+        "(users.country = $1 \
+         AND (users.age BETWEEN $2 AND $3 OR users.vip = $4) \
+         AND NOT (users.plan IN ($5, $6)) \
+         AND (CASE WHEN users.score > $7 THEN 1 ELSE 0 END \
+         + CASE WHEN users.id = $8 THEN 1 ELSE 0 END) >= 1)"
+    );
+    assert_eq!(
+        params,
+        [
+            SqlParam::String("FR".to_string()),
+            SqlParam::Int(18),
+            SqlParam::Int(25),
+            SqlParam::Bool(true),
+            SqlParam::String("free".to_string()),
+            SqlParam::String("trial".to_string()),
+            SqlParam::Float(0.5),
+            SqlParam::Uint(7),
+        ]
+    );
+
+    let (clause, mysql_params) =
+        condition.to_sql(SqlDialect::MySql, &columns).unwrap();
+    assert_eq!(
+        clause,
+        "(users.country = ? \
+         AND (users.age BETWEEN ? AND ? OR users.vip = ?) \
+         AND NOT (users.plan IN (?, ?)) \
+         AND (CASE WHEN users.score > ? THEN 1 ELSE 0 END \
+         + CASE WHEN users.id = ? THEN 1 ELSE 0 END) >= 1)"
+    );
+    assert_eq!(mysql_params, params);
+
+    // what can't be expressed is refused, or dropped in a way that keeps
+    // every row the condition could match
+    let unmapped = json!({
+        "field": "nickname",
+        "operator": "string_equals",
+        "value": "cj"
+    });
+    #[cfg(feature = "eval")]
+    let opaque = json!({ "expr": "facts.age * 2 > 40" });
+    #[cfg(not(feature = "eval"))]
+    let opaque = json!({
+        "field": "bio",
+        "operator": "string_contains",
+        "value": "rust"
+    });
+    let condition: Condition = serde_json::from_value(json!({
+        "and": [
+            {
+                "field": "country",
+                "operator": "string_equals",
+                "value": "FR"
+            },
+            opaque,
+            {
+                "or": [
+                    {
+                        "field": "age",
+                        "operator": "int_greater_than",
+                        "value": 18
+                    },
+                    opaque
+                ]
+            },
+            {
+                "not": {
+                    "and": [
+                        {
+                            "field": "vip",
+                            "operator": "bool_equals",
+                            "value": true
+                        },
+                        unmapped
+                    ]
+                }
+            }
+        ]
+    }))
+    .unwrap();
+
+    assert!(matches!(
+        condition.to_sql(SqlDialect::Postgres, &columns),
+        Err(Error::SqlError(_))
+    ));
+
+    let lossy = condition.to_sql_lossy(SqlDialect::Postgres, &columns);
+    assert_eq!(lossy.clause, "users.country = $1");
+    assert_eq!(lossy.params, [SqlParam::String("FR".to_string())]);
+    assert!(lossy.lossy);
+
+    let (clause, params) = serde_json::from_value::<Condition>(unmapped)
+        .unwrap()
+        .to_sql(SqlDialect::MySql, &|field| Some(field.to_string()))
+        .unwrap();
+    assert_eq!(clause, "nickname = ?");
+    assert_eq!(params, [SqlParam::String("cj".to_string())]);
+
+    // an open range compares with its only bound
+    let (clause, params) = json_rules_engine::int_at_least("age", 18)
+        .to_sql(SqlDialect::MySql, &columns)
+        .unwrap();
+    assert_eq!(clause, "users.age >= ?");
+    assert_eq!(params, [SqlParam::Int(18)]);
+}
+
+#[cfg(all(feature = "test_util", feature = "callback"))]
+#[tokio::test]
+async fn mock_callback_server() {
+    use json_rules_engine::{assert_event_sent, test_util::MockCallbackServer};
+
+    let server = MockCallbackServer::start().await;
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_equals",
+            "value": "Cheng JIANG"
+        },
+        "events": [
+            {
+                "type": "post_to_callback_url",
+                "params": {
+                    "callback_url": server.url(),
+                    "type": "info",
+                    "title": "Hello {{name}}",
+                    "app_data": { "environment": "staging" }
+                }
+            }
+        ]
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    engine.run(&json!({ "name": "Cheng JIANG" })).await.unwrap();
+
+    let received = server.received().await;
+    assert_eq!(received.len(), 1);
+    assert_eq!(received[0].event_type(), Some("info"));
+    assert_eq!(received[0].fact("/name"), Some(&json!("Cheng JIANG")));
+    assert_eq!(
+        received[0].app_data,
+        Some(json!({ "environment": "staging" }))
+    );
+    assert_eq!(received[0].header("Content-Type"), Some("application/json"));
+
+    assert_event_sent!(server, type = "info", title_contains = "Hello");
+    assert_event_sent!(server, title_contains = "Hello");
+    assert!(!received[0].matches(Some("warning"), None));
+    assert!(!received[0].matches(None, Some("Goodbye")));
+}
+
+#[cfg(feature = "test_util")]
+#[tokio::test]
+async fn capturing_event() {
+    use json_rules_engine::test_util::CapturingEvent;
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "age",
+            "operator": "int_greater_than",
+            "value": 18
+        },
+        "events": [
+            {
+                "type": "capturing_event",
+                "params": { "title": "adult" }
+            },
+            {
+                "type": "audit",
+                "params": { "level": 2 }
+            }
+        ]
+    }))
+    .unwrap();
+
+    let mut engine = Engine::new();
+    engine.add_rule(rule);
+    let capturing = Arc::new(RwLock::new(CapturingEvent::new()));
+    let audit = Arc::new(RwLock::new(CapturingEvent::with_type("audit")));
+    engine.add_event(capturing.clone());
+    engine.add_event(audit.clone());
+
+    engine.run(&json!({ "age": 24 })).await.unwrap();
+    engine.run(&json!({ "age": 12 })).await.unwrap();
+
+    let captured = capturing.read().unwrap().captured().to_vec();
+    assert_eq!(captured.len(), 1);
+    assert_eq!(captured[0].params["title"], json!("adult"));
+    assert_eq!(captured[0].facts, json!({ "age": 24 }));
+
+    assert_eq!(
+        audit.read().unwrap().captured()[0].params["level"],
+        json!(2)
+    );
+    audit.write().unwrap().clear();
+    assert!(audit.read().unwrap().captured().is_empty());
+}
+
+#[cfg(feature = "aws")]
+#[tokio::test]
+async fn aws_events_escape_modes() {
+    use aws_sdk_sns::operation::publish::PublishOutput;
+    use aws_sdk_sqs::operation::send_message::SendMessageOutput;
+    use aws_smithy_mocks::{mock, mock_client};
+    use json_rules_engine::{sns_publish::SnsPublish, sqs_send::SqsSend};
+
+    for (escape_mode, expected) in &[("html", "R&amp;D-42"), ("none", "R&D-42")]
+    {
+        let rule: Rule = serde_json::from_value(json!({
+            "conditions": {
+                "field": "total",
+                "operator": "int_greater_than",
+                "value": 1000
+            },
+            "events": [
+                {
+                    "type": "sns_publish",
+                    "params": {
+                        "topic_arn": "arn:aws:sns:us-east-1:123456789012:orders",
+                        "message_attributes": {
+                            "tenant": "{{ tenant }}-{{ order }}"
+                        },
+                        "escape_mode": escape_mode
+                    }
+                },
+                {
+                    "type": "sqs_send",
+                    "params": {
+                        "queue_url": "https://sqs.us-east-1.amazonaws.com/123456789012/orders.fifo",
+                        "message_group_id": "{{ tenant }}-{{ order }}",
+                        "escape_mode": escape_mode
+                    }
+                }
+            ]
+        }))
+        .unwrap();
+
+        let expected = expected.to_string();
+        let tenant = expected.clone();
+        let publish = mock!(aws_sdk_sns::Client::publish)
+            .match_requests(move |req| {
+                req.message_attributes()
+                    .and_then(|attributes| attributes.get("tenant"))
+                    .and_then(|tenant| tenant.string_value())
+                    == Some(tenant.as_str())
+            })
+            .then_output(|| PublishOutput::builder().message_id("1").build());
+        let send = mock!(aws_sdk_sqs::Client::send_message)
+            .match_requests(move |req| {
+                req.message_group_id() == Some(expected.as_str())
+            })
+            .then_output(|| {
+                SendMessageOutput::builder().message_id("1").build()
+            });
+
+        let mut engine = Engine::new();
+        engine.add_rule(rule);
+        engine.add_event(Arc::new(RwLock::new(SnsPublish::with_client(
+            mock_client!(aws_sdk_sns, [&publish]),
+        ))));
+        engine.add_event(Arc::new(RwLock::new(SqsSend::with_client(
+            mock_client!(aws_sdk_sqs, [&send]),
+        ))));
+
+        engine
+            .run(&json!({ "total": 1200, "tenant": "R&D", "order": 42 }))
+            .await
+            .unwrap();
+        assert_eq!(publish.num_calls(), 1);
+        assert_eq!(send.num_calls(), 1);
+    }
+}
+
+fn warm_start_rules() -> Vec<Rule> {
+    #[allow(unused_mut)]
+    let mut rules = vec![
+        json!({
+            "id": "paths",
+            "conditions": {
+                "and": [
+                    {
+                        "field": "user.address.country",
+                        "operator": "string_equals",
+                        "value": "FR",
+                        "path_syntax": "dotted"
+                    },
+                    {
+                        "field": "user/age",
+                        "operator": "int_greater_than_inclusive",
+                        "value": 18
+                    },
+                    {
+                        "field": "/user/tags/0",
+                        "operator": "string_equals",
+                        "value": "vip",
+                        "pointer": true
+                    }
+                ]
+            },
+            "events": [
+                {
+                    "type": "counting_event",
+                    "coalescence": 60,
+                    "coalescence_group": "{{ user.name }}",
+                    "params": { "title": "Hello {{ user.name }}" }
+                }
+            ]
+        }),
+        json!({
+            "id": "templated",
+            "conditions": {
+                "field": "shipping_country",
+                "operator": "string_in",
+                "value": ["{{ user.address.country }}", "US"],
+                "templated_value": true
+            },
+            "events": []
+        }),
+        json!({
+            "conditions": {
+                "or": [
+                    {
+                        "field": "a/b",
+                        "operator": "int_equals",
+                        "value": 1
+                    },
+                    {
+                        "field": "user.age",
+                        "operator": "int_less_than",
+                        "value": 18,
+                        "path_syntax": "dotted"
+                    }
+                ]
+            },
+            "events": []
+        }),
+    ];
+    #[cfg(feature = "eval")]
+    rules.push(json!({
+        "id": "eval",
+        "conditions": { "expr": "facts.user.age > 30 && now_ts > 0" },
+        "events": []
+    }));
+
+    rules
+        .into_iter()
+        .map(|rule| serde_json::from_value(rule).unwrap())
+        .collect()
+}
+
+#[test]
+fn compiled_rules_match_naive_ones() {
+    let compiled =
+        Engine::build(warm_start_rules(), Default::default()).unwrap();
+    let mut naive = Engine::new();
+    naive.add_rules(warm_start_rules());
+
+    let results = |engine: &Engine, facts: &Value| {
+        let mut results =
+            serde_json::to_value(engine.evaluate(facts).unwrap()).unwrap();
+        for result in results.as_array_mut().unwrap() {
+            result["evaluated_at"] = json!(0);
+            result["duration_micros"] = json!(0);
+        }
+        results
+    };
+
+    for facts in [
+        json!({
+            "user": {
+                "name": "Cheng",
+                "age": 34,
+                "tags": ["vip"],
+                "address": { "country": "FR" }
+            },
+            "shipping_country": "FR"
+        }),
+        json!({
+            "user": {
+                "name": "Benn",
+                "age": 17,
+                "tags": [],
+                "address": { "country": "US" }
+            },
+            "shipping_country": "DE",
+            "a/b": 1
+        }),
+        json!({
+            "user.address.country": "FR",
+            "user/age": 20,
+            "user": { "tags": ["vip"] },
+            "a": { "b": 1 }
+        }),
+    ] {
+        let expected = results(&naive, &facts);
+        assert!(!expected.as_array().unwrap().is_empty());
+        assert_eq!(results(&compiled, &facts), expected, "{}", facts);
+    }
+}
+
+#[test]
+fn build_reports_every_failure() {
+    #[allow(unused_mut)]
+    let mut rules = vec![
+        json!({
+            "conditions": {
+                "field": "name",
+                "operator": "string_in_named_set",
+                "value": "names"
+            },
+            "events": []
+        }),
+        json!({
+            "conditions": {
+                "field": "name",
+                "operator": "string_in_named_set",
+                "value": "missing"
+            },
+            "events": []
+        }),
+        json!({
+            "conditions": {
+                "field": "name",
+                "operator": "string_equals",
+                "value": "{{#name}}",
+                "templated_value": true
+            },
+            "events": []
+        }),
+        json!({
+            "conditions": {
+                "field": "name",
+                "operator": "string_equals",
+                "value": "{{#name}}"
+            },
+            "events": [
+                {
+                    "type": "counting_event",
+                    "params": { "escape_mode": "markdown" }
+                },
+            ]
+        }),
+        json!({
+            "conditions": {
+                "field": "name",
+                "operator": "string_equals",
+                "value": "a"
+            },
+            "events": [
+                {
+                    "type": "counting_event",
+                    "params": { "message": ["{{/name}}"] }
+                },
+            ]
+        }),
+    ];
+    #[cfg(feature = "eval")]
+    rules.push(json!({ "conditions": { "expr": "1 +" }, "events": [] }));
+    let rules = rules
+        .into_iter()
+        .map(|rule| serde_json::from_value(rule).unwrap())
+        .collect::<Vec<Rule>>();
+
+    let mut options = json_rules_engine::EngineOptions::default();
+    options
+        .sets
+        .insert("names".into(), vec!["a".to_string()].into_iter().collect());
+    let errors = match Engine::build(rules.clone(), options.clone()) {
+        Err(errors) => errors,
+        Ok(_) => panic!("invalid rules were built"),
+    };
+
+    let indices = errors.iter().map(|(i, _)| *i).collect::<Vec<_>>();
+    #[cfg(not(feature = "eval"))]
+    assert_eq!(indices, [1, 2, 3, 4]);
+    #[cfg(feature = "eval")]
+    assert_eq!(indices, [1, 2, 3, 4, 5]);
+    assert!(errors
+        .iter()
+        .all(|(_, e)| matches!(e, Error::ValidationError(_))));
+
+    let engine = Engine::build(rules[..1].to_vec(), options).unwrap();
+    assert_eq!(engine.evaluate(&json!({ "name": "a" })).unwrap().len(), 1);
+
+    let options = json_rules_engine::EngineOptions {
+        limits: json_rules_engine::Limits {
+            max_rules: Some(1),
+            ..Default::default()
+        },
+        ..Default::default()
+    };
+    let errors = match Engine::build(rules[2..].to_vec(), options) {
+        Err(errors) => errors,
+        Ok(_) => panic!("rules over the limit were built"),
+    };
+    assert!(matches!(errors[1], (1, Error::LimitError(_))));
+}
+
+#[tokio::test]
+async fn rule_index_matches_full_evaluation() {
+    #[allow(unused_mut)]
+    let mut conditions = vec![
+        json!({ "field": "name", "operator": "string_equals", "value": "a" }),
+        json!({
+            "field": "user.age",
+            "path_syntax": "dotted",
+            "operator": "int_greater_than",
+            "value": 18
+        }),
+        json!({
+            "field": "/user/roles",
+            "pointer": true,
+            "operator": "string_contains",
+            "value": "admin"
+        }),
+        // a top level key named after the field
+        json!({
+            "field": "a/b",
+            "operator": "int_equals",
+            "value": 1
+        }),
+        json!({
+            "or": [
+                { "field": "name", "operator": "string_equals", "value": "b" },
+                { "field": "country", "operator": "string_equals", "value": "FR" },
+            ]
+        }),
+        // met when every fact is missing
+        json!({
+            "not": {
+                "should_minimum_meet": 1,
+                "conditions": [
+                    { "field": "banned", "operator": "bool_equals", "value": true }
+                ]
+            }
+        }),
+        json!({
+            "field": "opt_out",
+            "operator": "bool_equals",
+            "value": false,
+            "default": false
+        }),
+        json!({ "and": [] }),
+    ];
+    #[cfg(feature = "eval")]
+    conditions.push(json!({ "expr": "facts.len() == 0" }));
+
+    let rules: Vec<Rule> = conditions
+        .into_iter()
+        .enumerate()
+        .map(|(i, conditions)| {
+            serde_json::from_value(json!({
+                "id": i.to_string(),
+                "conditions": conditions,
+                "events": [],
+            }))
+            .unwrap()
+        })
+        .collect();
+
+    let options = json_rules_engine::EngineOptions {
+        enable_rule_index: true,
+        ..Default::default()
+    };
+    let mut indexed = Engine::build(rules.clone(), options)
+        .unwrap_or_else(|_| panic!("rules failed to build"));
+    let mut engine = Engine::new();
+    engine.add_rules(rules);
+
+    let ids = |results: Vec<RuleResult>| {
+        results
+            .into_iter()
+            .map(|r| r.rule_id.unwrap())
+            .collect::<Vec<_>>()
+    };
+    for facts in &[
+        json!({}),
+        json!({ "name": "a" }),
+        json!({ "user": { "age": 20, "roles": "admin" } }),
+        json!({ "a/b": 1 }),
+        json!({ "a": { "b": 1 } }),
+        json!({ "country": "FR", "banned": true }),
+        json!({ "opt_out": true, "unrelated": 1 }),
+        json!([1, 2]),
+    ] {
+        let expected = ids(engine.evaluate(facts).unwrap());
+        assert_eq!(ids(indexed.evaluate(facts).unwrap()), expected);
+        assert_eq!(ids(indexed.run(facts).await.unwrap()), expected);
+    }
+
+    // only the rules that may be met against the facts are evaluated
+    let (_, info) = indexed.run_with_info(&json!({})).await.unwrap();
+    #[cfg(not(feature = "eval"))]
+    assert_eq!(info.rules_evaluated, 3);
+    #[cfg(feature = "eval")]
+    assert_eq!(info.rules_evaluated, 4);
+    let (_, info) = engine.run_with_info(&json!({})).await.unwrap();
+    assert_eq!(
+        info.rules_evaluated,
+        if cfg!(feature = "eval") { 9 } else { 8 }
+    );
+
+    // rules added afterwards are indexed too, until the index is turned off
+    indexed.add_rule(
+        serde_json::from_value(json!({
+            "id": "late",
+            "conditions": {
+                "field": "name", "operator": "string_equals", "value": "c"
+            },
+            "events": [],
+        }))
+        .unwrap(),
+    );
+    let (results, info) = indexed
+        .run_with_info(&json!({ "name": "c" }))
+        .await
+        .unwrap();
+    assert_eq!(ids(results).last().unwrap(), "late");
+    let evaluated = info.rules_evaluated;
+    indexed.set_rule_index(false);
+    let (_, info) = indexed
+        .run_with_info(&json!({ "name": "c" }))
+        .await
+        .unwrap();
+    assert!(info.rules_evaluated > evaluated);
+}
+
+#[test]
+fn backtest() {
+    use json_rules_engine::{and, int_greater_than, string_equals};
+
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": and(vec![
+            int_greater_than("age", 30),
+            string_equals("country", "FR"),
+        ]),
+        "events": [{ "type": "counting_event", "params": {} }],
+    }))
+    .unwrap();
+    // generated one at a time, every tenth without a country
+    let facts = (0..1_000).map(|i| {
+        let mut facts = json!({ "id": i, "age": i % 60 });
+        if i % 10 != 9 {
+            facts["country"] = json!(if i % 2 == 0 { "FR" } else { "DE" });
+        }
+        facts
+    });
+
+    let mut engine = Engine::new();
+    let event = Arc::new(RwLock::new(CountingEvent::new()));
+    engine.add_event(event.clone());
+    let report = engine.backtest(&rule, facts, 3);
+
+    assert_eq!(report.total(), 1_000);
+    assert_eq!(report.met, 228);
+    assert_eq!(report.not_met, 723);
+    assert_eq!(report.unknown, 49);
+    assert_eq!(
+        report
+            .samples
+            .iter()
+            .map(|facts| facts["id"].as_i64().unwrap())
+            .collect::<Vec<_>>(),
+        [32, 34, 36]
+    );
+    // `and` stops at its first failing child
+    assert_eq!(report.failed_leaves.len(), 2);
+    assert_eq!(report.failed_leaves["age"], 527);
+    assert_eq!(report.failed_leaves["country"], 196);
+
+    assert!(event.read().unwrap().triggered.is_empty());
+}
+
+#[test]
+fn plan_partial_facts() {
+    use json_rules_engine::{and, int_equals, or, string_equals};
+
+    let rule = |id: &str, conditions: json_rules_engine::Condition| -> Rule {
+        serde_json::from_value(json!({
+            "id": id,
+            "conditions": conditions,
+            "events": [],
+        }))
+        .unwrap()
+    };
+    let mut engine = Engine::new();
+    engine.add_rules(vec![
+        rule(
+            "either",
+            or(vec![string_equals("country", "FR"), int_equals("age", 18)]),
+        ),
+        rule(
+            "both",
+            and(vec![string_equals("country", "FR"), int_equals("age", 18)]),
+        ),
+        rule(
+            "neither",
+            and(vec![string_equals("country", "DE"), int_equals("age", 18)]),
+        ),
+        rule(
+            "nested",
+            or(vec![
+                and(vec![
+                    string_equals("country", "DE"),
+                    int_equals("user.score", 1),
+                ]),
+                int_equals("user/level", 3),
+            ]),
+        ),
+    ]);
+
+    let required = engine.required_fields();
+    assert_eq!(
+        required.keys().collect::<Vec<_>>(),
+        ["/age", "/country", "/user.score", "/user/level"]
+    );
+    assert_eq!(required["/age"], ["either", "both", "neither"]);
+    assert_eq!(required["/user/level"], ["nested"]);
+
+    let plan = engine.plan(&json!({ "country": "FR" }));
+    let decisions: Vec<_> = plan
+        .rules
+        .iter()
+        .map(|r| (r.rule_id.as_str(), r.status, r.missing_fields.clone()))
+        .collect();
+    assert_eq!(
+        decisions,
+        [
+            // an `or` met by its first child is decided
+            ("either", Status::Met, vec![]),
+            // an `and` met by its first child isn't
+            ("both", Status::Unknown, vec!["/age".to_string()]),
+            ("neither", Status::NotMet, vec![]),
+            // the `and` is decided, not the `or`
+            ("nested", Status::Unknown, vec!["/user/level".to_string()]),
+        ]
+    );
+    assert!(!plan.is_decided());
+    assert_eq!(plan.missing_fields(), ["/age", "/user/level"]);
+
+    let plan = engine.plan(&json!({
+        "country": "FR",
+        "age": 18,
+        "user": { "level": 1 }
+    }));
+    assert!(plan.is_decided());
+    assert!(plan.missing_fields().is_empty());
+}
+
+#[test]
+fn condition_defaults() {
+    let rule_json = json!({
+        "conditions": {
+            "field": "opt_out",
+            "operator": "bool_equals",
+            "value": false,
+            "default": false
+        },
+        "events": []
+    });
+    let rule = Rule::from_value_strict(rule_json).unwrap();
+    let result = |facts: Value| {
+        rule.check_value(
+            &facts,
+            #[cfg(feature = "eval")]
+            &rhai::Engine::new(),
+        )
+        .condition_result
+    };
+
+    for facts in [json!({}), json!({ "opt_out": null })] {
+        let result = result(facts);
+        assert_eq!(result.status, Status::Met);
+        assert!(result.used_default);
+        assert_eq!(
+            serde_json::to_value(&result).unwrap()["used_default"],
+            true
+        );
+    }
+
+    let result = result(json!({ "opt_out": true }));
+    assert_eq!(result.status, Status::NotMet);
+    assert!(!result.used_default);
+    assert!(serde_json::to_value(&result)
+        .unwrap()
+        .get("used_default")
+        .is_none());
+
+    // without a default, missing stays unknown and null is compared
+    let condition = json_rules_engine::bool_equals("opt_out", false);
+    let status = |facts: Value| {
+        condition
+            .check_value(
+                &facts,
+                #[cfg(feature = "eval")]
+                &rhai::Engine::new(),
+            )
+            .status
+    };
+    assert_eq!(status(json!({})), Status::Unknown);
+    assert_eq!(status(json!({ "opt_out": null })), Status::NotMet);
+}
+
+#[tokio::test]
+async fn engine_state_round_trip() {
+    use json_rules_engine::{EngineState, ENGINE_STATE_VERSION};
+
+    let t0 = chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+        .unwrap()
+        .with_timezone(&chrono::Utc);
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "name",
+            "operator": "string_not_equals",
+            "value": ""
+        },
+        "events": [
+            {
+                "type": "counting_event",
+                "params": {},
+                "coalescence": 60,
+                "coalescence_group": "{{ name }}"
+            },
+            {
+                "type": "limited_event",
+                "params": {}
+            }
+        ]
+    }))
+    .unwrap();
+
+    // an engine as started after `elapsed` seconds, with its event handlers
+    let engine_at = |elapsed: i64| {
+        let mut engine = Engine::new();
+        engine.add_rule(rule.clone());
+        engine.set_now_provider(Arc::new(move || {
+            t0 + chrono::Duration::seconds(elapsed)
+        }));
+        engine.set_rate_limit("limited_event", 2, Duration::from_secs(3600));
+        let coalesced = Arc::new(RwLock::new(CountingEvent::new()));
+        engine.add_event(coalesced.clone());
+        let limited = Arc::new(RwLock::new(CountingEvent {
+            ty: "limited_event".into(),
+            ..CountingEvent::new()
+        }));
+        engine.add_event(limited.clone());
+        (engine, coalesced, limited)
+    };
+    let triggered = |event: &Arc<RwLock<CountingEvent>>| {
+        event.read().unwrap().triggered.len()
+    };
+
+    let (mut engine, coalesced, limited) = engine_at(0);
+    engine.run(&json!({ "name": "Cheng" })).await.unwrap();
+    assert_eq!((triggered(&coalesced), triggered(&limited)), (1, 1));
+
+    let state = engine.export_state();
+    assert_eq!(state.version, ENGINE_STATE_VERSION);
+    let expires_in = state.coalescences["Cheng"] - t0.timestamp_millis();
+    assert!((59_000..=60_000).contains(&expires_in));
+    assert!((state.rate_limits["limited_event"].tokens - 1.0).abs() < 1e-3);
+
+    // through json, as it would be persisted
+    let saved = serde_json::to_string(&state).unwrap();
+    let restore = |elapsed: i64| {
+        let (mut engine, coalesced, limited) = engine_at(elapsed);
+        let state: EngineState = serde_json::from_str(&saved).unwrap();
+        engine.import_state(state).unwrap();
+        (engine, coalesced, limited)
+    };
+
+    // restarted 30 seconds later: still coalesced, one token left
+    let (mut engine, coalesced, limited) = restore(30);
+    for _ in 0..2 {
+        engine.run(&json!({ "name": "Cheng" })).await.unwrap();
+    }
+    assert_eq!((triggered(&coalesced), triggered(&limited)), (0, 1));
+
+    // restarted once the group expired and the bucket refilled
+    let (mut engine, coalesced, limited) = restore(3600);
+    assert!(engine.export_state().coalescences.is_empty());
+    for _ in 0..3 {
+        engine.run(&json!({ "name": "Cheng" })).await.unwrap();
+    }
+    assert_eq!((triggered(&coalesced), triggered(&limited)), (1, 2));
+
+    let mut state: Value = serde_json::from_str(&saved).unwrap();
+    state["version"] = json!(ENGINE_STATE_VERSION + 1);
+    assert!(matches!(
+        Engine::new().import_state(serde_json::from_value(state).unwrap()),
+        Err(Error::StateVersionError(_))
+    ));
+}
+
+#[cfg(feature = "delay")]
+#[tokio::test(start_paused = true)]
+async fn engine_state_delayed_events() {
+    let t0 = chrono::DateTime::parse_from_rfc3339("2024-01-01T00:00:00Z")
+        .unwrap()
+        .with_timezone(&chrono::Utc);
+    let rule: Rule = serde_json::from_value(json!({
+        "conditions": {
+            "field": "cpu",
+            "operator": "int_greater_than",
+            "value": 90
+        },
+        "events": [
+            { "type": "counting_event", "params": {}, "delay_secs": 300 }
+        ]
+    }))
+    .unwrap();
+    let engine_at = |elapsed: i64| {
+        let mut engine = Engine::new();
+        engine.add_rule(rule.clone());
+        engine.set_now_provider(Arc::new(move || {
+            t0 + chrono::Duration::seconds(elapsed)
+        }));
+        let event = Arc::new(RwLock::new(CountingEvent::new()));
+        engine.add_event(event.clone());
+        (engine, event)
+    };
+
+    let (mut engine, _) = engine_at(0);
+    engine.run(&json!({ "cpu": 95 })).await.unwrap();
+    let state = engine.export_state();
+    assert_eq!(state.delayed.len(), 1);
+    assert_eq!(state.delayed[0].due_at, t0.timestamp_millis() + 300_000);
+
+    // 100 seconds down, 200 left
+    let (mut engine, event) = engine_at(100);
+    engine.import_state(state.clone()).unwrap();
+    let left = engine.next_delayed_due().unwrap() - tokio::time::Instant::now();
+    assert_eq!(left, Duration::from_secs(200));
+    tokio::time::advance(Duration::from_secs(200)).await;
+    assert_eq!(engine.dispatch_delayed().await.unwrap().len(), 1);
+    assert_eq!(event.read().unwrap().triggered.len(), 1);
+    assert!(engine.dispatch_delayed().await.unwrap().is_empty());
+
+    // down past the due time, due right away
+    let (mut engine, event) = engine_at(1000);
+    engine.import_state(state).unwrap();
+    assert_eq!(engine.dispatch_delayed().await.unwrap().len(), 1);
+    assert_eq!(event.read().unwrap().triggered.len(), 1);
+}
+
+#[cfg(feature = "eval")]
+#[test]
+fn eval_flatten_scope() {
+    let rules = [
+        ("flat", r#"age > 20 && name == "Cheng JIANG""#),
+        (
+            "nested",
+            r#"facts.age > 20 && facts["first-name"] == "Cheng""#,
+        ),
+        ("reserved", r#"type_of(results) == "map" && now_ts > 0"#),
+        ("shadowed", "let age = 1; age == 1"),
+    ]
+    .iter()
+    .map(|(id, expr)| {
+        serde_json::from_value(json!({
+            "id": id,
+            "conditions": { "expr": expr },
+            "events": []
+        }))
+        .unwrap()
+    })
+    .collect::<Vec<Rule>>();
+    let facts = json!({
+        "age": 25,
+        "name": "Cheng JIANG",
+        "first-name": "Cheng",
+        "results": 0,
+        "now_ts": 0
+    });
+    let met = |engine: &Engine| {
+        engine
+            .evaluate(&facts)
+            .unwrap()
+            .into_iter()
+            .filter_map(|result| result.rule_id)
+            .collect::<Vec<_>>()
+    };
+
+    let mut engine = Engine::new();
+    engine.add_rules(rules.clone());
+    assert_eq!(met(&engine), ["nested", "reserved", "shadowed"]);
+
+    engine.set_eval_flatten_scope(true);
+    assert_eq!(met(&engine), ["flat", "nested", "reserved", "shadowed"]);
+
+    let options = json_rules_engine::EngineOptions {
+        eval_flatten_scope: true,
+        ..Default::default()
+    };
+    let engine = Engine::build(rules, options).unwrap();
+    assert_eq!(met(&engine), ["flat", "nested", "reserved", "shadowed"]);
+}
+
+#[tokio::test]
+async fn run_hooks() {
+    use json_rules_engine::{
+        Event, EventInterceptor, InterceptDecision, RunHooks,
+    };
+    use std::sync::Mutex;
+
+    type Log = Arc<Mutex<Vec<String>>>;
+
+    struct Dispatches(Log);
+
+    #[async_trait]
+    impl EventInterceptor for Dispatches {
+        async fn before_dispatch(
+            &self,
+            event: &mut Event,
+            _facts: &Value,
+            rule_id: &str,
+        ) -> InterceptDecision {
+            self.0
+                .lock()
+                .unwrap()
+                .push(format!("dispatch {} {}", rule_id, event.ty));
+            InterceptDecision::Proceed
+        }
+    }
+
+    let log: Log = Arc::default();
+    let mut engine = Engine::new();
+    engine.add_rule(
+        serde_json::from_value(json!({
+            "id": "adult",
+            "conditions": {
+                "field": "age",
+                "operator": "int_greater_than_inclusive",
+                "value": 18
+            },
+            "events": [{ "type": "counting_event", "params": {} }]
+        }))
+        .unwrap(),
+    );
+    engine.add_event(Arc::new(RwLock::new(CountingEvent::new())));
+    engine.add_event_interceptor(Arc::new(Dispatches(log.clone())));
+
+    let before_log = log.clone();
+    let after_log = log.clone();
+    engine.set_run_hooks(RunHooks {
+        before: Some(Arc::new(move |facts| {
+            before_log.lock().unwrap().push(format!("before {}", facts));
+            if facts["age"] == 99 {
+                panic!("audit backend down");
+            }
+        })),
+        after: Some(Arc::new(move |facts, rule_results| {
+            after_log.lock().unwrap().push(format!(
+                "after {} {}",
+                facts,
+                rule_results.len()
+            ));
+        })),
+    });
+
+    engine.run(&json!({ "age": 20 })).await.unwrap();
+    engine.run(&json!({ "age": 10 })).await.unwrap();
+    assert_eq!(engine.evaluate(&json!({ "age": 30 })).unwrap().len(), 1);
+    // a panicking hook doesn't abort the run
+    assert_eq!(engine.run(&json!({ "age": 99 })).await.unwrap().len(), 1);
+
+    assert_eq!(
+        *log.lock().unwrap(),
+        [
+            r#"before {"age":20}"#,
+            "dispatch adult counting_event",
+            r#"after {"age":20} 1"#,
+            r#"before {"age":10}"#,
+            r#"after {"age":10} 0"#,
+            r#"before {"age":30}"#,
+            r#"after {"age":30} 1"#,
+            r#"before {"age":99}"#,
+            "dispatch adult counting_event",
+            r#"after {"age":99} 1"#,
+        ]
+    );
+}
+
+#[tokio::test]
+async fn run_specs() {
+    use json_rules_engine::testkit::{Expectation, RuleSpec};
+
+    let mut engine = Engine::new();
+    engine.add_rule(
+        serde_json::from_value(json!({
+            "id": "adult",
+            "conditions": {
+                "and": [
+                    {
+                        "field": "age",
+                        "operator": "int_greater_than",
+                        "value": 17,
+                        "label": "old_enough"
+                    },
+                    {
+                        "field": "name",
+                        "operator": "string_not_equals",
+                        "value": ""
+                    }
+                ]
+            },
+            "events": [
+                {
+                    "type": "counting_event",
+                    "params": { "title": "Welcome {{ name }}" }
+                },
+                {
+                    "type": "reminder",
+                    "coalescence": 60,
+                    "coalescence_group": "{{ name }}",
+                    "params": {
+                        "message": "{{ name }}, your profile is waiting"
+                    }
+                }
+            ]
+        }))
+        .unwrap(),
+    );
+    let counting = Arc::new(RwLock::new(CountingEvent::new()));
+    let reminder = Arc::new(RwLock::new(CountingEvent {
+        ty: "reminder".into(),
+        triggered: Vec::new(),
+    }));
+    engine.add_event(counting.clone());
+    engine.add_event(reminder.clone());
+
+    let specs: Vec<RuleSpec> =
+        serde_json::from_str(include_str!("specs/welcome.json")).unwrap();
+    let report = engine.run_specs(&specs).await;
+    assert!(report.passed(), "{:#?}", report);
+    assert_eq!(report.results.len(), 3);
+    assert!(counting.read().unwrap().triggered.is_empty());
+    assert!(reminder.read().unwrap().triggered.is_empty());
+
+    let specs: Vec<RuleSpec> = serde_json::from_value(json!([{
+        "name": "minors are welcomed",
+        "facts": { "name": "Pitou", "age": 15 },
+        "expect": [
+            { "kind": "rule_status", "rule": "adult", "status": "Met" },
+            {
+                "kind": "event",
+                "rule": "adult",
+                "event_type": "counting_event",
+                "params": { "title": "Welcome Pitou" }
+            },
+            {
+                "kind": "leaf_status",
+                "rule": "adult",
+                "label": "young_enough",
+                "status": "Met"
+            },
+            { "kind": "rule_status", "rule": "child", "status": "Met" }
+        ]
+    }]))
+    .unwrap();
+    let report = engine.run_specs(&specs).await;
+    assert!(!report.passed());
+    let failures = &report.failed()[0].failures;
+    assert_eq!(failures.len(), 4);
+    assert!(matches!(
+        failures[0].expectation,
+        Expectation::RuleStatus { .. }
+    ));
+    assert_eq!(failures[0].expected, json!("Met"));
+    assert_eq!(failures[0].actual, json!("NotMet"));
+    assert_eq!(
+        failures[0].message,
+        "Rule `adult` is NotMet rather than Met"
+    );
+    assert_eq!(failures[1].message, "Rule `adult` wasn't met");
+    assert_eq!(
+        failures[2].message,
+        "Rule `adult` has no condition labeled `young_enough`"
+    );
+    assert_eq!(failures[3].message, "Rule `child` doesn't exist");
+
+    // the events dispatch again once the specs ran
+    engine
+        .run(&json!({ "name": "Mia", "age": 30 }))
+        .await
+        .unwrap();
+    assert_eq!(counting.read().unwrap().triggered.len(), 1);
+    assert_eq!(reminder.read().unwrap().triggered.len(), 1);
+}
@@ -0,0 +1,43 @@
+#![cfg(all(target_arch = "wasm32", feature = "wasm"))]
+//! Run with `wasm-pack test --node -- --features wasm`
+use json_rules_engine::evaluate;
+use serde_json::{json, Value};
+use wasm_bindgen_test::*;
+
+#[wasm_bindgen_test]
+fn basic_met() {
+    let rule_json = json!({
+        "conditions": {
+            "and": [
+                {
+                    "field": "name",
+                    "operator": "string_equals",
+                    "value": "Cheng JIANG"
+                },
+                {
+                    "field": "age",
+                    "operator": "int_in_range",
+                    "value": [20, 25]
+                },
+                {
+                    "field": "action",
+                    "operator": "string_equals",
+                    "value": "coding in rust"
+                }
+            ]
+        },
+        "events": []
+    });
+    let facts = json!({
+        "name": "Cheng JIANG",
+        "age": 24,
+        "action": "coding in rust",
+    });
+
+    let rule_result: Value = serde_json::from_str(
+        &evaluate(&rule_json.to_string(), &facts.to_string()).unwrap(),
+    )
+    .unwrap();
+
+    assert_eq!(rule_result["condition_result"]["status"], json!("Met"));
+}